@@ -0,0 +1,58 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use flemu_bench::{console, cpu_loop, frame_rom};
+use flemu_core::bare::{self, Stop};
+use flemu_core::nes::savestate::{StateReader, StateWriter};
+
+/*
+  cpu/instructions is the opcode decoding and addressing modes on their
+  own, reported as instructions per second; nes/frame is everything a
+  frame takes, PPU and APU included; savestate/round-trip is what rewind,
+  run-ahead and netplay rollback pay per snapshot.
+*/
+
+const INSTRUCTIONS: u64 = 100_000;
+
+// into the game a bit, past the power-on wait and most title fade-ins
+const WARM_UP_FRAMES: u32 = 120;
+
+fn cpu(c: &mut Criterion) {
+  let mut group = c.benchmark_group("cpu");
+  group.throughput(Throughput::Elements(INSTRUCTIONS));
+  let mut cpu = cpu_loop();
+  group.bench_function("instructions", |b| {
+    b.iter(|| assert_eq!(bare::run(&mut cpu, INSTRUCTIONS), Stop::Limit))
+  });
+  group.finish();
+}
+
+fn frame(c: &mut Criterion) {
+  let (name, rom) = frame_rom();
+  let mut group = c.benchmark_group("nes");
+  group.throughput(Throughput::Elements(1));
+  let mut cpu = console(rom, WARM_UP_FRAMES);
+  group.bench_function(format!("frame/{}", name), |b| b.iter(|| cpu.run_frame()));
+  group.finish();
+}
+
+fn savestate(c: &mut Criterion) {
+  let (_, rom) = frame_rom();
+  let cpu = console(rom, WARM_UP_FRAMES);
+  let checksum = cpu.bus.cartridge_checksum();
+  c.bench_function("savestate/round-trip", |b| {
+    b.iter_batched(
+      || cpu.clone(),
+      |mut cpu| {
+        let mut w = StateWriter::new(checksum);
+        cpu.save_state(&mut w);
+        let state = w.finish();
+        let mut r = StateReader::new(&state, checksum).unwrap();
+        cpu.load_state(&mut r).unwrap();
+        cpu
+      },
+      BatchSize::SmallInput,
+    )
+  });
+}
+
+criterion_group!(benches, cpu, frame, savestate);
+criterion_main!(benches);
@@ -0,0 +1,84 @@
+use flemu_core::bare::{self, BareBus, Config};
+use flemu_core::nes::cartridge::Rom;
+use flemu_core::nes::cpu::CPU;
+use std::fs;
+use std::path::PathBuf;
+
+/*
+  What the benchmarks run. Real games aren't part of the repository: point
+  FLEMU_BENCH_ROM at one (or drop it in as tests/roms/bench.nes in the
+  core) to time frames of it, any mapper the core has will do. Without
+  one the frames are of `busy_rom`, which keeps the PPU rendering and the
+  CPU busy but is no game, so numbers from the two don't compare.
+*/
+
+/// Instructions in `cpu_loop`'s loop, a few of everything the CPU does a
+/// lot: loads, stores, arithmetic, branches.
+#[rustfmt::skip]
+const LOOP: [u8; 14] = [
+  0xa2, 0x00,       // $0200 LDX #0
+  0xb5, 0x10,       // $0202 LDA $10,X
+  0x69, 0x03,       //       ADC #3
+  0x95, 0x10,       //       STA $10,X
+  0xe8,             //       INX
+  0xd0, 0xf7,       //       BNE $0202
+  0x4c, 0x00, 0x02, //       JMP $0200
+];
+
+/// A bare 6502 going around a loop forever, nothing but the CPU and RAM.
+pub fn cpu_loop() -> CPU<BareBus> {
+  let config = Config {
+    load_address: 0x0200,
+    start: Some(0x0200),
+    ..Config::default()
+  };
+  bare::machine(&LOOP, &config).unwrap()
+}
+
+/// The game to run frames of and what it's called in the report.
+pub fn frame_rom() -> (String, Rom) {
+  let path = std::env::var_os("FLEMU_BENCH_ROM")
+    .map(PathBuf::from)
+    .unwrap_or_else(|| {
+      PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../flemu-core/tests/roms/bench.nes")
+    });
+  match fs::read(&path) {
+    Ok(bytes) => {
+      let name = path
+        .file_stem()
+        .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+      let rom = Rom::from_bytes(&bytes).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+      (name, rom)
+    }
+    Err(_) => ("busy".to_string(), busy_rom()),
+  }
+}
+
+/// Background and sprites on, and the CPU adding up zero page in the
+/// meantime.
+#[rustfmt::skip]
+pub fn busy_rom() -> Rom {
+  Rom::from_program(&[
+    0x2c, 0x02, 0x20, // $8000 BIT $2002, wait for vblank
+    0x10, 0xfb,       //       BPL $8000
+    0xa9, 0x1e,       //       LDA #$1E, background and sprites
+    0x8d, 0x01, 0x20, //       STA $2001
+    0xa2, 0x00,       // $800A LDX #0
+    0xb5, 0x00,       // $800C LDA $00,X
+    0x75, 0x01,       //       ADC $01,X
+    0x95, 0x00,       //       STA $00,X
+    0xe8,             //       INX
+    0xd0, 0xf7,       //       BNE $800C
+    0x4c, 0x0a, 0x80, //       JMP $800A
+  ])
+}
+
+/// A machine `frames` frames into `rom`, past the power-on wait.
+pub fn console(rom: Rom, frames: u32) -> CPU {
+  let mut cpu = CPU::new();
+  cpu.load_rom(rom).unwrap();
+  for _ in 0..frames {
+    cpu.run_frame();
+  }
+  cpu
+}
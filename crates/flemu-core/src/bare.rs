@@ -0,0 +1,150 @@
+use crate::nes::bus::Mem;
+use crate::nes::cpu::{Fault, CPU};
+use std::fmt;
+
+/*
+  A plain 6502 with nothing but 64 KiB of RAM around it, for running CPU
+  test suites (Klaus Dormann's functional test) and hand-written programs
+  without going through a cartridge.
+
+  Unlike the 2A03 it honors the D flag, and BRK traps through $FFFE like
+  on hardware. Those suites signal the end, passed or failed, by jumping
+  to the instruction itself forever, so `run` stops when an instruction
+  leaves PC where it was and reports where.
+*/
+
+/// How to lay the program out and what to wire to it.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Config {
+  /// Where the first byte of the program goes.
+  pub load_address: u16,
+  /// Where execution starts, else at the reset vector.
+  pub start: Option<u16>,
+  /// Written over `$FFFA`, `$FFFC` and `$FFFE` after loading, if given.
+  pub nmi_vector: Option<u16>,
+  pub reset_vector: Option<u16>,
+  pub irq_vector: Option<u16>,
+  /// Bytes written here are collected as output instead of stored.
+  pub output_port: Option<u16>,
+}
+
+pub struct BareBus {
+  memory: Vec<u8>,
+  output_port: Option<u16>,
+  output: Vec<u8>,
+}
+
+impl BareBus {
+  pub fn new(output_port: Option<u16>) -> Self {
+    BareBus {
+      memory: vec![0; 0x10000],
+      output_port,
+      output: Vec::new(),
+    }
+  }
+
+  /// Everything written to the output port so far.
+  pub fn output(&self) -> &[u8] {
+    &self.output
+  }
+
+  pub fn take_output(&mut self) -> Vec<u8> {
+    std::mem::take(&mut self.output)
+  }
+}
+
+impl Mem for BareBus {
+  fn mem_read(&mut self, addr: u16) -> u8 {
+    self.memory[addr as usize]
+  }
+
+  fn mem_write(&mut self, addr: u16, data: u8) {
+    if self.output_port == Some(addr) {
+      self.output.push(data);
+    } else {
+      self.memory[addr as usize] = data;
+    }
+  }
+
+  fn mem_peek(&self, addr: u16) -> u8 {
+    self.memory[addr as usize]
+  }
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+  /// The program runs past $FFFF from the load address.
+  TooLarge { load_address: u16, len: usize },
+}
+
+impl fmt::Display for LoadError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      LoadError::TooLarge { load_address, len } => write!(
+        f,
+        "{} bytes don't fit in memory from ${:04X}",
+        len, load_address
+      ),
+    }
+  }
+}
+
+impl std::error::Error for LoadError {}
+
+/// Why `run` returned.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stop {
+  /// An instruction jumped to itself at this address.
+  Trap(u16),
+  Fault(Fault),
+  /// Still running after the instruction limit.
+  Limit,
+}
+
+/// A CPU on a fresh `BareBus` with `program` loaded as `config` says,
+/// ready to run.
+pub fn machine(program: &[u8], config: &Config) -> Result<CPU<BareBus>, LoadError> {
+  let start = config.load_address as usize;
+  if start + program.len() > 0x10000 {
+    return Err(LoadError::TooLarge {
+      load_address: config.load_address,
+      len: program.len(),
+    });
+  }
+  let mut cpu = CPU::with_bus(BareBus::new(config.output_port));
+  cpu.bus.memory[start..start + program.len()].copy_from_slice(program);
+  for &(addr, vector) in &[
+    (0xFFFA, config.nmi_vector),
+    (0xFFFC, config.reset_vector),
+    (0xFFFE, config.irq_vector),
+  ] {
+    if let Some(vector) = vector {
+      cpu.bus.memory[addr] = vector as u8;
+      cpu.bus.memory[addr + 1] = (vector >> 8) as u8;
+    }
+  }
+  cpu.decimal_enabled = true;
+  cpu.halt_on_brk = false;
+  cpu.program_counter = match config.start {
+    Some(start) => start,
+    None => cpu.mem_read_u16(0xFFFC),
+  };
+  Ok(cpu)
+}
+
+/// Execute until the program traps, faults, or `max_instructions` ran.
+pub fn run(cpu: &mut CPU<BareBus>, max_instructions: u64) -> Stop {
+  for _ in 0..max_instructions {
+    let pc = cpu.program_counter;
+    match cpu.step() {
+      Ok(Some(_)) => {}
+      // BRK only stops the CPU when halt_on_brk was turned back on
+      Ok(None) => return Stop::Trap(pc),
+      Err(_) => return cpu.fault().map_or(Stop::Trap(pc), Stop::Fault),
+    }
+    if cpu.program_counter == pc {
+      return Stop::Trap(pc);
+    }
+  }
+  Stop::Limit
+}
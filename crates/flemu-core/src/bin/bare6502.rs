@@ -0,0 +1,63 @@
+//! Run a raw 6502 binary on the bare machine until it traps.
+//!
+//!   bare6502 <binary> [load address] [start address] [output port]
+//!
+//! Addresses are hex. Without a start address execution begins at the
+//! reset vector. Prints what the program wrote to the output port and
+//! where it trapped, e.g. for Klaus Dormann's functional test:
+//!
+//!   bare6502 6502_functional_test.bin 0000 0400
+//!
+//! Exits with status 1 on a fault or if it never traps.
+
+use flemu_core::bare::{self, Config, Stop};
+use std::io::Write;
+use std::{env, fs, io, process};
+
+const USAGE: &str = "usage: bare6502 <binary> [load address] [start address] [output port]";
+// the functional test needs ~30M
+const MAX_INSTRUCTIONS: u64 = 200_000_000;
+
+fn fail(message: String) -> ! {
+  eprintln!("{}", message);
+  process::exit(2);
+}
+
+fn address(arg: Option<&String>) -> Option<u16> {
+  arg.map(|arg| {
+    u16::from_str_radix(arg.trim_start_matches('$'), 16)
+      .unwrap_or_else(|_| fail(format!("{}: not a hex address\n{}", arg, USAGE)))
+  })
+}
+
+fn main() {
+  let args: Vec<String> = env::args().skip(1).collect();
+  if args.is_empty() || args.len() > 4 {
+    fail(USAGE.to_string());
+  }
+  let config = Config {
+    load_address: address(args.get(1)).unwrap_or(0),
+    start: address(args.get(2)),
+    output_port: address(args.get(3)),
+    ..Config::default()
+  };
+
+  let path = &args[0];
+  let program = fs::read(path).unwrap_or_else(|e| fail(format!("{}: {}", path, e)));
+  let mut cpu =
+    bare::machine(&program, &config).unwrap_or_else(|e| fail(format!("{}: {}", path, e)));
+  let stop = bare::run(&mut cpu, MAX_INSTRUCTIONS);
+  io::stdout().write_all(cpu.bus.output()).ok();
+
+  match stop {
+    Stop::Trap(pc) => println!("trapped at ${:04X} after {} cycles", pc, cpu.cycles),
+    Stop::Fault(fault) => {
+      println!("{}", fault);
+      process::exit(1);
+    }
+    Stop::Limit => {
+      println!("still running at ${:04X}", cpu.program_counter);
+      process::exit(1);
+    }
+  }
+}
@@ -0,0 +1,298 @@
+//! Run a ROM without a window, for scripts and CI.
+//!
+//!   flemu-cli <rom> [--frames N] [--until-pc ADDR] [--until ADDR=VALUE]
+//!             [--png FILE] [--ram FILE] [--trace] [--region REGION]
+//!             [--movie FILE] [--crop-overscan] [--wav FILE]
+//!   flemu-cli [<rom>] --serve ADDR
+//!
+//! Runs N frames (60 by default), stopping early when PC reaches ADDR or
+//! the byte at ADDR holds VALUE, both in hex. Afterwards `--png` writes
+//! the last finished frame (`--crop-overscan` leaves out the 8 lines at
+//! the top and bottom TVs hid), `--ram` the 2KB of internal RAM and
+//! `--wav` the sound of the whole run, at 44.1kHz; `--trace`
+//! prints every instruction in nestest.log format as it runs. `--region`
+//! (ntsc, pal or dendy) overrides the timing the ROM header asks for.
+//! `--movie` plays back an FM2 movie, by default for as many frames as it
+//! has, so a TAS can be checked for ending where it should.
+//!
+//! Exits with status 1 if the program faulted or an `--until` condition
+//! was given and never held.
+//!
+//! `--serve` (built with the `server` feature) runs nothing by itself:
+//! it listens on ADDR, e.g. 127.0.0.1:9000, for WebSocket clients to
+//! control the console with JSON commands, see `flemu_core::server`. The
+//! ROM, if given, is loaded to start with.
+
+use flemu_core::nes::apu::DEFAULT_SAMPLE_RATE;
+use flemu_core::nes::bus::Mem;
+use flemu_core::nes::cartridge::Rom;
+use flemu_core::nes::cpu::CPU;
+use flemu_core::nes::movie::{Movie, MovieStart};
+use flemu_core::nes::palette::Palette;
+use flemu_core::nes::ppu::Frame;
+use flemu_core::nes::savestate::StateReader;
+use flemu_core::nes::timing::Timing;
+use flemu_core::video_dump::{self, OVERSCAN_LINES};
+use flemu_core::wav::WavRecorder;
+use std::io::{self, Write};
+use std::{env, fs, process};
+
+const USAGE: &str = "usage: flemu-cli <rom> [--frames N] [--until-pc ADDR] [--until ADDR=VALUE] \
+                     [--png FILE] [--ram FILE] [--trace] [--region REGION] [--movie FILE] \
+                     [--crop-overscan] [--wav FILE]\n       flemu-cli [<rom>] --serve ADDR";
+const DEFAULT_FRAMES: u64 = 60;
+const RAM_SIZE: u16 = 0x0800;
+
+fn fail(message: String) -> ! {
+  eprintln!("{}", message);
+  process::exit(2);
+}
+
+#[derive(Default)]
+struct Options {
+  rom: String,
+  frames: Option<u64>,
+  until_pc: Option<u16>,
+  until_memory: Option<(u16, u8)>,
+  png: Option<String>,
+  ram: Option<String>,
+  trace: bool,
+  region: Option<Timing>,
+  movie: Option<String>,
+  crop_overscan: bool,
+  wav: Option<String>,
+  serve: Option<String>,
+}
+
+impl Options {
+  fn has_condition(&self) -> bool {
+    self.until_pc.is_some() || self.until_memory.is_some()
+  }
+}
+
+// "C000", "$C000" or "0xC000"
+fn hex(text: &str) -> u16 {
+  let digits = text
+    .trim_start_matches('$')
+    .trim_start_matches("0x")
+    .trim_start_matches("0X");
+  u16::from_str_radix(digits, 16).unwrap_or_else(|_| fail(format!("not a hex number: {}", text)))
+}
+
+fn parse_options(args: &[String]) -> Options {
+  let mut options = Options::default();
+  let mut args = args.iter();
+  while let Some(arg) = args.next() {
+    let mut value = || {
+      args
+        .next()
+        .unwrap_or_else(|| fail(format!("{} needs a value\n{}", arg, USAGE)))
+    };
+    match arg.as_str() {
+      "--frames" => {
+        let frames = value();
+        options.frames = Some(
+          frames
+            .parse()
+            .unwrap_or_else(|_| fail(format!("not a frame count: {}", frames))),
+        );
+      }
+      "--until-pc" => options.until_pc = Some(hex(value())),
+      "--until" => {
+        let condition = value();
+        let (addr, byte) = condition
+          .split_once('=')
+          .unwrap_or_else(|| fail(format!("expected ADDR=VALUE: {}", condition)));
+        let byte = hex(byte);
+        if byte > 0xFF {
+          fail(format!("not a byte: {:X}", byte));
+        }
+        options.until_memory = Some((hex(addr), byte as u8));
+      }
+      "--png" => options.png = Some(value().clone()),
+      "--ram" => options.ram = Some(value().clone()),
+      "--trace" => options.trace = true,
+      "--region" => {
+        let region = value();
+        options.region = Some(
+          Timing::from_id(region).unwrap_or_else(|| fail(format!("unknown region: {}", region))),
+        );
+      }
+      "--movie" => options.movie = Some(value().clone()),
+      "--crop-overscan" => options.crop_overscan = true,
+      "--wav" => options.wav = Some(value().clone()),
+      "--serve" => options.serve = Some(value().clone()),
+      _ if arg.starts_with("--") || !options.rom.is_empty() => fail(USAGE.to_string()),
+      _ => options.rom = arg.clone(),
+    }
+  }
+  if options.rom.is_empty() && options.serve.is_none() {
+    fail(USAGE.to_string());
+  }
+  options
+}
+
+enum Stop {
+  Frames,
+  Condition,
+  Fault,
+}
+
+fn condition_holds(cpu: &CPU, options: &Options) -> bool {
+  options.until_pc == Some(cpu.program_counter)
+    || options
+      .until_memory
+      .map_or(false, |(addr, value)| cpu.mem_peek(addr) == value)
+}
+
+fn run(cpu: &mut CPU, options: &Options, movie: Option<&Movie>) -> Stop {
+  let default_frames = movie.map_or(DEFAULT_FRAMES, |movie| movie.len() as u64);
+  let frames = options.frames.unwrap_or(default_frames);
+  let start = cpu.bus.ppu.frame_count;
+  let mut played = 0;
+  let stdout = io::stdout();
+  let mut out = stdout.lock();
+  loop {
+    if condition_holds(cpu, options) {
+      return Stop::Condition;
+    }
+    let frame = cpu.bus.ppu.frame_count - start;
+    if frame >= frames {
+      return Stop::Frames;
+    }
+    if let Some(movie) = movie {
+      if frame == played {
+        movie.play(played as usize, cpu);
+        played += 1;
+      }
+    }
+    let running = cpu.step_instruction();
+    for line in cpu.take_nestest_log() {
+      // a closed pipe (`| head`) is no reason to keep emulating
+      if writeln!(out, "{}", line).is_err() {
+        process::exit(0);
+      }
+    }
+    if !running {
+      return Stop::Fault;
+    }
+  }
+}
+
+// the movie, with the console set up the way it starts
+fn load_movie(cpu: &mut CPU, path: &str) -> Movie {
+  let text = fs::read_to_string(path).unwrap_or_else(|e| fail(format!("{}: {}", path, e)));
+  let movie = Movie::from_fm2(&text).unwrap_or_else(|e| fail(format!("{}: {}", path, e)));
+  cpu.bus.set_four_score(movie.four_score);
+  if let MovieStart::State(state) = &movie.start {
+    let loaded = StateReader::new(state, cpu.bus.cartridge_checksum()).and_then(|mut r| {
+      cpu.load_state(&mut r)?;
+      r.finish()
+    });
+    loaded.unwrap_or_else(|e| fail(format!("{}: {}", path, e)));
+  } else if movie.pal != (cpu.bus.timing() == Timing::Pal) && cpu.bus.timing_override().is_none() {
+    let timing = if movie.pal { Timing::Pal } else { Timing::Ntsc };
+    cpu.bus.set_timing_override(Some(timing));
+  }
+  movie
+    .restore_controllers(cpu)
+    .unwrap_or_else(|e| fail(format!("{}: {}", path, e)));
+  movie
+}
+
+fn load(path: &str) -> CPU {
+  let bytes = fs::read(path).unwrap_or_else(|e| fail(format!("{}: {}", path, e)));
+  let rom = Rom::from_bytes(&bytes).unwrap_or_else(|e| fail(format!("{}: {}", path, e)));
+  let mut cpu = CPU::new();
+  cpu.reset();
+  cpu.halt_on_brk = false;
+  cpu
+    .load_rom(rom)
+    .unwrap_or_else(|e| fail(format!("{}: {}", path, e)));
+  cpu
+}
+
+#[cfg(feature = "server")]
+fn serve(addr: &str, options: &Options) -> ! {
+  use flemu_core::server::{self, Session};
+  use std::net::TcpListener;
+
+  let mut session = if options.rom.is_empty() {
+    Session::new()
+  } else {
+    Session::with_cpu(load(&options.rom))
+  };
+  let listener = TcpListener::bind(addr).unwrap_or_else(|e| fail(format!("{}: {}", addr, e)));
+  eprintln!("listening on {}", addr);
+  server::serve(listener, &mut session).unwrap_or_else(|e| fail(format!("{}: {}", addr, e)));
+  process::exit(0);
+}
+
+#[cfg(not(feature = "server"))]
+fn serve(_addr: &str, _options: &Options) -> ! {
+  fail("--serve needs flemu-cli built with the server feature".to_string());
+}
+
+fn main() {
+  let args: Vec<String> = env::args().skip(1).collect();
+  let options = parse_options(&args);
+  if let Some(addr) = &options.serve {
+    serve(addr, &options);
+  }
+
+  let mut cpu = load(&options.rom);
+  cpu.set_nestest_log(options.trace);
+  if options.region.is_some() {
+    cpu.bus.set_timing_override(options.region);
+  }
+  let movie = options
+    .movie
+    .as_ref()
+    .map(|path| load_movie(&mut cpu, path));
+
+  if options.wav.is_some() {
+    cpu.bus.apu.set_capture_rate(Some(DEFAULT_SAMPLE_RATE));
+  }
+
+  let stop = run(&mut cpu, &options, movie.as_ref());
+  let frame = cpu.bus.ppu.frame_count;
+  match stop {
+    Stop::Frames => eprintln!("ran {} frames", frame),
+    Stop::Condition => eprintln!(
+      "condition held at ${:04X}, frame {}",
+      cpu.program_counter, frame
+    ),
+    Stop::Fault => eprintln!("stopped at ${:04X}, frame {}", cpu.program_counter, frame),
+  }
+
+  if let Some(path) = &options.png {
+    let mut rgba = Palette::default().frame_rgba(&cpu.bus.ppu.frame);
+    let mut height = Frame::HEIGHT;
+    if options.crop_overscan {
+      height -= 2 * OVERSCAN_LINES;
+      let picture = (0, OVERSCAN_LINES, Frame::WIDTH, height);
+      rgba = video_dump::crop_rgba(&rgba, Frame::WIDTH, picture);
+    }
+    let png = video_dump::encode_png(Frame::WIDTH as u32, height as u32, &rgba);
+    fs::write(path, png).unwrap_or_else(|e| fail(format!("{}: {}", path, e)));
+  }
+  if let Some(path) = &options.ram {
+    let ram: Vec<u8> = (0..RAM_SIZE).map(|addr| cpu.mem_peek(addr)).collect();
+    fs::write(path, ram).unwrap_or_else(|e| fail(format!("{}: {}", path, e)));
+  }
+
+  if let Some(path) = &options.wav {
+    let mut recorder = WavRecorder::new(DEFAULT_SAMPLE_RATE);
+    recorder.push(&cpu.bus.apu.take_captured());
+    fs::write(path, recorder.to_wav()).unwrap_or_else(|e| fail(format!("{}: {}", path, e)));
+  }
+
+  let failed = match stop {
+    Stop::Frames => options.has_condition(),
+    Stop::Condition => false,
+    Stop::Fault => true,
+  };
+  if failed {
+    process::exit(1);
+  }
+}
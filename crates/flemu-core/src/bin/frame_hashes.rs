@@ -0,0 +1,95 @@
+//! Record or compare per-frame state hashes of a set of ROMs.
+//!
+//!   frame_hashes record <hash file> <frames> <rom>...
+//!   frame_hashes compare <hash file> <frames> <rom>...
+//!
+//! `compare` prints the first divergent frame of every ROM that no longer
+//! matches the recorded run and exits with status 1 if there was any.
+
+use flemu_core::nes::cartridge::Rom;
+use flemu_core::nes::regression::{self, HashLog};
+use std::path::Path;
+use std::{env, fs, process};
+
+const USAGE: &str = "usage: frame_hashes <record|compare> <hash file> <frames> <rom>...";
+
+fn rom_name(path: &str) -> String {
+  Path::new(path)
+    .file_name()
+    .map_or(path.to_string(), |name| name.to_string_lossy().into_owned())
+}
+
+fn run(roms: &[String], frames: u32) -> Result<HashLog, String> {
+  let mut log = HashLog::new();
+  for path in roms {
+    let bytes = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    let rom = Rom::from_bytes(&bytes).map_err(|e| format!("{}: {}", path, e))?;
+    let hashes = regression::record(rom, frames).map_err(|e| format!("{}: {}", path, e))?;
+    log.insert(&rom_name(path), hashes);
+  }
+  Ok(log)
+}
+
+fn compare(baseline: &HashLog, current: &HashLog) -> bool {
+  let mut matches = true;
+  for rom in current.roms() {
+    let hashes = current.get(rom).unwrap_or(&[]);
+    match baseline.get(rom) {
+      None => println!("{}: not in the recorded run", rom),
+      Some(recorded) => match regression::first_divergence(recorded, hashes) {
+        None => println!("{}: ok ({} frames)", rom, hashes.len()),
+        Some(frame) => {
+          println!("{}: diverges at frame {}", rom, frame);
+          matches = false;
+        }
+      },
+    }
+  }
+  matches
+}
+
+fn main() {
+  let args: Vec<String> = env::args().skip(1).collect();
+  if args.len() < 4 {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+  }
+  let (mode, hash_file, roms) = (&args[0], &args[1], &args[3..]);
+  let frames: u32 = match args[2].parse() {
+    Ok(frames) => frames,
+    Err(_) => {
+      eprintln!("{}", USAGE);
+      process::exit(2);
+    }
+  };
+
+  let current = run(roms, frames).unwrap_or_else(|e| {
+    eprintln!("{}", e);
+    process::exit(2);
+  });
+
+  match mode.as_str() {
+    "record" => {
+      if let Err(e) = fs::write(hash_file, current.to_text()) {
+        eprintln!("{}: {}", hash_file, e);
+        process::exit(2);
+      }
+    }
+    "compare" => {
+      let baseline = fs::read_to_string(hash_file)
+        .map_err(|e| e.to_string())
+        .and_then(|text| HashLog::from_text(&text).map_err(|e| e.to_string()))
+        .unwrap_or_else(|e| {
+          eprintln!("{}: {}", hash_file, e);
+          process::exit(2);
+        });
+      if !compare(&baseline, &current) {
+        process::exit(1);
+      }
+    }
+    _ => {
+      eprintln!("{}", USAGE);
+      process::exit(2);
+    }
+  }
+}
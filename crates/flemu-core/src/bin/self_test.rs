@@ -0,0 +1,16 @@
+//! Run the emulator's built-in self-test and print the report.
+//!
+//!   self_test
+//!
+//! Exits with status 1 if any check failed.
+
+use flemu_core::nes::self_test;
+use std::process;
+
+fn main() {
+  let report = self_test::run();
+  print!("{}", report.to_text());
+  if !report.passed() {
+    process::exit(1);
+  }
+}
@@ -0,0 +1,63 @@
+//! Write every Nth frame of a ROM as a numbered PNG.
+//!
+//!   video_dump <rom> <output dir> <frames> [every]
+//!
+//! Frames are numbered by the PPU's frame count, `every` defaults to 1.
+//! Stops early, with status 1, if the program faults.
+
+use flemu_core::nes::cartridge::Rom;
+use flemu_core::nes::cpu::CPU;
+use flemu_core::nes::palette::Palette;
+use flemu_core::nes::ppu::Frame;
+use flemu_core::video_dump::{self, VideoDump};
+use std::path::Path;
+use std::{env, fs, process};
+
+const USAGE: &str = "usage: video_dump <rom> <output dir> <frames> [every]";
+
+fn fail(message: String) -> ! {
+  eprintln!("{}", message);
+  process::exit(2);
+}
+
+fn main() {
+  let args: Vec<String> = env::args().skip(1).collect();
+  if args.len() < 3 || args.len() > 4 {
+    fail(USAGE.to_string());
+  }
+  let (rom_path, out_dir) = (&args[0], Path::new(&args[1]));
+  let frames: u64 = args[2].parse().unwrap_or_else(|_| fail(USAGE.to_string()));
+  let every: u64 = match args.get(3) {
+    Some(every) => every.parse().unwrap_or_else(|_| fail(USAGE.to_string())),
+    None => 1,
+  };
+
+  let bytes = fs::read(rom_path).unwrap_or_else(|e| fail(format!("{}: {}", rom_path, e)));
+  let rom = Rom::from_bytes(&bytes).unwrap_or_else(|e| fail(format!("{}: {}", rom_path, e)));
+  let mut cpu = CPU::new();
+  cpu.reset();
+  cpu.halt_on_brk = false;
+  cpu
+    .load_rom(rom)
+    .unwrap_or_else(|e| fail(format!("{}: {}", rom_path, e)));
+  fs::create_dir_all(out_dir).unwrap_or_else(|e| fail(format!("{}: {}", out_dir.display(), e)));
+
+  let palette = Palette::default();
+  let mut dump = VideoDump::new(every);
+  let mut written = 0;
+  while cpu.bus.ppu.frame_count < frames {
+    if !cpu.run_frame() {
+      eprintln!("stopped at frame {}", cpu.bus.ppu.frame_count);
+      process::exit(1);
+    }
+    let frame = cpu.bus.ppu.frame_count;
+    if dump.should_dump(frame) {
+      let rgba = palette.frame_rgba(&cpu.bus.ppu.frame);
+      let png = video_dump::encode_png(Frame::WIDTH as u32, Frame::HEIGHT as u32, &rgba);
+      let path = out_dir.join(video_dump::frame_file_name(frame));
+      fs::write(&path, png).unwrap_or_else(|e| fail(format!("{}: {}", path.display(), e)));
+      written += 1;
+    }
+  }
+  println!("wrote {} frames to {}", written, out_dir.display());
+}
@@ -0,0 +1,236 @@
+/*
+  Animated GIFs of recent gameplay: a rolling buffer of the last frames
+  the PPU drew, and an encoder for them. Lighter than a video recording,
+  and any browser or chat app plays the result.
+
+  Frames go in as palette indices, so the buffer is 60KB a frame and the
+  GIF uses the palette of the moment it's made. Each frame has its own
+  color table of the colors it uses, emphasis included, which is never
+  more than the 256 a GIF can have short of a game changing emphasis on
+  most lines; then emphasis is left out.
+
+  GIF delays are hundredths of a second and browsers make anything under
+  2 into 10, so 60 frames a second can't play at speed. Keep every other
+  frame, and the delays alternate 3 and 4.
+*/
+
+use crate::nes::palette::Palette;
+use crate::nes::ppu::Frame;
+use std::collections::{HashMap, VecDeque};
+
+// LZW codes are at most 12 bits
+const MAX_CODES: u16 = 4096;
+
+/// The last `capacity` frames pushed, oldest first.
+#[derive(Clone)]
+pub struct FrameHistory {
+  frames: VecDeque<Frame>,
+  capacity: usize,
+}
+
+impl FrameHistory {
+  pub fn new(capacity: usize) -> Self {
+    FrameHistory {
+      frames: VecDeque::with_capacity(capacity),
+      capacity,
+    }
+  }
+
+  pub fn capacity(&self) -> usize {
+    self.capacity
+  }
+
+  /// Drops the oldest frames down to `capacity`.
+  pub fn set_capacity(&mut self, capacity: usize) {
+    self.capacity = capacity;
+    while self.frames.len() > capacity {
+      self.frames.pop_front();
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.frames.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.frames.is_empty()
+  }
+
+  pub fn clear(&mut self) {
+    self.frames.clear();
+  }
+
+  /// A copy of `frame`, in the oldest one's buffer once full.
+  pub fn push(&mut self, frame: &Frame) {
+    if self.capacity == 0 {
+      return;
+    }
+    if self.frames.len() == self.capacity {
+      let mut oldest = self.frames.pop_front().unwrap();
+      oldest.data.copy_from_slice(&frame.data);
+      oldest.emphasis.copy_from_slice(&frame.emphasis);
+      self.frames.push_back(oldest);
+    } else {
+      self.frames.push_back(frame.clone());
+    }
+  }
+
+  /// The last `count` frames, or all there are, oldest first.
+  pub fn recent(&self, count: usize) -> Vec<&Frame> {
+    let skip = self.frames.len().saturating_sub(count);
+    self.frames.iter().skip(skip).collect()
+  }
+}
+
+/// `frames` as a looping GIF through `palette`, `fps` of them a second.
+pub fn encode_gif(frames: &[&Frame], palette: &Palette, fps: f64) -> Vec<u8> {
+  let mut gif = b"GIF89a".to_vec();
+  // the logical screen, no global color table
+  gif.extend_from_slice(&(Frame::WIDTH as u16).to_le_bytes());
+  gif.extend_from_slice(&(Frame::HEIGHT as u16).to_le_bytes());
+  gif.extend_from_slice(&[0, 0, 0]);
+  // NETSCAPE2.0, looping forever
+  gif.extend_from_slice(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00");
+  let centiseconds = |frame: usize| (frame as f64 * 100.0 / fps).round() as u16;
+  for (i, frame) in frames.iter().enumerate() {
+    let delay = centiseconds(i + 1) - centiseconds(i);
+    // graphic control: drawn over the last (disposal 1), no transparency
+    gif.extend_from_slice(&[0x21, 0xF9, 0x04, 0x04]);
+    gif.extend_from_slice(&delay.to_le_bytes());
+    gif.extend_from_slice(&[0, 0]);
+    write_image(&mut gif, frame, palette);
+  }
+  gif.push(0x3B);
+  gif
+}
+
+fn write_image(gif: &mut Vec<u8>, frame: &Frame, palette: &Palette) {
+  let (colors, indices) = match indexed(frame, true) {
+    Some(indexed) => indexed,
+    None => indexed(frame, false).expect("64 colors fit"),
+  };
+  // a power of two from 4 up, for the LZW code size
+  let bits = (colors.len().max(4) as u32)
+    .next_power_of_two()
+    .trailing_zeros() as u8;
+  gif.push(0x2C);
+  gif.extend_from_slice(&[0, 0, 0, 0]);
+  gif.extend_from_slice(&(Frame::WIDTH as u16).to_le_bytes());
+  gif.extend_from_slice(&(Frame::HEIGHT as u16).to_le_bytes());
+  // local color table of 2^bits entries
+  gif.push(0x80 | (bits - 1));
+  for i in 0..1 << bits {
+    let (r, g, b) = match colors.get(i) {
+      Some(&(color, emphasis)) => palette.emphasized(color, emphasis),
+      None => (0, 0, 0),
+    };
+    gif.extend_from_slice(&[r, g, b]);
+  }
+  gif.push(bits);
+  for block in lzw(&indices, bits).chunks(255) {
+    gif.push(block.len() as u8);
+    gif.extend_from_slice(block);
+  }
+  gif.push(0);
+}
+
+// the (color, emphasis) pairs a frame uses, and each pixel's index into
+// them
+type Indexed = (Vec<(u8, u8)>, Vec<u8>);
+
+// `Indexed` for `frame`, emphasis left at 0 without `emphasis`; None past
+// 256 colors
+fn indexed(frame: &Frame, emphasis: bool) -> Option<Indexed> {
+  let mut colors = Vec::new();
+  let mut table = [None; 64 * 8];
+  let mut indices = Vec::with_capacity(frame.data.len());
+  for (line, &line_emphasis) in frame.data.chunks(Frame::WIDTH).zip(&frame.emphasis) {
+    let line_emphasis = if emphasis { line_emphasis & 0b111 } else { 0 };
+    for &color in line {
+      let key = (line_emphasis as usize) << 6 | (color & 0x3F) as usize;
+      let index = match table[key] {
+        Some(index) => index,
+        None if colors.len() == 256 => return None,
+        None => {
+          colors.push((color & 0x3F, line_emphasis));
+          table[key] = Some((colors.len() - 1) as u8);
+          (colors.len() - 1) as u8
+        }
+      };
+      indices.push(index);
+    }
+  }
+  Some((colors, indices))
+}
+
+// GIF's variable-width LZW, codes packed from the low bit up
+fn lzw(indices: &[u8], min_bits: u8) -> Vec<u8> {
+  let clear = 1u16 << min_bits;
+  let end = clear + 1;
+  let mut out = BitWriter::default();
+  let mut codes: HashMap<(u16, u8), u16> = HashMap::new();
+  let mut next = end + 1;
+  let mut bits = min_bits + 1;
+  out.write(clear, bits);
+  let mut prefix: Option<u16> = None;
+  for &index in indices {
+    let current = match prefix {
+      None => {
+        prefix = Some(index as u16);
+        continue;
+      }
+      Some(current) => current,
+    };
+    if let Some(&code) = codes.get(&(current, index)) {
+      prefix = Some(code);
+      continue;
+    }
+    out.write(current, bits);
+    if next == MAX_CODES {
+      // table full: start over
+      out.write(clear, bits);
+      codes.clear();
+      next = end + 1;
+      bits = min_bits + 1;
+    } else {
+      codes.insert((current, index), next);
+      // the decoder widens a code later, once it has this one too
+      if next == 1 << bits {
+        bits += 1;
+      }
+      next += 1;
+    }
+    prefix = Some(index as u16);
+  }
+  if let Some(current) = prefix {
+    out.write(current, bits);
+  }
+  out.write(end, bits);
+  out.finish()
+}
+
+#[derive(Default)]
+struct BitWriter {
+  bytes: Vec<u8>,
+  pending: u32,
+  count: u8,
+}
+
+impl BitWriter {
+  fn write(&mut self, code: u16, bits: u8) {
+    self.pending |= (code as u32) << self.count;
+    self.count += bits;
+    while self.count >= 8 {
+      self.bytes.push(self.pending as u8);
+      self.pending >>= 8;
+      self.count -= 8;
+    }
+  }
+
+  fn finish(mut self) -> Vec<u8> {
+    if self.count > 0 {
+      self.bytes.push(self.pending as u8);
+    }
+    self.bytes
+  }
+}
@@ -0,0 +1,12 @@
+//! The emulator core: CPU, PPU, APU, cartridges and everything the
+//! debugger and test tools need, with no browser dependencies. The wasm
+//! frontend lives in the `hello` crate on top of it.
+
+pub mod bare;
+pub mod gif;
+pub mod nes;
+pub mod rng;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod video_dump;
+pub mod wav;
@@ -0,0 +1,45 @@
+pub mod achievements;
+pub mod apu;
+pub mod asm;
+pub mod attract;
+pub mod blargg;
+pub mod bus;
+pub mod cartridge;
+pub mod cheats;
+pub mod combo;
+pub mod cpu;
+pub mod debugger;
+pub mod desync;
+pub mod diagnostics;
+#[cfg(feature = "async")]
+pub mod driver;
+pub mod game_db;
+pub mod golden;
+pub mod joypad;
+pub mod mapper;
+pub mod memory_map;
+pub mod movie;
+pub mod nametable;
+pub mod netplay;
+pub mod nsf;
+mod opcodes;
+pub mod palette;
+pub mod patch;
+pub mod ppu;
+pub mod ppu_viewer;
+pub mod profile;
+pub mod regression;
+pub mod rewind;
+pub mod rollback;
+pub mod run_ahead;
+pub mod savestate;
+pub mod scanline_events;
+pub mod self_test;
+pub mod splash;
+pub mod time_travel;
+pub mod timing;
+pub mod warnings;
+pub mod zapper;
+
+// expose data
+pub use opcodes::OpCode;
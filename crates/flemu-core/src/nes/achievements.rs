@@ -0,0 +1,143 @@
+use crate::nes::cpu::{Mem, CPU};
+
+/// Kind of memory behind a region, mirroring rcheevos' `RC_MEMORY_TYPE_*`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RegionKind {
+  SystemRam,
+  VirtualRam,
+  HardwareController,
+  SaveRam,
+  ReadOnly,
+}
+
+/// One entry of the memory map an achievements runtime sees.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MemoryRegion {
+  pub start: u32,
+  pub end: u32,
+  /// CPU address the start of the region is read from
+  pub real_address: u16,
+  pub kind: RegionKind,
+  pub description: &'static str,
+}
+
+/// NES memory map following the rcheevos console conventions. Achievement
+/// addresses are plain CPU addresses, mirrors of system RAM read back from
+/// the 2 KiB at $0000.
+pub const MEMORY_REGIONS: [MemoryRegion; 9] = [
+  MemoryRegion {
+    start: 0x0000,
+    end: 0x07ff,
+    real_address: 0x0000,
+    kind: RegionKind::SystemRam,
+    description: "System RAM",
+  },
+  MemoryRegion {
+    start: 0x0800,
+    end: 0x1fff,
+    real_address: 0x0000,
+    kind: RegionKind::VirtualRam,
+    description: "Mirror RAM",
+  },
+  MemoryRegion {
+    start: 0x2000,
+    end: 0x2007,
+    real_address: 0x2000,
+    kind: RegionKind::HardwareController,
+    description: "PPU Register",
+  },
+  MemoryRegion {
+    start: 0x2008,
+    end: 0x3fff,
+    real_address: 0x2000,
+    kind: RegionKind::VirtualRam,
+    description: "Mirrored PPU Register",
+  },
+  MemoryRegion {
+    start: 0x4000,
+    end: 0x4017,
+    real_address: 0x4000,
+    kind: RegionKind::HardwareController,
+    description: "APU and I/O register",
+  },
+  MemoryRegion {
+    start: 0x4018,
+    end: 0x401f,
+    real_address: 0x4018,
+    kind: RegionKind::HardwareController,
+    description: "APU and I/O test register",
+  },
+  MemoryRegion {
+    start: 0x4020,
+    end: 0x5fff,
+    real_address: 0x4020,
+    kind: RegionKind::ReadOnly,
+    description: "Cartridge data",
+  },
+  MemoryRegion {
+    start: 0x6000,
+    end: 0x7fff,
+    real_address: 0x6000,
+    kind: RegionKind::SaveRam,
+    description: "Cartridge RAM",
+  },
+  MemoryRegion {
+    start: 0x8000,
+    end: 0xffff,
+    real_address: 0x8000,
+    kind: RegionKind::ReadOnly,
+    description: "Cartridge ROM",
+  },
+];
+
+pub fn find_region(address: u32) -> Option<&'static MemoryRegion> {
+  MEMORY_REGIONS
+    .iter()
+    .find(|region| region.start <= address && address <= region.end)
+}
+
+/// Read `num_bytes` (1, 2 or 4) little-endian bytes at an achievement
+/// address, the same contract as rcheevos' peek callback. Addresses outside
+/// the map, and any other size, read as 0.
+pub fn peek(cpu: &CPU, address: u32, num_bytes: u32) -> u32 {
+  if !matches!(num_bytes, 1 | 2 | 4) {
+    return 0;
+  }
+  let mut value = 0;
+  for i in 0..num_bytes {
+    value |= (peek_byte(cpu, address.wrapping_add(i)) as u32) << (8 * i);
+  }
+  value
+}
+
+/// An achievements runtime, called at the end of every frame the way
+/// rcheevos' `rc_runtime_do_frame` expects, to `peek` at what the frame
+/// left in memory.
+pub trait FrameHook {
+  fn do_frame(&mut self, cpu: &CPU);
+}
+
+impl<F: FnMut(&CPU)> FrameHook for F {
+  fn do_frame(&mut self, cpu: &CPU) {
+    self(cpu)
+  }
+}
+
+/// `CPU::run_frame`, then `hook` if the frame got to its end.
+pub fn run_frame(cpu: &mut CPU, hook: &mut dyn FrameHook) -> bool {
+  let done = cpu.run_frame();
+  if done {
+    hook.do_frame(cpu);
+  }
+  done
+}
+
+fn peek_byte(cpu: &CPU, address: u32) -> u8 {
+  match address {
+    0x0000..=0x1fff => cpu.mem_peek((address & 0x07ff) as u16),
+    // PPU/APU registers have read side effects, peeking must never touch them
+    0x2000..=0x401f => 0,
+    0x4020..=0xffff => cpu.mem_peek(address as u16),
+    _ => 0,
+  }
+}
@@ -0,0 +1,636 @@
+use crate::nes::mapper::Mapper;
+use crate::nes::savestate::{StateError, StateReader, StateWriter};
+use crate::nes::timing::Timing;
+use lazy_static::lazy_static;
+
+mod channels;
+mod dmc;
+mod mixer;
+mod noise;
+mod pulse;
+mod resampler;
+mod sample_ring;
+mod triangle;
+
+pub use channels::{Channel, ChannelFrame, ChannelLog, ChannelState};
+pub use dmc::{Dmc, FETCH_STALL};
+pub use mixer::{Mixer, MixerInput};
+pub use noise::Noise;
+pub use pulse::Pulse;
+pub use resampler::Resampler;
+pub use sample_ring::SampleRing;
+pub use triangle::Triangle;
+
+/*
+  APU: two pulse channels, triangle, noise and DMC, and the frame counter
+  that clocks their envelopes, sweeps and length counters.
+
+  $4000-$4003  pulse 1        $400C-$400F  noise
+  $4004-$4007  pulse 2        $4010-$4013  DMC
+  $4008-$400B  triangle
+
+  $4015 write  ---D NT21  enable channels; clearing one silences it, D
+                          (re)starts the DMC sample. Acknowledges the DMC
+                          IRQ.
+  $4015 read   IF-D NT21  which length counters are running, D if sample
+                          bytes are left, and the DMC (I) and frame (F)
+                          IRQs. Acknowledges the frame IRQ.
+  $4017        MI-- ----  frame counter mode (0: 4-step, 1: 5-step), and
+                          inhibit of the 4-step mode's frame IRQ
+
+  Pulse timers count APU cycles, one every other CPU cycle; the others
+  count CPU cycles. The frame counter fires quarter frames (envelopes,
+  triangle linear counter) and half frames (lengths and sweeps) at fixed
+  CPU cycles, about 240 times a second; PAL's steps are further apart
+  to keep that rate at its slower clock.
+
+  The DMC reads its samples from the cartridge by DMA, taking 3 or 4 CPU
+  cycles each time; `tick` returns them so the bus can stall the CPU.
+
+  CPU cycles alternate between get and put halves of an APU cycle, and a
+  few things depend on which one they land on:
+  - a $4017 write takes effect 3 CPU cycles later on a get cycle, 4 on a
+    put cycle
+  - a DMC fetch has to read on a get cycle, costing an extra alignment
+    cycle when it starts on a put cycle
+  The frame IRQ flag is also raised on 3 consecutive cycles around the end
+  of the 4-step sequence, so acknowledging it too early gets it back.
+
+  Output is mixed to [0, 1] through lookup tables of the two non-linear
+  DACs, in 16.16 fixed point: the levels the channels put out (and so
+  the machine) never go near a float, and the mix is the same integer on
+  every platform, unless `Mixer` turns channels down, when the tables'
+  formulas take the scaled levels instead. A sound chip on the cartridge
+  is added on top, in the same fixed point. From there it's resampled to
+  `sample_rate` and queued in a ring buffer holding twice the target
+  latency, 100ms unless `set_latency` says otherwise. `fill_audio` keeps
+  that buffer about half full by running the resampler up to half a
+  percent fast or slow, which absorbs the drift between the frame loop
+  and the audio clock; falling further behind or ahead still ends in an
+  underrun or overrun. A shorter target answers faster but has less to
+  play through a slow frame.
+*/
+
+/// NTSC's, see `Timing::cpu_clock` for the others.
+pub const CPU_CLOCK: f64 = 1_789_773.0;
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+
+#[rustfmt::skip]
+const LENGTH_TABLE: [u8; 32] = [
+  10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+  12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+// CPU cycles of each frame counter step; the last one of a sequence also
+// restarts it
+const FOUR_STEP: [u32; 4] = [7457, 14913, 22371, 29829];
+const FIVE_STEP: [u32; 5] = [7457, 14913, 22371, 29829, 37281];
+const PAL_FOUR_STEP: [u32; 4] = [8313, 16627, 24939, 33253];
+const PAL_FIVE_STEP: [u32; 5] = [8313, 16627, 24939, 33253, 41565];
+// cycles the frame IRQ flag keeps being raised, from one before the last
+// 4-step step to one after it
+const FRAME_IRQ_CYCLES: u8 = 3;
+
+/// Sound queued between the emulator and the speaker, in milliseconds: the
+/// default and the range `set_latency` takes.
+pub const DEFAULT_LATENCY_MS: u32 = 100;
+pub const MIN_LATENCY_MS: u32 = 20;
+pub const MAX_LATENCY_MS: u32 = 500;
+// most the resampler's rate is bent to keep the ring half full
+const MAX_RATE_ADJUST: f64 = 0.005;
+// volume while fast-forwarding
+const FAST_FORWARD_GAIN: f32 = 0.5;
+// 1.0 in the mixer's fixed point
+const MIX_ONE: u32 = 1 << 16;
+
+lazy_static! {
+  // nesdev's approximations, by the sum of both pulse levels and by
+  // 3 * triangle + 2 * noise + DMC
+  static ref PULSE_TABLE: Vec<u32> = (0..31)
+    .map(|n| mix_level(95.88, 8128.0, n as f64))
+    .collect();
+  static ref TND_TABLE: Vec<u32> = (0..203)
+    .map(|n| mix_level(163.67, 24329.0, n as f64))
+    .collect();
+}
+
+fn mix_level(scale: f64, divisor: f64, n: f64) -> u32 {
+  if n <= 0.0 {
+    return 0;
+  }
+  (scale / (divisor / n + 100.0) * MIX_ONE as f64).round() as u32
+}
+
+/// Volume of the pulse and noise channels: a constant, or a sawtooth
+/// decaying from 15 once per period + 1 quarter frames.
+#[derive(Debug, Clone, Default)]
+struct Envelope {
+  start: bool,
+  looping: bool,
+  constant: bool,
+  period: u8,
+  divider: u8,
+  decay: u8,
+}
+
+impl Envelope {
+  /// The --LC VVVV half of a channel's first register.
+  fn write(&mut self, value: u8) {
+    self.looping = value & 0x20 != 0;
+    self.constant = value & 0x10 != 0;
+    self.period = value & 0x0F;
+  }
+
+  fn clock(&mut self) {
+    if self.start {
+      self.start = false;
+      self.decay = 15;
+      self.divider = self.period;
+    } else if self.divider == 0 {
+      self.divider = self.period;
+      if self.decay > 0 {
+        self.decay -= 1;
+      } else if self.looping {
+        self.decay = 15;
+      }
+    } else {
+      self.divider -= 1;
+    }
+  }
+
+  fn volume(&self) -> u8 {
+    if self.constant {
+      self.period
+    } else {
+      self.decay
+    }
+  }
+
+  fn save_state(&self, w: &mut StateWriter) {
+    w.bool(self.start);
+    w.bool(self.looping);
+    w.bool(self.constant);
+    w.u8(self.period);
+    w.u8(self.divider);
+    w.u8(self.decay);
+  }
+
+  fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+    self.start = r.bool()?;
+    self.looping = r.bool()?;
+    self.constant = r.bool()?;
+    self.period = r.u8_below(16)?;
+    self.divider = r.u8_below(16)?;
+    self.decay = r.u8_below(16)?;
+    Ok(())
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Apu {
+  pub pulse1: Pulse,
+  pub pulse2: Pulse,
+  pub triangle: Triangle,
+  pub noise: Noise,
+  pub dmc: Dmc,
+  pub mixer: Mixer,
+  five_step: bool,
+  irq_inhibit: bool,
+  frame_irq: bool,
+  frame_cycle: u32,
+  frame_irq_cycles: u8,
+  // CPU cycles until a $4017 write restarts the sequence
+  frame_reset_delay: u8,
+  // odd cycles are get cycles
+  odd_cycle: bool,
+  timing: Timing,
+
+  sample_rate: u32,
+  latency_ms: u32,
+  // how full the output queue was last seen, 0 to 1: the ring's, or the
+  // frontend's own passed to match_fill
+  queue_fill: f64,
+  // emulated seconds per second of output
+  speed: f64,
+  gain: f32,
+  resampler: Resampler,
+  samples: SampleRing,
+  // a second stream at a rate that never moves, for recordings
+  capture: Option<(Resampler, Vec<f32>)>,
+}
+
+impl Default for Apu {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Apu {
+  pub fn new() -> Self {
+    Apu {
+      pulse1: Pulse::new(true),
+      pulse2: Pulse::new(false),
+      triangle: Triangle::default(),
+      noise: Noise::default(),
+      dmc: Dmc::default(),
+      mixer: Mixer::default(),
+      five_step: false,
+      irq_inhibit: false,
+      frame_irq: false,
+      frame_cycle: 0,
+      frame_irq_cycles: 0,
+      frame_reset_delay: 0,
+      odd_cycle: false,
+      timing: Timing::Ntsc,
+      sample_rate: DEFAULT_SAMPLE_RATE,
+      latency_ms: DEFAULT_LATENCY_MS,
+      queue_fill: 0.0,
+      speed: 1.0,
+      gain: 1.0,
+      resampler: Resampler::new(CPU_CLOCK, DEFAULT_SAMPLE_RATE as f64),
+      samples: SampleRing::new(ring_capacity(DEFAULT_SAMPLE_RATE, DEFAULT_LATENCY_MS)),
+      capture: None,
+    }
+  }
+
+  /// Rate of the samples handed out by `fill_audio` and `take_samples`,
+  /// normally the AudioContext's. Drops whatever is buffered.
+  pub fn set_sample_rate(&mut self, rate: u32) {
+    self.sample_rate = rate.max(1);
+    self
+      .resampler
+      .set_output_rate(self.sample_rate as f64 / self.speed);
+    self.resize_ring();
+  }
+
+  pub fn sample_rate(&self) -> u32 {
+    self.sample_rate
+  }
+
+  /// How much sound to keep queued ahead of the speaker, clamped to
+  /// `MIN_LATENCY_MS`-`MAX_LATENCY_MS`: the ring holds twice that and is
+  /// kept half full. Drops whatever is buffered.
+  pub fn set_latency(&mut self, ms: u32) {
+    self.latency_ms = ms.clamp(MIN_LATENCY_MS, MAX_LATENCY_MS);
+    self.resize_ring();
+  }
+
+  /// The target `set_latency` set, in milliseconds.
+  pub fn latency(&self) -> u32 {
+    self.latency_ms
+  }
+
+  /// The sound actually queued, in milliseconds, as of the last
+  /// `fill_audio` or `match_fill`.
+  pub fn measured_latency(&self) -> f64 {
+    self.queue_fill * self.samples.capacity() as f64 * 1000.0 / self.sample_rate as f64
+  }
+
+  fn resize_ring(&mut self) {
+    self.samples = SampleRing::new(ring_capacity(self.sample_rate, self.latency_ms));
+    self.queue_fill = 0.0;
+  }
+
+  /// Play `speed` seconds of emulation per second of output, for
+  /// fast-forward (above 1) and slow motion (below): the sound is squeezed
+  /// or stretched to fit, changing pitch, and turned down while going
+  /// faster.
+  pub fn set_speed(&mut self, speed: f64) {
+    self.speed = speed;
+    self.gain = if speed > 1.0 { FAST_FORWARD_GAIN } else { 1.0 };
+    self
+      .resampler
+      .set_output_rate(self.sample_rate as f64 / speed);
+  }
+
+  pub fn speed(&self) -> f64 {
+    self.speed
+  }
+
+  /// Follow the CPU clock of `timing`: frame counter steps, noise and DMC
+  /// periods, and the resampler's input rate.
+  pub fn set_timing(&mut self, timing: Timing) {
+    self.timing = timing;
+    self.noise.set_timing(timing);
+    self.dmc.set_timing(timing);
+    self.resampler.set_input_rate(timing.cpu_clock());
+    if let Some((resampler, _)) = &mut self.capture {
+      resampler.set_input_rate(timing.cpu_clock());
+    }
+  }
+
+  /// Also resample the mixer output to `rate` Hz for `take_captured`, or
+  /// stop with None. Unlike `take_samples` the rate is never bent to keep
+  /// up with a sound card, nor the volume turned down while
+  /// fast-forwarding: the same run captures the same samples.
+  pub fn set_capture_rate(&mut self, rate: Option<u32>) {
+    let input_rate = self.timing.cpu_clock();
+    self.capture = rate.map(|rate| (Resampler::new(input_rate, rate.max(1) as f64), Vec::new()));
+  }
+
+  pub fn capture_rate(&self) -> Option<u32> {
+    self
+      .capture
+      .as_ref()
+      .map(|(resampler, _)| resampler.output_rate() as u32)
+  }
+
+  /// Drain the captured samples, in [0, 1].
+  pub fn take_captured(&mut self) -> Vec<f32> {
+    match &mut self.capture {
+      Some((_, captured)) => std::mem::take(captured),
+      None => Vec::new(),
+    }
+  }
+
+  fn four_step(&self) -> &'static [u32; 4] {
+    match self.timing {
+      Timing::Pal => &PAL_FOUR_STEP,
+      Timing::Ntsc | Timing::Dendy => &FOUR_STEP,
+    }
+  }
+
+  fn five_step(&self) -> &'static [u32; 5] {
+    match self.timing {
+      Timing::Pal => &PAL_FIVE_STEP,
+      Timing::Ntsc | Timing::Dendy => &FIVE_STEP,
+    }
+  }
+
+  /// $4000-$4013, $4015 and $4017.
+  pub fn write_register(&mut self, addr: u16, value: u8) {
+    let index = addr & 0x03;
+    match addr {
+      0x4000..=0x4003 => self.pulse1.write(index, value),
+      0x4004..=0x4007 => self.pulse2.write(index, value),
+      0x4008..=0x400B => self.triangle.write(index, value),
+      0x400C..=0x400F => self.noise.write(index, value),
+      0x4010..=0x4013 => self.dmc.write(index, value),
+      0x4015 => self.write_status(value),
+      0x4017 => self.write_frame_counter(value),
+      _ => {}
+    }
+  }
+
+  /// $4015 write: enable or silence channels.
+  pub fn write_status(&mut self, value: u8) {
+    self.pulse1.set_enabled(value & 0x01 != 0);
+    self.pulse2.set_enabled(value & 0x02 != 0);
+    self.triangle.set_enabled(value & 0x04 != 0);
+    self.noise.set_enabled(value & 0x08 != 0);
+    self.dmc.set_enabled(value & 0x10 != 0);
+  }
+
+  /// $4015 read, which also acknowledges the frame IRQ.
+  pub fn read_status(&mut self) -> u8 {
+    let status = self.peek_status();
+    self.frame_irq = false;
+    status
+  }
+
+  /// $4015 without acknowledging anything.
+  pub fn peek_status(&self) -> u8 {
+    (self.pulse1.length_counter() > 0) as u8
+      | ((self.pulse2.length_counter() > 0) as u8) << 1
+      | ((self.triangle.length_counter() > 0) as u8) << 2
+      | ((self.noise.length_counter() > 0) as u8) << 3
+      | ((self.dmc.bytes_remaining() > 0) as u8) << 4
+      | (self.frame_irq as u8) << 6
+      | (self.dmc.irq() as u8) << 7
+  }
+
+  /// $4017. Inhibiting the IRQ is immediate, the new mode starts 3 or 4
+  /// cycles later.
+  pub fn write_frame_counter(&mut self, value: u8) {
+    self.five_step = value & 0x80 != 0;
+    self.irq_inhibit = value & 0x40 != 0;
+    if self.irq_inhibit {
+      self.frame_irq = false;
+      self.frame_irq_cycles = 0;
+    }
+    self.frame_reset_delay = if self.odd_cycle { 3 } else { 4 };
+  }
+
+  /// Whether the next cycle is a get cycle.
+  pub fn get_cycle(&self) -> bool {
+    self.odd_cycle
+  }
+
+  fn reset_frame_counter(&mut self) {
+    self.frame_cycle = 0;
+    // 5-step mode clocks everything right away
+    if self.five_step {
+      self.quarter_frame();
+      self.half_frame();
+    }
+  }
+
+  /// Whether the frame counter or the DMC is holding IRQ.
+  pub fn irq_pending(&self) -> bool {
+    self.frame_irq || self.dmc.irq()
+  }
+
+  /// Advance by `cycles` CPU cycles, reading DMC samples from `cart`.
+  /// Returns the CPU cycles those reads stole.
+  pub fn tick(&mut self, cart: &dyn Mapper, cycles: u32) -> u32 {
+    (0..cycles).map(|_| self.cycle(cart)).sum()
+  }
+
+  fn cycle(&mut self, cart: &dyn Mapper) -> u32 {
+    let get_cycle = self.odd_cycle;
+    if get_cycle {
+      self.pulse1.clock_timer();
+      self.pulse2.clock_timer();
+    }
+    self.odd_cycle = !self.odd_cycle;
+    self.triangle.clock_timer();
+    self.noise.clock_timer();
+    let stall = self.dmc.clock(cart, get_cycle);
+
+    if self.frame_reset_delay > 0 {
+      self.frame_reset_delay -= 1;
+      if self.frame_reset_delay == 0 {
+        self.reset_frame_counter();
+      }
+    }
+
+    self.frame_cycle += 1;
+    if !self.five_step && !self.irq_inhibit && self.frame_cycle == self.four_step()[3] - 1 {
+      self.frame_irq_cycles = FRAME_IRQ_CYCLES;
+    }
+    if self.frame_irq_cycles > 0 {
+      self.frame_irq_cycles -= 1;
+      self.frame_irq = true;
+    }
+    let steps: &[u32] = if self.five_step {
+      self.five_step()
+    } else {
+      self.four_step()
+    };
+    if let Some(step) = steps.iter().position(|&c| c == self.frame_cycle) {
+      let last = step == steps.len() - 1;
+      // the 5-step sequence's fourth step is silent
+      if !(self.five_step && step == 3) {
+        self.quarter_frame();
+      }
+      if step == 1 || last {
+        self.half_frame();
+      }
+      if last {
+        self.frame_cycle = 0;
+      }
+    }
+
+    let output = self.output_with(cart);
+    if let Some(sample) = self.resampler.push(output) {
+      self.samples.push(sample * self.gain * self.mixer.volume);
+    }
+    if let Some((resampler, captured)) = &mut self.capture {
+      captured.extend(resampler.push(output));
+    }
+    stall
+  }
+
+  fn quarter_frame(&mut self) {
+    self.pulse1.quarter_frame();
+    self.pulse2.quarter_frame();
+    self.triangle.quarter_frame();
+    self.noise.quarter_frame();
+  }
+
+  fn half_frame(&mut self) {
+    self.pulse1.half_frame();
+    self.pulse2.half_frame();
+    self.triangle.half_frame();
+    self.noise.half_frame();
+  }
+
+  /// Mixer output right now, 1 << 16 being full scale.
+  pub fn level(&self) -> u32 {
+    if !self.mixer.is_flat() {
+      return self.level_with_gains();
+    }
+    let pulse = self.pulse1.output() + self.pulse2.output();
+    let tnd = 3 * self.triangle.output() as usize
+      + 2 * self.noise.output() as usize
+      + self.dmc.output() as usize;
+    PULSE_TABLE[pulse as usize] + TND_TABLE[tnd]
+  }
+
+  // the tables' formulas, with each channel's level scaled by `mixer`
+  fn level_with_gains(&self) -> u32 {
+    let level =
+      |channel, output: u8| output as f64 * self.mixer.audible(MixerInput::Channel(channel)) as f64;
+    let pulse =
+      level(Channel::Pulse1, self.pulse1.output()) + level(Channel::Pulse2, self.pulse2.output());
+    let tnd = 3.0 * level(Channel::Triangle, self.triangle.output())
+      + 2.0 * level(Channel::Noise, self.noise.output())
+      + level(Channel::Dmc, self.dmc.output());
+    mix_level(95.88, 8128.0, pulse) + mix_level(163.67, 24329.0, tnd)
+  }
+
+  /// Mixer output right now, in [0, 1].
+  pub fn output(&self) -> f32 {
+    self.level() as f32 / MIX_ONE as f32
+  }
+
+  // with the cartridge's sound chip on top, all of it past 1 if both are
+  // loud enough
+  fn output_with(&self, cart: &dyn Mapper) -> f32 {
+    let expansion = match self.mixer.audible(MixerInput::Expansion) {
+      gain if gain >= 1.0 => cart.audio_level(),
+      gain => (cart.audio_level() as f64 * gain as f64) as u32,
+    };
+    (self.level() + expansion) as f32 / MIX_ONE as f32
+  }
+
+  /// Fill an audio callback's buffer, holding the last level if the
+  /// emulator is behind. Returns how many samples were real.
+  pub fn fill_audio(&mut self, out: &mut [f32]) -> usize {
+    let filled = self.samples.pop_into(out);
+    self.match_fill(self.samples.len() as f64 / self.samples.capacity() as f64);
+    filled
+  }
+
+  /// Keep a queue `fill` (0 to 1) full at about half, the way `fill_audio`
+  /// does with the ring, for consumers that queue `take_samples` on their
+  /// own, e.g. for an audio thread. For `measured_latency` to hold, `fill`
+  /// should be of the ring's `capacity`, however big the queue really is.
+  pub fn match_fill(&mut self, fill: f64) {
+    self.queue_fill = fill.clamp(0.0, 1.0);
+    // empty: run fast, full: run slow
+    self
+      .resampler
+      .set_adjust((1.0 - 2.0 * self.queue_fill) * MAX_RATE_ADJUST);
+  }
+
+  /// Drain the samples generated since the last call, for consumers with
+  /// a queue of their own.
+  pub fn take_samples(&mut self) -> Vec<f32> {
+    self.samples.drain()
+  }
+
+  /// Trade the output side, resampled sound and recording, with `other`,
+  /// leaving the channels as they are. Rollback swaps the output back in
+  /// after running frames again, so what was queued isn't played twice.
+  pub fn swap_output(&mut self, other: &mut Apu) {
+    std::mem::swap(&mut self.resampler, &mut other.resampler);
+    std::mem::swap(&mut self.samples, &mut other.samples);
+    std::mem::swap(&mut self.capture, &mut other.capture);
+  }
+
+  pub fn buffered_samples(&self) -> usize {
+    self.samples.len()
+  }
+
+  /// The sample ring, for its underrun and overrun counts.
+  pub fn sample_ring(&self) -> &SampleRing {
+    &self.samples
+  }
+
+  /// Current speed-up (or slow-down, if negative) of the resampler.
+  pub fn rate_adjust(&self) -> f64 {
+    self.resampler.adjust()
+  }
+
+  /// The channels and the frame counter. Output already resampled isn't
+  /// part of the machine.
+  pub fn save_state(&self, w: &mut StateWriter) {
+    self.pulse1.save_state(w);
+    self.pulse2.save_state(w);
+    self.triangle.save_state(w);
+    self.noise.save_state(w);
+    self.dmc.save_state(w);
+    w.bool(self.five_step);
+    w.bool(self.irq_inhibit);
+    w.bool(self.frame_irq);
+    w.u32(self.frame_cycle);
+    w.u8(self.frame_irq_cycles);
+    w.u8(self.frame_reset_delay);
+    w.bool(self.odd_cycle);
+  }
+
+  pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+    self.pulse1.load_state(r)?;
+    self.pulse2.load_state(r)?;
+    self.triangle.load_state(r)?;
+    self.noise.load_state(r)?;
+    self.dmc.load_state(r)?;
+    self.five_step = r.bool()?;
+    self.irq_inhibit = r.bool()?;
+    self.frame_irq = r.bool()?;
+    self.frame_cycle = r.u32()?;
+    if self.frame_cycle > self.five_step()[4] {
+      return Err(StateError::Corrupt);
+    }
+    self.frame_irq_cycles = r.u8_below(FRAME_IRQ_CYCLES + 1)?;
+    self.frame_reset_delay = r.u8_below(5)?;
+    self.odd_cycle = r.bool()?;
+    Ok(())
+  }
+}
+
+// twice the latency, so half full is on target
+fn ring_capacity(sample_rate: u32, latency_ms: u32) -> usize {
+  sample_rate as usize * 2 * latency_ms as usize / 1000
+}
@@ -0,0 +1,159 @@
+use super::{Apu, Pulse};
+use std::collections::VecDeque;
+
+/*
+  What each channel is playing, sampled once a frame at the start of
+  vblank, for piano rolls and spectrum views. Pitch comes from the timer
+  periods, so it's what the channel is set to play, not an analysis of the
+  output: a pulse channel muted by its sweep unit still has a frequency,
+  it just isn't `playing`.
+
+  The log only keeps the last few frames; a frontend polling every frame
+  loses nothing, one that stops polling doesn't grow it.
+*/
+
+// frames kept until somebody takes them
+const MAX_QUEUED: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Channel {
+  Pulse1,
+  Pulse2,
+  Triangle,
+  Noise,
+  Dmc,
+}
+
+impl Channel {
+  pub const ALL: [Channel; 5] = [
+    Channel::Pulse1,
+    Channel::Pulse2,
+    Channel::Triangle,
+    Channel::Noise,
+    Channel::Dmc,
+  ];
+
+  /// Stable id: "pulse1", "pulse2", "triangle", "noise", "dmc".
+  pub fn id(&self) -> &'static str {
+    match self {
+      Channel::Pulse1 => "pulse1",
+      Channel::Pulse2 => "pulse2",
+      Channel::Triangle => "triangle",
+      Channel::Noise => "noise",
+      Channel::Dmc => "dmc",
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      Channel::Pulse1 => "Pulse 1",
+      Channel::Pulse2 => "Pulse 2",
+      Channel::Triangle => "Triangle",
+      Channel::Noise => "Noise",
+      Channel::Dmc => "DMC",
+    }
+  }
+
+  pub fn from_id(id: &str) -> Option<Channel> {
+    Channel::ALL
+      .iter()
+      .copied()
+      .find(|channel| channel.id() == id)
+  }
+}
+
+/// One channel at one point in time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelState {
+  pub channel: Channel,
+  /// The timer reload value: as the game wrote it for pulse and
+  /// triangle, looked up from the rate index for noise and DMC.
+  pub period: u16,
+  /// Pitch in Hz. For noise the rate the LFSR shifts at, for the DMC the
+  /// bit rate of the sample.
+  pub frequency: f32,
+  /// 0 to 1: envelope or constant volume for pulse and noise, the output
+  /// level for the DMC. The triangle has no volume control, it's 1.
+  pub volume: f32,
+  /// Whether the channel is sounding a note: length counter running and
+  /// nothing muting it, or for the DMC sample bytes left.
+  pub playing: bool,
+}
+
+impl Apu {
+  /// What every channel is set to play right now, in `Channel::ALL` order.
+  pub fn channel_states(&self) -> [ChannelState; 5] {
+    let clock = self.timing.cpu_clock();
+    let pulse = |channel, pulse: &Pulse| {
+      let period = pulse.timer_period();
+      ChannelState {
+        channel,
+        period,
+        // 8 sequencer steps, each period + 1 APU cycles
+        frequency: (clock / (16.0 * (period as f64 + 1.0))) as f32,
+        volume: pulse.volume() as f32 / 15.0,
+        playing: pulse.sounding(),
+      }
+    };
+    let triangle = self.triangle.timer_period();
+    let noise = self.noise.timer_period();
+    let dmc = self.dmc.timer_period();
+    [
+      pulse(Channel::Pulse1, &self.pulse1),
+      pulse(Channel::Pulse2, &self.pulse2),
+      ChannelState {
+        channel: Channel::Triangle,
+        period: triangle,
+        // 32 steps, each period + 1 CPU cycles
+        frequency: (clock / (32.0 * (triangle as f64 + 1.0))) as f32,
+        volume: 1.0,
+        playing: self.triangle.sounding(),
+      },
+      ChannelState {
+        channel: Channel::Noise,
+        period: noise,
+        frequency: (clock / (noise as f64 + 1.0)) as f32,
+        volume: self.noise.volume() as f32 / 15.0,
+        playing: self.noise.length_counter() > 0,
+      },
+      ChannelState {
+        channel: Channel::Dmc,
+        period: dmc,
+        frequency: (clock / (dmc as f64 + 1.0)) as f32,
+        volume: self.dmc.output() as f32 / 127.0,
+        playing: self.dmc.bytes_remaining() > 0,
+      },
+    ]
+  }
+}
+
+/// The channels as they were at the start of a PPU frame's vblank.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelFrame {
+  pub frame: u64,
+  pub channels: [ChannelState; 5],
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ChannelLog {
+  queue: VecDeque<ChannelFrame>,
+}
+
+impl ChannelLog {
+  /// Queue the state of `apu` for `frame`, dropping the oldest frame when
+  /// the log is full.
+  pub fn record(&mut self, frame: u64, apu: &Apu) {
+    if self.queue.len() >= MAX_QUEUED {
+      self.queue.pop_front();
+    }
+    self.queue.push_back(ChannelFrame {
+      frame,
+      channels: apu.channel_states(),
+    });
+  }
+
+  /// Everything queued, oldest first.
+  pub fn take(&mut self) -> Vec<ChannelFrame> {
+    self.queue.drain(..).collect()
+  }
+}
@@ -0,0 +1,234 @@
+use crate::nes::mapper::Mapper;
+use crate::nes::savestate::{StateError, StateReader, StateWriter};
+use crate::nes::timing::Timing;
+
+// output clock periods in CPU cycles
+const RATES: [u16; 16] = [
+  428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+const PAL_RATES: [u16; 16] = [
+  398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+/// CPU cycles a sample fetch takes off the CPU when it starts on a put
+/// cycle: halt, dummy read, alignment, read. One less starting on a get
+/// cycle.
+pub const FETCH_STALL: u32 = 4;
+
+/// The delta modulation channel, $4010-$4013: 1-bit deltas read from
+/// $8000-$FFFF by DMA, nudging a 7-bit output level up or down by 2.
+#[derive(Debug, Clone)]
+pub struct Dmc {
+  irq_enabled: bool,
+  looping: bool,
+  timer: u16,
+  timer_period: u16,
+  // $4010's index into the rate table
+  rate: u8,
+  timing: Timing,
+  level: u8,
+
+  sample_address: u16,
+  sample_length: u16,
+  address: u16,
+  bytes_remaining: u16,
+  buffer: Option<u8>,
+
+  shift: u8,
+  bits_remaining: u8,
+  silence: bool,
+
+  irq: bool,
+}
+
+impl Default for Dmc {
+  fn default() -> Self {
+    Dmc {
+      irq_enabled: false,
+      looping: false,
+      timer: 0,
+      timer_period: RATES[0] - 1,
+      rate: 0,
+      timing: Timing::Ntsc,
+      level: 0,
+      sample_address: 0xC000,
+      sample_length: 1,
+      address: 0xC000,
+      bytes_remaining: 0,
+      buffer: None,
+      shift: 0,
+      bits_remaining: 8,
+      silence: true,
+      irq: false,
+    }
+  }
+}
+
+impl Dmc {
+  /// Register `index` (0-3) of the channel.
+  pub(super) fn write(&mut self, index: u16, value: u8) {
+    match index {
+      0 => {
+        self.irq_enabled = value & 0x80 != 0;
+        if !self.irq_enabled {
+          self.irq = false;
+        }
+        self.looping = value & 0x40 != 0;
+        self.rate = value & 0x0F;
+        self.update_rate();
+      }
+      1 => self.level = value & 0x7F,
+      2 => self.sample_address = 0xC000 | (value as u16) << 6,
+      _ => self.sample_length = (value as u16) << 4 | 1,
+    }
+  }
+
+  pub(super) fn set_timing(&mut self, timing: Timing) {
+    self.timing = timing;
+    self.update_rate();
+  }
+
+  fn update_rate(&mut self) {
+    let rates = match self.timing {
+      Timing::Pal => &PAL_RATES,
+      Timing::Ntsc | Timing::Dendy => &RATES,
+    };
+    self.timer_period = rates[self.rate as usize] - 1;
+  }
+
+  /// $4015 bit 4: start the sample if it isn't playing, or stop it.
+  /// Either way acknowledges the DMC IRQ.
+  pub(super) fn set_enabled(&mut self, enabled: bool) {
+    self.irq = false;
+    if !enabled {
+      self.bytes_remaining = 0;
+    } else if self.bytes_remaining == 0 {
+      self.restart();
+    }
+  }
+
+  fn restart(&mut self) {
+    self.address = self.sample_address;
+    self.bytes_remaining = self.sample_length;
+  }
+
+  /// Clocked every CPU cycle. Returns the cycles stolen from the CPU by a
+  /// sample fetch.
+  pub(super) fn clock(&mut self, cart: &dyn Mapper, get_cycle: bool) -> u32 {
+    let stall = self.fetch(cart, get_cycle);
+    if self.timer == 0 {
+      self.timer = self.timer_period;
+      self.clock_output();
+    } else {
+      self.timer -= 1;
+    }
+    stall
+  }
+
+  fn fetch(&mut self, cart: &dyn Mapper, get_cycle: bool) -> u32 {
+    if self.buffer.is_some() || self.bytes_remaining == 0 {
+      return 0;
+    }
+    self.buffer = Some(cart.prg_read(self.address));
+    // wraps to $8000, not $0000
+    self.address = self.address.checked_add(1).unwrap_or(0x8000);
+    self.bytes_remaining -= 1;
+    if self.bytes_remaining == 0 {
+      if self.looping {
+        self.restart();
+      } else if self.irq_enabled {
+        self.irq = true;
+      }
+    }
+    // the read lands on the next-but-one cycle, a get cycle if this is one
+    if get_cycle {
+      FETCH_STALL - 1
+    } else {
+      FETCH_STALL
+    }
+  }
+
+  fn clock_output(&mut self) {
+    if !self.silence {
+      if self.shift & 1 != 0 {
+        if self.level <= 125 {
+          self.level += 2;
+        }
+      } else if self.level >= 2 {
+        self.level -= 2;
+      }
+    }
+    self.shift >>= 1;
+    self.bits_remaining -= 1;
+    if self.bits_remaining == 0 {
+      self.bits_remaining = 8;
+      match self.buffer.take() {
+        Some(byte) => {
+          self.silence = false;
+          self.shift = byte;
+        }
+        None => self.silence = true,
+      }
+    }
+  }
+
+  /// Current 7-bit output level.
+  pub fn output(&self) -> u8 {
+    self.level
+  }
+
+  pub fn bytes_remaining(&self) -> u16 {
+    self.bytes_remaining
+  }
+
+  pub fn timer_period(&self) -> u16 {
+    self.timer_period
+  }
+
+  pub(super) fn save_state(&self, w: &mut StateWriter) {
+    w.bool(self.irq_enabled);
+    w.bool(self.looping);
+    w.u16(self.timer);
+    w.u8(self.rate);
+    w.u8(self.level);
+    w.u16(self.sample_address);
+    w.u16(self.sample_length);
+    w.u16(self.address);
+    w.u16(self.bytes_remaining);
+    w.bool(self.buffer.is_some());
+    w.u8(self.buffer.unwrap_or(0));
+    w.u8(self.shift);
+    w.u8(self.bits_remaining);
+    w.bool(self.silence);
+    w.bool(self.irq);
+  }
+
+  pub(super) fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+    self.irq_enabled = r.bool()?;
+    self.looping = r.bool()?;
+    self.timer = r.u16()?;
+    self.rate = r.u8_below(16)?;
+    self.update_rate();
+    self.level = r.u8_below(128)?;
+    self.sample_address = r.u16()?;
+    self.sample_length = r.u16()?;
+    self.address = r.u16()?;
+    self.bytes_remaining = r.u16()?;
+    let buffered = r.bool()?;
+    let buffer = r.u8()?;
+    self.buffer = if buffered { Some(buffer) } else { None };
+    self.shift = r.u8()?;
+    // counts down to 0 and reloads, 0 itself never stays
+    self.bits_remaining = match r.u8_below(9)? {
+      0 => return Err(StateError::Corrupt),
+      bits => bits,
+    };
+    self.silence = r.bool()?;
+    self.irq = r.bool()?;
+    Ok(())
+  }
+
+  pub fn irq(&self) -> bool {
+    self.irq
+  }
+}
@@ -0,0 +1,134 @@
+use super::Channel;
+
+/*
+  What the listener hears of each channel: a gain for each, muting and
+  soloing on top, and a master volume. Like the PPU's `Layers` it changes
+  nothing the game can see, only the sound, and it's a setting of the
+  frontend's rather than part of the machine: savestates don't keep it.
+
+  Channel gains go in before the non-linear DACs, as if the channel were
+  that much quieter, so turning one down changes how the others mix, as
+  it would on the console. The master volume goes on the played samples
+  and not on recordings, which keep their levels whatever the speakers
+  are set to.
+*/
+
+/// Something the mixer can turn up or down: the APU's channels, and
+/// sound from the cartridge.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MixerInput {
+  Channel(Channel),
+  /// Boards with their own sound chip, e.g. the Sunsoft 5B or Namco 163.
+  Expansion,
+}
+
+impl MixerInput {
+  pub const ALL: [MixerInput; 6] = [
+    MixerInput::Channel(Channel::Pulse1),
+    MixerInput::Channel(Channel::Pulse2),
+    MixerInput::Channel(Channel::Triangle),
+    MixerInput::Channel(Channel::Noise),
+    MixerInput::Channel(Channel::Dmc),
+    MixerInput::Expansion,
+  ];
+
+  /// Stable id: a `Channel`'s, or "expansion".
+  pub fn id(&self) -> &'static str {
+    match self {
+      MixerInput::Channel(channel) => channel.id(),
+      MixerInput::Expansion => "expansion",
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      MixerInput::Channel(channel) => channel.name(),
+      MixerInput::Expansion => "Expansion",
+    }
+  }
+
+  pub fn from_id(id: &str) -> Option<MixerInput> {
+    MixerInput::ALL
+      .iter()
+      .copied()
+      .find(|input| input.id() == id)
+  }
+
+  fn index(&self) -> usize {
+    match self {
+      MixerInput::Channel(channel) => *channel as usize,
+      MixerInput::Expansion => 5,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mixer {
+  /// 0 to 1.
+  pub volume: f32,
+  gains: [f32; 6],
+  muted: [bool; 6],
+  solo: [bool; 6],
+}
+
+impl Default for Mixer {
+  fn default() -> Self {
+    Mixer {
+      volume: 1.0,
+      gains: [1.0; 6],
+      muted: [false; 6],
+      solo: [false; 6],
+    }
+  }
+}
+
+impl Mixer {
+  /// 0 to 1, 0 being silent; out of range values are clamped.
+  pub fn set_gain(&mut self, input: MixerInput, gain: f32) {
+    self.gains[input.index()] = gain.clamp(0.0, 1.0);
+  }
+
+  pub fn gain(&self, input: MixerInput) -> f32 {
+    self.gains[input.index()]
+  }
+
+  pub fn set_muted(&mut self, input: MixerInput, muted: bool) {
+    self.muted[input.index()] = muted;
+  }
+
+  pub fn muted(&self, input: MixerInput) -> bool {
+    self.muted[input.index()]
+  }
+
+  /// With any input soloed only the soloed ones are heard, muted or not.
+  pub fn set_solo(&mut self, input: MixerInput, solo: bool) {
+    self.solo[input.index()] = solo;
+  }
+
+  pub fn solo(&self, input: MixerInput) -> bool {
+    self.solo[input.index()]
+  }
+
+  /// How loud `input` ends up after muting and soloing.
+  pub fn audible(&self, input: MixerInput) -> f32 {
+    let i = input.index();
+    let heard = if self.solo.contains(&true) {
+      self.solo[i]
+    } else {
+      !self.muted[i]
+    };
+    if heard {
+      self.gains[i]
+    } else {
+      0.0
+    }
+  }
+
+  /// Whether every channel is heard as it is, so the mix can take the
+  /// lookup tables.
+  pub fn is_flat(&self) -> bool {
+    Channel::ALL
+      .iter()
+      .all(|&channel| self.audible(MixerInput::Channel(channel)) >= 1.0)
+  }
+}
@@ -0,0 +1,162 @@
+use super::{Envelope, LENGTH_TABLE};
+use crate::nes::savestate::{StateError, StateReader, StateWriter};
+use crate::nes::timing::Timing;
+
+// timer periods in CPU cycles
+const PERIODS: [u16; 16] = [
+  4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+const PAL_PERIODS: [u16; 16] = [
+  4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+/// The noise channel, $400C-$400F: a 15-bit LFSR, in short mode tapping
+/// bit 6 instead of bit 1 for a metallic 93-step loop.
+#[derive(Debug, Clone)]
+pub struct Noise {
+  enabled: bool,
+  short_mode: bool,
+  shift: u16,
+  timer: u16,
+  timer_period: u16,
+  // $400E's index into the period table
+  period: u8,
+  timing: Timing,
+  length: u8,
+  length_halt: bool,
+  envelope: Envelope,
+}
+
+impl Default for Noise {
+  fn default() -> Self {
+    Noise {
+      enabled: false,
+      short_mode: false,
+      // loaded with 1 at power on
+      shift: 1,
+      timer: 0,
+      timer_period: PERIODS[0] - 1,
+      period: 0,
+      timing: Timing::Ntsc,
+      length: 0,
+      length_halt: false,
+      envelope: Envelope::default(),
+    }
+  }
+}
+
+impl Noise {
+  /// Register `index` (0-3) of the channel; 1 is unused.
+  pub(super) fn write(&mut self, index: u16, value: u8) {
+    match index {
+      0 => {
+        self.length_halt = value & 0x20 != 0;
+        self.envelope.write(value);
+      }
+      1 => {}
+      2 => {
+        self.short_mode = value & 0x80 != 0;
+        self.period = value & 0x0F;
+        self.update_period();
+      }
+      _ => {
+        if self.enabled {
+          self.length = LENGTH_TABLE[(value >> 3) as usize];
+        }
+        self.envelope.start = true;
+      }
+    }
+  }
+
+  pub(super) fn set_timing(&mut self, timing: Timing) {
+    self.timing = timing;
+    self.update_period();
+  }
+
+  fn update_period(&mut self) {
+    let periods = match self.timing {
+      Timing::Pal => &PAL_PERIODS,
+      Timing::Ntsc | Timing::Dendy => &PERIODS,
+    };
+    self.timer_period = periods[self.period as usize] - 1;
+  }
+
+  pub(super) fn set_enabled(&mut self, enabled: bool) {
+    self.enabled = enabled;
+    if !enabled {
+      self.length = 0;
+    }
+  }
+
+  /// Clocked every CPU cycle.
+  pub(super) fn clock_timer(&mut self) {
+    if self.timer == 0 {
+      self.timer = self.timer_period;
+      let tap = if self.short_mode { 6 } else { 1 };
+      let feedback = (self.shift ^ (self.shift >> tap)) & 1;
+      self.shift = (self.shift >> 1) | (feedback << 14);
+    } else {
+      self.timer -= 1;
+    }
+  }
+
+  pub(super) fn quarter_frame(&mut self) {
+    self.envelope.clock();
+  }
+
+  pub(super) fn half_frame(&mut self) {
+    if self.length > 0 && !self.length_halt {
+      self.length -= 1;
+    }
+  }
+
+  /// Current 4-bit output level.
+  pub fn output(&self) -> u8 {
+    if self.length == 0 || self.shift & 1 != 0 {
+      0
+    } else {
+      self.envelope.volume()
+    }
+  }
+
+  pub fn length_counter(&self) -> u8 {
+    self.length
+  }
+
+  /// Envelope or constant volume, before the LFSR and muting.
+  pub fn volume(&self) -> u8 {
+    self.envelope.volume()
+  }
+
+  pub fn timer_period(&self) -> u16 {
+    self.timer_period
+  }
+
+  pub(super) fn save_state(&self, w: &mut StateWriter) {
+    w.bool(self.enabled);
+    w.bool(self.short_mode);
+    w.u16(self.shift);
+    w.u16(self.timer);
+    w.u8(self.period);
+    w.u8(self.length);
+    w.bool(self.length_halt);
+    self.envelope.save_state(w);
+  }
+
+  pub(super) fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+    self.enabled = r.bool()?;
+    self.short_mode = r.bool()?;
+    self.shift = r.u16()?;
+    self.timer = r.u16()?;
+    self.period = r.u8_below(16)?;
+    self.update_period();
+    self.length = r.u8()?;
+    self.length_halt = r.bool()?;
+    self.envelope.load_state(r)
+  }
+
+  /// The LFSR, for tests and debuggers.
+  pub fn shift_register(&self) -> u16 {
+    self.shift
+  }
+}
@@ -0,0 +1,201 @@
+use super::{Envelope, LENGTH_TABLE};
+use crate::nes::savestate::{StateError, StateReader, StateWriter};
+
+const DUTY_CYCLES: [[u8; 8]; 4] = [
+  [0, 1, 0, 0, 0, 0, 0, 0],
+  [0, 1, 1, 0, 0, 0, 0, 0],
+  [0, 1, 1, 1, 1, 0, 0, 0],
+  [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+#[derive(Debug, Clone, Default)]
+struct Sweep {
+  enabled: bool,
+  period: u8,
+  negate: bool,
+  shift: u8,
+  divider: u8,
+  reload: bool,
+}
+
+/// One of the two square wave channels, $4000-$4003 and $4004-$4007.
+#[derive(Debug, Clone)]
+pub struct Pulse {
+  // pulse 1 negates with one's complement, pulse 2 with two's
+  ones_complement: bool,
+  enabled: bool,
+  duty: u8,
+  step: u8,
+  timer: u16,
+  timer_period: u16,
+  length: u8,
+  length_halt: bool,
+  envelope: Envelope,
+  sweep: Sweep,
+}
+
+impl Pulse {
+  pub(super) fn new(ones_complement: bool) -> Self {
+    Pulse {
+      ones_complement,
+      enabled: false,
+      duty: 0,
+      step: 0,
+      timer: 0,
+      timer_period: 0,
+      length: 0,
+      length_halt: false,
+      envelope: Envelope::default(),
+      sweep: Sweep::default(),
+    }
+  }
+
+  /// Register `index` (0-3) of the channel.
+  pub(super) fn write(&mut self, index: u16, value: u8) {
+    match index {
+      0 => {
+        self.duty = value >> 6;
+        self.length_halt = value & 0x20 != 0;
+        self.envelope.write(value);
+      }
+      1 => {
+        self.sweep.enabled = value & 0x80 != 0;
+        self.sweep.period = (value >> 4) & 0x07;
+        self.sweep.negate = value & 0x08 != 0;
+        self.sweep.shift = value & 0x07;
+        self.sweep.reload = true;
+      }
+      2 => self.timer_period = (self.timer_period & 0x0700) | value as u16,
+      _ => {
+        self.timer_period = (self.timer_period & 0x00FF) | ((value as u16 & 0x07) << 8);
+        if self.enabled {
+          self.length = LENGTH_TABLE[(value >> 3) as usize];
+        }
+        self.step = 0;
+        self.envelope.start = true;
+      }
+    }
+  }
+
+  pub(super) fn set_enabled(&mut self, enabled: bool) {
+    self.enabled = enabled;
+    if !enabled {
+      self.length = 0;
+    }
+  }
+
+  /// Clocked every APU cycle.
+  pub(super) fn clock_timer(&mut self) {
+    if self.timer == 0 {
+      self.timer = self.timer_period;
+      self.step = (self.step + 1) % 8;
+    } else {
+      self.timer -= 1;
+    }
+  }
+
+  pub(super) fn quarter_frame(&mut self) {
+    self.envelope.clock();
+  }
+
+  pub(super) fn half_frame(&mut self) {
+    if self.length > 0 && !self.length_halt {
+      self.length -= 1;
+    }
+    self.clock_sweep();
+  }
+
+  fn sweep_target(&self) -> u16 {
+    let change = self.timer_period >> self.sweep.shift;
+    if self.sweep.negate {
+      let change = change + self.ones_complement as u16;
+      self.timer_period.saturating_sub(change)
+    } else {
+      self.timer_period + change
+    }
+  }
+
+  // the sweep unit mutes the channel even while it isn't adjusting the
+  // period
+  fn sweep_mutes(&self) -> bool {
+    self.timer_period < 8 || self.sweep_target() > 0x7FF
+  }
+
+  fn clock_sweep(&mut self) {
+    if self.sweep.divider == 0 && self.sweep.enabled && self.sweep.shift > 0 && !self.sweep_mutes()
+    {
+      self.timer_period = self.sweep_target();
+    }
+    if self.sweep.divider == 0 || self.sweep.reload {
+      self.sweep.divider = self.sweep.period;
+      self.sweep.reload = false;
+    } else {
+      self.sweep.divider -= 1;
+    }
+  }
+
+  /// Current 4-bit output level.
+  pub fn output(&self) -> u8 {
+    if self.length == 0
+      || self.sweep_mutes()
+      || DUTY_CYCLES[self.duty as usize][self.step as usize] == 0
+    {
+      0
+    } else {
+      self.envelope.volume()
+    }
+  }
+
+  /// Envelope or constant volume, before the duty cycle and muting.
+  pub fn volume(&self) -> u8 {
+    self.envelope.volume()
+  }
+
+  pub fn length_counter(&self) -> u8 {
+    self.length
+  }
+
+  pub fn timer_period(&self) -> u16 {
+    self.timer_period
+  }
+
+  /// Length counter running and the sweep unit not muting the channel.
+  pub fn sounding(&self) -> bool {
+    self.length > 0 && !self.sweep_mutes()
+  }
+
+  pub(super) fn save_state(&self, w: &mut StateWriter) {
+    w.bool(self.enabled);
+    w.u8(self.duty);
+    w.u8(self.step);
+    w.u16(self.timer);
+    w.u16(self.timer_period);
+    w.u8(self.length);
+    w.bool(self.length_halt);
+    self.envelope.save_state(w);
+    w.bool(self.sweep.enabled);
+    w.u8(self.sweep.period);
+    w.bool(self.sweep.negate);
+    w.u8(self.sweep.shift);
+    w.u8(self.sweep.divider);
+    w.bool(self.sweep.reload);
+  }
+
+  pub(super) fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+    self.enabled = r.bool()?;
+    self.duty = r.u8_below(4)?;
+    self.step = r.u8_below(8)?;
+    self.timer = r.u16()?;
+    self.timer_period = r.u16()?;
+    self.length = r.u8()?;
+    self.length_halt = r.bool()?;
+    self.envelope.load_state(r)?;
+    self.sweep.enabled = r.bool()?;
+    self.sweep.period = r.u8_below(8)?;
+    self.sweep.negate = r.bool()?;
+    self.sweep.shift = r.u8_below(8)?;
+    self.sweep.divider = r.u8_below(8)?;
+    self.sweep.reload = r.bool()?;
+    Ok(())
+  }
+}
@@ -0,0 +1,78 @@
+/// Averages a fast input stream down to a slower output rate.
+///
+/// Each output sample is the mean of the input over exactly its own span of
+/// time, inputs straddling a boundary split between the two outputs by
+/// weight. Better than dropping or whole-sample averaging, which alias the
+/// pulse edges into audible hiss, and cheap enough to run every CPU cycle.
+#[derive(Debug, Clone)]
+pub struct Resampler {
+  input_rate: f64,
+  output_rate: f64,
+  adjust: f64,
+  // input samples per output sample
+  step: f64,
+  // input consumed towards the next output sample
+  phase: f64,
+  sum: f64,
+}
+
+impl Resampler {
+  pub fn new(input_rate: f64, output_rate: f64) -> Self {
+    let mut resampler = Resampler {
+      input_rate,
+      output_rate,
+      adjust: 0.0,
+      step: 0.0,
+      phase: 0.0,
+      sum: 0.0,
+    };
+    resampler.update_step();
+    resampler
+  }
+
+  fn update_step(&mut self) {
+    // below one input per output there'd be nothing to average
+    self.step = (self.input_rate / (self.output_rate * (1.0 + self.adjust))).max(1.0);
+  }
+
+  pub fn set_input_rate(&mut self, rate: f64) {
+    self.input_rate = rate;
+    self.update_step();
+  }
+
+  pub fn set_output_rate(&mut self, rate: f64) {
+    self.output_rate = rate;
+    self.update_step();
+  }
+
+  pub fn output_rate(&self) -> f64 {
+    self.output_rate
+  }
+
+  /// Produce `1 + adjust` times as many samples as the nominal rate, to
+  /// speed up or slow down a consumer's buffer by a hair.
+  pub fn set_adjust(&mut self, adjust: f64) {
+    self.adjust = adjust;
+    self.update_step();
+  }
+
+  pub fn adjust(&self) -> f64 {
+    self.adjust
+  }
+
+  /// Feed one input sample, returning an output sample when it completes
+  /// one.
+  pub fn push(&mut self, sample: f32) -> Option<f32> {
+    let sample = sample as f64;
+    let remaining = self.step - self.phase;
+    if remaining > 1.0 {
+      self.sum += sample;
+      self.phase += 1.0;
+      return None;
+    }
+    let out = (self.sum + sample * remaining) / self.step;
+    self.phase = 1.0 - remaining;
+    self.sum = sample * self.phase;
+    Some(out as f32)
+  }
+}
@@ -0,0 +1,106 @@
+/// Fixed-size FIFO between the emulator, which produces samples a frame at
+/// a time, and the audio callback, which consumes them at its own pace.
+///
+/// Neither side waits for the other. Filling it completely drops the oldest
+/// half, a single skip instead of one crackle per new sample; reading it
+/// dry holds the last level, as a jump to 0 would click.
+#[derive(Debug, Clone)]
+pub struct SampleRing {
+  buffer: Vec<f32>,
+  start: usize,
+  len: usize,
+  last: f32,
+  underruns: u32,
+  overruns: u32,
+}
+
+impl SampleRing {
+  pub fn new(capacity: usize) -> Self {
+    SampleRing {
+      buffer: vec![0.0; capacity.max(2)],
+      start: 0,
+      len: 0,
+      last: 0.0,
+      underruns: 0,
+      overruns: 0,
+    }
+  }
+
+  pub fn capacity(&self) -> usize {
+    self.buffer.len()
+  }
+
+  pub fn len(&self) -> usize {
+    self.len
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+
+  pub fn push(&mut self, sample: f32) {
+    if self.len == self.capacity() {
+      self.overruns += 1;
+      self.skip(self.len / 2);
+    }
+    let end = (self.start + self.len) % self.capacity();
+    self.buffer[end] = sample;
+    self.len += 1;
+  }
+
+  /// Drop the `count` oldest samples.
+  pub fn skip(&mut self, count: usize) {
+    let count = count.min(self.len);
+    if count > 0 {
+      self.last = self.buffer[(self.start + count - 1) % self.capacity()];
+    }
+    self.start = (self.start + count) % self.capacity();
+    self.len -= count;
+  }
+
+  fn pop(&mut self) -> Option<f32> {
+    if self.len == 0 {
+      return None;
+    }
+    self.last = self.buffer[self.start];
+    self.start = (self.start + 1) % self.capacity();
+    self.len -= 1;
+    Some(self.last)
+  }
+
+  /// Fill `out` with the oldest samples, padding with the last one if
+  /// there aren't enough. Returns how many were real.
+  pub fn pop_into(&mut self, out: &mut [f32]) -> usize {
+    let mut filled = 0;
+    for slot in out.iter_mut() {
+      match self.pop() {
+        Some(sample) => {
+          *slot = sample;
+          filled += 1;
+        }
+        None => *slot = self.last,
+      }
+    }
+    if filled < out.len() {
+      self.underruns += 1;
+    }
+    filled
+  }
+
+  /// Everything buffered, oldest first.
+  pub fn drain(&mut self) -> Vec<f32> {
+    let mut samples = vec![0.0; self.len];
+    self.pop_into(&mut samples);
+    samples
+  }
+
+  /// Times a read ran out of samples.
+  pub fn underruns(&self) -> u32 {
+    self.underruns
+  }
+
+  /// Times a full buffer dropped samples.
+  pub fn overruns(&self) -> u32 {
+    self.overruns
+  }
+}
@@ -0,0 +1,132 @@
+use super::LENGTH_TABLE;
+use crate::nes::savestate::{StateError, StateReader, StateWriter};
+
+#[rustfmt::skip]
+const SEQUENCE: [u8; 32] = [
+  15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+  0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+/// The triangle channel, $4008-$400B. No volume control; besides the
+/// length counter it has a linear counter with quarter frame resolution.
+#[derive(Debug, Clone, Default)]
+pub struct Triangle {
+  enabled: bool,
+  step: u8,
+  timer: u16,
+  timer_period: u16,
+  length: u8,
+  // also the length counter halt flag
+  control: bool,
+  linear: u8,
+  linear_reload_value: u8,
+  linear_reload: bool,
+}
+
+impl Triangle {
+  /// Register `index` (0-3) of the channel; 1 is unused.
+  pub(super) fn write(&mut self, index: u16, value: u8) {
+    match index {
+      0 => {
+        self.control = value & 0x80 != 0;
+        self.linear_reload_value = value & 0x7F;
+      }
+      1 => {}
+      2 => self.timer_period = (self.timer_period & 0x0700) | value as u16,
+      _ => {
+        self.timer_period = (self.timer_period & 0x00FF) | ((value as u16 & 0x07) << 8);
+        if self.enabled {
+          self.length = LENGTH_TABLE[(value >> 3) as usize];
+        }
+        self.linear_reload = true;
+      }
+    }
+  }
+
+  pub(super) fn set_enabled(&mut self, enabled: bool) {
+    self.enabled = enabled;
+    if !enabled {
+      self.length = 0;
+    }
+  }
+
+  /// Clocked every CPU cycle, twice as fast as the pulse timers.
+  pub(super) fn clock_timer(&mut self) {
+    if self.timer == 0 {
+      self.timer = self.timer_period;
+      // periods below 2 are ultrasonic: hold the level instead of
+      // popping between 7 and 8
+      if self.length > 0 && self.linear > 0 && self.timer_period >= 2 {
+        self.step = (self.step + 1) % 32;
+      }
+    } else {
+      self.timer -= 1;
+    }
+  }
+
+  pub(super) fn quarter_frame(&mut self) {
+    if self.linear_reload {
+      self.linear = self.linear_reload_value;
+    } else if self.linear > 0 {
+      self.linear -= 1;
+    }
+    if !self.control {
+      self.linear_reload = false;
+    }
+  }
+
+  pub(super) fn half_frame(&mut self) {
+    if self.length > 0 && !self.control {
+      self.length -= 1;
+    }
+  }
+
+  /// Current 4-bit output level. Silencing the channel stops the
+  /// sequencer where it is, it doesn't drop to 0.
+  pub fn output(&self) -> u8 {
+    SEQUENCE[self.step as usize]
+  }
+
+  pub fn length_counter(&self) -> u8 {
+    self.length
+  }
+
+  pub fn linear_counter(&self) -> u8 {
+    self.linear
+  }
+
+  pub fn timer_period(&self) -> u16 {
+    self.timer_period
+  }
+
+  /// Both counters running at an audible period, i.e. the sequencer
+  /// steps.
+  pub fn sounding(&self) -> bool {
+    self.length > 0 && self.linear > 0 && self.timer_period >= 2
+  }
+
+  pub(super) fn save_state(&self, w: &mut StateWriter) {
+    w.bool(self.enabled);
+    w.u8(self.step);
+    w.u16(self.timer);
+    w.u16(self.timer_period);
+    w.u8(self.length);
+    w.bool(self.control);
+    w.u8(self.linear);
+    w.u8(self.linear_reload_value);
+    w.bool(self.linear_reload);
+  }
+
+  pub(super) fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+    self.enabled = r.bool()?;
+    self.step = r.u8_below(32)?;
+    self.timer = r.u16()?;
+    self.timer_period = r.u16()?;
+    self.length = r.u8()?;
+    self.control = r.bool()?;
+    self.linear = r.u8()?;
+    self.linear_reload_value = r.u8()?;
+    self.linear_reload = r.bool()?;
+    Ok(())
+  }
+}
@@ -0,0 +1,414 @@
+use crate::nes::cpu::AddressingMode;
+use crate::nes::opcodes::{OpCode, CPU_OPS_CODES};
+use std::collections::HashMap;
+use std::fmt;
+
+/*
+  A small 6502 assembler, so tests and examples can be written as
+  assembly instead of opcode bytes.
+
+  One statement per line, `;` to the end of the line is a comment:
+
+    PPUCTRL = $2000       ; a constant, from what's defined above it
+    reset:                ; a label, the address of what follows
+      LDX #$08
+    loop: DEX             ; a label and an instruction on one line
+      BNE loop
+      STA PPUCTRL,X
+      JMP (vector)
+    vector: .word reset   ; also .byte, and .org to skip ahead
+
+  Mnemonics are those of the disassembler, unofficial ones with their `*`
+  (`*LAX $10`). Numbers are decimal, `$` hex or `%` binary, and operands
+  can add and subtract them and labels, `<` and `>` taking the low or
+  high byte. An address goes in the zero page when its value is known by
+  then and fits, unless it's hex written with more than two digits
+  (`STA $0010`), as other assemblers do; labels further down are taken as
+  absolute. Branches are relative, `A` is the accumulator (`ASL A`).
+*/
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AsmError {
+  /// Line `line` (from 1) isn't a statement.
+  Syntax {
+    line: usize,
+  },
+  /// No such instruction, or not with that addressing mode.
+  UnknownInstruction {
+    line: usize,
+  },
+  UnknownLabel {
+    line: usize,
+    label: String,
+  },
+  DuplicateLabel {
+    line: usize,
+    label: String,
+  },
+  /// A branch too far away, a value too big for its byte or word, or an
+  /// `.org` behind what's been assembled already.
+  OutOfRange {
+    line: usize,
+  },
+}
+
+impl fmt::Display for AsmError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      AsmError::Syntax { line } => write!(f, "line {}: syntax error", line),
+      AsmError::UnknownInstruction { line } => write!(f, "line {}: no such instruction", line),
+      AsmError::UnknownLabel { line, label } => {
+        write!(f, "line {}: undefined label {}", line, label)
+      }
+      AsmError::DuplicateLabel { line, label } => {
+        write!(f, "line {}: {} is already defined", line, label)
+      }
+      AsmError::OutOfRange { line } => write!(f, "line {}: value out of range", line),
+    }
+  }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Assemble `source` to run from $8000, where `Rom::from_program` and
+/// `CPU::load` put it.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+  assemble_at(source, 0x8000)
+}
+
+/// Assemble `source` to run from `origin`, the address of the first byte.
+pub fn assemble_at(source: &str, origin: u16) -> Result<Vec<u8>, AsmError> {
+  let mut asm = Assembler {
+    symbols: HashMap::new(),
+    items: Vec::new(),
+    pc: origin as u32,
+  };
+  for (i, text) in source.lines().enumerate() {
+    asm.statement(i + 1, strip_comment(text).trim())?;
+  }
+
+  let mut bytes = Vec::new();
+  for item in &asm.items {
+    let value = |expr: &Expr| expr.eval(&asm.symbols, item.line);
+    bytes.extend(item.code);
+    match &item.operand {
+      Operand::None => {}
+      Operand::Pad(len) => bytes.resize(bytes.len() + len, 0),
+      Operand::Byte(expr) => match value(expr)? {
+        v @ -0x80..=0xff => bytes.push(v as u8),
+        _ => return Err(AsmError::OutOfRange { line: item.line }),
+      },
+      Operand::Word(expr) => match value(expr)? {
+        v @ -0x8000..=0xffff => bytes.extend_from_slice(&(v as u16).to_le_bytes()),
+        _ => return Err(AsmError::OutOfRange { line: item.line }),
+      },
+      Operand::Relative(expr) => match value(expr)? - (item.address as i32 + 2) {
+        offset @ -0x80..=0x7f => bytes.push(offset as u8),
+        _ => return Err(AsmError::OutOfRange { line: item.line }),
+      },
+    }
+  }
+  Ok(bytes)
+}
+
+fn strip_comment(text: &str) -> &str {
+  text.split(';').next().unwrap_or("")
+}
+
+// what the first pass leaves for the second: sizes and addresses are
+// settled, operands wait for the labels further down
+struct Item {
+  line: usize,
+  address: u16,
+  code: Option<u8>,
+  operand: Operand,
+}
+
+enum Operand {
+  None,
+  Pad(usize),
+  Byte(Expr),
+  Word(Expr),
+  Relative(Expr),
+}
+
+impl Operand {
+  fn len(&self) -> usize {
+    match self {
+      Operand::None => 0,
+      Operand::Pad(len) => *len,
+      Operand::Byte(_) | Operand::Relative(_) => 1,
+      Operand::Word(_) => 2,
+    }
+  }
+}
+
+// how an operand is written
+enum Form {
+  Implied,
+  Immediate(Expr),
+  Address(Expr),
+  IndexedX(Expr),
+  IndexedY(Expr),
+  IndirectX(Expr),
+  IndirectY(Expr),
+  Indirect(Expr),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Part {
+  Whole,
+  Low,
+  High,
+}
+
+enum Term {
+  Number(u16),
+  Label(String),
+}
+
+struct Expr {
+  part: Part,
+  // each term added, or subtracted when the flag is set
+  terms: Vec<(bool, Term)>,
+  // hex with more than two digits, an absolute address even if it's small
+  wide: bool,
+}
+
+impl Expr {
+  fn parse(text: &str) -> Option<Expr> {
+    let (part, mut rest) = match text.as_bytes().first() {
+      Some(b'<') => (Part::Low, &text[1..]),
+      Some(b'>') => (Part::High, &text[1..]),
+      _ => (Part::Whole, text),
+    };
+    let mut expr = Expr {
+      part,
+      terms: Vec::new(),
+      wide: false,
+    };
+    let mut negative = false;
+    if let Some(after) = rest.strip_prefix('-') {
+      negative = true;
+      rest = after;
+    }
+    loop {
+      let end = rest.find(|c| c == '+' || c == '-').unwrap_or(rest.len());
+      let (term, wide) = parse_term(&rest[..end])?;
+      expr.wide |= wide;
+      expr.terms.push((negative, term));
+      if end == rest.len() {
+        return Some(expr);
+      }
+      negative = rest.as_bytes()[end] == b'-';
+      rest = &rest[end + 1..];
+    }
+  }
+
+  fn value(&self, symbols: &HashMap<String, u16>) -> Result<i32, String> {
+    let mut sum = 0;
+    for (negative, term) in &self.terms {
+      let v = match term {
+        Term::Number(n) => *n as i32,
+        Term::Label(label) => *symbols.get(label).ok_or_else(|| label.clone())? as i32,
+      };
+      sum += if *negative { -v } else { v };
+    }
+    Ok(match self.part {
+      Part::Whole => sum,
+      Part::Low => sum & 0xff,
+      Part::High => (sum >> 8) & 0xff,
+    })
+  }
+
+  fn eval(&self, symbols: &HashMap<String, u16>, line: usize) -> Result<i32, AsmError> {
+    self
+      .value(symbols)
+      .map_err(|label| AsmError::UnknownLabel { line, label })
+  }
+
+  // whether this can be a zero page address, so far as the first pass knows
+  fn zero_page(&self, symbols: &HashMap<String, u16>) -> bool {
+    !self.wide && matches!(self.value(symbols), Ok(0..=0xff))
+  }
+}
+
+fn parse_term(text: &str) -> Option<(Term, bool)> {
+  let number = |digits: &str, radix| match u32::from_str_radix(digits, radix) {
+    Ok(n) if n <= 0xffff => Some(Term::Number(n as u16)),
+    _ => None,
+  };
+  if let Some(hex) = text.strip_prefix('$') {
+    Some((number(hex, 16)?, hex.len() > 2))
+  } else if let Some(binary) = text.strip_prefix('%') {
+    Some((number(binary, 2)?, false))
+  } else if text.starts_with(|c: char| c.is_ascii_digit()) {
+    Some((number(text, 10)?, false))
+  } else if is_label(text) {
+    Some((Term::Label(text.to_string()), false))
+  } else {
+    None
+  }
+}
+
+fn is_label(text: &str) -> bool {
+  let mut chars = text.chars();
+  matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+    && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_form(operand: &str) -> Option<Form> {
+  // no spaces matter inside an operand
+  let text: String = operand.split_whitespace().collect();
+  let upper = text.to_ascii_uppercase();
+  let expr = |end: usize| Expr::parse(&text[..end]);
+  Some(if text.is_empty() || upper == "A" {
+    Form::Implied
+  } else if let Some(imm) = text.strip_prefix('#') {
+    Form::Immediate(Expr::parse(imm)?)
+  } else if let Some(inner) = text.strip_prefix('(') {
+    if upper.ends_with(",X)") {
+      Form::IndirectX(Expr::parse(&inner[..inner.len() - 3])?)
+    } else if upper.ends_with("),Y") {
+      Form::IndirectY(Expr::parse(&inner[..inner.len() - 3])?)
+    } else {
+      Form::Indirect(Expr::parse(inner.strip_suffix(')')?)?)
+    }
+  } else if upper.ends_with(",X") {
+    Form::IndexedX(expr(text.len() - 2)?)
+  } else if upper.ends_with(",Y") {
+    Form::IndexedY(expr(text.len() - 2)?)
+  } else {
+    Form::Address(expr(text.len())?)
+  })
+}
+
+struct Assembler {
+  symbols: HashMap<String, u16>,
+  items: Vec<Item>,
+  // where the next byte goes, past $FFFF only if the program is too long
+  pc: u32,
+}
+
+impl Assembler {
+  fn define(&mut self, line: usize, label: &str, value: u16) -> Result<(), AsmError> {
+    if self.symbols.insert(label.to_string(), value).is_some() {
+      return Err(AsmError::DuplicateLabel {
+        line,
+        label: label.to_string(),
+      });
+    }
+    Ok(())
+  }
+
+  fn push(&mut self, line: usize, code: Option<u8>, operand: Operand) -> Result<(), AsmError> {
+    let len = code.map_or(0, |_| 1) + operand.len() as u32;
+    if self.pc + len > 0x1_0000 {
+      return Err(AsmError::OutOfRange { line });
+    }
+    self.items.push(Item {
+      line,
+      address: self.pc as u16,
+      code,
+      operand,
+    });
+    self.pc += len;
+    Ok(())
+  }
+
+  fn statement(&mut self, line: usize, text: &str) -> Result<(), AsmError> {
+    let syntax = AsmError::Syntax { line };
+    if text.is_empty() {
+      return Ok(());
+    }
+    if let Some((name, value)) = text.split_once('=') {
+      let name = name.trim();
+      let expr = Expr::parse(&value.split_whitespace().collect::<String>());
+      if !is_label(name) {
+        return Err(syntax);
+      }
+      let value = expr.ok_or(syntax)?.eval(&self.symbols, line)?;
+      return self.define(line, name, value as u16);
+    }
+    if let Some((label, rest)) = text.split_once(':') {
+      if !is_label(label.trim()) {
+        return Err(syntax);
+      }
+      self.define(line, label.trim(), self.pc as u16)?;
+      return self.statement(line, rest.trim());
+    }
+
+    let (word, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let word = word.to_ascii_uppercase();
+    if let Some(directive) = word.strip_prefix('.') {
+      return self.directive(line, directive, rest);
+    }
+    let form = parse_form(rest).ok_or(syntax)?;
+    let (op, operand) = self
+      .instruction(&word, form)
+      .ok_or(AsmError::UnknownInstruction { line })?;
+    self.push(line, Some(op.code), operand)
+  }
+
+  fn directive(&mut self, line: usize, directive: &str, args: &str) -> Result<(), AsmError> {
+    let exprs = args
+      .split(',')
+      .map(|arg| Expr::parse(&arg.split_whitespace().collect::<String>()))
+      .collect::<Option<Vec<_>>>()
+      .ok_or(AsmError::Syntax { line })?;
+    match directive {
+      "BYTE" => exprs
+        .into_iter()
+        .try_for_each(|expr| self.push(line, None, Operand::Byte(expr))),
+      "WORD" => exprs
+        .into_iter()
+        .try_for_each(|expr| self.push(line, None, Operand::Word(expr))),
+      "ORG" if exprs.len() == 1 => {
+        let to = exprs[0].eval(&self.symbols, line)?;
+        if to < self.pc as i32 || to > 0xffff {
+          return Err(AsmError::OutOfRange { line });
+        }
+        self.push(line, None, Operand::Pad((to as u32 - self.pc) as usize))
+      }
+      _ => Err(AsmError::Syntax { line }),
+    }
+  }
+
+  // the opcode for `mnemonic` written with `form`, well ahead of
+  // knowing the operand
+  fn instruction(&self, mnemonic: &str, form: Form) -> Option<(&'static OpCode, Operand)> {
+    use AddressingMode::*;
+    let find = |test: &dyn Fn(&OpCode) -> bool| {
+      CPU_OPS_CODES
+        .iter()
+        .find(|op| op.mnemonic == mnemonic && test(op))
+    };
+    let by_mode = |mode: AddressingMode| find(&|op| op.mode == mode);
+    // branches, and JMP and JSR, are NoneAddressing with an operand
+    let none = |len: u8| find(&|op| op.mode == NoneAddressing && op.len == len && op.code != 0x6c);
+    // the zero page form when there is one and the operand allows it
+    let sized =
+      |expr: Expr, zero_page: AddressingMode, absolute: AddressingMode| match by_mode(zero_page) {
+        Some(op) if expr.zero_page(&self.symbols) => Some((op, Operand::Byte(expr))),
+        _ => by_mode(absolute).map(|op| (op, Operand::Word(expr))),
+      };
+    match form {
+      Form::Implied => none(1).map(|op| (op, Operand::None)),
+      Form::Immediate(expr) => by_mode(Immediate).map(|op| (op, Operand::Byte(expr))),
+      Form::IndirectX(expr) => by_mode(Indirect_X).map(|op| (op, Operand::Byte(expr))),
+      Form::IndirectY(expr) => by_mode(Indirect_Y).map(|op| (op, Operand::Byte(expr))),
+      Form::Indirect(expr) => find(&|op| op.code == 0x6c).map(|op| (op, Operand::Word(expr))),
+      Form::IndexedX(expr) => sized(expr, ZeroPage_X, Absolute_X),
+      Form::IndexedY(expr) => sized(expr, ZeroPage_Y, Absolute_Y),
+      Form::Address(expr) => {
+        if let Some(op) = none(2) {
+          Some((op, Operand::Relative(expr)))
+        } else if let Some(op) = none(3) {
+          Some((op, Operand::Word(expr)))
+        } else {
+          sized(expr, ZeroPage, Absolute)
+        }
+      }
+    }
+  }
+}
@@ -0,0 +1,214 @@
+use crate::nes::cartridge::{Rom, RomError};
+use crate::nes::cpu::CPU;
+use crate::nes::movie::{Movie, MovieStart};
+use crate::nes::savestate::{self, StateError, StateReader, StateWriter};
+use crate::nes::timing::Timing;
+
+/*
+  Attract mode, as arcade cabinets and kiosks have it: a cartridge's demo
+  movie plays by itself once nobody has touched the controls for a while,
+  over and over, and the first input afterwards stops it and puts the
+  console back exactly as it was left.
+
+  Demos are kept by cartridge, so a page showing several games gives
+  each its own and the one for whatever is inserted plays. A demo from
+  power-on starts from a fresh console with the cartridge in, one from a
+  savestate from the state; a PAL movie is played at PAL timing. What the
+  frontend counts as input is its business: it calls `input`, and
+  `frame` at the start of every frame.
+*/
+
+/// Idle time before a demo starts, 30 seconds of NTSC frames.
+pub const DEFAULT_IDLE_FRAMES: u32 = 1800;
+
+struct Demo {
+  checksum: u32,
+  movie: Movie,
+  // the savestate it starts from
+  start: Vec<u8>,
+}
+
+// a demo playing, and what to go back to
+struct Playing {
+  demo: usize,
+  // the next frame of the movie
+  frame: usize,
+  // the PPU's frame_count when the last frame's input went in
+  at: u64,
+  resume: Vec<u8>,
+  four_score: bool,
+  timing_override: Option<Timing>,
+}
+
+pub struct Attract {
+  demos: Vec<Demo>,
+  idle_frames: u32,
+  idle: u32,
+  playing: Option<Playing>,
+}
+
+impl Default for Attract {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Attract {
+  pub fn new() -> Self {
+    Attract {
+      demos: Vec::new(),
+      idle_frames: DEFAULT_IDLE_FRAMES,
+      idle: 0,
+      playing: None,
+    }
+  }
+
+  pub fn idle_frames(&self) -> u32 {
+    self.idle_frames
+  }
+
+  /// At least 1.
+  pub fn set_idle_frames(&mut self, frames: u32) {
+    self.idle_frames = frames.max(1);
+  }
+
+  /// Make `movie` the demo of `rom`, replacing the one it had. An empty
+  /// one never plays.
+  pub fn set_demo(&mut self, rom: &Rom, movie: Movie) -> Result<(), RomError> {
+    let checksum = savestate::cartridge_checksum(&rom.prg_rom, &rom.chr_rom);
+    let start = match &movie.start {
+      MovieStart::State(state) => state.clone(),
+      MovieStart::PowerOn => {
+        let mut fresh = CPU::new();
+        fresh.load_rom(rom.clone())?;
+        // savestates only carry a Four Score's state if one is plugged in
+        fresh.bus.set_four_score(movie.four_score);
+        let mut w = StateWriter::new(checksum);
+        fresh.save_state(&mut w);
+        w.finish()
+      }
+    };
+    self.remove_demo(checksum);
+    self.demos.push(Demo {
+      checksum,
+      movie,
+      start,
+    });
+    Ok(())
+  }
+
+  /// Forget the demo of the cartridge with `checksum`. Stops it if it's
+  /// playing, without going back; call `input` first for that.
+  pub fn remove_demo(&mut self, checksum: u32) {
+    if let Some(index) = self.demos.iter().position(|demo| demo.checksum == checksum) {
+      if self.playing.as_ref().map(|playing| playing.demo) == Some(index) {
+        self.playing = None;
+      }
+      self.demos.remove(index);
+      if let Some(playing) = &mut self.playing {
+        if playing.demo > index {
+          playing.demo -= 1;
+        }
+      }
+    }
+  }
+
+  /// The demo of the cartridge with `checksum`.
+  pub fn demo(&self, checksum: u32) -> Option<&Movie> {
+    self
+      .demos
+      .iter()
+      .find(|demo| demo.checksum == checksum)
+      .map(|demo| &demo.movie)
+  }
+
+  pub fn playing(&self) -> bool {
+    self.playing.is_some()
+  }
+
+  /// The player pressed something: the idle time starts over, and a demo
+  /// playing stops with `cpu` back where it was before it. Returns
+  /// whether one did.
+  pub fn input(&mut self, cpu: &mut CPU) -> Result<bool, StateError> {
+    self.idle = 0;
+    let playing = match self.playing.take() {
+      Some(playing) => playing,
+      None => return Ok(false),
+    };
+    cpu.bus.set_timing_override(playing.timing_override);
+    cpu.bus.set_four_score(playing.four_score);
+    let mut r = StateReader::new(&playing.resume, cpu.bus.cartridge_checksum())?;
+    cpu.load_state(&mut r)?;
+    r.finish()?;
+    Ok(true)
+  }
+
+  /// Before every frame: count it idle, start the demo of the cartridge
+  /// in `cpu` once enough have been and hold its input while it plays,
+  /// from the top again once it's over.
+  pub fn frame(&mut self, cpu: &mut CPU) -> Result<(), StateError> {
+    if self.playing.is_none() {
+      self.idle = self.idle.saturating_add(1);
+      if self.idle < self.idle_frames {
+        return Ok(());
+      }
+      let checksum = cpu.bus.cartridge_checksum();
+      let demo = self
+        .demos
+        .iter()
+        .position(|demo| demo.checksum == checksum && !demo.movie.is_empty());
+      let demo = match demo {
+        Some(demo) => demo,
+        None => return Ok(()),
+      };
+      let mut w = StateWriter::new(checksum);
+      cpu.save_state(&mut w);
+      self.playing = Some(Playing {
+        demo,
+        frame: 0,
+        at: cpu.bus.ppu.frame_count,
+        resume: w.finish(),
+        four_score: cpu.bus.four_score(),
+        timing_override: cpu.bus.timing_override(),
+      });
+      self.start(cpu)?;
+    }
+    let playing = self.playing.as_mut().unwrap();
+    let frame_count = cpu.bus.ppu.frame_count;
+    // a frame split by a breakpoint goes on with the same input
+    if playing.frame > 0 && playing.at == frame_count {
+      return Ok(());
+    }
+    if playing.frame == self.demos[playing.demo].movie.len() {
+      self.start(cpu)?;
+    }
+    let playing = self.playing.as_mut().unwrap();
+    let demo = &self.demos[playing.demo];
+    demo.movie.play(playing.frame, cpu);
+    playing.frame += 1;
+    playing.at = cpu.bus.ppu.frame_count;
+    Ok(())
+  }
+
+  // the playing demo's first frame
+  fn start(&mut self, cpu: &mut CPU) -> Result<(), StateError> {
+    let playing = self.playing.as_mut().unwrap();
+    let demo = &self.demos[playing.demo];
+    let pal = cpu.bus.timing() == Timing::Pal;
+    if demo.movie.pal != pal {
+      let timing = if demo.movie.pal {
+        Timing::Pal
+      } else {
+        Timing::Ntsc
+      };
+      cpu.bus.set_timing_override(Some(timing));
+    }
+    cpu.bus.set_four_score(demo.movie.four_score);
+    let mut r = StateReader::new(&demo.start, demo.checksum)?;
+    cpu.load_state(&mut r)?;
+    r.finish()?;
+    demo.movie.restore_controllers(cpu)?;
+    playing.frame = 0;
+    Ok(())
+  }
+}
@@ -0,0 +1,124 @@
+use crate::nes::bus::Mem;
+use crate::nes::cartridge::{Rom, RomError};
+use crate::nes::cpu::CPU;
+use std::fmt;
+
+/*
+  blargg's newer test ROMs (instr_test, ppu_vbl_nmi, apu_test and the
+  like) report through PRG RAM, so they can be run without looking at the
+  screen:
+
+    $6000       status: $80 running, $81 wants a reset press soon,
+                below $80 the result, 0 meaning passed
+    $6001-$6003 DE B0 61 once the status is valid
+    $6004-      zero terminated text, what the ROM printed on screen
+*/
+
+const STATUS: u16 = 0x6000;
+const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const TEXT: u16 = 0x6004;
+const RUNNING: u8 = 0x80;
+const RESET_REQUESTED: u8 = 0x81;
+// the ROMs want the reset at least 100ms after asking
+const RESET_DELAY_FRAMES: u32 = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TestResult {
+  Passed {
+    text: String,
+  },
+  /// The result code and what the ROM had to say about it.
+  Failed {
+    code: u8,
+    text: String,
+  },
+  /// Still running after the frame limit, or the CPU stopped.
+  Unfinished {
+    text: String,
+  },
+}
+
+impl TestResult {
+  pub fn passed(&self) -> bool {
+    matches!(self, TestResult::Passed { .. })
+  }
+
+  pub fn text(&self) -> &str {
+    match self {
+      TestResult::Passed { text }
+      | TestResult::Failed { text, .. }
+      | TestResult::Unfinished { text } => text,
+    }
+  }
+}
+
+impl fmt::Display for TestResult {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      TestResult::Passed { .. } => write!(f, "passed"),
+      TestResult::Failed { code, text } => write!(f, "failed with code {}: {}", code, text.trim()),
+      TestResult::Unfinished { text } => write!(f, "didn't finish: {}", text.trim()),
+    }
+  }
+}
+
+fn text(cpu: &CPU) -> String {
+  let bytes: Vec<u8> = (TEXT..0x8000)
+    .map(|addr| cpu.mem_peek(addr))
+    .take_while(|&byte| byte != 0)
+    .collect();
+  String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn status(cpu: &CPU) -> Option<u8> {
+  let signature = [
+    cpu.mem_peek(STATUS + 1),
+    cpu.mem_peek(STATUS + 2),
+    cpu.mem_peek(STATUS + 3),
+  ];
+  if signature == SIGNATURE {
+    Some(cpu.mem_peek(STATUS))
+  } else {
+    None
+  }
+}
+
+/// Boot a test ROM and run it until it reports a result, for at most
+/// `max_frames` frames. Reset requests are answered like someone pressing
+/// the button.
+pub fn run(rom: Rom, max_frames: u32) -> Result<TestResult, RomError> {
+  let mut cpu = CPU::new();
+  cpu.halt_on_brk = false;
+  cpu.load_rom(rom)?;
+
+  let mut reset_in = None;
+  // the status says $81 until the ROM is back up after the reset
+  let mut rebooting = false;
+  for _ in 0..max_frames {
+    if !cpu.run_frame() {
+      break;
+    }
+    match status(&cpu) {
+      None | Some(RUNNING) => rebooting = false,
+      Some(RESET_REQUESTED) if rebooting => {}
+      Some(RESET_REQUESTED) => match reset_in {
+        Some(0) => {
+          cpu.reset();
+          cpu.program_counter = cpu.mem_read_u16(0xFFFC);
+          reset_in = None;
+          rebooting = true;
+        }
+        Some(frames) => reset_in = Some(frames - 1),
+        None => reset_in = Some(RESET_DELAY_FRAMES),
+      },
+      Some(0) => return Ok(TestResult::Passed { text: text(&cpu) }),
+      Some(code) => {
+        return Ok(TestResult::Failed {
+          code,
+          text: text(&cpu),
+        })
+      }
+    }
+  }
+  Ok(TestResult::Unfinished { text: text(&cpu) })
+}
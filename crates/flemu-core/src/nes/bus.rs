@@ -0,0 +1,775 @@
+use crate::nes::apu::{Apu, ChannelLog};
+use crate::nes::cartridge::{Rom, RomError, RomInfo};
+use crate::nes::cheats::Cheats;
+use crate::nes::debugger::{Access, Watchpoints};
+use crate::nes::joypad::{FourScore, Joypad};
+use crate::nes::mapper::{self, Mapper, NoCartridge};
+use crate::nes::ppu::{NesPPU, DOTS_PER_SCANLINE};
+use crate::nes::profile::{Clock, ComponentTimes, Profiler};
+use crate::nes::savestate::{self, StateError, StateReader, StateWriter};
+use crate::nes::scanline_events::{LineEvents, ScanlineEvents};
+use crate::nes::timing::Timing;
+use crate::nes::warnings::{Warning, Warnings};
+use crate::nes::zapper::Zapper;
+use log::trace;
+
+//  _______________ $10000  _______________
+// | PRG-ROM       |       |               |
+// | Upper Bank    |       |               |
+// |_ _ _ _ _ _ _ _| $C000 | PRG-ROM       |
+// | PRG-ROM       |       |               |
+// | Lower Bank    |       |               |
+// |_______________| $8000 |_______________|
+// | SRAM          |       | SRAM          |
+// |_______________| $6000 |_______________|
+// | Expansion ROM |       | Expansion ROM |
+// |_______________| $4020 |_______________|
+// | I/O Registers |       |               |
+// |_ _ _ _ _ _ _ _| $4000 |               |
+// | Mirrors       |       | I/O Registers |
+// | $2000-$2007   |       |               |
+// |_ _ _ _ _ _ _ _| $2008 |               |
+// | I/O Registers |       |               |
+// |_______________| $2000 |_______________|
+// | Mirrors       |       |               |
+// | $0000-$07FF   |       |               |
+// |_ _ _ _ _ _ _ _| $0800 |               |
+// | RAM           |       | RAM           |
+// |_ _ _ _ _ _ _ _| $0200 |               |
+// | Stack         |       |               |
+// |_ _ _ _ _ _ _ _| $0100 |               |
+// | Zero Page     |       |               |
+// |_______________| $0000 |_______________|
+const RAM: u16 = 0x0000;
+const RAM_MIRRORS_END: u16 = 0x1FFF;
+const PPU_REGISTERS: u16 = 0x2000;
+const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
+const APU_IO_REGISTERS: u16 = 0x4000;
+const APU_REGISTERS_END: u16 = 0x4013;
+const OAM_DMA: u16 = 0x4014;
+// CPU cycles an OAM DMA takes at least
+pub const OAM_DMA_CYCLES: u32 = 513;
+const APU_STATUS: u16 = 0x4015;
+const JOYPAD1: u16 = 0x4016;
+// reads: the second joypad, writes: the APU frame counter
+const APU_FRAME_COUNTER: u16 = 0x4017;
+const JOYPAD2: u16 = 0x4017;
+const APU_IO_REGISTERS_END: u16 = 0x401F;
+const CARTRIDGE: u16 = 0x4020;
+
+pub trait Mem {
+  fn mem_read(&mut self, addr: u16) -> u8;
+
+  fn mem_write(&mut self, addr: u16, data: u8);
+
+  /// Read without side effects (no PPU latch or buffer updates), for
+  /// debuggers, achievements and dumps.
+  fn mem_peek(&self, addr: u16) -> u8;
+
+  fn mem_read_u16(&mut self, pos: u16) -> u16 {
+    let lo = self.mem_read(pos) as u16;
+    let hi = self.mem_read(pos.wrapping_add(1)) as u16;
+    (hi << 8) | lo
+  }
+
+  fn mem_write_u16(&mut self, pos: u16, data: u16) {
+    let hi = (data >> 8) as u8;
+    let lo = (data & 0xff) as u8;
+    self.mem_write(pos, lo);
+    self.mem_write(pos.wrapping_add(1), hi);
+  }
+
+  /// Let the devices on the bus catch up with `cycles` CPU cycles.
+  fn tick(&mut self, _cycles: u8) {}
+
+  /// Whether a device pulled the NMI line since the last poll.
+  fn poll_nmi_status(&mut self) -> bool {
+    false
+  }
+
+  /// Whether a device is holding the IRQ line low.
+  fn poll_irq_status(&self) -> bool {
+    false
+  }
+
+  /// The CPU is starting an NMI's (`nmi`) or an IRQ's handler.
+  fn interrupt_taken(&mut self, _nmi: bool) {}
+
+  /// CPU cycles devices took over since the last call (OAM DMA, DMC sample
+  /// fetches), already ticked.
+  fn take_stall_cycles(&mut self) -> u16 {
+    0
+  }
+
+  /// Something suspicious the CPU noticed, for the bus to report.
+  fn warn(&mut self, _warning: Warning) {}
+
+  /// Scanline and dot the PPU is at, for trace logs. 0, 0 without a PPU.
+  fn ppu_position(&self) -> (u16, u16) {
+    (0, 0)
+  }
+}
+
+/// An OAM DMA, the last one the game started, for the debugger to tell
+/// what the CPU's time went to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OamDma {
+  /// The page copied, $XX00-$XXFF.
+  pub page: u8,
+  /// Bytes written to OAM so far, 256 once it's over.
+  pub copied: u16,
+  /// CPU cycles it halts the CPU for, 513 or 514.
+  pub stall_cycles: u16,
+  /// Of those, the ones still to go.
+  pub stall_remaining: u16,
+  /// Frame and scanline of the $4014 write.
+  pub frame: u64,
+  pub scanline: u16,
+}
+
+/// Everything the CPU can reach, routed by address range.
+#[derive(Clone)]
+pub struct Bus {
+  cpu_vram: [u8; 2048],
+  pub ppu: NesPPU,
+  pub apu: Apu,
+  /// Players 1-4. 3 and 4 are only connected through a Four Score.
+  pub joypads: [Joypad; 4],
+  four_score: Option<FourScore>,
+  // plugged into port 2 in place of the second controller
+  zapper: Option<Zapper>,
+  pub cheats: Cheats,
+  pub warnings: Warnings,
+  /// The APU channels, recorded at every vblank.
+  pub channels: ChannelLog,
+  /// Addresses the debugger watches accesses to.
+  pub watchpoints: Watchpoints,
+  rom_info: Option<RomInfo>,
+  mapper: Box<dyn Mapper>,
+  // of the ROM inserted, what savestates are matched against
+  cartridge_checksum: u32,
+  // battery RAM was written since the last take_battery_dirty
+  battery_dirty: bool,
+  // battery RAM writes ever, wrapping; unlike battery_dirty nothing resets it
+  battery_writes: u32,
+  // a controller port was read since the last take_input_polled
+  input_polled: bool,
+  stall_cycles: u16,
+  // forced by the frontend, instead of what the header asks for
+  timing_override: Option<Timing>,
+  // PPU dots owed to the PPU, in fractions of a dot (PAL's 3.2 per cycle)
+  dot_phase: u32,
+  // $4014 was written, the CPU halts once the writing instruction is over
+  oam_dma_pending: bool,
+  oam_dma: Option<OamDma>,
+  // the last byte on the CPU's data bus, what reads of nothing return
+  open_bus: u8,
+  // the last access was a write, so a write now is on the very next cycle
+  wrote_last: bool,
+  profiler: Option<Profiler>,
+  scanline_events: Option<ScanlineEvents>,
+}
+
+impl Default for Bus {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Bus {
+  pub fn new() -> Self {
+    Bus {
+      cpu_vram: [0; 2048],
+      ppu: NesPPU::new(),
+      apu: Apu::new(),
+      joypads: [Joypad::new(), Joypad::new(), Joypad::new(), Joypad::new()],
+      four_score: None,
+      zapper: None,
+      cheats: Cheats::default(),
+      warnings: Warnings::default(),
+      channels: ChannelLog::default(),
+      watchpoints: Watchpoints::default(),
+      rom_info: None,
+      mapper: Box::new(NoCartridge),
+      cartridge_checksum: savestate::cartridge_checksum(&[], &[]),
+      battery_dirty: false,
+      battery_writes: 0,
+      input_polled: false,
+      stall_cycles: 0,
+      timing_override: None,
+      dot_phase: 0,
+      oam_dma_pending: false,
+      oam_dma: None,
+      open_bus: 0,
+      wrote_last: false,
+      profiler: None,
+      scanline_events: None,
+    }
+  }
+
+  pub fn with_rom(rom: Rom) -> Result<Self, RomError> {
+    let mut bus = Bus::new();
+    bus.insert_cartridge(rom)?;
+    Ok(bus)
+  }
+
+  /// Swap in a new cartridge, failing if its mapper isn't implemented.
+  pub fn insert_cartridge(&mut self, rom: Rom) -> Result<(), RomError> {
+    let info = rom.info;
+    let checksum = savestate::cartridge_checksum(&rom.prg_rom, &rom.chr_rom);
+    self.mapper = mapper::for_rom(rom)?;
+    self.rom_info = Some(info);
+    self.cartridge_checksum = checksum;
+    self.battery_dirty = false;
+    self.apply_timing();
+    Ok(())
+  }
+
+  /// Take over what a frontend set up on `old`, for a new console that
+  /// replaces it: the mixer and sound output, forced timing, what's
+  /// plugged into the ports, cheats, the debugger's layers and
+  /// watchpoints, the profiler and scanline events.
+  pub fn take_settings(&mut self, old: &mut Bus) {
+    self.ppu.layers = old.ppu.layers;
+    self.apu.mixer = old.apu.mixer;
+    self.apu.set_sample_rate(old.apu.sample_rate());
+    self.apu.set_latency(old.apu.latency());
+    self.apu.set_speed(old.apu.speed());
+    // what's queued to play and being recorded goes on playing
+    self.apu.swap_output(&mut old.apu);
+    self.set_four_score(old.four_score());
+    self.set_zapper(old.zapper.is_some());
+    self.cheats = std::mem::take(&mut old.cheats);
+    self.watchpoints = std::mem::take(&mut old.watchpoints);
+    self.profiler = old.profiler.take();
+    self.set_scanline_events(old.scanline_events.is_some());
+    // also puts the swapped output on this cartridge's clock
+    self.set_timing_override(old.timing_override);
+  }
+
+  /// Time the PPU and the APU with `clock` from now on, or stop with
+  /// None. See `profile`.
+  pub fn set_profiler(&mut self, clock: Option<Clock>) {
+    self.profiler = clock.map(Profiler::new);
+  }
+
+  /// Log the interrupts and DMAs of every scanline from now on, or stop.
+  pub fn set_scanline_events(&mut self, enabled: bool) {
+    self.scanline_events = if enabled {
+      Some(ScanlineEvents::new())
+    } else {
+      None
+    };
+  }
+
+  /// None unless `set_scanline_events` turned them on.
+  pub fn scanline_events(&self) -> Option<&ScanlineEvents> {
+    self.scanline_events.as_ref()
+  }
+
+  fn record_event(&mut self, events: LineEvents) {
+    if let Some(log) = &mut self.scanline_events {
+      log.record(self.ppu.scanline, events);
+    }
+  }
+
+  /// PPU and APU time since the last call, zero without a profiler.
+  pub fn take_component_times(&mut self) -> ComponentTimes {
+    self
+      .profiler
+      .as_mut()
+      .map_or_else(ComponentTimes::default, Profiler::take)
+  }
+
+  /// NTSC, PAL or Dendy, whichever the console runs as now.
+  pub fn timing(&self) -> Timing {
+    self.ppu.timing()
+  }
+
+  pub fn timing_override(&self) -> Option<Timing> {
+    self.timing_override
+  }
+
+  /// Run as `timing` whatever the cartridge says, or with None go back to
+  /// the timing its header asks for (NTSC without a cartridge).
+  pub fn set_timing_override(&mut self, timing: Option<Timing>) {
+    self.timing_override = timing;
+    self.apply_timing();
+  }
+
+  fn apply_timing(&mut self) {
+    let timing = self.timing_override.unwrap_or_else(|| {
+      self
+        .rom_info
+        .map_or(Timing::Ntsc, |info| Timing::for_region(info.region))
+    });
+    self.ppu.set_timing(timing);
+    self.apu.set_timing(timing);
+    self.dot_phase = 0;
+  }
+
+  /// Checksum of the inserted ROM, as written into savestates.
+  pub fn cartridge_checksum(&self) -> u32 {
+    self.cartridge_checksum
+  }
+
+  pub fn rom_info(&self) -> Option<&RomInfo> {
+    self.rom_info.as_ref()
+  }
+
+  pub fn mapper(&self) -> Option<&dyn Mapper> {
+    self.rom_info.map(|_| &*self.mapper)
+  }
+
+  /// Read the PPU's address space, $0000-$3FFF, without going through
+  /// $2006/$2007.
+  pub fn ppu_peek(&self, addr: u16) -> u8 {
+    self.ppu.read_vram(&*self.mapper, addr)
+  }
+
+  /// Write the PPU's address space: CHR RAM (CHR ROM stays as it is),
+  /// nametables and palette.
+  pub fn ppu_poke(&mut self, addr: u16, value: u8) {
+    self.ppu.write_vram(&mut *self.mapper, addr, value);
+  }
+
+  /// PRG RAM of a battery-backed cartridge, the part worth saving.
+  pub fn battery_ram(&self) -> Option<&[u8]> {
+    match self.rom_info {
+      Some(info) if info.battery => self.mapper.prg_ram(),
+      _ => None,
+    }
+  }
+
+  /// For restoring a save into the cartridge.
+  pub fn battery_ram_mut(&mut self) -> Option<&mut [u8]> {
+    match self.rom_info {
+      Some(info) if info.battery => self.mapper.prg_ram_mut(),
+      _ => None,
+    }
+  }
+
+  /// Whether the game wrote to battery RAM since the last call, i.e.
+  /// whether there's anything new to persist.
+  pub fn take_battery_dirty(&mut self) -> bool {
+    std::mem::take(&mut self.battery_dirty)
+  }
+
+  /// Counts battery RAM writes, wrapping around, so several watchers can
+  /// each see whether it changed since they last looked without taking
+  /// `take_battery_dirty` from the one persisting it.
+  pub fn battery_writes(&self) -> u32 {
+    self.battery_writes
+  }
+
+  /// Whether the game read a controller port since the last call. Taken
+  /// every frame, false means a lag frame: the game was too busy to look
+  /// at the buttons, so whatever was held made no difference.
+  pub fn take_input_polled(&mut self) -> bool {
+    std::mem::take(&mut self.input_polled)
+  }
+
+  fn has_battery(&self) -> bool {
+    self.rom_info.map_or(false, |info| info.battery)
+  }
+
+  /// Plug in or remove a Four Score multitap.
+  pub fn set_four_score(&mut self, connected: bool) {
+    self.four_score = if connected {
+      Some(FourScore::default())
+    } else {
+      None
+    };
+  }
+
+  pub fn four_score(&self) -> bool {
+    self.four_score.is_some()
+  }
+
+  /// Plug a Zapper into port 2, or the second controller back.
+  pub fn set_zapper(&mut self, connected: bool) {
+    self.zapper = if connected {
+      Some(Zapper::default())
+    } else {
+      None
+    };
+  }
+
+  pub fn zapper(&self) -> Option<&Zapper> {
+    self.zapper.as_ref()
+  }
+
+  pub fn zapper_mut(&mut self) -> Option<&mut Zapper> {
+    self.zapper.as_mut()
+  }
+
+  // $4016 or $4017 read
+  fn read_joypad(&mut self, port: usize) -> u8 {
+    self.input_polled = true;
+    if let (1, Some(zapper)) = (port, &self.zapper) {
+      return zapper.read(&self.ppu.frame, self.ppu.scanline);
+    }
+    match &mut self.four_score {
+      Some(four_score) => four_score.read(port, &self.joypads),
+      None => self.joypads[port].read(),
+    }
+  }
+
+  fn peek_joypad(&self, port: usize) -> u8 {
+    if let (1, Some(zapper)) = (port, &self.zapper) {
+      return zapper.read(&self.ppu.frame, self.ppu.scanline);
+    }
+    match &self.four_score {
+      Some(four_score) => four_score.peek(port, &self.joypads),
+      None => self.joypads[port].peek(),
+    }
+  }
+
+  /// RAM, the PPU, the APU, the controller ports and the board.
+  pub fn save_state(&self, w: &mut StateWriter) {
+    w.bytes(&self.cpu_vram);
+    self.ppu.save_state(w);
+    self.apu.save_state(w);
+    self.save_controllers(w);
+    self.mapper.save_state(w);
+    self.save_timing(w);
+  }
+
+  // the parts of `save_state` that are the bus's own: the controller
+  // ports, and DMA, stalls and open bus
+  pub(crate) fn save_controllers(&self, w: &mut StateWriter) {
+    for joypad in &self.joypads {
+      joypad.save_state(w);
+    }
+    w.bool(self.four_score.is_some());
+    if let Some(four_score) = &self.four_score {
+      four_score.save_state(w);
+    }
+  }
+
+  pub(crate) fn load_controllers(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+    for joypad in self.joypads.iter_mut() {
+      joypad.load_state(r)?;
+      joypad.set_frame(self.ppu.frame_count);
+    }
+    if r.bool()? {
+      let mut four_score = FourScore::default();
+      four_score.load_state(r)?;
+      if self.four_score.is_some() {
+        self.four_score = Some(four_score);
+      }
+    }
+    Ok(())
+  }
+
+  pub(crate) fn save_timing(&self, w: &mut StateWriter) {
+    w.u16(self.stall_cycles);
+    w.u8(self.dot_phase as u8);
+    w.bool(self.oam_dma_pending);
+    w.u8(self.open_bus);
+    w.u8(self.oam_dma.map_or(0, |dma| dma.page));
+  }
+
+  /// The Four Score is only restored if one is plugged in now, and stays
+  /// plugged in or out either way.
+  pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+    r.bytes_into(&mut self.cpu_vram)?;
+    self.ppu.load_state(r)?;
+    self.apu.load_state(r)?;
+    self.load_controllers(r)?;
+    self.mapper.load_state(r)?;
+    self.stall_cycles = r.u16()?;
+    self.dot_phase = r.u8_below(self.timing().dots_per_cycle().1 as u8)? as u32;
+    self.oam_dma_pending = r.bool()?;
+    self.open_bus = r.u8()?;
+    let page = r.u8()?;
+    if self.oam_dma_pending {
+      self.queue_oam_dma(page);
+    }
+    // the state's battery RAM replaced what was there
+    if self.has_battery() {
+      self.battery_dirty = true;
+      self.battery_writes = self.battery_writes.wrapping_add(1);
+    }
+    Ok(())
+  }
+
+  /// Write every frozen value back. Runs by itself at the start of each
+  /// vblank.
+  pub fn apply_cheats(&mut self) {
+    for freeze in self.cheats.freezes() {
+      match freeze.addr {
+        0x0000..=0x07FF => self.cpu_vram[freeze.addr as usize] = freeze.value,
+        _ => self.mapper.prg_write(freeze.addr, freeze.value),
+      }
+    }
+  }
+
+  fn tick_ppu(&mut self, mut dots: u32) {
+    while dots > 0 {
+      // up to the end of the line, so the mapper hears of every one
+      let step = dots.min((DOTS_PER_SCANLINE - self.ppu.cycle) as u32);
+      dots -= step;
+      let scanline = self.ppu.scanline;
+      let frame_done = self.ppu.tick(&*self.mapper, step);
+      if self.ppu.scanline != scanline {
+        let rendering = self.ppu.mask.rendering_enabled();
+        self.mapper.ppu_scanline(self.ppu.scanline, rendering);
+      }
+      if frame_done {
+        self.apply_cheats();
+        self.channels.record(self.ppu.frame_count, &self.apu);
+        for joypad in self.joypads.iter_mut() {
+          joypad.set_frame(self.ppu.frame_count);
+        }
+        if let Some(zapper) = &mut self.zapper {
+          zapper.end_frame();
+        }
+        if let Some(log) = &mut self.scanline_events {
+          log.end_frame();
+        }
+      }
+    }
+  }
+
+  /// $4014: copy page $XX00-$XXFF into OAM, once the writing instruction
+  /// is over.
+  fn start_oam_dma(&mut self, page: u8) {
+    let scanline = self.ppu.scanline;
+    if scanline < 240 && self.ppu.mask.rendering_enabled() {
+      self.warn(Warning::OamDmaDuringRendering { scanline });
+    }
+    self.queue_oam_dma(page);
+  }
+
+  fn queue_oam_dma(&mut self, page: u8) {
+    self.oam_dma = Some(OamDma {
+      page,
+      copied: 0,
+      stall_cycles: 0,
+      stall_remaining: 0,
+      frame: self.ppu.frame_count,
+      scanline: self.ppu.scanline,
+    });
+    self.oam_dma_pending = true;
+  }
+
+  // the CPU halted for the DMA: a halt cycle, one more if that was a get
+  // cycle so the reads line up with get cycles, then 256 get/put pairs,
+  // reading a byte on the get and writing it to OAM on the put
+  fn run_oam_dma(&mut self) {
+    let mut dma = match self.oam_dma {
+      Some(dma) => dma,
+      None => return,
+    };
+    self.record_event(LineEvents::OAM_DMA);
+    let stall = OAM_DMA_CYCLES as u16 + self.apu.get_cycle() as u16;
+    self.stall_cycles = self.stall_cycles.saturating_add(stall);
+    dma.stall_cycles = stall;
+    dma.stall_remaining = stall;
+    let alignment = stall - 512;
+    dma.stall_remaining -= alignment;
+    self.oam_dma = Some(dma);
+    self.run_cycles(alignment as u32);
+    let hi = (dma.page as u16) << 8;
+    for i in 0..256 {
+      let byte = self.mem_read(hi + i);
+      self.run_cycles(2);
+      self.ppu.write_to_oam_data(byte);
+      dma.copied += 1;
+      dma.stall_remaining -= 2;
+      self.oam_dma = Some(dma);
+    }
+  }
+
+  /// The last OAM DMA since power on, None if there wasn't any.
+  pub fn last_oam_dma(&self) -> Option<OamDma> {
+    self.oam_dma
+  }
+
+  // PPU dots in `cycles` CPU cycles, carrying the fraction over
+  fn dots(&mut self, cycles: u32) -> u32 {
+    let (dots, per_cycles) = self.timing().dots_per_cycle();
+    let total = cycles * dots + self.dot_phase;
+    self.dot_phase = total % per_cycles;
+    total / per_cycles
+  }
+
+  fn run_cycles(&mut self, cycles: u32) {
+    let dots = self.dots(cycles);
+    let clock = self.profiler.as_mut().and_then(Profiler::sample);
+    let start = clock.map_or(0.0, |now| now());
+    self.tick_ppu(dots);
+    let ppu_done = clock.map_or(0.0, |now| now());
+    self.mapper.cpu_cycles(cycles);
+    let mut stall = self.apu.tick(&*self.mapper, cycles);
+    if let (Some(now), Some(profiler)) = (clock, &mut self.profiler) {
+      profiler.add(ppu_done - start, now() - ppu_done);
+    }
+    // the CPU sits out DMC fetches while everything else keeps running
+    while stall > 0 {
+      self.record_event(LineEvents::DMC_DMA);
+      self.stall_cycles = self.stall_cycles.saturating_add(stall as u16);
+      let dots = self.dots(stall);
+      self.tick_ppu(dots);
+      self.mapper.cpu_cycles(stall);
+      stall = self.apu.tick(&*self.mapper, stall);
+    }
+  }
+}
+
+impl Mem for Bus {
+  fn mem_read(&mut self, addr: u16) -> u8 {
+    let data = match addr {
+      RAM..=RAM_MIRRORS_END => {
+        let mirror_down_addr = addr & 0b0000_0111_1111_1111;
+        self.cpu_vram[mirror_down_addr as usize]
+      }
+      PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
+        let mirror_down_addr = addr & 0b0010_0000_0000_0111;
+        // and which bits of it the PPU drives
+        let (data, driven) = match mirror_down_addr {
+          0x2002 => (self.ppu.read_status(), 0b1110_0000),
+          0x2004 => (self.ppu.read_oam_data(), 0xFF),
+          0x2007 => (self.ppu.read_data(&mut *self.mapper), 0xFF),
+          _ => {
+            self.warn(Warning::OpenBusRead { addr });
+            (self.ppu.open_bus(mirror_down_addr), 0)
+          }
+        };
+        self.ppu.drive_latch(data, driven);
+        data
+      }
+      // bit 5 isn't driven
+      APU_STATUS => self.apu.read_status() | (self.open_bus & 0x20),
+      JOYPAD1 => self.read_joypad(0),
+      JOYPAD2 => self.read_joypad(1),
+      APU_IO_REGISTERS..=APU_IO_REGISTERS_END => {
+        self.warn(Warning::OpenBusRead { addr });
+        self.open_bus
+      }
+      CARTRIDGE..=0xFFFF if self.mapper.prg_mapped(addr) => {
+        self.cheats.patch_read(addr, self.mapper.prg_read_mut(addr))
+      }
+      CARTRIDGE..=0xFFFF => self.open_bus,
+    };
+    self.open_bus = data;
+    self.wrote_last = false;
+    self.watchpoints.check(addr, data, Access::Read);
+    data
+  }
+
+  fn mem_write(&mut self, addr: u16, data: u8) {
+    self.open_bus = data;
+    let back_to_back = std::mem::replace(&mut self.wrote_last, true);
+    self.watchpoints.check(addr, data, Access::Write);
+    match addr {
+      RAM..=RAM_MIRRORS_END => {
+        let mirror_down_addr = addr & 0b0000_0111_1111_1111;
+        self.cpu_vram[mirror_down_addr as usize] = data;
+      }
+      PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
+        let mirror_down_addr = addr & 0b0010_0000_0000_0111;
+        self.ppu.drive_latch(data, 0xFF);
+        match mirror_down_addr {
+          0x2000 => self.ppu.write_to_ctrl(data),
+          0x2001 => self.ppu.write_to_mask(data),
+          0x2002 => trace!("attempt to write to PPU status register"),
+          0x2003 => self.ppu.write_to_oam_addr(data),
+          0x2004 => self.ppu.write_to_oam_data(data),
+          0x2005 => self.ppu.write_to_scroll(data),
+          0x2006 => self.ppu.write_to_ppu_addr(data),
+          _ => self.ppu.write_to_data(&mut *self.mapper, data),
+        }
+      }
+      OAM_DMA => self.start_oam_dma(data),
+      // one strobe line to both ports
+      JOYPAD1 => {
+        self.joypads.iter_mut().for_each(|pad| pad.write(data));
+        if let Some(four_score) = &mut self.four_score {
+          four_score.write(data);
+        }
+      }
+      APU_IO_REGISTERS..=APU_REGISTERS_END | APU_STATUS | APU_FRAME_COUNTER => {
+        self.apu.write_register(addr, data)
+      }
+      APU_IO_REGISTERS..=APU_IO_REGISTERS_END => {
+        trace!("APU/IO is not supported yet, write {:04x}", addr);
+      }
+      CARTRIDGE..=0xFFFF => {
+        // NROM has nothing to write to up there
+        if addr >= 0x8000 && self.rom_info.map_or(false, |info| info.mapper == 0) {
+          self.warn(Warning::RomWrite { addr, value: data });
+        }
+        if (0x6000..=0x7FFF).contains(&addr) && self.has_battery() {
+          self.battery_dirty = true;
+          self.battery_writes = self.battery_writes.wrapping_add(1);
+        }
+        if back_to_back && addr >= 0x8000 && self.mapper.ignores_back_to_back_writes() {
+          trace!(
+            "{} ignored back-to-back write to {:04x}",
+            self.mapper.name(),
+            addr
+          );
+          return;
+        }
+        self.mapper.prg_write(addr, data)
+      }
+    }
+  }
+
+  fn tick(&mut self, cycles: u8) {
+    self.run_cycles(cycles as u32);
+    if std::mem::take(&mut self.oam_dma_pending) {
+      self.run_oam_dma();
+    }
+  }
+
+  fn poll_nmi_status(&mut self) -> bool {
+    self.ppu.poll_nmi()
+  }
+
+  fn poll_irq_status(&self) -> bool {
+    self.mapper.irq_pending() || self.apu.irq_pending()
+  }
+
+  fn interrupt_taken(&mut self, nmi: bool) {
+    self.record_event(if nmi {
+      LineEvents::NMI
+    } else {
+      LineEvents::IRQ
+    });
+  }
+
+  fn take_stall_cycles(&mut self) -> u16 {
+    std::mem::take(&mut self.stall_cycles)
+  }
+
+  fn warn(&mut self, warning: Warning) {
+    self.warnings.report(self.ppu.frame_count, warning);
+  }
+
+  fn ppu_position(&self) -> (u16, u16) {
+    (self.ppu.scanline, self.ppu.cycle)
+  }
+
+  fn mem_peek(&self, addr: u16) -> u8 {
+    match addr {
+      RAM..=RAM_MIRRORS_END => self.cpu_vram[(addr & 0b0000_0111_1111_1111) as usize],
+      PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => match addr & 0b0010_0000_0000_0111 {
+        0x2002 => self.ppu.peek_status(),
+        0x2004 => self.ppu.read_oam_data(),
+        0x2007 => self.ppu.peek_data(),
+        _ => self.ppu.io_latch(),
+      },
+      APU_STATUS => self.apu.peek_status() | (self.open_bus & 0x20),
+      JOYPAD1 => self.peek_joypad(0),
+      JOYPAD2 => self.peek_joypad(1),
+      APU_IO_REGISTERS..=APU_IO_REGISTERS_END => self.open_bus,
+      CARTRIDGE..=0xFFFF if self.mapper.prg_mapped(addr) => {
+        self.cheats.patch_read(addr, self.mapper.prg_read(addr))
+      }
+      CARTRIDGE..=0xFFFF => self.open_bus,
+    }
+  }
+}
@@ -0,0 +1,300 @@
+use std::fmt;
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+pub const PRG_ROM_PAGE_SIZE: usize = 16384;
+pub const CHR_ROM_PAGE_SIZE: usize = 8192;
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mirroring {
+  Vertical,
+  Horizontal,
+  FourScreen,
+  /// Every nametable is the first 1KB of CIRAM (mapper controlled).
+  SingleScreenLower,
+  /// Every nametable is the second 1KB of CIRAM (mapper controlled).
+  SingleScreenUpper,
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeaderFormat {
+  INes,
+  Nes2,
+}
+
+/// CPU/PPU timing the game was made for.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Region {
+  Ntsc,
+  Pal,
+  /// Runs on both, e.g. by checking the PPU at boot.
+  MultiRegion,
+  Dendy,
+}
+
+/// Why a file couldn't be loaded as a cartridge.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RomError {
+  /// Missing the `NES<EOF>` tag.
+  NotINes,
+  /// Header is valid but the version isn't supported yet.
+  UnsupportedVersion,
+  /// The file is shorter than its header says.
+  Truncated { expected: usize, actual: usize },
+  /// The header gives ROM sizes too big to address, no file could hold
+  /// them.
+  SizeOverflow,
+  /// No PRG ROM at all, nothing to run.
+  NoPrgRom,
+  /// Parsed fine, but the board isn't emulated. `known_name` is what the
+  /// board is usually called, if it's one `mapper::board_name` knows.
+  UnsupportedMapper {
+    number: u16,
+    submapper: u8,
+    known_name: Option<&'static str>,
+  },
+}
+
+impl fmt::Display for RomError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      RomError::NotINes => write!(f, "file is not in iNES file format"),
+      RomError::UnsupportedVersion => write!(f, "unknown iNES header version"),
+      RomError::Truncated { expected, actual } => write!(
+        f,
+        "rom is truncated: expected {} bytes, got {}",
+        expected, actual
+      ),
+      RomError::SizeOverflow => write!(f, "rom header gives an impossible size"),
+      RomError::NoPrgRom => write!(f, "rom has no PRG ROM"),
+      RomError::UnsupportedMapper {
+        number,
+        submapper,
+        known_name,
+      } => {
+        write!(f, "mapper {}", number)?;
+        if *submapper != 0 {
+          write!(f, ".{}", submapper)?;
+        }
+        if let Some(name) = known_name {
+          write!(f, " ({})", name)?;
+        }
+        write!(f, " is not supported")
+      }
+    }
+  }
+}
+
+impl std::error::Error for RomError {}
+
+/// Everything the header says about the cartridge, sizes in bytes.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RomInfo {
+  pub format: HeaderFormat,
+  pub mapper: u16,
+  pub submapper: u8,
+  pub mirroring: Mirroring,
+  pub battery: bool,
+  pub trainer: bool,
+  pub region: Region,
+  pub prg_rom_size: usize,
+  pub chr_rom_size: usize,
+  pub prg_ram_size: usize,
+  pub prg_nvram_size: usize,
+  pub chr_ram_size: usize,
+  pub chr_nvram_size: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rom {
+  pub prg_rom: Vec<u8>,
+  pub chr_rom: Vec<u8>,
+  pub info: RomInfo,
+}
+
+/*
+  iNES header, 16 bytes:
+
+  0-3   "NES" followed by MS-DOS end-of-file ($1A)
+  4     number of 16KB PRG ROM banks
+  5     number of 8KB CHR ROM banks (0 means the board uses CHR RAM)
+  6     control byte 1
+        76543210
+        ||||||||
+        |||||||+- mirroring: 0 horizontal, 1 vertical
+        ||||||+-- battery-backed PRG RAM at $6000-$7FFF
+        |||||+--- 512-byte trainer before PRG ROM
+        ||||+---- four-screen VRAM
+        ++++----- lower 4 bits of mapper number
+  7     control byte 2
+        76543210
+        ||||||||
+        ||||++++- bits 2-3 equal to 2 mean NES 2.0
+        ++++----- upper 4 bits of mapper number
+  8-15  unused in iNES 1.0 (bit 0 of 9 is the TV system, rarely set)
+
+  NES 2.0 reuses bytes 8-15:
+
+  8     submapper (high nibble), mapper bits 8-11 (low nibble)
+  9     CHR ROM size MSB (high nibble), PRG ROM size MSB (low nibble)
+  10    PRG-NVRAM shift (high nibble), PRG-RAM shift (low nibble)
+  11    CHR-NVRAM shift (high nibble), CHR-RAM shift (low nibble)
+  12    CPU/PPU timing: 0 NTSC, 1 PAL, 2 multi-region, 3 Dendy
+
+  A RAM shift count of n means 64 << n bytes, 0 means none. A ROM size MSB
+  of $F switches the LSB to exponent-multiplier notation: EEEEEEMM gives
+  2^E * (MM * 2 + 1) bytes.
+*/
+impl Rom {
+  pub fn from_bytes(raw: &[u8]) -> Result<Rom, RomError> {
+    if raw.len() < HEADER_SIZE || raw[0..4] != NES_TAG {
+      return Err(RomError::NotINes);
+    }
+
+    let format = match (raw[7] >> 2) & 0b11 {
+      0 => HeaderFormat::INes,
+      2 => HeaderFormat::Nes2,
+      _ => return Err(RomError::UnsupportedVersion),
+    };
+
+    let four_screen = raw[6] & 0b1000 != 0;
+    let vertical_mirroring = raw[6] & 0b1 != 0;
+    let mirroring = match (four_screen, vertical_mirroring) {
+      (true, _) => Mirroring::FourScreen,
+      (false, true) => Mirroring::Vertical,
+      (false, false) => Mirroring::Horizontal,
+    };
+    let battery = raw[6] & 0b10 != 0;
+    let trainer = raw[6] & 0b100 != 0;
+    let mapper = ((raw[7] & 0b1111_0000) | (raw[6] >> 4)) as u16;
+
+    let info = match format {
+      HeaderFormat::INes => {
+        let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+        RomInfo {
+          format,
+          mapper,
+          submapper: 0,
+          mirroring,
+          battery,
+          trainer,
+          region: if raw[9] & 0b1 != 0 {
+            Region::Pal
+          } else {
+            Region::Ntsc
+          },
+          prg_rom_size: raw[4] as usize * PRG_ROM_PAGE_SIZE,
+          chr_rom_size,
+          // iNES 1.0 can't describe RAM, assume the usual 8KB
+          prg_ram_size: if battery { 0 } else { 0x2000 },
+          prg_nvram_size: if battery { 0x2000 } else { 0 },
+          chr_ram_size: if chr_rom_size == 0 { 0x2000 } else { 0 },
+          chr_nvram_size: 0,
+        }
+      }
+      HeaderFormat::Nes2 => RomInfo {
+        prg_rom_size: nes2_rom_size(raw[4], raw[9] & 0b1111, PRG_ROM_PAGE_SIZE)
+          .ok_or(RomError::SizeOverflow)?,
+        chr_rom_size: nes2_rom_size(raw[5], raw[9] >> 4, CHR_ROM_PAGE_SIZE)
+          .ok_or(RomError::SizeOverflow)?,
+        format,
+        mapper: mapper | ((raw[8] as u16 & 0b1111) << 8),
+        submapper: raw[8] >> 4,
+        mirroring,
+        battery,
+        trainer,
+        region: match raw[12] & 0b11 {
+          0 => Region::Ntsc,
+          1 => Region::Pal,
+          2 => Region::MultiRegion,
+          _ => Region::Dendy,
+        },
+        prg_ram_size: nes2_ram_size(raw[10] & 0b1111),
+        prg_nvram_size: nes2_ram_size(raw[10] >> 4),
+        chr_ram_size: nes2_ram_size(raw[11] & 0b1111),
+        chr_nvram_size: nes2_ram_size(raw[11] >> 4),
+      },
+    };
+
+    if info.prg_rom_size == 0 {
+      return Err(RomError::NoPrgRom);
+    }
+
+    let prg_rom_start = HEADER_SIZE + if trainer { TRAINER_SIZE } else { 0 };
+    let chr_rom_start = prg_rom_start
+      .checked_add(info.prg_rom_size)
+      .ok_or(RomError::SizeOverflow)?;
+    let expected = chr_rom_start
+      .checked_add(info.chr_rom_size)
+      .ok_or(RomError::SizeOverflow)?;
+    if raw.len() < expected {
+      return Err(RomError::Truncated {
+        expected,
+        actual: raw.len(),
+      });
+    }
+
+    Ok(Rom {
+      prg_rom: raw[prg_rom_start..chr_rom_start].to_vec(),
+      chr_rom: raw[chr_rom_start..expected].to_vec(),
+      info,
+    })
+  }
+
+  /// Wrap a bare 6502 program in a 32KB NROM image: the program sits at
+  /// $8000 and the reset vector points at it.
+  pub fn from_program(program: &[u8]) -> Rom {
+    let mut prg_rom = vec![0; 2 * PRG_ROM_PAGE_SIZE];
+    prg_rom[..program.len()].copy_from_slice(program);
+    // reset vector at $FFFC
+    prg_rom[0x7FFC] = 0x00;
+    prg_rom[0x7FFD] = 0x80;
+
+    Rom {
+      info: RomInfo {
+        format: HeaderFormat::INes,
+        mapper: 0,
+        submapper: 0,
+        mirroring: Mirroring::Horizontal,
+        battery: false,
+        trainer: false,
+        region: Region::Ntsc,
+        prg_rom_size: prg_rom.len(),
+        chr_rom_size: CHR_ROM_PAGE_SIZE,
+        prg_ram_size: 0x2000,
+        prg_nvram_size: 0,
+        chr_ram_size: 0,
+        chr_nvram_size: 0,
+      },
+      prg_rom,
+      chr_rom: vec![0; CHR_ROM_PAGE_SIZE],
+    }
+  }
+}
+
+// None when it doesn't fit in a usize
+fn nes2_rom_size(lsb: u8, msb: u8, page_size: usize) -> Option<usize> {
+  if msb == 0b1111 {
+    let exponent = (lsb >> 2) as u32;
+    let multiplier = (lsb & 0b11) as usize * 2 + 1;
+    let power = 1usize.checked_shl(exponent)?;
+    power.checked_mul(multiplier)
+  } else {
+    (((msb as usize) << 8) | lsb as usize).checked_mul(page_size)
+  }
+}
+
+fn nes2_ram_size(shift: u8) -> usize {
+  if shift == 0 {
+    0
+  } else {
+    64 << shift
+  }
+}
@@ -0,0 +1,158 @@
+use std::fmt;
+
+/*
+  Two kinds of cheats. Freezes write RAM back every frame. Game Genie codes
+  work like the real device sitting between the console and the cartridge:
+  they don't change anything, they answer some PRG ROM reads with another
+  value, either always or (8 letter codes) only when the ROM holds the
+  expected value there, so the patch only applies in the right bank.
+*/
+
+// each letter is a nibble, in this order
+const GAME_GENIE_LETTERS: &[u8; 16] = b"APZLGITYEOXUKSVN";
+
+/// A RAM address locked to a value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Freeze {
+  pub addr: u16,
+  pub value: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CheatError {
+  /// Only work RAM ($0000-$1FFF) and PRG RAM ($6000-$7FFF) can be frozen;
+  /// forcing a register every frame would replay its side effects.
+  NotRam(u16),
+  /// Not 6 or 8 Game Genie letters.
+  BadGameGenie(String),
+}
+
+impl fmt::Display for CheatError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      CheatError::NotRam(addr) => write!(f, "${:04X} is not RAM, it can't be frozen", addr),
+      CheatError::BadGameGenie(code) => write!(f, "{} is not a Game Genie code", code),
+    }
+  }
+}
+
+impl std::error::Error for CheatError {}
+
+/// A decoded Game Genie code: reads of `addr` return `value`, if
+/// `compare` is set only while the ROM has `compare` there.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameGenie {
+  /// As entered, upper case.
+  pub code: String,
+  pub addr: u16,
+  pub value: u8,
+  pub compare: Option<u8>,
+}
+
+impl GameGenie {
+  pub fn decode(code: &str) -> Result<GameGenie, CheatError> {
+    let code = code.trim().to_ascii_uppercase();
+    let n: Vec<u16> = code
+      .bytes()
+      .map(|letter| GAME_GENIE_LETTERS.iter().position(|&l| l == letter))
+      .collect::<Option<Vec<usize>>>()
+      .filter(|n| n.len() == 6 || n.len() == 8)
+      .ok_or_else(|| CheatError::BadGameGenie(code.clone()))?
+      .into_iter()
+      .map(|n| n as u16)
+      .collect();
+    let addr = 0x8000
+      | (n[3] & 7) << 12
+      | (n[5] & 7) << 8
+      | (n[4] & 8) << 8
+      | (n[2] & 7) << 4
+      | (n[1] & 8) << 4
+      | (n[4] & 7)
+      | (n[3] & 8);
+    // the bits of the last letter are shuffled into the compare value
+    // when there is one
+    let last = if n.len() == 8 { n[7] } else { n[5] };
+    let value = ((n[1] & 7) << 4 | (n[0] & 8) << 4 | (n[0] & 7) | (last & 8)) as u8;
+    let compare = if n.len() == 8 {
+      Some(((n[7] & 7) << 4 | (n[6] & 8) << 4 | (n[6] & 7) | (n[5] & 8)) as u8)
+    } else {
+      None
+    };
+    Ok(GameGenie {
+      code,
+      addr,
+      value,
+      compare,
+    })
+  }
+}
+
+/// Active cheats. The bus rewrites every frozen address once per frame,
+/// when vblank starts: after the frame's game logic, before its NMI
+/// handler reads anything back. Game Genie codes apply on every PRG ROM
+/// read.
+#[derive(Debug, Clone, Default)]
+pub struct Cheats {
+  freezes: Vec<Freeze>,
+  game_genie: Vec<GameGenie>,
+}
+
+impl Cheats {
+  /// Lock `addr` to `value`, replacing an earlier freeze of it. RAM
+  /// mirrors collapse onto the address they mirror.
+  pub fn freeze(&mut self, addr: u16, value: u8) -> Result<(), CheatError> {
+    let addr = match addr {
+      0x0000..=0x1FFF => addr & 0x07FF,
+      0x6000..=0x7FFF => addr,
+      _ => return Err(CheatError::NotRam(addr)),
+    };
+    self.unfreeze(addr);
+    self.freezes.push(Freeze { addr, value });
+    Ok(())
+  }
+
+  pub fn unfreeze(&mut self, addr: u16) {
+    let addr = if addr <= 0x1FFF { addr & 0x07FF } else { addr };
+    self.freezes.retain(|freeze| freeze.addr != addr);
+  }
+
+  /// Decode and enable a Game Genie code, replacing one for the same
+  /// address.
+  pub fn add_game_genie(&mut self, code: &str) -> Result<(), CheatError> {
+    let code = GameGenie::decode(code)?;
+    self.game_genie.retain(|other| other.addr != code.addr);
+    self.game_genie.push(code);
+    Ok(())
+  }
+
+  pub fn remove_game_genie(&mut self, code: &str) {
+    let code = code.trim().to_ascii_uppercase();
+    self.game_genie.retain(|other| other.code != code);
+  }
+
+  /// Freezes and Game Genie codes alike.
+  pub fn clear(&mut self) {
+    self.freezes.clear();
+    self.game_genie.clear();
+  }
+
+  /// In the order they were frozen.
+  pub fn freezes(&self) -> &[Freeze] {
+    &self.freezes
+  }
+
+  /// In the order they were added.
+  pub fn game_genie_codes(&self) -> &[GameGenie] {
+    &self.game_genie
+  }
+
+  /// What a PRG ROM read of `addr` returns with the codes applied, `rom`
+  /// being what the cartridge answered.
+  pub fn patch_read(&self, addr: u16, rom: u8) -> u8 {
+    self
+      .game_genie
+      .iter()
+      .find(|code| code.addr == addr && code.compare.map_or(true, |compare| compare == rom))
+      .map_or(rom, |code| code.value)
+  }
+}
@@ -0,0 +1,173 @@
+use crate::nes::joypad::{Joypad, JoypadButton};
+use std::fmt;
+
+/*
+  Combos: a few seconds of one controller's input, recorded once and
+  played back by a single key, e.g. a fighting game's special move or a
+  menu sequence.
+
+  Like turbo, playback is timed in the PPU's frames by the `Joypad`
+  itself: combo frame n is pressed, on top of whatever the player holds,
+  for the whole of the nth frame after `Joypad::play_combo`, so it comes
+  out the same however the frontend's key events line up with frames.
+
+  The text form, for configs, is one token per run of frames holding the
+  same buttons, `buttons*frames`, the buttons in FM2's RLDUTSBA letters
+  or `.` for none:
+
+    D*2 RD*2 R*1 RB*3
+*/
+
+/// The longest combo, 10 seconds of NTSC frames.
+pub const MAX_FRAMES: usize = 600;
+
+// letters for the buttons, in the order FM2 writes them
+const LETTERS: [(char, JoypadButton); 8] = [
+  ('R', JoypadButton::RIGHT),
+  ('L', JoypadButton::LEFT),
+  ('D', JoypadButton::DOWN),
+  ('U', JoypadButton::UP),
+  ('T', JoypadButton::START),
+  ('S', JoypadButton::SELECT),
+  ('B', JoypadButton::B),
+  ('A', JoypadButton::A),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ComboError {
+  /// Token `token` (from 1) isn't `buttons*frames`.
+  Syntax { token: usize },
+  /// More than `MAX_FRAMES` frames.
+  TooLong,
+}
+
+impl fmt::Display for ComboError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ComboError::Syntax { token } => write!(f, "bad combo token {}", token),
+      ComboError::TooLong => write!(f, "combo is longer than {} frames", MAX_FRAMES),
+    }
+  }
+}
+
+impl std::error::Error for ComboError {}
+
+/// The buttons of every frame, first to last.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Combo {
+  pub frames: Vec<JoypadButton>,
+}
+
+impl Combo {
+  pub fn len(&self) -> usize {
+    self.frames.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.frames.is_empty()
+  }
+
+  pub fn to_text(&self) -> String {
+    let mut runs: Vec<(JoypadButton, usize)> = Vec::new();
+    for &buttons in &self.frames {
+      match runs.last_mut() {
+        Some((held, frames)) if *held == buttons => *frames += 1,
+        _ => runs.push((buttons, 1)),
+      }
+    }
+    let tokens: Vec<String> = runs
+      .iter()
+      .map(|&(buttons, frames)| format!("{}*{}", letters(buttons), frames))
+      .collect();
+    tokens.join(" ")
+  }
+
+  pub fn from_text(text: &str) -> Result<Combo, ComboError> {
+    let mut frames = Vec::new();
+    for (i, token) in text.split_whitespace().enumerate() {
+      let error = ComboError::Syntax { token: i + 1 };
+      let (buttons, count) = token.split_once('*').ok_or_else(|| error.clone())?;
+      let count: usize = count.parse().map_err(|_| error.clone())?;
+      let buttons = parse_letters(buttons).ok_or_else(|| error.clone())?;
+      if count == 0 {
+        return Err(error);
+      }
+      if frames.len() + count > MAX_FRAMES {
+        return Err(ComboError::TooLong);
+      }
+      frames.extend(std::iter::repeat(buttons).take(count));
+    }
+    Ok(Combo { frames })
+  }
+}
+
+fn letters(buttons: JoypadButton) -> String {
+  if buttons.is_empty() {
+    return ".".to_string();
+  }
+  LETTERS
+    .iter()
+    .filter(|(_, button)| buttons.contains(*button))
+    .map(|(letter, _)| letter)
+    .collect()
+}
+
+fn parse_letters(text: &str) -> Option<JoypadButton> {
+  if text == "." {
+    return Some(JoypadButton::empty());
+  }
+  if text.is_empty() {
+    return None;
+  }
+  text.chars().try_fold(JoypadButton::empty(), |buttons, c| {
+    let &(_, button) = LETTERS.iter().find(|(letter, _)| *letter == c)?;
+    Some(buttons | button)
+  })
+}
+
+/// Builds a combo from a controller, sampled once at the start of every
+/// frame, as movies record. Frames before the first press and after the
+/// last release are left out, so it doesn't matter how long the player
+/// waits around starting and stopping.
+#[derive(Debug, Clone, Default)]
+pub struct ComboRecorder {
+  frames: Vec<JoypadButton>,
+}
+
+impl ComboRecorder {
+  pub fn new() -> Self {
+    ComboRecorder::default()
+  }
+
+  /// What `pad` presses this frame, turbo included. Past `MAX_FRAMES` the
+  /// rest is dropped.
+  pub fn record(&mut self, pad: &Joypad) {
+    let buttons = pad.buttons();
+    if (self.frames.is_empty() && buttons.is_empty()) || self.frames.len() == MAX_FRAMES {
+      return;
+    }
+    self.frames.push(buttons);
+  }
+
+  /// Frames recorded so far, waiting for the first press not counted.
+  pub fn len(&self) -> usize {
+    self.frames.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.frames.is_empty()
+  }
+
+  pub fn finish(mut self) -> Combo {
+    while self
+      .frames
+      .last()
+      .map_or(false, |buttons| buttons.is_empty())
+    {
+      self.frames.pop();
+    }
+    Combo {
+      frames: self.frames,
+    }
+  }
+}
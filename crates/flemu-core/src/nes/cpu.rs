@@ -0,0 +1,1511 @@
+use crate::nes::bus::Bus;
+pub use crate::nes::bus::Mem;
+use crate::nes::cartridge::{Rom, RomError};
+use crate::nes::diagnostics::{nestest_line, TraceEntry, TraceLog};
+use crate::nes::opcodes;
+use crate::nes::savestate::{StateError, StateReader, StateWriter};
+use crate::nes::warnings::Warning;
+use bitflags::bitflags;
+use log::{debug, error, trace};
+use std::fmt;
+
+use crate::rng::SeededRng;
+
+bitflags! {
+  /// # Status Register (P) http://wiki.nesdev.com/w/index.php/Status_flags
+  ///
+  ///  7 6 5 4 3 2 1 0
+  ///  N V _ B D I Z C
+  ///  | |   | | | | +--- Carry Flag
+  ///  | |   | | | +----- Zero Flag
+  ///  | |   | | +------- Interrupt Disable
+  ///  | |   | +--------- Decimal Mode (not used on NES)
+  ///  | |   +----------- Break Command
+  ///  | +--------------- Overflow Flag
+  ///  +----------------- Negative Flag
+  ///
+  #[cfg_attr(feature = "wasm", wasm_bindgen)]
+  pub struct CpuFlags: u8 {
+    const CARRY             = 0b00000001;
+    const ZERO              = 0b00000010;
+    const INTERRUPT_DISABLE = 0b00000100;
+    const DECIMAL_MODE      = 0b00001000;
+    const BREAK             = 0b00010000;
+    const BREAK2            = 0b00100000;
+    const OVERFLOW          = 0b01000000;
+    const NEGATIVE          = 0b10000000;
+  }
+}
+
+const STACK: u16 = 0x0100;
+const STACK_RESET: u8 = 0xfd;
+
+/*
+ NES CPU can address 65536 memory cells. It takes
+ 2 bytes to store an address. NES CPU uses
+ Little-Endian addressing rather than Big-Endian.
+ That means that the 8 least significant bits of
+ an address will be stored before the 8 most
+ significant bits.
+
+ For example:
+
+     Real address: 0x8000
+     address packed in big-endian:    80 00
+     address packed in little-endian: 00 80
+
+ ASM Example:
+
+     LDA $8000   <=>    ad 00 80
+*/
+#[derive(Clone)]
+pub struct CPU<B: Mem = Bus> {
+  // accumulator
+  pub register_a: u8,
+  // index x
+  pub register_x: u8,
+  // index y
+  pub register_y: u8,
+  // processor status
+  pub status: CpuFlags,
+  // current program address
+  pub program_counter: u16,
+  // current stack_pointer
+  pub stack_pointer: u8,
+  // cpu cycles elapsed since reset
+  pub cycles: u64,
+  // refuse to execute undocumented opcodes
+  pub strict: bool,
+  // pure 6502 mode: ADC/SBC honor the D flag, which the NES 2A03 ignores
+  pub decimal_enabled: bool,
+  // stop at BRK instead of trapping through $FFFE, how bare programs end
+  pub halt_on_brk: bool,
+  // everything outside the CPU: RAM, PPU/APU registers, cartridge
+  pub bus: B,
+  // the machine's random source (power-on RAM), saved with it so a state
+  // goes on drawing the same numbers
+  pub rng: SeededRng,
+  // set when execution hit something it can't recover from
+  fault: Option<Fault>,
+  // timing penalties of the instruction being executed
+  page_crossed: bool,
+  extra_cycles: u8,
+  // writes and read-modify-writes take the fix-up cycle of indexing,
+  // and its dummy read, whether or not the page changes
+  always_fix_up: bool,
+  // last executed instructions, for core dumps
+  trace: TraceLog,
+  // every instruction in nestest.log format, while somebody's collecting
+  nestest_log: Option<Vec<String>>,
+}
+
+/// Unrecoverable condition that stopped the CPU.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault {
+  /// Not even an undocumented opcode, e.g. one of the JAMs.
+  UnknownOpcode { pc: u16, code: u8 },
+  /// Undocumented opcode while `strict` is set.
+  UnofficialOpcode { pc: u16, code: u8 },
+  /// An opcode the table knows but the CPU can't execute, a bug in the
+  /// emulator rather than the program.
+  Unimplemented { pc: u16, code: u8 },
+}
+
+/// Why `load`, `step` or `run` couldn't go on.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EmuError {
+  /// The cartridge couldn't be inserted.
+  Rom(RomError),
+  /// The CPU halted, see `CPU::fault`.
+  Fault(Fault),
+}
+
+impl fmt::Display for EmuError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      EmuError::Rom(error) => write!(f, "{}", error),
+      EmuError::Fault(fault) => write!(f, "{}", fault),
+    }
+  }
+}
+
+impl std::error::Error for EmuError {}
+
+impl From<RomError> for EmuError {
+  fn from(error: RomError) -> Self {
+    EmuError::Rom(error)
+  }
+}
+
+impl From<Fault> for EmuError {
+  fn from(fault: Fault) -> Self {
+    EmuError::Fault(fault)
+  }
+}
+
+impl fmt::Display for Fault {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Fault::UnknownOpcode { pc, code } => {
+        write!(f, "OpCode {:x} is not recognized at {:04x}", code, pc)
+      }
+      Fault::UnofficialOpcode { pc, code } => write!(
+        f,
+        "Unofficial OpCode {:x} at {:04x} in strict mode",
+        code, pc
+      ),
+      Fault::Unimplemented { pc, code } => {
+        write!(f, "OpCode {:x} at {:04x} is not implemented", code, pc)
+      }
+    }
+  }
+}
+
+/// Snapshot of the CPU registers for display in a debugger, cheap enough
+/// to take every frame.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CpuState {
+  pub a: u8,
+  pub x: u8,
+  pub y: u8,
+  pub sp: u8,
+  pub pc: u16,
+  pub flags: u8,
+  pub cycles: u64,
+}
+
+/*
+  NES platform has a special mechanism to mark where
+  the CPU should start the execution. Upon inserting
+  a new cartridge, the CPU receives a special signal
+  called "Reset interrupt" that instructs CPU to:
+
+  - reset the state (registers and flags)
+  - set program_counter to the 16-bit address that is stored at 0xFFFC
+*/
+const DEFAULT_PROGRAM_COUNTER: u16 = 0x8000;
+
+fn page_crossed(from: u16, to: u16) -> bool {
+  from & 0xFF00 != to & 0xFF00
+}
+
+/*
+  Interrupts push the program counter and the status, set the interrupt
+  disable flag and continue at the address stored in their vector:
+
+  $FFFA  NMI, raised by the PPU when vblank starts
+  $FFFC  reset
+  $FFFE  IRQ/BRK
+*/
+mod interrupt {
+  pub(super) struct Interrupt {
+    pub(super) vector_addr: u16,
+    // B flag as pushed on the stack
+    pub(super) b_flag: bool,
+    pub(super) cpu_cycles: u8,
+  }
+
+  pub(super) const NMI: Interrupt = Interrupt {
+    vector_addr: 0xFFFA,
+    b_flag: false,
+    cpu_cycles: 7,
+  };
+
+  pub(super) const IRQ: Interrupt = Interrupt {
+    vector_addr: 0xFFFE,
+    b_flag: false,
+    cpu_cycles: 7,
+  };
+
+  // the opcode table already counts BRK's 7 cycles
+  pub(super) const BRK: Interrupt = Interrupt {
+    vector_addr: 0xFFFE,
+    b_flag: true,
+    cpu_cycles: 0,
+  };
+}
+
+impl<B: Mem> Mem for CPU<B> {
+  fn mem_read(&mut self, addr: u16) -> u8 {
+    self.bus.mem_read(addr)
+  }
+
+  fn mem_write(&mut self, addr: u16, data: u8) {
+    self.bus.mem_write(addr, data);
+  }
+
+  fn mem_peek(&self, addr: u16) -> u8 {
+    self.bus.mem_peek(addr)
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[allow(non_camel_case_types)]
+pub enum AddressingMode {
+  Immediate,
+  ZeroPage,
+  ZeroPage_X,
+  ZeroPage_Y,
+  Absolute,
+  Absolute_X,
+  Absolute_Y,
+  Indirect_X,
+  Indirect_Y,
+  NoneAddressing,
+}
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+impl Default for CPU {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl CPU {
+  pub fn new() -> Self {
+    CPU::with_bus(Bus::new())
+  }
+
+  pub fn load_and_run(&mut self, program: Vec<u8>) -> Result<(), EmuError> {
+    self.reset();
+    self.load(program)?;
+    self.run()
+  }
+
+  /// Load a bare program at $8000, see `Rom::from_program`.
+  pub fn load(&mut self, program: Vec<u8>) -> Result<(), EmuError> {
+    debug!(
+      "loading {} bytes at {:04x}",
+      program.len(),
+      DEFAULT_PROGRAM_COUNTER
+    );
+    self.load_rom(Rom::from_program(&program))?;
+    Ok(())
+  }
+
+  /// Insert a cartridge and jump to its reset vector.
+  pub fn load_rom(&mut self, rom: Rom) -> Result<(), RomError> {
+    debug!(
+      "inserting cartridge: mapper {}, {}KB PRG, {}KB CHR",
+      rom.info.mapper,
+      rom.prg_rom.len() / 1024,
+      rom.chr_rom.len() / 1024
+    );
+    self.bus.insert_cartridge(rom)?;
+    self.program_counter = self.mem_read_u16(0xFFFC);
+    Ok(())
+  }
+
+  /*
+    Run loop for interactive frontends, which can't hand the thread to
+    `run` until BRK: each call does a bounded slice of emulation and
+    returns, false meaning the CPU has stopped (a fault, or BRK in a bare
+    program) and there's nothing more to run.
+  */
+
+  /// Execute one instruction, or service a pending interrupt.
+  pub fn step_instruction(&mut self) -> bool {
+    !self.done() && matches!(self.step(), Ok(Some(_)))
+  }
+
+  /// Run until the PPU moves to the next scanline.
+  pub fn step_scanline(&mut self) -> bool {
+    let scanline = self.bus.ppu.scanline;
+    while self.bus.ppu.scanline == scanline {
+      if !self.step_instruction() {
+        return false;
+      }
+    }
+    true
+  }
+
+  /// Run until the PPU finishes a frame, i.e. from one vblank to the next.
+  pub fn run_frame(&mut self) -> bool {
+    let frame = self.bus.ppu.frame_count;
+    while self.bus.ppu.frame_count == frame {
+      if !self.step_instruction() {
+        return false;
+      }
+    }
+    true
+  }
+
+  /// Registers, cycle count and fault, then the whole bus. Between
+  /// instructions there's nothing else in flight; the mode switches
+  /// (`strict` and the like) are settings and aren't saved.
+  pub fn save_state(&self, w: &mut StateWriter) {
+    self.save_registers(w);
+    self.bus.save_state(w);
+    w.u64(self.rng.state());
+  }
+
+  // the CPU's own part of `save_state`
+  pub(crate) fn save_registers(&self, w: &mut StateWriter) {
+    w.u8(self.register_a);
+    w.u8(self.register_x);
+    w.u8(self.register_y);
+    w.u8(self.status.bits());
+    w.u16(self.program_counter);
+    w.u8(self.stack_pointer);
+    w.u64(self.cycles);
+    let (kind, pc, code) = match self.fault {
+      None => (0, 0, 0),
+      Some(Fault::UnknownOpcode { pc, code }) => (1, pc, code),
+      Some(Fault::UnofficialOpcode { pc, code }) => (2, pc, code),
+      Some(Fault::Unimplemented { pc, code }) => (3, pc, code),
+    };
+    w.u8(kind);
+    w.u16(pc);
+    w.u8(code);
+  }
+
+  pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+    self.register_a = r.u8()?;
+    self.register_x = r.u8()?;
+    self.register_y = r.u8()?;
+    self.status = CpuFlags::from_bits_truncate(r.u8()?);
+    self.program_counter = r.u16()?;
+    self.stack_pointer = r.u8()?;
+    self.cycles = r.u64()?;
+    let kind = r.u8_below(4)?;
+    let pc = r.u16()?;
+    let code = r.u8()?;
+    self.fault = match kind {
+      0 => None,
+      1 => Some(Fault::UnknownOpcode { pc, code }),
+      2 => Some(Fault::UnofficialOpcode { pc, code }),
+      _ => Some(Fault::Unimplemented { pc, code }),
+    };
+    self.trace.clear();
+    self.bus.load_state(r)?;
+    self.rng = SeededRng::from_state(r.u64()?);
+    Ok(())
+  }
+}
+
+impl<B: Mem> CPU<B> {
+  pub fn with_bus(bus: B) -> Self {
+    CPU {
+      register_a: 0,
+      register_x: 0,
+      register_y: 0,
+
+      status: CpuFlags::from_bits_truncate(0b100100),
+      program_counter: 0,
+      stack_pointer: STACK_RESET,
+      cycles: 0,
+      strict: false,
+      decimal_enabled: false,
+      halt_on_brk: true,
+      bus,
+      rng: SeededRng::default(),
+      fault: None,
+      page_crossed: false,
+      extra_cycles: 0,
+      always_fix_up: false,
+      trace: TraceLog::new(),
+      nestest_log: None,
+    }
+  }
+
+  pub fn state(&self) -> CpuState {
+    CpuState {
+      a: self.register_a,
+      x: self.register_x,
+      y: self.register_y,
+      sp: self.stack_pointer,
+      pc: self.program_counter,
+      flags: self.status.bits(),
+      cycles: self.cycles,
+    }
+  }
+
+  fn get_operand_address(&mut self, mode: &AddressingMode) -> u16 {
+    match mode {
+      AddressingMode::Immediate => self.program_counter,
+
+      AddressingMode::ZeroPage => self.mem_read(self.program_counter) as u16,
+
+      AddressingMode::Absolute => self.mem_read_u16(self.program_counter),
+
+      AddressingMode::ZeroPage_X => {
+        let pos = self.mem_read(self.program_counter);
+        pos.wrapping_add(self.register_x) as u16
+      }
+      AddressingMode::ZeroPage_Y => {
+        let pos = self.mem_read(self.program_counter);
+        pos.wrapping_add(self.register_y) as u16
+      }
+
+      AddressingMode::Absolute_X => {
+        let base = self.mem_read_u16(self.program_counter);
+        self.indexed(base, self.register_x)
+      }
+      AddressingMode::Absolute_Y => {
+        let base = self.mem_read_u16(self.program_counter);
+        self.indexed(base, self.register_y)
+      }
+
+      AddressingMode::Indirect_X => {
+        let base = self.mem_read(self.program_counter);
+
+        let ptr: u8 = base.wrapping_add(self.register_x);
+        let lo = self.mem_read(ptr as u16);
+        let hi = self.mem_read(ptr.wrapping_add(1) as u16);
+        (hi as u16) << 8 | (lo as u16)
+      }
+      AddressingMode::Indirect_Y => {
+        let base = self.mem_read(self.program_counter);
+
+        let lo = self.mem_read(base as u16);
+        let hi = self.mem_read(base.wrapping_add(1) as u16);
+        let deref_base = (hi as u16) << 8 | (lo as u16);
+        self.indexed(deref_base, self.register_y)
+      }
+
+      // an opcode that takes no operand asked for one: stop once it's
+      // done rather than tear everything down
+      AddressingMode::NoneAddressing => {
+        let pc = self.program_counter.wrapping_sub(1);
+        let code = self.mem_peek(pc);
+        self.halt(Fault::Unimplemented { pc, code });
+        0
+      }
+    }
+  }
+
+  // the CPU needs a cycle to fix up the high byte when indexing crosses a
+  // page, read instructions only pay for it then. That cycle reads from
+  // the address before the carry, which registers like $2007 notice.
+  fn indexed(&mut self, base: u16, index: u8) -> u16 {
+    let addr = base.wrapping_add(index as u16);
+    self.page_crossed = page_crossed(base, addr);
+    if self.page_crossed || self.always_fix_up {
+      self.mem_read((base & 0xFF00) | (addr & 0x00FF));
+    }
+    addr
+  }
+
+  // read-modify-writes write the old value back while working out the
+  // new one
+  fn modify(&mut self, addr: u16, old: u8, new: u8) {
+    self.mem_write(addr, old);
+    self.mem_write(addr, new);
+  }
+
+  pub fn reset(&mut self) {
+    debug!("reset");
+    self.register_a = 0;
+    self.register_x = 0;
+    self.register_y = 0;
+
+    self.stack_pointer = STACK_RESET;
+    self.status = CpuFlags::from_bits_truncate(0b100100);
+    self.cycles = 0;
+    self.fault = None;
+    self.trace.clear();
+
+    self.program_counter = 0;
+  }
+
+  /// Why the CPU stopped, if it was not a BRK.
+  pub fn fault(&self) -> Option<Fault> {
+    self.fault
+  }
+
+  pub fn trace(&self) -> &TraceLog {
+    &self.trace
+  }
+
+  /// Start or stop collecting a nestest.log line for every instruction
+  /// executed. Stopping drops what wasn't taken.
+  pub fn set_nestest_log(&mut self, enabled: bool) {
+    self.nestest_log = if enabled { Some(Vec::new()) } else { None };
+  }
+
+  /// Lines collected since the last call, oldest first.
+  pub fn take_nestest_log(&mut self) -> Vec<String> {
+    self
+      .nestest_log
+      .as_mut()
+      .map(std::mem::take)
+      .unwrap_or_default()
+  }
+
+  fn halt(&mut self, fault: Fault) -> EmuError {
+    error!("{}", fault);
+    self.fault = Some(fault);
+    fault.into()
+  }
+
+  /// Fill internal RAM ($0000-$07FF) with values from `rng`, as found on
+  /// a freshly powered console.
+  pub fn randomize_ram(&mut self) {
+    for addr in 0x0000..0x0800 {
+      let value = self.rng.next_u8();
+      self.mem_write(addr, value);
+    }
+  }
+
+  /// Run from the reset vector until BRK.
+  pub fn run(&mut self) -> Result<(), EmuError> {
+    self.run_with_callback(|_| {})
+  }
+
+  pub fn done(&self) -> bool {
+    if self.fault.is_some() {
+      return true;
+    }
+
+    if self.program_counter == 0 || !self.halt_on_brk {
+      // no program loaded/running???
+      return false;
+    }
+
+    let code = self.mem_peek(self.program_counter);
+    code == 0x00
+  }
+
+  pub fn step_run<F>(&mut self, mut callback: F)
+  where
+    F: FnMut(&mut CPU<B>),
+  {
+    if self.done() {
+      return;
+    }
+
+    if let Ok(Some(_)) = self.step() {
+      callback(self);
+    }
+  }
+
+  /// `run`, calling `callback` after every instruction.
+  pub fn run_with_callback<F>(&mut self, mut callback: F) -> Result<(), EmuError>
+  where
+    F: FnMut(&mut CPU<B>),
+  {
+    // TODO - we might have run as address in future
+    self.program_counter = self.mem_read_u16(0xFFFC);
+    while self.step()?.is_some() {
+      callback(self);
+    }
+    Ok(())
+  }
+
+  /// Execute the instruction at `program_counter`, or service a pending
+  /// interrupt, and return how many cycles it took. The bus has already
+  /// been ticked by that much. None once BRK stops the program, an error
+  /// when a fault halts the CPU.
+  pub fn step(&mut self) -> Result<Option<u16>, EmuError> {
+    if self.bus.poll_nmi_status() {
+      self.bus.interrupt_taken(true);
+      return Ok(Some(self.interrupt(interrupt::NMI)));
+    }
+    // IRQ is level triggered and masked by I
+    if !self.status.contains(CpuFlags::INTERRUPT_DISABLE) && self.bus.poll_irq_status() {
+      self.bus.interrupt_taken(false);
+      return Ok(Some(self.interrupt(interrupt::IRQ)));
+    }
+    self.page_crossed = false;
+    self.extra_cycles = 0;
+
+    if self.nestest_log.is_some() {
+      let line = nestest_line(self);
+      if let Some(log) = self.nestest_log.as_mut() {
+        log.push(line);
+      }
+    }
+    let pc = self.program_counter;
+    let code = self.mem_read(pc);
+    self.trace.push(TraceEntry {
+      code,
+      state: self.state(),
+    });
+
+    let opcode = match opcodes::lookup(code) {
+      Some(opcode) => opcode,
+      None => return Err(self.halt(Fault::UnknownOpcode { pc, code })),
+    };
+    if opcode.is_unofficial() {
+      if self.strict {
+        return Err(self.halt(Fault::UnofficialOpcode { pc, code }));
+      }
+      self.bus.warn(Warning::UnofficialOpcode { pc, code });
+    }
+    self.program_counter = self.program_counter.wrapping_add(1);
+    let program_counter_state = self.program_counter;
+    self.always_fix_up = !opcode.page_cross_penalty;
+
+    trace!(
+      "{:04x} {:02x} {:<4} A:{:02x} X:{:02x} Y:{:02x} P:{:02x} SP:{:02x}",
+      pc,
+      code,
+      opcode.mnemonic,
+      self.register_a,
+      self.register_x,
+      self.register_y,
+      self.status.bits(),
+      self.stack_pointer
+    );
+    match code {
+      /* LDA */
+      0xa9 | 0xa5 | 0xb5 | 0xbd | 0xb9 | 0xa1 | 0xb1 | 0xad => {
+        self.lda(&opcode.mode);
+      }
+
+      /* TAX */ 0xAA => self.tax(),
+
+      /* INX */ 0xE8 => self.inx(),
+
+      /* BRK */
+      0x00 => {
+        if self.halt_on_brk {
+          self.program_counter = 0;
+          return Ok(None);
+        }
+        // skips the padding byte after the opcode
+        self.program_counter = self.program_counter.wrapping_add(1);
+        self.interrupt(interrupt::BRK);
+      }
+
+      /* CLD */ 0xd8 => self.status.remove(CpuFlags::DECIMAL_MODE),
+
+      /* CLI */ 0x58 => self.status.remove(CpuFlags::INTERRUPT_DISABLE),
+
+      /* CLV */ 0xb8 => self.status.remove(CpuFlags::OVERFLOW),
+
+      /* CLC */ 0x18 => self.clear_carry_flag(),
+
+      /* SEC */ 0x38 => self.set_carry_flag(),
+
+      /* SEI */ 0x78 => self.status.insert(CpuFlags::INTERRUPT_DISABLE),
+
+      /* SED */ 0xf8 => self.status.insert(CpuFlags::DECIMAL_MODE),
+
+      /* PHA */ 0x48 => self.stack_push(self.register_a),
+
+      /* PLA */
+      0x68 => {
+        self.pla();
+      }
+
+      /* PHP */
+      0x08 => {
+        self.php();
+      }
+
+      /* PLP */
+      0x28 => {
+        self.plp();
+      }
+
+      /* ADC */
+      0x69 | 0x65 | 0x75 | 0x6d | 0x7d | 0x79 | 0x61 | 0x71 => {
+        self.adc(&opcode.mode);
+      }
+
+      /* SBC */
+      0xe9 | 0xe5 | 0xf5 | 0xed | 0xfd | 0xf9 | 0xe1 | 0xf1 => {
+        self.sbc(&opcode.mode);
+      }
+
+      /* AND */
+      0x29 | 0x25 | 0x35 | 0x2d | 0x3d | 0x39 | 0x21 | 0x31 => {
+        self.and(&opcode.mode);
+      }
+
+      /* EOR */
+      0x49 | 0x45 | 0x55 | 0x4d | 0x5d | 0x59 | 0x41 | 0x51 => {
+        self.eor(&opcode.mode);
+      }
+
+      /* ORA */
+      0x09 | 0x05 | 0x15 | 0x0d | 0x1d | 0x19 | 0x01 | 0x11 => {
+        self.ora(&opcode.mode);
+      }
+
+      /* LSR */ 0x4a => self.lsr_accumulator(),
+
+      /* LSR */
+      0x46 | 0x56 | 0x4e | 0x5e => {
+        self.lsr(&opcode.mode);
+      }
+
+      /*ASL*/ 0x0a => self.asl_accumulator(),
+
+      /* ASL */
+      0x06 | 0x16 | 0x0e | 0x1e => {
+        self.asl(&opcode.mode);
+      }
+      /* ROL */ 0x2a => self.rol_accumulator(),
+
+      /* ROL */
+      0x26 | 0x36 | 0x2e | 0x3e => {
+        self.rol(&opcode.mode);
+      }
+
+      /* ROR */ 0x6a => self.ror_accumulator(),
+
+      /* ROR */
+      0x66 | 0x76 | 0x6e | 0x7e => {
+        self.ror(&opcode.mode);
+      }
+
+      /* INC */
+      0xe6 | 0xf6 | 0xee | 0xfe => {
+        self.inc(&opcode.mode);
+      }
+
+      /* INY */
+      0xc8 => self.iny(),
+
+      /* DEC */
+      0xc6 | 0xd6 | 0xce | 0xde => {
+        self.dec(&opcode.mode);
+      }
+
+      /* DEX */
+      0xca => {
+        self.dex();
+      }
+
+      /* DEY */
+      0x88 => {
+        self.dey();
+      }
+
+      /* CMP */
+      0xc9 | 0xc5 | 0xd5 | 0xcd | 0xdd | 0xd9 | 0xc1 | 0xd1 => {
+        self.compare(&opcode.mode, self.register_a);
+      }
+
+      /* CPY */
+      0xc0 | 0xc4 | 0xcc => {
+        self.compare(&opcode.mode, self.register_y);
+      }
+
+      /* CPX */
+      0xe0 | 0xe4 | 0xec => self.compare(&opcode.mode, self.register_x),
+
+      /* JMP Absolute */
+      0x4c => {
+        let mem_address = self.mem_read_u16(self.program_counter);
+        self.program_counter = mem_address;
+      }
+
+      /* JMP Indirect */
+      0x6c => {
+        let mem_address = self.mem_read_u16(self.program_counter);
+        // let indirect_ref = self.mem_read_u16(mem_address);
+        //6502 bug mode with with page boundary:
+        //  if address $3000 contains $40, $30FF contains $80, and $3100 contains $50,
+        // the result of JMP ($30FF) will be a transfer of control to $4080 rather than $5080 as you intended
+        // i.e. the 6502 took the low byte of the address from $30FF and the high byte from $3000
+
+        let indirect_ref = if mem_address & 0x00FF == 0x00FF {
+          let lo = self.mem_read(mem_address);
+          let hi = self.mem_read(mem_address & 0xFF00);
+          (hi as u16) << 8 | (lo as u16)
+        } else {
+          self.mem_read_u16(mem_address)
+        };
+
+        self.program_counter = indirect_ref;
+      }
+
+      /* JSR */
+      0x20 => {
+        self.stack_push_u16(self.program_counter.wrapping_add(1));
+        let target_address = self.mem_read_u16(self.program_counter);
+        self.program_counter = target_address
+      }
+
+      /* RTS */
+      0x60 => {
+        self.program_counter = self.stack_pop_u16().wrapping_add(1);
+      }
+
+      /* RTI */
+      0x40 => {
+        self.status.bits = self.stack_pop();
+        self.status.remove(CpuFlags::BREAK);
+        self.status.insert(CpuFlags::BREAK2);
+
+        self.program_counter = self.stack_pop_u16();
+      }
+
+      /* BNE */
+      0xd0 => {
+        self.branch(!self.status.contains(CpuFlags::ZERO));
+      }
+
+      /* BVS */
+      0x70 => {
+        self.branch(self.status.contains(CpuFlags::OVERFLOW));
+      }
+
+      /* BVC */
+      0x50 => {
+        self.branch(!self.status.contains(CpuFlags::OVERFLOW));
+      }
+
+      /* BPL */
+      0x10 => {
+        self.branch(!self.status.contains(CpuFlags::NEGATIVE));
+      }
+
+      /* BMI */
+      0x30 => {
+        self.branch(self.status.contains(CpuFlags::NEGATIVE));
+      }
+
+      /* BEQ */
+      0xf0 => {
+        self.branch(self.status.contains(CpuFlags::ZERO));
+      }
+
+      /* BCS */
+      0xb0 => {
+        self.branch(self.status.contains(CpuFlags::CARRY));
+      }
+
+      /* BCC */
+      0x90 => {
+        self.branch(!self.status.contains(CpuFlags::CARRY));
+      }
+
+      /* BIT */
+      0x24 | 0x2c => {
+        self.bit(&opcode.mode);
+      }
+
+      /* STA */
+      0x85 | 0x95 | 0x8d | 0x9d | 0x99 | 0x81 | 0x91 => {
+        self.sta(&opcode.mode);
+      }
+
+      /* STX */
+      0x86 | 0x96 | 0x8e => {
+        let addr = self.get_operand_address(&opcode.mode);
+        self.mem_write(addr, self.register_x);
+      }
+
+      /* STY */
+      0x84 | 0x94 | 0x8c => {
+        let addr = self.get_operand_address(&opcode.mode);
+        self.mem_write(addr, self.register_y);
+      }
+
+      /* LDX */
+      0xa2 | 0xa6 | 0xb6 | 0xae | 0xbe => {
+        self.ldx(&opcode.mode);
+      }
+
+      /* LDY */
+      0xa0 | 0xa4 | 0xb4 | 0xac | 0xbc => {
+        self.ldy(&opcode.mode);
+      }
+
+      /* NOP */
+      0xea => {
+        //do nothing
+      }
+
+      /* TAY */
+      0xa8 => {
+        self.register_y = self.register_a;
+        self.update_zero_and_negative_flags(self.register_y);
+      }
+
+      /* TSX */
+      0xba => {
+        self.register_x = self.stack_pointer;
+        self.update_zero_and_negative_flags(self.register_x);
+      }
+
+      /* TXA */
+      0x8a => {
+        self.register_a = self.register_x;
+        self.update_zero_and_negative_flags(self.register_a);
+      }
+
+      /* TXS */
+      0x9a => {
+        self.stack_pointer = self.register_x;
+      }
+
+      /* TYA */
+      0x98 => {
+        self.register_a = self.register_y;
+        self.update_zero_and_negative_flags(self.register_a);
+      }
+
+      /* unofficial NOPs */
+      0x1a | 0x3a | 0x5a | 0x7a | 0xda | 0xfa | 0x80 | 0x82 | 0x89 | 0xc2 | 0xe2 => {
+        //do nothing
+      }
+
+      /* unofficial NOPs that read their operand, side effects and all */
+      0x04 | 0x44 | 0x64 | 0x14 | 0x34 | 0x54 | 0x74 | 0xd4 | 0xf4 | 0x0c | 0x1c | 0x3c | 0x5c
+      | 0x7c | 0xdc | 0xfc => {
+        let addr = self.get_operand_address(&opcode.mode);
+        self.mem_read(addr);
+      }
+
+      /* LAX */
+      0xa7 | 0xb7 | 0xaf | 0xbf | 0xa3 | 0xb3 => {
+        self.lda(&opcode.mode);
+        self.register_x = self.register_a;
+      }
+
+      /* SAX */
+      0x87 | 0x97 | 0x8f | 0x83 => {
+        let addr = self.get_operand_address(&opcode.mode);
+        self.mem_write(addr, self.register_a & self.register_x);
+      }
+
+      /* SBC (unofficial) */
+      0xeb => {
+        self.sbc(&opcode.mode);
+      }
+
+      /* DCP */
+      0xc7 | 0xd7 | 0xcf | 0xdf | 0xdb | 0xc3 | 0xd3 => {
+        let data = self.dec(&opcode.mode);
+        self.compare_value(data, self.register_a);
+      }
+
+      /* ISB */
+      0xe7 | 0xf7 | 0xef | 0xff | 0xfb | 0xe3 | 0xf3 => {
+        let data = self.inc(&opcode.mode);
+        self.sub_from_register_a(data);
+      }
+
+      /* SLO */
+      0x07 | 0x17 | 0x0f | 0x1f | 0x1b | 0x03 | 0x13 => {
+        let data = self.asl(&opcode.mode);
+        self.set_register_a(data | self.register_a);
+      }
+
+      /* RLA */
+      0x27 | 0x37 | 0x2f | 0x3f | 0x3b | 0x23 | 0x33 => {
+        let data = self.rol(&opcode.mode);
+        self.set_register_a(data & self.register_a);
+      }
+
+      /* SRE */
+      0x47 | 0x57 | 0x4f | 0x5f | 0x5b | 0x43 | 0x53 => {
+        let data = self.lsr(&opcode.mode);
+        self.set_register_a(data ^ self.register_a);
+      }
+
+      /* RRA */
+      0x67 | 0x77 | 0x6f | 0x7f | 0x7b | 0x63 | 0x73 => {
+        let data = self.ror(&opcode.mode);
+        self.adc_value(data);
+      }
+
+      /* ANC */
+      0x0b | 0x2b => {
+        self.and(&opcode.mode);
+        let negative = self.status.contains(CpuFlags::NEGATIVE);
+        self.status.set(CpuFlags::CARRY, negative);
+      }
+
+      /* ALR */
+      0x4b => {
+        self.and(&opcode.mode);
+        self.lsr_accumulator();
+      }
+
+      /* ARR */
+      0x6b => {
+        self.and(&opcode.mode);
+        self.ror_accumulator();
+        let bit_6 = self.register_a & 0b0100_0000 != 0;
+        let bit_5 = self.register_a & 0b0010_0000 != 0;
+        self.status.set(CpuFlags::CARRY, bit_6);
+        self.status.set(CpuFlags::OVERFLOW, bit_6 ^ bit_5);
+      }
+
+      /* AXS */
+      0xcb => {
+        let addr = self.get_operand_address(&opcode.mode);
+        let data = self.mem_read(addr);
+        let x_and_a = self.register_x & self.register_a;
+        self.status.set(CpuFlags::CARRY, data <= x_and_a);
+        self.register_x = x_and_a.wrapping_sub(data);
+        self.update_zero_and_negative_flags(self.register_x);
+      }
+      _ => return Err(self.halt(Fault::Unimplemented { pc, code })),
+    }
+    if self.fault == Some(Fault::Unimplemented { pc, code }) {
+      return Err(Fault::Unimplemented { pc, code }.into());
+    }
+
+    if program_counter_state == self.program_counter {
+      self.program_counter = self.program_counter.wrapping_add((opcode.len - 1) as u16);
+    }
+    let mut cycles = opcode.cycles + self.extra_cycles;
+    if self.page_crossed && opcode.page_cross_penalty {
+      cycles += 1;
+    }
+    Ok(Some(self.tick(cycles)))
+  }
+
+  /// Let the bus run for `cycles`, returning them plus any it stalled the
+  /// CPU for.
+  fn tick(&mut self, cycles: u8) -> u16 {
+    self.bus.tick(cycles);
+    let cycles = cycles as u16 + self.bus.take_stall_cycles();
+    self.cycles += cycles as u64;
+    cycles
+  }
+
+  fn ldy(&mut self, mode: &AddressingMode) {
+    let addr = self.get_operand_address(mode);
+    let data = self.mem_read(addr);
+    self.register_y = data;
+    self.update_zero_and_negative_flags(self.register_y);
+  }
+
+  fn ldx(&mut self, mode: &AddressingMode) {
+    let addr = self.get_operand_address(mode);
+    let data = self.mem_read(addr);
+    self.register_x = data;
+    self.update_zero_and_negative_flags(self.register_x);
+  }
+
+  fn lda(&mut self, mode: &AddressingMode) {
+    let addr = self.get_operand_address(mode);
+    let value = self.mem_read(addr);
+    self.set_register_a(value);
+  }
+
+  fn sta(&mut self, mode: &AddressingMode) {
+    let addr = self.get_operand_address(mode);
+    self.mem_write(addr, self.register_a);
+  }
+
+  fn set_register_a(&mut self, value: u8) {
+    self.register_a = value;
+    self.update_zero_and_negative_flags(self.register_a);
+  }
+
+  fn and(&mut self, mode: &AddressingMode) {
+    let addr = self.get_operand_address(mode);
+    let data = self.mem_read(addr);
+    self.set_register_a(data & self.register_a);
+  }
+
+  fn eor(&mut self, mode: &AddressingMode) {
+    let addr = self.get_operand_address(mode);
+    let data = self.mem_read(addr);
+    self.set_register_a(data ^ self.register_a);
+  }
+
+  fn ora(&mut self, mode: &AddressingMode) {
+    let addr = self.get_operand_address(mode);
+    let data = self.mem_read(addr);
+    self.set_register_a(data | self.register_a);
+  }
+
+  fn tax(&mut self) {
+    self.register_x = self.register_a;
+    self.update_zero_and_negative_flags(self.register_x);
+  }
+
+  fn update_zero_and_negative_flags(&mut self, result: u8) {
+    if result == 0 {
+      self.status.insert(CpuFlags::ZERO);
+    } else {
+      self.status.remove(CpuFlags::ZERO);
+    }
+
+    if result & 0b1000_0000 != 0 {
+      self.status.insert(CpuFlags::NEGATIVE);
+    } else {
+      self.status.remove(CpuFlags::NEGATIVE);
+    }
+  }
+
+  fn inx(&mut self) {
+    self.register_x = self.register_x.wrapping_add(1);
+    self.update_zero_and_negative_flags(self.register_x);
+  }
+
+  fn iny(&mut self) {
+    self.register_y = self.register_y.wrapping_add(1);
+    self.update_zero_and_negative_flags(self.register_y);
+  }
+
+  fn set_carry_flag(&mut self) {
+    self.status.insert(CpuFlags::CARRY)
+  }
+
+  fn clear_carry_flag(&mut self) {
+    self.status.remove(CpuFlags::CARRY)
+  }
+
+  /// Binary ADC. The D flag is deliberately ignored, as on the 2A03.
+  /// http://www.righto.com/2012/12/the-6502-overflow-flag-explained.html
+  fn add_to_register_a(&mut self, data: u8) {
+    let sum = self.register_a as u16
+      + data as u16
+      + (if self.status.contains(CpuFlags::CARRY) {
+        1
+      } else {
+        0
+      }) as u16;
+
+    let carry = sum > 0xff;
+
+    if carry {
+      self.status.insert(CpuFlags::CARRY);
+    } else {
+      self.status.remove(CpuFlags::CARRY);
+    }
+
+    let result = sum as u8;
+
+    if (data ^ result) & (result ^ self.register_a) & 0x80 != 0 {
+      self.status.insert(CpuFlags::OVERFLOW);
+    } else {
+      self.status.remove(CpuFlags::OVERFLOW)
+    }
+
+    self.set_register_a(result);
+  }
+
+  fn decimal_active(&self) -> bool {
+    self.decimal_enabled && self.status.contains(CpuFlags::DECIMAL_MODE)
+  }
+
+  fn adc_value(&mut self, data: u8) {
+    if self.decimal_active() {
+      self.decimal_add(data);
+    } else {
+      self.add_to_register_a(data);
+    }
+  }
+
+  fn sub_from_register_a(&mut self, data: u8) {
+    let a = self.register_a;
+    let carry = self.status.contains(CpuFlags::CARRY) as i16;
+    // flags are the binary ones even in decimal mode on an NMOS 6502
+    self.add_to_register_a(((data as i8).wrapping_neg().wrapping_sub(1)) as u8);
+
+    if self.decimal_active() {
+      // http://www.6502.org/tutorials/decimal_mode.html#A
+      let mut lo = (a & 0x0f) as i16 - (data & 0x0f) as i16 + carry - 1;
+      if lo < 0 {
+        lo = ((lo - 0x06) & 0x0f) - 0x10;
+      }
+      let mut result = (a & 0xf0) as i16 - (data & 0xf0) as i16 + lo;
+      if result < 0 {
+        result -= 0x60;
+      }
+      self.register_a = result as u8;
+    }
+  }
+
+  /// NMOS 6502 BCD addition, only reachable in pure 6502 mode. Z comes from
+  /// the binary sum, N and V from the sum before the high nibble is
+  /// adjusted, http://www.6502.org/tutorials/decimal_mode.html#A
+  fn decimal_add(&mut self, data: u8) {
+    let a = self.register_a;
+    let carry = self.status.contains(CpuFlags::CARRY) as u16;
+
+    let mut lo = (a & 0x0f) as u16 + (data & 0x0f) as u16 + carry;
+    if lo >= 0x0a {
+      lo = ((lo + 0x06) & 0x0f) + 0x10;
+    }
+    let mut sum = (a & 0xf0) as u16 + (data & 0xf0) as u16 + lo;
+
+    let intermediate = sum as u8;
+    self
+      .status
+      .set(CpuFlags::NEGATIVE, intermediate & 0x80 != 0);
+    self.status.set(
+      CpuFlags::OVERFLOW,
+      (a ^ intermediate) & (data ^ intermediate) & 0x80 != 0,
+    );
+    let binary = a.wrapping_add(data).wrapping_add(carry as u8);
+    self.status.set(CpuFlags::ZERO, binary == 0);
+
+    if sum >= 0xa0 {
+      sum += 0x60;
+    }
+    self.status.set(CpuFlags::CARRY, sum >= 0x100);
+    self.register_a = sum as u8;
+  }
+
+  fn sbc(&mut self, mode: &AddressingMode) {
+    let addr = self.get_operand_address(mode);
+    let data = self.mem_read(addr);
+    self.sub_from_register_a(data);
+  }
+
+  fn adc(&mut self, mode: &AddressingMode) {
+    let addr = self.get_operand_address(mode);
+    let value = self.mem_read(addr);
+    self.adc_value(value);
+  }
+
+  fn interrupt(&mut self, interrupt: interrupt::Interrupt) -> u16 {
+    self.stack_push_u16(self.program_counter);
+    let mut flags = self.status;
+    flags.set(CpuFlags::BREAK, interrupt.b_flag);
+    flags.insert(CpuFlags::BREAK2);
+    self.stack_push(flags.bits());
+    self.status.insert(CpuFlags::INTERRUPT_DISABLE);
+
+    let cycles = self.tick(interrupt.cpu_cycles);
+    self.program_counter = self.mem_read_u16(interrupt.vector_addr);
+    cycles
+  }
+
+  fn stack_pop(&mut self) -> u8 {
+    self.stack_pointer = self.stack_pointer.wrapping_add(1);
+    self.mem_read(STACK + self.stack_pointer as u16)
+  }
+
+  fn stack_push(&mut self, data: u8) {
+    self.mem_write(STACK + self.stack_pointer as u16, data);
+    self.stack_pointer = self.stack_pointer.wrapping_sub(1)
+  }
+
+  fn stack_push_u16(&mut self, data: u16) {
+    let hi = (data >> 8) as u8;
+    let lo = (data & 0xff) as u8;
+    self.stack_push(hi);
+    self.stack_push(lo);
+  }
+
+  fn stack_pop_u16(&mut self) -> u16 {
+    let lo = self.stack_pop() as u16;
+    let hi = self.stack_pop() as u16;
+
+    hi << 8 | lo
+  }
+
+  fn asl_accumulator(&mut self) {
+    let mut data = self.register_a;
+    if data >> 7 == 1 {
+      self.set_carry_flag();
+    } else {
+      self.clear_carry_flag();
+    }
+    data <<= 1;
+    self.set_register_a(data)
+  }
+
+  fn asl(&mut self, mode: &AddressingMode) -> u8 {
+    let addr = self.get_operand_address(mode);
+    let old = self.mem_read(addr);
+    let mut data = old;
+    if data >> 7 == 1 {
+      self.set_carry_flag();
+    } else {
+      self.clear_carry_flag();
+    }
+    data <<= 1;
+    self.modify(addr, old, data);
+    self.update_zero_and_negative_flags(data);
+    data
+  }
+
+  fn lsr_accumulator(&mut self) {
+    let mut data = self.register_a;
+    if data & 1 == 1 {
+      self.set_carry_flag();
+    } else {
+      self.clear_carry_flag();
+    }
+    data >>= 1;
+    self.set_register_a(data)
+  }
+
+  fn lsr(&mut self, mode: &AddressingMode) -> u8 {
+    let addr = self.get_operand_address(mode);
+    let old = self.mem_read(addr);
+    let mut data = old;
+    if data & 1 == 1 {
+      self.set_carry_flag();
+    } else {
+      self.clear_carry_flag();
+    }
+    data >>= 1;
+    self.modify(addr, old, data);
+    self.update_zero_and_negative_flags(data);
+    data
+  }
+
+  fn rol(&mut self, mode: &AddressingMode) -> u8 {
+    let addr = self.get_operand_address(mode);
+    let old = self.mem_read(addr);
+    let mut data = old;
+    let old_carry = self.status.contains(CpuFlags::CARRY);
+
+    if data >> 7 == 1 {
+      self.set_carry_flag();
+    } else {
+      self.clear_carry_flag();
+    }
+    data <<= 1;
+    if old_carry {
+      data |= 1;
+    }
+    self.modify(addr, old, data);
+    self.update_zero_and_negative_flags(data);
+    data
+  }
+
+  fn rol_accumulator(&mut self) {
+    let mut data = self.register_a;
+    let old_carry = self.status.contains(CpuFlags::CARRY);
+
+    if data >> 7 == 1 {
+      self.set_carry_flag();
+    } else {
+      self.clear_carry_flag();
+    }
+    data <<= 1;
+    if old_carry {
+      data |= 1;
+    }
+    self.set_register_a(data);
+  }
+
+  fn ror(&mut self, mode: &AddressingMode) -> u8 {
+    let addr = self.get_operand_address(mode);
+    let old = self.mem_read(addr);
+    let mut data = old;
+    let old_carry = self.status.contains(CpuFlags::CARRY);
+
+    if data & 1 == 1 {
+      self.set_carry_flag();
+    } else {
+      self.clear_carry_flag();
+    }
+    data >>= 1;
+    if old_carry {
+      data |= 0b10000000;
+    }
+    self.modify(addr, old, data);
+    self.update_zero_and_negative_flags(data);
+    data
+  }
+
+  fn ror_accumulator(&mut self) {
+    let mut data = self.register_a;
+    let old_carry = self.status.contains(CpuFlags::CARRY);
+
+    if data & 1 == 1 {
+      self.set_carry_flag();
+    } else {
+      self.clear_carry_flag();
+    }
+    data >>= 1;
+    if old_carry {
+      data |= 0b10000000;
+    }
+    self.set_register_a(data);
+  }
+
+  fn inc(&mut self, mode: &AddressingMode) -> u8 {
+    let addr = self.get_operand_address(mode);
+    let old = self.mem_read(addr);
+    let mut data = old;
+    data = data.wrapping_add(1);
+    self.modify(addr, old, data);
+    self.update_zero_and_negative_flags(data);
+    data
+  }
+
+  fn dey(&mut self) {
+    self.register_y = self.register_y.wrapping_sub(1);
+    self.update_zero_and_negative_flags(self.register_y);
+  }
+
+  fn dex(&mut self) {
+    self.register_x = self.register_x.wrapping_sub(1);
+    self.update_zero_and_negative_flags(self.register_x);
+  }
+
+  fn dec(&mut self, mode: &AddressingMode) -> u8 {
+    let addr = self.get_operand_address(mode);
+    let old = self.mem_read(addr);
+    let mut data = old;
+    data = data.wrapping_sub(1);
+    self.modify(addr, old, data);
+    self.update_zero_and_negative_flags(data);
+    data
+  }
+
+  fn pla(&mut self) {
+    let data = self.stack_pop();
+    self.set_register_a(data);
+  }
+
+  fn plp(&mut self) {
+    self.status.bits = self.stack_pop();
+    self.status.remove(CpuFlags::BREAK);
+    self.status.insert(CpuFlags::BREAK2);
+  }
+
+  fn php(&mut self) {
+    //http://wiki.nesdev.com/w/index.php/CPU_status_flag_behavior
+    let mut flags = self.status;
+    flags.insert(CpuFlags::BREAK);
+    flags.insert(CpuFlags::BREAK2);
+    self.stack_push(flags.bits());
+  }
+
+  fn bit(&mut self, mode: &AddressingMode) {
+    let addr = self.get_operand_address(mode);
+    let data = self.mem_read(addr);
+    let and = self.register_a & data;
+    if and == 0 {
+      self.status.insert(CpuFlags::ZERO);
+    } else {
+      self.status.remove(CpuFlags::ZERO);
+    }
+
+    self.status.set(CpuFlags::NEGATIVE, data & 0b10000000 > 0);
+    self.status.set(CpuFlags::OVERFLOW, data & 0b01000000 > 0);
+  }
+
+  fn compare(&mut self, mode: &AddressingMode, compare_with: u8) {
+    let addr = self.get_operand_address(mode);
+    let data = self.mem_read(addr);
+    self.compare_value(data, compare_with);
+  }
+
+  fn compare_value(&mut self, data: u8, compare_with: u8) {
+    if data <= compare_with {
+      self.status.insert(CpuFlags::CARRY);
+    } else {
+      self.status.remove(CpuFlags::CARRY);
+    }
+
+    self.update_zero_and_negative_flags(compare_with.wrapping_sub(data));
+  }
+
+  // taken branches cost a cycle, plus one more into another page
+  fn branch(&mut self, condition: bool) {
+    if condition {
+      let jump: i8 = self.mem_read(self.program_counter) as i8;
+      let next = self.program_counter.wrapping_add(1);
+      let jump_addr = next.wrapping_add(jump as u16);
+
+      self.extra_cycles += 1;
+      if page_crossed(next, jump_addr) {
+        self.extra_cycles += 1;
+      }
+      self.program_counter = jump_addr;
+    }
+  }
+}
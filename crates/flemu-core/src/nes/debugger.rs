@@ -0,0 +1,468 @@
+use crate::nes::bus::{Bus, Mem};
+use crate::nes::cpu::{CpuState, CPU};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/*
+  Breakpoints and symbols by what's mapped, not by CPU address.
+
+  On bank switching boards $8000 is a different piece of code depending on
+  the bank selected, so "break at $8123" would fire in every bank. PRG ROM
+  locations are therefore (bank, offset in bank), resolved against the
+  mapper at the moment the debugger asks; everything below $8000 (RAM,
+  registers, PRG RAM) isn't banked and stays a plain address.
+
+  Watchpoints are plain CPU addresses for the same reason: what's read and
+  written below $8000 is RAM and registers, not banked code. The bus checks
+  every access against them and keeps the first hit for whoever drives the
+  CPU to pick up after the instruction.
+*/
+
+// JSR, the only instruction step_over steps over
+const JSR: u8 = 0x20;
+
+/// A place in the program that survives PRG bank switches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Location {
+  Cpu(u16),
+  Prg { bank: usize, offset: u16 },
+}
+
+impl Location {
+  /// What `addr` points at right now.
+  pub fn resolve(bus: &Bus, addr: u16) -> Location {
+    match bus.mapper().and_then(|mapper| mapper.prg_bank(addr)) {
+      Some(bank) => Location::Prg {
+        bank: bank.index,
+        offset: (addr as usize % bank.size) as u16,
+      },
+      None => Location::Cpu(addr),
+    }
+  }
+}
+
+impl fmt::Display for Location {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Location::Cpu(addr) => write!(f, "${:04X}", addr),
+      Location::Prg { bank, offset } => write!(f, "{:02X}:{:04X}", bank, offset),
+    }
+  }
+}
+
+/// A CPU register a breakpoint condition can test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+  A,
+  X,
+  Y,
+  Sp,
+  P,
+}
+
+impl Register {
+  pub const ALL: [Register; 5] = [
+    Register::A,
+    Register::X,
+    Register::Y,
+    Register::Sp,
+    Register::P,
+  ];
+
+  /// Stable id: "a", "x", "y", "sp", "p".
+  pub fn id(&self) -> &'static str {
+    match self {
+      Register::A => "a",
+      Register::X => "x",
+      Register::Y => "y",
+      Register::Sp => "sp",
+      Register::P => "p",
+    }
+  }
+
+  pub fn from_id(id: &str) -> Option<Register> {
+    Register::ALL
+      .iter()
+      .copied()
+      .find(|register| register.id() == id)
+  }
+
+  pub fn value(&self, state: &CpuState) -> u8 {
+    match self {
+      Register::A => state.a,
+      Register::X => state.x,
+      Register::Y => state.y,
+      Register::Sp => state.sp,
+      Register::P => state.flags,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+  Equal,
+  NotEqual,
+  Less,
+  LessOrEqual,
+  Greater,
+  GreaterOrEqual,
+}
+
+impl Comparison {
+  pub const ALL: [Comparison; 6] = [
+    Comparison::Equal,
+    Comparison::NotEqual,
+    Comparison::Less,
+    Comparison::LessOrEqual,
+    Comparison::Greater,
+    Comparison::GreaterOrEqual,
+  ];
+
+  /// Stable id, the operator: "==", "!=", "<", "<=", ">", ">=".
+  pub fn id(&self) -> &'static str {
+    match self {
+      Comparison::Equal => "==",
+      Comparison::NotEqual => "!=",
+      Comparison::Less => "<",
+      Comparison::LessOrEqual => "<=",
+      Comparison::Greater => ">",
+      Comparison::GreaterOrEqual => ">=",
+    }
+  }
+
+  pub fn from_id(id: &str) -> Option<Comparison> {
+    Comparison::ALL
+      .iter()
+      .copied()
+      .find(|comparison| comparison.id() == id)
+  }
+
+  pub fn holds(&self, left: u8, right: u8) -> bool {
+    match self {
+      Comparison::Equal => left == right,
+      Comparison::NotEqual => left != right,
+      Comparison::Less => left < right,
+      Comparison::LessOrEqual => left <= right,
+      Comparison::Greater => left > right,
+      Comparison::GreaterOrEqual => left >= right,
+    }
+  }
+}
+
+/// A register compared with a value, e.g. `x == 05`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+  pub register: Register,
+  pub comparison: Comparison,
+  pub value: u8,
+}
+
+impl Condition {
+  pub fn holds(&self, state: &CpuState) -> bool {
+    self
+      .comparison
+      .holds(self.register.value(state), self.value)
+  }
+}
+
+impl fmt::Display for Condition {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "{} {} {:02X}",
+      self.register.id(),
+      self.comparison.id(),
+      self.value
+    )
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint {
+  pub location: Location,
+  /// Only stop when this holds, None to always stop.
+  pub condition: Option<Condition>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Breakpoints {
+  breakpoints: Vec<Breakpoint>,
+}
+
+impl Breakpoints {
+  pub fn add(&mut self, location: Location) {
+    self.add_breakpoint(Breakpoint {
+      location,
+      condition: None,
+    });
+  }
+
+  /// Stop at `location` only when `condition` holds.
+  pub fn add_conditional(&mut self, location: Location, condition: Condition) {
+    self.add_breakpoint(Breakpoint {
+      location,
+      condition: Some(condition),
+    });
+  }
+
+  fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+    if !self.breakpoints.contains(&breakpoint) {
+      self.breakpoints.push(breakpoint);
+    }
+  }
+
+  /// Every breakpoint at `location`, conditional or not.
+  pub fn remove(&mut self, location: Location) {
+    self.breakpoints.retain(|b| b.location != location);
+  }
+
+  pub fn clear(&mut self) {
+    self.breakpoints.clear();
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.breakpoints.is_empty()
+  }
+
+  /// In the order they were added.
+  pub fn breakpoints(&self) -> &[Breakpoint] {
+    &self.breakpoints
+  }
+
+  /// Whether a breakpoint is set where `pc` is with the banks mapped now,
+  /// conditions aside.
+  pub fn hit(&self, bus: &Bus, pc: u16) -> bool {
+    !self.is_empty() && {
+      let location = Location::resolve(bus, pc);
+      self.breakpoints.iter().any(|b| b.location == location)
+    }
+  }
+
+  /// Whether `cpu` should stop before the instruction at its PC: a
+  /// breakpoint there whose condition, if any, holds.
+  pub fn stops(&self, cpu: &CPU) -> bool {
+    if self.is_empty() {
+      return false;
+    }
+    let location = Location::resolve(&cpu.bus, cpu.program_counter);
+    let state = cpu.state();
+    self.breakpoints.iter().any(|b| {
+      b.location == location
+        && b
+          .condition
+          .map_or(true, |condition| condition.holds(&state))
+    })
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+  Read,
+  Write,
+}
+
+impl Access {
+  /// Stable id: "read", "write".
+  pub fn id(&self) -> &'static str {
+    match self {
+      Access::Read => "read",
+      Access::Write => "write",
+    }
+  }
+}
+
+/// CPU addresses `start..=end` watched for reads, writes or both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+  pub start: u16,
+  pub end: u16,
+  pub read: bool,
+  pub write: bool,
+}
+
+impl Watchpoint {
+  fn watches(&self, addr: u16, access: Access) -> bool {
+    (self.start..=self.end).contains(&addr)
+      && match access {
+        Access::Read => self.read,
+        Access::Write => self.write,
+      }
+  }
+}
+
+/// An access a watchpoint caught.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+  pub addr: u16,
+  /// What was read, or written.
+  pub value: u8,
+  pub access: Access,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Watchpoints {
+  watchpoints: Vec<Watchpoint>,
+  hit: Option<WatchHit>,
+}
+
+impl Watchpoints {
+  pub fn add(&mut self, watchpoint: Watchpoint) {
+    if !self.watchpoints.contains(&watchpoint) {
+      self.watchpoints.push(watchpoint);
+    }
+  }
+
+  /// Every watchpoint on exactly `start..=end`.
+  pub fn remove(&mut self, start: u16, end: u16) {
+    self
+      .watchpoints
+      .retain(|w| (w.start, w.end) != (start, end));
+  }
+
+  pub fn clear(&mut self) {
+    self.watchpoints.clear();
+    self.hit = None;
+  }
+
+  /// In the order they were added.
+  pub fn watchpoints(&self) -> &[Watchpoint] {
+    &self.watchpoints
+  }
+
+  /// Called by the bus on every access. Only the first hit is kept until
+  /// it's taken.
+  pub fn check(&mut self, addr: u16, value: u8, access: Access) {
+    if self.hit.is_none() && self.watchpoints.iter().any(|w| w.watches(addr, access)) {
+      self.hit = Some(WatchHit {
+        addr,
+        value,
+        access,
+      });
+    }
+  }
+
+  pub fn take_hit(&mut self) -> Option<WatchHit> {
+    self.hit.take()
+  }
+}
+
+/// Why the debugger stopped running the CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+  /// Ran as far as asked: the end of the frame, the instruction stepped,
+  /// the address run to.
+  Done,
+  /// The CPU stopped, on a fault or, for bare programs, a BRK.
+  Halted,
+  /// At a breakpoint, before executing the instruction at `pc`.
+  Breakpoint { pc: u16 },
+  /// The last instruction accessed a watched address.
+  Watchpoint(WatchHit),
+  /// Gave up after the instruction limit without getting there.
+  Limit,
+}
+
+impl StopReason {
+  /// Stable id: "done", "halted", "breakpoint", "watchpoint", "limit".
+  pub fn id(&self) -> &'static str {
+    match self {
+      StopReason::Done => "done",
+      StopReason::Halted => "halted",
+      StopReason::Breakpoint { .. } => "breakpoint",
+      StopReason::Watchpoint(_) => "watchpoint",
+      StopReason::Limit => "limit",
+    }
+  }
+}
+
+/// Execute instructions through `step` until `done` says the CPU got
+/// where it was going, a breakpoint or watchpoint stops it, or
+/// `max_instructions` ran. `step` returns false when the CPU stopped.
+///
+/// Breakpoints are checked after each instruction, before the next one
+/// runs, so continuing from a breakpoint doesn't stop at it again right
+/// away. When the CPU gets where it was going onto a breakpoint, the
+/// breakpoint wins.
+pub fn run_until<S, D>(
+  cpu: &mut CPU,
+  breakpoints: &Breakpoints,
+  max_instructions: u32,
+  mut step: S,
+  mut done: D,
+) -> StopReason
+where
+  S: FnMut(&mut CPU) -> bool,
+  D: FnMut(&CPU) -> bool,
+{
+  // left from accesses outside the debugger
+  cpu.bus.watchpoints.take_hit();
+  for _ in 0..max_instructions {
+    if !step(cpu) {
+      return StopReason::Halted;
+    }
+    if let Some(hit) = cpu.bus.watchpoints.take_hit() {
+      return StopReason::Watchpoint(hit);
+    }
+    if breakpoints.stops(cpu) {
+      return StopReason::Breakpoint {
+        pc: cpu.program_counter,
+      };
+    }
+    if done(cpu) {
+      return StopReason::Done;
+    }
+  }
+  StopReason::Limit
+}
+
+/// Execute one instruction like `run_until`, or for a JSR the whole
+/// subroutine: run until the instruction after it with the stack back as
+/// it was, so recursion doesn't stop early.
+pub fn step_over<S>(
+  cpu: &mut CPU,
+  breakpoints: &Breakpoints,
+  max_instructions: u32,
+  step: S,
+) -> StopReason
+where
+  S: FnMut(&mut CPU) -> bool,
+{
+  let pc = cpu.program_counter;
+  if cpu.mem_peek(pc) != JSR {
+    return run_until(cpu, breakpoints, 1, step, |_| true);
+  }
+  let next = pc.wrapping_add(3);
+  let sp = cpu.stack_pointer;
+  run_until(cpu, breakpoints, max_instructions, step, |cpu| {
+    cpu.program_counter == next && cpu.stack_pointer == sp
+  })
+}
+
+/// Names for locations, e.g. from an assembler's label file.
+#[derive(Debug, Clone, Default)]
+pub struct Symbols {
+  names: BTreeMap<Location, String>,
+}
+
+impl Symbols {
+  pub fn add(&mut self, location: Location, name: &str) {
+    self.names.insert(location, name.to_string());
+  }
+
+  pub fn remove(&mut self, location: Location) {
+    self.names.remove(&location);
+  }
+
+  pub fn get(&self, location: Location) -> Option<&str> {
+    self.names.get(&location).map(String::as_str)
+  }
+
+  /// The name of whatever `addr` maps to now.
+  pub fn lookup(&self, bus: &Bus, addr: u16) -> Option<&str> {
+    self.get(Location::resolve(bus, addr))
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (&Location, &String)> {
+    self.names.iter()
+  }
+}
@@ -0,0 +1,272 @@
+use crate::nes::bus::Mem;
+use crate::nes::cpu::CPU;
+use crate::nes::regression::{fnv1a, FNV_OFFSET};
+use crate::nes::savestate::{StateError, StateReader, StateWriter};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Write;
+
+/*
+  Finding where two runs that should be the same drifted apart: netplay
+  peers, a movie and its playback, a CI run and the one it was recorded
+  against.
+
+  `state_hash` is a 64-bit FNV-1a of the machine as a savestate has it,
+  so unlike `regression::state_hash`, which looks at what a frame shows,
+  it covers everything the console goes on from: the APU, the
+  controllers' shift registers and the board too. Two runs with the same
+  hash at the start of a frame carry on the same way with the same input.
+
+  A `ChecksumTrace` keeps that hash at the start of every frame. Comparing
+  the traces of two runs gives the first frame they disagree on, and
+  `diff` what differs in savestates taken there. Traces are text, one
+  frame per line:
+
+    <frame>\t<hash as 16 hex digits>
+*/
+
+/// Hash of the machine as a savestate keeps it.
+pub fn state_hash(cpu: &CPU) -> u64 {
+  let mut w = StateWriter::new(0);
+  cpu.save_state(&mut w);
+  fnv1a(FNV_OFFSET, &w.finish())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseTraceError {
+  /// 1-based line of the trace.
+  pub line: usize,
+}
+
+impl fmt::Display for ParseTraceError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "malformed checksum trace line {}", self.line)
+  }
+}
+
+impl std::error::Error for ParseTraceError {}
+
+/// `state_hash` at the start of each frame, by the PPU's `frame_count`.
+/// It grows by a frame every frame until cleared.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChecksumTrace {
+  hashes: BTreeMap<u64, u64>,
+}
+
+impl ChecksumTrace {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Hash `cpu` for the frame it's at. A frame run again, after a
+  /// rollback or rewind, keeps the hash of the last time.
+  pub fn record(&mut self, cpu: &CPU) {
+    self.insert(cpu.bus.ppu.frame_count, state_hash(cpu));
+  }
+
+  pub fn insert(&mut self, frame: u64, hash: u64) {
+    self.hashes.insert(frame, hash);
+  }
+
+  pub fn get(&self, frame: u64) -> Option<u64> {
+    self.hashes.get(&frame).copied()
+  }
+
+  pub fn len(&self) -> usize {
+    self.hashes.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.hashes.is_empty()
+  }
+
+  pub fn clear(&mut self) {
+    self.hashes.clear();
+  }
+
+  /// (frame, hash), oldest first.
+  pub fn iter(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+    self.hashes.iter().map(|(&frame, &hash)| (frame, hash))
+  }
+
+  /// The first frame both traces have with different hashes. Frames only
+  /// one of them has don't count, so runs over different stretches can
+  /// be compared where they overlap.
+  pub fn first_divergence(&self, other: &ChecksumTrace) -> Option<u64> {
+    self
+      .iter()
+      .find(|&(frame, hash)| other.get(frame).map_or(false, |theirs| theirs != hash))
+      .map(|(frame, _)| frame)
+  }
+
+  pub fn to_text(&self) -> String {
+    let mut out = String::new();
+    for (frame, hash) in self.iter() {
+      // writing into a String can't fail
+      writeln!(out, "{}\t{:016x}", frame, hash).unwrap();
+    }
+    out
+  }
+
+  /// Parse `to_text` output.
+  pub fn from_text(text: &str) -> Result<ChecksumTrace, ParseTraceError> {
+    let mut trace = ChecksumTrace::new();
+    for (i, line) in text.lines().enumerate() {
+      if line.is_empty() {
+        continue;
+      }
+      let (frame, hash) = line
+        .split_once('\t')
+        .and_then(|(frame, hash)| Some((frame.parse().ok()?, u64::from_str_radix(hash, 16).ok()?)))
+        .ok_or(ParseTraceError { line: i + 1 })?;
+      trace.insert(frame, hash);
+    }
+    Ok(trace)
+  }
+}
+
+/// A part of the console `diff` tells apart.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Subsystem {
+  /// Registers, cycle count and fault.
+  Cpu,
+  /// The 2KB of internal RAM.
+  Ram,
+  /// Registers, the beam position and the picture drawn so far.
+  Ppu,
+  /// The 2KB of nametable RAM.
+  Vram,
+  Palette,
+  Oam,
+  Apu,
+  /// The shift registers of the pads and the Four Score.
+  Controllers,
+  /// The board's RAM and registers.
+  Mapper,
+  /// DMA, stalls, open bus.
+  Bus,
+  /// The state of `CPU::rng`.
+  Rng,
+}
+
+impl Subsystem {
+  pub const ALL: [Subsystem; 11] = [
+    Subsystem::Cpu,
+    Subsystem::Ram,
+    Subsystem::Ppu,
+    Subsystem::Vram,
+    Subsystem::Palette,
+    Subsystem::Oam,
+    Subsystem::Apu,
+    Subsystem::Controllers,
+    Subsystem::Mapper,
+    Subsystem::Bus,
+    Subsystem::Rng,
+  ];
+
+  pub fn id(&self) -> &'static str {
+    match self {
+      Subsystem::Cpu => "cpu",
+      Subsystem::Ram => "ram",
+      Subsystem::Ppu => "ppu",
+      Subsystem::Vram => "vram",
+      Subsystem::Palette => "palette",
+      Subsystem::Oam => "oam",
+      Subsystem::Apu => "apu",
+      Subsystem::Controllers => "controllers",
+      Subsystem::Mapper => "mapper",
+      Subsystem::Bus => "bus",
+      Subsystem::Rng => "rng",
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      Subsystem::Cpu => "CPU",
+      Subsystem::Ram => "RAM",
+      Subsystem::Ppu => "PPU",
+      Subsystem::Vram => "PPU VRAM",
+      Subsystem::Palette => "Palette RAM",
+      Subsystem::Oam => "OAM",
+      Subsystem::Apu => "APU",
+      Subsystem::Controllers => "Controllers",
+      Subsystem::Mapper => "Mapper",
+      Subsystem::Bus => "Bus",
+      Subsystem::Rng => "Random source",
+    }
+  }
+
+  // its bytes: the memory itself for RAM, VRAM, palette and OAM, the
+  // savestate fields for the rest
+  fn bytes(&self, cpu: &CPU) -> Vec<u8> {
+    let (bus, ppu) = (&cpu.bus, &cpu.bus.ppu);
+    let mut w = StateWriter::default();
+    match self {
+      Subsystem::Cpu => cpu.save_registers(&mut w),
+      Subsystem::Ram => return (0..0x0800).map(|addr| bus.mem_peek(addr)).collect(),
+      Subsystem::Ppu => {
+        w.u8(ppu.oam_addr);
+        ppu.save_registers(&mut w);
+      }
+      Subsystem::Vram => return ppu.vram.to_vec(),
+      Subsystem::Palette => return ppu.palette_table.to_vec(),
+      Subsystem::Oam => return ppu.oam_data.to_vec(),
+      Subsystem::Apu => bus.apu.save_state(&mut w),
+      Subsystem::Controllers => bus.save_controllers(&mut w),
+      Subsystem::Mapper => {
+        if let Some(mapper) = bus.mapper() {
+          mapper.save_state(&mut w);
+        }
+      }
+      Subsystem::Bus => bus.save_timing(&mut w),
+      Subsystem::Rng => w.u64(cpu.rng.state()),
+    }
+    w.finish()
+  }
+}
+
+/// How one subsystem differs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Difference {
+  pub subsystem: Subsystem,
+  /// The first byte that differs: the address in RAM, VRAM, palette RAM
+  /// and OAM, an offset into its savestate fields for the rest.
+  pub first: usize,
+  /// How many bytes differ.
+  pub count: usize,
+}
+
+/// What differs between two machines, in `Subsystem::ALL` order; empty
+/// when they're the same.
+pub fn diff(a: &CPU, b: &CPU) -> Vec<Difference> {
+  Subsystem::ALL
+    .iter()
+    .filter_map(|&subsystem| {
+      let (a, b) = (subsystem.bytes(a), subsystem.bytes(b));
+      // bytes only one of them has differ too
+      let common = a.len().min(b.len());
+      let mut differing = (0..common)
+        .filter(|&i| a[i] != b[i])
+        .chain(common..a.len().max(b.len()));
+      let first = differing.next()?;
+      Some(Difference {
+        subsystem,
+        first,
+        count: 1 + differing.count(),
+      })
+    })
+    .collect()
+}
+
+/// `diff` of two savestates of `cpu`'s cartridge, loaded into copies of
+/// it.
+pub fn diff_states(cpu: &CPU, a: &[u8], b: &[u8]) -> Result<Vec<Difference>, StateError> {
+  let load = |state: &[u8]| -> Result<CPU, StateError> {
+    let mut copy = cpu.clone();
+    let mut r = StateReader::new(state, cpu.bus.cartridge_checksum())?;
+    copy.load_state(&mut r)?;
+    r.finish()?;
+    Ok(copy)
+  };
+  Ok(diff(&load(a)?, &load(b)?))
+}
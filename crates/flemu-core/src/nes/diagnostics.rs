@@ -0,0 +1,339 @@
+use crate::nes::bus::Mem;
+use crate::nes::cartridge::{Rom, RomError};
+use crate::nes::cpu::{AddressingMode, CpuState, CPU};
+use crate::nes::opcodes;
+use std::fmt;
+use std::fmt::Write;
+
+// nestest's automation mode entry point, where its log starts
+const NESTEST_START: u16 = 0xC000;
+// the log starts after the 7 cycles of the reset sequence
+const NESTEST_START_CYCLES: u8 = 7;
+
+/// How many executed instructions a core dump can show.
+pub const TRACE_LINES: usize = 64;
+
+/// One executed instruction, as seen right before it ran.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TraceEntry {
+  pub code: u8,
+  pub state: CpuState,
+}
+
+impl TraceEntry {
+  /// nestest-like log line, e.g. `C000  4C JMP  A:00 X:00 Y:00 P:24 SP:FD CYC:7`
+  pub fn line(&self) -> String {
+    let mnemonic = opcodes::lookup(self.code).map_or("???", |opcode| opcode.mnemonic);
+    format!(
+      "{:04X}  {:02X} {:<4} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+      self.state.pc,
+      self.code,
+      mnemonic,
+      self.state.a,
+      self.state.x,
+      self.state.y,
+      self.state.flags,
+      self.state.sp,
+      self.state.cycles
+    )
+  }
+}
+
+/// The instruction at PC about to run, formatted exactly like a line of
+/// the canonical nestest.log:
+///
+/// `C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7`
+///
+/// Operands are annotated with the address they resolve to and the value
+/// there, all read with `mem_peek` so formatting has no side effects.
+pub fn nestest_line<B: Mem>(cpu: &CPU<B>) -> String {
+  let pc = cpu.program_counter;
+  let code = cpu.mem_peek(pc);
+  let (mnemonic, len, operand) = match opcodes::lookup(code) {
+    Some(opcode) => (opcode.mnemonic, opcode.len, nestest_operand(cpu, opcode)),
+    None => ("???", 1, String::new()),
+  };
+  let bytes: Vec<String> = (0..len as u16)
+    .map(|i| format!("{:02X}", cpu.mem_peek(pc.wrapping_add(i))))
+    .collect();
+  let asm = format!(
+    "{:04X}  {:<8} {:>4} {}",
+    pc,
+    bytes.join(" "),
+    mnemonic,
+    operand
+  );
+  let (scanline, dot) = cpu.bus.ppu_position();
+  format!(
+    "{:<47} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+    asm.trim_end(),
+    cpu.register_a,
+    cpu.register_x,
+    cpu.register_y,
+    cpu.status.bits(),
+    cpu.stack_pointer,
+    scanline,
+    dot,
+    cpu.cycles
+  )
+}
+
+fn nestest_operand<B: Mem>(cpu: &CPU<B>, opcode: &opcodes::OpCode) -> String {
+  let pc = cpu.program_counter;
+  let byte = cpu.mem_peek(pc.wrapping_add(1));
+  let word = u16::from_le_bytes([byte, cpu.mem_peek(pc.wrapping_add(2))]);
+  // a pointer in the zero page, its high byte wrapping within it
+  let pointer = |base: u8| {
+    u16::from_le_bytes([
+      cpu.mem_peek(base as u16),
+      cpu.mem_peek(base.wrapping_add(1) as u16),
+    ])
+  };
+  let x = cpu.register_x;
+  let y = cpu.register_y;
+  match opcode.mode {
+    AddressingMode::Immediate => format!("#${:02X}", byte),
+    AddressingMode::ZeroPage => format!("${:02X} = {:02X}", byte, cpu.mem_peek(byte as u16)),
+    AddressingMode::ZeroPage_X => {
+      let addr = byte.wrapping_add(x);
+      format!(
+        "${:02X},X @ {:02X} = {:02X}",
+        byte,
+        addr,
+        cpu.mem_peek(addr as u16)
+      )
+    }
+    AddressingMode::ZeroPage_Y => {
+      let addr = byte.wrapping_add(y);
+      format!(
+        "${:02X},Y @ {:02X} = {:02X}",
+        byte,
+        addr,
+        cpu.mem_peek(addr as u16)
+      )
+    }
+    AddressingMode::Absolute => format!("${:04X} = {:02X}", word, cpu.mem_peek(word)),
+    AddressingMode::Absolute_X => {
+      let addr = word.wrapping_add(x as u16);
+      format!(
+        "${:04X},X @ {:04X} = {:02X}",
+        word,
+        addr,
+        cpu.mem_peek(addr)
+      )
+    }
+    AddressingMode::Absolute_Y => {
+      let addr = word.wrapping_add(y as u16);
+      format!(
+        "${:04X},Y @ {:04X} = {:02X}",
+        word,
+        addr,
+        cpu.mem_peek(addr)
+      )
+    }
+    AddressingMode::Indirect_X => {
+      let ptr = byte.wrapping_add(x);
+      let addr = pointer(ptr);
+      format!(
+        "(${:02X},X) @ {:02X} = {:04X} = {:02X}",
+        byte,
+        ptr,
+        addr,
+        cpu.mem_peek(addr)
+      )
+    }
+    AddressingMode::Indirect_Y => {
+      let base = pointer(byte);
+      let addr = base.wrapping_add(y as u16);
+      format!(
+        "(${:02X}),Y = {:04X} @ {:04X} = {:02X}",
+        byte,
+        base,
+        addr,
+        cpu.mem_peek(addr)
+      )
+    }
+    AddressingMode::NoneAddressing => match (opcode.code, opcode.len) {
+      (0x0a | 0x2a | 0x4a | 0x6a, _) => String::from("A"),
+      // the 6502 doesn't carry into the high byte of the pointer
+      (0x6c, _) => {
+        let hi = (word & 0xFF00) | (word.wrapping_add(1) & 0x00FF);
+        let target = u16::from_le_bytes([cpu.mem_peek(word), cpu.mem_peek(hi)]);
+        format!("(${:04X}) = {:04X}", word, target)
+      }
+      (_, 3) => format!("${:04X}", word),
+      // branches, relative to the next instruction
+      (_, 2) => format!(
+        "${:04X}",
+        pc.wrapping_add(2).wrapping_add(byte as i8 as u16)
+      ),
+      _ => String::new(),
+    },
+  }
+}
+
+/// Run `rom` the way the reference nestest.log was made: from $C000
+/// (nestest's automation mode, no PPU needed) with the clock where the
+/// reset sequence leaves it. Returns up to `lines` log lines, fewer if the
+/// CPU stops first.
+pub fn nestest_run(rom: Rom, lines: usize) -> Result<Vec<String>, RomError> {
+  let mut cpu = CPU::new();
+  cpu.load_rom(rom)?;
+  cpu.halt_on_brk = false;
+  cpu.program_counter = NESTEST_START;
+  cpu.bus.tick(NESTEST_START_CYCLES);
+  cpu.cycles = NESTEST_START_CYCLES as u64;
+  cpu.set_nestest_log(true);
+
+  let mut log = Vec::new();
+  while log.len() < lines {
+    let running = cpu.step_instruction();
+    log.append(&mut cpu.take_nestest_log());
+    if !running {
+      break;
+    }
+  }
+  log.truncate(lines);
+  Ok(log)
+}
+
+/// Where a trace stops matching a reference log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogMismatch {
+  /// 1-based, like an editor's.
+  pub line: usize,
+  /// None past the end of the log.
+  pub expected: Option<String>,
+  pub actual: Option<String>,
+}
+
+impl fmt::Display for LogMismatch {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let or_end = |line: &Option<String>| line.clone().unwrap_or_else(|| "(end of log)".to_string());
+    write!(
+      f,
+      "line {} differs\nexpected: {}\nactual:   {}",
+      self.line,
+      or_end(&self.expected),
+      or_end(&self.actual)
+    )
+  }
+}
+
+/// The first line of `actual` that isn't the corresponding line of the
+/// `expected` log text, line endings and trailing blanks aside. A trace
+/// that ends early, or runs past the end, mismatches there.
+pub fn first_log_mismatch(expected: &str, actual: &[String]) -> Option<LogMismatch> {
+  let expected: Vec<&str> = expected.lines().map(str::trim_end).collect();
+  (0..expected.len().max(actual.len())).find_map(|i| {
+    let want = expected.get(i).copied();
+    let got = actual.get(i).map(|line| line.trim_end());
+    if want == got {
+      None
+    } else {
+      Some(LogMismatch {
+        line: i + 1,
+        expected: want.map(str::to_string),
+        actual: got.map(str::to_string),
+      })
+    }
+  })
+}
+
+/// Ring of the last `TRACE_LINES` instructions. Entries are stored raw and
+/// only formatted when somebody asks, so recording is cheap enough to stay
+/// on all the time.
+#[derive(Clone, Copy)]
+pub struct TraceLog {
+  entries: [Option<TraceEntry>; TRACE_LINES],
+  next: usize,
+}
+
+impl Default for TraceLog {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl TraceLog {
+  pub fn new() -> Self {
+    TraceLog {
+      entries: [None; TRACE_LINES],
+      next: 0,
+    }
+  }
+
+  pub fn push(&mut self, entry: TraceEntry) {
+    self.entries[self.next] = Some(entry);
+    self.next = (self.next + 1) % TRACE_LINES;
+  }
+
+  /// Oldest first.
+  pub fn iter(&self) -> impl Iterator<Item = &TraceEntry> {
+    let (newer, older) = self.entries.split_at(self.next);
+    older.iter().chain(newer.iter()).flatten()
+  }
+
+  pub fn clear(&mut self) {
+    self.entries = [None; TRACE_LINES];
+    self.next = 0;
+  }
+}
+
+/// Everything needed to make sense of a crash in a bug report.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoreDump {
+  pub reason: String,
+  pub frame: u64,
+  pub state: CpuState,
+  pub trace: Vec<String>,
+  /// Internal RAM, $0000-$07FF.
+  pub ram: Vec<u8>,
+}
+
+impl CoreDump {
+  /// Collect a dump of `cpu`. `frame` comes from whoever drives the frames.
+  pub fn capture<B: Mem>(cpu: &CPU<B>, frame: u64) -> CoreDump {
+    CoreDump {
+      reason: match cpu.fault() {
+        Some(fault) => fault.to_string(),
+        None => String::from("no fault"),
+      },
+      frame,
+      state: cpu.state(),
+      trace: cpu.trace().iter().map(TraceEntry::line).collect(),
+      ram: (0x0000..0x0800).map(|addr| cpu.mem_peek(addr)).collect(),
+    }
+  }
+
+  /// Plain text, meant to be attached to a bug report as is.
+  pub fn to_text(&self) -> String {
+    let mut out = String::new();
+    // writing into a String can't fail
+    writeln!(out, "flemu core dump").unwrap();
+    writeln!(out, "reason: {}", self.reason).unwrap();
+    writeln!(out, "frame: {}", self.frame).unwrap();
+    let s = &self.state;
+    writeln!(
+      out,
+      "registers: PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+      s.pc, s.a, s.x, s.y, s.flags, s.sp, s.cycles
+    )
+    .unwrap();
+
+    writeln!(out, "\ntrace (last {} instructions):", self.trace.len()).unwrap();
+    for line in self.trace.iter() {
+      writeln!(out, "{}", line).unwrap();
+    }
+
+    writeln!(out, "\nram:").unwrap();
+    for (row, chunk) in self.ram.chunks(16).enumerate() {
+      write!(out, "{:04X}:", row * 16).unwrap();
+      for byte in chunk {
+        write!(out, " {:02X}", byte).unwrap();
+      }
+      writeln!(out).unwrap();
+    }
+    out
+  }
+}
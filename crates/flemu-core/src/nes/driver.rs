@@ -0,0 +1,129 @@
+use crate::nes::cpu::CPU;
+use crate::nes::joypad::JoypadButton;
+use crate::nes::ppu::Frame;
+use futures_core::Stream;
+use std::pin::Pin;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::task::{Context, Poll};
+
+/*
+  The console for frontends built on an async runtime (a web server
+  streaming frames, a Tauri or egui app): a `Stream` with a frame per
+  item, controlled through a channel that any task can send into.
+
+  Every poll runs a frame, after taking whatever input was sent since the
+  last one, so a stream is never pending; how fast it goes is the
+  consumer's business, e.g. zipped with a 60Hz interval, or as fast as
+  it can for a bot. It ends when the CPU halts.
+*/
+
+/// What frontends send the driver, applied in order at the start of the
+/// next frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DriverInput {
+  /// Press or release `button` on `player`'s (0-3) controller.
+  Button {
+    player: usize,
+    button: JoypadButton,
+    pressed: bool,
+  },
+  /// Hold exactly `buttons` on `player`'s controller.
+  Hold {
+    player: usize,
+    buttons: JoypadButton,
+  },
+  /// The reset button.
+  Reset,
+}
+
+/// A frame as the stream yields it.
+#[derive(Clone)]
+pub struct DriverFrame {
+  /// The PPU's `frame_count` after it.
+  pub number: u64,
+  /// Palette indices, for `Palette::frame_rgba` and the like.
+  pub frame: Frame,
+  /// The sound it made, at the APU's sample rate.
+  pub samples: Vec<f32>,
+}
+
+pub struct NesDriver {
+  cpu: CPU,
+  input: Receiver<DriverInput>,
+  halted: bool,
+}
+
+impl NesDriver {
+  /// Drive `cpu`, with a cartridge loaded. The sender is its input.
+  pub fn new(cpu: CPU) -> (NesDriver, Sender<DriverInput>) {
+    let (sender, input) = mpsc::channel();
+    let driver = NesDriver {
+      cpu,
+      input,
+      halted: false,
+    };
+    (driver, sender)
+  }
+
+  pub fn cpu(&self) -> &CPU {
+    &self.cpu
+  }
+
+  /// For savestates, cheats and the like between frames.
+  pub fn cpu_mut(&mut self) -> &mut CPU {
+    &mut self.cpu
+  }
+
+  pub fn into_cpu(self) -> CPU {
+    self.cpu
+  }
+
+  // everything sent so far; senders gone just means no more input
+  fn take_input(&mut self) {
+    while let Ok(input) = self.input.try_recv() {
+      self.apply(input);
+    }
+  }
+
+  fn apply(&mut self, input: DriverInput) {
+    match input {
+      DriverInput::Button {
+        player,
+        button,
+        pressed,
+      } => {
+        if let Some(pad) = self.cpu.bus.joypads.get_mut(player) {
+          pad.set_button_pressed_status(button, pressed);
+        }
+      }
+      DriverInput::Hold { player, buttons } => {
+        if let Some(pad) = self.cpu.bus.joypads.get_mut(player) {
+          pad.set_button_pressed_status(JoypadButton::all(), false);
+          pad.set_button_pressed_status(buttons, true);
+        }
+      }
+      DriverInput::Reset => self.cpu.reset(),
+    }
+  }
+}
+
+impl Stream for NesDriver {
+  type Item = DriverFrame;
+
+  fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<DriverFrame>> {
+    let driver = self.get_mut();
+    if driver.halted {
+      return Poll::Ready(None);
+    }
+    driver.take_input();
+    if !driver.cpu.run_frame() {
+      driver.halted = true;
+      return Poll::Ready(None);
+    }
+    Poll::Ready(Some(DriverFrame {
+      number: driver.cpu.bus.ppu.frame_count,
+      frame: driver.cpu.bus.ppu.frame.clone(),
+      samples: driver.cpu.bus.apu.take_samples(),
+    }))
+  }
+}
@@ -0,0 +1,255 @@
+use crate::nes::cartridge::{Mirroring, Region, Rom, RomInfo};
+use crate::nes::mapper::Mmc3Irq;
+use crate::nes::patch::crc32;
+use std::collections::HashMap;
+use std::fmt;
+
+/*
+  Games recognised by the CRC32 of their PRG and CHR ROM, header left out
+  as NesCartDB and No-Intro do, so a dump with a bad header is still the
+  same game.
+
+  An entry gives the title and whatever the header can't be trusted with.
+  Many iNES 1.0 dumps have the wrong mapper, mirroring or battery bit,
+  and some PAL releases don't say they are; the database wins over them.
+  It can also say what the game is played with, a Zapper or a Four Score,
+  for the frontend to plug in.
+
+  The text format, one game per line, `#` comments:
+
+    # crc32  | title              | overrides
+    3337ec46 | Super Mario Bros.  |
+    00000001 | Some PAL game      | region=pal mirroring=vertical battery
+    00000002 | Some shooting game | zapper
+
+  Overrides are `mapper=N`, `submapper=N`, `mirroring=` one of
+  horizontal, vertical or four-screen, `region=` one of ntsc, pal,
+  multi or dendy, `battery` or `no-battery`, `prg-ram=` a size in bytes
+  or with a k for KB, `mmc3-irq=` old or new, and `zapper` or
+  `four-score`.
+
+  `prg-ram` is all of the PRG RAM, battery-backed or not as the battery
+  bit ends up. `mmc3-irq` picks the scanline counter revision for MMC3
+  games that only run on one, through the submapper that selects it.
+
+  Only a handful of games are built in, game_db.txt; frontends add the
+  rest with `parse` and `extend`, from a file converted from a bigger
+  database.
+*/
+
+const BUILTIN: &str = include_str!("game_db.txt");
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GameDbError {
+  /// Line `line` (from 1) isn't an entry.
+  Syntax { line: usize },
+}
+
+impl fmt::Display for GameDbError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      GameDbError::Syntax { line } => write!(f, "bad game database line {}", line),
+    }
+  }
+}
+
+impl std::error::Error for GameDbError {}
+
+/// What a game is played with instead of controllers alone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Peripheral {
+  /// In port 2.
+  Zapper,
+  FourScore,
+}
+
+impl Peripheral {
+  pub fn id(&self) -> &'static str {
+    match self {
+      Peripheral::Zapper => "zapper",
+      Peripheral::FourScore => "four-score",
+    }
+  }
+}
+
+/// The header fields a dump may have wrong. None keeps the header's.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct HeaderFix {
+  pub mapper: Option<u16>,
+  pub submapper: Option<u8>,
+  pub mirroring: Option<Mirroring>,
+  pub battery: Option<bool>,
+  pub region: Option<Region>,
+  pub prg_ram_size: Option<usize>,
+  pub mmc3_irq: Option<Mmc3Irq>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Game {
+  pub crc32: u32,
+  pub title: String,
+  pub fix: HeaderFix,
+  pub peripheral: Option<Peripheral>,
+}
+
+impl Game {
+  /// Correct `info`, giving the names of the fields that changed.
+  pub fn apply(&self, info: &mut RomInfo) -> Vec<&'static str> {
+    let mut changed = Vec::new();
+    let fix = &self.fix;
+    if let Some(mapper) = fix.mapper.filter(|&m| m != info.mapper) {
+      info.mapper = mapper;
+      changed.push("mapper");
+    }
+    if let Some(submapper) = fix.submapper.filter(|&s| s != info.submapper) {
+      info.submapper = submapper;
+      changed.push("submapper");
+    }
+    if let Some(mirroring) = fix.mirroring.filter(|&m| m != info.mirroring) {
+      info.mirroring = mirroring;
+      changed.push("mirroring");
+    }
+    if let Some(battery) = fix.battery.filter(|&b| b != info.battery) {
+      info.battery = battery;
+      changed.push("battery");
+    }
+    if let Some(region) = fix.region.filter(|&r| r != info.region) {
+      info.region = region;
+      changed.push("region");
+    }
+    if let Some(size) = fix.prg_ram_size {
+      let sizes = match info.battery {
+        true => (0, size),
+        false => (size, 0),
+      };
+      if sizes != (info.prg_ram_size, info.prg_nvram_size) {
+        info.prg_ram_size = sizes.0;
+        info.prg_nvram_size = sizes.1;
+        changed.push("prg_ram_size");
+      }
+    }
+    if let Some(irq) = fix
+      .mmc3_irq
+      .filter(|&i| i != Mmc3Irq::for_submapper(info.submapper))
+    {
+      info.submapper = irq.submapper();
+      changed.push("mmc3_irq");
+    }
+    changed
+  }
+}
+
+/// The CRC32 games are looked up by: PRG ROM and then CHR ROM, without
+/// the header or trainer.
+pub fn rom_crc32(rom: &Rom) -> u32 {
+  crc32(&[&rom.prg_rom[..], &rom.chr_rom[..]].concat())
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GameDb {
+  games: HashMap<u32, Game>,
+}
+
+impl GameDb {
+  /// The games that come with the emulator.
+  pub fn builtin() -> Self {
+    GameDb::parse(BUILTIN).expect("the built-in game database parses")
+  }
+
+  pub fn parse(text: &str) -> Result<Self, GameDbError> {
+    let mut db = GameDb::default();
+    for (i, line) in text.lines().enumerate() {
+      let line = line.split('#').next().unwrap_or("").trim();
+      if line.is_empty() {
+        continue;
+      }
+      let game = parse_game(line).ok_or(GameDbError::Syntax { line: i + 1 })?;
+      db.games.insert(game.crc32, game);
+    }
+    Ok(db)
+  }
+
+  /// Add `other`'s games, replacing ours where both have one.
+  pub fn extend(&mut self, other: GameDb) {
+    self.games.extend(other.games);
+  }
+
+  pub fn len(&self) -> usize {
+    self.games.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.games.is_empty()
+  }
+
+  pub fn lookup(&self, crc32: u32) -> Option<&Game> {
+    self.games.get(&crc32)
+  }
+
+  /// The game `rom` is, if it's in here.
+  pub fn identify(&self, rom: &Rom) -> Option<&Game> {
+    self.lookup(rom_crc32(rom))
+  }
+}
+
+fn parse_game(line: &str) -> Option<Game> {
+  let mut fields = line.split('|').map(str::trim);
+  let crc = fields.next()?;
+  let title = fields.next().filter(|title| !title.is_empty())?;
+  let overrides = fields.next().unwrap_or("");
+  if crc.len() != 8 || fields.next().is_some() {
+    return None;
+  }
+  let mut game = Game {
+    crc32: u32::from_str_radix(crc, 16).ok()?,
+    title: title.to_string(),
+    fix: HeaderFix::default(),
+    peripheral: None,
+  };
+  for token in overrides.split_whitespace() {
+    let fix = &mut game.fix;
+    match token.split_once('=') {
+      Some(("mapper", n)) => fix.mapper = Some(n.parse().ok()?),
+      Some(("submapper", n)) => fix.submapper = Some(n.parse().ok()?),
+      Some(("mirroring", mirroring)) => {
+        fix.mirroring = Some(match mirroring {
+          "horizontal" => Mirroring::Horizontal,
+          "vertical" => Mirroring::Vertical,
+          "four-screen" => Mirroring::FourScreen,
+          _ => return None,
+        })
+      }
+      Some(("region", region)) => {
+        fix.region = Some(match region {
+          "ntsc" => Region::Ntsc,
+          "pal" => Region::Pal,
+          "multi" => Region::MultiRegion,
+          "dendy" => Region::Dendy,
+          _ => return None,
+        })
+      }
+      Some(("prg-ram", size)) => {
+        fix.prg_ram_size = Some(match size.strip_suffix('k') {
+          Some(kb) => kb.parse::<usize>().ok()? * 0x400,
+          None => size.parse().ok()?,
+        })
+      }
+      Some(("mmc3-irq", irq)) => {
+        fix.mmc3_irq = Some(match irq {
+          "old" => Mmc3Irq::Old,
+          "new" => Mmc3Irq::New,
+          _ => return None,
+        })
+      }
+      Some(_) => return None,
+      None => match token {
+        "battery" => fix.battery = Some(true),
+        "no-battery" => fix.battery = Some(false),
+        "zapper" => game.peripheral = Some(Peripheral::Zapper),
+        "four-score" => game.peripheral = Some(Peripheral::FourScore),
+        _ => return None,
+      },
+    }
+  }
+  Some(game)
+}
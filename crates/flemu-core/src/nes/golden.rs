@@ -0,0 +1,90 @@
+use crate::nes::cartridge::{Rom, RomError};
+use crate::nes::cpu::CPU;
+use crate::nes::palette::Palette;
+use crate::nes::ppu::Frame;
+use crate::video_dump;
+use std::fmt;
+
+/*
+  Golden frames: run a ROM for a fixed number of frames and compare the
+  picture with a reference PNG checked in next to the tests. Frame hashes
+  (`regression`) say that something changed; a golden frame says whether
+  what's on screen did, and the PNG of a failure shows how.
+
+  References are written with `video_dump::encode_png`, through the default
+  palette, and read back pixel for pixel by `video_dump::decode_png`.
+*/
+
+/// Boot `rom`, run `frames` frames and return the picture as RGBA through
+/// the default palette. A program that faults earlier leaves the picture
+/// it stopped at.
+pub fn render(rom: Rom, frames: u32) -> Result<Vec<u8>, RomError> {
+  let mut cpu = CPU::new();
+  cpu.reset();
+  cpu.halt_on_brk = false;
+  cpu.load_rom(rom)?;
+  for _ in 0..frames {
+    if !cpu.run_frame() {
+      break;
+    }
+  }
+  Ok(Palette::default().frame_rgba(&cpu.bus.ppu.frame))
+}
+
+/// How a rendered frame differs from its reference.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mismatch {
+  /// The reference isn't a 256x240 PNG `decode_png` can read.
+  BadReference,
+  Pixels {
+    count: usize,
+    /// x, y of the first one, row by row.
+    first: (usize, usize),
+  },
+}
+
+impl fmt::Display for Mismatch {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Mismatch::BadReference => write!(
+        f,
+        "the reference isn't a {}x{} frame",
+        Frame::WIDTH,
+        Frame::HEIGHT
+      ),
+      Mismatch::Pixels { count, first } => write!(
+        f,
+        "{} pixels differ, the first at {},{}",
+        count, first.0, first.1
+      ),
+    }
+  }
+}
+
+impl std::error::Error for Mismatch {}
+
+/// Check `rgba`, a `render`ed frame, against the reference PNG.
+pub fn compare(reference: &[u8], rgba: &[u8]) -> Result<(), Mismatch> {
+  let expected = match video_dump::decode_png(reference) {
+    Some((width, height, expected))
+      if width as usize == Frame::WIDTH && height as usize == Frame::HEIGHT =>
+    {
+      expected
+    }
+    _ => return Err(Mismatch::BadReference),
+  };
+  let mut differing = expected
+    .chunks(4)
+    .zip(rgba.chunks(4))
+    .enumerate()
+    .filter(|(_, (a, b))| a != b)
+    .map(|(i, _)| i);
+  let first = match differing.next() {
+    Some(i) => i,
+    None => return Ok(()),
+  };
+  Err(Mismatch::Pixels {
+    count: differing.count() + 1,
+    first: (first % Frame::WIDTH, first / Frame::WIDTH),
+  })
+}
@@ -0,0 +1,267 @@
+use crate::nes::combo::Combo;
+use crate::nes::savestate::{StateError, StateReader, StateWriter};
+use bitflags::bitflags;
+
+bitflags! {
+  /// Buttons of a standard controller, in the order the shift register
+  /// reports them.
+  pub struct JoypadButton: u8 {
+    const A      = 0b00000001;
+    const B      = 0b00000010;
+    const SELECT = 0b00000100;
+    const START  = 0b00001000;
+    const UP     = 0b00010000;
+    const DOWN   = 0b00100000;
+    const LEFT   = 0b01000000;
+    const RIGHT  = 0b10000000;
+  }
+}
+
+// upper bits of $4016/$4017 reads: open bus, the high byte of the address
+const OPEN_BUS: u8 = 0x40;
+
+/// How fast turbo buttons fire: pressed for `on` frames, then released
+/// for `off`, both at least 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TurboRate {
+  pub on: u8,
+  pub off: u8,
+}
+
+impl Default for TurboRate {
+  // 15 presses a second on NTSC; some games miss presses a frame long
+  fn default() -> Self {
+    TurboRate { on: 2, off: 2 }
+  }
+}
+
+/// A standard controller on $4016 (player 1) or $4017 (player 2).
+///
+/// Writing 1 to bit 0 of $4016 (strobe) keeps reloading the shift register,
+/// so every read sees A as it is right now. The buttons are latched when
+/// strobe goes from 1 to 0, then shifted out one per read in `JoypadButton`
+/// order, the latched ones even if the player lets go; 1s once all eight
+/// are out, however many reads follow.
+///
+/// Turbo buttons, held with `set_turbo_pressed_status`, press and release
+/// their button at `TurboRate`, counted in the PPU's frames, which the
+/// bus passes on at every vblank. The same frames always fire the same
+/// way, and `buttons` has the result, as the game and movies see it.
+/// A combo from `play_combo` is timed by the same frames.
+#[derive(Debug, Clone)]
+pub struct Joypad {
+  strobe: bool,
+  index: u8,
+  latched: JoypadButton,
+  buttons: JoypadButton,
+  turbo: JoypadButton,
+  turbo_rate: TurboRate,
+  frame: u64,
+  // the combo playing and the frame it started on
+  combo: Combo,
+  combo_start: u64,
+}
+
+impl Default for Joypad {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Joypad {
+  pub fn new() -> Self {
+    Joypad {
+      strobe: false,
+      index: 0,
+      latched: JoypadButton::empty(),
+      buttons: JoypadButton::empty(),
+      turbo: JoypadButton::empty(),
+      turbo_rate: TurboRate::default(),
+      frame: 0,
+      combo: Combo::default(),
+      combo_start: 0,
+    }
+  }
+
+  /// $4016 write.
+  pub fn write(&mut self, data: u8) {
+    let strobe = data & 1 == 1;
+    if self.strobe && !strobe {
+      self.latched = self.buttons();
+    }
+    self.strobe = strobe;
+    if self.strobe {
+      self.index = 0;
+    }
+  }
+
+  pub fn read(&mut self) -> u8 {
+    let bit = self.peek();
+    if !self.strobe && self.index <= 7 {
+      self.index += 1;
+    }
+    bit
+  }
+
+  /// The next read without shifting.
+  pub fn peek(&self) -> u8 {
+    OPEN_BUS | self.bit(self.index)
+  }
+
+  // button `index` of the shift register, 1s past the eighth
+  fn bit(&self, index: u8) -> u8 {
+    if self.strobe {
+      // still reloading: every read sees A
+      self.buttons().bits() & 1
+    } else if index > 7 {
+      1
+    } else {
+      (self.latched.bits() >> index) & 1
+    }
+  }
+
+  pub fn set_button_pressed_status(&mut self, button: JoypadButton, pressed: bool) {
+    self.buttons.set(button, pressed);
+  }
+
+  /// Hold or let go of `button`'s turbo button.
+  pub fn set_turbo_pressed_status(&mut self, button: JoypadButton, pressed: bool) {
+    self.turbo.set(button, pressed);
+  }
+
+  /// The turbo buttons held.
+  pub fn turbo(&self) -> JoypadButton {
+    self.turbo
+  }
+
+  /// The buttons held, not counting turbo ones.
+  pub fn held(&self) -> JoypadButton {
+    self.buttons
+  }
+
+  /// Pressed right now: those held, the turbo ones if they're firing
+  /// this frame, and the combo's.
+  pub fn buttons(&self) -> JoypadButton {
+    let TurboRate { on, off } = self.turbo_rate;
+    let combo = self.combo_buttons();
+    if self.frame % (on as u64 + off as u64) < on as u64 {
+      self.buttons | self.turbo | combo
+    } else {
+      self.buttons | combo
+    }
+  }
+
+  fn combo_buttons(&self) -> JoypadButton {
+    let index = self.frame.wrapping_sub(self.combo_start);
+    if index < self.combo.len() as u64 {
+      self.combo.frames[index as usize]
+    } else {
+      JoypadButton::empty()
+    }
+  }
+
+  /// Press `combo`'s buttons, its first frame's from this frame on, in
+  /// place of any combo still playing.
+  pub fn play_combo(&mut self, combo: &Combo) {
+    self.combo = combo.clone();
+    self.combo_start = self.frame;
+  }
+
+  pub fn stop_combo(&mut self) {
+    self.combo = Combo::default();
+  }
+
+  pub fn combo_playing(&self) -> bool {
+    !self.combo.is_empty()
+  }
+
+  /// Clamped to at least a frame on and a frame off.
+  pub fn set_turbo_rate(&mut self, rate: TurboRate) {
+    self.turbo_rate = TurboRate {
+      on: rate.on.max(1),
+      off: rate.off.max(1),
+    };
+  }
+
+  pub fn turbo_rate(&self) -> TurboRate {
+    self.turbo_rate
+  }
+
+  /// The PPU's `frame_count`, which turbo buttons are timed by.
+  pub fn set_frame(&mut self, frame: u64) {
+    self.frame = frame;
+    // done, or the frames went back past its start with a savestate
+    if frame.wrapping_sub(self.combo_start) >= self.combo.len() as u64 {
+      self.stop_combo();
+    }
+  }
+
+  /// The shift register and what it latched. Buttons are whatever the
+  /// player holds now.
+  pub fn save_state(&self, w: &mut StateWriter) {
+    w.bool(self.strobe);
+    w.u8(self.index);
+    w.u8(self.latched.bits());
+  }
+
+  pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+    self.strobe = r.bool()?;
+    self.index = r.u8_below(9)?;
+    self.latched = JoypadButton::from_bits_truncate(r.u8()?);
+    Ok(())
+  }
+}
+
+// what a Four Score reports after its two pads, in read order
+const FOUR_SCORE_SIGNATURES: [[u8; 8]; 2] = [[0, 0, 0, 1, 0, 0, 0, 0], [0, 0, 1, 0, 0, 0, 0, 0]];
+
+/// The Four Score multitap. Each port reads 24 bits: the buttons of its
+/// first pad (player 1 or 2), of its second (player 3 or 4), then a
+/// signature telling games the adapter is there; 1s after that. The pads
+/// latch on the same strobe, so their buttons are the latched ones.
+#[derive(Debug, Clone, Default)]
+pub struct FourScore {
+  strobe: bool,
+  index: [u8; 2],
+}
+
+impl FourScore {
+  /// $4016 write, reaching both ports.
+  pub fn write(&mut self, data: u8) {
+    self.strobe = data & 1 == 1;
+    if self.strobe {
+      self.index = [0, 0];
+    }
+  }
+
+  /// $4016 (`port` 0) or $4017 (`port` 1) read of `pads`, players 1-4.
+  pub fn read(&mut self, port: usize, pads: &[Joypad; 4]) -> u8 {
+    let bit = self.peek(port, pads);
+    if !self.strobe && self.index[port] < 24 {
+      self.index[port] += 1;
+    }
+    bit
+  }
+
+  pub fn peek(&self, port: usize, pads: &[Joypad; 4]) -> u8 {
+    let index = if self.strobe { 0 } else { self.index[port] };
+    let bit = match index {
+      0..=7 => pads[port].bit(index),
+      8..=15 => pads[port + 2].bit(index - 8),
+      16..=23 => FOUR_SCORE_SIGNATURES[port][index as usize - 16],
+      _ => 1,
+    };
+    OPEN_BUS | bit
+  }
+
+  pub fn save_state(&self, w: &mut StateWriter) {
+    w.bool(self.strobe);
+    w.bytes(&self.index);
+  }
+
+  pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+    self.strobe = r.bool()?;
+    self.index = [r.u8_below(25)?, r.u8_below(25)?];
+    Ok(())
+  }
+}
@@ -0,0 +1,381 @@
+use crate::nes::cartridge::{Mirroring, Rom, RomError};
+use crate::nes::nametable::NametableMap;
+use crate::nes::savestate::{StateError, StateReader, StateWriter};
+use log::trace;
+
+mod axrom;
+mod camerica;
+mod cnrom;
+mod color_dreams;
+mod fme7;
+mod four_screen;
+mod gxrom;
+mod inl_nsf;
+mod jaleco_j87;
+mod mmc1;
+mod mmc3;
+mod mmc5;
+mod namco163;
+mod nrom;
+mod sunsoft1;
+mod sunsoft5b;
+mod test_harness;
+mod unrom_180;
+mod uxrom;
+
+pub use axrom::Axrom;
+pub use camerica::Camerica;
+pub use cnrom::Cnrom;
+pub use color_dreams::ColorDreams;
+pub use fme7::Fme7;
+pub use four_screen::FourScreenVram;
+pub use gxrom::Gxrom;
+pub use inl_nsf::InlNsf;
+pub use jaleco_j87::JalecoJ87;
+pub use mmc1::Mmc1;
+pub use mmc3::{Mmc3, Mmc3Irq};
+pub use mmc5::Mmc5;
+pub use namco163::Namco163;
+pub use nrom::Nrom;
+pub use sunsoft1::Sunsoft1;
+pub use test_harness::{MapperTestHarness, SyntheticHeader};
+pub use unrom_180::Unrom180;
+pub use uxrom::Uxrom;
+
+/// The cartridge board: decides what the CPU sees in $4020-$FFFF and what
+/// the PPU sees in pattern table space ($0000-$1FFF).
+///
+/// Addresses are passed unchanged from the bus, so each mapper does its own
+/// bank math. `Send` because the machine lives behind a global mutex.
+pub trait Mapper: Send {
+  /// Board name for debuggers, e.g. "MMC1".
+  fn name(&self) -> &'static str;
+
+  fn prg_read(&self, addr: u16) -> u8;
+
+  /// A CPU read, for boards where reading a register does something
+  /// (acknowledging an IRQ). `prg_read` is what debuggers peek with.
+  fn prg_read_mut(&mut self, addr: u16) -> u8 {
+    self.prg_read(addr)
+  }
+
+  fn prg_write(&mut self, addr: u16, data: u8);
+
+  fn chr_read(&self, addr: u16) -> u8;
+
+  fn chr_write(&mut self, addr: u16, data: u8);
+
+  fn mirroring(&self) -> Mirroring;
+
+  /// How nametable fetches are routed. Plain mirroring by default, override
+  /// for boards that remap CIRAM or bring their own nametable memory.
+  fn nametables(&self) -> NametableMap {
+    NametableMap::from_mirroring(self.mirroring())
+  }
+
+  /// Read cartridge nametable memory, for pages mapped as
+  /// `NametablePage::Cartridge`.
+  fn nametable_read(&self, offset: u16) -> u8 {
+    trace!("no cartridge nametable memory at {:04x}", offset);
+    0
+  }
+
+  fn nametable_write(&mut self, offset: u16, data: u8) {
+    trace!(
+      "no cartridge nametable memory at {:04x}, dropped {:02x}",
+      offset,
+      data
+    );
+  }
+
+  /// Whether the board answers CPU reads at `addr` ($4020-$FFFF).
+  /// Elsewhere the CPU reads open bus, whatever `prg_read` says.
+  fn prg_mapped(&self, addr: u16) -> bool {
+    addr >= 0x8000 || (addr >= 0x6000 && self.prg_ram().is_some())
+  }
+
+  /// Whether the board drops a write on the cycle right after another, as
+  /// MMC1 does with the second write of a read-modify-write.
+  fn ignores_back_to_back_writes(&self) -> bool {
+    false
+  }
+
+  /// PRG ROM bank the CPU sees at `addr`, for debugger labels. None where
+  /// there's no ROM.
+  fn prg_bank(&self, _addr: u16) -> Option<Bank> {
+    None
+  }
+
+  /// CHR bank the PPU sees at `addr` ($0000-$1FFF).
+  fn chr_bank(&self, _addr: u16) -> Option<Bank> {
+    None
+  }
+
+  /// PRG RAM at $6000-$7FFF, on boards that have it. What a battery keeps
+  /// when the header says there's one.
+  fn prg_ram(&self) -> Option<&[u8]> {
+    None
+  }
+
+  fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+    None
+  }
+
+  /// Whether the board is asserting IRQ (scanline counters and the like).
+  fn irq_pending(&self) -> bool {
+    false
+  }
+
+  /// The PPU moved on to `scanline`, with rendering on or off. For boards
+  /// counting scanlines, which real ones do by watching the PPU's fetches.
+  fn ppu_scanline(&mut self, _scanline: u16, _rendering: bool) {}
+
+  /// `cycles` CPU cycles went by, for boards counting them (FME-7's IRQ)
+  /// and for sound chips.
+  fn cpu_cycles(&mut self, _cycles: u32) {}
+
+  /// What the board's sound chip puts out right now, mixed in with the
+  /// APU in its 16.16 fixed point. Silent on boards without one.
+  fn audio_level(&self) -> u32 {
+    0
+  }
+
+  /// The fetches for one background tile while rendering. `read` reads
+  /// PPU memory as the PPU would; boards with their own idea of
+  /// attributes or split screens (MMC5) answer some of it themselves.
+  fn background_tile(&self, fetch: &TileFetch, read: &dyn Fn(u16) -> u8) -> TileRow {
+    fetch_tile(fetch, read)
+  }
+
+  /// A sprite pattern byte while rendering, `tall` in 8x16 mode, for
+  /// boards that bank sprites apart from the background.
+  fn sprite_chr_read(&self, addr: u16, _tall: bool) -> u8 {
+    self.chr_read(addr)
+  }
+
+  /// Bank registers and RAM (PRG, CHR, nametable) for savestates, never
+  /// ROM: a state only loads into a board built from the same image, so
+  /// every size is known on both ends.
+  fn save_state(&self, w: &mut StateWriter);
+
+  fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError>;
+
+  /// Copy of the mapper with all of its banks and registers, for rollback
+  /// and for loading savestates without touching the running machine.
+  fn box_clone(&self) -> Box<dyn Mapper>;
+}
+
+/// A background tile the PPU is about to draw a row of.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TileFetch {
+  /// The PPU's VRAM address with this tile's scroll: fine Y, nametable,
+  /// coarse Y and X.
+  pub v: u16,
+  /// Tiles fetched on the line before this one, 0 to 32.
+  pub column: u8,
+  pub scanline: u16,
+  /// PPUCTRL's background pattern table, $0000 or $1000.
+  pub pattern_table: u16,
+  /// PPUCTRL's sprite size is 8x16.
+  pub tall_sprites: bool,
+}
+
+/// What a tile's fetches come to: the row's two pattern bytes and the
+/// attribute's palette, 0 to 3.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TileRow {
+  pub lo: u8,
+  pub hi: u8,
+  pub palette: u8,
+}
+
+/// A tile's nametable, attribute and pattern fetches, as every board
+/// but MMC5 leaves them.
+pub fn fetch_tile(fetch: &TileFetch, read: &dyn Fn(u16) -> u8) -> TileRow {
+  let v = fetch.v;
+  let tile = read(0x2000 | (v & 0x0FFF)) as u16;
+  let attribute = read(0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07));
+  let shift = ((v >> 4) & 0b100) | (v & 0b10);
+  let addr = fetch.pattern_table + tile * 16 + ((v >> 12) & 0b111);
+  TileRow {
+    lo: read(addr),
+    hi: read(addr + 8),
+    palette: (attribute >> shift) & 0b11,
+  }
+}
+
+/// A window of PRG or CHR memory: the `index`th block of `size` bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bank {
+  pub index: usize,
+  pub size: usize,
+}
+
+impl Bank {
+  fn at(offset: usize, size: usize) -> Bank {
+    Bank {
+      index: offset / size,
+      size,
+    }
+  }
+}
+
+impl Clone for Box<dyn Mapper> {
+  fn clone(&self) -> Self {
+    self.box_clone()
+  }
+}
+
+/// Stands in for an empty cartridge slot: every read is 0, writes vanish.
+#[derive(Clone)]
+pub struct NoCartridge;
+
+impl Mapper for NoCartridge {
+  fn name(&self) -> &'static str {
+    "no cartridge"
+  }
+
+  fn prg_read(&self, _addr: u16) -> u8 {
+    0
+  }
+
+  fn prg_write(&mut self, _addr: u16, _data: u8) {}
+
+  fn chr_read(&self, _addr: u16) -> u8 {
+    0
+  }
+
+  fn chr_write(&mut self, _addr: u16, _data: u8) {}
+
+  fn mirroring(&self) -> Mirroring {
+    Mirroring::Horizontal
+  }
+
+  fn prg_mapped(&self, _addr: u16) -> bool {
+    true
+  }
+
+  fn save_state(&self, _w: &mut StateWriter) {}
+
+  fn load_state(&mut self, _r: &mut StateReader) -> Result<(), StateError> {
+    Ok(())
+  }
+
+  fn box_clone(&self) -> Box<dyn Mapper> {
+    Box::new(NoCartridge)
+  }
+}
+
+/// Build the mapper the header asks for, with four-screen VRAM on top when
+/// the header says so.
+pub fn for_rom(rom: Rom) -> Result<Box<dyn Mapper>, RomError> {
+  let four_screen = rom.info.mirroring == Mirroring::FourScreen;
+  let board = board_for_rom(rom)?;
+  Ok(match four_screen {
+    true => Box::new(FourScreenVram::new(board)),
+    false => board,
+  })
+}
+
+fn board_for_rom(rom: Rom) -> Result<Box<dyn Mapper>, RomError> {
+  match rom.info.mapper {
+    0 => Ok(Box::new(Nrom::new(rom))),
+    1 => Ok(Box::new(Mmc1::new(rom))),
+    2 => Ok(Box::new(Uxrom::new(rom))),
+    3 => Ok(Box::new(Cnrom::new(rom))),
+    4 => Ok(Box::new(Mmc3::new(rom))),
+    5 => Ok(Box::new(Mmc5::new(rom))),
+    7 => Ok(Box::new(Axrom::new(rom))),
+    11 => Ok(Box::new(ColorDreams::new(rom))),
+    19 => Ok(Box::new(Namco163::new(rom))),
+    31 => Ok(Box::new(InlNsf::new(rom))),
+    66 => Ok(Box::new(Gxrom::new(rom))),
+    69 => Ok(Box::new(Fme7::new(rom))),
+    71 => Ok(Box::new(Camerica::new(rom))),
+    87 => Ok(Box::new(JalecoJ87::new(rom))),
+    180 => Ok(Box::new(Unrom180::new(rom))),
+    184 => Ok(Box::new(Sunsoft1::new(rom))),
+    number => Err(RomError::UnsupportedMapper {
+      number,
+      submapper: rom.info.submapper,
+      known_name: board_name(number),
+    }),
+  }
+}
+
+// iNES mapper numbers and what the boards behind them are usually called,
+// by number
+const BOARD_NAMES: &[(u16, &str)] = &[
+  (0, "NROM"),
+  (1, "MMC1"),
+  (2, "UxROM"),
+  (3, "CNROM"),
+  (4, "MMC3"),
+  (5, "MMC5"),
+  (7, "AxROM"),
+  (9, "MMC2"),
+  (10, "MMC4"),
+  (11, "Color Dreams"),
+  (13, "CPROM"),
+  (16, "Bandai FCG"),
+  (18, "Jaleco SS88006"),
+  (19, "Namco 163"),
+  (21, "VRC4a/VRC4c"),
+  (22, "VRC2a"),
+  (23, "VRC2b/VRC4e"),
+  (24, "VRC6a"),
+  (25, "VRC4b/VRC4d"),
+  (26, "VRC6b"),
+  (31, "INL NSF"),
+  (32, "Irem G-101"),
+  (33, "Taito TC0190"),
+  (34, "BNROM/NINA-001"),
+  (48, "Taito TC0690"),
+  (64, "Tengen RAMBO-1"),
+  (65, "Irem H3001"),
+  (66, "GxROM"),
+  (67, "Sunsoft-3"),
+  (68, "Sunsoft-4"),
+  (69, "Sunsoft FME-7"),
+  (70, "Bandai 74161"),
+  (71, "Camerica"),
+  (73, "VRC3"),
+  (75, "VRC1"),
+  (79, "NINA-03/NINA-06"),
+  (85, "VRC7"),
+  (86, "Jaleco JF-13"),
+  (87, "Jaleco J87"),
+  (89, "Sunsoft-2"),
+  (94, "UN1ROM"),
+  (105, "NES-EVENT"),
+  (118, "TxSROM"),
+  (119, "TQROM"),
+  (140, "Jaleco JF-11/JF-14"),
+  (163, "Nanjing"),
+  (180, "UNROM (Crazy Climber)"),
+  (184, "Sunsoft-1"),
+  (185, "CNROM with copy protection"),
+  (206, "Namco 108"),
+  (210, "Namco 175/340"),
+  (228, "Action 52"),
+  (232, "Camerica Quattro"),
+];
+
+/// What board iNES mapper `number` usually is, e.g. "MMC3" for 4, whether
+/// it's emulated or not.
+pub fn board_name(number: u16) -> Option<&'static str> {
+  BOARD_NAMES
+    .binary_search_by_key(&number, |&(number, _)| number)
+    .ok()
+    .map(|i| BOARD_NAMES[i].1)
+}
+
+/// CHR ROM as is, or 8KB of CHR RAM for boards without it. The flag tells
+/// whether writes should stick.
+fn chr_memory(chr_rom: Vec<u8>) -> (Vec<u8>, bool) {
+  if chr_rom.is_empty() {
+    (vec![0; 0x2000], true)
+  } else {
+    (chr_rom, false)
+  }
+}
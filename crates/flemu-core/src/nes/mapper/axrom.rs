@@ -0,0 +1,111 @@
+use crate::nes::cartridge::{Mirroring, Rom};
+use crate::nes::mapper::{self, Bank, Mapper};
+use crate::nes::savestate::{StateError, StateReader, StateWriter};
+use log::trace;
+
+const PRG_BANK_SIZE: usize = 0x8000;
+
+/// Mapper 7 (AxROM): Battletoads, Marble Madness, Wizards & Warriors.
+///
+/// Writes to $8000-$FFFF take `...M.PPP`: a 32KB PRG bank and which CIRAM
+/// half every nametable uses.
+#[derive(Clone)]
+pub struct Axrom {
+  prg_rom: Vec<u8>,
+  chr: Vec<u8>,
+  chr_is_ram: bool,
+  register: u8,
+}
+
+impl Axrom {
+  pub fn new(rom: Rom) -> Self {
+    let (chr, chr_is_ram) = mapper::chr_memory(rom.chr_rom);
+    Axrom {
+      prg_rom: rom.prg_rom,
+      chr,
+      chr_is_ram,
+      register: 0,
+    }
+  }
+}
+
+impl Axrom {
+  fn prg_offset(&self, addr: u16) -> usize {
+    let banks = (self.prg_rom.len() / PRG_BANK_SIZE).max(1);
+    let bank = (self.register & 0b111) as usize % banks;
+    (bank * PRG_BANK_SIZE + (addr - 0x8000) as usize) % self.prg_rom.len()
+  }
+}
+
+impl Mapper for Axrom {
+  fn name(&self) -> &'static str {
+    "AxROM"
+  }
+
+  fn prg_read(&self, addr: u16) -> u8 {
+    match addr {
+      0x8000..=0xFFFF => self.prg_rom[self.prg_offset(addr)],
+      _ => {
+        trace!("AxROM has nothing at {:04x}", addr);
+        0
+      }
+    }
+  }
+
+  fn prg_write(&mut self, addr: u16, data: u8) {
+    match addr {
+      0x8000..=0xFFFF => self.register = data,
+      _ => trace!("AxROM ignored write to {:04x}", addr),
+    }
+  }
+
+  fn chr_read(&self, addr: u16) -> u8 {
+    self.chr[(addr & 0x1FFF) as usize % self.chr.len()]
+  }
+
+  fn chr_write(&mut self, addr: u16, data: u8) {
+    if self.chr_is_ram {
+      self.chr[(addr & 0x1FFF) as usize] = data;
+    } else {
+      trace!("attempt to write to CHR ROM {:04x}", addr);
+    }
+  }
+
+  fn prg_bank(&self, addr: u16) -> Option<Bank> {
+    match addr {
+      0x8000..=0xFFFF => Some(Bank::at(self.prg_offset(addr), PRG_BANK_SIZE)),
+      _ => None,
+    }
+  }
+
+  fn chr_bank(&self, _addr: u16) -> Option<Bank> {
+    Some(Bank::at(0, self.chr.len()))
+  }
+
+  fn mirroring(&self) -> Mirroring {
+    if self.register & 0b1_0000 == 0 {
+      Mirroring::SingleScreenLower
+    } else {
+      Mirroring::SingleScreenUpper
+    }
+  }
+
+  fn save_state(&self, w: &mut StateWriter) {
+    if self.chr_is_ram {
+      w.bytes(&self.chr);
+    }
+    w.u8(self.register);
+  }
+
+  fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+    if self.chr_is_ram {
+      r.bytes_into(&mut self.chr)?;
+    }
+    self.register = r.u8()?;
+    Ok(())
+  }
+
+  fn box_clone(&self) -> Box<dyn Mapper> {
+    Box::new(self.clone())
+  }
+}
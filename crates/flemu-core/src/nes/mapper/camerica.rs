@@ -0,0 +1,134 @@
+use crate::nes::cartridge::{Mirroring, Rom};
+use crate::nes::mapper::{self, Bank, Mapper};
+use crate::nes::savestate::{StateError, StateReader, StateWriter};
+use log::trace;
+
+const PRG_BANK_SIZE: usize = 0x4000;
+
+/// Mapper 71 (Camerica/Codemasters BF909x): Micro Machines, Fire Hawk,
+/// Bee 52.
+///
+/// UxROM with the bank register moved to $C000-$FFFF. Fire Hawk's board
+/// (submapper 1) also picks a CIRAM half for every nametable with bit 4 of
+/// writes to $8000-$9FFF; everything else has the header's mirroring.
+#[derive(Clone)]
+pub struct Camerica {
+  prg_rom: Vec<u8>,
+  chr: Vec<u8>,
+  chr_is_ram: bool,
+  mirroring: Mirroring,
+  switches_mirroring: bool,
+  prg_bank: u8,
+}
+
+impl Camerica {
+  pub fn new(rom: Rom) -> Self {
+    let (chr, chr_is_ram) = mapper::chr_memory(rom.chr_rom);
+    let switches_mirroring = rom.info.submapper == 1;
+    Camerica {
+      prg_rom: rom.prg_rom,
+      chr,
+      chr_is_ram,
+      mirroring: match switches_mirroring {
+        true => Mirroring::SingleScreenLower,
+        false => rom.info.mirroring,
+      },
+      switches_mirroring,
+      prg_bank: 0,
+    }
+  }
+}
+
+impl Camerica {
+  fn bank_at(&self, addr: u16) -> Option<usize> {
+    let banks = self.prg_rom.len() / PRG_BANK_SIZE;
+    match addr {
+      0x8000..=0xBFFF => Some(self.prg_bank as usize % banks),
+      0xC000..=0xFFFF => Some(banks - 1),
+      _ => None,
+    }
+  }
+}
+
+impl Mapper for Camerica {
+  fn name(&self) -> &'static str {
+    "Camerica"
+  }
+
+  fn prg_read(&self, addr: u16) -> u8 {
+    match self.bank_at(addr) {
+      Some(bank) => self.prg_rom[bank * PRG_BANK_SIZE + (addr as usize % PRG_BANK_SIZE)],
+      None => {
+        trace!("Camerica has nothing at {:04x}", addr);
+        0
+      }
+    }
+  }
+
+  fn prg_write(&mut self, addr: u16, data: u8) {
+    match addr {
+      0x8000..=0x9FFF if self.switches_mirroring => {
+        self.mirroring = match data & 0x10 {
+          0 => Mirroring::SingleScreenLower,
+          _ => Mirroring::SingleScreenUpper,
+        }
+      }
+      0xC000..=0xFFFF => self.prg_bank = data & 0x0F,
+      _ => trace!("Camerica ignored write to {:04x}", addr),
+    }
+  }
+
+  fn chr_read(&self, addr: u16) -> u8 {
+    self.chr[(addr & 0x1FFF) as usize % self.chr.len()]
+  }
+
+  fn chr_write(&mut self, addr: u16, data: u8) {
+    if self.chr_is_ram {
+      self.chr[(addr & 0x1FFF) as usize] = data;
+    } else {
+      trace!("attempt to write to CHR ROM {:04x}", addr);
+    }
+  }
+
+  fn prg_bank(&self, addr: u16) -> Option<Bank> {
+    self.bank_at(addr).map(|index| Bank {
+      index,
+      size: PRG_BANK_SIZE,
+    })
+  }
+
+  fn chr_bank(&self, _addr: u16) -> Option<Bank> {
+    Some(Bank::at(0, self.chr.len()))
+  }
+
+  fn mirroring(&self) -> Mirroring {
+    self.mirroring
+  }
+
+  fn save_state(&self, w: &mut StateWriter) {
+    if self.chr_is_ram {
+      w.bytes(&self.chr);
+    }
+    w.u8(self.prg_bank);
+    w.bool(self.mirroring == Mirroring::SingleScreenUpper);
+  }
+
+  fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+    if self.chr_is_ram {
+      r.bytes_into(&mut self.chr)?;
+    }
+    self.prg_bank = r.u8()?;
+    let upper = r.bool()?;
+    if self.switches_mirroring {
+      self.mirroring = match upper {
+        true => Mirroring::SingleScreenUpper,
+        false => Mirroring::SingleScreenLower,
+      };
+    }
+    Ok(())
+  }
+
+  fn box_clone(&self) -> Box<dyn Mapper> {
+    Box::new(self.clone())
+  }
+}
@@ -0,0 +1,251 @@
+use crate::nes::cartridge::{Mirroring, Rom};
+use crate::nes::mapper::sunsoft5b::Sunsoft5b;
+use crate::nes::mapper::{self, Bank, Mapper};
+use crate::nes::savestate::{StateError, StateReader, StateWriter};
+use log::trace;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x400;
+
+/// Mapper 69 (Sunsoft FME-7 and 5B): Batman: Return of the Joker, Mr.
+/// Gimmick, Hebereke.
+///
+/// $8000-$9FFF picks a command, $A000-$BFFF gives its parameter:
+///
+///   $0-$7  1KB CHR banks
+///   $8     $6000-$7FFF: ERBBBBBB  RAM enable, RAM (1) or ROM, ROM bank
+///   $9-$B  8KB PRG banks at $8000, $A000, $C000; $E000 is the last bank
+///   $C     mirroring: vertical, horizontal, single-screen lower, upper
+///   $D     IRQ control: C......T  count, fire on underflow; acknowledges
+///   $E-$F  IRQ counter, low and high byte
+///
+/// The counter goes down every CPU cycle while counting, firing when it
+/// wraps from 0 to $FFFF. Only the 5B has the sound chip, but as nothing
+/// else answers its registers every board gets it.
+#[derive(Clone)]
+pub struct Fme7 {
+  prg_rom: Vec<u8>,
+  chr: Vec<u8>,
+  chr_is_ram: bool,
+  prg_ram: [u8; 0x2000],
+
+  command: u8,
+  chr_banks: [u8; 8],
+  // command $8
+  low_bank: u8,
+  prg_banks: [u8; 3],
+  mirroring: Mirroring,
+
+  irq_control: u8,
+  irq_counter: u16,
+  irq_pending: bool,
+
+  audio: Sunsoft5b,
+}
+
+impl Fme7 {
+  pub fn new(rom: Rom) -> Self {
+    let (chr, chr_is_ram) = mapper::chr_memory(rom.chr_rom);
+    Fme7 {
+      prg_rom: rom.prg_rom,
+      chr,
+      chr_is_ram,
+      prg_ram: [0; 0x2000],
+      command: 0,
+      chr_banks: [0; 8],
+      low_bank: 0,
+      prg_banks: [0; 3],
+      mirroring: rom.info.mirroring,
+      irq_control: 0,
+      irq_counter: 0,
+      irq_pending: false,
+      audio: Sunsoft5b::default(),
+    }
+  }
+
+  fn ram_selected(&self) -> bool {
+    self.low_bank & 0x40 != 0
+  }
+
+  fn ram_enabled(&self) -> bool {
+    self.ram_selected() && self.low_bank & 0x80 != 0
+  }
+
+  fn prg_offset(&self, addr: u16) -> usize {
+    let banks = self.prg_rom.len() / PRG_BANK_SIZE;
+    let bank = match addr {
+      0x6000..=0x7FFF => self.low_bank & 0x3F,
+      0x8000..=0x9FFF => self.prg_banks[0],
+      0xA000..=0xBFFF => self.prg_banks[1],
+      0xC000..=0xDFFF => self.prg_banks[2],
+      _ => (banks - 1) as u8,
+    } as usize;
+    (bank % banks) * PRG_BANK_SIZE + addr as usize % PRG_BANK_SIZE
+  }
+
+  fn chr_offset(&self, addr: u16) -> usize {
+    let banks = self.chr.len() / CHR_BANK_SIZE;
+    let bank = self.chr_banks[(addr as usize & 0x1FFF) / CHR_BANK_SIZE] as usize;
+    (bank % banks) * CHR_BANK_SIZE + addr as usize % CHR_BANK_SIZE
+  }
+
+  fn run_command(&mut self, data: u8) {
+    match self.command {
+      0x0..=0x7 => self.chr_banks[self.command as usize] = data,
+      0x8 => self.low_bank = data,
+      0x9..=0xB => self.prg_banks[self.command as usize - 9] = data & 0x3F,
+      0xC => {
+        self.mirroring = match data & 0b11 {
+          0 => Mirroring::Vertical,
+          1 => Mirroring::Horizontal,
+          2 => Mirroring::SingleScreenLower,
+          _ => Mirroring::SingleScreenUpper,
+        }
+      }
+      0xD => {
+        self.irq_control = data;
+        self.irq_pending = false;
+      }
+      0xE => self.irq_counter = (self.irq_counter & 0xFF00) | data as u16,
+      _ => self.irq_counter = (self.irq_counter & 0x00FF) | (data as u16) << 8,
+    }
+  }
+}
+
+impl Mapper for Fme7 {
+  fn name(&self) -> &'static str {
+    "FME-7"
+  }
+
+  fn prg_read(&self, addr: u16) -> u8 {
+    match addr {
+      0x6000..=0x7FFF if self.ram_enabled() => self.prg_ram[(addr - 0x6000) as usize],
+      0x6000..=0x7FFF if !self.ram_selected() => self.prg_rom[self.prg_offset(addr)],
+      0x8000..=0xFFFF => self.prg_rom[self.prg_offset(addr)],
+      _ => {
+        trace!("FME-7 has nothing at {:04x}", addr);
+        0
+      }
+    }
+  }
+
+  fn prg_write(&mut self, addr: u16, data: u8) {
+    match addr {
+      0x6000..=0x7FFF if self.ram_enabled() => self.prg_ram[(addr - 0x6000) as usize] = data,
+      0x8000..=0x9FFF => self.command = data & 0x0F,
+      0xA000..=0xBFFF => self.run_command(data),
+      0xC000..=0xDFFF => self.audio.select(data),
+      0xE000..=0xFFFF => self.audio.write(data),
+      _ => trace!("FME-7 ignored write to {:04x}", addr),
+    }
+  }
+
+  fn chr_read(&self, addr: u16) -> u8 {
+    self.chr[self.chr_offset(addr)]
+  }
+
+  fn chr_write(&mut self, addr: u16, data: u8) {
+    if self.chr_is_ram {
+      let offset = self.chr_offset(addr);
+      self.chr[offset] = data;
+    } else {
+      trace!("attempt to write to CHR ROM {:04x}", addr);
+    }
+  }
+
+  fn mirroring(&self) -> Mirroring {
+    self.mirroring
+  }
+
+  fn prg_mapped(&self, addr: u16) -> bool {
+    addr >= 0x8000 || (addr >= 0x6000 && (self.ram_enabled() || !self.ram_selected()))
+  }
+
+  fn prg_bank(&self, addr: u16) -> Option<Bank> {
+    match addr {
+      0x6000..=0x7FFF if !self.ram_selected() => {
+        Some(Bank::at(self.prg_offset(addr), PRG_BANK_SIZE))
+      }
+      0x8000..=0xFFFF => Some(Bank::at(self.prg_offset(addr), PRG_BANK_SIZE)),
+      _ => None,
+    }
+  }
+
+  fn chr_bank(&self, addr: u16) -> Option<Bank> {
+    Some(Bank::at(self.chr_offset(addr), CHR_BANK_SIZE))
+  }
+
+  fn prg_ram(&self) -> Option<&[u8]> {
+    Some(&self.prg_ram)
+  }
+
+  fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+    Some(&mut self.prg_ram)
+  }
+
+  fn irq_pending(&self) -> bool {
+    self.irq_pending
+  }
+
+  fn cpu_cycles(&mut self, cycles: u32) {
+    for _ in 0..cycles {
+      if self.irq_control & 0x80 != 0 {
+        self.irq_counter = self.irq_counter.wrapping_sub(1);
+        if self.irq_counter == 0xFFFF && self.irq_control & 1 != 0 {
+          self.irq_pending = true;
+        }
+      }
+      self.audio.clock();
+    }
+  }
+
+  fn audio_level(&self) -> u32 {
+    self.audio.level()
+  }
+
+  fn save_state(&self, w: &mut StateWriter) {
+    if self.chr_is_ram {
+      w.bytes(&self.chr);
+    }
+    w.bytes(&self.prg_ram);
+    w.u8(self.command);
+    w.bytes(&self.chr_banks);
+    w.u8(self.low_bank);
+    w.bytes(&self.prg_banks);
+    w.u8(match self.mirroring {
+      Mirroring::Horizontal => 1,
+      Mirroring::SingleScreenLower => 2,
+      Mirroring::SingleScreenUpper => 3,
+      _ => 0,
+    });
+    w.u8(self.irq_control);
+    w.u16(self.irq_counter);
+    w.bool(self.irq_pending);
+    self.audio.save_state(w);
+  }
+
+  fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+    if self.chr_is_ram {
+      r.bytes_into(&mut self.chr)?;
+    }
+    r.bytes_into(&mut self.prg_ram)?;
+    self.command = r.u8_below(0x10)?;
+    r.bytes_into(&mut self.chr_banks)?;
+    self.low_bank = r.u8()?;
+    r.bytes_into(&mut self.prg_banks)?;
+    self.mirroring = match r.u8_below(4)? {
+      0 => Mirroring::Vertical,
+      1 => Mirroring::Horizontal,
+      2 => Mirroring::SingleScreenLower,
+      _ => Mirroring::SingleScreenUpper,
+    };
+    self.irq_control = r.u8()?;
+    self.irq_counter = r.u16()?;
+    self.irq_pending = r.bool()?;
+    self.audio.load_state(r)
+  }
+
+  fn box_clone(&self) -> Box<dyn Mapper> {
+    Box::new(self.clone())
+  }
+}
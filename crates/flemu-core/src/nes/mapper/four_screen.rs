@@ -0,0 +1,131 @@
+use crate::nes::cartridge::Mirroring;
+use crate::nes::mapper::{Bank, Mapper, TileFetch, TileRow};
+use crate::nes::nametable::NametableMap;
+use crate::nes::savestate::{StateError, StateReader, StateWriter};
+
+const VRAM_SIZE: usize = 0x800;
+
+/// A board wired for four-screen mirroring (Gauntlet, Rad Racer II): 2KB
+/// of extra VRAM on the cartridge backs nametables 2 and 3, CIRAM keeps 0
+/// and 1, and whatever the board does with mirroring is ignored.
+///
+/// Wraps the board the mapper number asks for, so any of them can come
+/// with it as the header says.
+#[derive(Clone)]
+pub struct FourScreenVram {
+  board: Box<dyn Mapper>,
+  vram: Vec<u8>,
+}
+
+impl FourScreenVram {
+  pub fn new(board: Box<dyn Mapper>) -> Self {
+    FourScreenVram {
+      board,
+      vram: vec![0; VRAM_SIZE],
+    }
+  }
+}
+
+impl Mapper for FourScreenVram {
+  fn name(&self) -> &'static str {
+    self.board.name()
+  }
+
+  fn prg_read(&self, addr: u16) -> u8 {
+    self.board.prg_read(addr)
+  }
+
+  fn prg_read_mut(&mut self, addr: u16) -> u8 {
+    self.board.prg_read_mut(addr)
+  }
+
+  fn prg_write(&mut self, addr: u16, data: u8) {
+    self.board.prg_write(addr, data)
+  }
+
+  fn chr_read(&self, addr: u16) -> u8 {
+    self.board.chr_read(addr)
+  }
+
+  fn chr_write(&mut self, addr: u16, data: u8) {
+    self.board.chr_write(addr, data)
+  }
+
+  fn mirroring(&self) -> Mirroring {
+    Mirroring::FourScreen
+  }
+
+  fn nametables(&self) -> NametableMap {
+    NametableMap::from_mirroring(Mirroring::FourScreen)
+  }
+
+  fn nametable_read(&self, offset: u16) -> u8 {
+    self.vram[offset as usize % VRAM_SIZE]
+  }
+
+  fn nametable_write(&mut self, offset: u16, data: u8) {
+    self.vram[offset as usize % VRAM_SIZE] = data;
+  }
+
+  fn prg_mapped(&self, addr: u16) -> bool {
+    self.board.prg_mapped(addr)
+  }
+
+  fn ignores_back_to_back_writes(&self) -> bool {
+    self.board.ignores_back_to_back_writes()
+  }
+
+  fn prg_bank(&self, addr: u16) -> Option<Bank> {
+    self.board.prg_bank(addr)
+  }
+
+  fn chr_bank(&self, addr: u16) -> Option<Bank> {
+    self.board.chr_bank(addr)
+  }
+
+  fn prg_ram(&self) -> Option<&[u8]> {
+    self.board.prg_ram()
+  }
+
+  fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+    self.board.prg_ram_mut()
+  }
+
+  fn irq_pending(&self) -> bool {
+    self.board.irq_pending()
+  }
+
+  fn ppu_scanline(&mut self, scanline: u16, rendering: bool) {
+    self.board.ppu_scanline(scanline, rendering)
+  }
+
+  fn cpu_cycles(&mut self, cycles: u32) {
+    self.board.cpu_cycles(cycles)
+  }
+
+  fn audio_level(&self) -> u32 {
+    self.board.audio_level()
+  }
+
+  fn background_tile(&self, fetch: &TileFetch, read: &dyn Fn(u16) -> u8) -> TileRow {
+    self.board.background_tile(fetch, read)
+  }
+
+  fn sprite_chr_read(&self, addr: u16, tall: bool) -> u8 {
+    self.board.sprite_chr_read(addr, tall)
+  }
+
+  fn save_state(&self, w: &mut StateWriter) {
+    self.board.save_state(w);
+    w.bytes(&self.vram);
+  }
+
+  fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+    self.board.load_state(r)?;
+    r.bytes_into(&mut self.vram)
+  }
+
+  fn box_clone(&self) -> Box<dyn Mapper> {
+    Box::new(self.clone())
+  }
+}
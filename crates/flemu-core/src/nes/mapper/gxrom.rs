@@ -0,0 +1,118 @@
+use crate::nes::cartridge::{Mirroring, Rom};
+use crate::nes::mapper::{self, Bank, Mapper};
+use crate::nes::savestate::{StateError, StateReader, StateWriter};
+use log::trace;
+
+const PRG_BANK_SIZE: usize = 0x8000;
+const CHR_BANK_SIZE: usize = 0x2000;
+
+/// Mapper 66 (GxROM/MxROM): Super Mario Bros. + Duck Hunt, Dragon Power,
+/// Gumshoe.
+///
+/// Writes to $8000-$FFFF take `..PP..CC`: a 32KB PRG bank and an 8KB
+/// CHR bank.
+#[derive(Clone)]
+pub struct Gxrom {
+  prg_rom: Vec<u8>,
+  chr: Vec<u8>,
+  chr_is_ram: bool,
+  mirroring: Mirroring,
+  register: u8,
+}
+
+impl Gxrom {
+  pub fn new(rom: Rom) -> Self {
+    let (chr, chr_is_ram) = mapper::chr_memory(rom.chr_rom);
+    Gxrom {
+      prg_rom: rom.prg_rom,
+      chr,
+      chr_is_ram,
+      mirroring: rom.info.mirroring,
+      register: 0,
+    }
+  }
+}
+
+impl Gxrom {
+  fn prg_offset(&self, addr: u16) -> usize {
+    let banks = (self.prg_rom.len() / PRG_BANK_SIZE).max(1);
+    let bank = ((self.register >> 4) & 0b11) as usize % banks;
+    (bank * PRG_BANK_SIZE + (addr - 0x8000) as usize) % self.prg_rom.len()
+  }
+
+  fn chr_offset(&self, addr: u16) -> usize {
+    let banks = self.chr.len() / CHR_BANK_SIZE;
+    let bank = (self.register & 0b11) as usize % banks;
+    bank * CHR_BANK_SIZE + (addr & 0x1FFF) as usize
+  }
+}
+
+impl Mapper for Gxrom {
+  fn name(&self) -> &'static str {
+    "GxROM"
+  }
+
+  fn prg_read(&self, addr: u16) -> u8 {
+    match addr {
+      0x8000..=0xFFFF => self.prg_rom[self.prg_offset(addr)],
+      _ => {
+        trace!("GxROM has nothing at {:04x}", addr);
+        0
+      }
+    }
+  }
+
+  fn prg_write(&mut self, addr: u16, data: u8) {
+    match addr {
+      0x8000..=0xFFFF => self.register = data,
+      _ => trace!("GxROM ignored write to {:04x}", addr),
+    }
+  }
+
+  fn chr_read(&self, addr: u16) -> u8 {
+    self.chr[self.chr_offset(addr)]
+  }
+
+  fn chr_write(&mut self, addr: u16, data: u8) {
+    if self.chr_is_ram {
+      let offset = self.chr_offset(addr);
+      self.chr[offset] = data;
+    } else {
+      trace!("attempt to write to CHR ROM {:04x}", addr);
+    }
+  }
+
+  fn prg_bank(&self, addr: u16) -> Option<Bank> {
+    match addr {
+      0x8000..=0xFFFF => Some(Bank::at(self.prg_offset(addr), PRG_BANK_SIZE)),
+      _ => None,
+    }
+  }
+
+  fn chr_bank(&self, addr: u16) -> Option<Bank> {
+    Some(Bank::at(self.chr_offset(addr), CHR_BANK_SIZE))
+  }
+
+  fn mirroring(&self) -> Mirroring {
+    self.mirroring
+  }
+
+  fn save_state(&self, w: &mut StateWriter) {
+    if self.chr_is_ram {
+      w.bytes(&self.chr);
+    }
+    w.u8(self.register);
+  }
+
+  fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+    if self.chr_is_ram {
+      r.bytes_into(&mut self.chr)?;
+    }
+    self.register = r.u8()?;
+    Ok(())
+  }
+
+  fn box_clone(&self) -> Box<dyn Mapper> {
+    Box::new(self.clone())
+  }
+}
@@ -0,0 +1,143 @@
+use crate::nes::cartridge::{Mirroring, Rom};
+use crate::nes::mapper::{self, Bank, Mapper};
+use crate::nes::savestate::{StateError, StateReader, StateWriter};
+use log::trace;
+
+const PRG_BANK_SIZE: usize = 0x1000;
+
+/// Mapper 31 (INL-NSF): homebrew music compilations, and the board NSF
+/// files are loaded onto.
+///
+/// Eight 4KB PRG banks at $8000-$FFFF, each picked by a write to
+/// $5FF8-$5FFF (anywhere in $5000-$5FFF, by the low 3 bits), the NSF
+/// bankswitching scheme. The last register starts at $FF so the reset
+/// vector comes from the end of the ROM. 8KB of PRG RAM at $6000 when the
+/// header asks for it, which NSF tunes count on.
+#[derive(Clone)]
+pub struct InlNsf {
+  prg_rom: Vec<u8>,
+  chr: Vec<u8>,
+  chr_is_ram: bool,
+  prg_ram: Vec<u8>,
+  mirroring: Mirroring,
+  banks: [u8; 8],
+}
+
+impl InlNsf {
+  pub fn new(rom: Rom) -> Self {
+    let (chr, chr_is_ram) = mapper::chr_memory(rom.chr_rom);
+    let ram_size = rom.info.prg_ram_size + rom.info.prg_nvram_size;
+    InlNsf {
+      prg_rom: rom.prg_rom,
+      chr,
+      chr_is_ram,
+      prg_ram: vec![0; if ram_size > 0 { 0x2000 } else { 0 }],
+      mirroring: rom.info.mirroring,
+      banks: [0, 0, 0, 0, 0, 0, 0, 0xFF],
+    }
+  }
+
+  fn bank_at(&self, addr: u16) -> Option<usize> {
+    let banks = (self.prg_rom.len() / PRG_BANK_SIZE).max(1);
+    match addr {
+      0x8000..=0xFFFF => {
+        Some(self.banks[(addr as usize - 0x8000) / PRG_BANK_SIZE] as usize % banks)
+      }
+      _ => None,
+    }
+  }
+}
+
+impl Mapper for InlNsf {
+  fn name(&self) -> &'static str {
+    "INL-NSF"
+  }
+
+  fn prg_read(&self, addr: u16) -> u8 {
+    if (0x6000..=0x7FFF).contains(&addr) && !self.prg_ram.is_empty() {
+      return self.prg_ram[(addr - 0x6000) as usize];
+    }
+    match self.bank_at(addr) {
+      Some(bank) => self
+        .prg_rom
+        .get(bank * PRG_BANK_SIZE + (addr as usize % PRG_BANK_SIZE))
+        .copied()
+        .unwrap_or(0),
+      None => {
+        trace!("INL-NSF has nothing at {:04x}", addr);
+        0
+      }
+    }
+  }
+
+  fn prg_write(&mut self, addr: u16, data: u8) {
+    match addr {
+      0x5000..=0x5FFF => self.banks[(addr & 7) as usize] = data,
+      0x6000..=0x7FFF if !self.prg_ram.is_empty() => self.prg_ram[(addr - 0x6000) as usize] = data,
+      _ => trace!("INL-NSF ignored write to {:04x}", addr),
+    }
+  }
+
+  fn chr_read(&self, addr: u16) -> u8 {
+    self.chr[(addr & 0x1FFF) as usize % self.chr.len()]
+  }
+
+  fn chr_write(&mut self, addr: u16, data: u8) {
+    if self.chr_is_ram {
+      self.chr[(addr & 0x1FFF) as usize] = data;
+    } else {
+      trace!("attempt to write to CHR ROM {:04x}", addr);
+    }
+  }
+
+  fn prg_bank(&self, addr: u16) -> Option<Bank> {
+    self.bank_at(addr).map(|index| Bank {
+      index,
+      size: PRG_BANK_SIZE,
+    })
+  }
+
+  fn chr_bank(&self, _addr: u16) -> Option<Bank> {
+    Some(Bank::at(0, self.chr.len()))
+  }
+
+  fn prg_ram(&self) -> Option<&[u8]> {
+    if self.prg_ram.is_empty() {
+      None
+    } else {
+      Some(&self.prg_ram)
+    }
+  }
+
+  fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+    if self.prg_ram.is_empty() {
+      None
+    } else {
+      Some(&mut self.prg_ram)
+    }
+  }
+
+  fn mirroring(&self) -> Mirroring {
+    self.mirroring
+  }
+
+  fn save_state(&self, w: &mut StateWriter) {
+    if self.chr_is_ram {
+      w.bytes(&self.chr);
+    }
+    w.bytes(&self.prg_ram);
+    w.bytes(&self.banks);
+  }
+
+  fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+    if self.chr_is_ram {
+      r.bytes_into(&mut self.chr)?;
+    }
+    r.bytes_into(&mut self.prg_ram)?;
+    r.bytes_into(&mut self.banks)
+  }
+
+  fn box_clone(&self) -> Box<dyn Mapper> {
+    Box::new(self.clone())
+  }
+}
@@ -0,0 +1,109 @@
+use crate::nes::cartridge::{Mirroring, Rom};
+use crate::nes::mapper::{Bank, Mapper};
+use crate::nes::savestate::{StateError, StateReader, StateWriter};
+use log::trace;
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x2000;
+
+/// Mapper 87 (Jaleco J87 and friends): City Connection, Argus, The
+/// Goonies.
+///
+/// PRG is laid out like NROM. Writes to $6000-$7FFF pick the 8KB CHR bank
+/// with the two low bits swapped: `......LH`.
+#[derive(Clone)]
+pub struct JalecoJ87 {
+  prg_rom: Vec<u8>,
+  chr_rom: Vec<u8>,
+  mirroring: Mirroring,
+  chr_bank: u8,
+}
+
+impl JalecoJ87 {
+  pub fn new(rom: Rom) -> Self {
+    JalecoJ87 {
+      prg_rom: rom.prg_rom,
+      chr_rom: if rom.chr_rom.is_empty() {
+        vec![0; CHR_BANK_SIZE]
+      } else {
+        rom.chr_rom
+      },
+      mirroring: rom.info.mirroring,
+      chr_bank: 0,
+    }
+  }
+}
+
+impl JalecoJ87 {
+  fn chr_offset(&self, addr: u16) -> usize {
+    let banks = self.chr_rom.len() / CHR_BANK_SIZE;
+    let bank = self.chr_bank as usize % banks;
+    bank * CHR_BANK_SIZE + (addr & 0x1FFF) as usize
+  }
+}
+
+impl Mapper for JalecoJ87 {
+  fn name(&self) -> &'static str {
+    "Jaleco J87"
+  }
+
+  fn prg_read(&self, addr: u16) -> u8 {
+    match addr {
+      0x8000..=0xFFFF => {
+        // mirror if needed
+        let addr = (addr - 0x8000) as usize % self.prg_rom.len();
+        self.prg_rom[addr]
+      }
+      _ => {
+        trace!("Jaleco J87 has nothing at {:04x}", addr);
+        0
+      }
+    }
+  }
+
+  fn prg_write(&mut self, addr: u16, data: u8) {
+    match addr {
+      0x6000..=0x7FFF => self.chr_bank = ((data & 0b01) << 1) | ((data & 0b10) >> 1),
+      _ => trace!("Jaleco J87 ignored write to {:04x}", addr),
+    }
+  }
+
+  fn chr_read(&self, addr: u16) -> u8 {
+    self.chr_rom[self.chr_offset(addr)]
+  }
+
+  fn chr_write(&mut self, addr: u16, _data: u8) {
+    trace!("attempt to write to CHR ROM {:04x}", addr);
+  }
+
+  fn prg_bank(&self, addr: u16) -> Option<Bank> {
+    match addr {
+      0x8000..=0xFFFF => Some(Bank::at(
+        (addr - 0x8000) as usize % self.prg_rom.len(),
+        PRG_BANK_SIZE,
+      )),
+      _ => None,
+    }
+  }
+
+  fn chr_bank(&self, addr: u16) -> Option<Bank> {
+    Some(Bank::at(self.chr_offset(addr), CHR_BANK_SIZE))
+  }
+
+  fn mirroring(&self) -> Mirroring {
+    self.mirroring
+  }
+
+  fn save_state(&self, w: &mut StateWriter) {
+    w.u8(self.chr_bank);
+  }
+
+  fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+    self.chr_bank = r.u8()?;
+    Ok(())
+  }
+
+  fn box_clone(&self) -> Box<dyn Mapper> {
+    Box::new(self.clone())
+  }
+}
@@ -0,0 +1,236 @@
+use crate::nes::cartridge::{Mirroring, Rom};
+use crate::nes::mapper::{self, Bank, Mapper};
+use crate::nes::savestate::{StateError, StateReader, StateWriter};
+use log::trace;
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x1000;
+
+/// Mapper 1 (SxROM): Zelda, Metroid, Mega Man 2...
+///
+/// The CPU talks to MMC1 one bit at a time: each write to $8000-$FFFF
+/// shifts bit 0 into a 5-bit shift register, and the fifth write copies it
+/// into the register picked by address bits 13-14:
+///
+///   $8000-$9FFF control    CPPMM: CHR mode, PRG mode, mirroring
+///   $A000-$BFFF CHR bank 0 (4KB, or 8KB with the low bit ignored)
+///   $C000-$DFFF CHR bank 1 (4KB mode only)
+///   $E000-$FFFF PRG bank   RPPPP: PRG RAM disable, 16KB bank
+///
+/// Writing a value with bit 7 set resets the shift register and locks the
+/// last PRG bank at $C000.
+#[derive(Clone)]
+pub struct Mmc1 {
+  prg_rom: Vec<u8>,
+  chr: Vec<u8>,
+  chr_is_ram: bool,
+  prg_ram: [u8; 0x2000],
+
+  shift_register: u8,
+  shift_count: u8,
+
+  control: u8,
+  chr_bank_0: u8,
+  chr_bank_1: u8,
+  prg_bank: u8,
+}
+
+impl Mmc1 {
+  pub fn new(rom: Rom) -> Self {
+    let (chr, chr_is_ram) = mapper::chr_memory(rom.chr_rom);
+    Mmc1 {
+      prg_rom: rom.prg_rom,
+      chr,
+      chr_is_ram,
+      prg_ram: [0; 0x2000],
+      shift_register: 0,
+      shift_count: 0,
+      // power on in PRG mode 3, like most boards rely on
+      control: 0x0C,
+      chr_bank_0: 0,
+      chr_bank_1: 0,
+      prg_bank: 0,
+    }
+  }
+
+  fn write_register(&mut self, addr: u16, value: u8) {
+    match addr {
+      0x8000..=0x9FFF => self.control = value,
+      0xA000..=0xBFFF => self.chr_bank_0 = value,
+      0xC000..=0xDFFF => self.chr_bank_1 = value,
+      _ => self.prg_bank = value,
+    }
+  }
+
+  fn prg_ram_enabled(&self) -> bool {
+    self.prg_bank & 0b1_0000 == 0
+  }
+
+  fn prg_offset(&self, addr: u16) -> usize {
+    let banks = self.prg_rom.len() / PRG_BANK_SIZE;
+    let bank = (self.prg_bank & 0b1111) as usize;
+    let offset = (addr as usize - 0x8000) % PRG_BANK_SIZE;
+    let upper = addr >= 0xC000;
+
+    let bank = match (self.control >> 2) & 0b11 {
+      // 32KB at $8000, low bit of the bank number ignored
+      0 | 1 => (bank & !1) + upper as usize,
+      // first bank fixed at $8000, switch $C000
+      2 => {
+        if upper {
+          bank
+        } else {
+          0
+        }
+      }
+      // switch $8000, last bank fixed at $C000
+      _ => {
+        if upper {
+          banks - 1
+        } else {
+          bank
+        }
+      }
+    };
+    (bank % banks) * PRG_BANK_SIZE + offset
+  }
+
+  fn chr_offset(&self, addr: u16) -> usize {
+    let banks = self.chr.len() / CHR_BANK_SIZE;
+    let addr = (addr & 0x1FFF) as usize;
+    let upper = addr >= CHR_BANK_SIZE;
+
+    let bank = if self.control & 0b1_0000 == 0 {
+      // one 8KB bank, low bit ignored
+      (self.chr_bank_0 & !1) as usize + upper as usize
+    } else if upper {
+      self.chr_bank_1 as usize
+    } else {
+      self.chr_bank_0 as usize
+    };
+    (bank % banks) * CHR_BANK_SIZE + addr % CHR_BANK_SIZE
+  }
+}
+
+impl Mapper for Mmc1 {
+  fn name(&self) -> &'static str {
+    "MMC1"
+  }
+
+  fn prg_read(&self, addr: u16) -> u8 {
+    match addr {
+      0x6000..=0x7FFF if self.prg_ram_enabled() => self.prg_ram[(addr - 0x6000) as usize],
+      0x8000..=0xFFFF => self.prg_rom[self.prg_offset(addr)],
+      _ => {
+        trace!("MMC1 has nothing at {:04x}", addr);
+        0
+      }
+    }
+  }
+
+  fn prg_write(&mut self, addr: u16, data: u8) {
+    match addr {
+      0x6000..=0x7FFF if self.prg_ram_enabled() => {
+        self.prg_ram[(addr - 0x6000) as usize] = data;
+      }
+      0x8000..=0xFFFF => {
+        if data & 0b1000_0000 != 0 {
+          self.shift_register = 0;
+          self.shift_count = 0;
+          self.control |= 0x0C;
+          return;
+        }
+
+        self.shift_register |= (data & 1) << self.shift_count;
+        self.shift_count += 1;
+        if self.shift_count == 5 {
+          let value = self.shift_register;
+          self.write_register(addr, value);
+          self.shift_register = 0;
+          self.shift_count = 0;
+        }
+      }
+      _ => trace!("MMC1 ignored write to {:04x}", addr),
+    }
+  }
+
+  fn chr_read(&self, addr: u16) -> u8 {
+    self.chr[self.chr_offset(addr)]
+  }
+
+  fn chr_write(&mut self, addr: u16, data: u8) {
+    if self.chr_is_ram {
+      let offset = self.chr_offset(addr);
+      self.chr[offset] = data;
+    } else {
+      trace!("attempt to write to CHR ROM {:04x}", addr);
+    }
+  }
+
+  fn prg_bank(&self, addr: u16) -> Option<Bank> {
+    match addr {
+      0x8000..=0xFFFF => Some(Bank::at(self.prg_offset(addr), PRG_BANK_SIZE)),
+      _ => None,
+    }
+  }
+
+  fn chr_bank(&self, addr: u16) -> Option<Bank> {
+    Some(Bank::at(self.chr_offset(addr), CHR_BANK_SIZE))
+  }
+
+  fn ignores_back_to_back_writes(&self) -> bool {
+    true
+  }
+
+  fn prg_mapped(&self, addr: u16) -> bool {
+    addr >= 0x8000 || (addr >= 0x6000 && self.prg_ram_enabled())
+  }
+
+  fn prg_ram(&self) -> Option<&[u8]> {
+    Some(&self.prg_ram)
+  }
+
+  fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+    Some(&mut self.prg_ram)
+  }
+
+  fn mirroring(&self) -> Mirroring {
+    match self.control & 0b11 {
+      0 => Mirroring::SingleScreenLower,
+      1 => Mirroring::SingleScreenUpper,
+      2 => Mirroring::Vertical,
+      _ => Mirroring::Horizontal,
+    }
+  }
+
+  fn save_state(&self, w: &mut StateWriter) {
+    if self.chr_is_ram {
+      w.bytes(&self.chr);
+    }
+    w.bytes(&self.prg_ram);
+    w.u8(self.shift_register);
+    w.u8(self.shift_count);
+    w.u8(self.control);
+    w.u8(self.chr_bank_0);
+    w.u8(self.chr_bank_1);
+    w.u8(self.prg_bank);
+  }
+
+  fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+    if self.chr_is_ram {
+      r.bytes_into(&mut self.chr)?;
+    }
+    r.bytes_into(&mut self.prg_ram)?;
+    self.shift_register = r.u8_below(0x20)?;
+    self.shift_count = r.u8_below(5)?;
+    self.control = r.u8_below(0x20)?;
+    self.chr_bank_0 = r.u8_below(0x20)?;
+    self.chr_bank_1 = r.u8_below(0x20)?;
+    self.prg_bank = r.u8_below(0x20)?;
+    Ok(())
+  }
+
+  fn box_clone(&self) -> Box<dyn Mapper> {
+    Box::new(self.clone())
+  }
+}
@@ -0,0 +1,296 @@
+use crate::nes::cartridge::{Mirroring, Rom};
+use crate::nes::mapper::{self, Bank, Mapper};
+use crate::nes::savestate::{StateError, StateReader, StateWriter};
+use log::trace;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x400;
+
+/// How the scanline counter decides to fire, which changed between chip
+/// revisions. A few games only work with one of them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mmc3Irq {
+  /// MMC3B/MMC3C (Sharp), what almost every board has: fires whenever the
+  /// counter is 0 after a clock, so a latch of 0 fires on every line.
+  New,
+  /// MMC3A (NEC) and Acclaim's MC-ACC, NES 2.0 submappers 4 and 3: fires
+  /// only when the counter counts down to 0 or is reloaded through $C001,
+  /// not when it reloads 0 by itself.
+  Old,
+}
+
+impl Mmc3Irq {
+  pub fn for_submapper(submapper: u8) -> Self {
+    match submapper {
+      3 | 4 => Mmc3Irq::Old,
+      _ => Mmc3Irq::New,
+    }
+  }
+
+  /// The NES 2.0 submapper that picks this revision on a plain MMC3.
+  pub fn submapper(&self) -> u8 {
+    match self {
+      Mmc3Irq::New => 0,
+      Mmc3Irq::Old => 4,
+    }
+  }
+}
+
+/// Mapper 4 (MMC3, TxROM): Super Mario Bros. 3, Kirby's Adventure, Mega
+/// Man 3-6...
+///
+/// Registers by address range and parity:
+///
+///   $8000 even  bank select: CPxx.RRR, CHR A12 inversion, PRG mode,
+///               register for the next $8001 write
+///   $8001 odd   bank data: R0/R1 2KB and R2-R5 1KB CHR, R6/R7 8KB PRG
+///   $A000 even  mirroring: vertical (0) or horizontal (1)
+///   $A001 odd   PRG RAM: enable (bit 7), write protect (bit 6)
+///   $C000 even  IRQ latch
+///   $C001 odd   IRQ reload: the counter takes the latch on its next clock
+///   $E000 even  IRQ disable, which also acknowledges
+///   $E001 odd   IRQ enable
+///
+/// The second-last 8KB PRG bank is fixed at $C000 (or $8000 in PRG mode
+/// 1), the last at $E000. The real counter clocks on rises of PPU A12;
+/// here it clocks once at the end of every rendered line and the pre-render
+/// line, which is what the usual background at $0000 and sprites at $1000
+/// come to. MMC6's 1KB of PRG RAM isn't emulated.
+#[derive(Clone)]
+pub struct Mmc3 {
+  prg_rom: Vec<u8>,
+  chr: Vec<u8>,
+  chr_is_ram: bool,
+  prg_ram: [u8; 0x2000],
+  irq_variant: Mmc3Irq,
+
+  bank_select: u8,
+  // R0-R7
+  banks: [u8; 8],
+  mirroring: Mirroring,
+  ram_protect: u8,
+
+  irq_latch: u8,
+  irq_counter: u8,
+  irq_reload: bool,
+  irq_enabled: bool,
+  irq_pending: bool,
+}
+
+impl Mmc3 {
+  pub fn new(rom: Rom) -> Self {
+    let (chr, chr_is_ram) = mapper::chr_memory(rom.chr_rom);
+    Mmc3 {
+      prg_rom: rom.prg_rom,
+      chr,
+      chr_is_ram,
+      prg_ram: [0; 0x2000],
+      irq_variant: Mmc3Irq::for_submapper(rom.info.submapper),
+      bank_select: 0,
+      banks: [0, 2, 4, 5, 6, 7, 0, 1],
+      mirroring: rom.info.mirroring,
+      // enabled and writable, as games that never touch $A001 expect
+      ram_protect: 0x80,
+      irq_latch: 0,
+      irq_counter: 0,
+      irq_reload: false,
+      irq_enabled: false,
+      irq_pending: false,
+    }
+  }
+
+  pub fn irq_variant(&self) -> Mmc3Irq {
+    self.irq_variant
+  }
+
+  fn prg_ram_enabled(&self) -> bool {
+    self.ram_protect & 0x80 != 0
+  }
+
+  fn prg_ram_writable(&self) -> bool {
+    self.prg_ram_enabled() && self.ram_protect & 0x40 == 0
+  }
+
+  fn prg_offset(&self, addr: u16) -> usize {
+    let banks = self.prg_rom.len() / PRG_BANK_SIZE;
+    let slot = (addr as usize - 0x8000) / PRG_BANK_SIZE;
+    let swapped = self.bank_select & 0x40 != 0;
+    let bank = match (slot, swapped) {
+      (0, false) | (2, true) => self.banks[6] as usize,
+      (1, _) => self.banks[7] as usize,
+      (0, true) | (2, false) => banks - 2,
+      _ => banks - 1,
+    };
+    (bank % banks) * PRG_BANK_SIZE + addr as usize % PRG_BANK_SIZE
+  }
+
+  fn chr_offset(&self, addr: u16) -> usize {
+    let banks = self.chr.len() / CHR_BANK_SIZE;
+    let mut addr = (addr & 0x1FFF) as usize;
+    if self.bank_select & 0x80 != 0 {
+      addr ^= 0x1000;
+    }
+    let slot = addr / CHR_BANK_SIZE;
+    let bank = match slot {
+      // R0 and R1 are 2KB, the low bit ignored
+      0..=3 => (self.banks[slot / 2] & !1) as usize + slot % 2,
+      _ => self.banks[slot - 2] as usize,
+    };
+    (bank % banks) * CHR_BANK_SIZE + addr % CHR_BANK_SIZE
+  }
+
+  fn clock_irq_counter(&mut self) {
+    let before = self.irq_counter;
+    let reloaded = self.irq_reload;
+    if self.irq_counter == 0 || self.irq_reload {
+      self.irq_counter = self.irq_latch;
+    } else {
+      self.irq_counter -= 1;
+    }
+    self.irq_reload = false;
+
+    let fires = match self.irq_variant {
+      Mmc3Irq::New => self.irq_counter == 0,
+      Mmc3Irq::Old => self.irq_counter == 0 && (before > 0 || reloaded),
+    };
+    if fires && self.irq_enabled {
+      self.irq_pending = true;
+    }
+  }
+}
+
+impl Mapper for Mmc3 {
+  fn name(&self) -> &'static str {
+    "MMC3"
+  }
+
+  fn prg_read(&self, addr: u16) -> u8 {
+    match addr {
+      0x6000..=0x7FFF if self.prg_ram_enabled() => self.prg_ram[(addr - 0x6000) as usize],
+      0x8000..=0xFFFF => self.prg_rom[self.prg_offset(addr)],
+      _ => {
+        trace!("MMC3 has nothing at {:04x}", addr);
+        0
+      }
+    }
+  }
+
+  fn prg_write(&mut self, addr: u16, data: u8) {
+    match (addr, addr & 1) {
+      (0x6000..=0x7FFF, _) if self.prg_ram_writable() => {
+        self.prg_ram[(addr - 0x6000) as usize] = data;
+      }
+      (0x8000..=0x9FFF, 0) => self.bank_select = data,
+      (0x8000..=0x9FFF, _) => self.banks[(self.bank_select & 0b111) as usize] = data,
+      (0xA000..=0xBFFF, 0) => {
+        self.mirroring = match data & 1 {
+          0 => Mirroring::Vertical,
+          _ => Mirroring::Horizontal,
+        }
+      }
+      (0xA000..=0xBFFF, _) => self.ram_protect = data,
+      (0xC000..=0xDFFF, 0) => self.irq_latch = data,
+      (0xC000..=0xDFFF, _) => {
+        self.irq_counter = 0;
+        self.irq_reload = true;
+      }
+      (0xE000..=0xFFFF, 0) => {
+        self.irq_enabled = false;
+        self.irq_pending = false;
+      }
+      (0xE000..=0xFFFF, _) => self.irq_enabled = true,
+      _ => trace!("MMC3 ignored write to {:04x}", addr),
+    }
+  }
+
+  fn chr_read(&self, addr: u16) -> u8 {
+    self.chr[self.chr_offset(addr)]
+  }
+
+  fn chr_write(&mut self, addr: u16, data: u8) {
+    if self.chr_is_ram {
+      let offset = self.chr_offset(addr);
+      self.chr[offset] = data;
+    } else {
+      trace!("attempt to write to CHR ROM {:04x}", addr);
+    }
+  }
+
+  fn mirroring(&self) -> Mirroring {
+    self.mirroring
+  }
+
+  fn prg_mapped(&self, addr: u16) -> bool {
+    addr >= 0x8000 || (addr >= 0x6000 && self.prg_ram_enabled())
+  }
+
+  fn prg_bank(&self, addr: u16) -> Option<Bank> {
+    match addr {
+      0x8000..=0xFFFF => Some(Bank::at(self.prg_offset(addr), PRG_BANK_SIZE)),
+      _ => None,
+    }
+  }
+
+  fn chr_bank(&self, addr: u16) -> Option<Bank> {
+    Some(Bank::at(self.chr_offset(addr), CHR_BANK_SIZE))
+  }
+
+  fn prg_ram(&self) -> Option<&[u8]> {
+    Some(&self.prg_ram)
+  }
+
+  fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+    Some(&mut self.prg_ram)
+  }
+
+  fn irq_pending(&self) -> bool {
+    self.irq_pending
+  }
+
+  fn ppu_scanline(&mut self, scanline: u16, rendering: bool) {
+    // a visible line (or, for 0, the pre-render line) just ended
+    if rendering && scanline <= 240 {
+      self.clock_irq_counter();
+    }
+  }
+
+  fn save_state(&self, w: &mut StateWriter) {
+    if self.chr_is_ram {
+      w.bytes(&self.chr);
+    }
+    w.bytes(&self.prg_ram);
+    w.u8(self.bank_select);
+    w.bytes(&self.banks);
+    w.bool(self.mirroring == Mirroring::Horizontal);
+    w.u8(self.ram_protect);
+    w.u8(self.irq_latch);
+    w.u8(self.irq_counter);
+    w.bool(self.irq_reload);
+    w.bool(self.irq_enabled);
+    w.bool(self.irq_pending);
+  }
+
+  fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+    if self.chr_is_ram {
+      r.bytes_into(&mut self.chr)?;
+    }
+    r.bytes_into(&mut self.prg_ram)?;
+    self.bank_select = r.u8()?;
+    r.bytes_into(&mut self.banks)?;
+    self.mirroring = match r.bool()? {
+      true => Mirroring::Horizontal,
+      false => Mirroring::Vertical,
+    };
+    self.ram_protect = r.u8()?;
+    self.irq_latch = r.u8()?;
+    self.irq_counter = r.u8()?;
+    self.irq_reload = r.bool()?;
+    self.irq_enabled = r.bool()?;
+    self.irq_pending = r.bool()?;
+    Ok(())
+  }
+
+  fn box_clone(&self) -> Box<dyn Mapper> {
+    Box::new(self.clone())
+  }
+}
@@ -0,0 +1,526 @@
+use crate::nes::cartridge::{HeaderFormat, Mirroring, Rom};
+use crate::nes::mapper::{self, Bank, Mapper, TileFetch, TileRow};
+use crate::nes::nametable::{NametableMap, NametablePage};
+use crate::nes::savestate::{StateError, StateReader, StateWriter};
+use log::trace;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x400;
+const EXRAM_SIZE: usize = 0x400;
+
+/// Mapper 5 (MMC5, ExROM): Castlevania III, Just Breed, Uchuu Keibitai...
+///
+/// Everything is a register in $5000-$5FFF:
+///
+///   $5100       PRG mode: 32KB, 16+16KB, 16+8+8KB or four 8KB banks
+///   $5101       CHR mode: 8KB, 4KB, 2KB or 1KB banks
+///   $5102/3     PRG RAM write protect, writable only with 2 and 1
+///   $5104       ExRAM mode: nametable, extended attributes, RAM, ROM
+///   $5105       nametable mapping, 2 bits each: CIRAM A, CIRAM B, ExRAM, fill
+///   $5106/7     fill mode tile and attribute
+///   $5113-$5117 PRG banks for $6000, $8000, $A000, $C000, $E000, bit 7
+///               picking ROM over RAM ($5117 is always ROM)
+///   $5120-$512B CHR banks, two sets: A ($5120-$5127) for sprites and
+///               B ($5128-$512B) for the background in 8x16 sprite mode,
+///               otherwise whichever was written last for everything
+///   $5130       upper CHR bank bits, latched into the next CHR bank write
+///   $5200-$5202 vertical split: side and column, Y scroll, 4KB CHR bank
+///   $5203/4     scanline IRQ: compare value, enable and status
+///   $5205/6     8x8 multiplier, the 16-bit product read back from both
+///   $5C00-$5FFF ExRAM
+///
+/// The scanline counter watches the PPU render, so it counts lines only
+/// while rendering is on. In extended attribute mode every background
+/// tile picks its own palette and 4KB CHR bank from the ExRAM byte at its
+/// nametable position. The expansion audio ($5000-$5015) isn't emulated.
+#[derive(Clone)]
+pub struct Mmc5 {
+  prg_rom: Vec<u8>,
+  prg_ram: Vec<u8>,
+  chr: Vec<u8>,
+  chr_is_ram: bool,
+  exram: [u8; EXRAM_SIZE],
+
+  prg_mode: u8,
+  chr_mode: u8,
+  ram_protect: [u8; 2],
+  exram_mode: u8,
+  nametable_mapping: u8,
+  fill_tile: u8,
+  fill_attribute: u8,
+  // $5113-$5117
+  prg_banks: [u8; 5],
+  // set A then set B, with the upper bits from $5130
+  chr_banks: [u16; 12],
+  chr_upper: u8,
+  // which set the CPU and, without 8x16 sprites, the PPU sees
+  last_chr_set_b: bool,
+
+  split_control: u8,
+  split_scroll: u8,
+  split_bank: u8,
+
+  irq_compare: u8,
+  irq_enabled: bool,
+  irq_pending: bool,
+  in_frame: bool,
+  scanline_counter: u8,
+
+  multiplicand: u8,
+  multiplier: u8,
+}
+
+enum PrgTarget {
+  Rom(usize),
+  Ram(usize),
+}
+
+impl Mmc5 {
+  pub fn new(rom: Rom) -> Self {
+    let (chr, chr_is_ram) = mapper::chr_memory(rom.chr_rom);
+    // iNES headers can't say, so the most any board has
+    let prg_ram_size = match rom.info.format {
+      HeaderFormat::INes => 0x10000,
+      HeaderFormat::Nes2 => rom.info.prg_ram_size + rom.info.prg_nvram_size,
+    };
+    Mmc5 {
+      prg_rom: rom.prg_rom,
+      prg_ram: vec![0; prg_ram_size],
+      chr,
+      chr_is_ram,
+      exram: [0; EXRAM_SIZE],
+      // power on with four 8KB banks and the last one at $E000
+      prg_mode: 3,
+      chr_mode: 0,
+      ram_protect: [0; 2],
+      exram_mode: 0,
+      nametable_mapping: 0,
+      fill_tile: 0,
+      fill_attribute: 0,
+      prg_banks: [0, 0, 0, 0, 0xFF],
+      chr_banks: [0; 12],
+      chr_upper: 0,
+      last_chr_set_b: false,
+      split_control: 0,
+      split_scroll: 0,
+      split_bank: 0,
+      irq_compare: 0,
+      irq_enabled: false,
+      irq_pending: false,
+      in_frame: false,
+      scanline_counter: 0,
+      multiplicand: 0xFF,
+      multiplier: 0xFF,
+    }
+  }
+
+  fn prg_ram_writable(&self) -> bool {
+    self.ram_protect[0] & 0b11 == 0b10 && self.ram_protect[1] & 0b11 == 0b01
+  }
+
+  fn prg_ram_offset(&self, bank: usize, addr: u16) -> usize {
+    let banks = self.prg_ram.len() / PRG_BANK_SIZE;
+    (bank % banks) * PRG_BANK_SIZE + addr as usize % PRG_BANK_SIZE
+  }
+
+  fn prg_target(&self, addr: u16) -> Option<PrgTarget> {
+    if addr < 0x8000 {
+      if self.prg_ram.is_empty() {
+        return None;
+      }
+      let bank = (self.prg_banks[0] & 0x07) as usize;
+      return Some(PrgTarget::Ram(self.prg_ram_offset(bank, addr)));
+    }
+    let slot = ((addr - 0x8000) as usize) / PRG_BANK_SIZE;
+    // the register and how many 8KB banks it switches at once
+    let (register, size) = match (self.prg_mode, slot) {
+      (0, _) => (4, 4),
+      (1, 0..=1) => (2, 2),
+      (1, _) => (4, 2),
+      (2, 0..=1) => (2, 2),
+      (2, 2) => (3, 1),
+      (2, _) => (4, 1),
+      (_, slot) => (slot + 1, 1),
+    };
+    let value = self.prg_banks[register];
+    let bank = (value & 0x7F) as usize & !(size - 1);
+    let bank = bank + slot % size;
+    if value & 0x80 != 0 || register == 4 {
+      let banks = self.prg_rom.len() / PRG_BANK_SIZE;
+      let offset = addr as usize % PRG_BANK_SIZE;
+      Some(PrgTarget::Rom((bank % banks) * PRG_BANK_SIZE + offset))
+    } else if self.prg_ram.is_empty() {
+      None
+    } else {
+      Some(PrgTarget::Ram(self.prg_ram_offset(bank & 0x07, addr)))
+    }
+  }
+
+  // set A's register for a 1KB slot and its bank size, in 1KB banks
+  fn chr_register(&self, slot: usize) -> (usize, usize) {
+    match self.chr_mode {
+      0 => (7, 8),
+      1 => (slot / 4 * 4 + 3, 4),
+      2 => (slot / 2 * 2 + 1, 2),
+      _ => (slot, 1),
+    }
+  }
+
+  fn chr_offset(&self, addr: u16, set_b: bool) -> usize {
+    let addr = (addr & 0x1FFF) as usize;
+    let slot = addr / CHR_BANK_SIZE;
+    let (register, size) = if set_b {
+      // set B is the upper half of set A's layout, in both pattern tables
+      let (register, size) = self.chr_register(slot & 0b11 | 0b100);
+      (register + 4, size)
+    } else {
+      self.chr_register(slot)
+    };
+    let bank_size = size * CHR_BANK_SIZE;
+    let banks = (self.chr.len() / bank_size).max(1);
+    // an 8KB set B bank is the same 4KB twice
+    let offset = addr % bank_size.min(if set_b { 0x1000 } else { 0x2000 });
+    (self.chr_banks[register] as usize % banks) * bank_size + offset
+  }
+
+  fn ppu_chr_read(&self, addr: u16, tall_sprites: bool, sprite: bool) -> u8 {
+    let set_b = if tall_sprites {
+      !sprite
+    } else {
+      self.last_chr_set_b
+    };
+    self.chr[self.chr_offset(addr, set_b)]
+  }
+
+  // a 4KB bank of CHR, for extended attributes and the split
+  fn chr_4k(&self, bank: usize, addr: u16) -> u8 {
+    let banks = (self.chr.len() / 0x1000).max(1);
+    self.chr[(bank % banks) * 0x1000 + (addr & 0x0FFF) as usize]
+  }
+
+  fn in_split(&self, column: u8) -> bool {
+    if self.split_control & 0x80 == 0 || self.exram_mode > 1 {
+      return false;
+    }
+    let threshold = self.split_control & 0x1F;
+    if self.split_control & 0x40 == 0 {
+      column < threshold
+    } else {
+      column >= threshold
+    }
+  }
+
+  fn split_tile(&self, fetch: &TileFetch) -> TileRow {
+    let mut y = fetch.scanline + self.split_scroll as u16;
+    if self.split_scroll < 240 {
+      y %= 240;
+    }
+    let (coarse_y, fine_y) = ((y / 8) as usize % 32, y % 8);
+    let column = (fetch.column % 32) as usize;
+    let tile = self.exram[coarse_y * 32 + column] as u16;
+    let attribute = self.exram[0x3C0 + coarse_y / 4 * 8 + column / 4];
+    let shift = (coarse_y & 0b10) << 1 | (column & 0b10);
+    let addr = tile * 16 + fine_y;
+    let bank = self.split_bank as usize;
+    TileRow {
+      lo: self.chr_4k(bank, addr),
+      hi: self.chr_4k(bank, addr + 8),
+      palette: (attribute >> shift) & 0b11,
+    }
+  }
+
+  fn write_register(&mut self, addr: u16, data: u8) {
+    match addr {
+      0x5100 => self.prg_mode = data & 0b11,
+      0x5101 => self.chr_mode = data & 0b11,
+      0x5102 | 0x5103 => self.ram_protect[(addr - 0x5102) as usize] = data,
+      0x5104 => self.exram_mode = data & 0b11,
+      0x5105 => self.nametable_mapping = data,
+      0x5106 => self.fill_tile = data,
+      0x5107 => self.fill_attribute = data & 0b11,
+      0x5113..=0x5117 => self.prg_banks[(addr - 0x5113) as usize] = data,
+      0x5120..=0x512B => {
+        let register = (addr - 0x5120) as usize;
+        self.chr_banks[register] = data as u16 | (self.chr_upper as u16) << 8;
+        self.last_chr_set_b = register >= 8;
+      }
+      0x5130 => self.chr_upper = data & 0b11,
+      0x5200 => self.split_control = data,
+      0x5201 => self.split_scroll = data,
+      0x5202 => self.split_bank = data,
+      0x5203 => self.irq_compare = data,
+      0x5204 => self.irq_enabled = data & 0x80 != 0,
+      0x5205 => self.multiplicand = data,
+      0x5206 => self.multiplier = data,
+      0x5C00..=0x5FFF => {
+        let offset = (addr - 0x5C00) as usize;
+        match self.exram_mode {
+          // the PPU has it while rendering, other writes come out as 0
+          0 | 1 => self.exram[offset] = if self.in_frame { data } else { 0 },
+          2 => self.exram[offset] = data,
+          _ => trace!("MMC5 ExRAM is read-only, dropped {:02x}", data),
+        }
+      }
+      _ => trace!("MMC5 ignored write to {:04x}", addr),
+    }
+  }
+
+  fn product(&self) -> u16 {
+    self.multiplicand as u16 * self.multiplier as u16
+  }
+
+  fn irq_status(&self) -> u8 {
+    (self.irq_pending as u8) << 7 | (self.in_frame as u8) << 6
+  }
+}
+
+impl Mapper for Mmc5 {
+  fn name(&self) -> &'static str {
+    "MMC5"
+  }
+
+  fn prg_read(&self, addr: u16) -> u8 {
+    match addr {
+      0x5204 => self.irq_status(),
+      0x5205 => self.product() as u8,
+      0x5206 => (self.product() >> 8) as u8,
+      0x5C00..=0x5FFF if self.exram_mode >= 2 => self.exram[(addr - 0x5C00) as usize],
+      0x6000..=0xFFFF => match self.prg_target(addr) {
+        Some(PrgTarget::Rom(offset)) => self.prg_rom[offset],
+        Some(PrgTarget::Ram(offset)) => self.prg_ram[offset],
+        None => 0,
+      },
+      _ => {
+        trace!("MMC5 has nothing at {:04x}", addr);
+        0
+      }
+    }
+  }
+
+  fn prg_read_mut(&mut self, addr: u16) -> u8 {
+    let data = self.prg_read(addr);
+    if addr == 0x5204 {
+      self.irq_pending = false;
+    }
+    data
+  }
+
+  fn prg_write(&mut self, addr: u16, data: u8) {
+    match addr {
+      0x5000..=0x5FFF => self.write_register(addr, data),
+      0x6000..=0xDFFF if self.prg_ram_writable() => {
+        if let Some(PrgTarget::Ram(offset)) = self.prg_target(addr) {
+          self.prg_ram[offset] = data;
+        }
+      }
+      _ => trace!("MMC5 ignored write to {:04x}", addr),
+    }
+  }
+
+  fn prg_mapped(&self, addr: u16) -> bool {
+    match addr {
+      0x5204..=0x5206 => true,
+      0x5C00..=0x5FFF => self.exram_mode >= 2,
+      0x6000..=0xFFFF => self.prg_target(addr).is_some(),
+      _ => false,
+    }
+  }
+
+  fn chr_read(&self, addr: u16) -> u8 {
+    self.chr[self.chr_offset(addr, self.last_chr_set_b)]
+  }
+
+  fn chr_write(&mut self, addr: u16, data: u8) {
+    if self.chr_is_ram {
+      let offset = self.chr_offset(addr, self.last_chr_set_b);
+      self.chr[offset] = data;
+    } else {
+      trace!("attempt to write to CHR ROM {:04x}", addr);
+    }
+  }
+
+  fn mirroring(&self) -> Mirroring {
+    match self.nametable_mapping {
+      0x00 => Mirroring::SingleScreenLower,
+      0x55 => Mirroring::SingleScreenUpper,
+      0x50 => Mirroring::Horizontal,
+      _ => Mirroring::Vertical,
+    }
+  }
+
+  fn nametables(&self) -> NametableMap {
+    let mut pages = [NametablePage::Ciram(0); 4];
+    for (table, page) in pages.iter_mut().enumerate() {
+      *page = match (self.nametable_mapping >> (table * 2)) & 0b11 {
+        0 => NametablePage::Ciram(0),
+        1 => NametablePage::Ciram(1),
+        2 => NametablePage::Cartridge(0),
+        _ => NametablePage::Cartridge(1),
+      };
+    }
+    NametableMap { pages }
+  }
+
+  fn nametable_read(&self, offset: u16) -> u8 {
+    match offset {
+      // ExRAM, a nametable only in the first two modes
+      0x000..=0x3FF if self.exram_mode <= 1 => self.exram[offset as usize],
+      0x000..=0x3FF => 0,
+      0x400..=0x7BF => self.fill_tile,
+      _ => self.fill_attribute * 0x55,
+    }
+  }
+
+  fn nametable_write(&mut self, offset: u16, data: u8) {
+    match offset {
+      0x000..=0x3FF if self.exram_mode <= 2 => self.exram[offset as usize] = data,
+      _ => trace!("MMC5 dropped nametable write to {:04x}", offset),
+    }
+  }
+
+  fn background_tile(&self, fetch: &TileFetch, read: &dyn Fn(u16) -> u8) -> TileRow {
+    if self.in_split(fetch.column) {
+      return self.split_tile(fetch);
+    }
+    if self.exram_mode == 1 {
+      let v = fetch.v;
+      let tile = read(0x2000 | (v & 0x0FFF)) as u16;
+      let ex = self.exram[(v & 0x03FF) as usize];
+      let bank = (ex & 0x3F) as usize | (self.chr_upper as usize) << 6;
+      let addr = tile * 16 + ((v >> 12) & 0b111);
+      return TileRow {
+        lo: self.chr_4k(bank, addr),
+        hi: self.chr_4k(bank, addr + 8),
+        palette: ex >> 6,
+      };
+    }
+    let fetch_chr = |addr: u16| {
+      if addr < 0x2000 {
+        self.ppu_chr_read(addr, fetch.tall_sprites, false)
+      } else {
+        read(addr)
+      }
+    };
+    mapper::fetch_tile(fetch, &fetch_chr)
+  }
+
+  fn sprite_chr_read(&self, addr: u16, tall: bool) -> u8 {
+    self.ppu_chr_read(addr, tall, true)
+  }
+
+  fn ppu_scanline(&mut self, scanline: u16, rendering: bool) {
+    if !rendering || scanline >= 240 {
+      self.in_frame = false;
+    } else if !self.in_frame {
+      self.in_frame = true;
+      self.scanline_counter = 0;
+      self.irq_pending = false;
+    } else {
+      self.scanline_counter = self.scanline_counter.wrapping_add(1);
+      if self.scanline_counter == self.irq_compare {
+        self.irq_pending = true;
+      }
+    }
+  }
+
+  fn irq_pending(&self) -> bool {
+    self.irq_pending && self.irq_enabled
+  }
+
+  fn prg_bank(&self, addr: u16) -> Option<Bank> {
+    match self.prg_target(addr) {
+      Some(PrgTarget::Rom(offset)) if addr >= 0x8000 => Some(Bank::at(offset, PRG_BANK_SIZE)),
+      _ => None,
+    }
+  }
+
+  fn chr_bank(&self, addr: u16) -> Option<Bank> {
+    Some(Bank::at(
+      self.chr_offset(addr, self.last_chr_set_b),
+      CHR_BANK_SIZE,
+    ))
+  }
+
+  fn prg_ram(&self) -> Option<&[u8]> {
+    if self.prg_ram.is_empty() {
+      None
+    } else {
+      Some(&self.prg_ram)
+    }
+  }
+
+  fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+    if self.prg_ram.is_empty() {
+      None
+    } else {
+      Some(&mut self.prg_ram)
+    }
+  }
+
+  fn save_state(&self, w: &mut StateWriter) {
+    if self.chr_is_ram {
+      w.bytes(&self.chr);
+    }
+    w.bytes(&self.prg_ram);
+    w.bytes(&self.exram);
+    w.u8(self.prg_mode);
+    w.u8(self.chr_mode);
+    w.bytes(&self.ram_protect);
+    w.u8(self.exram_mode);
+    w.u8(self.nametable_mapping);
+    w.u8(self.fill_tile);
+    w.u8(self.fill_attribute);
+    w.bytes(&self.prg_banks);
+    for &bank in &self.chr_banks {
+      w.u16(bank);
+    }
+    w.u8(self.chr_upper);
+    w.bool(self.last_chr_set_b);
+    w.u8(self.split_control);
+    w.u8(self.split_scroll);
+    w.u8(self.split_bank);
+    w.u8(self.irq_compare);
+    w.bool(self.irq_enabled);
+    w.bool(self.irq_pending);
+    w.bool(self.in_frame);
+    w.u8(self.scanline_counter);
+    w.u8(self.multiplicand);
+    w.u8(self.multiplier);
+  }
+
+  fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+    if self.chr_is_ram {
+      r.bytes_into(&mut self.chr)?;
+    }
+    r.bytes_into(&mut self.prg_ram)?;
+    r.bytes_into(&mut self.exram)?;
+    self.prg_mode = r.u8_below(4)?;
+    self.chr_mode = r.u8_below(4)?;
+    r.bytes_into(&mut self.ram_protect)?;
+    self.exram_mode = r.u8_below(4)?;
+    self.nametable_mapping = r.u8()?;
+    self.fill_tile = r.u8()?;
+    self.fill_attribute = r.u8_below(4)?;
+    r.bytes_into(&mut self.prg_banks)?;
+    for bank in self.chr_banks.iter_mut() {
+      *bank = r.u16()?;
+    }
+    self.chr_upper = r.u8_below(4)?;
+    self.last_chr_set_b = r.bool()?;
+    self.split_control = r.u8()?;
+    self.split_scroll = r.u8()?;
+    self.split_bank = r.u8()?;
+    self.irq_compare = r.u8()?;
+    self.irq_enabled = r.bool()?;
+    self.irq_pending = r.bool()?;
+    self.in_frame = r.bool()?;
+    self.scanline_counter = r.u8()?;
+    self.multiplicand = r.u8()?;
+    self.multiplier = r.u8()?;
+    Ok(())
+  }
+
+  fn box_clone(&self) -> Box<dyn Mapper> {
+    Box::new(self.clone())
+  }
+}
@@ -0,0 +1,417 @@
+use crate::nes::cartridge::{Mirroring, Rom};
+use crate::nes::mapper::{self, Bank, Mapper};
+use crate::nes::nametable::{NametableMap, NametablePage};
+use crate::nes::savestate::{StateError, StateReader, StateWriter};
+use log::trace;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x400;
+
+// CPU cycles the sound chip spends on each channel in turn
+const CHANNEL_CYCLES: u8 = 15;
+// one step of a channel's wave sample times its volume, in the APU's 16.16
+// fixed point: a full channel playing alone about twice as loud as an APU
+// pulse channel at 15
+const LEVEL_STEP: u32 = 87;
+
+/// Mapper 19 (Namco 163): Megami Tensei II, King of Kings, Rolling Thunder.
+///
+///   $4800-$4FFF  internal RAM at the address port, read and write
+///   $5000-$57FF  IRQ counter, low 8 bits
+///   $5800-$5FFF  IRQ counter: EHHHHHHH  count, high 7 bits
+///   $8000-$BFFF  1KB CHR banks, one register every $800
+///   $C000-$DFFF  nametables 0-3, one register every $800: $E0 and up is
+///                CIRAM (bit 0), anything else a 1KB CHR ROM bank
+///   $E000-$E7FF  .SPPPPPP  sound off, PRG bank at $8000
+///   $E800-$EFFF  ..PPPPPP  PRG bank at $A000
+///   $F000-$F7FF  ..PPPPPP  PRG bank at $C000; $E000 is the last bank
+///   $F800-$FFFF  IAAAAAAA  internal RAM address, increment after each
+///                access; also PRG RAM write protection, see below
+///
+/// The IRQ counter goes up every CPU cycle while counting and fires when it
+/// reaches $7FFF, where it stays. Writing either half acknowledges.
+///
+/// PRG RAM at $6000-$7FFF only takes writes while $F800's high nibble is
+/// $4, and then not in the 2KB windows whose bits 0-3 are set.
+///
+/// CHR bank values of $E0 and up are meant to put CIRAM in the pattern
+/// tables unless $E800 bits 6-7 say otherwise; nothing here can reach
+/// CIRAM from pattern table space, so they stay CHR ROM banks.
+#[derive(Clone)]
+pub struct Namco163 {
+  prg_rom: Vec<u8>,
+  chr: Vec<u8>,
+  chr_is_ram: bool,
+  prg_ram: [u8; 0x2000],
+
+  chr_banks: [u8; 8],
+  nametable_banks: [u8; 4],
+  prg_banks: [u8; 3],
+  sound_off: bool,
+  // $F800: internal RAM address and auto-increment, and PRG RAM protection
+  address_port: u8,
+
+  irq_counter: u16,
+  irq_enabled: bool,
+  irq_pending: bool,
+
+  ram: [u8; 0x80],
+  wavetable: Wavetable,
+}
+
+impl Namco163 {
+  pub fn new(rom: Rom) -> Self {
+    let (chr, chr_is_ram) = mapper::chr_memory(rom.chr_rom);
+    Namco163 {
+      prg_rom: rom.prg_rom,
+      chr,
+      chr_is_ram,
+      prg_ram: [0; 0x2000],
+      chr_banks: [0; 8],
+      nametable_banks: match rom.info.mirroring {
+        Mirroring::Horizontal => [0xE0, 0xE0, 0xE1, 0xE1],
+        _ => [0xE0, 0xE1, 0xE0, 0xE1],
+      },
+      prg_banks: [0; 3],
+      sound_off: false,
+      address_port: 0,
+      irq_counter: 0,
+      irq_enabled: false,
+      irq_pending: false,
+      ram: [0; 0x80],
+      wavetable: Wavetable::default(),
+    }
+  }
+
+  fn prg_offset(&self, addr: u16) -> usize {
+    let banks = self.prg_rom.len() / PRG_BANK_SIZE;
+    let bank = match addr {
+      0x8000..=0x9FFF => self.prg_banks[0] as usize,
+      0xA000..=0xBFFF => self.prg_banks[1] as usize,
+      0xC000..=0xDFFF => self.prg_banks[2] as usize,
+      _ => banks - 1,
+    };
+    (bank % banks) * PRG_BANK_SIZE + addr as usize % PRG_BANK_SIZE
+  }
+
+  fn chr_offset(&self, bank: u8, addr: u16) -> usize {
+    let banks = self.chr.len() / CHR_BANK_SIZE;
+    (bank as usize % banks) * CHR_BANK_SIZE + addr as usize % CHR_BANK_SIZE
+  }
+
+  fn pattern_offset(&self, addr: u16) -> usize {
+    let slot = (addr as usize & 0x1FFF) / CHR_BANK_SIZE;
+    self.chr_offset(self.chr_banks[slot], addr)
+  }
+
+  // $F800 high nibble $4 enables writes, its low bits protect each 2KB
+  fn prg_ram_writable(&self, addr: u16) -> bool {
+    let window = (addr - 0x6000) / 0x800;
+    self.address_port & 0xF0 == 0x40 && self.address_port & (1 << window) == 0
+  }
+
+  fn ram_address(&self) -> usize {
+    (self.address_port & 0x7F) as usize
+  }
+
+  fn step_address(&mut self) {
+    if self.address_port & 0x80 != 0 {
+      self.address_port = 0x80 | ((self.address_port & 0x7F) + 1) & 0x7F;
+    }
+  }
+}
+
+impl Mapper for Namco163 {
+  fn name(&self) -> &'static str {
+    "Namco 163"
+  }
+
+  fn prg_read(&self, addr: u16) -> u8 {
+    match addr {
+      0x4800..=0x4FFF => self.ram[self.ram_address()],
+      0x5000..=0x57FF => self.irq_counter as u8,
+      0x5800..=0x5FFF => (self.irq_enabled as u8) << 7 | (self.irq_counter >> 8) as u8,
+      0x6000..=0x7FFF => self.prg_ram[(addr - 0x6000) as usize],
+      0x8000..=0xFFFF => self.prg_rom[self.prg_offset(addr)],
+      _ => {
+        trace!("Namco 163 has nothing at {:04x}", addr);
+        0
+      }
+    }
+  }
+
+  fn prg_read_mut(&mut self, addr: u16) -> u8 {
+    let data = self.prg_read(addr);
+    if let 0x4800..=0x4FFF = addr {
+      self.step_address();
+    }
+    data
+  }
+
+  fn prg_write(&mut self, addr: u16, data: u8) {
+    match addr {
+      0x4800..=0x4FFF => {
+        self.ram[self.ram_address()] = data;
+        self.step_address();
+      }
+      0x5000..=0x57FF => {
+        self.irq_counter = (self.irq_counter & 0x7F00) | data as u16;
+        self.irq_pending = false;
+      }
+      0x5800..=0x5FFF => {
+        self.irq_counter = (self.irq_counter & 0x00FF) | (data as u16 & 0x7F) << 8;
+        self.irq_enabled = data & 0x80 != 0;
+        self.irq_pending = false;
+      }
+      0x6000..=0x7FFF if self.prg_ram_writable(addr) => {
+        self.prg_ram[(addr - 0x6000) as usize] = data
+      }
+      0x8000..=0xBFFF => self.chr_banks[(addr as usize - 0x8000) / 0x800] = data,
+      0xC000..=0xDFFF => self.nametable_banks[(addr as usize - 0xC000) / 0x800] = data,
+      0xE000..=0xE7FF => {
+        self.prg_banks[0] = data & 0x3F;
+        self.sound_off = data & 0x40 != 0;
+      }
+      0xE800..=0xEFFF => self.prg_banks[1] = data & 0x3F,
+      0xF000..=0xF7FF => self.prg_banks[2] = data & 0x3F,
+      0xF800..=0xFFFF => self.address_port = data,
+      _ => trace!("Namco 163 ignored write to {:04x}", addr),
+    }
+  }
+
+  fn chr_read(&self, addr: u16) -> u8 {
+    self.chr[self.pattern_offset(addr)]
+  }
+
+  fn chr_write(&mut self, addr: u16, data: u8) {
+    if self.chr_is_ram {
+      let offset = self.pattern_offset(addr);
+      self.chr[offset] = data;
+    } else {
+      trace!("attempt to write to CHR ROM {:04x}", addr);
+    }
+  }
+
+  fn mirroring(&self) -> Mirroring {
+    match self.nametable_banks {
+      [0xE0, 0xE0, 0xE1, 0xE1] => Mirroring::Horizontal,
+      [0xE0, 0xE0, 0xE0, 0xE0] => Mirroring::SingleScreenLower,
+      [0xE1, 0xE1, 0xE1, 0xE1] => Mirroring::SingleScreenUpper,
+      _ => Mirroring::Vertical,
+    }
+  }
+
+  fn nametables(&self) -> NametableMap {
+    let mut pages = [NametablePage::Ciram(0); 4];
+    for (table, page) in pages.iter_mut().enumerate() {
+      *page = match self.nametable_banks[table] {
+        bank if bank >= 0xE0 => NametablePage::Ciram(bank & 1),
+        _ => NametablePage::Cartridge(table as u8),
+      };
+    }
+    NametableMap { pages }
+  }
+
+  // the cartridge pages are the four tables themselves, each a CHR bank
+  fn nametable_read(&self, offset: u16) -> u8 {
+    let bank = self.nametable_banks[offset as usize / 0x400];
+    self.chr[self.chr_offset(bank, offset)]
+  }
+
+  fn nametable_write(&mut self, offset: u16, data: u8) {
+    if self.chr_is_ram {
+      let bank = self.nametable_banks[offset as usize / 0x400];
+      let offset = self.chr_offset(bank, offset);
+      self.chr[offset] = data;
+    } else {
+      trace!(
+        "Namco 163 dropped nametable write to CHR ROM {:04x}",
+        offset
+      );
+    }
+  }
+
+  fn prg_mapped(&self, addr: u16) -> bool {
+    addr >= 0x4800
+  }
+
+  fn prg_bank(&self, addr: u16) -> Option<Bank> {
+    match addr {
+      0x8000..=0xFFFF => Some(Bank::at(self.prg_offset(addr), PRG_BANK_SIZE)),
+      _ => None,
+    }
+  }
+
+  fn chr_bank(&self, addr: u16) -> Option<Bank> {
+    Some(Bank::at(self.pattern_offset(addr), CHR_BANK_SIZE))
+  }
+
+  fn prg_ram(&self) -> Option<&[u8]> {
+    Some(&self.prg_ram)
+  }
+
+  fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+    Some(&mut self.prg_ram)
+  }
+
+  fn irq_pending(&self) -> bool {
+    self.irq_pending
+  }
+
+  fn cpu_cycles(&mut self, cycles: u32) {
+    if self.irq_enabled && self.irq_counter < 0x7FFF {
+      let left = 0x7FFF - self.irq_counter as u32;
+      self.irq_counter += cycles.min(left) as u16;
+      if self.irq_counter == 0x7FFF {
+        self.irq_pending = true;
+      }
+    }
+    if !self.sound_off {
+      self.wavetable.clock(&mut self.ram, cycles);
+    }
+  }
+
+  fn audio_level(&self) -> u32 {
+    match self.sound_off {
+      true => 0,
+      false => self.wavetable.level(&self.ram),
+    }
+  }
+
+  fn save_state(&self, w: &mut StateWriter) {
+    if self.chr_is_ram {
+      w.bytes(&self.chr);
+    }
+    w.bytes(&self.prg_ram);
+    w.bytes(&self.chr_banks);
+    w.bytes(&self.nametable_banks);
+    w.bytes(&self.prg_banks);
+    w.bool(self.sound_off);
+    w.u8(self.address_port);
+    w.u16(self.irq_counter);
+    w.bool(self.irq_enabled);
+    w.bool(self.irq_pending);
+    w.bytes(&self.ram);
+    self.wavetable.save_state(w);
+  }
+
+  fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+    if self.chr_is_ram {
+      r.bytes_into(&mut self.chr)?;
+    }
+    r.bytes_into(&mut self.prg_ram)?;
+    r.bytes_into(&mut self.chr_banks)?;
+    r.bytes_into(&mut self.nametable_banks)?;
+    r.bytes_into(&mut self.prg_banks)?;
+    self.sound_off = r.bool()?;
+    self.address_port = r.u8()?;
+    self.irq_counter = match r.u16()? {
+      counter if counter <= 0x7FFF => counter,
+      _ => return Err(StateError::Corrupt),
+    };
+    self.irq_enabled = r.bool()?;
+    self.irq_pending = r.bool()?;
+    r.bytes_into(&mut self.ram)?;
+    self.wavetable.load_state(r)
+  }
+
+  fn box_clone(&self) -> Box<dyn Mapper> {
+    Box::new(self.clone())
+  }
+}
+
+/*
+  The sound chip: up to 8 wavetable channels, their registers and the
+  waves themselves in the top of the internal RAM. Channel n's registers
+  are at $40 + 8n:
+
+  +0, +2, +4  frequency, 18 bits (low 2 of +4)
+  +1, +3, +5  phase, 24 bits, its top 8 the position in the wave
+  +4          LLLLLL..  wave length, 256 - 4L samples
+  +6          wave address, in 4-bit samples (low nibble first)
+  +7          ....VVVV  volume; in channel 7's, .CCC....  C+1 channels on
+
+  Channels 7 down to 7-C are on. The chip works on one of them every 15
+  CPU cycles, adding the frequency to its phase and putting out its
+  sample times volume, so the more channels on, the slower and quieter
+  each one. Real chips switch the DAC between channels that fast; the
+  average of what each last put out is what reaches speakers anyway.
+*/
+#[derive(Clone)]
+struct Wavetable {
+  cycle: u8,
+  // the channel worked on next, going down from 7 to the first one on
+  channel: u8,
+  outputs: [u8; 8],
+}
+
+impl Default for Wavetable {
+  fn default() -> Self {
+    Wavetable {
+      cycle: 0,
+      channel: 7,
+      outputs: [0; 8],
+    }
+  }
+}
+
+impl Wavetable {
+  fn first_channel(ram: &[u8; 0x80]) -> u8 {
+    7 - ((ram[0x7F] >> 4) & 0b111)
+  }
+
+  fn clock(&mut self, ram: &mut [u8; 0x80], cycles: u32) {
+    for _ in 0..cycles {
+      self.cycle += 1;
+      if self.cycle < CHANNEL_CYCLES {
+        continue;
+      }
+      self.cycle = 0;
+      let first = Self::first_channel(ram);
+      let channel = self.channel.max(first);
+      self.outputs[channel as usize] = Self::step_channel(ram, channel);
+      self.channel = if channel == first { 7 } else { channel - 1 };
+    }
+  }
+
+  // move `channel` one step along its wave, giving its new sample times
+  // volume
+  fn step_channel(ram: &mut [u8; 0x80], channel: u8) -> u8 {
+    let base = 0x40 + channel as usize * 8;
+    let mut registers = [0; 8];
+    registers.copy_from_slice(&ram[base..base + 8]);
+    let at = |offset: usize| registers[offset] as u32;
+    let frequency = at(0) | at(2) << 8 | (at(4) & 0b11) << 16;
+    let length = 256 - (at(4) & 0xFC);
+    let phase = ((at(1) | at(3) << 8 | at(5) << 16) + frequency) % (length << 16);
+    ram[base + 1] = phase as u8;
+    ram[base + 3] = (phase >> 8) as u8;
+    ram[base + 5] = (phase >> 16) as u8;
+
+    let address = ((phase >> 16) + at(6)) as usize & 0xFF;
+    let sample = (ram[address / 2] >> ((address & 1) * 4)) & 0x0F;
+    sample * (registers[7] & 0x0F)
+  }
+
+  fn level(&self, ram: &[u8; 0x80]) -> u32 {
+    let first = Self::first_channel(ram) as usize;
+    let sum: u32 = self.outputs[first..]
+      .iter()
+      .map(|&output| output as u32)
+      .sum();
+    sum * LEVEL_STEP / (8 - first) as u32
+  }
+
+  fn save_state(&self, w: &mut StateWriter) {
+    w.u8(self.cycle);
+    w.u8(self.channel);
+    w.bytes(&self.outputs);
+  }
+
+  fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+    self.cycle = r.u8_below(CHANNEL_CYCLES)?;
+    self.channel = r.u8_below(8)?;
+    r.bytes_into(&mut self.outputs)?;
+    Ok(())
+  }
+}
@@ -0,0 +1,115 @@
+use crate::nes::cartridge::{Mirroring, Rom};
+use crate::nes::mapper::{self, Bank, Mapper};
+use crate::nes::savestate::{StateError, StateReader, StateWriter};
+use log::trace;
+
+const PRG_BANK_SIZE: usize = 0x4000;
+
+/// Mapper 0: no bank switching. 16KB or 32KB of PRG ROM at $8000 (16KB
+/// images are mirrored into $C000), 8KB of CHR ROM or RAM, and optional
+/// PRG RAM at $6000 as used by Family Basic.
+#[derive(Clone)]
+pub struct Nrom {
+  prg_rom: Vec<u8>,
+  chr: Vec<u8>,
+  chr_is_ram: bool,
+  prg_ram: [u8; 0x2000],
+  mirroring: Mirroring,
+}
+
+impl Nrom {
+  pub fn new(rom: Rom) -> Self {
+    let (chr, chr_is_ram) = mapper::chr_memory(rom.chr_rom);
+    Nrom {
+      prg_rom: rom.prg_rom,
+      chr,
+      chr_is_ram,
+      prg_ram: [0; 0x2000],
+      mirroring: rom.info.mirroring,
+    }
+  }
+}
+
+impl Mapper for Nrom {
+  fn name(&self) -> &'static str {
+    "NROM"
+  }
+
+  fn prg_read(&self, addr: u16) -> u8 {
+    match addr {
+      0x6000..=0x7FFF => self.prg_ram[(addr - 0x6000) as usize],
+      0x8000..=0xFFFF => {
+        // mirror if needed
+        let addr = (addr - 0x8000) as usize % self.prg_rom.len();
+        self.prg_rom[addr]
+      }
+      _ => {
+        trace!("NROM has nothing at {:04x}", addr);
+        0
+      }
+    }
+  }
+
+  fn prg_write(&mut self, addr: u16, data: u8) {
+    match addr {
+      0x6000..=0x7FFF => self.prg_ram[(addr - 0x6000) as usize] = data,
+      _ => trace!("attempt to write to cartridge ROM space {:04x}", addr),
+    }
+  }
+
+  fn chr_read(&self, addr: u16) -> u8 {
+    self.chr[(addr & 0x1FFF) as usize % self.chr.len()]
+  }
+
+  fn chr_write(&mut self, addr: u16, data: u8) {
+    if self.chr_is_ram {
+      self.chr[(addr & 0x1FFF) as usize] = data;
+    } else {
+      trace!("attempt to write to CHR ROM {:04x}", addr);
+    }
+  }
+
+  fn prg_bank(&self, addr: u16) -> Option<Bank> {
+    match addr {
+      0x8000..=0xFFFF => Some(Bank::at(
+        (addr - 0x8000) as usize % self.prg_rom.len(),
+        PRG_BANK_SIZE,
+      )),
+      _ => None,
+    }
+  }
+
+  fn chr_bank(&self, _addr: u16) -> Option<Bank> {
+    Some(Bank::at(0, self.chr.len()))
+  }
+
+  fn prg_ram(&self) -> Option<&[u8]> {
+    Some(&self.prg_ram)
+  }
+
+  fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+    Some(&mut self.prg_ram)
+  }
+
+  fn mirroring(&self) -> Mirroring {
+    self.mirroring
+  }
+
+  fn save_state(&self, w: &mut StateWriter) {
+    if self.chr_is_ram {
+      w.bytes(&self.chr);
+    }
+    w.bytes(&self.prg_ram);
+  }
+
+  fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+    if self.chr_is_ram {
+      r.bytes_into(&mut self.chr)?;
+    }
+    r.bytes_into(&mut self.prg_ram)
+  }
+
+  fn box_clone(&self) -> Box<dyn Mapper> {
+    Box::new(self.clone())
+  }
+}
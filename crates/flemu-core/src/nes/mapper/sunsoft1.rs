@@ -0,0 +1,111 @@
+use crate::nes::cartridge::{Mirroring, Rom};
+use crate::nes::mapper::{Bank, Mapper};
+use crate::nes::savestate::{StateError, StateReader, StateWriter};
+use log::trace;
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x1000;
+
+/// Mapper 184 (Sunsoft-1): Atlantis no Nazo, Wing of Madoola.
+///
+/// PRG is laid out like NROM. Writes to $6000-$7FFF take `.HHH.LLL`: the
+/// 4KB CHR banks at $1000 and $0000.
+#[derive(Clone)]
+pub struct Sunsoft1 {
+  prg_rom: Vec<u8>,
+  chr_rom: Vec<u8>,
+  mirroring: Mirroring,
+  register: u8,
+}
+
+impl Sunsoft1 {
+  pub fn new(rom: Rom) -> Self {
+    Sunsoft1 {
+      prg_rom: rom.prg_rom,
+      chr_rom: if rom.chr_rom.is_empty() {
+        vec![0; 0x2000]
+      } else {
+        rom.chr_rom
+      },
+      mirroring: rom.info.mirroring,
+      register: 0,
+    }
+  }
+}
+
+impl Sunsoft1 {
+  fn chr_offset(&self, addr: u16) -> usize {
+    let banks = self.chr_rom.len() / CHR_BANK_SIZE;
+    let bank = match addr & 0x1000 {
+      0 => self.register & 0b111,
+      _ => (self.register >> 4) & 0b111,
+    };
+    (bank as usize % banks) * CHR_BANK_SIZE + (addr & 0x0FFF) as usize
+  }
+}
+
+impl Mapper for Sunsoft1 {
+  fn name(&self) -> &'static str {
+    "Sunsoft-1"
+  }
+
+  fn prg_read(&self, addr: u16) -> u8 {
+    match addr {
+      0x8000..=0xFFFF => {
+        // mirror if needed
+        let addr = (addr - 0x8000) as usize % self.prg_rom.len();
+        self.prg_rom[addr]
+      }
+      _ => {
+        trace!("Sunsoft-1 has nothing at {:04x}", addr);
+        0
+      }
+    }
+  }
+
+  fn prg_write(&mut self, addr: u16, data: u8) {
+    match addr {
+      0x6000..=0x7FFF => self.register = data,
+      _ => trace!("Sunsoft-1 ignored write to {:04x}", addr),
+    }
+  }
+
+  fn chr_read(&self, addr: u16) -> u8 {
+    self.chr_rom[self.chr_offset(addr)]
+  }
+
+  fn chr_write(&mut self, addr: u16, _data: u8) {
+    trace!("attempt to write to CHR ROM {:04x}", addr);
+  }
+
+  fn prg_bank(&self, addr: u16) -> Option<Bank> {
+    match addr {
+      0x8000..=0xFFFF => Some(Bank::at(
+        (addr - 0x8000) as usize % self.prg_rom.len(),
+        PRG_BANK_SIZE,
+      )),
+      _ => None,
+    }
+  }
+
+  fn chr_bank(&self, addr: u16) -> Option<Bank> {
+    Some(Bank::at(self.chr_offset(addr), CHR_BANK_SIZE))
+  }
+
+  fn mirroring(&self) -> Mirroring {
+    self.mirroring
+  }
+
+  fn save_state(&self, w: &mut StateWriter) {
+    w.u8(self.register);
+  }
+
+  fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+    self.register = r.u8()?;
+    Ok(())
+  }
+
+  fn box_clone(&self) -> Box<dyn Mapper> {
+    Box::new(self.clone())
+  }
+}
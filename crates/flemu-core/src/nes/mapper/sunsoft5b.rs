@@ -0,0 +1,209 @@
+use crate::nes::savestate::{StateError, StateReader, StateWriter};
+
+// CPU cycles per tick of the tone, noise and envelope counters
+const PRESCALER: u8 = 16;
+
+// each channel's level at every 1.5dB step of the 5-bit envelope, a full
+// channel about as loud as an APU pulse channel at 15, in the APU's 16.16
+// fixed point. Fixed volumes take every other step.
+#[rustfmt::skip]
+const LEVELS: [u32; 32] = [
+  0, 55, 66, 78, 93, 110, 131, 156, 185, 220, 262, 311, 369, 439, 522, 620,
+  737, 876, 1041, 1238, 1471, 1748, 2078, 2469, 2935, 3488, 4145, 4927, 5855, 6959, 8271, 9830,
+];
+
+/*
+  The Sunsoft 5B, an FME-7 with a YM2149-style sound chip: three square
+  channels with a shared noise generator and envelope. $C000-$DFFF picks a
+  register, $E000-$FFFF writes it:
+
+  $00-$05  tone periods of A, B and C, 12 bits, low byte first
+  $06      noise period, 5 bits
+  $07      ..CBAcba  noise (CBA) and tone (cba) off per channel
+  $08-$0A  ...EVVVV  volume of A, B and C, or the envelope's with E
+  $0B-$0C  envelope period, 16 bits, low byte first
+  $0D      envelope shape: continue, attack, alternate, hold; restarts it
+
+  A channel's square toggles every 16 * period CPU cycles. With its tone
+  and noise both off it sits high, which games use to play samples
+  through the volume.
+*/
+#[derive(Clone)]
+pub struct Sunsoft5b {
+  selected: u8,
+  registers: [u8; 16],
+  prescaler: u8,
+  tone_counters: [u16; 3],
+  tone_high: [bool; 3],
+  noise_counter: u8,
+  // 17-bit LFSR
+  noise: u32,
+  envelope_counter: u16,
+  envelope_step: u8,
+  envelope_attack: bool,
+  envelope_holding: bool,
+}
+
+impl Default for Sunsoft5b {
+  fn default() -> Self {
+    Sunsoft5b {
+      selected: 0,
+      registers: [0; 16],
+      prescaler: 0,
+      tone_counters: [0; 3],
+      tone_high: [false; 3],
+      noise_counter: 0,
+      noise: 1,
+      envelope_counter: 0,
+      envelope_step: 0,
+      envelope_attack: false,
+      envelope_holding: false,
+    }
+  }
+}
+
+impl Sunsoft5b {
+  /// $C000-$DFFF. The upper bits have to be 0 for the chip to listen.
+  pub fn select(&mut self, value: u8) {
+    self.selected = value;
+  }
+
+  /// $E000-$FFFF
+  pub fn write(&mut self, value: u8) {
+    if self.selected > 0x0F {
+      return;
+    }
+    self.registers[self.selected as usize] = value;
+    if self.selected == 0x0D {
+      self.envelope_counter = 0;
+      self.envelope_step = 0;
+      self.envelope_attack = value & 0b0100 != 0;
+      self.envelope_holding = false;
+    }
+  }
+
+  fn tone_period(&self, channel: usize) -> u16 {
+    let period =
+      self.registers[channel * 2] as u16 | (self.registers[channel * 2 + 1] as u16 & 0x0F) << 8;
+    period.max(1)
+  }
+
+  fn envelope_period(&self) -> u16 {
+    (self.registers[0x0B] as u16 | (self.registers[0x0C] as u16) << 8).max(1)
+  }
+
+  pub fn clock(&mut self) {
+    self.prescaler += 1;
+    if self.prescaler < PRESCALER {
+      return;
+    }
+    self.prescaler = 0;
+
+    for channel in 0..3 {
+      self.tone_counters[channel] += 1;
+      if self.tone_counters[channel] >= self.tone_period(channel) {
+        self.tone_counters[channel] = 0;
+        self.tone_high[channel] = !self.tone_high[channel];
+      }
+    }
+
+    self.noise_counter += 1;
+    if self.noise_counter >= (self.registers[0x06] & 0x1F).max(1) {
+      self.noise_counter = 0;
+      let feedback = (self.noise ^ (self.noise >> 3)) & 1;
+      self.noise = (self.noise >> 1) | (feedback << 16);
+    }
+
+    self.envelope_counter += 1;
+    if self.envelope_counter >= self.envelope_period() {
+      self.envelope_counter = 0;
+      self.step_envelope();
+    }
+  }
+
+  fn step_envelope(&mut self) {
+    if self.envelope_holding {
+      return;
+    }
+    if self.envelope_step < 31 {
+      self.envelope_step += 1;
+      return;
+    }
+    let shape = self.registers[0x0D];
+    let (continues, alternate, hold) = (shape & 0b1000 != 0, shape & 0b10 != 0, shape & 1 != 0);
+    self.envelope_step = 0;
+    if !continues {
+      self.envelope_holding = true;
+      self.envelope_attack = false;
+    } else {
+      self.envelope_holding = hold;
+      if alternate {
+        self.envelope_attack = !self.envelope_attack;
+      }
+    }
+  }
+
+  fn envelope_level(&self) -> u8 {
+    match (self.envelope_holding, self.envelope_attack) {
+      (true, true) => 31,
+      (true, false) => 0,
+      (false, true) => self.envelope_step,
+      (false, false) => 31 - self.envelope_step,
+    }
+  }
+
+  /// The three channels mixed, in the APU's 16.16 fixed point.
+  pub fn level(&self) -> u32 {
+    let disabled = self.registers[0x07];
+    let noise_high = self.noise & 1 != 0;
+    (0..3)
+      .filter(|&channel| {
+        let tone = self.tone_high[channel] || disabled & (1 << channel) != 0;
+        let noise = noise_high || disabled & (8 << channel) != 0;
+        tone && noise
+      })
+      .map(|channel| {
+        let volume = self.registers[0x08 + channel];
+        let step = match (volume & 0x10 != 0, volume & 0x0F) {
+          (true, _) => self.envelope_level(),
+          (false, 0) => 0,
+          (false, volume) => volume * 2 + 1,
+        };
+        LEVELS[step as usize]
+      })
+      .sum()
+  }
+
+  pub fn save_state(&self, w: &mut StateWriter) {
+    w.u8(self.selected);
+    w.bytes(&self.registers);
+    w.u8(self.prescaler);
+    for channel in 0..3 {
+      w.u16(self.tone_counters[channel]);
+      w.bool(self.tone_high[channel]);
+    }
+    w.u8(self.noise_counter);
+    w.u32(self.noise);
+    w.u16(self.envelope_counter);
+    w.u8(self.envelope_step);
+    w.bool(self.envelope_attack);
+    w.bool(self.envelope_holding);
+  }
+
+  pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+    self.selected = r.u8()?;
+    r.bytes_into(&mut self.registers)?;
+    self.prescaler = r.u8_below(PRESCALER)?;
+    for channel in 0..3 {
+      self.tone_counters[channel] = r.u16()?;
+      self.tone_high[channel] = r.bool()?;
+    }
+    self.noise_counter = r.u8()?;
+    self.noise = r.u32()? & 0x1_FFFF;
+    self.envelope_counter = r.u16()?;
+    self.envelope_step = r.u8_below(32)?;
+    self.envelope_attack = r.bool()?;
+    self.envelope_holding = r.bool()?;
+    Ok(())
+  }
+}
@@ -0,0 +1,229 @@
+use crate::nes::cartridge::{Mirroring, Rom, RomError, CHR_ROM_PAGE_SIZE, PRG_ROM_PAGE_SIZE};
+use crate::nes::mapper::{self, Mapper};
+use crate::nes::savestate::{StateReader, StateWriter};
+
+/*
+  Scaffolding for testing boards, new ones included: build a cartridge
+  from a header the way a dump would be loaded, poke its registers, and
+  check which part of ROM shows up where.
+
+  Every 1KB of PRG and CHR ROM the harness makes holds its own index, as
+  little-endian u16s, so two bytes read anywhere tell which 1KB of ROM is
+  there. `assert_prg_bank`/`assert_chr_bank` check a whole window that way
+  and against what the board reports through `prg_bank`/`chr_bank` for
+  debuggers. `pulse_a12` stands in for the PPU for scanline counters.
+
+  A new board's tests usually come down to: a `SyntheticHeader` with its
+  mapper number and sizes, `write` to its registers, and an
+  `assert_*_bank` after each, plus `reloaded` to see a savestate keeps it
+  all. Board-specific checks go straight to `mapper`.
+*/
+
+/// What goes in the NES 2.0 header of a test cartridge.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SyntheticHeader {
+  pub mapper: u16,
+  pub submapper: u8,
+  /// In 16KB units.
+  pub prg_rom_banks: usize,
+  /// In 8KB units, 0 for CHR RAM.
+  pub chr_rom_banks: usize,
+  /// Horizontal, vertical or four-screen; headers can't say single-screen,
+  /// those come out horizontal.
+  pub mirroring: Mirroring,
+  pub battery: bool,
+  /// Bytes, a power of two from 128 up or 0. Counted as NVRAM with
+  /// `battery`.
+  pub prg_ram_size: usize,
+}
+
+impl Default for SyntheticHeader {
+  /// NROM-256 with 8KB of CHR ROM, vertical mirroring.
+  fn default() -> Self {
+    SyntheticHeader {
+      mapper: 0,
+      submapper: 0,
+      prg_rom_banks: 2,
+      chr_rom_banks: 1,
+      mirroring: Mirroring::Vertical,
+      battery: false,
+      prg_ram_size: 0,
+    }
+  }
+}
+
+// NES 2.0 RAM sizes are 64 << shift
+fn ram_shift(size: usize) -> u8 {
+  match size {
+    0 => 0,
+    size => (size / 64).trailing_zeros() as u8,
+  }
+}
+
+impl SyntheticHeader {
+  pub fn to_bytes(&self) -> [u8; 16] {
+    let mut header = [0; 16];
+    header[..4].copy_from_slice(b"NES\x1A");
+    header[4] = self.prg_rom_banks as u8;
+    header[5] = self.chr_rom_banks as u8;
+    header[6] = (self.mapper as u8 & 0x0F) << 4
+      | ((self.mirroring == Mirroring::FourScreen) as u8) << 3
+      | (self.battery as u8) << 1
+      | (self.mirroring == Mirroring::Vertical) as u8;
+    header[7] = (self.mapper as u8 & 0xF0) | 0b1000;
+    header[8] = self.submapper << 4 | (self.mapper >> 8) as u8 & 0x0F;
+    header[9] =
+      ((self.chr_rom_banks >> 8) as u8 & 0x0F) << 4 | (self.prg_rom_banks >> 8) as u8 & 0x0F;
+    header[10] = match self.battery {
+      true => ram_shift(self.prg_ram_size) << 4,
+      false => ram_shift(self.prg_ram_size),
+    };
+    if self.chr_rom_banks == 0 {
+      header[11] = ram_shift(0x2000);
+    }
+    header
+  }
+
+  /// The whole file: header, then PRG and CHR ROM filled with their 1KB
+  /// indices.
+  pub fn to_image(&self) -> Vec<u8> {
+    let mut image = self.to_bytes().to_vec();
+    image.extend(numbered_kbs(self.prg_rom_banks * PRG_ROM_PAGE_SIZE));
+    image.extend(numbered_kbs(self.chr_rom_banks * CHR_ROM_PAGE_SIZE));
+    image
+  }
+}
+
+fn numbered_kbs(len: usize) -> impl Iterator<Item = u8> {
+  (0..len).map(|i| (((i / 0x400) as u16) >> (i % 2 * 8)) as u8)
+}
+
+/// A board built from a `SyntheticHeader`, through `Rom::from_bytes` and
+/// `mapper::for_rom` like any cartridge. The asserts panic with what the
+/// CPU or PPU actually sees, for use in tests.
+pub struct MapperTestHarness {
+  pub mapper: Box<dyn Mapper>,
+  pub header: SyntheticHeader,
+  // the line the PPU was last on, for `pulse_a12`
+  scanline: u16,
+}
+
+impl MapperTestHarness {
+  pub fn new(header: SyntheticHeader) -> Result<Self, RomError> {
+    let rom = Rom::from_bytes(&header.to_image())?;
+    Ok(MapperTestHarness {
+      mapper: mapper::for_rom(rom)?,
+      header,
+      scanline: 241,
+    })
+  }
+
+  /// A CPU write.
+  pub fn write(&mut self, addr: u16, data: u8) {
+    self.mapper.prg_write(addr, data);
+  }
+
+  /// CPU writes in order.
+  pub fn write_all(&mut self, writes: &[(u16, u8)]) {
+    for &(addr, data) in writes {
+      self.write(addr, data);
+    }
+  }
+
+  /// Which 1KB of PRG ROM the CPU sees at `addr`, peeking like a debugger.
+  pub fn prg_kb(&self, addr: u16) -> usize {
+    let addr = addr & !1;
+    self.mapper.prg_read(addr) as usize | (self.mapper.prg_read(addr + 1) as usize) << 8
+  }
+
+  /// Which 1KB of CHR ROM the PPU sees at `addr`.
+  pub fn chr_kb(&self, addr: u16) -> usize {
+    let addr = addr & !1;
+    self.mapper.chr_read(addr) as usize | (self.mapper.chr_read(addr + 1) as usize) << 8
+  }
+
+  /// `size` bytes of CPU space from `addr` show the `bank`th `size` bytes
+  /// of PRG ROM, and the board says so too.
+  pub fn assert_prg_bank(&self, addr: u16, bank: usize, size: usize) {
+    for kb in 0..(size / 0x400).max(1) {
+      let at = addr as usize + kb * 0x400;
+      assert_eq!(
+        self.prg_kb(at as u16),
+        bank * size / 0x400 + kb,
+        "PRG ROM at ${:04X}, expected {}KB bank {}",
+        at,
+        size / 0x400,
+        bank,
+      );
+    }
+    if let Some(reported) = self.mapper.prg_bank(addr) {
+      assert_eq!(
+        reported.index * reported.size,
+        bank * size / reported.size * reported.size,
+        "{} reports PRG bank {:?} at ${:04X}",
+        self.mapper.name(),
+        reported,
+        addr
+      );
+    }
+  }
+
+  /// `size` bytes of pattern table space from `addr` show the `bank`th
+  /// `size` bytes of CHR ROM, and the board says so too.
+  pub fn assert_chr_bank(&self, addr: u16, bank: usize, size: usize) {
+    for kb in 0..(size / 0x400).max(1) {
+      let at = addr as usize + kb * 0x400;
+      assert_eq!(
+        self.chr_kb(at as u16),
+        bank * size / 0x400 + kb,
+        "CHR ROM at ${:04X}, expected {}KB bank {}",
+        at,
+        size / 0x400,
+        bank,
+      );
+    }
+    if let Some(reported) = self.mapper.chr_bank(addr) {
+      assert_eq!(
+        reported.index * reported.size,
+        bank * size / reported.size * reported.size,
+        "{} reports CHR bank {:?} at ${:04X}",
+        self.mapper.name(),
+        reported,
+        addr
+      );
+    }
+  }
+
+  /// One rise of PPU A12, as a rendered line with the background at $0000
+  /// and sprites at $1000 makes it: the board hears of the PPU moving on
+  /// to the next line with rendering on. Goes around the frame's 241 such
+  /// lines, the pre-render line's as 0 and the visible ones' as 1-240,
+  /// passing through vblank after 240. Whether IRQ is asserted after.
+  pub fn pulse_a12(&mut self) -> bool {
+    if self.scanline == 240 {
+      for scanline in 241..=261 {
+        self.mapper.ppu_scanline(scanline, true);
+      }
+    }
+    self.scanline = match self.scanline {
+      0..=239 => self.scanline + 1,
+      _ => 0,
+    };
+    self.mapper.ppu_scanline(self.scanline, true);
+    self.mapper.irq_pending()
+  }
+
+  /// A fresh board from the same header with this one's savestate
+  /// loaded.
+  pub fn reloaded(&self) -> MapperTestHarness {
+    let mut w = StateWriter::new(0);
+    self.mapper.save_state(&mut w);
+    let bytes = w.finish();
+    let mut reloaded = MapperTestHarness::new(self.header).expect("the header loaded once");
+    let mut r = StateReader::new(&bytes, 0).expect("a state just written");
+    let loaded = reloaded.mapper.load_state(&mut r).and_then(|_| r.finish());
+    assert_eq!(loaded, Ok(()), "{} savestate", self.mapper.name());
+    reloaded.scanline = self.scanline;
+    reloaded
+  }
+}
@@ -0,0 +1,112 @@
+use crate::nes::cartridge::{Mirroring, Rom};
+use crate::nes::mapper::{self, Bank, Mapper};
+use crate::nes::savestate::{StateError, StateReader, StateWriter};
+use log::trace;
+
+const PRG_BANK_SIZE: usize = 0x4000;
+
+/// Mapper 180 (UNROM with a 74HC08): Crazy Climber.
+///
+/// UxROM upside down: the first 16KB bank is fixed at $8000 and writes to
+/// $8000-$FFFF pick the one at $C000.
+#[derive(Clone)]
+pub struct Unrom180 {
+  prg_rom: Vec<u8>,
+  chr: Vec<u8>,
+  chr_is_ram: bool,
+  mirroring: Mirroring,
+  prg_bank: u8,
+}
+
+impl Unrom180 {
+  pub fn new(rom: Rom) -> Self {
+    let (chr, chr_is_ram) = mapper::chr_memory(rom.chr_rom);
+    Unrom180 {
+      prg_rom: rom.prg_rom,
+      chr,
+      chr_is_ram,
+      mirroring: rom.info.mirroring,
+      prg_bank: 0,
+    }
+  }
+}
+
+impl Unrom180 {
+  fn bank_at(&self, addr: u16) -> Option<usize> {
+    let banks = self.prg_rom.len() / PRG_BANK_SIZE;
+    match addr {
+      0x8000..=0xBFFF => Some(0),
+      0xC000..=0xFFFF => Some(self.prg_bank as usize % banks),
+      _ => None,
+    }
+  }
+}
+
+impl Mapper for Unrom180 {
+  fn name(&self) -> &'static str {
+    "UNROM (Crazy Climber)"
+  }
+
+  fn prg_read(&self, addr: u16) -> u8 {
+    match self.bank_at(addr) {
+      Some(bank) => self.prg_rom[bank * PRG_BANK_SIZE + (addr as usize % PRG_BANK_SIZE)],
+      None => {
+        trace!("UNROM (Crazy Climber) has nothing at {:04x}", addr);
+        0
+      }
+    }
+  }
+
+  fn prg_write(&mut self, addr: u16, data: u8) {
+    match addr {
+      0x8000..=0xFFFF => self.prg_bank = data & 0x07,
+      _ => trace!("UNROM (Crazy Climber) ignored write to {:04x}", addr),
+    }
+  }
+
+  fn chr_read(&self, addr: u16) -> u8 {
+    self.chr[(addr & 0x1FFF) as usize % self.chr.len()]
+  }
+
+  fn chr_write(&mut self, addr: u16, data: u8) {
+    if self.chr_is_ram {
+      self.chr[(addr & 0x1FFF) as usize] = data;
+    } else {
+      trace!("attempt to write to CHR ROM {:04x}", addr);
+    }
+  }
+
+  fn prg_bank(&self, addr: u16) -> Option<Bank> {
+    self.bank_at(addr).map(|index| Bank {
+      index,
+      size: PRG_BANK_SIZE,
+    })
+  }
+
+  fn chr_bank(&self, _addr: u16) -> Option<Bank> {
+    Some(Bank::at(0, self.chr.len()))
+  }
+
+  fn mirroring(&self) -> Mirroring {
+    self.mirroring
+  }
+
+  fn save_state(&self, w: &mut StateWriter) {
+    if self.chr_is_ram {
+      w.bytes(&self.chr);
+    }
+    w.u8(self.prg_bank);
+  }
+
+  fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+    if self.chr_is_ram {
+      r.bytes_into(&mut self.chr)?;
+    }
+    self.prg_bank = r.u8()?;
+    Ok(())
+  }
+
+  fn box_clone(&self) -> Box<dyn Mapper> {
+    Box::new(self.clone())
+  }
+}
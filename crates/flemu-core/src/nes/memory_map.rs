@@ -0,0 +1,232 @@
+use crate::nes::bus::{Bus, Mem};
+use crate::nes::mapper::{Bank, Mapper};
+use crate::nes::nametable::NametablePage;
+
+/*
+  What lives at each address, for hex viewers and trace logs. Cartridge
+  ranges are asked from the mapper, so the labels follow bank switches: the
+  map is only valid for the moment it was taken.
+
+  Reads and writes for hex editors go through the same buses: CPU reads
+  are peeks with no side effects, CPU writes are what the CPU writing
+  would do, registers and bank switches included.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressSpace {
+  /// $0000-$FFFF, as the CPU sees it.
+  Cpu,
+  /// $0000-$3FFF, pattern tables, nametables and palette.
+  Ppu,
+}
+
+impl AddressSpace {
+  pub const ALL: [AddressSpace; 2] = [AddressSpace::Cpu, AddressSpace::Ppu];
+
+  /// Stable id: "cpu", "ppu".
+  pub fn id(&self) -> &'static str {
+    match self {
+      AddressSpace::Cpu => "cpu",
+      AddressSpace::Ppu => "ppu",
+    }
+  }
+
+  pub fn from_id(id: &str) -> Option<AddressSpace> {
+    AddressSpace::ALL
+      .iter()
+      .copied()
+      .find(|space| space.id() == id)
+  }
+
+  /// Addresses in the space, mirrors included.
+  pub fn size(&self) -> usize {
+    match self {
+      AddressSpace::Cpu => 0x10000,
+      AddressSpace::Ppu => 0x4000,
+    }
+  }
+}
+
+/// `len` bytes from `addr` on, wrapping around at the end of the space
+/// and never more than the whole space.
+pub fn read_range(bus: &Bus, space: AddressSpace, addr: u16, len: usize) -> Vec<u8> {
+  let size = space.size();
+  (0..len.min(size))
+    .map(|i| {
+      let addr = ((addr as usize + i) % size) as u16;
+      match space {
+        AddressSpace::Cpu => bus.mem_peek(addr),
+        AddressSpace::Ppu => bus.ppu_peek(addr),
+      }
+    })
+    .collect()
+}
+
+pub fn write_byte(bus: &mut Bus, space: AddressSpace, addr: u16, value: u8) {
+  match space {
+    AddressSpace::Cpu => bus.mem_write(addr, value),
+    AddressSpace::Ppu => bus.ppu_poke(addr, value),
+  }
+}
+
+/// A labelled address range, `end` inclusive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Region {
+  pub start: u16,
+  pub end: u16,
+  pub description: String,
+}
+
+// cartridge labels can change every 4KB (the smallest PRG/CHR window of
+// the boards we know)
+const CARTRIDGE_GRANULE: u16 = 0x1000;
+
+const PPU_REGISTER_NAMES: [&str; 8] = [
+  "PPUCTRL",
+  "PPUMASK",
+  "PPUSTATUS",
+  "OAMADDR",
+  "OAMDATA",
+  "PPUSCROLL",
+  "PPUADDR",
+  "PPUDATA",
+];
+
+fn bank_description(kind: &str, bank: Option<Bank>, mapper: &dyn Mapper) -> String {
+  match bank {
+    Some(bank) => format!(
+      "{} bank {} ({}KB, {})",
+      kind,
+      bank.index,
+      bank.size / 1024,
+      mapper.name()
+    ),
+    None => format!("{} ({})", kind, mapper.name()),
+  }
+}
+
+fn cpu_region(bus: &Bus, addr: u16) -> Region {
+  let (start, end, description) = match addr {
+    0x0000..=0x07FF => (0x0000, 0x07FF, "RAM".to_string()),
+    0x0800..=0x1FFF => (0x0800, 0x1FFF, "RAM mirror".to_string()),
+    0x2000..=0x2007 => (0x2000, 0x2007, "PPU registers".to_string()),
+    0x2008..=0x3FFF => (0x2008, 0x3FFF, "PPU register mirrors".to_string()),
+    0x4000..=0x4013 => (0x4000, 0x4013, "APU registers".to_string()),
+    0x4014 => (0x4014, 0x4014, "OAMDMA".to_string()),
+    0x4015 => (0x4015, 0x4015, "APU status".to_string()),
+    0x4016 => (0x4016, 0x4016, "JOY1".to_string()),
+    0x4017 => (0x4017, 0x4017, "JOY2 / APU frame counter".to_string()),
+    0x4018..=0x401F => (0x4018, 0x401F, "APU and I/O test registers".to_string()),
+    0x4020..=0x5FFF => (0x4020, 0x5FFF, "cartridge expansion".to_string()),
+    0x6000..=0x7FFF => (0x6000, 0x7FFF, "PRG RAM".to_string()),
+    _ => {
+      let start = addr & !(CARTRIDGE_GRANULE - 1);
+      let description = match bus.mapper() {
+        Some(mapper) => bank_description("PRG ROM", mapper.prg_bank(addr), mapper),
+        None => "empty cartridge slot".to_string(),
+      };
+      (start, start + (CARTRIDGE_GRANULE - 1), description)
+    }
+  };
+  Region {
+    start,
+    end,
+    description,
+  }
+}
+
+fn ppu_region(bus: &Bus, addr: u16) -> Region {
+  let addr = addr & 0x3FFF;
+  let (start, end, description) = match addr {
+    0x0000..=0x1FFF => {
+      let start = addr & !(CARTRIDGE_GRANULE - 1);
+      let table = addr / 0x1000;
+      let description = match bus.mapper() {
+        Some(mapper) => bank_description(
+          &format!("pattern table {}, CHR", table),
+          mapper.chr_bank(addr),
+          mapper,
+        ),
+        None => format!("pattern table {}, empty cartridge slot", table),
+      };
+      (start, start + (CARTRIDGE_GRANULE - 1), description)
+    }
+    0x2000..=0x3EFF => {
+      let mirror = addr >= 0x3000;
+      let index = ((addr - 0x2000) % 0x1000) / 0x400;
+      let start = 0x2000 + mirror as u16 * 0x1000 + index * 0x400;
+      let page = match bus.mapper() {
+        Some(mapper) => mapper.nametables().pages[index as usize],
+        None => NametablePage::Ciram((index >> 1) as u8),
+      };
+      let backing = match page {
+        NametablePage::Ciram(half) => format!("CIRAM {}", if half == 0 { "A" } else { "B" }),
+        NametablePage::Cartridge(page) => format!("cartridge VRAM page {}", page),
+      };
+      let end = if mirror && index == 3 {
+        0x3EFF
+      } else {
+        start + 0x3FF
+      };
+      let description = if mirror {
+        format!("nametable {} mirror, {}", index, backing)
+      } else {
+        format!("nametable {}, {}", index, backing)
+      };
+      (start, end, description)
+    }
+    0x3F00..=0x3F1F => (0x3F00, 0x3F1F, "palette RAM".to_string()),
+    _ => (0x3F20, 0x3FFF, "palette RAM mirrors".to_string()),
+  };
+  Region {
+    start,
+    end,
+    description,
+  }
+}
+
+fn regions(region_at: impl Fn(u16) -> Region, last: u16) -> Vec<Region> {
+  let mut map: Vec<Region> = Vec::new();
+  let mut addr = 0u32;
+  while addr <= last as u32 {
+    let region = region_at(addr as u16);
+    addr = region.end as u32 + 1;
+    match map.last_mut() {
+      // neighbouring windows of the same bank read as one region
+      Some(previous) if previous.description == region.description => previous.end = region.end,
+      _ => map.push(region),
+    }
+  }
+  map
+}
+
+/// The whole CPU address space, $0000-$FFFF, as the bus routes it right now.
+pub fn cpu_map(bus: &Bus) -> Vec<Region> {
+  regions(|addr| cpu_region(bus, addr), 0xFFFF)
+}
+
+/// The PPU address space, $0000-$3FFF.
+pub fn ppu_map(bus: &Bus) -> Vec<Region> {
+  regions(|addr| ppu_region(bus, addr), 0x3FFF)
+}
+
+/// Label for one CPU address, e.g. "PPUSTATUS" or "RAM mirror of $0012".
+pub fn describe_cpu_address(bus: &Bus, addr: u16) -> String {
+  match addr {
+    0x0800..=0x1FFF => format!("RAM mirror of ${:04X}", addr & 0x07FF),
+    0x2000..=0x3FFF => {
+      let name = PPU_REGISTER_NAMES[(addr & 0x7) as usize];
+      if addr < 0x2008 {
+        name.to_string()
+      } else {
+        format!("{} mirror", name)
+      }
+    }
+    _ => cpu_region(bus, addr).description,
+  }
+}
+
+/// Label for one PPU address.
+pub fn describe_ppu_address(bus: &Bus, addr: u16) -> String {
+  ppu_region(bus, addr).description
+}
@@ -0,0 +1,393 @@
+use crate::nes::bus::Mem;
+use crate::nes::cpu::CPU;
+use crate::nes::joypad::JoypadButton;
+use crate::nes::savestate::{StateError, StateReader, StateWriter};
+use std::fmt;
+
+/*
+  Movies: the controller input of every frame, replayed from a known start.
+
+  Emulation is deterministic, so the same start and the same buttons on the
+  same frames make the same game. A movie starts at power-on, like the TAS
+  movies people share, or from a savestate of this emulator.
+
+  FM2, the FCEUX text format, is what they're exchanged as: `key value`
+  header lines, then one line per frame, starting with the first frame
+  after power-on:
+
+    |commands|port0|port1|port2|
+
+  `commands` is a bit mask (1 reset, 2 power cycle, FDS and VS System
+  switches above) and each controller is 8 columns, RLDUTSBA, `.` or a
+  space for a button that's up. With a Four Score there are four
+  controller columns before port2, which is the Famicom expansion port and
+  always empty here. A movie from a savestate of ours keeps it under
+  `flemuSavestate`, which other emulators won't know about.
+
+  Ours also keep the controllers' shift registers as recording started,
+  strobe, bits read and buttons latched, under `flemuControllers` in the
+  savestate format. A game halfway through reading its pads on the first
+  frame then reads the same bits on replay, savestate or not, so
+  segments spliced together keep their input timing.
+*/
+
+/// `MovieFrame::commands` bit: press reset before the frame.
+pub const RESET: u8 = 1;
+/// `MovieFrame::commands` bit: power cycle. Only the first frame may have
+/// it, where it's the power-on every FM2 movie starts with anyway.
+pub const POWER: u8 = 2;
+
+// FM2's columns, left to right
+const FM2_BUTTONS: [JoypadButton; 8] = [
+  JoypadButton::RIGHT,
+  JoypadButton::LEFT,
+  JoypadButton::DOWN,
+  JoypadButton::UP,
+  JoypadButton::START,
+  JoypadButton::SELECT,
+  JoypadButton::B,
+  JoypadButton::A,
+];
+const FM2_VERSION: &str = "3";
+// what FM2 calls a standard controller in port0/port1
+const FM2_GAMEPAD: &str = "1";
+// header keys written from the movie's fields, not kept as they are
+const FM2_FIELDS: [&str; 10] = [
+  "version",
+  "rerecordCount",
+  "palFlag",
+  "fourscore",
+  "port0",
+  "port1",
+  "port2",
+  "binary",
+  "flemuSavestate",
+  "flemuControllers",
+];
+const BASE64_PREFIX: &str = "base64:";
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MovieError {
+  /// Line `line` (from 1) is neither a header nor a frame.
+  Syntax { line: usize },
+  /// Valid FM2 asking for something that can't be replayed here: the
+  /// binary variant, FCEUX savestates, a Zapper, FDS or VS switches.
+  Unsupported(String),
+}
+
+impl fmt::Display for MovieError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      MovieError::Syntax { line } => write!(f, "bad movie line {}", line),
+      MovieError::Unsupported(what) => write!(f, "movie needs {}, which isn't supported", what),
+    }
+  }
+}
+
+impl std::error::Error for MovieError {}
+
+/// Where playback begins.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MovieStart {
+  PowerOn,
+  /// An `Emulator::save_state` snapshot.
+  State(Vec<u8>),
+}
+
+/// The input of one frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MovieFrame {
+  pub buttons: [JoypadButton; 4],
+  /// `RESET` and `POWER` bits.
+  pub commands: u8,
+}
+
+impl Default for MovieFrame {
+  fn default() -> Self {
+    MovieFrame {
+      buttons: [JoypadButton::empty(); 4],
+      commands: 0,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+  pub start: MovieStart,
+  pub frames: Vec<MovieFrame>,
+  /// Players 3 and 4 are plugged in through a Four Score.
+  pub four_score: bool,
+  /// Recorded on a PAL console.
+  pub pal: bool,
+  /// How many times the author went back to a savestate while making it.
+  pub rerecords: u32,
+  /// The controller ports as a savestate has them, from
+  /// `latch_controllers`; None for movies from elsewhere.
+  pub controllers: Option<Vec<u8>>,
+  /// Header lines carried over as they were read: romFilename, guid,
+  /// comments and the like.
+  pub header: Vec<(String, String)>,
+}
+
+impl Movie {
+  /// An empty movie to `record` into.
+  pub fn new(start: MovieStart, four_score: bool, pal: bool) -> Self {
+    Movie {
+      start,
+      frames: Vec::new(),
+      four_score,
+      pal,
+      rerecords: 0,
+      controllers: None,
+      header: Vec::new(),
+    }
+  }
+
+  /// Keep the controllers' shift registers as they are now, where the
+  /// movie starts.
+  pub fn latch_controllers(&mut self, cpu: &CPU) {
+    let mut w = StateWriter::new(cpu.bus.cartridge_checksum());
+    cpu.bus.save_controllers(&mut w);
+    self.controllers = Some(w.finish());
+  }
+
+  /// Put the shift registers back the way `latch_controllers` found them,
+  /// once the console is at the movie's start. Nothing to do for movies
+  /// without them.
+  pub fn restore_controllers(&self, cpu: &mut CPU) -> Result<(), StateError> {
+    if let Some(bytes) = &self.controllers {
+      let mut r = StateReader::new(bytes, cpu.bus.cartridge_checksum())?;
+      cpu.bus.load_controllers(&mut r)?;
+      r.finish()?;
+    }
+    Ok(())
+  }
+
+  pub fn len(&self) -> usize {
+    self.frames.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.frames.is_empty()
+  }
+
+  /// Add what the controllers hold now as the input of the frame about to
+  /// run, with `commands` done before it.
+  pub fn record(&mut self, cpu: &CPU, commands: u8) {
+    let mut frame = MovieFrame {
+      commands,
+      ..MovieFrame::default()
+    };
+    for (buttons, joypad) in frame.buttons.iter_mut().zip(&cpu.bus.joypads) {
+      *buttons = joypad.buttons();
+    }
+    self.frames.push(frame);
+  }
+
+  /// Before running frame `index`: carry out its commands and hold its
+  /// buttons. False, touching nothing, once the movie is over.
+  pub fn play(&self, index: usize, cpu: &mut CPU) -> bool {
+    let frame = match self.frames.get(index) {
+      Some(frame) => frame,
+      None => return false,
+    };
+    if frame.commands & RESET != 0 {
+      // the reset button, as Emulator::reset presses it
+      cpu.reset();
+      cpu.program_counter = cpu.mem_read_u16(0xFFFC);
+    }
+    // turbo presses were recorded as they fired
+    for (joypad, &buttons) in cpu.bus.joypads.iter_mut().zip(&frame.buttons) {
+      joypad.set_button_pressed_status(JoypadButton::all(), false);
+      joypad.set_turbo_pressed_status(JoypadButton::all(), false);
+      joypad.set_button_pressed_status(buttons, true);
+    }
+    true
+  }
+
+  pub fn from_fm2(text: &str) -> Result<Movie, MovieError> {
+    let mut movie = Movie::new(MovieStart::PowerOn, false, false);
+    for (number, line) in text.lines().enumerate() {
+      let number = number + 1;
+      let line = line.trim_end_matches('\r');
+      if line.starts_with('|') {
+        let frame = parse_frame(line, movie.four_score, movie.frames.is_empty())
+          .ok_or(MovieError::Syntax { line: number })?;
+        movie.frames.push(frame);
+        continue;
+      }
+      if line.trim().is_empty() {
+        continue;
+      }
+      let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+      let value = value.trim();
+      let syntax = MovieError::Syntax { line: number };
+      match key {
+        "version" if value != FM2_VERSION => return Err(syntax),
+        "version" => {}
+        "rerecordCount" => movie.rerecords = value.parse().map_err(|_| syntax)?,
+        "palFlag" => movie.pal = flag(value).ok_or(syntax)?,
+        "fourscore" => movie.four_score = flag(value).ok_or(syntax)?,
+        "port0" | "port1" if value != "0" && value != FM2_GAMEPAD => {
+          return Err(MovieError::Unsupported(format!("{} device {}", key, value)))
+        }
+        "port2" if value != "0" => {
+          return Err(MovieError::Unsupported(
+            "a Famicom expansion device".to_string(),
+          ))
+        }
+        "port0" | "port1" | "port2" => {}
+        "binary" => match flag(value) {
+          Some(false) => {}
+          Some(true) => return Err(MovieError::Unsupported("the binary format".to_string())),
+          None => return Err(syntax),
+        },
+        "savestate" => return Err(MovieError::Unsupported("an FCEUX savestate".to_string())),
+        "flemuSavestate" => {
+          let state = value
+            .strip_prefix(BASE64_PREFIX)
+            .and_then(base64_decode)
+            .ok_or(syntax)?;
+          movie.start = MovieStart::State(state);
+        }
+        "flemuControllers" => {
+          let controllers = value
+            .strip_prefix(BASE64_PREFIX)
+            .and_then(base64_decode)
+            .ok_or(syntax)?;
+          movie.controllers = Some(controllers);
+        }
+        _ => movie.header.push((key.to_string(), value.to_string())),
+      }
+    }
+    Ok(movie)
+  }
+
+  pub fn to_fm2(&self) -> String {
+    let mut out = String::new();
+    let mut line = |key: &str, value: &str| {
+      out.push_str(key);
+      out.push(' ');
+      out.push_str(value);
+      out.push('\n');
+    };
+    line("version", FM2_VERSION);
+    line("rerecordCount", &self.rerecords.to_string());
+    line("palFlag", if self.pal { "1" } else { "0" });
+    line("fourscore", if self.four_score { "1" } else { "0" });
+    line("port0", FM2_GAMEPAD);
+    line("port1", FM2_GAMEPAD);
+    line("port2", "0");
+    for (key, value) in self
+      .header
+      .iter()
+      .filter(|(key, _)| !FM2_FIELDS.contains(&key.as_str()))
+    {
+      line(key, value);
+    }
+    if let MovieStart::State(state) = &self.start {
+      line(
+        "flemuSavestate",
+        &format!("{}{}", BASE64_PREFIX, base64_encode(state)),
+      );
+    }
+    if let Some(controllers) = &self.controllers {
+      line(
+        "flemuControllers",
+        &format!("{}{}", BASE64_PREFIX, base64_encode(controllers)),
+      );
+    }
+    let players = if self.four_score { 4 } else { 2 };
+    for frame in &self.frames {
+      out.push_str(&format!("|{}|", frame.commands));
+      for &buttons in &frame.buttons[..players] {
+        for (&button, &letter) in FM2_BUTTONS.iter().zip(b"RLDUTSBA") {
+          out.push(if buttons.contains(button) {
+            letter as char
+          } else {
+            '.'
+          });
+        }
+        out.push('|');
+      }
+      out.push_str("|\n");
+    }
+    out
+  }
+}
+
+fn flag(value: &str) -> Option<bool> {
+  match value {
+    "0" => Some(false),
+    "1" => Some(true),
+    _ => None,
+  }
+}
+
+// `|commands|pad|pad||`; with a Four Score there are four pads
+fn parse_frame(line: &str, four_score: bool, first: bool) -> Option<MovieFrame> {
+  let fields: Vec<&str> = line[1..].split('|').collect();
+  let pads = if four_score { 4 } else { 2 };
+  // the port2 field and the empty one after the closing bar
+  if fields.len() != pads + 3 || !fields[pads + 1].is_empty() || !fields[pads + 2].is_empty() {
+    return None;
+  }
+  let commands: u8 = fields[0].parse().ok()?;
+  if commands & !(RESET | POWER) != 0 || (commands & POWER != 0 && !first) {
+    return None;
+  }
+  let mut frame = MovieFrame {
+    commands,
+    ..MovieFrame::default()
+  };
+  for (buttons, field) in frame.buttons.iter_mut().zip(&fields[1..=pads]) {
+    // an unplugged port has an empty field
+    if field.is_empty() {
+      continue;
+    }
+    if field.len() != FM2_BUTTONS.len() {
+      return None;
+    }
+    for (&button, letter) in FM2_BUTTONS.iter().zip(field.bytes()) {
+      buttons.set(button, letter != b'.' && letter != b' ');
+    }
+  }
+  Some(frame)
+}
+
+pub(crate) fn base64_encode(bytes: &[u8]) -> String {
+  let mut out = String::with_capacity((bytes.len() + 2) / 3 * 4);
+  for chunk in bytes.chunks(3) {
+    let n = chunk
+      .iter()
+      .enumerate()
+      .fold(0u32, |n, (i, &byte)| n | (byte as u32) << (16 - 8 * i));
+    for i in 0..4 {
+      if i <= chunk.len() {
+        out.push(BASE64[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+      } else {
+        out.push('=');
+      }
+    }
+  }
+  out
+}
+
+pub(crate) fn base64_decode(text: &str) -> Option<Vec<u8>> {
+  let text = text.trim_end_matches('=');
+  let mut out = Vec::with_capacity(text.len() * 3 / 4);
+  let mut n = 0u32;
+  let mut bits = 0;
+  for letter in text.bytes() {
+    let value = BASE64.iter().position(|&b| b == letter)? as u32;
+    n = n << 6 | value;
+    bits += 6;
+    if bits >= 8 {
+      bits -= 8;
+      out.push((n >> bits) as u8);
+      n &= (1 << bits) - 1;
+    }
+  }
+  Some(out)
+}
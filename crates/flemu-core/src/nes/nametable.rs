@@ -0,0 +1,71 @@
+use crate::nes::cartridge::Mirroring;
+
+/*
+  The PPU sees four 1KB nametables at $2000-$2FFF (mirrored again up to
+  $3EFF), but the console only has 2KB of VRAM (CIRAM). The cartridge
+  decides how they line up: it drives CIRAM A10 from one of the PPU address
+  lines (that's horizontal/vertical mirroring), ties it high or low
+  (single screen), or pulls CIRAM /CE and answers the fetch itself (MMC5
+  ExRAM). Four-screen boards do both: CIRAM for nametables 0 and 1, 2KB of
+  their own for 2 and 3.
+
+      $2000 +------+------+ $2400
+            |  0   |  1   |
+      $2800 +------+------+ $2C00
+            |  2   |  3   |
+            +------+------+
+*/
+
+/// What backs one of the four logical nametables.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NametablePage {
+  /// One of the two 1KB halves of CIRAM, selected by A10.
+  Ciram(u8),
+  /// A 1KB page of memory on the cartridge, CIRAM disabled.
+  Cartridge(u8),
+}
+
+/// Where a nametable access ends up, as a byte offset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NametableTarget {
+  /// Offset into the console's 2KB of CIRAM.
+  Ciram(u16),
+  /// Offset into cartridge nametable memory, see `Mapper::nametable_read`.
+  Cartridge(u16),
+}
+
+/// Routing for the four nametables. Mappers that switch mirroring just
+/// rebuild it, exotic ones can put any page anywhere.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NametableMap {
+  pub pages: [NametablePage; 4],
+}
+
+impl NametableMap {
+  pub fn from_mirroring(mirroring: Mirroring) -> Self {
+    use NametablePage::*;
+    let pages = match mirroring {
+      // A10 = PPU A11
+      Mirroring::Horizontal => [Ciram(0), Ciram(0), Ciram(1), Ciram(1)],
+      // A10 = PPU A10
+      Mirroring::Vertical => [Ciram(0), Ciram(1), Ciram(0), Ciram(1)],
+      Mirroring::SingleScreenLower => [Ciram(0); 4],
+      Mirroring::SingleScreenUpper => [Ciram(1); 4],
+      // CIRAM A10 = PPU A10, CIRAM /CE = PPU A11
+      Mirroring::FourScreen => [Ciram(0), Ciram(1), Cartridge(0), Cartridge(1)],
+    };
+    NametableMap { pages }
+  }
+
+  /// Translate a PPU address in $2000-$3EFF.
+  pub fn translate(&self, addr: u16) -> NametableTarget {
+    // $3000-$3EFF mirrors $2000-$2EFF
+    let addr = (addr - 0x2000) & 0x0FFF;
+    let table = (addr / 0x400) as usize;
+    let offset = addr % 0x400;
+    match self.pages[table] {
+      NametablePage::Ciram(a10) => NametableTarget::Ciram((a10 as u16 & 1) * 0x400 + offset),
+      NametablePage::Cartridge(page) => NametableTarget::Cartridge(page as u16 * 0x400 + offset),
+    }
+  }
+}
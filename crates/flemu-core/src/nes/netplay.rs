@@ -0,0 +1,359 @@
+use crate::nes::cpu::CPU;
+use crate::nes::savestate::{self, StateWriter};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/*
+  Netplay between two consoles running the same game, one controller
+  each, over whatever reliable, ordered channel the frontend has (a WebRTC
+  data channel in browsers).
+
+  Both run the same frames with the same buttons, so they stay in step
+  without sending anything but input. A player's buttons are for `delay`
+  frames after they were pressed, which gives them that long to reach the
+  other side. When they're later than that, the frame runs anyway with a
+  guess, the other player's last known buttons, and each frame is
+  snapshotted before it runs; once the real input for a frame that was
+  guessed wrong comes in, `take_rollback` says where to go back to and
+  the frames since are run again. Neither side gets more than
+  `max_rollback` frames ahead of the input it has, it waits instead.
+
+  The host starts the session by sending its savestate and the delay, so
+  both start from the same machine, whatever their battery saves. Every
+  `CHECKSUM_INTERVAL` frames each side sends a checksum of the machine at
+  the start of that frame once the input before it is final; a mismatch
+  is a desync, from which nothing can be trusted.
+
+  Messages, a tag byte and then little-endian fields:
+
+    0  state     delay u8, savestate
+    1  input     frame u32, buttons u8
+    2  checksum  frame u32, checksum u32
+*/
+
+/// Frames between desync checks, a second at 60fps.
+pub const CHECKSUM_INTERVAL: u32 = 60;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NetplayError {
+  /// Not a message of ours, or cut short.
+  BadMessage,
+  /// The consoles disagree about the machine at the start of `frame`.
+  Desync { frame: u32 },
+  /// The input for `frame` came in too late to go back for.
+  TooLate { frame: u32 },
+}
+
+impl fmt::Display for NetplayError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      NetplayError::BadMessage => write!(f, "bad netplay message"),
+      NetplayError::Desync { frame } => write!(f, "netplay desynced at frame {}", frame),
+      NetplayError::TooLate { frame } => {
+        write!(f, "netplay input for frame {} came too late", frame)
+      }
+    }
+  }
+}
+
+impl std::error::Error for NetplayError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+  /// From the host, what the session starts from.
+  State {
+    delay: u8,
+    state: Vec<u8>,
+  },
+  /// A player's buttons for `frame`, bit 0 A to bit 7 Right.
+  Input {
+    frame: u32,
+    buttons: u8,
+  },
+  Checksum {
+    frame: u32,
+    checksum: u32,
+  },
+}
+
+fn le32(bytes: &[u8]) -> Result<u32, NetplayError> {
+  match bytes {
+    [a, b, c, d, ..] => Ok(u32::from_le_bytes([*a, *b, *c, *d])),
+    _ => Err(NetplayError::BadMessage),
+  }
+}
+
+impl Message {
+  pub fn to_bytes(&self) -> Vec<u8> {
+    match self {
+      Message::State { delay, state } => [&[0, *delay][..], state].concat(),
+      Message::Input { frame, buttons } => {
+        let mut bytes = vec![1];
+        bytes.extend_from_slice(&frame.to_le_bytes());
+        bytes.push(*buttons);
+        bytes
+      }
+      Message::Checksum { frame, checksum } => {
+        let mut bytes = vec![2];
+        bytes.extend_from_slice(&frame.to_le_bytes());
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+        bytes
+      }
+    }
+  }
+
+  pub fn from_bytes(bytes: &[u8]) -> Result<Message, NetplayError> {
+    match bytes {
+      [0, delay, state @ ..] => Ok(Message::State {
+        delay: *delay,
+        state: state.to_vec(),
+      }),
+      [1, rest @ ..] if rest.len() == 5 => Ok(Message::Input {
+        frame: le32(rest)?,
+        buttons: rest[4],
+      }),
+      [2, rest @ ..] if rest.len() == 8 => Ok(Message::Checksum {
+        frame: le32(rest)?,
+        checksum: le32(&rest[4..])?,
+      }),
+      _ => Err(NetplayError::BadMessage),
+    }
+  }
+}
+
+/// What desync checks compare: the machine as a savestate would keep it,
+/// which leaves out the picture and sound already made.
+pub fn checksum(cpu: &CPU) -> u32 {
+  let mut w = StateWriter::new(0);
+  cpu.save_state(&mut w);
+  // FNV-1a, as savestates tell cartridges apart with
+  savestate::cartridge_checksum(&w.finish(), &[])
+}
+
+/// One side of a session: whose input each frame runs with, and when to
+/// wait, roll back or check for a desync. Driving the console is up to
+/// the caller.
+#[derive(Debug, Clone)]
+pub struct Netplay {
+  // 0 for the host, on controller 1
+  player: usize,
+  delay: u32,
+  max_rollback: u32,
+  // the joining side has nothing to run until the host's state comes
+  started: bool,
+  // the next frame to run
+  frame: u32,
+  local: BTreeMap<u32, u8>,
+  remote: BTreeMap<u32, u8>,
+  // the first frame whose remote input hasn't come yet
+  remote_next: u32,
+  // what frames run without the remote input guessed it was
+  predicted: BTreeMap<u32, u8>,
+  rollback: Option<u32>,
+  local_checksums: BTreeMap<u32, u32>,
+  remote_checksums: BTreeMap<u32, u32>,
+  // the next frame to send a checksum of
+  next_checksum: u32,
+  desync: Option<u32>,
+}
+
+impl Netplay {
+  /// The side that starts the session, on controller 1, with buttons
+  /// `delay` frames late. It sends `state_message` first.
+  pub fn host(delay: u8, max_rollback: u32) -> Self {
+    let mut netplay = Netplay::join(max_rollback);
+    netplay.player = 0;
+    netplay.started = true;
+    netplay.set_delay(delay);
+    netplay
+  }
+
+  /// The other side, on controller 2, waiting for the host's state.
+  pub fn join(max_rollback: u32) -> Self {
+    Netplay {
+      player: 1,
+      delay: 0,
+      max_rollback: max_rollback.max(1),
+      started: false,
+      frame: 0,
+      local: BTreeMap::new(),
+      remote: BTreeMap::new(),
+      remote_next: 0,
+      predicted: BTreeMap::new(),
+      rollback: None,
+      local_checksums: BTreeMap::new(),
+      remote_checksums: BTreeMap::new(),
+      next_checksum: 0,
+      desync: None,
+    }
+  }
+
+  fn set_delay(&mut self, delay: u8) {
+    // nobody pressed anything before the session
+    self.delay = delay as u32;
+    self.remote_next = self.delay;
+  }
+
+  /// The host's opening message, with the machine both sides start from.
+  pub fn state_message(&self, state: Vec<u8>) -> Message {
+    Message::State {
+      delay: self.delay as u8,
+      state,
+    }
+  }
+
+  /// 0 for the host, 1 for the side that joined, the controller each
+  /// one plays with.
+  pub fn player(&self) -> usize {
+    self.player
+  }
+
+  pub fn delay(&self) -> u32 {
+    self.delay
+  }
+
+  /// The next frame to run, counted from the start of the session.
+  pub fn frame(&self) -> u32 {
+    self.frame
+  }
+
+  /// The first frame the other side's input is still missing for.
+  pub fn remote_frame(&self) -> u32 {
+    self.remote_next
+  }
+
+  /// Where the sides first disagreed, if they did.
+  pub fn desync(&self) -> Option<u32> {
+    self.desync
+  }
+
+  /// Whether the next frame can run: the session started, no desync, and
+  /// not too far ahead of the other side.
+  pub fn ready(&self) -> bool {
+    self.started && self.desync.is_none() && self.frame < self.remote_next + self.max_rollback
+  }
+
+  /// Take in a message from the other side. A state message to a joining
+  /// side comes back out for the caller to load before running anything.
+  pub fn receive(&mut self, message: Message) -> Result<Option<Vec<u8>>, NetplayError> {
+    match message {
+      Message::State { delay, state } if !self.started => {
+        self.started = true;
+        self.set_delay(delay);
+        return Ok(Some(state));
+      }
+      Message::State { .. } => {}
+      Message::Input { frame, buttons } => {
+        if frame != self.remote_next {
+          return Err(NetplayError::BadMessage);
+        }
+        self.remote.insert(frame, buttons);
+        self.remote_next += 1;
+        if let Some(guess) = self.predicted.remove(&frame) {
+          if guess != buttons {
+            if frame + self.max_rollback < self.frame {
+              return Err(NetplayError::TooLate { frame });
+            }
+            self.rollback = Some(self.rollback.map_or(frame, |from| from.min(frame)));
+          }
+        }
+      }
+      Message::Checksum { frame, checksum } => {
+        self.remote_checksums.insert(frame, checksum);
+        self.compare(frame);
+      }
+    }
+    Ok(None)
+  }
+
+  /// The earliest frame run with a wrong guess since the last call.
+  /// Restore the snapshot from before it and run it and every frame up to
+  /// `frame` again with `inputs`.
+  pub fn take_rollback(&mut self) -> Option<u32> {
+    self.rollback.take()
+  }
+
+  /// Buttons for each controller in `frame`, the other side's guessed
+  /// when they haven't come yet.
+  pub fn inputs(&mut self, frame: u32) -> [u8; 2] {
+    let local = self.local.get(&frame).copied().unwrap_or(0);
+    let remote = match self.remote.get(&frame) {
+      Some(&buttons) => buttons,
+      None => {
+        let guess = self
+          .remote
+          .range(..frame)
+          .next_back()
+          .map_or(0, |(_, &buttons)| buttons);
+        self.predicted.insert(frame, guess);
+        guess
+      }
+    };
+    if self.player == 0 {
+      [local, remote]
+    } else {
+      [remote, local]
+    }
+  }
+
+  /// Run the next frame: the buttons held now go in `delay` frames on, in
+  /// the returned message for the other side, followed by what to run
+  /// this frame with. Only when `ready`.
+  pub fn advance(&mut self, buttons: u8) -> (Message, [u8; 2]) {
+    let frame = self.frame + self.delay;
+    self.local.insert(frame, buttons);
+    let inputs = self.inputs(self.frame);
+    self.frame += 1;
+    self.forget();
+    (Message::Input { frame, buttons }, inputs)
+  }
+
+  // what nothing can go back to any more
+  fn forget(&mut self) {
+    let oldest = self
+      .frame
+      .min(self.remote_next)
+      .saturating_sub(self.max_rollback + 1);
+    self.local = self.local.split_off(&oldest);
+    self.remote = self.remote.split_off(&oldest);
+    self.predicted = self.predicted.split_off(&oldest);
+    // a checksum that was never matched up is of no use after a while
+    let oldest = oldest.saturating_sub(4 * CHECKSUM_INTERVAL);
+    self.local_checksums = self.local_checksums.split_off(&oldest);
+    self.remote_checksums = self.remote_checksums.split_off(&oldest);
+  }
+
+  /// The frame to take a checksum of at its start, once the input of
+  /// every frame before it is final and no rollback is waiting.
+  pub fn checksum_due(&self) -> Option<u32> {
+    let frame = self.next_checksum;
+    if frame <= self.frame && frame <= self.remote_next && self.rollback.is_none() {
+      Some(frame)
+    } else {
+      None
+    }
+  }
+
+  /// The checksum of the machine at the start of `checksum_due`'s frame,
+  /// or None if that isn't around any more. Gives the message for the
+  /// other side.
+  pub fn checksum(&mut self, frame: u32, checksum: Option<u32>) -> Option<Message> {
+    self.next_checksum = frame + CHECKSUM_INTERVAL;
+    let checksum = checksum?;
+    self.local_checksums.insert(frame, checksum);
+    self.compare(frame);
+    Some(Message::Checksum { frame, checksum })
+  }
+
+  fn compare(&mut self, frame: u32) {
+    match (
+      self.local_checksums.get(&frame),
+      self.remote_checksums.get(&frame),
+    ) {
+      (Some(local), Some(remote)) if local != remote => {
+        self.desync = Some(self.desync.map_or(frame, |at| at.min(frame)));
+      }
+      _ => {}
+    }
+  }
+}
@@ -0,0 +1,286 @@
+use crate::nes::bus::Mem;
+use crate::nes::cartridge::{HeaderFormat, Mirroring, Region, Rom, RomInfo};
+use crate::nes::cpu::CPU;
+use crate::nes::timing::Timing;
+use std::fmt;
+
+/*
+  NSF, ripped game music: the sound driver and song data of a game, with
+  a header saying where to load it and which two routines to call.
+
+    init  once per song, A = song (from 0), X = 0 on NTSC, 1 on PAL
+    play  at the rate the header gives, 60.1Hz for most tunes
+
+  Both return with RTS. The file is loaded onto an INL-NSF board (mapper
+  31), which has the 4KB bankswitching NSF expects at $5FF8-$5FFF, and
+  `NsfPlayer` takes the place of the game's main loop: it calls the
+  routines by pushing a return address that points at `RETURN`, and
+  while neither runs, lets time pass without executing anything, so the
+  APU keeps playing.
+
+  Tunes for expansion audio (VRC6, FDS, N163, ...) are refused rather
+  than played with channels missing.
+*/
+
+const MAGIC: &[u8; 5] = b"NESM\x1a";
+const HEADER_SIZE: usize = 0x80;
+const BANK_SIZE: usize = 0x1000;
+// where init and play return to, open bus that's never executed
+const RETURN: u16 = 0x4100;
+// the bank registers
+const BANKS: u16 = 0x5FF8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NsfError {
+  /// Missing the `NESM<EOF>` tag.
+  NotNsf,
+  /// Shorter than its header.
+  Truncated,
+  /// The header asks for a sound chip that isn't emulated, e.g. "VRC6".
+  ExpansionAudio(&'static str),
+}
+
+impl fmt::Display for NsfError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      NsfError::NotNsf => write!(f, "file is not in NSF format"),
+      NsfError::Truncated => write!(f, "NSF file is truncated"),
+      NsfError::ExpansionAudio(chip) => write!(f, "{} audio is not supported", chip),
+    }
+  }
+}
+
+impl std::error::Error for NsfError {}
+
+// the header's expansion audio bits
+const CHIPS: [&str; 6] = ["VRC6", "VRC7", "FDS", "MMC5", "Namco 163", "Sunsoft 5B"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Nsf {
+  pub songs: u8,
+  /// The song to start with, from 0.
+  pub first_song: u8,
+  pub load: u16,
+  pub init: u16,
+  pub play: u16,
+  pub title: String,
+  pub artist: String,
+  pub copyright: String,
+  /// Microseconds between play calls on NTSC and on PAL.
+  pub ntsc_speed: u16,
+  pub pal_speed: u16,
+  /// Initial bank of each 4KB of $8000-$FFFF, None for tunes that don't
+  /// bankswitch and are loaded at `load` as they are.
+  pub banks: Option<[u8; 8]>,
+  pub region: Region,
+  pub data: Vec<u8>,
+}
+
+fn text(bytes: &[u8]) -> String {
+  let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+  String::from_utf8_lossy(&bytes[..end]).trim().to_string()
+}
+
+fn le16(bytes: &[u8], at: usize) -> u16 {
+  u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+impl Nsf {
+  pub fn is_nsf(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+  }
+
+  pub fn from_bytes(bytes: &[u8]) -> Result<Nsf, NsfError> {
+    if !Nsf::is_nsf(bytes) {
+      return Err(NsfError::NotNsf);
+    }
+    if bytes.len() < HEADER_SIZE {
+      return Err(NsfError::Truncated);
+    }
+    let chips = bytes[0x7B];
+    if let Some(bit) = (0..CHIPS.len()).find(|bit| chips & 1 << bit != 0) {
+      return Err(NsfError::ExpansionAudio(CHIPS[bit]));
+    }
+    let mut banks = [0; 8];
+    banks.copy_from_slice(&bytes[0x70..0x78]);
+    // NSF2 can say where the data ends, metadata following it
+    let length = bytes[0x7D] as usize | (bytes[0x7E] as usize) << 8 | (bytes[0x7F] as usize) << 16;
+    let data = &bytes[HEADER_SIZE..];
+    let data = match bytes[5] {
+      2 if length > 0 => data.get(..length).ok_or(NsfError::Truncated)?,
+      _ => data,
+    };
+    Ok(Nsf {
+      songs: bytes[6].max(1),
+      first_song: bytes[7].max(1).min(bytes[6].max(1)) - 1,
+      load: le16(bytes, 0x08),
+      init: le16(bytes, 0x0A),
+      play: le16(bytes, 0x0C),
+      title: text(&bytes[0x0E..0x2E]),
+      artist: text(&bytes[0x2E..0x4E]),
+      copyright: text(&bytes[0x4E..0x6E]),
+      ntsc_speed: le16(bytes, 0x6E),
+      pal_speed: le16(bytes, 0x78),
+      banks: if banks == [0; 8] { None } else { Some(banks) },
+      region: match bytes[0x7A] & 0b11 {
+        0 => Region::Ntsc,
+        1 => Region::Pal,
+        _ => Region::MultiRegion,
+      },
+      data: data.to_vec(),
+    })
+  }
+
+  /// The tune as a mapper 31 cartridge: bankswitched data in 4KB banks
+  /// from `load`'s offset in its bank, the rest as one 32KB image.
+  pub fn to_rom(&self) -> Rom {
+    let prg_rom = match self.banks {
+      Some(_) => {
+        let offset = self.load as usize % BANK_SIZE;
+        let size = (offset + self.data.len() + BANK_SIZE - 1) / BANK_SIZE * BANK_SIZE;
+        let mut prg_rom = vec![0; size.max(BANK_SIZE)];
+        prg_rom[offset..offset + self.data.len()].copy_from_slice(&self.data);
+        prg_rom
+      }
+      None => {
+        let mut prg_rom = vec![0; 8 * BANK_SIZE];
+        let offset = (self.load as usize)
+          .saturating_sub(0x8000)
+          .min(prg_rom.len());
+        let len = self.data.len().min(prg_rom.len() - offset);
+        prg_rom[offset..offset + len].copy_from_slice(&self.data[..len]);
+        prg_rom
+      }
+    };
+    Rom {
+      info: RomInfo {
+        format: HeaderFormat::INes,
+        mapper: 31,
+        submapper: 0,
+        mirroring: Mirroring::Horizontal,
+        battery: false,
+        trainer: false,
+        region: self.region,
+        prg_rom_size: prg_rom.len(),
+        chr_rom_size: 0,
+        prg_ram_size: 0x2000,
+        prg_nvram_size: 0,
+        chr_ram_size: 0x2000,
+        chr_nvram_size: 0,
+      },
+      prg_rom,
+      chr_rom: Vec::new(),
+    }
+  }
+
+  /// CPU cycles between play calls at `timing`'s clock.
+  pub fn play_period(&self, timing: Timing) -> f64 {
+    let speed = match timing {
+      Timing::Ntsc => self.ntsc_speed,
+      Timing::Pal | Timing::Dendy => self.pal_speed,
+    };
+    let seconds = match speed {
+      // unset, the frame rate
+      0 => 1.0 / timing.frame_rate(),
+      speed => speed as f64 / 1_000_000.0,
+    };
+    seconds * timing.cpu_clock()
+  }
+}
+
+/// Drives a loaded NSF: song selection and the play routine's timing.
+#[derive(Debug, Clone)]
+pub struct NsfPlayer {
+  nsf: Nsf,
+  song: u8,
+  // CPU cycle count the next play call is due at
+  next_play: f64,
+}
+
+impl NsfPlayer {
+  pub fn new(nsf: Nsf) -> Self {
+    let song = nsf.first_song;
+    NsfPlayer {
+      nsf,
+      song,
+      next_play: 0.0,
+    }
+  }
+
+  /// The header, for titles and the song count.
+  pub fn nsf(&self) -> &Nsf {
+    &self.nsf
+  }
+
+  /// The song playing, from 0.
+  pub fn song(&self) -> u8 {
+    self.song
+  }
+
+  /// Start song `song` (from 0, wrapping around the song count) from the
+  /// beginning: clear RAM, silence the APU, reset the banks and call init.
+  /// `cpu` must have `Nsf::to_rom` inserted.
+  pub fn start(&mut self, cpu: &mut CPU, song: u8) {
+    self.song = song % self.nsf.songs;
+    cpu.reset();
+    for addr in (0x0000..0x0800).chain(0x6000..0x8000) {
+      cpu.mem_write(addr, 0);
+    }
+    for addr in 0x4000..0x4014 {
+      cpu.mem_write(addr, 0);
+    }
+    cpu.mem_write(0x4015, 0x00);
+    cpu.mem_write(0x4015, 0x0F);
+    // 4-step frame counter, no IRQ
+    cpu.mem_write(0x4017, 0x40);
+    let banks = self.nsf.banks.unwrap_or([0, 1, 2, 3, 4, 5, 6, 7]);
+    for (i, &bank) in banks.iter().enumerate() {
+      cpu.mem_write(BANKS + i as u16, bank);
+    }
+
+    cpu.register_a = self.song;
+    cpu.register_x = (cpu.bus.timing() != Timing::Ntsc) as u8;
+    call(cpu, self.nsf.init);
+    // play right after init returns
+    self.next_play = cpu.cycles as f64;
+  }
+
+  /// Run until the PPU finishes a frame, calling play whenever it's due
+  /// and nothing else is running. False if the CPU stopped.
+  pub fn run_frame(&mut self, cpu: &mut CPU) -> bool {
+    let period = self.nsf.play_period(cpu.bus.timing());
+    let frame = cpu.bus.ppu.frame_count;
+    while cpu.bus.ppu.frame_count == frame {
+      if cpu.program_counter != RETURN {
+        if !cpu.step_instruction() {
+          return false;
+        }
+        continue;
+      }
+      let now = cpu.cycles as f64;
+      // a savestate or rewind from before the last call
+      if self.next_play > now + period {
+        self.next_play = now;
+      }
+      if now >= self.next_play {
+        // keep the pace, unless play ran so long a whole call was missed
+        self.next_play = (self.next_play + period).max(now);
+        call(cpu, self.nsf.play);
+      } else {
+        cpu.bus.tick(1);
+        cpu.cycles += 1;
+      }
+    }
+    true
+  }
+}
+
+// JSR `addr` from `RETURN`
+fn call(cpu: &mut CPU, addr: u16) {
+  let from = RETURN.wrapping_sub(1);
+  for &byte in &[(from >> 8) as u8, from as u8] {
+    cpu.mem_write(0x0100 | cpu.stack_pointer as u16, byte);
+    cpu.stack_pointer = cpu.stack_pointer.wrapping_sub(1);
+  }
+  cpu.program_counter = addr;
+}
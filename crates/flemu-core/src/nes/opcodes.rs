@@ -1,29 +1,97 @@
 use crate::nes::cpu::AddressingMode;
-use lazy_static::lazy_static;
-use std::collections::HashMap;
 
+const READS_WITH_PAGE_CROSS_PENALTY: &[&str] = &[
+  "ADC", "AND", "CMP", "EOR", "LAX", "LDA", "LDX", "LDY", "NOP", "ORA", "SBC", "LAS",
+];
+
+#[derive(Clone, Copy)]
 pub struct OpCode {
   pub code: u8,
   pub mnemonic: &'static str,
   pub len: u8,
   pub cycles: u8,
   pub mode: AddressingMode,
+  /// Read instructions with indexed addressing take a cycle more when the
+  /// index carries into the next page; writes and read-modify-writes always
+  /// pay it and have it in `cycles` already.
+  pub page_cross_penalty: bool,
 }
 
 impl OpCode {
-  fn new(code: u8, mnemonic: &'static str, len: u8, cycles: u8, mode: AddressingMode) -> Self {
+  /// Undocumented opcodes, only executed when the CPU isn't strict.
+  pub fn is_unofficial(&self) -> bool {
+    self.mnemonic.starts_with('*')
+  }
+
+  const fn new(
+    code: u8,
+    mnemonic: &'static str,
+    len: u8,
+    cycles: u8,
+    mode: AddressingMode,
+  ) -> Self {
+    let indexed = matches!(
+      mode,
+      AddressingMode::Absolute_X | AddressingMode::Absolute_Y | AddressingMode::Indirect_Y
+    );
     OpCode {
       code,
       mnemonic,
       len,
       cycles,
       mode,
+      page_cross_penalty: indexed && reads_with_penalty(mnemonic),
+    }
+  }
+}
+
+// `mnemonic` is one of READS_WITH_PAGE_CROSS_PENALTY, ignoring the `*` of
+// the unofficial ones; byte loops as this runs at compile time
+const fn reads_with_penalty(mnemonic: &str) -> bool {
+  let name = mnemonic.as_bytes();
+  let skip = if !name.is_empty() && name[0] == b'*' {
+    1
+  } else {
+    0
+  };
+  let mut i = 0;
+  while i < READS_WITH_PAGE_CROSS_PENALTY.len() {
+    let read = READS_WITH_PAGE_CROSS_PENALTY[i].as_bytes();
+    if read.len() == name.len() - skip {
+      let mut j = 0;
+      while j < read.len() && read[j] == name[j + skip] {
+        j += 1;
+      }
+      if j == read.len() {
+        return true;
+      }
     }
+    i += 1;
   }
+  false
 }
 
-lazy_static! {
-  pub static ref CPU_OPS_CODES: Vec<OpCode> = vec![
+/// The opcode for `code`, None for the ones that jam the CPU. A table
+/// lookup, as this is on every instruction.
+pub fn lookup(code: u8) -> Option<&'static OpCode> {
+  OPCODES[code as usize].as_ref()
+}
+
+static OPCODES: [Option<OpCode>; 256] = by_code(CPU_OPS_CODES);
+
+// indexed by the opcode byte, at compile time
+const fn by_code(ops: &[OpCode]) -> [Option<OpCode>; 256] {
+  let mut table = [None; 256];
+  let mut i = 0;
+  while i < ops.len() {
+    table[ops[i].code as usize] = Some(ops[i]);
+    i += 1;
+  }
+  table
+}
+
+#[rustfmt::skip]
+pub(crate) const CPU_OPS_CODES: &[OpCode] = &[
     OpCode::new(0x00, "BRK", 1, 7, AddressingMode::NoneAddressing),
     OpCode::new(0xea, "NOP", 1, 2, AddressingMode::NoneAddressing),
 
@@ -217,13 +285,102 @@ lazy_static! {
     OpCode::new(0x68, "PLA", 1, 4, AddressingMode::NoneAddressing),
     OpCode::new(0x08, "PHP", 1, 3, AddressingMode::NoneAddressing),
     OpCode::new(0x28, "PLP", 1, 4, AddressingMode::NoneAddressing),
-  ];
 
-  pub static ref OPCODES_MAP: HashMap<u8, &'static OpCode> = {
-    let mut map = HashMap::new();
-    for cpuop in &*CPU_OPS_CODES {
-        map.insert(cpuop.code, cpuop);
-    }
-    map
-  };
-}
+    /* Unofficial, mnemonics marked with '*' like nestest.log does */
+    OpCode::new(0x1a, "*NOP", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x3a, "*NOP", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x5a, "*NOP", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x7a, "*NOP", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0xda, "*NOP", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0xfa, "*NOP", 1, 2, AddressingMode::NoneAddressing),
+
+    OpCode::new(0x80, "*NOP", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x82, "*NOP", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x89, "*NOP", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xc2, "*NOP", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xe2, "*NOP", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x04, "*NOP", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x44, "*NOP", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x64, "*NOP", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x14, "*NOP", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0x34, "*NOP", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0x54, "*NOP", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0x74, "*NOP", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0xd4, "*NOP", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0xf4, "*NOP", 2, 4, AddressingMode::ZeroPage_X),
+    OpCode::new(0x0c, "*NOP", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x1c, "*NOP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
+    OpCode::new(0x3c, "*NOP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
+    OpCode::new(0x5c, "*NOP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
+    OpCode::new(0x7c, "*NOP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
+    OpCode::new(0xdc, "*NOP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
+    OpCode::new(0xfc, "*NOP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
+
+    OpCode::new(0xa7, "*LAX", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xb7, "*LAX", 2, 4, AddressingMode::ZeroPage_Y),
+    OpCode::new(0xaf, "*LAX", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xbf, "*LAX", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
+    OpCode::new(0xa3, "*LAX", 2, 6, AddressingMode::Indirect_X),
+    OpCode::new(0xb3, "*LAX", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
+
+    OpCode::new(0x87, "*SAX", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x97, "*SAX", 2, 4, AddressingMode::ZeroPage_Y),
+    OpCode::new(0x8f, "*SAX", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x83, "*SAX", 2, 6, AddressingMode::Indirect_X),
+
+    OpCode::new(0xeb, "*SBC", 2, 2, AddressingMode::Immediate),
+
+    OpCode::new(0xc7, "*DCP", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0xd7, "*DCP", 2, 6, AddressingMode::ZeroPage_X),
+    OpCode::new(0xcf, "*DCP", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0xdf, "*DCP", 3, 7, AddressingMode::Absolute_X),
+    OpCode::new(0xdb, "*DCP", 3, 7, AddressingMode::Absolute_Y),
+    OpCode::new(0xc3, "*DCP", 2, 8, AddressingMode::Indirect_X),
+    OpCode::new(0xd3, "*DCP", 2, 8, AddressingMode::Indirect_Y),
+
+    OpCode::new(0xe7, "*ISB", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0xf7, "*ISB", 2, 6, AddressingMode::ZeroPage_X),
+    OpCode::new(0xef, "*ISB", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0xff, "*ISB", 3, 7, AddressingMode::Absolute_X),
+    OpCode::new(0xfb, "*ISB", 3, 7, AddressingMode::Absolute_Y),
+    OpCode::new(0xe3, "*ISB", 2, 8, AddressingMode::Indirect_X),
+    OpCode::new(0xf3, "*ISB", 2, 8, AddressingMode::Indirect_Y),
+
+    OpCode::new(0x07, "*SLO", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x17, "*SLO", 2, 6, AddressingMode::ZeroPage_X),
+    OpCode::new(0x0f, "*SLO", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x1f, "*SLO", 3, 7, AddressingMode::Absolute_X),
+    OpCode::new(0x1b, "*SLO", 3, 7, AddressingMode::Absolute_Y),
+    OpCode::new(0x03, "*SLO", 2, 8, AddressingMode::Indirect_X),
+    OpCode::new(0x13, "*SLO", 2, 8, AddressingMode::Indirect_Y),
+
+    OpCode::new(0x27, "*RLA", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x37, "*RLA", 2, 6, AddressingMode::ZeroPage_X),
+    OpCode::new(0x2f, "*RLA", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x3f, "*RLA", 3, 7, AddressingMode::Absolute_X),
+    OpCode::new(0x3b, "*RLA", 3, 7, AddressingMode::Absolute_Y),
+    OpCode::new(0x23, "*RLA", 2, 8, AddressingMode::Indirect_X),
+    OpCode::new(0x33, "*RLA", 2, 8, AddressingMode::Indirect_Y),
+
+    OpCode::new(0x47, "*SRE", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x57, "*SRE", 2, 6, AddressingMode::ZeroPage_X),
+    OpCode::new(0x4f, "*SRE", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x5f, "*SRE", 3, 7, AddressingMode::Absolute_X),
+    OpCode::new(0x5b, "*SRE", 3, 7, AddressingMode::Absolute_Y),
+    OpCode::new(0x43, "*SRE", 2, 8, AddressingMode::Indirect_X),
+    OpCode::new(0x53, "*SRE", 2, 8, AddressingMode::Indirect_Y),
+
+    OpCode::new(0x67, "*RRA", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x77, "*RRA", 2, 6, AddressingMode::ZeroPage_X),
+    OpCode::new(0x6f, "*RRA", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x7f, "*RRA", 3, 7, AddressingMode::Absolute_X),
+    OpCode::new(0x7b, "*RRA", 3, 7, AddressingMode::Absolute_Y),
+    OpCode::new(0x63, "*RRA", 2, 8, AddressingMode::Indirect_X),
+    OpCode::new(0x73, "*RRA", 2, 8, AddressingMode::Indirect_Y),
+
+    OpCode::new(0x0b, "*ANC", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x2b, "*ANC", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x4b, "*ALR", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x6b, "*ARR", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xcb, "*AXS", 2, 2, AddressingMode::Immediate),
+];
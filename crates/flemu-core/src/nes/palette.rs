@@ -0,0 +1,384 @@
+use crate::nes::ppu::Frame;
+use std::fmt;
+
+/*
+  The PPU outputs 6-bit color indexes, not RGB. What they look like depends
+  on the TV decoding the composite signal, so emulators ship a palette
+  mapping the 64 indexes to RGB. .pal files are that table as 64 RGB
+  triples, optionally followed by 7 more tables for the emphasis bits.
+
+  The three PPUMASK emphasis bits darken the picture on the 2C02 by
+  attenuating the signal during two thirds of the color cycle, each
+  bit roughly keeping its own channel and dimming the other two. A
+  palette without emphasis tables gets them computed that way;
+  greyscale is applied by the PPU itself, before the palette lookup.
+*/
+pub const COLORS: usize = 64;
+/// One table of `COLORS` for every combination of the emphasis bits.
+pub const EMPHASIS_TABLES: usize = 8;
+const PAL_FILE_SIZE: usize = COLORS * 3;
+const PAL_FILE_WITH_EMPHASIS_SIZE: usize = PAL_FILE_SIZE * EMPHASIS_TABLES;
+// what one emphasis bit leaves of the channels it doesn't emphasize
+const EMPHASIS_ATTENUATION: f32 = 0.816;
+
+/// FCEUX's default palette.
+#[rustfmt::skip]
+const FCEUX: [u32; COLORS] = [
+  0x757575, 0x271B8F, 0x0000AB, 0x47009F, 0x8F0077, 0xAB0013, 0xA70000, 0x7F0B00,
+  0x432F00, 0x004700, 0x005100, 0x003F17, 0x1B3F5F, 0x000000, 0x000000, 0x000000,
+  0xBCBCBC, 0x0073EF, 0x233BEF, 0x8300F3, 0xBF00BF, 0xE7005B, 0xDB2B00, 0xCB4F0F,
+  0x8B7300, 0x009700, 0x00AB00, 0x00933B, 0x00838B, 0x000000, 0x000000, 0x000000,
+  0xFFFFFF, 0x3FBFFF, 0x5F97FF, 0xA78BFD, 0xF77BFF, 0xFF77B7, 0xFF7763, 0xFF9B3B,
+  0xF3BF3F, 0x83D313, 0x4FDF4B, 0x58F898, 0x00EBDB, 0x000000, 0x000000, 0x000000,
+  0xFFFFFF, 0xABE7FF, 0xC7D7FF, 0xD7CBFF, 0xFFC7FF, 0xFFC7DB, 0xFFBFB3, 0xFFDBAB,
+  0xFFE7A3, 0xE3FFA3, 0xABF3BF, 0xB3FFCF, 0x9FFFF3, 0x000000, 0x000000, 0x000000,
+];
+
+/// As decoded by the Sony CXA2025AS found in many US TVs.
+#[rustfmt::skip]
+const SONY_CXA: [u32; COLORS] = [
+  0x585858, 0x00238C, 0x00139B, 0x2D0585, 0x5D0052, 0x7A0017, 0x7A0800, 0x5F1800,
+  0x352A00, 0x093900, 0x003F00, 0x003C22, 0x00325D, 0x000000, 0x000000, 0x000000,
+  0xA1A1A1, 0x0053EE, 0x153CFE, 0x6028E4, 0xA91D98, 0xD41E41, 0xD22C00, 0xAA4400,
+  0x6C5E00, 0x2D7300, 0x007D06, 0x007852, 0x0069A9, 0x000000, 0x000000, 0x000000,
+  0xFFFFFF, 0x1FA5FE, 0x5E89FE, 0xB572FE, 0xFE65F6, 0xFE6790, 0xFE773C, 0xFE9308,
+  0xC4B200, 0x79CA10, 0x3AD54A, 0x11D1A4, 0x06BFFE, 0x424242, 0x000000, 0x000000,
+  0xFFFFFF, 0xA0D9FE, 0xBDCCFE, 0xE1C2FE, 0xFEBCFB, 0xFEBDD0, 0xFEC5A9, 0xFED18E,
+  0xE9DE86, 0xC7E992, 0xA8EEB0, 0x95ECD9, 0x91E4FE, 0xACACAC, 0x000000, 0x000000,
+];
+
+/// The 2C02 palette from the NESDev wiki.
+#[rustfmt::skip]
+const NESDEV: [u32; COLORS] = [
+  0x626262, 0x001FB2, 0x2404C8, 0x5200B2, 0x730076, 0x800024, 0x730B00, 0x522800,
+  0x244400, 0x005700, 0x005C00, 0x005324, 0x003C76, 0x000000, 0x000000, 0x000000,
+  0xABABAB, 0x0D57FF, 0x4B30FF, 0x8A13FF, 0xBC08D6, 0xD21269, 0xC72E00, 0x9D5400,
+  0x607B00, 0x209800, 0x00A300, 0x009942, 0x007DB4, 0x000000, 0x000000, 0x000000,
+  0xFFFFFF, 0x53AEFF, 0x9085FF, 0xD365FF, 0xFF57FF, 0xFF5DCF, 0xFF7757, 0xFA9E00,
+  0xBDC700, 0x7AE700, 0x43F611, 0x26EF7E, 0x2CD5F6, 0x4E4E4E, 0x000000, 0x000000,
+  0xFFFFFF, 0xB6E1FF, 0xCED1FF, 0xE9C3FF, 0xFFBCFF, 0xFFBDF4, 0xFFC6C3, 0xFFD59A,
+  0xE9E681, 0xCEF481, 0xB6FB9A, 0xA9FAC3, 0xA9F0F4, 0xB8B8B8, 0x000000, 0x000000,
+];
+
+/// Built-in palettes, looked up by the id stored in frontend settings.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PalettePreset {
+  Fceux,
+  SonyCxa,
+  NesDev,
+}
+
+impl PalettePreset {
+  pub const ALL: [PalettePreset; 3] = [
+    PalettePreset::Fceux,
+    PalettePreset::SonyCxa,
+    PalettePreset::NesDev,
+  ];
+
+  pub fn id(self) -> &'static str {
+    match self {
+      PalettePreset::Fceux => "fceux",
+      PalettePreset::SonyCxa => "sony-cxa",
+      PalettePreset::NesDev => "nesdev",
+    }
+  }
+
+  pub fn name(self) -> &'static str {
+    match self {
+      PalettePreset::Fceux => "FCEUX default",
+      PalettePreset::SonyCxa => "Sony CXA2025AS",
+      PalettePreset::NesDev => "NESDev consensus",
+    }
+  }
+
+  pub fn from_id(id: &str) -> Option<PalettePreset> {
+    PalettePreset::ALL
+      .iter()
+      .copied()
+      .find(|preset| preset.id() == id)
+  }
+
+  fn colors(self) -> &'static [u32; COLORS] {
+    match self {
+      PalettePreset::Fceux => &FCEUX,
+      PalettePreset::SonyCxa => &SONY_CXA,
+      PalettePreset::NesDev => &NESDEV,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaletteError {
+  pub size: usize,
+}
+
+impl fmt::Display for PaletteError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "a .pal file has {} or {} bytes, got {}",
+      PAL_FILE_SIZE, PAL_FILE_WITH_EMPHASIS_SIZE, self.size
+    )
+  }
+}
+
+impl std::error::Error for PaletteError {}
+
+/// RGB for each of the 64 NES colors, under each emphasis setting.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+  // table `emphasis` starts at `emphasis * COLORS`; table 0 is the plain one
+  colors: Vec<(u8, u8, u8)>,
+}
+
+impl Default for Palette {
+  fn default() -> Self {
+    Palette::preset(PalettePreset::NesDev)
+  }
+}
+
+impl Palette {
+  pub fn preset(preset: PalettePreset) -> Palette {
+    let colors = preset
+      .colors()
+      .iter()
+      .map(|&hex| ((hex >> 16) as u8, (hex >> 8) as u8, hex as u8))
+      .collect();
+    Palette::with_emphasis(colors)
+  }
+
+  // the 7 emphasized tables computed from the plain one
+  fn with_emphasis(mut colors: Vec<(u8, u8, u8)>) -> Palette {
+    for emphasis in 1..EMPHASIS_TABLES {
+      for color in 0..COLORS {
+        let emphasized = emphasize(colors[color], emphasis as u8);
+        colors.push(emphasized);
+      }
+    }
+    Palette { colors }
+  }
+
+  /// Parse a .pal file, with or without emphasis tables.
+  pub fn from_pal(bytes: &[u8]) -> Result<Palette, PaletteError> {
+    if bytes.len() != PAL_FILE_SIZE && bytes.len() != PAL_FILE_WITH_EMPHASIS_SIZE {
+      return Err(PaletteError { size: bytes.len() });
+    }
+    let colors: Vec<_> = bytes
+      .chunks(3)
+      .map(|chunk| (chunk[0], chunk[1], chunk[2]))
+      .collect();
+    if colors.len() == COLORS {
+      Ok(Palette::with_emphasis(colors))
+    } else {
+      Ok(Palette { colors })
+    }
+  }
+
+  pub fn rgb(&self, color: u8) -> (u8, u8, u8) {
+    self.colors[(color & 0x3F) as usize]
+  }
+
+  /// `color` with PPUMASK emphasis bits `emphasis` (red in bit 0).
+  pub fn emphasized(&self, color: u8, emphasis: u8) -> (u8, u8, u8) {
+    self.colors[(emphasis & 0b111) as usize * COLORS + (color & 0x3F) as usize]
+  }
+
+  /// All `EMPHASIS_TABLES` tables, the plain one first.
+  pub fn colors(&self) -> &[(u8, u8, u8)] {
+    &self.colors
+  }
+
+  pub fn colors_mut(&mut self) -> &mut [(u8, u8, u8)] {
+    &mut self.colors
+  }
+
+  /// RGBA bytes for a frame of color indexes, ready for an ImageData.
+  pub fn to_rgba(&self, frame: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(frame.len() * 4);
+    for &color in frame {
+      let (r, g, b) = self.rgb(color);
+      out.extend_from_slice(&[r, g, b, 0xFF]);
+    }
+    out
+  }
+
+  /// Like `to_rgba`, with every line's emphasis bits applied.
+  pub fn frame_rgba(&self, frame: &Frame) -> Vec<u8> {
+    let mut out = Vec::with_capacity(frame.data.len() * 4);
+    for (line, &emphasis) in frame.data.chunks(Frame::WIDTH).zip(&frame.emphasis) {
+      for &color in line {
+        let (r, g, b) = self.emphasized(color, emphasis);
+        out.extend_from_slice(&[r, g, b, 0xFF]);
+      }
+    }
+    out
+  }
+}
+
+fn emphasize((r, g, b): (u8, u8, u8), emphasis: u8) -> (u8, u8, u8) {
+  let mut channels = [r as f32, g as f32, b as f32];
+  for bit in 0..3 {
+    if emphasis & (1 << bit) == 0 {
+      continue;
+    }
+    for (channel, value) in channels.iter_mut().enumerate() {
+      if channel != bit {
+        *value *= EMPHASIS_ATTENUATION;
+      }
+    }
+  }
+  let [r, g, b] = channels;
+  (r.round() as u8, g.round() as u8, b.round() as u8)
+}
+
+/// Convert an RGB triple to (hue in degrees 0..360, saturation 0..1,
+/// lightness 0..1).
+pub fn rgb_to_hsl((r, g, b): (u8, u8, u8)) -> (f32, f32, f32) {
+  let red = r as f32 / 255.0;
+  let green = g as f32 / 255.0;
+  let blue = b as f32 / 255.0;
+  let max = red.max(green).max(blue);
+  let min = red.min(green).min(blue);
+  let lightness = (max + min) / 2.0;
+  let delta = max - min;
+  if delta <= f32::EPSILON {
+    // grey, hue and saturation are meaningless
+    return (0.0, 0.0, lightness);
+  }
+
+  let saturation = delta / (1.0 - (2.0 * lightness - 1.0).abs());
+  let hue = if (max - red).abs() <= f32::EPSILON {
+    60.0 * (((green - blue) / delta) % 6.0)
+  } else if (max - green).abs() <= f32::EPSILON {
+    60.0 * ((blue - red) / delta + 2.0)
+  } else {
+    60.0 * ((red - green) / delta + 4.0)
+  };
+  let hue = if hue < 0.0 { hue + 360.0 } else { hue };
+  (hue, saturation, lightness)
+}
+
+pub fn hsl_to_rgb((hue, saturation, lightness): (f32, f32, f32)) -> (u8, u8, u8) {
+  let hue = hue.rem_euclid(360.0);
+  let saturation = clamp_unit(saturation);
+  let lightness = clamp_unit(lightness);
+  let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+  let second = chroma * (1.0 - ((hue / 60.0) % 2.0 - 1.0).abs());
+  let base = lightness - chroma / 2.0;
+  let (red, green, blue) = match hue as u32 {
+    0..=59 => (chroma, second, 0.0),
+    60..=119 => (second, chroma, 0.0),
+    120..=179 => (0.0, chroma, second),
+    180..=239 => (0.0, second, chroma),
+    240..=299 => (second, 0.0, chroma),
+    _ => (chroma, 0.0, second),
+  };
+  (
+    to_byte(red + base),
+    to_byte(green + base),
+    to_byte(blue + base),
+  )
+}
+
+/// Shift every channel by `amount` (-1..1 of full scale).
+pub fn adjust_brightness((r, g, b): (u8, u8, u8), amount: f32) -> (u8, u8, u8) {
+  let shift = |c: u8| to_byte(c as f32 / 255.0 + amount);
+  (shift(r), shift(g), shift(b))
+}
+
+/// Scale the distance of every channel from mid grey, 1.0 leaves the color
+/// untouched.
+pub fn adjust_contrast((r, g, b): (u8, u8, u8), factor: f32) -> (u8, u8, u8) {
+  let scale = |c: u8| to_byte((c as f32 / 255.0 - 0.5) * factor + 0.5);
+  (scale(r), scale(g), scale(b))
+}
+
+/// Scale the HSL saturation, 0.0 gives greyscale and 1.0 leaves the color
+/// untouched.
+pub fn adjust_saturation(rgb: (u8, u8, u8), factor: f32) -> (u8, u8, u8) {
+  let (h, s, l) = rgb_to_hsl(rgb);
+  hsl_to_rgb((h, s * factor, l))
+}
+
+/// Rotate the hue by `degrees`.
+pub fn rotate_hue(rgb: (u8, u8, u8), degrees: f32) -> (u8, u8, u8) {
+  let (h, s, l) = rgb_to_hsl(rgb);
+  hsl_to_rgb((h + degrees, s, l))
+}
+
+/// Linear mix of two colors, `t` = 0.0 gives `a` and 1.0 gives `b`.
+pub fn blend(a: (u8, u8, u8), b: (u8, u8, u8), t: f32) -> (u8, u8, u8) {
+  let t = clamp_unit(t);
+  let mix = |x: u8, y: u8| to_byte((x as f32 * (1.0 - t) + y as f32 * t) / 255.0);
+  (mix(a.0, b.0), mix(a.1, b.1), mix(a.2, b.2))
+}
+
+/// Display tuning applied on top of a palette, so frontends can offer
+/// "warm" or "vivid" looks without shipping extra .pal files.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ColorAdjustments {
+  pub brightness: f32,
+  pub contrast: f32,
+  pub saturation: f32,
+  pub hue: f32,
+  /// color blended over the result, with its strength 0..1
+  pub tint: (u8, u8, u8),
+  pub tint_strength: f32,
+}
+
+impl Default for ColorAdjustments {
+  fn default() -> Self {
+    ColorAdjustments {
+      brightness: 0.0,
+      contrast: 1.0,
+      saturation: 1.0,
+      hue: 0.0,
+      tint: (255, 255, 255),
+      tint_strength: 0.0,
+    }
+  }
+}
+
+impl ColorAdjustments {
+  pub fn warm() -> Self {
+    ColorAdjustments {
+      tint: (255, 160, 60),
+      tint_strength: 0.12,
+      ..ColorAdjustments::default()
+    }
+  }
+
+  pub fn vivid() -> Self {
+    ColorAdjustments {
+      contrast: 1.1,
+      saturation: 1.3,
+      ..ColorAdjustments::default()
+    }
+  }
+
+  pub fn apply(&self, rgb: (u8, u8, u8)) -> (u8, u8, u8) {
+    let mut out = rgb;
+    if self.hue != 0.0 || (self.saturation - 1.0).abs() > f32::EPSILON {
+      let (h, s, l) = rgb_to_hsl(out);
+      out = hsl_to_rgb((h + self.hue, s * self.saturation, l));
+    }
+    out = adjust_contrast(out, self.contrast);
+    out = adjust_brightness(out, self.brightness);
+    blend(out, self.tint, self.tint_strength)
+  }
+
+  /// Adjust every entry of a palette in place.
+  pub fn apply_palette(&self, palette: &mut [(u8, u8, u8)]) {
+    for entry in palette.iter_mut() {
+      *entry = self.apply(*entry);
+    }
+  }
+}
+
+fn clamp_unit(v: f32) -> f32 {
+  v.clamp(0.0, 1.0)
+}
+
+fn to_byte(v: f32) -> u8 {
+  (clamp_unit(v) * 255.0).round() as u8
+}
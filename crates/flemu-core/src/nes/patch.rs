@@ -0,0 +1,292 @@
+use std::fmt;
+
+/*
+  ROM patches, applied in memory before the cartridge is parsed.
+
+  IPS: "PATCH", then records until "EOF":
+    3 bytes offset (big endian), 2 bytes size, `size` bytes of data
+    a size of 0 is an RLE record: 2 bytes run length, 1 byte value
+  optionally followed by 3 bytes to truncate the output to.
+
+  BPS: "BPS1", source size, target size, metadata size (all varints), the
+  metadata, then actions up to a 12-byte footer with the CRC32 of the
+  source, the target and the patch itself. Each action is a varint:
+  (length - 1) << 2 | command
+
+    0 SourceRead  copy from the source at the output position
+    1 TargetRead  copy from the patch
+    2 SourceCopy  copy from a relative offset in the source
+    3 TargetCopy  copy from a relative offset in the output so far
+*/
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const BPS_MAGIC: &[u8] = b"BPS1";
+const BPS_FOOTER_SIZE: usize = 12;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatchError {
+  /// Neither an IPS nor a BPS file.
+  UnknownFormat,
+  /// The patch ends in the middle of a record.
+  Truncated,
+  /// A record points outside of the source or the output.
+  OutOfBounds,
+  /// The patch is for a different ROM.
+  SourceMismatch { expected: u32, actual: u32 },
+  /// The patch itself is corrupted.
+  PatchChecksum { expected: u32, actual: u32 },
+  /// Applying went fine but the result isn't what the patch promised.
+  TargetChecksum { expected: u32, actual: u32 },
+}
+
+impl fmt::Display for PatchError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      PatchError::UnknownFormat => write!(f, "not an IPS or BPS patch"),
+      PatchError::Truncated => write!(f, "patch is truncated"),
+      PatchError::OutOfBounds => write!(f, "patch record is out of bounds"),
+      PatchError::SourceMismatch { expected, actual } => write!(
+        f,
+        "patch is for a different rom: expected crc32 {:08x}, got {:08x}",
+        expected, actual
+      ),
+      PatchError::PatchChecksum { expected, actual } => write!(
+        f,
+        "patch is corrupted: expected crc32 {:08x}, got {:08x}",
+        expected, actual
+      ),
+      PatchError::TargetChecksum { expected, actual } => write!(
+        f,
+        "patched rom is wrong: expected crc32 {:08x}, got {:08x}",
+        expected, actual
+      ),
+    }
+  }
+}
+
+impl std::error::Error for PatchError {}
+
+/// Apply an IPS or BPS patch to `rom`, picking the format from its magic.
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+  if patch.starts_with(IPS_MAGIC) {
+    apply_ips(rom, patch)
+  } else if patch.starts_with(BPS_MAGIC) {
+    apply_bps(rom, patch)
+  } else {
+    Err(PatchError::UnknownFormat)
+  }
+}
+
+pub fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+  if !patch.starts_with(IPS_MAGIC) {
+    return Err(PatchError::UnknownFormat);
+  }
+
+  let mut out = rom.to_vec();
+  let mut reader = Reader::new(patch, IPS_MAGIC.len());
+  loop {
+    let record = reader.bytes(3)?;
+    if record == IPS_EOF {
+      break;
+    }
+    let offset = be(record);
+    let size = be(reader.bytes(2)?);
+    let (data, size) = if size == 0 {
+      let run = be(reader.bytes(2)?);
+      (None, run)
+    } else {
+      (Some(reader.bytes(size)?), size)
+    };
+
+    if out.len() < offset + size {
+      out.resize(offset + size, 0);
+    }
+    match data {
+      Some(data) => out[offset..offset + size].copy_from_slice(data),
+      None => {
+        let value = reader.byte()?;
+        for byte in out[offset..offset + size].iter_mut() {
+          *byte = value;
+        }
+      }
+    }
+  }
+
+  // truncation extension
+  if let Ok(size) = reader.bytes(3) {
+    out.truncate(be(size));
+  }
+  Ok(out)
+}
+
+pub fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+  if !patch.starts_with(BPS_MAGIC) {
+    return Err(PatchError::UnknownFormat);
+  }
+  if patch.len() < BPS_MAGIC.len() + BPS_FOOTER_SIZE {
+    return Err(PatchError::Truncated);
+  }
+
+  let footer = &patch[patch.len() - BPS_FOOTER_SIZE..];
+  let source_crc = le(&footer[0..4]);
+  let target_crc = le(&footer[4..8]);
+  let patch_crc = le(&footer[8..12]);
+
+  let actual = crc32(&patch[..patch.len() - 4]);
+  if actual != patch_crc {
+    return Err(PatchError::PatchChecksum {
+      expected: patch_crc,
+      actual,
+    });
+  }
+  let actual = crc32(rom);
+  if actual != source_crc {
+    return Err(PatchError::SourceMismatch {
+      expected: source_crc,
+      actual,
+    });
+  }
+
+  let actions = &patch[..patch.len() - BPS_FOOTER_SIZE];
+  let mut reader = Reader::new(actions, BPS_MAGIC.len());
+  let _source_size = reader.varint()?;
+  let target_size = reader.varint()?;
+  let metadata_size = reader.varint()?;
+  reader.bytes(metadata_size)?;
+
+  let mut out: Vec<u8> = Vec::with_capacity(target_size);
+  let mut source_offset: isize = 0;
+  let mut target_offset: isize = 0;
+  while !reader.at_end() {
+    let action = reader.varint()?;
+    let length = (action >> 2) + 1;
+    match action & 0b11 {
+      // SourceRead
+      0 => {
+        let start = out.len();
+        let data = rom
+          .get(start..start + length)
+          .ok_or(PatchError::OutOfBounds)?;
+        out.extend_from_slice(data);
+      }
+      // TargetRead
+      1 => out.extend_from_slice(reader.bytes(length)?),
+      // SourceCopy
+      2 => {
+        source_offset += reader.signed_varint()?;
+        let start = usize_offset(source_offset)?;
+        let data = rom
+          .get(start..start + length)
+          .ok_or(PatchError::OutOfBounds)?;
+        out.extend_from_slice(data);
+        source_offset += length as isize;
+      }
+      // TargetCopy, may overlap what it writes so go byte by byte
+      _ => {
+        target_offset += reader.signed_varint()?;
+        for _ in 0..length {
+          let byte = *out
+            .get(usize_offset(target_offset)?)
+            .ok_or(PatchError::OutOfBounds)?;
+          out.push(byte);
+          target_offset += 1;
+        }
+      }
+    }
+  }
+
+  if out.len() != target_size {
+    return Err(PatchError::OutOfBounds);
+  }
+  let actual = crc32(&out);
+  if actual != target_crc {
+    return Err(PatchError::TargetChecksum {
+      expected: target_crc,
+      actual,
+    });
+  }
+  Ok(out)
+}
+
+/// CRC-32 (IEEE), as used by BPS, zip and friends.
+pub fn crc32(data: &[u8]) -> u32 {
+  let mut crc = 0xFFFF_FFFFu32;
+  for byte in data {
+    crc ^= *byte as u32;
+    for _ in 0..8 {
+      let mask = (crc & 1).wrapping_neg();
+      crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+    }
+  }
+  !crc
+}
+
+fn be(bytes: &[u8]) -> usize {
+  bytes.iter().fold(0, |acc, b| (acc << 8) | *b as usize)
+}
+
+fn le(bytes: &[u8]) -> u32 {
+  bytes.iter().rev().fold(0, |acc, b| (acc << 8) | *b as u32)
+}
+
+fn usize_offset(offset: isize) -> Result<usize, PatchError> {
+  if offset < 0 {
+    Err(PatchError::OutOfBounds)
+  } else {
+    Ok(offset as usize)
+  }
+}
+
+struct Reader<'a> {
+  data: &'a [u8],
+  pos: usize,
+}
+
+impl<'a> Reader<'a> {
+  fn new(data: &'a [u8], pos: usize) -> Self {
+    Reader { data, pos }
+  }
+
+  fn at_end(&self) -> bool {
+    self.pos >= self.data.len()
+  }
+
+  fn byte(&mut self) -> Result<u8, PatchError> {
+    Ok(self.bytes(1)?[0])
+  }
+
+  fn bytes(&mut self, count: usize) -> Result<&'a [u8], PatchError> {
+    let bytes = self
+      .data
+      .get(self.pos..self.pos + count)
+      .ok_or(PatchError::Truncated)?;
+    self.pos += count;
+    Ok(bytes)
+  }
+
+  // BPS numbers: 7 bits at a time, the high bit ends the number, and each
+  // continuation adds one to remove duplicate encodings
+  fn varint(&mut self) -> Result<usize, PatchError> {
+    let mut data: usize = 0;
+    let mut shift: usize = 1;
+    loop {
+      let x = self.byte()?;
+      data = data
+        .checked_add((x & 0x7f) as usize * shift)
+        .ok_or(PatchError::OutOfBounds)?;
+      if x & 0x80 != 0 {
+        return Ok(data);
+      }
+      shift = shift.checked_shl(7).ok_or(PatchError::OutOfBounds)?;
+      data = data.checked_add(shift).ok_or(PatchError::OutOfBounds)?;
+    }
+  }
+
+  // relative offsets: the low bit is the sign
+  fn signed_varint(&mut self) -> Result<isize, PatchError> {
+    let data = self.varint()?;
+    let magnitude = (data >> 1) as isize;
+    Ok(if data & 1 != 0 { -magnitude } else { magnitude })
+  }
+}
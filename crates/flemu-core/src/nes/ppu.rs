@@ -0,0 +1,922 @@
+use crate::nes::mapper::{Mapper, TileFetch};
+use crate::nes::nametable::NametableTarget;
+use crate::nes::savestate::{StateError, StateReader, StateWriter};
+use crate::nes::timing::Timing;
+use log::trace;
+
+mod frame;
+mod registers;
+
+pub use frame::Frame;
+pub use registers::{ControlRegister, MaskRegister, StatusRegister};
+
+pub const DOTS_PER_SCANLINE: u16 = 341;
+/// Of an NTSC frame; see `Timing::scanlines` for the others.
+pub const SCANLINES_PER_FRAME: u16 = 262;
+const SPRITES_PER_LINE: usize = 8;
+const SPRITES: usize = 64;
+// frames a bit of the I/O latch holds its charge, about 600ms
+const LATCH_DECAY_FRAMES: u64 = 36;
+
+/*
+  PPU memory map:
+
+  $0000-$1FFF  pattern tables, on the cartridge (CHR ROM/RAM via the mapper)
+  $2000-$2FFF  nametables, CIRAM routed by the mapper
+  $3000-$3EFF  mirror of $2000-$2EFF
+  $3F00-$3F1F  palette RAM
+  $3F20-$3FFF  mirrors of $3F00-$3F1F
+
+  Scrolling and PPUADDR share the "loopy" registers:
+
+  v  current VRAM address (15 bits)
+  t  temporary VRAM address (15 bits), the top-left onscreen tile
+  x  fine X scroll (3 bits)
+  w  first/second write toggle, shared by $2005 and $2006
+
+  v and t are laid out as yyy NN YYYYY XXXXX: fine Y, nametable, coarse Y,
+  coarse X.
+
+  Timing (NTSC): 262 scanlines of 341 dots, 3 dots per CPU cycle. PAL and
+  Dendy frames are longer, see `Timing`.
+
+  0-239  visible, background and sprites are drawn a whole line at a time
+         at dot 256; PPUMASK, scroll ($2005 fine X, $2006 v) and palette
+         writes before then are kept with the dot they were made at, and
+         only apply from that pixel on
+  240    post-render, idle
+  241    vblank starts at dot 1, with an NMI if PPUCTRL asks for one
+  261    pre-render, clears the flags at dot 1 and reloads v's vertical
+         scroll from t at dots 280-304; one dot shorter on odd frames
+*/
+/// Debugger switches for what makes it into the picture. Hiding a layer or
+/// a sprite only changes the frame: sprite 0 hits, overflow and everything
+/// else the game can see happen as before.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Layers {
+  pub background: bool,
+  pub sprites: bool,
+  /// Draw at most 8 sprites a line, like the PPU. Off, every sprite on
+  /// the line is drawn and games that flicker sprites to get around the
+  /// limit stop flickering; the overflow flag is still set past the 8th.
+  pub sprite_limit: bool,
+  // bit n hides OAM entry n
+  hidden_sprites: u64,
+}
+
+impl Default for Layers {
+  fn default() -> Self {
+    Layers {
+      background: true,
+      sprites: true,
+      sprite_limit: true,
+      hidden_sprites: 0,
+    }
+  }
+}
+
+impl Layers {
+  /// Show or hide OAM entry `index` (0-63) on its own.
+  pub fn set_sprite_visible(&mut self, index: usize, visible: bool) {
+    if index >= 64 {
+      return;
+    }
+    if visible {
+      self.hidden_sprites &= !(1 << index);
+    } else {
+      self.hidden_sprites |= 1 << index;
+    }
+  }
+
+  pub fn sprite_visible(&self, index: usize) -> bool {
+    self.sprites && index < 64 && self.hidden_sprites & (1 << index) == 0
+  }
+}
+
+#[derive(Clone)]
+pub struct NesPPU {
+  pub palette_table: [u8; 32],
+  pub vram: [u8; 2048],
+  pub oam_addr: u8,
+  pub oam_data: [u8; 256],
+
+  pub ctrl: ControlRegister,
+  pub mask: MaskRegister,
+  pub status: StatusRegister,
+
+  pub v: u16,
+  pub t: u16,
+  pub x: u8,
+  pub w: bool,
+
+  pub scanline: u16,
+  pub cycle: u16,
+  pub frame_count: u64,
+  pub frame: Frame,
+  pub layers: Layers,
+  /// Leave `frame` as it is, for frames that won't be shown while
+  /// fast-forwarding. Only the picture is skipped: sprite 0 hits and
+  /// overflow still happen.
+  pub skip_rendering: bool,
+
+  internal_data_buf: u8,
+  odd_frame: bool,
+  timing: Timing,
+  nmi_pending: bool,
+  suppress_vblank: bool,
+  io_latch: u8,
+  // frame each latch bit was last driven
+  latch_driven: [u64; 8],
+  // PPUMASK writes on the line being drawn: the dot, and the value before
+  mask_writes: Vec<(u16, u8)>,
+  // the same for scroll and palette writes
+  line_writes: Vec<(u16, LineWrite)>,
+}
+
+// what a write on the line being drawn changed, as it was before
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LineWrite {
+  // v and fine X
+  Scroll { v: u16, x: u8 },
+  // a palette RAM entry
+  Palette { index: u8, value: u8 },
+}
+
+impl Default for NesPPU {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl NesPPU {
+  pub fn new() -> Self {
+    NesPPU {
+      palette_table: [0; 32],
+      vram: [0; 2048],
+      oam_addr: 0,
+      oam_data: [0; 64 * 4],
+      ctrl: ControlRegister::from_bits_truncate(0),
+      mask: MaskRegister::from_bits_truncate(0),
+      status: StatusRegister::from_bits_truncate(0),
+      v: 0,
+      t: 0,
+      x: 0,
+      w: false,
+      scanline: 0,
+      cycle: 0,
+      frame_count: 0,
+      frame: Frame::new(),
+      layers: Layers::default(),
+      skip_rendering: false,
+      internal_data_buf: 0,
+      odd_frame: false,
+      timing: Timing::Ntsc,
+      nmi_pending: false,
+      suppress_vblank: false,
+      io_latch: 0,
+      latch_driven: [0; 8],
+      mask_writes: Vec::new(),
+      line_writes: Vec::new(),
+    }
+  }
+
+  /// $2000
+  pub fn write_to_ctrl(&mut self, value: u8) {
+    let nmi_was_enabled = self.ctrl.generate_vblank_nmi();
+    self.ctrl = ControlRegister::from_bits_truncate(value);
+    // enabling NMI during vblank fires one right away
+    if self.ctrl.generate_vblank_nmi() {
+      if !nmi_was_enabled && self.status.contains(StatusRegister::VBLANK_STARTED) {
+        self.nmi_pending = true;
+      }
+    } else {
+      self.nmi_pending = false;
+    }
+    // nametable select goes to t's NN
+    self.t = (self.t & !0x0C00) | ((value as u16 & 0b11) << 10);
+  }
+
+  /// $2001. Pixels of a visible line not yet output when it's written
+  /// get the new value, though the line is drawn later.
+  pub fn write_to_mask(&mut self, value: u8) {
+    if self.mid_line() {
+      self.mask_writes.push((self.cycle, self.mask.bits()));
+    }
+    self.mask = MaskRegister::from_bits_truncate(value);
+  }
+
+  // partway through a visible line, before it's drawn
+  fn mid_line(&self) -> bool {
+    self.scanline < 240 && self.cycle < 256
+  }
+
+  // keep v and fine X as they were for the pixels already output, when
+  // a write moves v or fine X
+  fn latch_scroll(&mut self) {
+    if self.mid_line() {
+      let write = LineWrite::Scroll {
+        v: self.v,
+        x: self.x,
+      };
+      self.line_writes.push((self.cycle, write));
+    }
+  }
+
+  /// $2002, clears vblank and the write toggle. Racing the start of vblank
+  /// loses that frame's NMI: a read one dot early also never sees the
+  /// flag.
+  pub fn read_status(&mut self) -> u8 {
+    if self.scanline == self.timing.vblank_scanline() {
+      match self.cycle {
+        0 => self.suppress_vblank = true,
+        1 | 2 => self.nmi_pending = false,
+        _ => {}
+      }
+    }
+    let data = self.peek_status();
+    self.status.remove(StatusRegister::VBLANK_STARTED);
+    self.w = false;
+    data
+  }
+
+  /// The low 5 bits aren't driven, they come from the I/O latch.
+  pub fn peek_status(&self) -> u8 {
+    self.status.bits() & 0b1110_0000 | self.io_latch() & 0b0001_1111
+  }
+
+  /// $2003
+  pub fn write_to_oam_addr(&mut self, value: u8) {
+    self.oam_addr = value;
+  }
+
+  /// $2004
+  pub fn write_to_oam_data(&mut self, value: u8) {
+    self.oam_data[self.oam_addr as usize] = value;
+    self.oam_addr = self.oam_addr.wrapping_add(1);
+  }
+
+  /// $2004
+  pub fn read_oam_data(&self) -> u8 {
+    self.oam_data[self.oam_addr as usize]
+  }
+
+  /// $4014, a whole page copied in one go starting at OAMADDR
+  pub fn write_oam_dma(&mut self, data: &[u8; 256]) {
+    for x in data.iter() {
+      self.write_to_oam_data(*x);
+    }
+  }
+
+  /// $2005. Fine X takes effect from the next pixel; the rest waits in t.
+  pub fn write_to_scroll(&mut self, value: u8) {
+    if !self.w {
+      self.latch_scroll();
+      self.t = (self.t & !0x001F) | (value as u16 >> 3);
+      self.x = value & 0b111;
+    } else {
+      self.t = (self.t & !0x73E0) | ((value as u16 & 0b111) << 12) | ((value as u16 & 0xF8) << 2);
+    }
+    self.w = !self.w;
+  }
+
+  /// $2006, high byte first; the second write also loads v
+  pub fn write_to_ppu_addr(&mut self, value: u8) {
+    if !self.w {
+      self.t = (self.t & 0x00FF) | ((value as u16 & 0x3F) << 8);
+    } else {
+      self.latch_scroll();
+      self.t = (self.t & 0xFF00) | value as u16;
+      self.v = self.t;
+    }
+    self.w = !self.w;
+  }
+
+  fn increment_vram_addr(&mut self) {
+    self.latch_scroll();
+    self.v = self.v.wrapping_add(self.ctrl.vram_addr_increment()) & 0x7FFF;
+  }
+
+  /// $2007. A palette entry changed partway through a line shows the
+  /// new color from there on.
+  pub fn write_to_data(&mut self, cart: &mut dyn Mapper, value: u8) {
+    if self.v & 0x3F00 == 0x3F00 && self.mid_line() {
+      let index = palette_index(self.v);
+      let write = LineWrite::Palette {
+        index: index as u8,
+        value: self.palette_table[index],
+      };
+      self.line_writes.push((self.cycle, write));
+    }
+    self.write_vram(cart, self.v, value);
+    self.increment_vram_addr();
+  }
+
+  /// $2007. Everything below the palette goes through a one-read delay
+  /// buffer; palette reads are immediate but still refill the buffer with
+  /// the nametable byte "under" them.
+  pub fn read_data(&mut self, cart: &mut dyn Mapper) -> u8 {
+    let addr = self.v & 0x3FFF;
+    self.increment_vram_addr();
+
+    match addr {
+      0..=0x3EFF => {
+        let result = self.internal_data_buf;
+        self.internal_data_buf = self.read_vram(cart, addr);
+        result
+      }
+      _ => {
+        self.internal_data_buf = self.read_vram(cart, addr - 0x1000);
+        self.palette_table[palette_index(addr)]
+      }
+    }
+  }
+
+  /// What the next $2007 read would return, without touching anything.
+  pub fn peek_data(&self) -> u8 {
+    let addr = self.v & 0x3FFF;
+    match addr {
+      0..=0x3EFF => self.internal_data_buf,
+      _ => self.palette_table[palette_index(addr)],
+    }
+  }
+
+  /// Read PPU memory ($0000-$3FFF) directly, without the buffer.
+  pub fn read_vram(&self, cart: &dyn Mapper, addr: u16) -> u8 {
+    let addr = addr & 0x3FFF;
+    match addr {
+      0..=0x1FFF => cart.chr_read(addr),
+      0x2000..=0x3EFF => match cart.nametables().translate(addr) {
+        NametableTarget::Ciram(offset) => self.vram[offset as usize],
+        NametableTarget::Cartridge(offset) => cart.nametable_read(offset),
+      },
+      _ => self.palette_table[palette_index(addr)],
+    }
+  }
+
+  /// Write PPU memory ($0000-$3FFF) directly, leaving `v` alone.
+  pub fn write_vram(&mut self, cart: &mut dyn Mapper, addr: u16, value: u8) {
+    let addr = addr & 0x3FFF;
+    match addr {
+      0..=0x1FFF => cart.chr_write(addr, value),
+      0x2000..=0x3EFF => match cart.nametables().translate(addr) {
+        NametableTarget::Ciram(offset) => self.vram[offset as usize] = value,
+        NametableTarget::Cartridge(offset) => cart.nametable_write(offset, value),
+      },
+      _ => self.palette_table[palette_index(addr)] = value,
+    }
+  }
+
+  pub fn timing(&self) -> Timing {
+    self.timing
+  }
+
+  /// Switch frame layouts. Past the end of the new, shorter frame the
+  /// next one starts right away.
+  pub fn set_timing(&mut self, timing: Timing) {
+    self.timing = timing;
+    if self.scanline >= timing.scanlines() {
+      self.scanline = 0;
+      self.cycle = 0;
+    }
+  }
+
+  /// Advance by `dots` PPU cycles. Returns true if vblank started, i.e. a
+  /// finished picture is in `frame`.
+  pub fn tick(&mut self, cart: &dyn Mapper, dots: u32) -> bool {
+    let mut frame_done = false;
+    for _ in 0..dots {
+      self.cycle += 1;
+      if self.cycle == DOTS_PER_SCANLINE
+        || (self.cycle == DOTS_PER_SCANLINE - 1
+          && self.scanline == self.timing.pre_render_scanline()
+          && self.odd_frame
+          && self.timing.skips_odd_dot()
+          && self.mask.rendering_enabled())
+      {
+        self.cycle = 0;
+        self.scanline += 1;
+        if self.scanline == self.timing.scanlines() {
+          self.scanline = 0;
+          self.odd_frame = !self.odd_frame;
+        }
+      }
+      frame_done |= self.dot(cart);
+    }
+    frame_done
+  }
+
+  fn dot(&mut self, cart: &dyn Mapper) -> bool {
+    let rendering = self.mask.rendering_enabled();
+    let vblank = self.timing.vblank_scanline();
+    let pre_render = self.timing.pre_render_scanline();
+    match (self.scanline, self.cycle) {
+      (0..=239, 256) => {
+        self.render_line(cart);
+        if rendering {
+          self.increment_y();
+        }
+      }
+      (line, 257) if rendering && (line < 240 || line == pre_render) => self.copy_horizontal(),
+      (line, 1) if line == vblank => {
+        if !self.suppress_vblank {
+          self.status.insert(StatusRegister::VBLANK_STARTED);
+          self.nmi_pending = self.ctrl.generate_vblank_nmi();
+        }
+        self.suppress_vblank = false;
+        self.frame_count += 1;
+        return true;
+      }
+      (line, 1) if line == pre_render => {
+        self.status.remove(
+          StatusRegister::VBLANK_STARTED
+            | StatusRegister::SPRITE_ZERO_HIT
+            | StatusRegister::SPRITE_OVERFLOW,
+        );
+      }
+      (line, 280..=304) if rendering && line == pre_render => self.copy_vertical(),
+      _ => {}
+    }
+    false
+  }
+
+  fn render_line(&mut self, cart: &dyn Mapper) {
+    let masks = self.line_masks();
+    self.mask_writes.clear();
+    let scrolls = self.line_scrolls();
+    let line_writes = std::mem::take(&mut self.line_writes);
+    if self.skip_rendering {
+      self.skip_line(cart, &masks, &scrolls);
+      return;
+    }
+    // palette RAM index of every pixel, 0 is the backdrop
+    let mut line = [0u8; Frame::WIDTH];
+    if shown(&masks, MaskRegister::SHOW_BACKGROUND) {
+      self.background_line(cart, &masks, &scrolls, &mut line);
+    }
+    if sprites_evaluated(&masks) {
+      let (sprites, count) = self.evaluate_sprites();
+      if shown(&masks, MaskRegister::SHOW_SPRITES) {
+        self.render_sprites(cart, &sprites[..count], &masks, &mut line);
+      }
+    }
+    if !self.layers.background {
+      // sprite pixels are the ones from the upper half of the palette
+      for index in line.iter_mut().filter(|index| **index < 0x10) {
+        *index = 0;
+      }
+    }
+
+    let y = self.scanline as usize;
+    self.frame.set_emphasis(y, self.mask.emphasis());
+    // right to left, undoing palette writes made after each pixel
+    let mut palette = self.palette_table;
+    let mut writes = line_writes.iter().rev().peekable();
+    for x in (0..Frame::WIDTH).rev() {
+      while let Some((_, write)) = writes.next_if(|&&(dot, _)| dot as usize > x) {
+        if let LineWrite::Palette { index, value } = *write {
+          palette[index as usize] = value;
+        }
+      }
+      // with rendering off the PPU outputs the backdrop, or the palette
+      // entry v points at
+      let index = if masks[x].rendering_enabled() {
+        line[x]
+      } else {
+        match scrolls[x].0 & 0x3F00 {
+          0x3F00 => palette_index(scrolls[x].0) as u8,
+          _ => 0,
+        }
+      };
+      let color = output_color(masks[x], palette[index as usize]);
+      self.frame.set_pixel(x, y, color);
+    }
+  }
+
+  // what a line does besides drawing: the overflow flag, and the
+  // sprite 0 hit, which needs the background only when sprite 0 is on it
+  fn skip_line(
+    &mut self,
+    cart: &dyn Mapper,
+    masks: &[MaskRegister; Frame::WIDTH],
+    scrolls: &[(u16, u8); Frame::WIDTH],
+  ) {
+    if !sprites_evaluated(masks) {
+      return;
+    }
+    let (sprites, count) = self.evaluate_sprites();
+    let sprite_zero = count > 0 && sprites[0] == 0;
+    if sprite_zero && shown(masks, MaskRegister::SHOW_SPRITES) {
+      let mut line = [0u8; Frame::WIDTH];
+      if shown(masks, MaskRegister::SHOW_BACKGROUND) {
+        self.background_line(cart, masks, scrolls, &mut line);
+      }
+      self.render_sprites(cart, &sprites[..1], masks, &mut line);
+    }
+  }
+
+  // PPUMASK as each pixel of the line saw it: pixel x is output at dot
+  // x + 1, after the writes made up to dot x
+  fn line_masks(&self) -> [MaskRegister; Frame::WIDTH] {
+    let mut masks = [self.mask; Frame::WIDTH];
+    for &(dot, before) in self.mask_writes.iter().rev() {
+      for mask in masks[..dot as usize].iter_mut() {
+        *mask = MaskRegister::from_bits_truncate(before);
+      }
+    }
+    masks
+  }
+
+  // v and fine X as each pixel of the line saw them, like `line_masks`
+  fn line_scrolls(&self) -> [(u16, u8); Frame::WIDTH] {
+    let mut scrolls = [(self.v, self.x); Frame::WIDTH];
+    for &(dot, write) in self.line_writes.iter().rev() {
+      if let LineWrite::Scroll { v, x } = write {
+        for scroll in scrolls[..dot as usize].iter_mut() {
+          *scroll = (v, x);
+        }
+      }
+    }
+    scrolls
+  }
+
+  // the background of the line, each run of pixels between scroll
+  // writes drawn with the scroll it saw
+  fn background_line(
+    &self,
+    cart: &dyn Mapper,
+    masks: &[MaskRegister; Frame::WIDTH],
+    scrolls: &[(u16, u8); Frame::WIDTH],
+    line: &mut [u8; Frame::WIDTH],
+  ) {
+    let mut start = 0;
+    while start < Frame::WIDTH {
+      let scroll = scrolls[start];
+      let end = scrolls[start..]
+        .iter()
+        .position(|&s| s != scroll)
+        .map_or(Frame::WIDTH, |run| start + run);
+      if start == 0 && end == Frame::WIDTH {
+        self.render_background_line(cart, masks, scroll, line);
+        return;
+      }
+      let mut run = [0u8; Frame::WIDTH];
+      self.render_background_line(cart, masks, scroll, &mut run);
+      line[start..end].copy_from_slice(&run[start..end]);
+      start = end;
+    }
+  }
+
+  fn render_background_line(
+    &self,
+    cart: &dyn Mapper,
+    masks: &[MaskRegister; Frame::WIDTH],
+    (v, fine_x): (u16, u8),
+    line: &mut [u8; Frame::WIDTH],
+  ) {
+    let mut fetch = TileFetch {
+      v,
+      column: 0,
+      scanline: self.scanline,
+      pattern_table: self.ctrl.background_pattern_addr(),
+      tall_sprites: self.ctrl.sprite_size() == 16,
+    };
+    let read = |addr| self.read_vram(cart, addr);
+    let mut x = 0usize;
+    let mut skip = fine_x as usize;
+    // 33 tiles cover the line when fine x isn't 0
+    while x < Frame::WIDTH {
+      let row = cart.background_tile(&fetch, &read);
+      let (lo, hi, palette) = (row.lo, row.hi, row.palette);
+
+      for bit in (0..8).rev().skip(skip) {
+        if x == Frame::WIDTH {
+          break;
+        }
+        let value = (((hi >> bit) & 1) << 1) | ((lo >> bit) & 1);
+        let hidden = !masks[x].contains(MaskRegister::SHOW_BACKGROUND)
+          || (x < 8 && !masks[x].contains(MaskRegister::LEFTMOST_8PXL_BACKGROUND));
+        if value != 0 && !hidden {
+          line[x] = palette * 4 + value;
+        }
+        x += 1;
+      }
+      skip = 0;
+      fetch.v = increment_coarse_x(fetch.v);
+      fetch.column += 1;
+    }
+  }
+
+  /// OAM indexes of the first 8 sprites on the current scanline. A 9th one
+  /// sets the overflow flag (without the hardware's false positives and
+  /// negatives).
+  fn evaluate_sprites(&mut self) -> ([usize; SPRITES], usize) {
+    let height = self.ctrl.sprite_size() as u16;
+    let mut sprites = [0; SPRITES];
+    let mut count = 0;
+    for i in 0..SPRITES {
+      // sprites show up one line below their Y
+      let top = self.oam_data[i * 4] as u16 + 1;
+      if self.scanline < top || self.scanline >= top + height {
+        continue;
+      }
+      if count == SPRITES_PER_LINE {
+        self.status.insert(StatusRegister::SPRITE_OVERFLOW);
+        if self.layers.sprite_limit {
+          break;
+        }
+      }
+      sprites[count] = i;
+      count += 1;
+    }
+    (sprites, count)
+  }
+
+  /*
+    OAM, 4 bytes per sprite:
+      0  Y of the top row, minus one
+      1  tile; in 8x16 mode bit 0 picks the pattern table, the rest the
+         top tile
+      2  VHP. ..PP  flip vertically, flip horizontally, behind background,
+                    palette 4-7
+      3  X of the left column
+  */
+  fn render_sprites(
+    &mut self,
+    cart: &dyn Mapper,
+    sprites: &[usize],
+    masks: &[MaskRegister; Frame::WIDTH],
+    line: &mut [u8; Frame::WIDTH],
+  ) {
+    let height = self.ctrl.sprite_size() as u16;
+    // the first opaque sprite pixel wins, even when it's behind the
+    // background
+    let mut taken = [false; Frame::WIDTH];
+    for &i in sprites {
+      let (top, tile, attributes, left) = (
+        self.oam_data[i * 4] as u16 + 1,
+        self.oam_data[i * 4 + 1] as u16,
+        self.oam_data[i * 4 + 2],
+        self.oam_data[i * 4 + 3] as usize,
+      );
+      let mut row = self.scanline - top;
+      if attributes & 0x80 != 0 {
+        row = height - 1 - row;
+      }
+      let addr = if height == 16 {
+        (tile & 1) * 0x1000 + ((tile & 0xFE) + row / 8) * 16 + row % 8
+      } else {
+        self.ctrl.sprite_pattern_addr() + tile * 16 + row
+      };
+      let lo = cart.sprite_chr_read(addr, height == 16);
+      let hi = cart.sprite_chr_read(addr + 8, height == 16);
+      let palette = attributes & 0b11;
+      let behind = attributes & 0x20 != 0;
+
+      for column in 0..8 {
+        let x = left + column;
+        if x >= Frame::WIDTH {
+          break;
+        }
+        let bit = if attributes & 0x40 != 0 {
+          column
+        } else {
+          7 - column
+        };
+        let value = (((hi >> bit) & 1) << 1) | ((lo >> bit) & 1);
+        let hidden = !masks[x].contains(MaskRegister::SHOW_SPRITES)
+          || (x < 8 && !masks[x].contains(MaskRegister::LEFTMOST_8PXL_SPRITE));
+        if value == 0 || hidden || taken[x] {
+          continue;
+        }
+
+        let background_opaque = line[x] & 0b11 != 0;
+        if i == 0 && background_opaque && x != 255 {
+          self.status.insert(StatusRegister::SPRITE_ZERO_HIT);
+        }
+        // a hidden sprite doesn't cover the ones after it either
+        if !self.layers.sprite_visible(i) {
+          continue;
+        }
+        taken[x] = true;
+        if !behind || !background_opaque || !self.layers.background {
+          line[x] = 0x10 + palette * 4 + value;
+        }
+      }
+    }
+  }
+
+  fn increment_y(&mut self) {
+    if (self.v & 0x7000) != 0x7000 {
+      // fine Y
+      self.v += 0x1000;
+      return;
+    }
+    self.v &= !0x7000;
+    let mut y = (self.v & 0x03E0) >> 5;
+    if y == 29 {
+      // last row of the nametable, switch vertical nametable
+      y = 0;
+      self.v ^= 0x0800;
+    } else if y == 31 {
+      // out of bounds (attribute table), wraps without switching
+      y = 0;
+    } else {
+      y += 1;
+    }
+    self.v = (self.v & !0x03E0) | (y << 5);
+  }
+
+  fn copy_horizontal(&mut self) {
+    self.v = (self.v & !0x041F) | (self.t & 0x041F);
+  }
+
+  fn copy_vertical(&mut self) {
+    self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0);
+  }
+
+  /// Take the NMI raised since the last call, if any.
+  pub fn poll_nmi(&mut self) -> bool {
+    let pending = self.nmi_pending;
+    self.nmi_pending = false;
+    pending
+  }
+
+  /// Reads of write-only registers, which return the I/O latch.
+  pub fn open_bus(&self, addr: u16) -> u8 {
+    trace!("read of write-only PPU register {:04x}", addr);
+    self.io_latch()
+  }
+
+  /// Record the bits of `mask` of a register access, as driven onto the
+  /// CPU-PPU data bus: all of them on writes, the ones a read returns.
+  pub fn drive_latch(&mut self, value: u8, mask: u8) {
+    self.io_latch = (self.io_latch & !mask) | (value & mask);
+    for (bit, driven) in self.latch_driven.iter_mut().enumerate() {
+      if mask & (1 << bit) != 0 {
+        *driven = self.frame_count;
+      }
+    }
+  }
+
+  /// What's left on the data bus: the last value driven, less the bits
+  /// that have since discharged. The charge is timed in emulated frames,
+  /// so pausing, rolling back or restoring a copy of the PPU resumes the
+  /// decay where it was instead of restarting or skipping it.
+  pub fn io_latch(&self) -> u8 {
+    (0..8)
+      .filter(|&bit| self.frame_count - self.latch_driven[bit] < LATCH_DECAY_FRAMES)
+      .fold(0, |latch, bit| latch | (self.io_latch & (1 << bit)))
+  }
+
+  /// Memory, registers, the beam position and the picture drawn so far.
+  /// `layers` belong to the debugger and aren't saved.
+  pub fn save_state(&self, w: &mut StateWriter) {
+    w.bytes(&self.palette_table);
+    w.bytes(&self.vram);
+    w.u8(self.oam_addr);
+    w.bytes(&self.oam_data);
+    self.save_registers(w);
+  }
+
+  // `save_state` after the memories: registers, timing and the picture
+  pub(crate) fn save_registers(&self, w: &mut StateWriter) {
+    w.u8(self.ctrl.bits());
+    w.u8(self.mask.bits());
+    w.u8(self.status.bits());
+    w.u16(self.v);
+    w.u16(self.t);
+    w.u8(self.x);
+    w.bool(self.w);
+    w.u16(self.scanline);
+    w.u16(self.cycle);
+    w.u64(self.frame_count);
+    w.bytes(&self.frame.data);
+    w.bytes(&self.frame.emphasis);
+    w.u8(self.internal_data_buf);
+    w.bool(self.odd_frame);
+    w.bool(self.nmi_pending);
+    w.bool(self.suppress_vblank);
+    w.u8(self.io_latch);
+    for &driven in &self.latch_driven {
+      w.u64(driven);
+    }
+    w.u16(self.mask_writes.len() as u16);
+    for &(dot, before) in &self.mask_writes {
+      w.u16(dot);
+      w.u8(before);
+    }
+    w.u16(self.line_writes.len() as u16);
+    for &(dot, write) in &self.line_writes {
+      w.u16(dot);
+      match write {
+        LineWrite::Scroll { v, x } => {
+          w.u8(0);
+          w.u16(v);
+          w.u8(x);
+        }
+        LineWrite::Palette { index, value } => {
+          w.u8(1);
+          w.u8(index);
+          w.u8(value);
+        }
+      }
+    }
+  }
+
+  pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+    r.bytes_into(&mut self.palette_table)?;
+    r.bytes_into(&mut self.vram)?;
+    self.oam_addr = r.u8()?;
+    r.bytes_into(&mut self.oam_data)?;
+    self.ctrl = ControlRegister::from_bits_truncate(r.u8()?);
+    self.mask = MaskRegister::from_bits_truncate(r.u8()?);
+    self.status = StatusRegister::from_bits_truncate(r.u8()?);
+    self.v = r.u16()? & 0x7FFF;
+    self.t = r.u16()? & 0x7FFF;
+    self.x = r.u8_below(8)?;
+    self.w = r.bool()?;
+    self.scanline = r.u16()?;
+    self.cycle = r.u16()?;
+    if self.scanline >= self.timing.scanlines() || self.cycle >= DOTS_PER_SCANLINE {
+      return Err(StateError::Corrupt);
+    }
+    self.frame_count = r.u64()?;
+    r.bytes_into(&mut self.frame.data)?;
+    r.bytes_into(&mut self.frame.emphasis)?;
+    self.internal_data_buf = r.u8()?;
+    self.odd_frame = r.bool()?;
+    self.nmi_pending = r.bool()?;
+    self.suppress_vblank = r.bool()?;
+    self.io_latch = r.u8()?;
+    for driven in self.latch_driven.iter_mut() {
+      *driven = r.u64()?;
+      if *driven > self.frame_count {
+        return Err(StateError::Corrupt);
+      }
+    }
+    self.mask_writes.clear();
+    for _ in 0..r.u16()? {
+      let dot = r.u16()?;
+      if dot >= Frame::WIDTH as u16 {
+        return Err(StateError::Corrupt);
+      }
+      self.mask_writes.push((dot, r.u8()?));
+    }
+    self.line_writes.clear();
+    for _ in 0..r.u16()? {
+      let dot = r.u16()?;
+      if dot >= Frame::WIDTH as u16 {
+        return Err(StateError::Corrupt);
+      }
+      let write = match r.u8()? {
+        0 => LineWrite::Scroll {
+          v: r.u16()? & 0x7FFF,
+          x: r.u8_below(8)?,
+        },
+        1 => LineWrite::Palette {
+          index: r.u8_below(32)?,
+          value: r.u8()?,
+        },
+        _ => return Err(StateError::Corrupt),
+      };
+      self.line_writes.push((dot, write));
+    }
+    Ok(())
+  }
+}
+
+fn output_color(mask: MaskRegister, color: u8) -> u8 {
+  if mask.contains(MaskRegister::GREYSCALE) {
+    color & 0x30
+  } else {
+    color & 0x3F
+  }
+}
+
+fn shown(masks: &[MaskRegister; Frame::WIDTH], layer: MaskRegister) -> bool {
+  masks.iter().any(|mask| mask.contains(layer))
+}
+
+// sprites for the line are only found if rendering stayed on from dot 65
+// to 256, where the PPU looks through OAM
+fn sprites_evaluated(masks: &[MaskRegister; Frame::WIDTH]) -> bool {
+  masks[64..].iter().all(|mask| mask.rendering_enabled())
+}
+
+// $3F10/$3F14/$3F18/$3F1C mirror $3F00/$3F04/$3F08/$3F0C
+fn palette_index(addr: u16) -> usize {
+  let index = addr & 0x1F;
+  match index {
+    0x10 | 0x14 | 0x18 | 0x1C => (index - 0x10) as usize,
+    _ => index as usize,
+  }
+}
+
+fn increment_coarse_x(v: u16) -> u16 {
+  if (v & 0x001F) == 31 {
+    // wrap and switch horizontal nametable
+    (v & !0x001F) ^ 0x0400
+  } else {
+    v + 1
+  }
+}
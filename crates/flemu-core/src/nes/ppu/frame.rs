@@ -0,0 +1,40 @@
+/// What the PPU drew, as 6-bit palette indices, one byte per pixel, and
+/// the PPUMASK emphasis bits of every line (lines are drawn in one go, so
+/// they can't change within one). Turning it into RGB is up to the
+/// palette code.
+#[derive(Clone)]
+pub struct Frame {
+  pub data: Vec<u8>,
+  pub emphasis: Vec<u8>,
+}
+
+impl Frame {
+  pub const WIDTH: usize = 256;
+  pub const HEIGHT: usize = 240;
+
+  pub fn new() -> Self {
+    Frame {
+      data: vec![0; Frame::WIDTH * Frame::HEIGHT],
+      emphasis: vec![0; Frame::HEIGHT],
+    }
+  }
+
+  pub fn set_pixel(&mut self, x: usize, y: usize, color: u8) {
+    self.data[y * Frame::WIDTH + x] = color;
+  }
+
+  pub fn pixel(&self, x: usize, y: usize) -> u8 {
+    self.data[y * Frame::WIDTH + x]
+  }
+
+  /// Emphasis bits of line `y`, as `MaskRegister::emphasis`.
+  pub fn set_emphasis(&mut self, y: usize, emphasis: u8) {
+    self.emphasis[y] = emphasis & 0b111;
+  }
+}
+
+impl Default for Frame {
+  fn default() -> Self {
+    Self::new()
+  }
+}
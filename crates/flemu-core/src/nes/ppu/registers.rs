@@ -0,0 +1,132 @@
+use bitflags::bitflags;
+
+bitflags! {
+  /// PPUCTRL ($2000), write only
+  ///
+  ///  7  bit  0
+  ///  ---- ----
+  ///  VPHB SINN
+  ///  |||| ||||
+  ///  |||| ||++- Base nametable address
+  ///  |||| ||    (0 = $2000; 1 = $2400; 2 = $2800; 3 = $2C00)
+  ///  |||| |+--- VRAM address increment per CPU read/write of PPUDATA
+  ///  |||| |     (0: add 1, going across; 1: add 32, going down)
+  ///  |||| +---- Sprite pattern table address for 8x8 sprites
+  ///  ||||       (0: $0000; 1: $1000; ignored in 8x16 mode)
+  ///  |||+------ Background pattern table address (0: $0000; 1: $1000)
+  ///  ||+------- Sprite size (0: 8x8 pixels; 1: 8x16 pixels)
+  ///  |+-------- PPU master/slave select
+  ///  |          (0: read backdrop from EXT pins; 1: output color on EXT pins)
+  ///  +--------- Generate an NMI at the start of the
+  ///             vertical blanking interval (0: off; 1: on)
+  pub struct ControlRegister: u8 {
+    const NAMETABLE1              = 0b00000001;
+    const NAMETABLE2              = 0b00000010;
+    const VRAM_ADD_INCREMENT      = 0b00000100;
+    const SPRITE_PATTERN_ADDR     = 0b00001000;
+    const BACKROUND_PATTERN_ADDR  = 0b00010000;
+    const SPRITE_SIZE             = 0b00100000;
+    const MASTER_SLAVE_SELECT     = 0b01000000;
+    const GENERATE_NMI            = 0b10000000;
+  }
+}
+
+impl ControlRegister {
+  pub fn vram_addr_increment(&self) -> u16 {
+    if !self.contains(ControlRegister::VRAM_ADD_INCREMENT) {
+      1
+    } else {
+      32
+    }
+  }
+
+  pub fn sprite_pattern_addr(&self) -> u16 {
+    if !self.contains(ControlRegister::SPRITE_PATTERN_ADDR) {
+      0
+    } else {
+      0x1000
+    }
+  }
+
+  pub fn background_pattern_addr(&self) -> u16 {
+    if !self.contains(ControlRegister::BACKROUND_PATTERN_ADDR) {
+      0
+    } else {
+      0x1000
+    }
+  }
+
+  pub fn sprite_size(&self) -> u8 {
+    if !self.contains(ControlRegister::SPRITE_SIZE) {
+      8
+    } else {
+      16
+    }
+  }
+
+  pub fn generate_vblank_nmi(&self) -> bool {
+    self.contains(ControlRegister::GENERATE_NMI)
+  }
+}
+
+bitflags! {
+  /// PPUMASK ($2001), write only
+  ///
+  ///  7  bit  0
+  ///  ---- ----
+  ///  BGRs bMmG
+  ///  |||| ||||
+  ///  |||| |||+- Greyscale (0: normal color, 1: produce a greyscale display)
+  ///  |||| ||+-- 1: Show background in leftmost 8 pixels of screen, 0: Hide
+  ///  |||| |+--- 1: Show sprites in leftmost 8 pixels of screen, 0: Hide
+  ///  |||| +---- 1: Show background
+  ///  |||+------ 1: Show sprites
+  ///  ||+------- Emphasize red
+  ///  |+-------- Emphasize green
+  ///  +--------- Emphasize blue
+  pub struct MaskRegister: u8 {
+    const GREYSCALE               = 0b00000001;
+    const LEFTMOST_8PXL_BACKGROUND = 0b00000010;
+    const LEFTMOST_8PXL_SPRITE    = 0b00000100;
+    const SHOW_BACKGROUND         = 0b00001000;
+    const SHOW_SPRITES            = 0b00010000;
+    const EMPHASISE_RED           = 0b00100000;
+    const EMPHASISE_GREEN         = 0b01000000;
+    const EMPHASISE_BLUE          = 0b10000000;
+  }
+}
+
+impl MaskRegister {
+  pub fn rendering_enabled(&self) -> bool {
+    self.intersects(MaskRegister::SHOW_BACKGROUND | MaskRegister::SHOW_SPRITES)
+  }
+
+  /// The emphasis bits on their own, red in bit 0: which of the 8
+  /// emphasis tables of a palette applies.
+  pub fn emphasis(&self) -> u8 {
+    self.bits() >> 5
+  }
+}
+
+bitflags! {
+  /// PPUSTATUS ($2002), read only
+  ///
+  ///  7  bit  0
+  ///  ---- ----
+  ///  VSO. ....
+  ///  |||| ||||
+  ///  |||+-++++- Least significant bits previously written into a PPU register
+  ///  ||+------- Sprite overflow
+  ///  |+-------- Sprite 0 Hit
+  ///  +--------- Vertical blank has started (0: not in vblank; 1: in vblank)
+  pub struct StatusRegister: u8 {
+    const NOTUSED          = 0b00000001;
+    const NOTUSED2         = 0b00000010;
+    const NOTUSED3         = 0b00000100;
+    const NOTUSED4         = 0b00001000;
+    const NOTUSED5         = 0b00010000;
+    const SPRITE_OVERFLOW  = 0b00100000;
+    const SPRITE_ZERO_HIT  = 0b01000000;
+    const VBLANK_STARTED   = 0b10000000;
+  }
+}
@@ -0,0 +1,309 @@
+use crate::nes::bus::Bus;
+use crate::nes::palette::{self, Palette};
+use crate::nes::ppu::Frame;
+use crate::nes::scanline_events::LineEvents;
+
+/*
+  Pictures of what the PPU has to draw with, for a graphics debugger:
+  the pattern tables, the four nametables, the palette and the sprites in
+  OAM. They're read as things are now, with the banks the mapper has
+  switched in at the end of the frame, not as each scanline saw them,
+  and without setting anything off (no buffered $2007 reads, no mapper
+  IRQ counters clocked).
+
+  Images are RGBA, rows top to bottom, colors from `Palette` without
+  emphasis or greyscale.
+
+  `event_frame` is the other way round, the frame as drawn with every
+  scanline of the frame, not just the picture's, tinted by what
+  interrupted the CPU on it (see `scanline_events`).
+*/
+
+/// Each pattern table as 16x16 tiles.
+pub const PATTERN_TABLE_SIZE: usize = 128;
+/// The four nametables, two across and two down.
+pub const NAMETABLES_WIDTH: usize = 512;
+pub const NAMETABLES_HEIGHT: usize = 480;
+/// Eight sprites across, each in an 8x16 cell (8x8 ones in its top half).
+pub const SPRITE_SHEET_WIDTH: usize = 64;
+pub const SPRITE_SHEET_HEIGHT: usize = 128;
+
+const SCROLL_OUTLINE: (u8, u8, u8) = (0xFF, 0xFF, 0xFF);
+
+/// What each event tints a scanline in `event_frame`, mixed when a line
+/// has several.
+pub const EVENT_TINTS: [(LineEvents, (u8, u8, u8)); 4] = [
+  (LineEvents::NMI, (0xFF, 0x30, 0x30)),
+  (LineEvents::IRQ, (0x30, 0xFF, 0x30)),
+  (LineEvents::OAM_DMA, (0x30, 0x60, 0xFF)),
+  (LineEvents::DMC_DMA, (0xFF, 0xD0, 0x20)),
+];
+// the lines outside the picture, before any tint
+const OFFSCREEN: (u8, u8, u8) = (0x20, 0x20, 0x20);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+  pub width: usize,
+  pub height: usize,
+  pub rgba: Vec<u8>,
+}
+
+impl Image {
+  // all transparent
+  fn new(width: usize, height: usize) -> Self {
+    Image {
+      width,
+      height,
+      rgba: vec![0; width * height * 4],
+    }
+  }
+
+  fn set(&mut self, x: usize, y: usize, rgb: (u8, u8, u8)) {
+    let at = (y * self.width + x) * 4;
+    self.rgba[at..at + 4].copy_from_slice(&[rgb.0, rgb.1, rgb.2, 0xFF]);
+  }
+}
+
+/// One of the 64 sprites in OAM, decoded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sprite {
+  pub index: u8,
+  pub x: u8,
+  /// The top row; OAM holds one less.
+  pub y: u16,
+  /// As in OAM: in 8x16 mode bit 0 is the pattern table.
+  pub tile: u8,
+  /// 4-7, the sprite half of the palette.
+  pub palette: u8,
+  pub behind_background: bool,
+  pub flip_horizontal: bool,
+  pub flip_vertical: bool,
+}
+
+// the 2-bit color of pixel `x` in a row of a tile
+fn tile_pixel(lo: u8, hi: u8, x: usize) -> u8 {
+  let bit = 7 - x;
+  (((hi >> bit) & 1) << 1) | ((lo >> bit) & 1)
+}
+
+// the palette RAM entry a 2-bit color of `palette` (0-7) shows
+fn color(bus: &Bus, palette: u8, value: u8) -> u8 {
+  if value == 0 {
+    // the backdrop, behind every palette
+    bus.ppu_peek(0x3F00)
+  } else {
+    bus.ppu_peek(0x3F00 + palette as u16 * 4 + value as u16)
+  }
+}
+
+/// Pattern table `table` (0 at $0000, 1 at $1000) with the colors of
+/// palette `palette`, 0-3 for the background ones and 4-7 for sprites.
+pub fn pattern_table(bus: &Bus, table: u8, palette: u8, colors: &Palette) -> Image {
+  let mut image = Image::new(PATTERN_TABLE_SIZE, PATTERN_TABLE_SIZE);
+  let base = (table as u16 & 1) * 0x1000;
+  for tile in 0..256u16 {
+    let (left, top) = ((tile % 16) as usize * 8, (tile / 16) as usize * 8);
+    for row in 0..8 {
+      let addr = base + tile * 16 + row as u16;
+      let (lo, hi) = (bus.ppu_peek(addr), bus.ppu_peek(addr + 8));
+      for x in 0..8 {
+        let value = tile_pixel(lo, hi, x);
+        image.set(
+          left + x,
+          top + row,
+          colors.rgb(color(bus, palette & 7, value)),
+        );
+      }
+    }
+  }
+  image
+}
+
+/// All four nametables with the background pattern table PPUCTRL
+/// selects, and the screen the scroll the game last set shows outlined
+/// when `scroll` is on, wrapping around the edges as the PPU does.
+pub fn nametables(bus: &Bus, colors: &Palette, scroll: bool) -> Image {
+  let mut image = Image::new(NAMETABLES_WIDTH, NAMETABLES_HEIGHT);
+  let pattern_table = bus.ppu.ctrl.background_pattern_addr();
+  for table in 0..4u16 {
+    let base = 0x2000 + table * 0x400;
+    let (left, top) = ((table % 2) as usize * 256, (table / 2) as usize * 240);
+    for tile_y in 0..30u16 {
+      for tile_x in 0..32u16 {
+        let tile = bus.ppu_peek(base + tile_y * 32 + tile_x) as u16;
+        let attribute = bus.ppu_peek(base + 0x3C0 + tile_y / 4 * 8 + tile_x / 4);
+        let shift = (tile_y % 4 / 2) * 4 + (tile_x % 4 / 2) * 2;
+        let palette = (attribute >> shift) & 0b11;
+        for row in 0..8 {
+          let addr = pattern_table + tile * 16 + row;
+          let (lo, hi) = (bus.ppu_peek(addr), bus.ppu_peek(addr + 8));
+          for x in 0..8 {
+            let value = tile_pixel(lo, hi, x);
+            image.set(
+              left + tile_x as usize * 8 + x,
+              top + tile_y as usize * 8 + row as usize,
+              colors.rgb(color(bus, palette, value)),
+            );
+          }
+        }
+      }
+    }
+  }
+  if scroll {
+    let (x, y) = scroll_position(bus);
+    outline(&mut image, x, y, 256, 240);
+  }
+  image
+}
+
+/// Where the top left of the screen is in the four nametables, from the
+/// scroll in `t` and fine X: (0-511, 0-479).
+pub fn scroll_position(bus: &Bus) -> (usize, usize) {
+  let t = bus.ppu.t as usize;
+  let x = (t >> 10 & 1) * 256 + (t & 0x1F) * 8 + bus.ppu.x as usize;
+  // coarse Y 30 and 31 are the attribute rows, off the bottom
+  let y = (t >> 11 & 1) * 240 + (t >> 5 & 0x1F) * 8 + (t >> 12 & 7);
+  (x, y % NAMETABLES_HEIGHT)
+}
+
+fn outline(image: &mut Image, left: usize, top: usize, width: usize, height: usize) {
+  let (w, h) = (image.width, image.height);
+  for i in 0..width {
+    image.set((left + i) % w, top, SCROLL_OUTLINE);
+    image.set((left + i) % w, (top + height - 1) % h, SCROLL_OUTLINE);
+  }
+  for i in 0..height {
+    image.set(left, (top + i) % h, SCROLL_OUTLINE);
+    image.set((left + width - 1) % w, (top + i) % h, SCROLL_OUTLINE);
+  }
+}
+
+/// The 32 palette RAM entries, one pixel each: the background palettes
+/// on the top row of 16, the sprite ones below. $3F10, $3F14, $3F18 and
+/// $3F1C show what they mirror.
+pub fn palette(bus: &Bus, colors: &Palette) -> Image {
+  let mut image = Image::new(16, 2);
+  for i in 0..32u16 {
+    let entry = bus.ppu_peek(0x3F00 + i);
+    image.set(i as usize % 16, i as usize / 16, colors.rgb(entry));
+  }
+  image
+}
+
+/// OAM, decoded, as the comment on the PPU's sprite rendering lays it
+/// out.
+pub fn sprites(bus: &Bus) -> Vec<Sprite> {
+  bus
+    .ppu
+    .oam_data
+    .chunks(4)
+    .enumerate()
+    .map(|(i, bytes)| Sprite {
+      index: i as u8,
+      x: bytes[3],
+      y: bytes[0] as u16 + 1,
+      tile: bytes[1],
+      palette: 4 + (bytes[2] & 0b11),
+      behind_background: bytes[2] & 0x20 != 0,
+      flip_horizontal: bytes[2] & 0x40 != 0,
+      flip_vertical: bytes[2] & 0x80 != 0,
+    })
+    .collect()
+}
+
+/// Every sprite as it's drawn, flipped and in its palette, with the
+/// transparent pixels left transparent; sprite `n` in column `n % 8`, row
+/// `n / 8`.
+pub fn sprite_sheet(bus: &Bus, colors: &Palette) -> Image {
+  let mut image = Image::new(SPRITE_SHEET_WIDTH, SPRITE_SHEET_HEIGHT);
+  let height = bus.ppu.ctrl.sprite_size() as u16;
+  let tall = height == 16;
+  for sprite in sprites(bus) {
+    let (left, top) = (
+      (sprite.index % 8) as usize * 8,
+      (sprite.index / 8) as usize * 16,
+    );
+    let tile = sprite.tile as u16;
+    for row in 0..height {
+      let from = if sprite.flip_vertical {
+        height - 1 - row
+      } else {
+        row
+      };
+      let addr = if tall {
+        (tile & 1) * 0x1000 + ((tile & 0xFE) + from / 8) * 16 + from % 8
+      } else {
+        bus.ppu.ctrl.sprite_pattern_addr() + tile * 16 + from
+      };
+      let (lo, hi) = match bus.mapper() {
+        Some(mapper) => (
+          mapper.sprite_chr_read(addr, tall),
+          mapper.sprite_chr_read(addr + 8, tall),
+        ),
+        None => (0, 0),
+      };
+      for x in 0..8 {
+        let column = if sprite.flip_horizontal { 7 - x } else { x };
+        let value = tile_pixel(lo, hi, column);
+        if value != 0 {
+          let rgb = colors.rgb(color(bus, sprite.palette, value));
+          image.set(left + x, top + row as usize, rgb);
+        }
+      }
+    }
+  }
+  image
+}
+
+/// The last frame, 256 wide and as tall as the frame has scanlines, the
+/// picture on top and the lines the PPU draws nothing on dark grey under
+/// it. Lines are tinted halfway to their events' `EVENT_TINTS`, without
+/// any unless `Bus::set_scanline_events` is on.
+pub fn event_frame(bus: &Bus, colors: &Palette) -> Image {
+  let frame = &bus.ppu.frame;
+  let lines = bus.timing().scanlines() as usize;
+  let mut image = Image::new(Frame::WIDTH, lines);
+  let picture = colors.frame_rgba(frame);
+  image.rgba[..picture.len()].copy_from_slice(&picture);
+  for y in Frame::HEIGHT..lines {
+    for x in 0..Frame::WIDTH {
+      image.set(x, y, OFFSCREEN);
+    }
+  }
+  let log = match bus.scanline_events() {
+    Some(log) => log,
+    None => return image,
+  };
+  for y in 0..lines {
+    let tint = match event_tint(log.line(y as u16)) {
+      Some(tint) => tint,
+      None => continue,
+    };
+    let row = &mut image.rgba[y * Frame::WIDTH * 4..(y + 1) * Frame::WIDTH * 4];
+    for pixel in row.chunks_mut(4) {
+      let (r, g, b) = palette::blend((pixel[0], pixel[1], pixel[2]), tint, 0.5);
+      pixel[..3].copy_from_slice(&[r, g, b]);
+    }
+  }
+  image
+}
+
+// the average of the tints of `events`, None without any
+fn event_tint(events: LineEvents) -> Option<(u8, u8, u8)> {
+  let tints: Vec<(u8, u8, u8)> = EVENT_TINTS
+    .iter()
+    .filter(|(event, _)| events.contains(*event))
+    .map(|&(_, tint)| tint)
+    .collect();
+  if tints.is_empty() {
+    return None;
+  }
+  let average = |channel: fn(&(u8, u8, u8)) -> u8| {
+    (tints
+      .iter()
+      .map(|tint| channel(tint) as usize)
+      .sum::<usize>()
+      / tints.len()) as u8
+  };
+  Some((average(|t| t.0), average(|t| t.1), average(|t| t.2)))
+}
@@ -0,0 +1,62 @@
+/*
+  Where the time of a frame goes between the PPU and the APU (the CPU
+  gets the rest). They run interleaved, a few cycles at a time, so timing
+  every slice would cost more than the slices themselves: one slice in
+  `SAMPLE_EVERY` is timed and counted that many times over, which evens
+  out over a frame's thousands of them.
+
+  The core has no clock of its own, the frontend passes one in. Nothing
+  timed feeds back into emulation, so a profiled run is the same run.
+*/
+
+/// Slices of CPU cycles per timed one.
+pub const SAMPLE_EVERY: u32 = 16;
+
+/// Milliseconds since whenever, e.g. `performance.now()`.
+pub type Clock = fn() -> f64;
+
+/// Time spent ticking the PPU and the APU since the last `take`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ComponentTimes {
+  pub ppu_ms: f64,
+  pub apu_ms: f64,
+}
+
+#[derive(Clone)]
+pub struct Profiler {
+  clock: Clock,
+  // slices until the next timed one
+  countdown: u32,
+  times: ComponentTimes,
+}
+
+impl Profiler {
+  pub fn new(clock: Clock) -> Self {
+    Profiler {
+      clock,
+      countdown: 0,
+      times: ComponentTimes::default(),
+    }
+  }
+
+  /// The clock if this slice is one to time.
+  pub fn sample(&mut self) -> Option<Clock> {
+    if self.countdown == 0 {
+      self.countdown = SAMPLE_EVERY - 1;
+      Some(self.clock)
+    } else {
+      self.countdown -= 1;
+      None
+    }
+  }
+
+  /// What a timed slice took, standing in for the ones that weren't.
+  pub fn add(&mut self, ppu_ms: f64, apu_ms: f64) {
+    self.times.ppu_ms += ppu_ms * SAMPLE_EVERY as f64;
+    self.times.apu_ms += apu_ms * SAMPLE_EVERY as f64;
+  }
+
+  pub fn take(&mut self) -> ComponentTimes {
+    std::mem::take(&mut self.times)
+  }
+}
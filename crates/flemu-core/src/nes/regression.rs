@@ -0,0 +1,152 @@
+use crate::nes::bus::Mem;
+use crate::nes::cartridge::{Rom, RomError};
+use crate::nes::cpu::CPU;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Write;
+
+/*
+  Frame hashes: run a ROM for N frames and hash the machine after every
+  frame. Comparing the hashes of two builds points at the first frame where
+  they stopped agreeing, which is where to start looking after touching the
+  CPU or PPU.
+
+  Hash files are plain text, one line per frame:
+    <rom name>\t<frame>\t<hash as 16 hex digits>
+*/
+
+pub(crate) const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+pub(crate) fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+  bytes.iter().fold(hash, |hash, &byte| {
+    (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+  })
+}
+
+/// Hash of everything a frame can change: registers, internal RAM, the PPU
+/// memories and the rendered picture.
+pub fn state_hash(cpu: &CPU) -> u64 {
+  let s = cpu.state();
+  let mut hash = fnv1a(FNV_OFFSET, &s.pc.to_le_bytes());
+  hash = fnv1a(hash, &[s.a, s.x, s.y, s.flags, s.sp]);
+  hash = fnv1a(hash, &s.cycles.to_le_bytes());
+
+  let ram: Vec<u8> = (0x0000..0x0800).map(|addr| cpu.mem_peek(addr)).collect();
+  hash = fnv1a(hash, &ram);
+
+  let ppu = &cpu.bus.ppu;
+  hash = fnv1a(hash, &ppu.palette_table);
+  hash = fnv1a(hash, &ppu.vram);
+  hash = fnv1a(hash, &ppu.oam_data);
+  fnv1a(hash, &ppu.frame.data)
+}
+
+/// Boot `rom` and hash up to `frames` frames. Fewer hashes come back when
+/// the program hits a fault.
+pub fn record(rom: Rom, frames: u32) -> Result<Vec<u64>, RomError> {
+  let mut cpu = CPU::new();
+  cpu.reset();
+  cpu.halt_on_brk = false;
+  cpu.load_rom(rom)?;
+
+  let mut hashes = Vec::new();
+  for _ in 0..frames {
+    if !cpu.run_frame() {
+      break;
+    }
+    hashes.push(state_hash(&cpu));
+  }
+  Ok(hashes)
+}
+
+/// Index of the first frame where the runs disagree, counting a run that
+/// ends early as diverging.
+pub fn first_divergence(baseline: &[u64], current: &[u64]) -> Option<usize> {
+  baseline
+    .iter()
+    .zip(current.iter())
+    .position(|(a, b)| a != b)
+    .or_else(|| {
+      if baseline.len() == current.len() {
+        None
+      } else {
+        Some(baseline.len().min(current.len()))
+      }
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseHashLogError {
+  /// 1-based line of the hash file.
+  pub line: usize,
+}
+
+impl fmt::Display for ParseHashLogError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "malformed frame hash on line {}", self.line)
+  }
+}
+
+impl std::error::Error for ParseHashLogError {}
+
+/// Per-ROM frame hashes of one run.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HashLog {
+  runs: BTreeMap<String, Vec<u64>>,
+}
+
+impl HashLog {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn insert(&mut self, rom: &str, hashes: Vec<u64>) {
+    self.runs.insert(rom.to_string(), hashes);
+  }
+
+  pub fn get(&self, rom: &str) -> Option<&[u64]> {
+    self.runs.get(rom).map(Vec::as_slice)
+  }
+
+  pub fn roms(&self) -> impl Iterator<Item = &str> {
+    self.runs.keys().map(String::as_str)
+  }
+
+  pub fn to_text(&self) -> String {
+    let mut out = String::new();
+    for (rom, hashes) in self.runs.iter() {
+      for (frame, hash) in hashes.iter().enumerate() {
+        // writing into a String can't fail
+        writeln!(out, "{}\t{}\t{:016x}", rom, frame, hash).unwrap();
+      }
+    }
+    out
+  }
+
+  /// Parse `to_text` output. Frames of a ROM are expected in order.
+  pub fn from_text(text: &str) -> Result<HashLog, ParseHashLogError> {
+    let mut log = HashLog::new();
+    for (i, line) in text.lines().enumerate() {
+      if line.is_empty() {
+        continue;
+      }
+      let error = ParseHashLogError { line: i + 1 };
+      let mut fields = line.rsplitn(3, '\t');
+      let hash = fields.next().and_then(|h| u64::from_str_radix(h, 16).ok());
+      let frame = fields.next().and_then(|f| f.parse::<usize>().ok());
+      let rom = fields.next();
+      let (rom, frame, hash) = match (rom, frame, hash) {
+        (Some(rom), Some(frame), Some(hash)) => (rom, frame, hash),
+        _ => return Err(error),
+      };
+
+      let hashes = log.runs.entry(rom.to_string()).or_default();
+      if hashes.len() != frame {
+        return Err(error);
+      }
+      hashes.push(hash);
+    }
+    Ok(log)
+  }
+}
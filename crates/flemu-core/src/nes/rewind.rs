@@ -0,0 +1,273 @@
+use crate::nes::palette::Palette;
+use crate::nes::ppu::Frame;
+use std::collections::VecDeque;
+
+/*
+  Savestates taken every few frames, for stepping back through gameplay.
+
+  Half a minute of full states would be the better part of a hundred
+  megabytes, but states a few frames apart barely differ, so only the
+  newest is kept whole. Each older one is stored as its XOR with the state
+  after it, run length encoded: long runs of unchanged bytes shrink to a
+  couple of bytes. Going back decodes one step at a time from the newest,
+  and since nothing depends on the oldest entry, dropping it when the
+  buffer is full costs nothing.
+
+  Each state keeps a thumbnail of the frame it was taken at, so a
+  frontend can show a filmstrip to scrub through before picking one to
+  go back to. States are counted from the newest, 0, back.
+
+  Delta encoding, repeated to the end:
+    zero run length (LEB128), literal length (LEB128), literal bytes
+*/
+
+/// Thumbnails are this many times smaller than a frame each way.
+pub const THUMBNAIL_SCALE: usize = 4;
+
+/// A frame shrunk by `THUMBNAIL_SCALE`, palette indices and line emphasis
+/// like `Frame`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Thumbnail {
+  pub data: Vec<u8>,
+  pub emphasis: Vec<u8>,
+}
+
+impl Thumbnail {
+  pub const WIDTH: usize = Frame::WIDTH / THUMBNAIL_SCALE;
+  pub const HEIGHT: usize = Frame::HEIGHT / THUMBNAIL_SCALE;
+
+  /// The middle pixel of every block of `frame`.
+  pub fn new(frame: &Frame) -> Self {
+    let middle = THUMBNAIL_SCALE / 2;
+    let mut data = Vec::with_capacity(Thumbnail::WIDTH * Thumbnail::HEIGHT);
+    let mut emphasis = Vec::with_capacity(Thumbnail::HEIGHT);
+    for y in 0..Thumbnail::HEIGHT {
+      let line = y * THUMBNAIL_SCALE + middle;
+      for x in 0..Thumbnail::WIDTH {
+        data.push(frame.pixel(x * THUMBNAIL_SCALE + middle, line));
+      }
+      emphasis.push(frame.emphasis[line]);
+    }
+    Thumbnail { data, emphasis }
+  }
+
+  /// RGBA, rows top to bottom, as `Palette::frame_rgba`.
+  pub fn rgba(&self, palette: &Palette) -> Vec<u8> {
+    let mut out = Vec::with_capacity(self.data.len() * 4);
+    for (line, &emphasis) in self.data.chunks(Thumbnail::WIDTH).zip(&self.emphasis) {
+      for &color in line {
+        let (r, g, b) = palette.emphasized(color, emphasis);
+        out.extend_from_slice(&[r, g, b, 0xFF]);
+      }
+    }
+    out
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Rewind {
+  interval: u32,
+  capacity: usize,
+  newest: Option<Vec<u8>>,
+  // oldest first, each against the one after it (the last against newest)
+  older: VecDeque<Vec<u8>>,
+  // of every state, oldest first
+  thumbnails: VecDeque<Thumbnail>,
+  frames: u32,
+}
+
+impl Rewind {
+  /// Keep a state every `interval` frames, up to `capacity` of them. A
+  /// capacity of 0 turns rewinding off.
+  pub fn new(interval: u32, capacity: usize) -> Self {
+    Rewind {
+      interval: interval.max(1),
+      capacity,
+      newest: None,
+      older: VecDeque::new(),
+      thumbnails: VecDeque::new(),
+      frames: 0,
+    }
+  }
+
+  pub fn interval(&self) -> u32 {
+    self.interval
+  }
+
+  pub fn capacity(&self) -> usize {
+    self.capacity
+  }
+
+  /// Count a frame. True when a state is due for `push`.
+  pub fn tick(&mut self) -> bool {
+    let due = self.capacity > 0 && self.frames % self.interval == 0;
+    self.frames = self.frames.wrapping_add(1);
+    due
+  }
+
+  /// Keep `state` as the newest, with a thumbnail of `frame`, the
+  /// picture it was taken at. Drops the oldest when full.
+  pub fn push(&mut self, state: Vec<u8>, frame: &Frame) {
+    if self.capacity == 0 {
+      return;
+    }
+    if let Some(newest) = self.newest.take() {
+      if newest.len() == state.len() {
+        self.older.push_back(delta(&newest, &state));
+      } else {
+        // another layout (a Four Score plugged in), nothing to diff against
+        self.older.clear();
+        self.thumbnails.clear();
+      }
+    }
+    self.newest = Some(state);
+    self.thumbnails.push_back(Thumbnail::new(frame));
+    while self.len() > self.capacity {
+      self.older.pop_front();
+      self.thumbnails.pop_front();
+    }
+  }
+
+  /// Take the newest state off, the one before it becoming the newest.
+  pub fn pop(&mut self) -> Option<Vec<u8>> {
+    let newest = self.newest.take()?;
+    self.newest = self.older.pop_back().map(|older| apply(&newest, &older));
+    self.thumbnails.pop_back();
+    self.frames = 0;
+    Some(newest)
+  }
+
+  /// The state `back` from the newest, left where it is.
+  pub fn get(&self, back: usize) -> Option<Vec<u8>> {
+    if back >= self.len() {
+      return None;
+    }
+    let newest = self.newest.clone()?;
+    Some(
+      self
+        .older
+        .iter()
+        .rev()
+        .take(back)
+        .fold(newest, |state, older| apply(&state, older)),
+    )
+  }
+
+  /// The thumbnail of the state `back` from the newest.
+  pub fn thumbnail(&self, back: usize) -> Option<&Thumbnail> {
+    let index = self.thumbnails.len().checked_sub(back + 1)?;
+    self.thumbnails.get(index)
+  }
+
+  /// Go back to the state `back` from the newest: take it off with every
+  /// newer one, the one before it becoming the newest. `seek(0)` is
+  /// `pop`.
+  pub fn seek(&mut self, back: usize) -> Option<Vec<u8>> {
+    if back >= self.len() {
+      return None;
+    }
+    for _ in 0..back {
+      self.pop();
+    }
+    self.pop()
+  }
+
+  /// States held.
+  pub fn len(&self) -> usize {
+    self.newest.as_ref().map_or(0, |_| 1 + self.older.len())
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.newest.is_none()
+  }
+
+  /// Memory the states take, compressed, and their thumbnails.
+  pub fn bytes(&self) -> usize {
+    let thumbnails = self.thumbnails.len() * (Thumbnail::WIDTH + 1) * Thumbnail::HEIGHT;
+    self.newest.as_ref().map_or(0, Vec::len)
+      + self.older.iter().map(Vec::len).sum::<usize>()
+      + thumbnails
+  }
+
+  pub fn clear(&mut self) {
+    self.newest = None;
+    self.older.clear();
+    self.thumbnails.clear();
+    self.frames = 0;
+  }
+}
+
+fn write_length(out: &mut Vec<u8>, mut length: usize) {
+  while length >= 0x80 {
+    out.push(length as u8 | 0x80);
+    length >>= 7;
+  }
+  out.push(length as u8);
+}
+
+fn read_length(bytes: &[u8], pos: &mut usize) -> usize {
+  let mut length = 0;
+  let mut shift = 0;
+  while let Some(&byte) = bytes.get(*pos) {
+    *pos += 1;
+    length |= ((byte & 0x7F) as usize) << shift;
+    if byte & 0x80 == 0 {
+      break;
+    }
+    shift += 7;
+  }
+  length
+}
+
+/// `state` stored against `base`, both of the same length.
+pub fn delta(state: &[u8], base: &[u8]) -> Vec<u8> {
+  let mut out = Vec::new();
+  let mut i = 0;
+  while i < state.len() {
+    let zeros = state[i..]
+      .iter()
+      .zip(&base[i..])
+      .take_while(|(a, b)| a == b)
+      .count();
+    i += zeros;
+    // a literal ends at the first run of 4 equal bytes, shorter runs
+    // cost more as a new pair of lengths than as literals
+    let start = i;
+    let mut equal = 0;
+    while i < state.len() && equal < 4 {
+      equal = if state[i] == base[i] { equal + 1 } else { 0 };
+      i += 1;
+    }
+    let end = if equal == 4 { i - 4 } else { i };
+    i = end;
+    write_length(&mut out, zeros);
+    write_length(&mut out, end - start);
+    out.extend(
+      state[start..end]
+        .iter()
+        .zip(&base[start..end])
+        .map(|(a, b)| a ^ b),
+    );
+  }
+  out
+}
+
+/// The state `delta` encoded against `base`.
+pub fn apply(base: &[u8], delta: &[u8]) -> Vec<u8> {
+  let mut state = base.to_vec();
+  let mut pos = 0;
+  let mut i = 0;
+  while pos < delta.len() {
+    i += read_length(delta, &mut pos);
+    let literal = read_length(delta, &mut pos);
+    for (byte, &xor) in state[i..i + literal]
+      .iter_mut()
+      .zip(&delta[pos..pos + literal])
+    {
+      *byte ^= xor;
+    }
+    pos += literal;
+    i += literal;
+  }
+  state
+}
@@ -0,0 +1,58 @@
+/// Fixed-size ring of machine snapshots for rollback netcode.
+///
+/// Unlike user savestates nothing gets serialized: every slot is allocated up
+/// front and taking or restoring a snapshot is a plain copy, so a netplay
+/// layer can re-simulate several frames inside a single frame budget.
+pub struct RollbackBuffer<T> {
+  slots: Vec<T>,
+  frames: Vec<Option<u32>>,
+}
+
+impl<T: Clone + Default> RollbackBuffer<T> {
+  pub fn new(capacity: usize) -> Self {
+    assert!(capacity > 0, "rollback buffer needs at least one slot");
+    RollbackBuffer {
+      slots: vec![T::default(); capacity],
+      frames: vec![None; capacity],
+    }
+  }
+
+  pub fn capacity(&self) -> usize {
+    self.slots.len()
+  }
+
+  /// Store the machine state for `frame`, overwriting the oldest slot.
+  pub fn snapshot(&mut self, frame: u32, machine: &T) {
+    let slot = frame as usize % self.slots.len();
+    self.slots[slot].clone_from(machine);
+    self.frames[slot] = Some(frame);
+  }
+
+  /// The snapshot saved for `frame`, if it hasn't been overwritten yet.
+  pub fn get(&self, frame: u32) -> Option<&T> {
+    let slot = frame as usize % self.slots.len();
+    if self.frames[slot] != Some(frame) {
+      return None;
+    }
+    Some(&self.slots[slot])
+  }
+
+  /// Put the machine back to the state saved for `frame`. Returns false when
+  /// that frame has already been overwritten (or was never saved).
+  pub fn restore(&self, frame: u32, machine: &mut T) -> bool {
+    match self.get(frame) {
+      Some(snapshot) => {
+        machine.clone_from(snapshot);
+        true
+      }
+      None => false,
+    }
+  }
+
+  /// Forget every snapshot, e.g. after loading a different game.
+  pub fn clear(&mut self) {
+    for frame in self.frames.iter_mut() {
+      *frame = None;
+    }
+  }
+}
@@ -0,0 +1,82 @@
+use crate::nes::cpu::CPU;
+use std::mem;
+
+/*
+  Run-ahead, hiding a game's own input lag.
+
+  Most games read the controller during one frame and only draw the result
+  a frame or two later. After each real frame `RunAhead::run` takes a copy
+  of the machine, runs `frames` more with the buttons held right now, and
+  keeps just the picture from the last one before putting the copy back.
+  The screen then shows what is about to happen, and a button press turns
+  up that many frames sooner. Sound, RAM and everything else stay with the
+  real timeline, so the speculative frames leave no trace; if the game
+  reacts to input slower than `frames`, nothing looks wrong either, the
+  picture is just a little further ahead.
+
+  As with rollback nothing gets serialized: the copy is a clone into a
+  machine kept around for the purpose, cheap enough to do every frame.
+*/
+
+pub struct RunAhead {
+  frames: u32,
+  saved: CPU,
+}
+
+impl Default for RunAhead {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl RunAhead {
+  /// Most frames `set_frames` takes; more than a game's lag runs into
+  /// visible glitches, and two covers most games.
+  pub const MAX_FRAMES: u32 = 2;
+
+  /// Off, 0 frames.
+  pub fn new() -> Self {
+    RunAhead {
+      frames: 0,
+      saved: CPU::new(),
+    }
+  }
+
+  pub fn frames(&self) -> u32 {
+    self.frames
+  }
+
+  /// Show the picture `frames` frames ahead, 0 turning it off. False, and
+  /// no change, above `MAX_FRAMES`.
+  pub fn set_frames(&mut self, frames: u32) -> bool {
+    if frames > Self::MAX_FRAMES {
+      return false;
+    }
+    self.frames = frames;
+    true
+  }
+
+  /// Right after `cpu` finished a frame: replace the picture with the one
+  /// `frames` frames on and leave everything else as it was. False if the
+  /// CPU stopped in one of those frames, in which case the real picture
+  /// stays; the fault happens for real when emulation gets there.
+  pub fn run(&mut self, cpu: &mut CPU) -> bool {
+    if self.frames == 0 {
+      return true;
+    }
+    self.saved.clone_from(cpu);
+    let mut ran = true;
+    for left in (0..self.frames).rev() {
+      cpu.bus.ppu.skip_rendering = left > 0;
+      if !cpu.run_frame() {
+        ran = false;
+        break;
+      }
+    }
+    mem::swap(cpu, &mut self.saved);
+    if ran {
+      mem::swap(&mut cpu.bus.ppu.frame, &mut self.saved.bus.ppu.frame);
+    }
+    ran
+  }
+}
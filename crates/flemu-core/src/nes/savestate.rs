@@ -0,0 +1,202 @@
+use std::fmt;
+
+/*
+  Savestates: everything that makes up the running console, written field
+  by field in little endian behind a small header.
+
+    "FLMU" magic, version u32, cartridge checksum u32, machine
+
+  Every component writes its own fields through `StateWriter` and reads
+  them back in the same order through `StateReader`; there are no tags or
+  lengths in between, so any change to what a component writes has to
+  bump `VERSION`. Older versions are refused, not converted.
+
+  Only the machine goes in. Settings (strict mode, palette, layers hidden
+  in the debugger, cheats), what the frontend has plugged in (Zapper aim)
+  and the audio already resampled for output stay as they are when a
+  state is loaded. ROM data isn't included either, just the checksum of
+  the cartridge the state was made with, and only the RAM and registers of
+  the board.
+*/
+
+pub const MAGIC: &[u8; 4] = b"FLMU";
+pub const VERSION: u32 = 9;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StateError {
+  /// Doesn't start like a savestate.
+  NotAState,
+  /// Written by another version of the format.
+  Version { found: u32, supported: u32 },
+  /// Made with another cartridge inserted.
+  WrongCartridge,
+  /// Ends in the middle of a field.
+  Truncated,
+  /// A value no machine can be in, or bytes left over at the end.
+  Corrupt,
+}
+
+impl fmt::Display for StateError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      StateError::NotAState => write!(f, "not a savestate"),
+      StateError::Version { found, supported } => write!(
+        f,
+        "savestate version {} can't be loaded, this build reads version {}",
+        found, supported
+      ),
+      StateError::WrongCartridge => write!(f, "savestate was made with another cartridge"),
+      StateError::Truncated => write!(f, "savestate is truncated"),
+      StateError::Corrupt => write!(f, "savestate is corrupt"),
+    }
+  }
+}
+
+impl std::error::Error for StateError {}
+
+/// FNV-1a over PRG and CHR ROM, to tell the cartridge a state belongs to.
+pub fn cartridge_checksum(prg_rom: &[u8], chr_rom: &[u8]) -> u32 {
+  prg_rom
+    .iter()
+    .chain(chr_rom)
+    .fold(0x811c_9dc5, |hash: u32, &byte| {
+      (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+#[derive(Debug, Default)]
+pub struct StateWriter {
+  bytes: Vec<u8>,
+}
+
+impl StateWriter {
+  /// A writer with the header already in place.
+  pub fn new(cartridge_checksum: u32) -> Self {
+    let mut writer = StateWriter::default();
+    writer.bytes(MAGIC);
+    writer.u32(VERSION);
+    writer.u32(cartridge_checksum);
+    writer
+  }
+
+  pub fn u8(&mut self, value: u8) {
+    self.bytes.push(value);
+  }
+
+  pub fn bool(&mut self, value: bool) {
+    self.u8(value as u8);
+  }
+
+  pub fn u16(&mut self, value: u16) {
+    self.bytes.extend_from_slice(&value.to_le_bytes());
+  }
+
+  pub fn u32(&mut self, value: u32) {
+    self.bytes.extend_from_slice(&value.to_le_bytes());
+  }
+
+  pub fn u64(&mut self, value: u64) {
+    self.bytes.extend_from_slice(&value.to_le_bytes());
+  }
+
+  /// Raw bytes; the reader has to know how many to expect.
+  pub fn bytes(&mut self, bytes: &[u8]) {
+    self.bytes.extend_from_slice(bytes);
+  }
+
+  pub fn finish(self) -> Vec<u8> {
+    self.bytes
+  }
+}
+
+pub struct StateReader<'a> {
+  bytes: &'a [u8],
+  pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+  /// Check the header of `bytes` against this build and the cartridge
+  /// inserted, and start reading the machine after it.
+  pub fn new(bytes: &'a [u8], cartridge_checksum: u32) -> Result<Self, StateError> {
+    if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+      return Err(StateError::NotAState);
+    }
+    let mut reader = StateReader {
+      bytes,
+      pos: MAGIC.len(),
+    };
+    let version = reader.u32()?;
+    if version != VERSION {
+      return Err(StateError::Version {
+        found: version,
+        supported: VERSION,
+      });
+    }
+    if reader.u32()? != cartridge_checksum {
+      return Err(StateError::WrongCartridge);
+    }
+    Ok(reader)
+  }
+
+  fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+    if self.bytes.len() - self.pos < len {
+      return Err(StateError::Truncated);
+    }
+    let taken = &self.bytes[self.pos..self.pos + len];
+    self.pos += len;
+    Ok(taken)
+  }
+
+  pub fn u8(&mut self) -> Result<u8, StateError> {
+    Ok(self.take(1)?[0])
+  }
+
+  pub fn bool(&mut self) -> Result<bool, StateError> {
+    match self.u8()? {
+      0 => Ok(false),
+      1 => Ok(true),
+      _ => Err(StateError::Corrupt),
+    }
+  }
+
+  pub fn u16(&mut self) -> Result<u16, StateError> {
+    let mut bytes = [0; 2];
+    bytes.copy_from_slice(self.take(2)?);
+    Ok(u16::from_le_bytes(bytes))
+  }
+
+  pub fn u32(&mut self) -> Result<u32, StateError> {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(self.take(4)?);
+    Ok(u32::from_le_bytes(bytes))
+  }
+
+  pub fn u64(&mut self) -> Result<u64, StateError> {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(self.take(8)?);
+    Ok(u64::from_le_bytes(bytes))
+  }
+
+  /// Fill `out` completely.
+  pub fn bytes_into(&mut self, out: &mut [u8]) -> Result<(), StateError> {
+    out.copy_from_slice(self.take(out.len())?);
+    Ok(())
+  }
+
+  /// A u8 that has to be below `limit`, e.g. an index into a table.
+  pub fn u8_below(&mut self, limit: u8) -> Result<u8, StateError> {
+    match self.u8()? {
+      value if value < limit => Ok(value),
+      _ => Err(StateError::Corrupt),
+    }
+  }
+
+  /// Everything was read.
+  pub fn finish(self) -> Result<(), StateError> {
+    if self.pos == self.bytes.len() {
+      Ok(())
+    } else {
+      Err(StateError::Corrupt)
+    }
+  }
+}
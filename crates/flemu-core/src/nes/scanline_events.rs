@@ -0,0 +1,72 @@
+use bitflags::bitflags;
+
+/*
+  What interrupted the CPU on each scanline of a frame, for a debugger
+  to show where a game's NMI handler, mapper IRQs and DMAs fall: an NMI
+  that runs into the picture, an IRQ a line late, a DMC fetch stealing
+  cycles from a timed loop.
+
+  Lines are numbered as the PPU counts them, 0-239 the picture, then
+  post-render, vblank and pre-render, 262 of them on NTSC and 312 on
+  PAL and Dendy. An event is logged on the line the PPU is at when it
+  starts.
+*/
+
+bitflags! {
+  pub struct LineEvents: u8 {
+    const NMI     = 0b0001;
+    const IRQ     = 0b0010;
+    /// A $4014 copy into OAM.
+    const OAM_DMA = 0b0100;
+    /// The DMC fetching a sample byte.
+    const DMC_DMA = 0b1000;
+  }
+}
+
+/// The events of the frame being drawn and of the last one finished.
+#[derive(Debug, Clone, Default)]
+pub struct ScanlineEvents {
+  current: Vec<LineEvents>,
+  last: Vec<LineEvents>,
+}
+
+impl ScanlineEvents {
+  pub fn new() -> Self {
+    ScanlineEvents::default()
+  }
+
+  pub fn record(&mut self, scanline: u16, events: LineEvents) {
+    let line = scanline as usize;
+    if self.current.len() <= line {
+      self.current.resize(line + 1, LineEvents::empty());
+    }
+    self.current[line] |= events;
+  }
+
+  /// At vblank: the frame so far becomes the last one.
+  pub fn end_frame(&mut self) {
+    std::mem::swap(&mut self.current, &mut self.last);
+    self.current.clear();
+  }
+
+  /// Every line of the frame being drawn up to the last with an event.
+  pub fn current_frame(&self) -> &[LineEvents] {
+    &self.current
+  }
+
+  /// The last whole frame, by line. Frames end at vblank, so it's the
+  /// lines from the NMI's on, around to the picture; lines past the last
+  /// with an event are left out.
+  pub fn last_frame(&self) -> &[LineEvents] {
+    &self.last
+  }
+
+  /// `scanline`'s events in the last frame.
+  pub fn line(&self, scanline: u16) -> LineEvents {
+    self
+      .last
+      .get(scanline as usize)
+      .copied()
+      .unwrap_or_else(LineEvents::empty)
+  }
+}
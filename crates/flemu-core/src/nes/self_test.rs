@@ -0,0 +1,274 @@
+use crate::nes::apu::{Apu, FETCH_STALL};
+use crate::nes::bus::Mem;
+use crate::nes::cartridge::Rom;
+use crate::nes::cpu::{CpuFlags, CPU};
+use crate::nes::mapper::{NoCartridge, Nrom};
+use std::fmt::{Debug, Write};
+
+/*
+  A handful of tiny programs and register pokes with known outcomes, run
+  on a fresh machine each. Not a replacement for the test ROMs: the point
+  is a one-click answer to "does this build in this browser behave", to
+  attach to a bug report.
+*/
+
+// generous: the slowest check waits out one frame
+const MAX_STEPS: usize = 100_000;
+
+fn expect<T: PartialEq + Debug>(what: &str, got: T, want: T) -> Result<(), String> {
+  if got == want {
+    Ok(())
+  } else {
+    Err(format!("{}: expected {:?}, got {:?}", what, want, got))
+  }
+}
+
+/// Run `cpu` until BRK, failing on a fault or if it never gets there.
+fn run_to_brk(cpu: &mut CPU) -> Result<(), String> {
+  for _ in 0..MAX_STEPS {
+    match cpu.step() {
+      Ok(Some(_)) => {}
+      Ok(None) => return Ok(()),
+      Err(error) => return Err(error.to_string()),
+    }
+  }
+  Err(format!("still running after {} instructions", MAX_STEPS))
+}
+
+fn run_program(program: Vec<u8>) -> Result<CPU, String> {
+  let mut cpu = CPU::new();
+  cpu.reset();
+  cpu.load(program).map_err(|error| error.to_string())?;
+  run_to_brk(&mut cpu)?;
+  Ok(cpu)
+}
+
+fn cpu_arithmetic() -> Result<(), String> {
+  // LDA #$7f, ADC #$01, TAX, SEC, SBC #$81
+  let cpu = run_program(vec![0xa9, 0x7f, 0x69, 0x01, 0xaa, 0x38, 0xe9, 0x81, 0x00])?;
+  expect("X after ADC", cpu.register_x, 0x80)?;
+  expect("A after SBC", cpu.register_a, 0xff)?;
+  expect("carry", cpu.status.contains(CpuFlags::CARRY), false)
+}
+
+fn cpu_stack() -> Result<(), String> {
+  // LDA #$42, PHA, JSR sub, PLA, BRK; sub: LDA #0, RTS
+  let cpu = run_program(vec![
+    0xa9, 0x42, 0x48, 0x20, 0x08, 0x80, 0x68, 0x00, 0xa9, 0x00, 0x60,
+  ])?;
+  expect("A", cpu.register_a, 0x42)?;
+  expect("stack pointer", cpu.stack_pointer, 0xfd)
+}
+
+fn cpu_branches() -> Result<(), String> {
+  // LDX #5, loop: INY, DEX, BNE loop
+  let cpu = run_program(vec![0xa2, 0x05, 0xc8, 0xca, 0xd0, 0xfc, 0x00])?;
+  expect("Y", cpu.register_y, 5)
+}
+
+fn cpu_cycles() -> Result<(), String> {
+  let mut cpu = CPU::new();
+  cpu.reset();
+  // NOP, LDA $12ff,X into the next page, BRK
+  cpu
+    .load(vec![0xea, 0xbd, 0xff, 0x12, 0x00])
+    .map_err(|error| error.to_string())?;
+  cpu.register_x = 1;
+  expect("NOP cycles", cpu.step(), Ok(Some(2)))?;
+  expect("page crossing LDA cycles", cpu.step(), Ok(Some(5)))
+}
+
+fn ppu_vram() -> Result<(), String> {
+  let mut cpu = CPU::new();
+  let bus = &mut cpu.bus;
+  bus.mem_write(0x2006, 0x21);
+  bus.mem_write(0x2006, 0x08);
+  bus.mem_write(0x2007, 0x5a);
+  bus.mem_write(0x2007, 0xa5);
+  bus.mem_write(0x2006, 0x21);
+  bus.mem_write(0x2006, 0x08);
+  // the first read only fills the buffer
+  bus.mem_read(0x2007);
+  expect("$2108", bus.mem_read(0x2007), 0x5a)?;
+  expect("$2109", bus.mem_read(0x2007), 0xa5)
+}
+
+fn ppu_vblank() -> Result<(), String> {
+  let mut cpu = CPU::new();
+  let bus = &mut cpu.bus;
+  let frame = bus.ppu.frame_count;
+  while bus.ppu.frame_count == frame {
+    bus.tick(1);
+  }
+  expect("vblank flag", bus.mem_read(0x2002) & 0x80, 0x80)?;
+  expect("vblank flag after a read", bus.mem_read(0x2002) & 0x80, 0)
+}
+
+fn ppu_nmi() -> Result<(), String> {
+  // LDA #$80, STA $2000, loop: JMP loop; NMI at $8010: LDA #$42, BRK
+  let mut program = vec![0xa9, 0x80, 0x8d, 0x00, 0x20, 0x4c, 0x05, 0x80];
+  program.resize(0x10, 0xea);
+  program.extend(&[0xa9, 0x42, 0x00]);
+  let mut rom = Rom::from_program(&program);
+  rom.prg_rom[0x7ffa] = 0x10;
+  rom.prg_rom[0x7ffb] = 0x80;
+
+  let mut cpu = CPU::new();
+  cpu.reset();
+  cpu.load_rom(rom).map_err(|e| e.to_string())?;
+  run_to_brk(&mut cpu)?;
+  expect("A in the NMI handler", cpu.register_a, 0x42)
+}
+
+fn apu_length_counter() -> Result<(), String> {
+  let mut apu = Apu::new();
+  apu.write_register(0x4015, 0x01);
+  apu.write_register(0x4000, 0x10);
+  // length index 3: two half frames
+  apu.write_register(0x4003, 0x18);
+  expect("$4015 while playing", apu.peek_status() & 0x01, 0x01)?;
+  apu.tick(&NoCartridge, 29830);
+  expect("$4015 once expired", apu.peek_status() & 0x01, 0)
+}
+
+fn apu_frame_irq() -> Result<(), String> {
+  let mut apu = Apu::new();
+  apu.tick(&NoCartridge, 29830);
+  expect("frame IRQ", apu.irq_pending(), true)?;
+  apu.read_status();
+  expect("frame IRQ after reading $4015", apu.irq_pending(), false)
+}
+
+fn apu_dmc() -> Result<(), String> {
+  let cart = Nrom::new(Rom::from_program(&[]));
+  let mut apu = Apu::new();
+  apu.write_register(0x4010, 0x8f);
+  apu.write_register(0x4015, 0x10);
+  expect("stall of a sample fetch", apu.tick(&cart, 1), FETCH_STALL)?;
+  expect("DMC IRQ at the end of the sample", apu.irq_pending(), true)
+}
+
+/// One self-test check.
+pub struct Check {
+  /// "cpu", "ppu" or "apu".
+  pub component: &'static str,
+  pub name: &'static str,
+  run: fn() -> Result<(), String>,
+}
+
+pub const CHECKS: [Check; 10] = [
+  Check {
+    component: "cpu",
+    name: "arithmetic",
+    run: cpu_arithmetic,
+  },
+  Check {
+    component: "cpu",
+    name: "stack and subroutines",
+    run: cpu_stack,
+  },
+  Check {
+    component: "cpu",
+    name: "branches",
+    run: cpu_branches,
+  },
+  Check {
+    component: "cpu",
+    name: "cycle counts",
+    run: cpu_cycles,
+  },
+  Check {
+    component: "ppu",
+    name: "VRAM access",
+    run: ppu_vram,
+  },
+  Check {
+    component: "ppu",
+    name: "vblank flag",
+    run: ppu_vblank,
+  },
+  Check {
+    component: "ppu",
+    name: "NMI",
+    run: ppu_nmi,
+  },
+  Check {
+    component: "apu",
+    name: "length counter",
+    run: apu_length_counter,
+  },
+  Check {
+    component: "apu",
+    name: "frame IRQ",
+    run: apu_frame_irq,
+  },
+  Check {
+    component: "apu",
+    name: "DMC fetch",
+    run: apu_dmc,
+  },
+];
+
+/// Outcome of one check.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckResult {
+  pub component: &'static str,
+  pub name: &'static str,
+  /// What went wrong, None if it passed.
+  pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+  pub results: Vec<CheckResult>,
+}
+
+impl Report {
+  pub fn passed(&self) -> bool {
+    self.results.iter().all(|result| result.error.is_none())
+  }
+
+  pub fn failures(&self) -> usize {
+    self
+      .results
+      .iter()
+      .filter(|result| result.error.is_some())
+      .count()
+  }
+
+  /// One line per check, then a summary.
+  pub fn to_text(&self) -> String {
+    let mut out = String::new();
+    for result in &self.results {
+      // writing into a String can't fail
+      match &result.error {
+        None => writeln!(out, "ok    {} {}", result.component, result.name).unwrap(),
+        Some(error) => {
+          writeln!(out, "FAIL  {} {}: {}", result.component, result.name, error).unwrap()
+        }
+      }
+    }
+    writeln!(
+      out,
+      "{} of {} checks passed",
+      self.results.len() - self.failures(),
+      self.results.len()
+    )
+    .unwrap();
+    out
+  }
+}
+
+/// Run every check in `CHECKS`.
+pub fn run() -> Report {
+  Report {
+    results: CHECKS
+      .iter()
+      .map(|check| CheckResult {
+        component: check.component,
+        name: check.name,
+        error: (check.run)().err(),
+      })
+      .collect(),
+  }
+}
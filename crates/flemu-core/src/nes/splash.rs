@@ -0,0 +1,100 @@
+use crate::nes::ppu::Frame;
+
+/*
+  Idle screen shown while the slot is empty: the logo bobbing over a
+  scrolling floor, drawn straight into a frame with NES colors so it goes
+  through the same palette and presentation code as a running game.
+*/
+
+const BACKDROP: u8 = 0x0F;
+const FLOOR_TOP: usize = 168;
+const FLOOR: [u8; 4] = [0x01, 0x11, 0x21, 0x11];
+// hue cycle for the logo
+const LOGO: [u8; 6] = [0x21, 0x22, 0x24, 0x26, 0x28, 0x2A];
+const CAPTION: u8 = 0x10;
+
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+
+// 5x7, most significant of the low 5 bits is the leftmost column
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+  match c {
+    'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+    'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+    'D' => [0x1E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1E],
+    'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+    'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+    'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+    'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+    'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+    'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+    'N' => [0x11, 0x19, 0x15, 0x13, 0x11, 0x11, 0x11],
+    'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+    'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+    'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+    'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+    _ => [0; GLYPH_HEIGHT],
+  }
+}
+
+/// Width in pixels of `text` drawn at `scale`, one blank column between
+/// letters.
+fn text_width(text: &str, scale: usize) -> usize {
+  let letters = text.chars().count();
+  (letters * (GLYPH_WIDTH + 1) - 1) * scale
+}
+
+fn draw_text(frame: &mut Frame, text: &str, left: usize, top: usize, scale: usize, color: u8) {
+  for (i, c) in text.chars().enumerate() {
+    let rows = glyph(c);
+    let x0 = left + i * (GLYPH_WIDTH + 1) * scale;
+    for (row, bits) in rows.iter().enumerate() {
+      for column in 0..GLYPH_WIDTH {
+        if bits & (0x10 >> column) == 0 {
+          continue;
+        }
+        for dy in 0..scale {
+          for dx in 0..scale {
+            let (x, y) = (x0 + column * scale + dx, top + row * scale + dy);
+            if x < Frame::WIDTH && y < Frame::HEIGHT {
+              frame.set_pixel(x, y, color);
+            }
+          }
+        }
+      }
+    }
+  }
+}
+
+/// Draw frame number `tick` of the splash animation.
+pub fn draw(frame: &mut Frame, tick: u64) {
+  for y in 0..Frame::HEIGHT {
+    for x in 0..Frame::WIDTH {
+      let color = if y < FLOOR_TOP {
+        BACKDROP
+      } else {
+        // diagonal stripes scrolling left, faster further down
+        let depth = y - FLOOR_TOP;
+        let offset = tick as usize * (1 + depth / 24);
+        FLOOR[((x + depth + offset) / 16) % FLOOR.len()]
+      };
+      frame.set_pixel(x, y, color);
+    }
+  }
+
+  let scale = 4;
+  let title = "FLEMU";
+  // 0..8..0 pixels of bounce
+  let phase = (tick / 4 % 16) as i64;
+  let bounce = (8 - (phase - 8).abs()) as usize;
+  let left = (Frame::WIDTH - text_width(title, scale)) / 2;
+  let color = LOGO[(tick / 8) as usize % LOGO.len()];
+  draw_text(frame, title, left, 64 + bounce, scale, color);
+
+  // blink the caption every half second
+  if tick / 30 % 2 == 0 {
+    let caption = "NO CARTRIDGE";
+    let left = (Frame::WIDTH - text_width(caption, 1)) / 2;
+    draw_text(frame, caption, left, 120, 1, CAPTION);
+  }
+}
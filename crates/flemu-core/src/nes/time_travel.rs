@@ -0,0 +1,112 @@
+use crate::nes::cpu::{EmuError, CPU};
+use crate::nes::rollback::RollbackBuffer;
+use std::fmt;
+
+/*
+  Stepping backwards, one instruction at a time.
+
+  Instructions can't be undone, so the debugger executes through
+  `TimeTravel::step`, which snapshots the machine into a rollback ring every
+  `interval` instructions. Going back to instruction N restores the last
+  snapshot at or before N and re-runs forward to it; emulation is
+  deterministic, so the result is exactly the machine that was there, trace
+  log included. How far back it reaches is `checkpoints * interval`
+  instructions, minus whatever the oldest checkpoint has been overwritten by.
+*/
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TimeTravelError {
+  /// Already at the first recorded instruction.
+  AtStart,
+  /// That instruction's checkpoint has been overwritten.
+  OutOfWindow(u64),
+}
+
+impl fmt::Display for TimeTravelError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      TimeTravelError::AtStart => write!(f, "nothing recorded before this instruction"),
+      TimeTravelError::OutOfWindow(position) => write!(
+        f,
+        "instruction {} is older than the time travel window",
+        position
+      ),
+    }
+  }
+}
+
+impl std::error::Error for TimeTravelError {}
+
+pub struct TimeTravel {
+  checkpoints: RollbackBuffer<CPU>,
+  interval: u64,
+  // instructions executed through `step` since recording started
+  position: u64,
+}
+
+impl TimeTravel {
+  pub fn new(checkpoints: usize, interval: u64) -> Self {
+    TimeTravel {
+      checkpoints: RollbackBuffer::new(checkpoints),
+      interval: interval.max(1),
+      position: 0,
+    }
+  }
+
+  /// Instructions executed since recording started.
+  pub fn position(&self) -> u64 {
+    self.position
+  }
+
+  /// Execute one instruction, recording a checkpoint when one is due.
+  /// Returns what `CPU::step` returns.
+  pub fn step(&mut self, cpu: &mut CPU) -> Result<Option<u16>, EmuError> {
+    if self.position % self.interval == 0 {
+      self
+        .checkpoints
+        .snapshot(self.checkpoint(self.position), cpu);
+    }
+    let cycles = cpu.step();
+    if let Ok(Some(_)) = cycles {
+      self.position += 1;
+    }
+    cycles
+  }
+
+  fn checkpoint(&self, position: u64) -> u32 {
+    (position / self.interval) as u32
+  }
+
+  /// Bring `cpu` to how it was after instruction `position`, re-running
+  /// from the nearest checkpoint. Positions ahead just run forward.
+  pub fn seek(&mut self, cpu: &mut CPU, position: u64) -> Result<(), TimeTravelError> {
+    if position < self.position {
+      let checkpoint = self.checkpoint(position);
+      if !self.checkpoints.restore(checkpoint, cpu) {
+        return Err(TimeTravelError::OutOfWindow(position));
+      }
+      self.position = checkpoint as u64 * self.interval;
+    }
+    while self.position < position {
+      if !matches!(self.step(cpu), Ok(Some(_))) {
+        break;
+      }
+    }
+    Ok(())
+  }
+
+  /// Undo the last instruction.
+  pub fn step_back(&mut self, cpu: &mut CPU) -> Result<(), TimeTravelError> {
+    if self.position == 0 {
+      return Err(TimeTravelError::AtStart);
+    }
+    self.seek(cpu, self.position - 1)
+  }
+
+  /// Start recording over from `cpu`'s current state, e.g. after loading a
+  /// ROM or a state that didn't come from stepping.
+  pub fn clear(&mut self) {
+    self.checkpoints.clear();
+    self.position = 0;
+  }
+}
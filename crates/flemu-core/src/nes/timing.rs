@@ -0,0 +1,119 @@
+use crate::nes::cartridge::Region;
+
+/*
+  Console timing: how fast the CPU runs, how many PPU dots fit in a CPU
+  cycle and how a frame is laid out.
+
+           CPU clock     dots/cycle  scanlines  vblank from  odd frames
+  NTSC     1789773 Hz    3           262        241          a dot short
+  PAL      1662607 Hz    3.2         312        241          full length
+  Dendy    1773448 Hz    3           312        291          full length
+
+  The pre-render line is always the last one. PAL's extra lines all go to
+  vblank; the Dendy, a famiclone built to run NTSC games on PAL TVs, puts
+  50 of them idle after the picture instead so vblank lasts about as many
+  cycles as on NTSC and NMI handlers written for it still fit.
+
+  The APU follows the CPU clock: PAL has its own frame counter steps and
+  noise and DMC period tables, the Dendy uses NTSC's.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timing {
+  Ntsc,
+  Pal,
+  Dendy,
+}
+
+impl Default for Timing {
+  fn default() -> Self {
+    Timing::Ntsc
+  }
+}
+
+impl Timing {
+  pub const ALL: [Timing; 3] = [Timing::Ntsc, Timing::Pal, Timing::Dendy];
+
+  /// Stable id: "ntsc", "pal", "dendy".
+  pub fn id(&self) -> &'static str {
+    match self {
+      Timing::Ntsc => "ntsc",
+      Timing::Pal => "pal",
+      Timing::Dendy => "dendy",
+    }
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      Timing::Ntsc => "NTSC",
+      Timing::Pal => "PAL",
+      Timing::Dendy => "Dendy",
+    }
+  }
+
+  pub fn from_id(id: &str) -> Option<Timing> {
+    Timing::ALL.iter().copied().find(|timing| timing.id() == id)
+  }
+
+  /// What a ROM header asks for. Games for both run as NTSC.
+  pub fn for_region(region: Region) -> Timing {
+    match region {
+      Region::Ntsc | Region::MultiRegion => Timing::Ntsc,
+      Region::Pal => Timing::Pal,
+      Region::Dendy => Timing::Dendy,
+    }
+  }
+
+  /// CPU cycles per second.
+  pub fn cpu_clock(&self) -> f64 {
+    match self {
+      Timing::Ntsc => 1_789_773.0,
+      Timing::Pal => 1_662_607.0,
+      Timing::Dendy => 1_773_448.0,
+    }
+  }
+
+  /// PPU dots per CPU cycle, as a fraction (numerator, denominator).
+  pub fn dots_per_cycle(&self) -> (u32, u32) {
+    match self {
+      Timing::Pal => (16, 5),
+      Timing::Ntsc | Timing::Dendy => (3, 1),
+    }
+  }
+
+  pub fn scanlines(&self) -> u16 {
+    match self {
+      Timing::Ntsc => 262,
+      Timing::Pal | Timing::Dendy => 312,
+    }
+  }
+
+  /// The line vblank starts on, at dot 1.
+  pub fn vblank_scanline(&self) -> u16 {
+    match self {
+      Timing::Ntsc | Timing::Pal => 241,
+      Timing::Dendy => 291,
+    }
+  }
+
+  pub fn pre_render_scanline(&self) -> u16 {
+    self.scanlines() - 1
+  }
+
+  /// Whether the pre-render line of odd frames is a dot short while
+  /// rendering is on.
+  pub fn skips_odd_dot(&self) -> bool {
+    *self == Timing::Ntsc
+  }
+
+  /// Frames per second, about 60.1 on NTSC and 50 on the others.
+  pub fn frame_rate(&self) -> f64 {
+    let (dots, cycles) = self.dots_per_cycle();
+    let dots_per_second = self.cpu_clock() * dots as f64 / cycles as f64;
+    let mut dots_per_frame = 341.0 * self.scanlines() as f64;
+    if self.skips_odd_dot() {
+      dots_per_frame -= 0.5;
+    }
+    dots_per_second / dots_per_frame
+  }
+}
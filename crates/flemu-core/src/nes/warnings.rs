@@ -0,0 +1,113 @@
+use std::fmt;
+
+/*
+  Things a game can do that work by accident on some emulators and not on
+  hardware, or the other way round. None of them stop emulation; they're
+  queued for whoever is developing the game to look at.
+
+  A buggy loop can hit the same one thousands of times a frame, so each
+  kind is rate limited: the first `MAX_PER_WINDOW` of a kind every
+  `WINDOW_FRAMES` frames are kept, the rest only counted.
+*/
+
+pub const MAX_PER_WINDOW: u32 = 4;
+pub const WINDOW_FRAMES: u64 = 60;
+// warnings kept until somebody takes them
+const MAX_QUEUED: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Warning {
+  /// A write to PRG ROM on a board without registers there.
+  RomWrite { addr: u16, value: u8 },
+  /// A read of a write-only or unmapped register, returning open bus.
+  OpenBusRead { addr: u16 },
+  /// An undocumented opcode ran (not an error outside strict mode).
+  UnofficialOpcode { pc: u16, code: u8 },
+  /// OAM DMA while the PPU was drawing, which corrupts sprites on
+  /// hardware.
+  OamDmaDuringRendering { scanline: u16 },
+}
+
+impl Warning {
+  pub const KINDS: usize = 4;
+
+  /// Stable id for filtering: "rom-write", "open-bus-read", ...
+  pub fn kind(&self) -> &'static str {
+    match self {
+      Warning::RomWrite { .. } => "rom-write",
+      Warning::OpenBusRead { .. } => "open-bus-read",
+      Warning::UnofficialOpcode { .. } => "unofficial-opcode",
+      Warning::OamDmaDuringRendering { .. } => "oam-dma-during-rendering",
+    }
+  }
+
+  fn index(&self) -> usize {
+    match self {
+      Warning::RomWrite { .. } => 0,
+      Warning::OpenBusRead { .. } => 1,
+      Warning::UnofficialOpcode { .. } => 2,
+      Warning::OamDmaDuringRendering { .. } => 3,
+    }
+  }
+}
+
+impl fmt::Display for Warning {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Warning::RomWrite { addr, value } => {
+        write!(f, "write of ${:02X} to ROM at ${:04X}", value, addr)
+      }
+      Warning::OpenBusRead { addr } => write!(f, "read of open bus at ${:04X}", addr),
+      Warning::UnofficialOpcode { pc, code } => {
+        write!(f, "unofficial opcode ${:02X} at ${:04X}", code, pc)
+      }
+      Warning::OamDmaDuringRendering { scanline } => {
+        write!(f, "OAM DMA during rendering, scanline {}", scanline)
+      }
+    }
+  }
+}
+
+/// A warning and the PPU frame it happened in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Report {
+  pub frame: u64,
+  pub warning: Warning,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Warnings {
+  queue: Vec<Report>,
+  // per kind, in the current window
+  counts: [u32; Warning::KINDS],
+  window_start: u64,
+  suppressed: u64,
+}
+
+impl Warnings {
+  /// Queue `warning` unless its kind is over the limit for this window or
+  /// the queue is full.
+  pub fn report(&mut self, frame: u64, warning: Warning) {
+    if frame >= self.window_start + WINDOW_FRAMES {
+      self.window_start = frame;
+      self.counts = [0; Warning::KINDS];
+    }
+    let count = &mut self.counts[warning.index()];
+    if *count >= MAX_PER_WINDOW || self.queue.len() >= MAX_QUEUED {
+      self.suppressed += 1;
+      return;
+    }
+    *count += 1;
+    self.queue.push(Report { frame, warning });
+  }
+
+  /// Everything queued, oldest first.
+  pub fn take(&mut self) -> Vec<Report> {
+    std::mem::take(&mut self.queue)
+  }
+
+  /// Warnings dropped by the rate limit since the start.
+  pub fn suppressed(&self) -> u64 {
+    self.suppressed
+  }
+}
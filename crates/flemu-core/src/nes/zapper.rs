@@ -0,0 +1,99 @@
+use crate::nes::ppu::Frame;
+
+// $4017 bits; the rest is open bus like a controller read
+const OPEN_BUS: u8 = 0x40;
+// clear while the photodiode sees light
+const LIGHT_NOT_SENSED: u8 = 0x08;
+const TRIGGER_PULLED: u8 = 0x10;
+
+// scanlines the photodiode keeps reporting light after the beam passed
+// the cursor, about what the real circuit does
+const SENSE_LINES: u16 = 20;
+// pixels around the cursor the gun sees
+const SENSE_RADIUS: usize = 2;
+// a click holds the trigger this many frames, long enough for a game
+// polling once per frame to see it
+pub const TRIGGER_FRAMES: u8 = 3;
+
+/// Whether the TV would be bright enough at `color` for the photodiode:
+/// the two lightest rows of the NES palette minus the blacks. Decided on
+/// the color index, so the frontend's palette doesn't change gameplay.
+pub fn is_bright(color: u8) -> bool {
+  color & 0x30 >= 0x20 && color & 0x0F < 0x0D
+}
+
+/// The Zapper light gun on $4017: no shift register, every read reports the
+/// trigger and whether the spot the gun points at was just lit by the beam.
+#[derive(Debug, Clone, Default)]
+pub struct Zapper {
+  // picture coordinates, None off screen
+  aim: Option<(usize, usize)>,
+  trigger_frames: u8,
+}
+
+impl Zapper {
+  /// Point at picture pixel (`x`, `y`); anywhere outside 256x240 is off
+  /// screen.
+  pub fn aim(&mut self, x: i32, y: i32) {
+    self.aim = if (0..Frame::WIDTH as i32).contains(&x) && (0..Frame::HEIGHT as i32).contains(&y) {
+      Some((x as usize, y as usize))
+    } else {
+      None
+    };
+  }
+
+  pub fn aim_off_screen(&mut self) {
+    self.aim = None;
+  }
+
+  pub fn aimed_at(&self) -> Option<(usize, usize)> {
+    self.aim
+  }
+
+  /// Pull the trigger for `TRIGGER_FRAMES` frames.
+  pub fn pull_trigger(&mut self) {
+    self.trigger_frames = TRIGGER_FRAMES;
+  }
+
+  pub fn trigger_pulled(&self) -> bool {
+    self.trigger_frames > 0
+  }
+
+  /// Count down the trigger pulse. The bus calls this at every vblank.
+  pub fn end_frame(&mut self) {
+    self.trigger_frames = self.trigger_frames.saturating_sub(1);
+  }
+
+  /// Light reaches the gun for `SENSE_LINES` scanlines after the PPU drew a
+  /// bright pixel near the cursor. `frame` is the picture being drawn and
+  /// `scanline` the PPU's position in it; lines are drawn a whole line at
+  /// a time, so line `y` is fresh once the PPU moved past it.
+  pub fn light_sensed(&self, frame: &Frame, scanline: u16) -> bool {
+    let (x, y) = match self.aim {
+      Some(aim) => aim,
+      None => return false,
+    };
+    let top = y.saturating_sub(SENSE_RADIUS);
+    let bottom = (y + SENSE_RADIUS).min(Frame::HEIGHT - 1);
+    (top..=bottom).any(|line| {
+      let line_passed = (scanline as usize) > line;
+      let still_lit = (scanline as usize) < line + SENSE_LINES as usize;
+      line_passed
+        && still_lit
+        && (x.saturating_sub(SENSE_RADIUS)..=(x + SENSE_RADIUS).min(Frame::WIDTH - 1))
+          .any(|column| is_bright(frame.pixel(column, line)))
+    })
+  }
+
+  /// $4017 read. Reading has no side effects.
+  pub fn read(&self, frame: &Frame, scanline: u16) -> u8 {
+    let mut value = OPEN_BUS;
+    if !self.light_sensed(frame, scanline) {
+      value |= LIGHT_NOT_SENSED;
+    }
+    if self.trigger_pulled() {
+      value |= TRIGGER_PULLED;
+    }
+    value
+  }
+}
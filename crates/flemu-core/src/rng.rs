@@ -0,0 +1,56 @@
+/// Small seedable PRNG (xorshift64*) for frontend features that need
+/// randomness but must stay reproducible in deterministic mode, such as
+/// power-up RAM randomization.
+///
+/// The whole generator is a single `u64`, so it can be stored alongside the
+/// machine state and resumed exactly.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SeededRng {
+  state: u64,
+}
+
+impl SeededRng {
+  pub fn new(seed: u64) -> Self {
+    // splitmix64 scramble, so small or zero seeds still give a good
+    // (and never all-zero) xorshift state
+    let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    SeededRng::from_state(z)
+  }
+
+  /// Resume a generator from a value previously returned by `state()`.
+  pub fn from_state(state: u64) -> Self {
+    SeededRng {
+      state: if state == 0 { 1 } else { state },
+    }
+  }
+
+  pub fn state(&self) -> u64 {
+    self.state
+  }
+
+  pub fn next_u64(&mut self) -> u64 {
+    self.state ^= self.state >> 12;
+    self.state ^= self.state << 25;
+    self.state ^= self.state >> 27;
+    self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+  }
+
+  pub fn next_u8(&mut self) -> u8 {
+    (self.next_u64() >> 56) as u8
+  }
+
+  pub fn fill(&mut self, buf: &mut [u8]) {
+    for byte in buf.iter_mut() {
+      *byte = self.next_u8();
+    }
+  }
+}
+
+impl Default for SeededRng {
+  fn default() -> Self {
+    SeededRng::new(0)
+  }
+}
@@ -0,0 +1,266 @@
+/*
+  A control server for the native build: the console driven by JSON
+  commands over a WebSocket, so bots, scripts and integration tests in
+  any language can load a ROM, press buttons, run frames and look at
+  the results. `flemu-cli --serve ADDR` runs one.
+
+  Each text message is a request, answered by one reply in order:
+
+    {"id": 1, "cmd": "load", "path": "game.nes"}
+    {"id": 2, "cmd": "input", "player": 0, "buttons": ["a", "right"]}
+    {"id": 3, "cmd": "step", "frames": 60}
+    {"id": 4, "cmd": "read", "addr": 1904, "len": 16}
+    {"id": 5, "cmd": "screenshot"}
+
+  `load` takes the ROM from `path` on the server's disk or as base64 in
+  `rom`; `reset` is the reset button. Buttons are held until the next
+  `input` for that player. `step` runs 1 frame unless told more, `read`
+  1 byte from CPU space without side effects, and `screenshot` is the
+  last frame as a base64 PNG. Replies echo the id with what was asked:
+
+    {"id": 3, "ok": true, "frame": 61}
+    {"id": 4, "ok": true, "data": [0, 4, 255, ...]}
+    {"id": 5, "ok": true, "png": "iVBORw0..."}
+    {"id": 6, "ok": false, "error": "no ROM loaded"}
+
+  An id can be anything or left out.
+
+  Clients are served one at a time, the console carrying over from one
+  to the next. There's no authentication and `path` reads any file the
+  server can, so listen on localhost.
+*/
+
+pub mod json;
+pub mod websocket;
+
+use crate::nes::bus::Mem;
+use crate::nes::cartridge::Rom;
+use crate::nes::cpu::CPU;
+use crate::nes::joypad::JoypadButton;
+use crate::nes::movie::{base64_decode, base64_encode};
+use crate::nes::palette::Palette;
+use crate::nes::ppu::Frame;
+use crate::video_dump;
+use json::Json;
+use std::fs;
+use std::io;
+use std::net::{TcpListener, TcpStream};
+
+/// The most frames one `step` runs, about a minute of NTSC.
+pub const MAX_STEP: u32 = 3600;
+
+/// Names for `input`'s buttons.
+pub const BUTTONS: [(&str, JoypadButton); 8] = [
+  ("a", JoypadButton::A),
+  ("b", JoypadButton::B),
+  ("select", JoypadButton::SELECT),
+  ("start", JoypadButton::START),
+  ("up", JoypadButton::UP),
+  ("down", JoypadButton::DOWN),
+  ("left", JoypadButton::LEFT),
+  ("right", JoypadButton::RIGHT),
+];
+
+type Reply = Result<Vec<(&'static str, Json)>, String>;
+
+const NO_ROM: &str = "no ROM loaded";
+
+/// The console the commands act on, with or without a ROM in it.
+pub struct Session {
+  cpu: Option<CPU>,
+  palette: Palette,
+}
+
+impl Default for Session {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Session {
+  pub fn new() -> Self {
+    Session {
+      cpu: None,
+      palette: Palette::default(),
+    }
+  }
+
+  /// Start with `cpu`, a ROM already loaded.
+  pub fn with_cpu(cpu: CPU) -> Self {
+    Session {
+      cpu: Some(cpu),
+      palette: Palette::default(),
+    }
+  }
+
+  pub fn cpu(&self) -> Option<&CPU> {
+    self.cpu.as_ref()
+  }
+
+  /// The reply to `request`, both JSON text.
+  pub fn handle(&mut self, request: &str) -> String {
+    let request = match Json::parse(request) {
+      Ok(request) => request,
+      Err(e) => return reply(Json::Null, Err(e.to_string())),
+    };
+    let id = request.get("id").cloned().unwrap_or(Json::Null);
+    let result = match request.get("cmd").and_then(Json::as_str) {
+      Some(cmd) => self.command(cmd, &request),
+      None => Err("no cmd".to_string()),
+    };
+    reply(id, result)
+  }
+
+  fn command(&mut self, cmd: &str, request: &Json) -> Reply {
+    match cmd {
+      "load" => self.load(request),
+      "reset" => {
+        self.loaded()?.reset();
+        Ok(vec![])
+      }
+      "input" => self.input(request),
+      "step" => self.step(request),
+      "read" => self.read(request),
+      "screenshot" => self.screenshot(),
+      _ => Err(format!("unknown cmd {}", cmd)),
+    }
+  }
+
+  fn loaded(&mut self) -> Result<&mut CPU, String> {
+    self.cpu.as_mut().ok_or_else(|| NO_ROM.to_string())
+  }
+
+  fn load(&mut self, request: &Json) -> Reply {
+    let bytes = match (request.get("path"), request.get("rom")) {
+      (Some(path), None) => {
+        let path = path.as_str().ok_or("path isn't a string")?;
+        fs::read(path).map_err(|e| format!("{}: {}", path, e))?
+      }
+      (None, Some(rom)) => rom
+        .as_str()
+        .and_then(base64_decode)
+        .ok_or("rom isn't base64")?,
+      _ => return Err("load needs a path or a rom".to_string()),
+    };
+    let rom = Rom::from_bytes(&bytes).map_err(|e| e.to_string())?;
+    let mapper = rom.info.mapper as u32;
+    // like flemu-cli: BRK is an instruction, not the end of the program
+    let mut cpu = CPU::new();
+    cpu.reset();
+    cpu.halt_on_brk = false;
+    cpu.load_rom(rom).map_err(|e| e.to_string())?;
+    self.cpu = Some(cpu);
+    Ok(vec![("mapper", mapper.into())])
+  }
+
+  fn input(&mut self, request: &Json) -> Reply {
+    let player = field_u32(request, "player", 0)? as usize;
+    let names = request
+      .get("buttons")
+      .and_then(Json::as_array)
+      .ok_or("buttons isn't a list")?;
+    let mut buttons = JoypadButton::empty();
+    for name in names {
+      let name = name.as_str().unwrap_or("");
+      let &(_, button) = BUTTONS
+        .iter()
+        .find(|(id, _)| *id == name)
+        .ok_or_else(|| format!("unknown button {}", name))?;
+      buttons |= button;
+    }
+    let cpu = self.loaded()?;
+    let pad = cpu
+      .bus
+      .joypads
+      .get_mut(player)
+      .ok_or_else(|| format!("no player {}", player))?;
+    pad.set_button_pressed_status(JoypadButton::all(), false);
+    pad.set_button_pressed_status(buttons, true);
+    Ok(vec![])
+  }
+
+  fn step(&mut self, request: &Json) -> Reply {
+    let frames = field_u32(request, "frames", 1)?;
+    if frames > MAX_STEP {
+      return Err(format!("at most {} frames a step", MAX_STEP));
+    }
+    let cpu = self.loaded()?;
+    for _ in 0..frames {
+      if !cpu.run_frame() {
+        return Err(format!("stopped at ${:04X}", cpu.program_counter));
+      }
+    }
+    Ok(vec![("frame", (cpu.bus.ppu.frame_count as u32).into())])
+  }
+
+  fn read(&mut self, request: &Json) -> Reply {
+    let addr = request
+      .get("addr")
+      .and_then(Json::as_u32)
+      .filter(|&addr| addr <= 0xFFFF)
+      .ok_or("addr isn't a CPU address")?;
+    let len = field_u32(request, "len", 1)?;
+    if len > 0x10000 - addr {
+      return Err("read goes past $FFFF".to_string());
+    }
+    let cpu = self.loaded()?;
+    let data = (addr..addr + len)
+      .map(|addr| (cpu.mem_peek(addr as u16) as u32).into())
+      .collect();
+    Ok(vec![("data", Json::Array(data))])
+  }
+
+  fn screenshot(&self) -> Reply {
+    let cpu = self.cpu.as_ref().ok_or(NO_ROM)?;
+    let rgba = self.palette.frame_rgba(&cpu.bus.ppu.frame);
+    let png = video_dump::encode_png(Frame::WIDTH as u32, Frame::HEIGHT as u32, &rgba);
+    Ok(vec![("png", base64_encode(&png).into())])
+  }
+}
+
+// `key` as a whole number, `default` when it's left out
+fn field_u32(request: &Json, key: &str, default: u32) -> Result<u32, String> {
+  match request.get(key) {
+    None => Ok(default),
+    Some(value) => value
+      .as_u32()
+      .ok_or_else(|| format!("{} isn't a whole number", key)),
+  }
+}
+
+fn reply(id: Json, result: Reply) -> String {
+  let mut fields = vec![("id".to_string(), id)];
+  match result {
+    Ok(values) => {
+      fields.push(("ok".to_string(), true.into()));
+      fields.extend(values.into_iter().map(|(k, v)| (k.to_string(), v)));
+    }
+    Err(error) => {
+      fields.push(("ok".to_string(), false.into()));
+      fields.push(("error".to_string(), error.into()));
+    }
+  }
+  Json::Object(fields).to_string()
+}
+
+/// Talk to one client until it closes.
+pub fn serve_client(mut stream: TcpStream, session: &mut Session) -> io::Result<()> {
+  websocket::accept(&mut stream)?;
+  while let Some(request) = websocket::read_message(&mut stream)? {
+    websocket::write_message(&mut stream, &session.handle(&request))?;
+  }
+  Ok(())
+}
+
+/// Serve clients from `listener` one after another, for good. A client
+/// breaking the protocol is dropped, not the server.
+pub fn serve(listener: TcpListener, session: &mut Session) -> io::Result<()> {
+  for stream in listener.incoming() {
+    let stream = stream?;
+    let peer = stream.peer_addr()?;
+    if let Err(e) = serve_client(stream, session) {
+      log::warn!("{}: {}", peer, e);
+    }
+  }
+  Ok(())
+}
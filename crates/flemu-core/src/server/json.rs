@@ -0,0 +1,315 @@
+use std::fmt;
+
+/*
+  Just enough JSON for the control protocol: parsing requests, writing
+  replies. Objects keep their keys in order, numbers are f64 like
+  JavaScript's, and `\u` escapes outside the BMP come in as surrogate
+  pairs.
+*/
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+  Null,
+  Bool(bool),
+  Number(f64),
+  String(String),
+  Array(Vec<Json>),
+  Object(Vec<(String, Json)>),
+}
+
+/// Not JSON from byte `offset` on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JsonError {
+  pub offset: usize,
+}
+
+impl fmt::Display for JsonError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "bad JSON at byte {}", self.offset)
+  }
+}
+
+impl std::error::Error for JsonError {}
+
+impl Json {
+  pub fn parse(text: &str) -> Result<Json, JsonError> {
+    let mut parser = Parser {
+      text: text.as_bytes(),
+      at: 0,
+    };
+    let value = parser.value()?;
+    parser.skip_space();
+    if parser.at != parser.text.len() {
+      return Err(parser.error());
+    }
+    Ok(value)
+  }
+
+  /// The value of `key` in an object, None for other values.
+  pub fn get(&self, key: &str) -> Option<&Json> {
+    match self {
+      Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+      _ => None,
+    }
+  }
+
+  pub fn as_f64(&self) -> Option<f64> {
+    match self {
+      Json::Number(n) => Some(*n),
+      _ => None,
+    }
+  }
+
+  /// A whole number from 0 to `u32::MAX`.
+  pub fn as_u32(&self) -> Option<u32> {
+    self
+      .as_f64()
+      .filter(|n| n.fract() == 0.0 && *n >= 0.0 && *n <= u32::MAX as f64)
+      .map(|n| n as u32)
+  }
+
+  pub fn as_str(&self) -> Option<&str> {
+    match self {
+      Json::String(s) => Some(s),
+      _ => None,
+    }
+  }
+
+  pub fn as_array(&self) -> Option<&[Json]> {
+    match self {
+      Json::Array(items) => Some(items),
+      _ => None,
+    }
+  }
+}
+
+impl From<bool> for Json {
+  fn from(b: bool) -> Json {
+    Json::Bool(b)
+  }
+}
+
+impl From<u32> for Json {
+  fn from(n: u32) -> Json {
+    Json::Number(n as f64)
+  }
+}
+
+impl From<&str> for Json {
+  fn from(s: &str) -> Json {
+    Json::String(s.to_string())
+  }
+}
+
+impl From<String> for Json {
+  fn from(s: String) -> Json {
+    Json::String(s)
+  }
+}
+
+// compact, no spaces
+impl fmt::Display for Json {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Json::Null => write!(f, "null"),
+      Json::Bool(b) => write!(f, "{}", b),
+      Json::Number(n) if n.is_finite() => write!(f, "{}", n),
+      Json::Number(_) => write!(f, "null"),
+      Json::String(s) => write_string(f, s),
+      Json::Array(items) => {
+        write!(f, "[")?;
+        for (i, item) in items.iter().enumerate() {
+          if i > 0 {
+            write!(f, ",")?;
+          }
+          write!(f, "{}", item)?;
+        }
+        write!(f, "]")
+      }
+      Json::Object(fields) => {
+        write!(f, "{{")?;
+        for (i, (key, value)) in fields.iter().enumerate() {
+          if i > 0 {
+            write!(f, ",")?;
+          }
+          write_string(f, key)?;
+          write!(f, ":{}", value)?;
+        }
+        write!(f, "}}")
+      }
+    }
+  }
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+  write!(f, "\"")?;
+  for c in s.chars() {
+    match c {
+      '"' => write!(f, "\\\"")?,
+      '\\' => write!(f, "\\\\")?,
+      '\n' => write!(f, "\\n")?,
+      '\r' => write!(f, "\\r")?,
+      '\t' => write!(f, "\\t")?,
+      c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+      c => write!(f, "{}", c)?,
+    }
+  }
+  write!(f, "\"")
+}
+
+struct Parser<'a> {
+  text: &'a [u8],
+  at: usize,
+}
+
+impl<'a> Parser<'a> {
+  fn error(&self) -> JsonError {
+    JsonError { offset: self.at }
+  }
+
+  fn skip_space(&mut self) {
+    while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.text.get(self.at) {
+      self.at += 1;
+    }
+  }
+
+  fn eat(&mut self, byte: u8) -> Result<(), JsonError> {
+    if self.text.get(self.at) != Some(&byte) {
+      return Err(self.error());
+    }
+    self.at += 1;
+    Ok(())
+  }
+
+  fn literal(&mut self, word: &str, value: Json) -> Result<Json, JsonError> {
+    if !self.text[self.at..].starts_with(word.as_bytes()) {
+      return Err(self.error());
+    }
+    self.at += word.len();
+    Ok(value)
+  }
+
+  fn value(&mut self) -> Result<Json, JsonError> {
+    self.skip_space();
+    match self.text.get(self.at) {
+      Some(b'n') => self.literal("null", Json::Null),
+      Some(b't') => self.literal("true", Json::Bool(true)),
+      Some(b'f') => self.literal("false", Json::Bool(false)),
+      Some(b'"') => self.string().map(Json::String),
+      Some(b'[') => self.array(),
+      Some(b'{') => self.object(),
+      Some(b'-' | b'0'..=b'9') => self.number(),
+      _ => Err(self.error()),
+    }
+  }
+
+  fn array(&mut self) -> Result<Json, JsonError> {
+    self.eat(b'[')?;
+    let mut items = Vec::new();
+    self.skip_space();
+    if self.eat(b']').is_ok() {
+      return Ok(Json::Array(items));
+    }
+    loop {
+      items.push(self.value()?);
+      self.skip_space();
+      if self.eat(b']').is_ok() {
+        return Ok(Json::Array(items));
+      }
+      self.eat(b',')?;
+    }
+  }
+
+  fn object(&mut self) -> Result<Json, JsonError> {
+    self.eat(b'{')?;
+    let mut fields = Vec::new();
+    self.skip_space();
+    if self.eat(b'}').is_ok() {
+      return Ok(Json::Object(fields));
+    }
+    loop {
+      self.skip_space();
+      let key = self.string()?;
+      self.skip_space();
+      self.eat(b':')?;
+      fields.push((key, self.value()?));
+      self.skip_space();
+      if self.eat(b'}').is_ok() {
+        return Ok(Json::Object(fields));
+      }
+      self.eat(b',')?;
+    }
+  }
+
+  fn number(&mut self) -> Result<Json, JsonError> {
+    let start = self.at;
+    while let Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') = self.text.get(self.at) {
+      self.at += 1;
+    }
+    // the slice is ASCII; parse rejects what JSON would, and a bit more
+    let text = std::str::from_utf8(&self.text[start..self.at]).unwrap();
+    text
+      .parse()
+      .map(Json::Number)
+      .map_err(|_| JsonError { offset: start })
+  }
+
+  fn string(&mut self) -> Result<String, JsonError> {
+    self.eat(b'"')?;
+    let mut out = Vec::new();
+    loop {
+      let byte = *self.text.get(self.at).ok_or_else(|| self.error())?;
+      self.at += 1;
+      match byte {
+        b'"' => break,
+        b'\\' => {
+          let escape = *self.text.get(self.at).ok_or_else(|| self.error())?;
+          self.at += 1;
+          let c = match escape {
+            b'"' => '"',
+            b'\\' => '\\',
+            b'/' => '/',
+            b'b' => '\u{8}',
+            b'f' => '\u{c}',
+            b'n' => '\n',
+            b'r' => '\r',
+            b't' => '\t',
+            b'u' => self.unicode_escape()?,
+            _ => return Err(self.error()),
+          };
+          out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+        }
+        byte if byte < 0x20 => return Err(self.error()),
+        byte => out.push(byte),
+      }
+    }
+    // the input was a &str, and escapes only add whole characters
+    Ok(String::from_utf8(out).unwrap())
+  }
+
+  // after `\u`: four hex digits, and a low surrogate's after a high one
+  fn unicode_escape(&mut self) -> Result<char, JsonError> {
+    let high = self.hex4()?;
+    if !(0xD800..0xDC00).contains(&high) {
+      return char::from_u32(high).ok_or_else(|| self.error());
+    }
+    self.eat(b'\\')?;
+    self.eat(b'u')?;
+    let low = self.hex4()?;
+    if !(0xDC00..0xE000).contains(&low) {
+      return Err(self.error());
+    }
+    char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)).ok_or_else(|| self.error())
+  }
+
+  fn hex4(&mut self) -> Result<u32, JsonError> {
+    let digits = self
+      .text
+      .get(self.at..self.at + 4)
+      .and_then(|digits| std::str::from_utf8(digits).ok())
+      .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+      .ok_or_else(|| self.error())?;
+    self.at += 4;
+    Ok(digits)
+  }
+}
@@ -0,0 +1,211 @@
+use crate::nes::movie::base64_encode;
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+/*
+  The server side of RFC 6455 WebSockets, as much as the control
+  protocol needs: the HTTP upgrade, then text messages either way, pings
+  answered and a close ending it. Client frames come masked and may be
+  fragmented; ours go out whole and unmasked. Extensions and
+  subprotocols aren't offered, so a browser's `new WebSocket(url)`
+  connects as is.
+*/
+
+// appended to the client's key for the accept hash
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// Biggest message taken, well over a base64 ROM.
+pub const MAX_MESSAGE: usize = 16 << 20;
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xA;
+
+fn bad(message: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Read the client's upgrade request off `stream` and agree to it.
+pub fn accept<S: Read + Write>(stream: &mut S) -> io::Result<()> {
+  let mut key = None;
+  let mut reader = BufReader::new(&mut *stream);
+  loop {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+      return Err(bad("connection closed during the handshake"));
+    }
+    let line = line.trim_end();
+    if line.is_empty() {
+      break;
+    }
+    if let Some((name, value)) = line.split_once(':') {
+      if name.trim().eq_ignore_ascii_case("sec-websocket-key") {
+        key = Some(value.trim().to_string());
+      }
+    }
+  }
+  // a client only sends once it's heard back, so nothing's left buffered
+  drop(reader);
+  let key = match key {
+    Some(key) => key,
+    None => {
+      stream.write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n")?;
+      return Err(bad("not a WebSocket upgrade"));
+    }
+  };
+  let accept = base64_encode(&sha1(format!("{}{}", key, GUID).as_bytes()));
+  write!(
+    stream,
+    "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+     Sec-WebSocket-Accept: {}\r\n\r\n",
+    accept
+  )?;
+  stream.flush()
+}
+
+/// The next text message, None once the client closes. Pings are
+/// answered on the way.
+pub fn read_message<S: Read + Write>(stream: &mut S) -> io::Result<Option<String>> {
+  let mut message = Vec::new();
+  let mut started = false;
+  loop {
+    let (fin, opcode, payload) = match read_frame(stream)? {
+      Some(frame) => frame,
+      None => return Ok(None),
+    };
+    match opcode {
+      PING => write_frame(stream, PONG, &payload)?,
+      PONG => {}
+      CLOSE => {
+        // the client waits for a close back, with its status code
+        write_frame(stream, CLOSE, &payload[..payload.len().min(2)])?;
+        return Ok(None);
+      }
+      TEXT | CONTINUATION => {
+        if (opcode == TEXT) == started {
+          return Err(bad("message fragments out of order"));
+        }
+        started = true;
+        if message.len() + payload.len() > MAX_MESSAGE {
+          return Err(bad("message too long"));
+        }
+        message.extend_from_slice(&payload);
+        if fin {
+          return String::from_utf8(message)
+            .map(Some)
+            .map_err(|_| bad("text message isn't UTF-8"));
+        }
+      }
+      BINARY => return Err(bad("binary messages aren't part of the protocol")),
+      _ => return Err(bad("unknown opcode")),
+    }
+  }
+}
+
+/// Send `text` as one text message.
+pub fn write_message<S: Write>(stream: &mut S, text: &str) -> io::Result<()> {
+  write_frame(stream, TEXT, text.as_bytes())
+}
+
+// (fin, opcode, unmasked payload), None on a clean end of stream
+fn read_frame<S: Read>(stream: &mut S) -> io::Result<Option<(bool, u8, Vec<u8>)>> {
+  let mut head = [0; 2];
+  match stream.read_exact(&mut head) {
+    Ok(()) => {}
+    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+    Err(e) => return Err(e),
+  }
+  let fin = head[0] & 0x80 != 0;
+  let opcode = head[0] & 0x0F;
+  if head[1] & 0x80 == 0 {
+    return Err(bad("client frames must be masked"));
+  }
+  let len = match head[1] & 0x7F {
+    126 => {
+      let mut len = [0; 2];
+      stream.read_exact(&mut len)?;
+      u16::from_be_bytes(len) as u64
+    }
+    127 => {
+      let mut len = [0; 8];
+      stream.read_exact(&mut len)?;
+      u64::from_be_bytes(len)
+    }
+    len => len as u64,
+  };
+  if len > MAX_MESSAGE as u64 {
+    return Err(bad("message too long"));
+  }
+  let mut mask = [0; 4];
+  stream.read_exact(&mut mask)?;
+  let mut payload = vec![0; len as usize];
+  stream.read_exact(&mut payload)?;
+  for (i, byte) in payload.iter_mut().enumerate() {
+    *byte ^= mask[i % 4];
+  }
+  Ok(Some((fin, opcode, payload)))
+}
+
+fn write_frame<S: Write>(stream: &mut S, opcode: u8, payload: &[u8]) -> io::Result<()> {
+  let mut frame = vec![0x80 | opcode];
+  match payload.len() {
+    len if len < 126 => frame.push(len as u8),
+    len if len <= 0xFFFF => {
+      frame.push(126);
+      frame.extend_from_slice(&(len as u16).to_be_bytes());
+    }
+    len => {
+      frame.push(127);
+      frame.extend_from_slice(&(len as u64).to_be_bytes());
+    }
+  }
+  frame.extend_from_slice(payload);
+  stream.write_all(&frame)?;
+  stream.flush()
+}
+
+// only for the handshake
+fn sha1(data: &[u8]) -> [u8; 20] {
+  let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+  let mut message = data.to_vec();
+  message.push(0x80);
+  while message.len() % 64 != 56 {
+    message.push(0);
+  }
+  message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+  for block in message.chunks(64) {
+    let mut words = [0u32; 80];
+    for (i, word) in block.chunks(4).enumerate() {
+      words[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+    }
+    for i in 16..80 {
+      words[i] = (words[i - 3] ^ words[i - 8] ^ words[i - 14] ^ words[i - 16]).rotate_left(1);
+    }
+    // a to e
+    let mut v = state;
+    for (i, &word) in words.iter().enumerate() {
+      let (mix, constant) = match i {
+        0..=19 => ((v[1] & v[2]) | (!v[1] & v[3]), 0x5A827999),
+        20..=39 => (v[1] ^ v[2] ^ v[3], 0x6ED9EBA1),
+        40..=59 => ((v[1] & v[2]) | (v[1] & v[3]) | (v[2] & v[3]), 0x8F1BBCDC),
+        _ => (v[1] ^ v[2] ^ v[3], 0xCA62C1D6),
+      };
+      let temp = v[0]
+        .rotate_left(5)
+        .wrapping_add(mix)
+        .wrapping_add(v[4])
+        .wrapping_add(constant)
+        .wrapping_add(word);
+      v = [temp, v[0], v[1].rotate_left(30), v[2], v[3]];
+    }
+    for (word, value) in state.iter_mut().zip(&v) {
+      *word = word.wrapping_add(*value);
+    }
+  }
+  let mut out = [0; 20];
+  for (i, word) in state.iter().enumerate() {
+    out[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+  }
+  out
+}
@@ -0,0 +1,108 @@
+/*
+  Frame dumps for looking at rendering frame by frame outside the
+  emulator: every Nth frame as a numbered PNG (the video_dump binary) or
+  handed to a JS callback (Emulator::start_video_dump).
+
+  PNGs go through the png crate both ways: `encode_png` writes 8-bit RGBA,
+  `decode_png` reads it back for the golden frames tests compare against,
+  along with whatever else an image editor may have saved a reference as.
+*/
+
+use png::{BitDepth, ColorType, Decoder, Encoder};
+
+/// Encode `rgba` (`width * height * 4` bytes, row by row) as a PNG.
+pub fn encode_png(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+  assert_eq!(
+    rgba.len(),
+    width as usize * height as usize * 4,
+    "RGBA data doesn't match the picture size"
+  );
+  let mut png = Vec::new();
+  let mut encoder = Encoder::new(&mut png, width, height);
+  encoder.set_color(ColorType::RGBA);
+  encoder.set_depth(BitDepth::Eight);
+  // into memory, with the size checked, nothing can fail
+  let mut writer = encoder.write_header().unwrap();
+  writer.write_image_data(rgba).unwrap();
+  // IEND goes out when the writer does
+  drop(writer);
+  png
+}
+
+/// Read back a PNG: width, height and 8-bit RGBA, whatever the file's
+/// color type and depth. None for anything that isn't a PNG.
+pub fn decode_png(png: &[u8]) -> Option<(u32, u32, Vec<u8>)> {
+  let (info, mut reader) = Decoder::new(png).read_info().ok()?;
+  // palettes and depths other than 8 come out as 8-bit RGB(A) or gray
+  let mut pixels = vec![0; reader.output_buffer_size()];
+  reader.next_frame(&mut pixels).ok()?;
+  let rgba = match reader.output_color_type() {
+    (ColorType::RGBA, BitDepth::Eight) => pixels,
+    (ColorType::RGB, BitDepth::Eight) => pixels
+      .chunks(3)
+      .flat_map(|rgb| vec![rgb[0], rgb[1], rgb[2], 0xFF])
+      .collect(),
+    (ColorType::Grayscale, BitDepth::Eight) => {
+      pixels.iter().flat_map(|&y| vec![y, y, y, 0xFF]).collect()
+    }
+    (ColorType::GrayscaleAlpha, BitDepth::Eight) => pixels
+      .chunks(2)
+      .flat_map(|ya| vec![ya[0], ya[0], ya[0], ya[1]])
+      .collect(),
+    _ => return None,
+  };
+  Some((info.width, info.height, rgba))
+}
+
+/// Lines at the top and at the bottom of the picture that most TVs never
+/// showed, and games often leave messy.
+pub const OVERSCAN_LINES: usize = 8;
+
+/// The `width`x`height` rectangle at `x`, `y` of an RGBA picture `stride`
+/// pixels wide.
+pub fn crop_rgba(
+  rgba: &[u8],
+  stride: usize,
+  (x, y, width, height): (usize, usize, usize, usize),
+) -> Vec<u8> {
+  let mut out = Vec::with_capacity(width * height * 4);
+  for row in rgba.chunks(stride * 4).skip(y).take(height) {
+    out.extend_from_slice(&row[x * 4..(x + width) * 4]);
+  }
+  out
+}
+
+/// File name of a dumped frame, sorting in frame order.
+pub fn frame_file_name(frame: u64) -> String {
+  format!("frame-{:06}.png", frame)
+}
+
+/// Picks which frames to dump: every `every`th by PPU frame count, each
+/// once however often it's asked about.
+#[derive(Debug, Clone)]
+pub struct VideoDump {
+  every: u64,
+  last_frame: Option<u64>,
+}
+
+impl VideoDump {
+  pub fn new(every: u64) -> Self {
+    VideoDump {
+      every: every.max(1),
+      last_frame: None,
+    }
+  }
+
+  pub fn every(&self) -> u64 {
+    self.every
+  }
+
+  /// Whether `frame`, the PPU's current frame count, should be dumped now.
+  pub fn should_dump(&mut self, frame: u64) -> bool {
+    if self.last_frame == Some(frame) || frame % self.every != 0 {
+      return false;
+    }
+    self.last_frame = Some(frame);
+    true
+  }
+}
@@ -0,0 +1,80 @@
+/*
+  Audio captures as WAV files: mono 16-bit PCM, the simplest thing every
+  player and diff tool reads.
+
+  What gets recorded is the APU's capture stream (`Apu::set_capture_rate`),
+  not the samples played: those go through a resampler that's sped up and
+  slowed down to keep up with the sound card, and are turned down while
+  fast-forwarding. The capture's rate never moves, so the same run of the
+  same ROM records the same bytes, and two builds can be compared sample
+  by sample.
+*/
+
+/// Encode mono 16-bit samples at `rate` Hz as a WAV file.
+pub fn encode_wav(rate: u32, samples: &[i16]) -> Vec<u8> {
+  let data_len = samples.len() as u32 * 2;
+  let mut wav = Vec::with_capacity(44 + data_len as usize);
+  wav.extend_from_slice(b"RIFF");
+  wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+  wav.extend_from_slice(b"WAVE");
+
+  wav.extend_from_slice(b"fmt ");
+  wav.extend_from_slice(&16u32.to_le_bytes());
+  // PCM, one channel
+  wav.extend_from_slice(&1u16.to_le_bytes());
+  wav.extend_from_slice(&1u16.to_le_bytes());
+  wav.extend_from_slice(&rate.to_le_bytes());
+  // bytes per second, bytes per sample frame, bits per sample
+  wav.extend_from_slice(&(rate * 2).to_le_bytes());
+  wav.extend_from_slice(&2u16.to_le_bytes());
+  wav.extend_from_slice(&16u16.to_le_bytes());
+
+  wav.extend_from_slice(b"data");
+  wav.extend_from_slice(&data_len.to_le_bytes());
+  for sample in samples {
+    wav.extend_from_slice(&sample.to_le_bytes());
+  }
+  wav
+}
+
+/// Collects captured samples until they're wanted as a WAV file.
+#[derive(Debug, Clone)]
+pub struct WavRecorder {
+  rate: u32,
+  samples: Vec<i16>,
+}
+
+impl WavRecorder {
+  pub fn new(rate: u32) -> Self {
+    WavRecorder {
+      rate,
+      samples: Vec::new(),
+    }
+  }
+
+  pub fn rate(&self) -> u32 {
+    self.rate
+  }
+
+  /// Add mixer output in [0, 1], stretched over the whole 16-bit range.
+  pub fn push(&mut self, samples: &[f32]) {
+    self.samples.extend(
+      samples
+        .iter()
+        .map(|&sample| ((sample.clamp(0.0, 1.0) * 65535.0).round() as i32 - 32768) as i16),
+    );
+  }
+
+  pub fn samples(&self) -> &[i16] {
+    &self.samples
+  }
+
+  /// Seconds recorded so far.
+  pub fn duration(&self) -> f64 {
+    self.samples.len() as f64 / self.rate as f64
+  }
+
+  pub fn to_wav(&self) -> Vec<u8> {
+    encode_wav(self.rate, &self.samples)
+  }
+}
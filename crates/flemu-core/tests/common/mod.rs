@@ -0,0 +1,35 @@
+// shared by the integration tests, each of which uses only some of it
+#![allow(dead_code)]
+
+use flemu_core::nes::asm;
+use flemu_core::nes::cartridge::Rom;
+use flemu_core::nes::cpu::CPU;
+
+/// A console running `source`, assembled from $8000 where the reset
+/// vector points.
+pub fn console(source: &str) -> CPU {
+  let program = asm::assemble(source).unwrap();
+  let mut cpu = CPU::new();
+  cpu.load_rom(Rom::from_program(&program)).unwrap();
+  cpu
+}
+
+/// A counter in X going up by 2 every loop while A is held on
+/// controller 1, 1 otherwise.
+pub fn counter() -> CPU {
+  console(
+    "
+    loop:
+      LDA #$01
+      STA $4016
+      LDA #$00
+      STA $4016
+      LDA $4016
+      AND #$01
+      BEQ up
+      INX
+    up: INX
+      JMP loop
+    ",
+  )
+}
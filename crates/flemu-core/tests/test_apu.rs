@@ -0,0 +1,653 @@
+use flemu_core::nes::apu::*;
+use flemu_core::nes::bus::{Bus, Mem};
+use flemu_core::nes::cartridge::Rom;
+use flemu_core::nes::cpu::CPU;
+use flemu_core::nes::mapper::{Fme7, Mapper, NoCartridge, Nrom};
+
+// CPU cycles of one 4-step frame counter sequence, and up to its first
+// quarter and half frames
+const SEQUENCE: u32 = 29830;
+const FIRST_QUARTER_FRAME: u32 = 7457;
+const FIRST_HALF_FRAME: u32 = 14913;
+
+fn square(apu: &mut Apu, control: u8, period: u16, length_index: u8) {
+  apu.write_register(0x4015, 0x01);
+  apu.write_register(0x4000, control);
+  apu.write_register(0x4002, period as u8);
+  apu.write_register(0x4003, (length_index << 3) | (period >> 8) as u8);
+}
+
+#[test]
+fn test_length_counter_runs_out() {
+  let mut apu = Apu::new();
+  // 50% duty, constant volume 15, length index 3 loads 2
+  square(&mut apu, 0b1001_1111, 0x100, 3);
+  assert_eq!(apu.pulse1.length_counter(), 2);
+  assert_eq!(apu.read_status(), 0x01);
+
+  // two half frames per sequence
+  apu.tick(&NoCartridge, SEQUENCE);
+  assert_eq!(apu.pulse1.length_counter(), 0);
+  assert_eq!(apu.read_status() & 0x1f, 0x00);
+}
+
+#[test]
+fn test_length_halt_and_disable() {
+  let mut apu = Apu::new();
+  square(&mut apu, 0b1001_1111 | 0x20, 0x100, 3);
+  apu.tick(&NoCartridge, SEQUENCE * 2);
+  assert_eq!(apu.pulse1.length_counter(), 2);
+
+  apu.write_register(0x4015, 0x00);
+  assert_eq!(apu.pulse1.length_counter(), 0);
+  // loading the length of a disabled channel does nothing
+  apu.write_register(0x4003, 1 << 3);
+  assert_eq!(apu.read_status() & 0x1f, 0x00);
+}
+
+#[test]
+fn test_duty_cycle_output() {
+  let mut apu = Apu::new();
+  // 25% duty, constant volume 9
+  square(&mut apu, 0b0101_1001, 8, 1);
+  // the sequencer steps every timer period + 1 APU cycles
+  let step = 2 * (8 + 1);
+  let levels: Vec<u8> = (0..8)
+    .map(|_| {
+      apu.tick(&NoCartridge, step);
+      apu.pulse1.output()
+    })
+    .collect();
+  assert_eq!(levels.iter().filter(|&&level| level == 9).count(), 2);
+  assert_eq!(levels.iter().filter(|&&level| level == 0).count(), 6);
+}
+
+#[test]
+fn test_sweep_mutes_out_of_range_periods() {
+  let mut apu = Apu::new();
+  square(&mut apu, 0b1001_1111, 7, 1);
+  assert_eq!(apu.pulse1.output(), 0);
+
+  // a target past $7FF mutes even with the sweep disabled
+  square(&mut apu, 0b1001_1111, 0x700, 1);
+  apu.write_register(0x4001, 0x01);
+  apu.tick(&NoCartridge, 4);
+  assert_eq!(apu.pulse1.output(), 0);
+}
+
+#[test]
+fn test_sweep_adjusts_period() {
+  let mut apu = Apu::new();
+  // enabled, period 0, negate, shift 1
+  square(&mut apu, 0b1001_1111, 0x100, 1);
+  apu.write_register(0x4001, 0b1000_1001);
+  apu.tick(&NoCartridge, FIRST_HALF_FRAME);
+  // pulse 1 subtracts one more than pulse 2 would
+  assert_eq!(apu.pulse1.timer_period(), 0x100 - 0x80 - 1);
+
+  let mut apu = Apu::new();
+  apu.write_register(0x4015, 0x02);
+  apu.write_register(0x4004, 0b1001_1111);
+  apu.write_register(0x4006, 0x00);
+  apu.write_register(0x4007, 0x09);
+  apu.write_register(0x4005, 0b1000_1001);
+  apu.tick(&NoCartridge, FIRST_HALF_FRAME);
+  assert_eq!(apu.pulse2.timer_period(), 0x100 - 0x80);
+}
+
+#[test]
+fn test_envelope_decays() {
+  let mut apu = Apu::new();
+  // envelope with period 0: one level per quarter frame
+  square(&mut apu, 0b1000_0000, 0x100, 1);
+  assert_eq!(apu.pulse1.volume(), 0);
+  apu.tick(&NoCartridge, FIRST_QUARTER_FRAME);
+  assert_eq!(apu.pulse1.volume(), 15);
+  apu.tick(&NoCartridge, FIRST_HALF_FRAME - FIRST_QUARTER_FRAME);
+  assert_eq!(apu.pulse1.volume(), 14);
+
+  // without the loop flag it stays silent at the end
+  apu.tick(&NoCartridge, SEQUENCE * 4);
+  assert_eq!(apu.pulse1.volume(), 0);
+
+  // with it, 15 follows 0: 20 quarter frames in it's back at 12
+  square(&mut apu, 0b1010_0000, 0x100, 1);
+  apu.tick(&NoCartridge, SEQUENCE * 5);
+  assert_eq!(apu.pulse1.volume(), 12);
+}
+
+#[test]
+fn test_samples_at_requested_rate() {
+  let mut apu = Apu::new();
+  apu.set_sample_rate(48_000);
+  apu.tick(&NoCartridge, CPU_CLOCK as u32 / 10);
+  let samples = apu.take_samples();
+  assert!((samples.len() as i32 - 4800).abs() <= 1);
+  // silence is a flat line, the triangle holding its level
+  let silence = samples[0];
+  assert!(samples.iter().all(|&s| (s - silence).abs() < f32::EPSILON));
+  assert_eq!(apu.buffered_samples(), 0);
+
+  square(&mut apu, 0b1001_1111, 0x100, 1);
+  apu.tick(&NoCartridge, CPU_CLOCK as u32 / 10);
+  let samples = apu.take_samples();
+  let loudest = samples.iter().cloned().fold(0.0, f32::max);
+  // one pulse at full volume through the mixer
+  assert!((loudest - silence - 95.88 / (8128.0 / 15.0 + 100.0)).abs() < 1e-4);
+}
+
+#[test]
+fn test_mixer_is_fixed_point() {
+  let mut apu = Apu::new();
+  square(&mut apu, 0b1001_1111, 0x100, 1);
+  apu.tick(&NoCartridge, 100);
+  let level = apu.level();
+  assert!((apu.output() - level as f32 / 65536.0).abs() < f32::EPSILON);
+}
+
+// both pulse channels loud, caught while both are high
+fn two_squares() -> Apu {
+  let mut apu = Apu::new();
+  apu.write_register(0x4015, 0x03);
+  for &base in &[0x4000, 0x4004] {
+    apu.write_register(base, 0b1101_1111);
+    apu.write_register(base + 2, 0x80);
+    apu.write_register(base + 3, 0x08);
+  }
+  while apu.pulse1.output() == 0 || apu.pulse2.output() == 0 {
+    apu.tick(&NoCartridge, 1);
+  }
+  apu
+}
+
+// `apu` with only the channels in `mask` ($4015's bits) still on
+fn only(apu: &Apu, mask: u8) -> Apu {
+  let mut apu = apu.clone();
+  apu.write_register(0x4015, mask);
+  apu
+}
+
+#[test]
+fn test_mixer_mutes_and_solos_channels() {
+  let pulse1 = MixerInput::Channel(Channel::Pulse1);
+  let pulse2 = MixerInput::Channel(Channel::Pulse2);
+  let mut apu = two_squares();
+  let both = apu.level();
+
+  apu.mixer.set_muted(pulse2, true);
+  assert_eq!(apu.level(), only(&apu, 0x01).level());
+  // soloing wins over muting
+  apu.mixer.set_solo(pulse2, true);
+  assert_eq!(apu.level(), only(&apu, 0x02).level());
+  assert!(apu.mixer.audible(pulse1) <= 0.0);
+
+  apu.mixer = Mixer::default();
+  assert_eq!(apu.level(), both);
+  apu.mixer.set_gain(pulse1, 0.5);
+  assert!(apu.level() < both && apu.level() > only(&apu, 0x02).level());
+  apu.mixer.set_gain(pulse1, 7.0);
+  assert!((apu.mixer.gain(pulse1) - 1.0).abs() < f32::EPSILON);
+  assert_eq!(apu.level(), both);
+}
+
+#[test]
+fn test_master_volume_is_for_playback_only() {
+  let mut apu = two_squares();
+  apu.set_capture_rate(Some(48_000));
+  apu.mixer.volume = 0.25;
+  apu.tick(&NoCartridge, CPU_CLOCK as u32 / 100);
+  let loudest = |samples: &[f32]| samples.iter().cloned().fold(0.0, f32::max);
+  let played = loudest(&apu.take_samples());
+  let captured = loudest(&apu.take_captured());
+  assert!(played > 0.0);
+  assert!((played - captured / 4.0).abs() < 1e-4);
+}
+
+#[test]
+fn test_cartridge_audio_goes_through_the_expansion_input() {
+  // a 5B channel held high at full volume, the APU silent
+  let mut fme7 = Fme7::new(Rom::from_program(&[]));
+  for &(register, value) in &[(0x07, 0x3f), (0x08, 0x0f)] {
+    fme7.prg_write(0xc000, register);
+    fme7.prg_write(0xe000, value);
+  }
+  // what the cartridge adds to the captured output over the APU's own
+  let added = |apu: &Apu| {
+    let last = |mut apu: Apu, cart: &dyn Mapper| {
+      apu.tick(cart, CPU_CLOCK as u32 / 100);
+      *apu.take_captured().last().unwrap()
+    };
+    last(apu.clone(), &fme7) - last(apu.clone(), &NoCartridge)
+  };
+  let mut apu = Apu::new();
+  apu.set_capture_rate(Some(48_000));
+  let full = added(&apu);
+  assert!((full - fme7.audio_level() as f32 / 65536.0).abs() < 1e-4);
+
+  apu.mixer.set_gain(MixerInput::Expansion, 0.5);
+  assert!((added(&apu) - full / 2.0).abs() < 1e-4);
+  apu.mixer.set_muted(MixerInput::Expansion, true);
+  assert!(added(&apu).abs() < f32::EPSILON);
+}
+
+#[test]
+fn test_mixer_input_ids() {
+  for input in &MixerInput::ALL {
+    assert_eq!(MixerInput::from_id(input.id()), Some(*input));
+  }
+  assert_eq!(
+    MixerInput::from_id("expansion"),
+    Some(MixerInput::Expansion)
+  );
+  assert_eq!(MixerInput::from_id("pulse3"), None);
+}
+
+#[test]
+fn test_fast_forward_squeezes_and_quiets_the_audio() {
+  let mut apu = Apu::new();
+  apu.set_sample_rate(48_000);
+  square(&mut apu, 0b1001_1111, 0x100, 1);
+  apu.tick(&NoCartridge, CPU_CLOCK as u32 / 10);
+  let normal = apu.take_samples();
+
+  apu.set_speed(2.0);
+  square(&mut apu, 0b1001_1111, 0x100, 1);
+  apu.tick(&NoCartridge, CPU_CLOCK as u32 / 10);
+  let fast = apu.take_samples();
+  assert!((fast.len() as i32 - 2400).abs() <= 1);
+  let loudest = |samples: &[f32]| samples.iter().cloned().fold(0.0, f32::max);
+  assert!((loudest(&fast) - loudest(&normal) / 2.0).abs() < 1e-4);
+
+  apu.set_speed(1.0);
+  apu.tick(&NoCartridge, CPU_CLOCK as u32 / 10);
+  assert!((apu.take_samples().len() as i32 - 4800).abs() <= 1);
+}
+
+#[test]
+fn test_capture_keeps_its_rate_and_volume() {
+  let mut apu = Apu::new();
+  apu.set_capture_rate(Some(48_000));
+  assert_eq!(apu.capture_rate(), Some(48_000));
+  square(&mut apu, 0b1001_1111, 0x100, 1);
+  apu.tick(&NoCartridge, CPU_CLOCK as u32 / 10);
+  let normal = apu.take_captured();
+  assert!((normal.len() as i32 - 4800).abs() <= 1);
+
+  apu.set_speed(2.0);
+  square(&mut apu, 0b1001_1111, 0x100, 1);
+  apu.tick(&NoCartridge, CPU_CLOCK as u32 / 10);
+  let fast = apu.take_captured();
+  assert!((fast.len() as i32 - 4800).abs() <= 1);
+  let loudest = |samples: &[f32]| samples.iter().cloned().fold(0.0, f32::max);
+  assert!((loudest(&fast) - loudest(&normal)).abs() < 1e-4);
+
+  apu.set_capture_rate(None);
+  apu.tick(&NoCartridge, 1000);
+  assert!(apu.take_captured().is_empty());
+}
+
+#[test]
+fn test_bus_routes_apu_registers() {
+  let mut bus = Bus::new();
+  bus.mem_write(0x4015, 0x03);
+  bus.mem_write(0x4007, 0x08);
+  assert_eq!(bus.mem_read(0x4015), 0x02);
+  assert_eq!(bus.mem_peek(0x4015), 0x02);
+
+  bus.tick(100);
+  assert!(bus.apu.buffered_samples() > 0);
+}
+
+#[test]
+fn test_channel_states() {
+  let mut apu = Apu::new();
+  // A4: 1789773 / (16 * 254)
+  square(&mut apu, 0b1001_1010, 253, 3);
+  let states = apu.channel_states();
+  assert_eq!(states[0].channel, Channel::Pulse1);
+  assert_eq!(states[0].period, 253);
+  assert!((states[0].frequency - 440.4).abs() < 0.1);
+  assert!((states[0].volume - 10.0 / 15.0).abs() < 1e-6);
+  assert!(states[0].playing);
+  assert!(!states[1].playing);
+
+  // periods below 8 are muted by the sweep unit
+  square(&mut apu, 0b1001_1010, 7, 3);
+  assert!(!apu.channel_states()[0].playing);
+
+  // the triangle only plays once its linear counter is loaded
+  apu.write_register(0x4015, 0x04);
+  apu.write_register(0x4008, 0x7f);
+  apu.write_register(0x400A, 253);
+  apu.write_register(0x400B, 3 << 3);
+  assert!(!apu.channel_states()[2].playing);
+  apu.tick(&NoCartridge, FIRST_QUARTER_FRAME);
+  let triangle = apu.channel_states()[2];
+  assert!(triangle.playing);
+  assert!((triangle.frequency - 220.2).abs() < 0.1);
+}
+
+#[test]
+fn test_bus_records_channels_every_frame() {
+  let mut bus = Bus::new();
+  bus.mem_write(0x4015, 0x01);
+  bus.mem_write(0x4000, 0b1001_1111);
+  bus.mem_write(0x4002, 0xfd);
+  bus.mem_write(0x4003, 0x08);
+  // three and a bit frames of CPU cycles
+  for _ in 0..400 {
+    bus.tick(250);
+  }
+  let frames = bus.channels.take();
+  assert_eq!(frames.len(), 3);
+  assert!(frames.windows(2).all(|w| w[1].frame == w[0].frame + 1));
+  assert!(frames[0].channels[0].playing);
+  assert!(bus.channels.take().is_empty());
+
+  // a log nobody reads stays bounded
+  for _ in 0..4000 {
+    bus.tick(250);
+  }
+  assert_eq!(bus.channels.take().len(), 8);
+}
+
+#[test]
+fn test_triangle_linear_counter() {
+  let mut apu = Apu::new();
+  apu.write_register(0x4015, 0x04);
+  // control clear, linear counter reload 3
+  apu.write_register(0x4008, 0x03);
+  apu.write_register(0x400a, 0x40);
+  apu.write_register(0x400b, 0x08);
+  assert_eq!(apu.triangle.length_counter(), 254);
+
+  apu.tick(&NoCartridge, FIRST_QUARTER_FRAME);
+  assert_eq!(apu.triangle.linear_counter(), 3);
+  // reloaded once, then counts down a quarter frame at a time
+  apu.tick(&NoCartridge, SEQUENCE);
+  assert_eq!(apu.triangle.linear_counter(), 0);
+
+  // with the counter out the sequencer holds still
+  let level = apu.triangle.output();
+  for _ in 0..100 {
+    apu.tick(&NoCartridge, 7);
+    assert_eq!(apu.triangle.output(), level);
+  }
+}
+
+#[test]
+fn test_triangle_steps_through_its_sequence() {
+  let mut apu = Apu::new();
+  apu.write_register(0x4015, 0x04);
+  apu.write_register(0x4008, 0xff);
+  apu.write_register(0x400a, 0x10);
+  apu.write_register(0x400b, 0x08);
+  apu.tick(&NoCartridge, FIRST_QUARTER_FRAME);
+
+  let mut levels = Vec::new();
+  for _ in 0..32 {
+    levels.push(apu.triangle.output());
+    apu.tick(&NoCartridge, 0x10 + 1);
+  }
+  levels.sort_unstable();
+  levels.dedup();
+  assert_eq!(levels, (0..16).collect::<Vec<u8>>());
+}
+
+fn noise_period(short_mode: bool) -> u32 {
+  let mut apu = Apu::new();
+  apu.write_register(0x400e, if short_mode { 0x80 } else { 0x00 });
+  let start = apu.noise.shift_register();
+  let mut steps = 0;
+  loop {
+    apu.tick(&NoCartridge, 4);
+    steps += 1;
+    if apu.noise.shift_register() == start {
+      return steps;
+    }
+  }
+}
+
+#[test]
+fn test_noise_lfsr_periods() {
+  assert_eq!(noise_period(false), 32767);
+  assert_eq!(noise_period(true), 93);
+}
+
+#[test]
+fn test_noise_output() {
+  let mut apu = Apu::new();
+  apu.write_register(0x4015, 0x08);
+  apu.write_register(0x400c, 0x37);
+  apu.write_register(0x400f, 0x08);
+  let mut levels: Vec<u8> = (0..64)
+    .map(|_| {
+      apu.tick(&NoCartridge, 4);
+      apu.noise.output()
+    })
+    .collect();
+  levels.sort_unstable();
+  levels.dedup();
+  assert_eq!(levels, vec![0, 7]);
+}
+
+fn dmc_cart(sample: u8) -> Nrom {
+  let mut rom = Rom::from_program(&[]);
+  // $C000
+  rom.prg_rom[0x4000] = sample;
+  Nrom::new(rom)
+}
+
+#[test]
+fn test_dmc_plays_a_sample() {
+  let cart = dmc_cart(0xff);
+  let mut apu = Apu::new();
+  // IRQ on, fastest rate; one byte at $C000 starting from level 64
+  apu.write_register(0x4010, 0x8f);
+  apu.write_register(0x4011, 64);
+  apu.write_register(0x4012, 0x00);
+  apu.write_register(0x4013, 0x00);
+  apu.write_register(0x4015, 0x10);
+  assert_eq!(apu.peek_status() & 0x10, 0x10);
+
+  // the fetch happens right away and stalls the CPU
+  assert_eq!(apu.tick(&cart, 1), FETCH_STALL);
+  assert_eq!(apu.dmc.bytes_remaining(), 0);
+  assert!(apu.irq_pending());
+  assert_eq!(apu.peek_status() & 0x90, 0x80);
+
+  // eight 1 bits after the first (empty) output cycle
+  assert_eq!(apu.tick(&cart, 54 * 17), 0);
+  assert_eq!(apu.dmc.output(), 64 + 16);
+
+  // writing $4015 acknowledges
+  apu.write_register(0x4015, 0x00);
+  assert!(!apu.irq_pending());
+}
+
+#[test]
+fn test_dmc_loops() {
+  let cart = dmc_cart(0x00);
+  let mut apu = Apu::new();
+  apu.write_register(0x4010, 0xcf);
+  apu.write_register(0x4011, 100);
+  apu.write_register(0x4015, 0x10);
+  let stalls = apu.tick(&cart, 54 * 8 * 4);
+  // a fetch per byte played, never an IRQ
+  assert!(stalls >= 3 * FETCH_STALL);
+  assert_eq!(apu.peek_status() & 0x10, 0x10);
+  assert!(!apu.irq_pending());
+  assert!(apu.dmc.output() < 100);
+}
+
+#[test]
+fn test_frame_irq() {
+  let mut apu = Apu::new();
+  // raised from the cycle before the sequence's last step
+  apu.tick(&NoCartridge, SEQUENCE - 3);
+  assert!(!apu.irq_pending());
+  apu.tick(&NoCartridge, 1);
+  assert!(apu.irq_pending());
+  assert_eq!(apu.peek_status() & 0x40, 0x40);
+  // reading $4015 acknowledges
+  assert_eq!(apu.read_status() & 0x40, 0x40);
+  assert!(!apu.irq_pending());
+
+  // inhibited, and never raised in 5-step mode
+  apu.write_register(0x4017, 0x40);
+  apu.tick(&NoCartridge, SEQUENCE * 2);
+  assert!(!apu.irq_pending());
+  apu.write_register(0x4017, 0x80);
+  apu.tick(&NoCartridge, SEQUENCE * 2);
+  assert!(!apu.irq_pending());
+}
+
+#[test]
+fn test_frame_irq_is_raised_for_three_cycles() {
+  let mut apu = Apu::new();
+  apu.tick(&NoCartridge, SEQUENCE - 2);
+  apu.read_status();
+  apu.tick(&NoCartridge, 1);
+  // acknowledged during the window: back on the next cycle
+  assert_eq!(apu.read_status() & 0x40, 0x40);
+  apu.tick(&NoCartridge, 1);
+  assert_eq!(apu.read_status() & 0x40, 0x40);
+  apu.tick(&NoCartridge, 1);
+  assert_eq!(apu.read_status() & 0x40, 0x00);
+}
+
+#[test]
+fn test_frame_counter_write_delay() {
+  // 5-step mode clocks a half frame once the write takes effect
+  let write_at = |cycles: u32| {
+    let mut apu = Apu::new();
+    apu.tick(&NoCartridge, cycles);
+    square(&mut apu, 0b1001_1111, 0x100, 3);
+    apu.write_register(0x4017, 0x80);
+    (1..=4)
+      .map(|_| {
+        apu.tick(&NoCartridge, 1);
+        apu.pulse1.length_counter()
+      })
+      .collect::<Vec<u8>>()
+  };
+  // on a put cycle it takes 4 cycles, on a get cycle 3
+  assert_eq!(write_at(0), vec![2, 2, 2, 1]);
+  assert_eq!(write_at(1), vec![2, 2, 1, 1]);
+}
+
+#[test]
+fn test_dmc_fetch_alignment() {
+  let cart = Nrom::new(Rom::from_program(&[]));
+  for (start, stall) in [(0, FETCH_STALL), (1, FETCH_STALL - 1)].iter() {
+    let mut apu = Apu::new();
+    apu.tick(&cart, *start);
+    apu.write_register(0x4015, 0x10);
+    assert_eq!(apu.tick(&cart, 1), *stall);
+  }
+}
+
+#[test]
+fn test_dmc_fetch_stalls_the_cpu() {
+  let mut cpu = CPU::new();
+  // NOP, NOP
+  cpu.load(vec![0xea, 0xea, 0x00]).unwrap();
+  assert_eq!(cpu.step(), Ok(Some(2)));
+  cpu.bus.mem_write(0x4015, 0x10);
+  assert_eq!(cpu.step(), Ok(Some(2 + FETCH_STALL as u16)));
+  assert_eq!(cpu.cycles, 4 + FETCH_STALL as u64);
+}
+
+#[test]
+fn test_resampler_averages_exact_spans() {
+  // 2.5 inputs per output: the third input is split between two outputs
+  let mut resampler = Resampler::new(5.0, 2.0);
+  let out: Vec<f32> = [1.0, 1.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0, 1.0]
+    .iter()
+    .filter_map(|&s| resampler.push(s))
+    .collect();
+  assert_eq!(out, vec![0.8, 0.0, 1.0, 1.0]);
+
+  // running 1% fast yields 1% more samples
+  let mut resampler = Resampler::new(CPU_CLOCK, 48_000.0);
+  resampler.set_adjust(0.01);
+  let count = (0..CPU_CLOCK as u32)
+    .filter_map(|_| resampler.push(0.5))
+    .count();
+  assert!((count as i32 - 48_480).abs() <= 1);
+}
+
+#[test]
+fn test_sample_ring_underrun_and_overrun() {
+  let mut ring = SampleRing::new(4);
+  ring.push(0.1);
+  ring.push(0.2);
+  let mut out = [0.0; 3];
+  assert_eq!(ring.pop_into(&mut out), 2);
+  // the last level is held
+  assert_eq!(out.to_vec(), vec![0.1, 0.2, 0.2]);
+  assert_eq!(ring.underruns(), 1);
+
+  for i in 0..5 {
+    ring.push(i as f32);
+  }
+  // the fifth push dropped the oldest half
+  assert_eq!(ring.overruns(), 1);
+  assert_eq!(ring.drain(), vec![2.0, 3.0, 4.0]);
+  assert_eq!(ring.underruns(), 1);
+}
+
+#[test]
+fn test_fill_audio_keeps_the_ring_half_full() {
+  let mut apu = Apu::new();
+  apu.set_sample_rate(48_000);
+  let mut out = [0.0; 1024];
+  // nothing buffered: speed up
+  assert_eq!(apu.fill_audio(&mut out), 0);
+  assert!(apu.rate_adjust() > 0.0);
+  assert_eq!(apu.sample_ring().underruns(), 1);
+
+  // nearly full: slow down
+  apu.tick(&NoCartridge, CPU_CLOCK as u32 / 5 - 10_000);
+  assert_eq!(apu.fill_audio(&mut out), out.len());
+  assert!(apu.rate_adjust() < 0.0);
+  assert_eq!(apu.sample_ring().overruns(), 0);
+}
+
+#[test]
+fn test_match_fill_for_a_queue_elsewhere() {
+  let mut apu = Apu::new();
+  apu.match_fill(0.1);
+  assert!(apu.rate_adjust() > 0.0);
+  apu.match_fill(0.5);
+  assert!(apu.rate_adjust().abs() < 1e-9);
+  apu.match_fill(1.5);
+  let slowest = apu.rate_adjust();
+  apu.match_fill(1.0);
+  assert!(slowest < 0.0 && (apu.rate_adjust() - slowest).abs() < 1e-9);
+}
+
+#[test]
+fn test_latency_sizes_the_ring() {
+  let mut apu = Apu::new();
+  apu.set_sample_rate(48_000);
+  assert_eq!(apu.latency(), DEFAULT_LATENCY_MS);
+  assert_eq!(apu.sample_ring().capacity(), 9_600);
+
+  apu.set_latency(40);
+  assert_eq!(apu.sample_ring().capacity(), 3_840);
+  assert!(apu.measured_latency() < 1e-9);
+  // a quarter of 80ms queued
+  apu.tick(&NoCartridge, CPU_CLOCK as u32 / 50);
+  let mut out = [0.0; 0];
+  apu.fill_audio(&mut out);
+  assert!((apu.measured_latency() - 20.0).abs() < 0.5);
+  // from a queue elsewhere
+  apu.match_fill(0.5);
+  assert!((apu.measured_latency() - 40.0).abs() < 1e-9);
+
+  apu.set_latency(1);
+  assert_eq!(apu.latency(), MIN_LATENCY_MS);
+  apu.set_latency(10_000);
+  assert_eq!(apu.latency(), MAX_LATENCY_MS);
+}
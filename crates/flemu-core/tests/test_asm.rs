@@ -0,0 +1,205 @@
+use flemu_core::nes::asm::{assemble, assemble_at, AsmError};
+use flemu_core::nes::bus::Mem;
+use flemu_core::nes::cpu::CPU;
+
+#[test]
+fn test_addressing_modes() {
+  let source = "
+    LDA #$c0
+    LDA $10
+    LDA $10,X
+    LDX $10,Y
+    LDA $1234
+    LDA $1234,X
+    LDA $1234,Y
+    LDA ($20,X)
+    LDA ($20),Y
+    JMP ($1234)
+    ASL A
+    ASL
+    TAX
+  ";
+  #[rustfmt::skip]
+  assert_eq!(
+    assemble(source),
+    Ok(vec![
+      0xa9, 0xc0,
+      0xa5, 0x10,
+      0xb5, 0x10,
+      0xb6, 0x10,
+      0xad, 0x34, 0x12,
+      0xbd, 0x34, 0x12,
+      0xb9, 0x34, 0x12,
+      0xa1, 0x20,
+      0xb1, 0x20,
+      0x6c, 0x34, 0x12,
+      0x0a,
+      0x0a,
+      0xaa,
+    ])
+  );
+}
+
+#[test]
+fn test_case_spaces_and_comments_dont_matter() {
+  assert_eq!(
+    assemble("  lda ( $20 ) , y ; a comment\n\n; a whole line of one\nsta $0200, x"),
+    Ok(vec![0xb1, 0x20, 0x9d, 0x00, 0x02])
+  );
+}
+
+#[test]
+fn test_zero_page_unless_written_wide() {
+  assert_eq!(assemble("STA $0010"), Ok(vec![0x8d, 0x10, 0x00]));
+  assert_eq!(assemble("STA 16"), Ok(vec![0x85, 0x10]));
+  assert_eq!(assemble("STA %00010000"), Ok(vec![0x85, 0x10]));
+  // LDY has no zero page,Y form
+  assert_eq!(
+    assemble("LDX $10,Y\nSTX $10,Y"),
+    Ok(vec![0xb6, 0x10, 0x96, 0x10])
+  );
+  assert_eq!(assemble("LDA $10,Y"), Ok(vec![0xb9, 0x10, 0x00]));
+}
+
+#[test]
+fn test_labels_and_branches() {
+  let source = "
+    start:
+      LDX #$08
+    loop: DEX
+      BNE loop
+      BEQ done
+      JMP start
+    done: JSR start
+  ";
+  #[rustfmt::skip]
+  assert_eq!(
+    assemble_at(source, 0xc000),
+    Ok(vec![
+      0xa2, 0x08,
+      0xca,
+      0xd0, 0xfd,
+      0xf0, 0x03,
+      0x4c, 0x00, 0xc0,
+      0x20, 0x00, 0xc0,
+    ])
+  );
+}
+
+#[test]
+fn test_constants_and_expressions() {
+  let source = "
+    PPUCTRL = $2000
+    counter = $10
+      LDA #<table+1
+      LDX #>table
+      STA PPUCTRL+1
+      INC counter
+      DEC counter-1,X
+      LDA #-1
+    table: .byte 1, $02, %11
+      .word table, PPUCTRL
+  ";
+  #[rustfmt::skip]
+  assert_eq!(
+    assemble(source),
+    Ok(vec![
+      0xa9, 0x0e,
+      0xa2, 0x80,
+      0x8d, 0x01, 0x20,
+      0xe6, 0x10,
+      0xd6, 0x0f,
+      0xa9, 0xff,
+      0x01, 0x02, 0x03,
+      0x0d, 0x80, 0x00, 0x20,
+    ])
+  );
+}
+
+#[test]
+fn test_org_pads() {
+  assert_eq!(
+    assemble_at("NOP\n.org $0204\nvector: .word vector", 0x0200),
+    Ok(vec![0xea, 0, 0, 0, 0x04, 0x02])
+  );
+  assert_eq!(
+    assemble_at("NOP\nNOP\n.org $0201", 0x0200),
+    Err(AsmError::OutOfRange { line: 3 })
+  );
+}
+
+#[test]
+fn test_unofficial_opcodes_keep_their_star() {
+  assert_eq!(
+    assemble("*LAX $10\n*NOP\nNOP\n*SBC #1"),
+    Ok(vec![0xa7, 0x10, 0x1a, 0xea, 0xeb, 0x01])
+  );
+  assert_eq!(
+    assemble("LAX $10"),
+    Err(AsmError::UnknownInstruction { line: 1 })
+  );
+}
+
+#[test]
+fn test_errors_say_where() {
+  assert_eq!(
+    assemble("NOP\nLDA"),
+    Err(AsmError::UnknownInstruction { line: 2 })
+  );
+  assert_eq!(
+    assemble("STA #1"),
+    Err(AsmError::UnknownInstruction { line: 1 })
+  );
+  assert_eq!(assemble("\nLDA #$1g"), Err(AsmError::Syntax { line: 2 }));
+  assert_eq!(assemble("1up: NOP"), Err(AsmError::Syntax { line: 1 }));
+  assert_eq!(
+    assemble("JMP nowhere"),
+    Err(AsmError::UnknownLabel {
+      line: 1,
+      label: "nowhere".to_string()
+    })
+  );
+  assert_eq!(
+    assemble("a: NOP\na: NOP"),
+    Err(AsmError::DuplicateLabel {
+      line: 2,
+      label: "a".to_string()
+    })
+  );
+  assert_eq!(assemble("LDA #256"), Err(AsmError::OutOfRange { line: 1 }));
+  assert_eq!(
+    assemble("BNE far\n.org $8100\nfar: NOP"),
+    Err(AsmError::OutOfRange { line: 1 })
+  );
+  assert_eq!(
+    AsmError::UnknownLabel {
+      line: 3,
+      label: "far".to_string()
+    }
+    .to_string(),
+    "line 3: undefined label far"
+  );
+}
+
+#[test]
+fn test_assembled_program_runs() {
+  // sum 1..=10 into $10
+  let program = assemble(
+    "
+      LDA #0
+      LDX #10
+    loop:
+      STX $00
+      CLC
+      ADC $00
+      DEX
+      BNE loop
+      STA $10
+      BRK
+    ",
+  )
+  .unwrap();
+  let mut cpu = CPU::new();
+  cpu.load_and_run(program).unwrap();
+  assert_eq!(cpu.mem_peek(0x10), 55);
+}
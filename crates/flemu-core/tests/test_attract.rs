@@ -0,0 +1,201 @@
+use flemu_core::nes::asm;
+use flemu_core::nes::attract::{Attract, DEFAULT_IDLE_FRAMES};
+use flemu_core::nes::bus::Mem;
+use flemu_core::nes::cartridge::Rom;
+use flemu_core::nes::cpu::CPU;
+use flemu_core::nes::joypad::JoypadButton;
+use flemu_core::nes::movie::{Movie, MovieFrame, MovieStart};
+use flemu_core::nes::savestate::cartridge_checksum;
+use flemu_core::nes::timing::Timing;
+
+// every NMI counts in $10 and reads controller 1 into $11, A in bit 7
+// down to Right in bit 0; `tag` makes a different cartridge
+fn rom(tag: u8) -> Rom {
+  let program = asm::assemble(&format!(
+    "
+      LDA #${:02X}
+      STA $12
+      LDA #$80
+      STA $2000
+    loop: JMP loop
+    nmi:
+      INC $10
+      LDA #$01
+      STA $4016
+      LDA #$00
+      STA $4016
+      LDX #$08
+    bit: LDA $4016
+      LSR A
+      ROL $11
+      DEX
+      BNE bit
+      RTI
+      .org $FFFA
+      .word nmi
+      .word $8000
+      .word nmi
+    ",
+    tag
+  ))
+  .unwrap();
+  Rom::from_program(&program)
+}
+
+fn console(rom: &Rom) -> CPU {
+  let mut cpu = CPU::new();
+  cpu.load_rom(rom.clone()).unwrap();
+  cpu
+}
+
+// `frames` frames holding `buttons` on controller 1
+fn demo(frames: usize, buttons: JoypadButton) -> Movie {
+  let mut movie = Movie::new(MovieStart::PowerOn, false, false);
+  let mut frame = MovieFrame::default();
+  frame.buttons[0] = buttons;
+  movie.frames = vec![frame; frames];
+  movie
+}
+
+fn run(attract: &mut Attract, cpu: &mut CPU, frames: usize) {
+  for _ in 0..frames {
+    attract.frame(cpu).unwrap();
+    assert!(cpu.run_frame());
+  }
+}
+
+#[test]
+fn test_demo_starts_when_idle_and_input_stops_it() {
+  let rom = rom(0);
+  let mut cpu = console(&rom);
+  let mut attract = Attract::new();
+  assert_eq!(attract.idle_frames(), DEFAULT_IDLE_FRAMES);
+  attract.set_idle_frames(20);
+  attract.set_demo(&rom, demo(100, JoypadButton::A)).unwrap();
+
+  run(&mut attract, &mut cpu, 19);
+  assert!(!attract.playing());
+  let before = (
+    cpu.program_counter,
+    cpu.bus.ppu.frame_count,
+    cpu.mem_peek(0x10),
+  );
+  assert_eq!(cpu.mem_peek(0x11), 0);
+
+  // the 20th frame is the demo's first, from power on
+  run(&mut attract, &mut cpu, 1);
+  assert!(attract.playing());
+  assert_eq!(cpu.bus.ppu.frame_count, 1);
+  run(&mut attract, &mut cpu, 10);
+  assert_eq!(cpu.mem_peek(0x10), 10);
+  assert_eq!(cpu.mem_peek(0x11), 0x80);
+
+  assert!(attract.input(&mut cpu).unwrap());
+  assert!(!attract.playing());
+  assert_eq!(
+    (
+      cpu.program_counter,
+      cpu.bus.ppu.frame_count,
+      cpu.mem_peek(0x10)
+    ),
+    before
+  );
+  assert_eq!(cpu.mem_peek(0x11), 0);
+  assert!(!attract.input(&mut cpu).unwrap());
+
+  // and it takes as long again to start
+  run(&mut attract, &mut cpu, 19);
+  assert!(!attract.playing());
+  run(&mut attract, &mut cpu, 1);
+  assert!(attract.playing());
+}
+
+#[test]
+fn test_demo_loops() {
+  let rom = rom(0);
+  let mut cpu = console(&rom);
+  let mut attract = Attract::new();
+  attract.set_idle_frames(1);
+  attract.set_demo(&rom, demo(5, JoypadButton::B)).unwrap();
+  run(&mut attract, &mut cpu, 5);
+  assert_eq!(cpu.bus.ppu.frame_count, 5);
+  // over, so from the top: power on again
+  run(&mut attract, &mut cpu, 2);
+  assert!(attract.playing());
+  assert_eq!(cpu.bus.ppu.frame_count, 2);
+  assert_eq!(cpu.mem_peek(0x10), 1);
+}
+
+#[test]
+fn test_steps_within_a_frame_keep_its_input() {
+  let rom = rom(0);
+  let mut cpu = console(&rom);
+  let mut attract = Attract::new();
+  attract.set_idle_frames(1);
+  let mut movie = demo(2, JoypadButton::A);
+  movie.frames[1].buttons[0] = JoypadButton::B;
+  attract.set_demo(&rom, movie).unwrap();
+  attract.frame(&mut cpu).unwrap();
+  assert!(cpu.step_scanline());
+  // still the first frame: not the second's input yet
+  attract.frame(&mut cpu).unwrap();
+  assert_eq!(cpu.bus.joypads[0].buttons(), JoypadButton::A);
+  assert!(cpu.run_frame());
+  attract.frame(&mut cpu).unwrap();
+  assert_eq!(cpu.bus.joypads[0].buttons(), JoypadButton::B);
+}
+
+#[test]
+fn test_demos_are_per_cartridge() {
+  let (rom, other) = (rom(0), rom(1));
+  let mut cpu = console(&other);
+  let mut attract = Attract::new();
+  attract.set_idle_frames(1);
+  attract.set_demo(&rom, demo(5, JoypadButton::A)).unwrap();
+  run(&mut attract, &mut cpu, 3);
+  assert!(!attract.playing());
+  assert_eq!(cpu.mem_peek(0x12), 1);
+
+  let checksum = cartridge_checksum(&rom.prg_rom, &rom.chr_rom);
+  assert_eq!(attract.demo(checksum).map(Movie::len), Some(5));
+  // replacing it keeps one
+  attract.set_demo(&rom, demo(7, JoypadButton::A)).unwrap();
+  assert_eq!(attract.demo(checksum).map(Movie::len), Some(7));
+
+  let mut cpu = console(&rom);
+  run(&mut attract, &mut cpu, 1);
+  assert!(attract.playing());
+  attract.remove_demo(checksum);
+  assert!(!attract.playing());
+  assert!(attract.demo(checksum).is_none());
+  run(&mut attract, &mut cpu, 3);
+  assert!(!attract.playing());
+}
+
+#[test]
+fn test_pal_demo_plays_at_pal_timing() {
+  let rom = rom(0);
+  let mut cpu = console(&rom);
+  let mut attract = Attract::new();
+  attract.set_idle_frames(1);
+  let mut movie = demo(5, JoypadButton::A);
+  movie.pal = true;
+  attract.set_demo(&rom, movie).unwrap();
+  run(&mut attract, &mut cpu, 2);
+  assert_eq!(cpu.bus.timing(), Timing::Pal);
+  attract.input(&mut cpu).unwrap();
+  assert_eq!(cpu.bus.timing(), Timing::Ntsc);
+  assert_eq!(cpu.bus.timing_override(), None);
+}
+
+#[test]
+fn test_empty_demo_never_plays() {
+  let rom = rom(0);
+  let mut cpu = console(&rom);
+  let mut attract = Attract::new();
+  attract.set_idle_frames(1);
+  attract.set_demo(&rom, demo(0, JoypadButton::A)).unwrap();
+  run(&mut attract, &mut cpu, 3);
+  assert!(!attract.playing());
+  assert_eq!(cpu.bus.ppu.frame_count, 3);
+}
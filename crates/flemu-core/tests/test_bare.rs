@@ -0,0 +1,72 @@
+use flemu_core::bare::*;
+use flemu_core::nes::bus::Mem;
+
+#[test]
+fn test_output_port_and_trap() {
+  let program = [
+    0xa2, 0x00, // LDX #0
+    0xbd, 0x10, 0x02, // LDA $0210,X
+    0xf0, 0x06, // BEQ done
+    0x8d, 0x01, 0xf0, // STA $F001
+    0xe8, // INX
+    0xd0, 0xf5, // BNE, back to the LDA
+    0x4c, 0x0d, 0x02, // done: JMP done
+    b'H', b'I', 0x00,
+  ];
+  let config = Config {
+    load_address: 0x0200,
+    start: Some(0x0200),
+    output_port: Some(0xf001),
+    ..Config::default()
+  };
+  let mut cpu = machine(&program, &config).unwrap();
+  assert_eq!(run(&mut cpu, 1000), Stop::Trap(0x020d));
+  assert_eq!(cpu.bus.output(), b"HI");
+  assert_eq!(cpu.bus.take_output(), b"HI".to_vec());
+  assert!(cpu.bus.output().is_empty());
+}
+
+#[test]
+fn test_vectors_and_decimal_mode() {
+  let program = [
+    0xf8, // SED
+    0x18, // CLC
+    0xa9, 0x19, // LDA #$19
+    0x69, 0x28, // ADC #$28
+    0x00, 0xff, // BRK
+    0x4c, 0x08, 0x03, // JMP *
+  ];
+  // BRK handler: STA $10, JMP *
+  let handler = [0x85, 0x10, 0x4c, 0x02, 0x04];
+  let config = Config {
+    load_address: 0x0300,
+    reset_vector: Some(0x0300),
+    irq_vector: Some(0x0400),
+    ..Config::default()
+  };
+  let mut cpu = machine(&program, &config).unwrap();
+  assert_eq!(cpu.program_counter, 0x0300);
+  for (i, &byte) in handler.iter().enumerate() {
+    cpu.bus.mem_write(0x0400 + i as u16, byte);
+  }
+  assert_eq!(run(&mut cpu, 1000), Stop::Trap(0x0402));
+  assert_eq!(cpu.bus.mem_peek(0x10), 0x47);
+}
+
+#[test]
+fn test_program_too_large() {
+  let config = Config {
+    load_address: 0xff00,
+    ..Config::default()
+  };
+  let err = machine(&[0; 0x101], &config).err().unwrap();
+  assert_eq!(err.to_string(), "257 bytes don't fit in memory from $FF00");
+  assert!(machine(&[0; 0x100], &config).is_ok());
+}
+
+#[test]
+fn test_runaway_program() {
+  // INX, JMP $0000, started through the (zero) reset vector
+  let mut cpu = machine(&[0xe8, 0x4c, 0x00, 0x00], &Config::default()).unwrap();
+  assert_eq!(run(&mut cpu, 100), Stop::Limit);
+}
@@ -0,0 +1,144 @@
+use flemu_core::nes::blargg::*;
+use flemu_core::nes::cartridge::Rom;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/*
+  blargg's test ROMs aren't part of the repository. Unpack the suites into
+  tests/roms/blargg (or point BLARGG_DIR at them), e.g.
+  tests/roms/blargg/instr_test-v5/rom_singles/01-basics.nes, and each
+  suite's test runs every ROM of it. Suites that aren't there are skipped.
+  They're slow in debug builds; `cargo test --release` helps.
+
+  sprite_hit_tests_2005.10.05 predates the $6000 protocol and only shows
+  its result on screen, its ROMs come out unfinished.
+*/
+
+// a minute of emulated time
+const MAX_FRAMES: u32 = 60 * 60;
+
+fn suite_roms(suite: &str) -> Option<Vec<PathBuf>> {
+  let root = std::env::var_os("BLARGG_DIR")
+    .map(PathBuf::from)
+    .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/roms/blargg"));
+  let dir = root.join(suite);
+  // the singles report one result each, the combined ROM only the first
+  // failure
+  let dir = match dir.join("rom_singles") {
+    singles if singles.is_dir() => singles,
+    _ => dir,
+  };
+  let mut roms: Vec<PathBuf> = fs::read_dir(&dir)
+    .ok()?
+    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+    .filter(|path| path.extension().map_or(false, |ext| ext == "nes"))
+    .collect();
+  roms.sort();
+  Some(roms)
+}
+
+fn run_rom(path: &Path) -> TestResult {
+  let rom = Rom::from_bytes(&fs::read(path).unwrap()).unwrap();
+  run(rom, MAX_FRAMES).unwrap()
+}
+
+fn run_suite(suite: &str) {
+  let roms = match suite_roms(suite) {
+    Some(roms) => roms,
+    None => {
+      eprintln!("{} not found, skipping", suite);
+      return;
+    }
+  };
+  let mut failures = Vec::new();
+  for path in roms.iter() {
+    let name = path.file_name().unwrap().to_string_lossy();
+    let result = run_rom(path);
+    eprintln!("{}/{}: {}", suite, name, result);
+    if !result.passed() {
+      failures.push(format!("{}: {}", name, result));
+    }
+  }
+  assert!(
+    failures.is_empty(),
+    "{} of {} failed:\n{}",
+    failures.len(),
+    roms.len(),
+    failures.join("\n")
+  );
+}
+
+#[test]
+fn test_instr_test() {
+  run_suite("instr_test-v5");
+}
+
+#[test]
+fn test_ppu_vbl_nmi() {
+  run_suite("ppu_vbl_nmi");
+}
+
+#[test]
+fn test_sprite_hit() {
+  run_suite("sprite_hit_tests_2005.10.05");
+}
+
+#[test]
+fn test_apu_test() {
+  run_suite("apu_test");
+}
+
+// signature at $6001, "ok" at $6004
+#[rustfmt::skip]
+const REPORTING: [u8; 25] = [
+  0xa9, 0xde, 0x8d, 0x01, 0x60,
+  0xa9, 0xb0, 0x8d, 0x02, 0x60,
+  0xa9, 0x61, 0x8d, 0x03, 0x60,
+  0xa9, 0x6f, 0x8d, 0x04, 0x60,
+  0xa9, 0x6b, 0x8d, 0x05, 0x60,
+];
+
+fn reporting(then: &[u8]) -> Rom {
+  Rom::from_program(&[&REPORTING[..], then].concat())
+}
+
+#[test]
+fn test_run_reads_the_result() {
+  // status 3, JMP to itself
+  let failing = reporting(&[0xa9, 0x03, 0x8d, 0x00, 0x60, 0x4c, 0x1e, 0x80]);
+  assert_eq!(
+    run(failing, 10).unwrap(),
+    TestResult::Failed {
+      code: 3,
+      text: "ok".to_string()
+    }
+  );
+
+  // running forever
+  let running = reporting(&[0xa9, 0x80, 0x8d, 0x00, 0x60, 0x4c, 0x1e, 0x80]);
+  let result = run(running, 10).unwrap();
+  assert_eq!(
+    result,
+    TestResult::Unfinished {
+      text: "ok".to_string()
+    }
+  );
+  assert_eq!(result.to_string(), "didn't finish: ok");
+}
+
+#[test]
+fn test_run_presses_reset_when_asked() {
+  #[rustfmt::skip]
+  let rom = reporting(&[
+    0xad, 0x10, 0x60,       // $8019 LDA $6010, survives the reset
+    0xd0, 0x0b,             //       BNE passed
+    0xee, 0x10, 0x60,       //       INC $6010
+    0xa9, 0x81, 0x8d, 0x00, 0x60, // status $81
+    0x4c, 0x26, 0x80,       // $8026 JMP $8026
+    0xa9, 0x00, 0x8d, 0x00, 0x60, // passed: status 0
+    0x4c, 0x2e, 0x80,       // $802E JMP $802E
+  ]);
+  let result = run(rom, 30).unwrap();
+  assert!(result.passed(), "{}", result);
+  assert_eq!(result.text(), "ok");
+}
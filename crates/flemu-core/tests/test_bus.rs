@@ -0,0 +1,206 @@
+use flemu_core::nes::bus::{Bus, Mem};
+use flemu_core::nes::cartridge::{Rom, PRG_ROM_PAGE_SIZE};
+use flemu_core::nes::timing::Timing;
+
+fn rom_with_prg(prg_rom: Vec<u8>) -> Rom {
+  let mut rom = Rom::from_program(&[]);
+  rom.prg_rom = prg_rom;
+  rom
+}
+
+#[test]
+fn test_ram_is_mirrored_every_2k() {
+  let mut bus = Bus::new();
+  bus.mem_write(0x0012, 0x42);
+  assert_eq!(bus.mem_read(0x0812), 0x42);
+  assert_eq!(bus.mem_read(0x1012), 0x42);
+  assert_eq!(bus.mem_read(0x1812), 0x42);
+
+  bus.mem_write(0x1fff, 0x17);
+  assert_eq!(bus.mem_read(0x07ff), 0x17);
+}
+
+#[test]
+fn test_ppu_registers_are_mirrored_every_8_bytes() {
+  let mut bus = Bus::with_rom(Rom::from_program(&[])).unwrap();
+  // $2006 = $2400 and a byte through $2007, at the top of the mirrors
+  bus.mem_write(0x3ffe, 0x24);
+  bus.mem_write(0x3ffe, 0x00);
+  bus.mem_write(0x3fff, 0x5a);
+  bus.mem_write(0x200e, 0x24);
+  bus.mem_write(0x200e, 0x00);
+  // the first read only fills the buffer
+  bus.mem_read(0x2807);
+  assert_eq!(bus.mem_read(0x2007), 0x5a);
+}
+
+#[test]
+fn test_ppu_and_apu_registers_are_not_backed_by_ram() {
+  let mut bus = Bus::new();
+  bus.mem_write(0x2000, 0xff);
+  bus.mem_write(0x3ff8, 0xff);
+  bus.mem_write(0x4015, 0xff);
+  // write-only registers read back the last value on the PPU's data bus
+  bus.mem_write(0x2003, 0x00);
+  assert_eq!(bus.mem_read(0x2000), 0);
+  assert_eq!(bus.mem_read(0x3ff8), 0);
+  // APU status: no length counters loaded, only the DMC's sample pending
+  assert_eq!(bus.mem_read(0x4015), 0x10);
+}
+
+#[test]
+fn test_prg_ram() {
+  let mut bus = Bus::with_rom(Rom::from_program(&[])).unwrap();
+  bus.mem_write(0x6000, 0x99);
+  bus.mem_write(0x7fff, 0x98);
+  assert_eq!(bus.mem_read(0x6000), 0x99);
+  assert_eq!(bus.mem_read(0x7fff), 0x98);
+}
+
+#[test]
+fn test_battery_ram_writes_mark_it_dirty() {
+  let mut rom = Rom::from_program(&[]);
+  rom.info.battery = true;
+  let mut bus = Bus::with_rom(rom).unwrap();
+  assert!(!bus.take_battery_dirty());
+  bus.mem_write(0x0010, 1);
+  assert!(!bus.take_battery_dirty());
+  bus.mem_write(0x7FFF, 1);
+  assert!(bus.take_battery_dirty());
+  assert!(!bus.take_battery_dirty());
+
+  // without a battery there's nothing to persist
+  let mut bus = Bus::with_rom(Rom::from_program(&[])).unwrap();
+  bus.mem_write(0x6000, 1);
+  assert!(!bus.take_battery_dirty());
+}
+
+#[test]
+fn test_battery_writes_count_regardless_of_taking_dirty() {
+  let mut rom = Rom::from_program(&[]);
+  rom.info.battery = true;
+  let mut bus = Bus::with_rom(rom).unwrap();
+  assert_eq!(bus.battery_writes(), 0);
+  bus.mem_write(0x0010, 1);
+  bus.mem_write(0x6000, 1);
+  assert!(bus.take_battery_dirty());
+  bus.mem_write(0x6001, 1);
+  assert_eq!(bus.battery_writes(), 2);
+}
+
+#[test]
+fn test_prg_rom_is_read_only() {
+  let mut bus = Bus::with_rom(Rom::from_program(&[0xa9, 0x01])).unwrap();
+  bus.mem_write(0x8000, 0xff);
+  assert_eq!(bus.mem_read(0x8000), 0xa9);
+  assert_eq!(bus.mem_read_u16(0xfffc), 0x8000);
+}
+
+#[test]
+fn test_16k_prg_rom_is_mirrored() {
+  let mut prg_rom = vec![0; PRG_ROM_PAGE_SIZE];
+  prg_rom[0x0123] = 0x42;
+  let mut bus = Bus::with_rom(rom_with_prg(prg_rom)).unwrap();
+  assert_eq!(bus.mem_read(0x8123), 0x42);
+  assert_eq!(bus.mem_read(0xc123), 0x42);
+}
+
+#[test]
+fn test_u16_read_wraps_at_top_of_memory() {
+  let mut prg_rom = vec![0; PRG_ROM_PAGE_SIZE];
+  prg_rom[0x3fff] = 0x34;
+  let mut bus = Bus::with_rom(rom_with_prg(prg_rom)).unwrap();
+  bus.mem_write(0x0000, 0x12);
+  assert_eq!(bus.mem_read_u16(0xffff), 0x1234);
+}
+
+#[test]
+fn test_no_cartridge_reads_zero() {
+  let mut bus = Bus::new();
+  bus.mem_write(0x6000, 0x99);
+  assert_eq!(bus.mem_read(0x6000), 0);
+  assert_eq!(bus.mem_read_u16(0xfffc), 0);
+}
+
+#[test]
+fn test_unmapped_reads_return_open_bus() {
+  // UxROM has no PRG RAM
+  let mut rom = rom_with_prg(vec![0; PRG_ROM_PAGE_SIZE]);
+  rom.info.mapper = 2;
+  let mut bus = Bus::with_rom(rom).unwrap();
+  bus.mem_write(0x0010, 0x5a);
+  assert_eq!(bus.mem_read(0x4018), 0x5a);
+  assert_eq!(bus.mem_read(0x5000), 0x5a);
+  assert_eq!(bus.mem_read(0x6000), 0x5a);
+
+  // and the bit of $4015 the APU doesn't drive
+  bus.mem_write(0x0010, 0xff);
+  assert_eq!(bus.mem_read(0x0010), 0xff);
+  assert_eq!(bus.mem_read(0x4015), 0x20);
+}
+
+#[test]
+fn test_mmc1_ignores_back_to_back_writes() {
+  let prg_rom = (0..8 * PRG_ROM_PAGE_SIZE)
+    .map(|i| (i / PRG_ROM_PAGE_SIZE) as u8 + 1)
+    .collect();
+  let mut rom = rom_with_prg(prg_rom);
+  rom.info.mapper = 1;
+  let mut bus = Bus::with_rom(rom).unwrap();
+  // INC $E000 on a 1 writes the 1 back, then the 2 on the next cycle
+  bus.mem_write(0xe000, 1);
+  bus.mem_write(0xe000, 2);
+  for _ in 0..4 {
+    bus.mem_read(0x0000);
+    bus.mem_write(0xe000, 1);
+  }
+  // 0b11111, bank 7, not 0b11101 with the 2 shifted in
+  assert_eq!(bus.mem_read(0x8000), 8);
+}
+
+#[test]
+fn test_chr_ram_when_the_header_has_no_chr_rom() {
+  // NROM, MMC1, UxROM and AxROM boards with CHR RAM
+  for &mapper in &[0u8, 1, 2, 7] {
+    let mut bytes = vec![0x4E, 0x45, 0x53, 0x1A, 0x02, 0x00, mapper << 4, 0];
+    bytes.resize(16 + 2 * PRG_ROM_PAGE_SIZE, 0);
+    let mut bus = Bus::with_rom(Rom::from_bytes(&bytes).unwrap()).unwrap();
+    // pattern tables written and read back through PPUADDR/PPUDATA
+    for &(addr, value) in &[(0x0000u16, 0x11), (0x1FFF, 0x22)] {
+      bus.mem_write(0x2006, (addr >> 8) as u8);
+      bus.mem_write(0x2006, addr as u8);
+      bus.mem_write(0x2007, value);
+      bus.mem_write(0x2006, (addr >> 8) as u8);
+      bus.mem_write(0x2006, addr as u8);
+      // the first read only fills the buffer
+      bus.mem_read(0x2007);
+      assert_eq!(
+        bus.mem_read(0x2007),
+        value,
+        "mapper {} at {:04x}",
+        mapper,
+        addr
+      );
+    }
+  }
+}
+
+#[test]
+fn test_a_new_console_takes_the_frontends_settings() {
+  let mut old = Bus::with_rom(Rom::from_program(&[])).unwrap();
+  old.apu.set_sample_rate(48000);
+  old.set_timing_override(Some(Timing::Pal));
+  old.set_zapper(true);
+  old.ppu.layers.background = false;
+  old.cheats.freeze(0x0075, 9).unwrap();
+  let mut bus = Bus::with_rom(Rom::from_program(&[])).unwrap();
+  bus.take_settings(&mut old);
+  assert_eq!(bus.apu.sample_rate(), 48000);
+  assert_eq!(bus.timing(), Timing::Pal);
+  assert_eq!(bus.timing_override(), Some(Timing::Pal));
+  assert!(bus.zapper().is_some());
+  assert!(!bus.four_score());
+  assert!(!bus.ppu.layers.background);
+  assert_eq!(bus.cheats.freezes().len(), 1);
+  assert!(old.cheats.freezes().is_empty());
+}
@@ -0,0 +1,238 @@
+use flemu_core::nes::cartridge::*;
+use flemu_core::nes::cpu::*;
+
+struct TestRom {
+  header: Vec<u8>,
+  trainer: Option<Vec<u8>>,
+  prg_rom: Vec<u8>,
+  chr_rom: Vec<u8>,
+}
+
+fn create_rom(rom: TestRom) -> Vec<u8> {
+  let mut result = Vec::with_capacity(
+    rom.header.len()
+      + rom.trainer.as_ref().map_or(0, |t| t.len())
+      + rom.prg_rom.len()
+      + rom.chr_rom.len(),
+  );
+
+  result.extend(&rom.header);
+  if let Some(t) = rom.trainer {
+    result.extend(t);
+  }
+  result.extend(&rom.prg_rom);
+  result.extend(&rom.chr_rom);
+
+  result
+}
+
+#[test]
+fn test_from_bytes() {
+  let test_rom = create_rom(TestRom {
+    header: vec![
+      0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x31, 0x00, 00, 00, 00, 00, 00, 00, 00, 00,
+    ],
+    trainer: None,
+    prg_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+    chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+  });
+
+  let rom = Rom::from_bytes(&test_rom).unwrap();
+
+  assert_eq!(rom.chr_rom, vec![2; CHR_ROM_PAGE_SIZE]);
+  assert_eq!(rom.prg_rom, vec![1; 2 * PRG_ROM_PAGE_SIZE]);
+  assert_eq!(rom.info.mapper, 3);
+  assert_eq!(rom.info.mirroring, Mirroring::Vertical);
+  assert!(!rom.info.battery);
+}
+
+#[test]
+fn test_with_trainer() {
+  let test_rom = create_rom(TestRom {
+    header: vec![
+      0x4E,
+      0x45,
+      0x53,
+      0x1A,
+      0x02,
+      0x01,
+      0x31 | 0b110,
+      0x10,
+      00,
+      00,
+      00,
+      00,
+      00,
+      00,
+      00,
+      00,
+    ],
+    trainer: Some(vec![0; 512]),
+    prg_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+    chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+  });
+
+  let rom = Rom::from_bytes(&test_rom).unwrap();
+
+  assert_eq!(rom.chr_rom, vec![2; CHR_ROM_PAGE_SIZE]);
+  assert_eq!(rom.prg_rom, vec![1; 2 * PRG_ROM_PAGE_SIZE]);
+  assert_eq!(rom.info.mapper, 0x13);
+  assert_eq!(rom.info.mirroring, Mirroring::Vertical);
+  assert!(rom.info.battery);
+}
+
+#[test]
+fn test_four_screen_mirroring() {
+  let test_rom = create_rom(TestRom {
+    header: vec![
+      0x4E, 0x45, 0x53, 0x1A, 0x01, 0x00, 0b1001, 0x00, 00, 00, 00, 00, 00, 00, 00, 00,
+    ],
+    trainer: None,
+    prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+    chr_rom: vec![],
+  });
+
+  let rom = Rom::from_bytes(&test_rom).unwrap();
+
+  assert_eq!(rom.info.mirroring, Mirroring::FourScreen);
+  assert!(rom.chr_rom.is_empty());
+}
+
+#[test]
+fn test_invalid_roms() {
+  assert_eq!(
+    Rom::from_bytes(&[0x4E, 0x45, 0x53]).unwrap_err(),
+    RomError::NotINes
+  );
+
+  let mut header = vec![
+    0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 0x04, 00, 00, 00, 00, 00, 00, 00, 00,
+  ];
+  assert_eq!(
+    Rom::from_bytes(&header).unwrap_err(),
+    RomError::UnsupportedVersion
+  );
+
+  header[7] = 0x00;
+  assert_eq!(
+    Rom::from_bytes(&header).unwrap_err(),
+    RomError::Truncated {
+      expected: 16 + PRG_ROM_PAGE_SIZE + CHR_ROM_PAGE_SIZE,
+      actual: 16
+    }
+  );
+
+  header[4] = 0x00;
+  assert_eq!(Rom::from_bytes(&header).unwrap_err(), RomError::NoPrgRom);
+}
+
+#[test]
+fn test_load_rom_jumps_to_reset_vector() {
+  let mut prg_rom = vec![0; PRG_ROM_PAGE_SIZE];
+  // LDX #$07 at $c010, mirrored from $8010
+  prg_rom[0x0010] = 0xa2;
+  prg_rom[0x0011] = 0x07;
+  prg_rom[0x3ffc] = 0x10;
+  prg_rom[0x3ffd] = 0xc0;
+  let test_rom = create_rom(TestRom {
+    header: vec![
+      0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 0x00, 00, 00, 00, 00, 00, 00, 00, 00,
+    ],
+    trainer: None,
+    prg_rom,
+    chr_rom: vec![0; CHR_ROM_PAGE_SIZE],
+  });
+
+  let mut cpu = CPU::new();
+  cpu.load_rom(Rom::from_bytes(&test_rom).unwrap()).unwrap();
+  assert_eq!(cpu.program_counter, 0xc010);
+  cpu.run().unwrap();
+  assert_eq!(cpu.register_x, 0x07);
+}
+
+#[test]
+fn test_nes2_header() {
+  let test_rom = create_rom(TestRom {
+    header: vec![
+      0x4E, 0x45, 0x53, 0x1A, 0x02, 0x00, 0x42, 0x08, 0x31, 0x00, 0x70, 0x07, 0x01, 00, 00, 00,
+    ],
+    trainer: None,
+    prg_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+    chr_rom: vec![],
+  });
+
+  let info = Rom::from_bytes(&test_rom).unwrap().info;
+
+  assert_eq!(info.format, HeaderFormat::Nes2);
+  assert_eq!(info.mapper, 0x104);
+  assert_eq!(info.submapper, 3);
+  assert!(info.battery);
+  assert_eq!(info.region, Region::Pal);
+  assert_eq!(info.prg_rom_size, 2 * PRG_ROM_PAGE_SIZE);
+  assert_eq!(info.chr_rom_size, 0);
+  assert_eq!(info.prg_ram_size, 0);
+  assert_eq!(info.prg_nvram_size, 8192);
+  assert_eq!(info.chr_ram_size, 8192);
+  assert_eq!(info.chr_nvram_size, 0);
+}
+
+#[test]
+fn test_nes2_extended_rom_sizes() {
+  // PRG size in exponent-multiplier form: 2^14 * (1 * 2 + 1) = 48KB
+  let test_rom = create_rom(TestRom {
+    header: vec![
+      0x4E,
+      0x45,
+      0x53,
+      0x1A,
+      (14 << 2) | 0b01,
+      0x01,
+      0x00,
+      0x08,
+      0x00,
+      0x0F,
+      00,
+      00,
+      0x03,
+      00,
+      00,
+      00,
+    ],
+    trainer: None,
+    prg_rom: vec![1; 3 * PRG_ROM_PAGE_SIZE],
+    chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+  });
+
+  let rom = Rom::from_bytes(&test_rom).unwrap();
+
+  assert_eq!(rom.prg_rom.len(), 3 * PRG_ROM_PAGE_SIZE);
+  assert_eq!(rom.chr_rom, vec![2; CHR_ROM_PAGE_SIZE]);
+  assert_eq!(rom.info.region, Region::Dendy);
+}
+
+#[test]
+fn test_nes2_sizes_too_big_are_errors() {
+  // NES 2.0, both ROM sizes in exponent-multiplier form
+  let header = |prg: u8, chr: u8| {
+    vec![
+      0x4E, 0x45, 0x53, 0x1A, prg, chr, 0x00, 0x08, 0x00, 0xFF, 00, 00, 00, 00, 00, 00,
+    ]
+  };
+  // 2^63 * 7
+  assert_eq!(
+    Rom::from_bytes(&header(63 << 2 | 0b11, 0)).unwrap_err(),
+    RomError::SizeOverflow
+  );
+  // each fits, not both
+  assert_eq!(
+    Rom::from_bytes(&header(63 << 2, 63 << 2)).unwrap_err(),
+    RomError::SizeOverflow
+  );
+  assert_eq!(
+    Rom::from_bytes(&header(40 << 2, 0)).unwrap_err(),
+    RomError::Truncated {
+      expected: 16 + (1 << 40) + 1,
+      actual: 16
+    }
+  );
+}
@@ -0,0 +1,109 @@
+use flemu_core::nes::bus::{Bus, Mem};
+use flemu_core::nes::cartridge::Rom;
+use flemu_core::nes::cheats::*;
+
+fn run_frame(bus: &mut Bus) {
+  let frame = bus.ppu.frame_count;
+  while bus.ppu.frame_count == frame {
+    bus.tick(1);
+  }
+}
+
+#[test]
+fn test_freeze_is_enforced_every_frame() {
+  let mut bus = Bus::with_rom(Rom::from_program(&[])).unwrap();
+  bus.cheats.freeze(0x0842, 9).unwrap();
+  bus.cheats.freeze(0x6000, 0x99).unwrap();
+  bus.mem_write(0x0042, 0);
+  bus.mem_write(0x6000, 0);
+
+  run_frame(&mut bus);
+  assert_eq!(bus.mem_read(0x0042), 9);
+  assert_eq!(bus.mem_read(0x6000), 0x99);
+
+  // between frames the game sees its own writes
+  bus.mem_write(0x0042, 3);
+  assert_eq!(bus.mem_read(0x0042), 3);
+  run_frame(&mut bus);
+  assert_eq!(bus.mem_read(0x0042), 9);
+
+  bus.cheats.unfreeze(0x0042);
+  bus.mem_write(0x0042, 3);
+  run_frame(&mut bus);
+  assert_eq!(bus.mem_read(0x0042), 3);
+  assert_eq!(bus.mem_read(0x6000), 0x99);
+}
+
+#[test]
+fn test_freeze_replaces_and_rejects() {
+  let mut cheats = Cheats::default();
+  cheats.freeze(0x0010, 1).unwrap();
+  // $0810 mirrors $0010
+  cheats.freeze(0x0810, 2).unwrap();
+  assert_eq!(
+    cheats.freezes(),
+    &[Freeze {
+      addr: 0x10,
+      value: 2
+    }]
+  );
+
+  assert_eq!(cheats.freeze(0x2000, 0), Err(CheatError::NotRam(0x2000)));
+  assert_eq!(
+    cheats.freeze(0x8000, 0).unwrap_err().to_string(),
+    "$8000 is not RAM, it can't be frozen"
+  );
+  cheats.clear();
+  assert!(cheats.freezes().is_empty());
+}
+
+#[test]
+fn test_game_genie_decoding() {
+  // Super Mario Bros., infinite lives
+  assert_eq!(
+    GameGenie::decode("sxiopo").unwrap(),
+    GameGenie {
+      code: "SXIOPO".to_string(),
+      addr: 0x91d9,
+      value: 0xad,
+      compare: None,
+    }
+  );
+  let code = GameGenie::decode("ZEXPYGLA").unwrap();
+  assert_eq!(
+    (code.addr, code.value, code.compare),
+    (0x94a7, 0x02, Some(0x03))
+  );
+
+  assert_eq!(
+    GameGenie::decode("SXIOP"),
+    Err(CheatError::BadGameGenie("SXIOP".to_string()))
+  );
+  assert_eq!(
+    GameGenie::decode("SXIOPB").unwrap_err().to_string(),
+    "SXIOPB is not a Game Genie code"
+  );
+}
+
+#[test]
+fn test_game_genie_patches_rom_reads() {
+  // LDA #$EA, the ROM has $EA at $8001
+  let mut bus = Bus::with_rom(Rom::from_program(&[0xa9, 0xea])).unwrap();
+  // $8001 reads $42
+  bus.cheats.add_game_genie("ZGAAPA").unwrap();
+  assert_eq!(bus.mem_read(0x8001), 0x42);
+  assert_eq!(bus.mem_peek(0x8001), 0x42);
+  assert_eq!(bus.mem_read(0x8000), 0xa9);
+
+  // the same, only while the ROM has $00 there; it replaces the first
+  bus.cheats.add_game_genie("ZGEAPAAA").unwrap();
+  assert_eq!(bus.cheats.game_genie_codes().len(), 1);
+  assert_eq!(bus.mem_read(0x8001), 0xea);
+
+  // only while the ROM has $EA there
+  bus.cheats.add_game_genie("ZGEAPEXT").unwrap();
+  assert_eq!(bus.mem_read(0x8001), 0x42);
+
+  bus.cheats.remove_game_genie("zgeapext");
+  assert_eq!(bus.mem_read(0x8001), 0xea);
+}
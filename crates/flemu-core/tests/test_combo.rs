@@ -0,0 +1,143 @@
+mod common;
+
+use common::counter;
+use flemu_core::nes::combo::*;
+use flemu_core::nes::joypad::{Joypad, JoypadButton};
+
+const HADOUKEN: &str = "D*2 RD*2 R*1 RB*3";
+
+#[test]
+fn test_text_round_trip() {
+  let combo = Combo::from_text(HADOUKEN).unwrap();
+  assert_eq!(combo.len(), 8);
+  assert_eq!(combo.frames[2], JoypadButton::DOWN | JoypadButton::RIGHT);
+  assert_eq!(combo.frames[7], JoypadButton::RIGHT | JoypadButton::B);
+  assert_eq!(combo.to_text(), HADOUKEN);
+  // letters in any order, runs split or not, come out the FM2 way
+  let combo = Combo::from_text(" .*1 AT*1  TA*2 ").unwrap();
+  assert_eq!(combo.to_text(), ".*1 TA*3");
+  assert!(Combo::from_text("").unwrap().is_empty());
+}
+
+#[test]
+fn test_bad_text_says_where() {
+  for (text, token) in &[
+    ("D*2 X*1", 2),
+    ("D", 1),
+    ("D*0", 1),
+    ("*3", 1),
+    ("D*2 R*y", 2),
+  ] {
+    assert_eq!(
+      Combo::from_text(text),
+      Err(ComboError::Syntax { token: *token }),
+      "{}",
+      text
+    );
+  }
+  let long = format!("A*{} B*1", MAX_FRAMES);
+  assert_eq!(Combo::from_text(&long), Err(ComboError::TooLong));
+}
+
+#[test]
+fn test_recorder_trims_the_wait_around_it() {
+  let mut pad = Joypad::new();
+  let mut recorder = ComboRecorder::new();
+  let held = [
+    JoypadButton::empty(),
+    JoypadButton::empty(),
+    JoypadButton::DOWN,
+    JoypadButton::empty(),
+    JoypadButton::A,
+    JoypadButton::empty(),
+    JoypadButton::empty(),
+  ];
+  for (frame, &buttons) in held.iter().enumerate() {
+    pad.set_frame(frame as u64);
+    pad.set_button_pressed_status(JoypadButton::all(), false);
+    pad.set_button_pressed_status(buttons, true);
+    recorder.record(&pad);
+  }
+  assert_eq!(recorder.finish().to_text(), "D*1 .*1 A*1");
+
+  // nothing pressed, nothing recorded
+  let mut recorder = ComboRecorder::new();
+  recorder.record(&Joypad::new());
+  assert!(recorder.is_empty());
+  assert!(recorder.finish().is_empty());
+}
+
+#[test]
+fn test_recorder_stops_at_the_limit() {
+  let mut pad = Joypad::new();
+  pad.set_button_pressed_status(JoypadButton::A, true);
+  let mut recorder = ComboRecorder::new();
+  for _ in 0..MAX_FRAMES + 10 {
+    recorder.record(&pad);
+  }
+  assert_eq!(recorder.len(), MAX_FRAMES);
+}
+
+#[test]
+fn test_playback_is_timed_by_frames() {
+  let combo = Combo::from_text("D*2 .*1 A*1").unwrap();
+  let mut pad = Joypad::new();
+  pad.set_frame(10);
+  pad.set_button_pressed_status(JoypadButton::B, true);
+  pad.play_combo(&combo);
+  let mut pressed = Vec::new();
+  for frame in 10..16 {
+    pad.set_frame(frame);
+    pressed.push(pad.buttons() - JoypadButton::B);
+    // the player's buttons stay pressed along with it
+    assert!(pad.buttons().contains(JoypadButton::B));
+  }
+  assert_eq!(
+    pressed,
+    vec![
+      JoypadButton::DOWN,
+      JoypadButton::DOWN,
+      JoypadButton::empty(),
+      JoypadButton::A,
+      JoypadButton::empty(),
+      JoypadButton::empty(),
+    ]
+  );
+  assert!(!pad.combo_playing());
+
+  // frames going back past the start, with a savestate, end it
+  pad.play_combo(&combo);
+  pad.set_frame(3);
+  assert!(!pad.combo_playing());
+  assert_eq!(pad.buttons(), JoypadButton::B);
+}
+
+#[test]
+fn test_combo_plays_like_holding_the_buttons() {
+  let held = [true, false, false, true, true, false, false];
+  let combo = Combo::from_text("A*1 .*2 A*2").unwrap();
+
+  // the player pressing A by hand on those frames, recorded
+  let mut cpu = counter();
+  let mut recorder = ComboRecorder::new();
+  let mut by_hand = Vec::new();
+  for &a in &held {
+    cpu.bus.joypads[0].set_button_pressed_status(JoypadButton::A, a);
+    recorder.record(&cpu.bus.joypads[0]);
+    cpu.run_frame();
+    by_hand.push(cpu.register_x);
+  }
+  assert_eq!(recorder.finish(), combo);
+
+  // one key starting the combo instead
+  let mut cpu = counter();
+  cpu.bus.joypads[0].play_combo(&combo);
+  let played: Vec<u8> = (0..held.len())
+    .map(|_| {
+      cpu.run_frame();
+      cpu.register_x
+    })
+    .collect();
+  assert_eq!(played, by_hand);
+  assert!(!cpu.bus.joypads[0].combo_playing());
+}
@@ -0,0 +1,659 @@
+use flemu_core::nes::asm::assemble;
+use flemu_core::nes::cpu::*;
+
+fn run(program: Vec<u8>) -> CPU {
+  let mut cpu = CPU::new();
+  cpu.load_and_run(program).unwrap();
+  cpu
+}
+
+#[test]
+fn test_load_store() {
+  let cpu = run(
+    assemble(
+      "
+        LDX #$11
+        LDY #$22
+        STX $10
+        STY $11
+        LDA $10
+        STA $0200,X
+        BRK
+      ",
+    )
+    .unwrap(),
+  );
+  assert_eq!(cpu.register_x, 0x11);
+  assert_eq!(cpu.register_y, 0x22);
+  assert_eq!(cpu.mem_peek(0x10), 0x11);
+  assert_eq!(cpu.mem_peek(0x11), 0x22);
+  assert_eq!(cpu.mem_peek(0x0211), 0x11);
+}
+
+#[test]
+fn test_indirect_addressing() {
+  let mut cpu = CPU::new();
+  let program = assemble(
+    "
+      LDX #$04
+      LDA ($00,X) ; pointer at $04
+      LDY #$01
+      EOR ($04),Y ; $0301
+      BRK
+    ",
+  );
+  cpu.load(program.unwrap()).unwrap();
+  cpu.mem_write_u16(0x04, 0x0300);
+  cpu.mem_write(0x0300, 0x0f);
+  cpu.mem_write(0x0301, 0xff);
+  cpu.run().unwrap();
+  assert_eq!(cpu.register_a, 0xf0);
+  assert!(cpu.status.contains(CpuFlags::NEGATIVE));
+}
+
+#[test]
+fn test_transfers() {
+  // LDA #$80, TAY, TAX, LDA #0, TXA, TSX
+  let cpu = run(vec![0xa9, 0x80, 0xa8, 0xaa, 0xa9, 0x00, 0x8a, 0xba, 0x00]);
+  assert_eq!(cpu.register_y, 0x80);
+  assert_eq!(cpu.register_a, 0x80);
+  assert_eq!(cpu.register_x, 0xfd);
+
+  // LDX #$40, TXS, LDY #$00, TYA
+  let cpu = run(vec![0xa2, 0x40, 0x9a, 0xa0, 0x00, 0x98, 0x00]);
+  assert_eq!(cpu.stack_pointer, 0x40);
+  assert_eq!(cpu.register_a, 0);
+  assert!(cpu.status.contains(CpuFlags::ZERO));
+}
+
+#[test]
+fn test_adc_carry_and_overflow() {
+  // LDA #$7f, ADC #$01 -> $80, V set, C clear
+  let cpu = run(vec![0xa9, 0x7f, 0x69, 0x01, 0x00]);
+  assert_eq!(cpu.register_a, 0x80);
+  assert!(cpu.status.contains(CpuFlags::OVERFLOW));
+  assert!(!cpu.status.contains(CpuFlags::CARRY));
+
+  // LDA #$ff, ADC #$02 -> $01, C set, V clear
+  let cpu = run(vec![0xa9, 0xff, 0x69, 0x02, 0x00]);
+  assert_eq!(cpu.register_a, 0x01);
+  assert!(cpu.status.contains(CpuFlags::CARRY));
+  assert!(!cpu.status.contains(CpuFlags::OVERFLOW));
+
+  // SEC, LDA #$01, ADC #$01 -> $03
+  let cpu = run(vec![0x38, 0xa9, 0x01, 0x69, 0x01, 0x00]);
+  assert_eq!(cpu.register_a, 0x03);
+}
+
+#[test]
+fn test_sbc_borrow() {
+  // SEC, LDA #$05, SBC #$03 -> $02, no borrow
+  let cpu = run(vec![0x38, 0xa9, 0x05, 0xe9, 0x03, 0x00]);
+  assert_eq!(cpu.register_a, 0x02);
+  assert!(cpu.status.contains(CpuFlags::CARRY));
+
+  // SEC, LDA #$03, SBC #$05 -> $fe, borrow
+  let cpu = run(vec![0x38, 0xa9, 0x03, 0xe9, 0x05, 0x00]);
+  assert_eq!(cpu.register_a, 0xfe);
+  assert!(!cpu.status.contains(CpuFlags::CARRY));
+  assert!(cpu.status.contains(CpuFlags::NEGATIVE));
+
+  // CLC, LDA #$05, SBC #$03 -> $01, carry clear subtracts one more
+  let cpu = run(vec![0x18, 0xa9, 0x05, 0xe9, 0x03, 0x00]);
+  assert_eq!(cpu.register_a, 0x01);
+}
+
+#[test]
+fn test_logic() {
+  // LDA #$f0, AND #$3c, ORA #$01, EOR #$ff
+  let cpu = run(vec![0xa9, 0xf0, 0x29, 0x3c, 0x09, 0x01, 0x49, 0xff, 0x00]);
+  assert_eq!(cpu.register_a, 0xce);
+  assert!(cpu.status.contains(CpuFlags::NEGATIVE));
+}
+
+#[test]
+fn test_shifts_and_rotates() {
+  // LDA #$81, ASL A -> $02 C=1, ROL A -> $05 C=0
+  let cpu = run(vec![0xa9, 0x81, 0x0a, 0x2a, 0x00]);
+  assert_eq!(cpu.register_a, 0x05);
+  assert!(!cpu.status.contains(CpuFlags::CARRY));
+
+  // LDA #$01, LSR A -> $00 C=1 Z=1, ROR A -> $80 C=0
+  let cpu = run(vec![0xa9, 0x01, 0x4a, 0x6a, 0x00]);
+  assert_eq!(cpu.register_a, 0x80);
+  assert!(!cpu.status.contains(CpuFlags::CARRY));
+  assert!(cpu.status.contains(CpuFlags::NEGATIVE));
+
+  // memory operands: LDA #$c0, STA $10, ASL $10, LSR $10, ROL $10, ROR $10
+  let cpu = run(vec![
+    0xa9, 0xc0, 0x85, 0x10, 0x06, 0x10, 0x46, 0x10, 0x26, 0x10, 0x66, 0x10, 0x00,
+  ]);
+  // $c0 -ASL-> $80 C=1 -LSR-> $40 C=0 -ROL-> $80 C=0 -ROR-> $40 C=0
+  assert_eq!(cpu.mem_peek(0x10), 0x40);
+  assert!(!cpu.status.contains(CpuFlags::CARRY));
+}
+
+#[test]
+fn test_increments_and_decrements() {
+  // LDX #0, DEX, LDY #$ff, INY, INC $10, INC $10, DEC $11
+  let cpu = run(vec![
+    0xa2, 0x00, 0xca, 0xa0, 0xff, 0xc8, 0xe6, 0x10, 0xe6, 0x10, 0xc6, 0x11, 0x88, 0x00,
+  ]);
+  assert_eq!(cpu.register_x, 0xff);
+  assert_eq!(cpu.register_y, 0xff);
+  assert_eq!(cpu.mem_peek(0x10), 0x02);
+  assert_eq!(cpu.mem_peek(0x11), 0xff);
+  assert!(cpu.status.contains(CpuFlags::NEGATIVE));
+}
+
+#[test]
+fn test_compares() {
+  // LDA #$10, CMP #$10
+  let cpu = run(vec![0xa9, 0x10, 0xc9, 0x10, 0x00]);
+  assert!(cpu.status.contains(CpuFlags::ZERO));
+  assert!(cpu.status.contains(CpuFlags::CARRY));
+
+  // LDX #$05, CPX #$06
+  let cpu = run(vec![0xa2, 0x05, 0xe0, 0x06, 0x00]);
+  assert!(!cpu.status.contains(CpuFlags::CARRY));
+  assert!(cpu.status.contains(CpuFlags::NEGATIVE));
+
+  // LDY #$07, CPY #$06
+  let cpu = run(vec![0xa0, 0x07, 0xc0, 0x06, 0x00]);
+  assert!(cpu.status.contains(CpuFlags::CARRY));
+  assert!(!cpu.status.contains(CpuFlags::ZERO));
+}
+
+#[test]
+fn test_branches() {
+  // count X down from 3 with a backwards BNE, bump Y each iteration
+  // LDX #3, loop: INY, DEX, BNE loop
+  let cpu = run(vec![0xa2, 0x03, 0xc8, 0xca, 0xd0, 0xfc, 0x00]);
+  assert_eq!(cpu.register_y, 3);
+
+  // taken forward branches skip the LDA #$ff
+  for branch in &[
+    vec![0x38, 0xb0],       // SEC, BCS
+    vec![0x18, 0x90],       // CLC, BCC
+    vec![0xa9, 0x00, 0xf0], // LDA #0, BEQ
+    vec![0xa9, 0x80, 0x30], // LDA #$80, BMI
+    vec![0xa9, 0x01, 0x10], // LDA #1, BPL
+    vec![0xb8, 0x50],       // CLV, BVC
+  ] {
+    let mut program = branch.clone();
+    program.extend(&[0x02, 0xa9, 0xff, 0x00]);
+    let cpu = run(program);
+    assert_ne!(cpu.register_a, 0xff);
+  }
+
+  // BVS after an overflowing ADC
+  let cpu = run(vec![0xa9, 0x7f, 0x69, 0x01, 0x70, 0x02, 0xa9, 0xff, 0x00]);
+  assert_eq!(cpu.register_a, 0x80);
+}
+
+#[test]
+fn test_jmp() {
+  // JMP $8005, LDA #$ff (skipped), LDA #1
+  let cpu = run(vec![0x4c, 0x05, 0x80, 0xa9, 0xff, 0xa9, 0x01, 0x00]);
+  assert_eq!(cpu.register_a, 0x01);
+
+  // JMP ($02ff) takes the high byte from $0200, not $0300
+  let mut cpu = CPU::new();
+  cpu
+    .load(vec![0x6c, 0xff, 0x02, 0x00, 0x00, 0xe8, 0x00])
+    .unwrap(); // ..., $8005: INX
+  cpu.mem_write(0x02ff, 0x05);
+  cpu.mem_write(0x0200, 0x80);
+  cpu.mem_write(0x0300, 0x40);
+  cpu.run().unwrap();
+  assert_eq!(cpu.register_x, 1);
+}
+
+#[test]
+fn test_jsr_rts() {
+  // JSR $8006, INX, BRK, sub: LDX #$41, RTS
+  let cpu = run(vec![0x20, 0x06, 0x80, 0xe8, 0x00, 0x00, 0xa2, 0x41, 0x60]);
+  assert_eq!(cpu.register_x, 0x42);
+  assert_eq!(cpu.stack_pointer, 0xfd);
+}
+
+#[test]
+fn test_stack_ops() {
+  // LDA #$42, PHA, LDA #0, PLA
+  let cpu = run(vec![0xa9, 0x42, 0x48, 0xa9, 0x00, 0x68, 0x00]);
+  assert_eq!(cpu.register_a, 0x42);
+  assert_eq!(cpu.stack_pointer, 0xfd);
+
+  // SEC, SED, PHP, CLC, CLD, PLP
+  let cpu = run(vec![0x38, 0xf8, 0x08, 0x18, 0xd8, 0x28, 0x00]);
+  assert!(cpu.status.contains(CpuFlags::CARRY));
+  assert!(cpu.status.contains(CpuFlags::DECIMAL_MODE));
+  assert!(!cpu.status.contains(CpuFlags::BREAK));
+  // PHP pushes with B set
+  assert_eq!(cpu.mem_peek(0x01fd) & 0b0011_0000, 0b0011_0000);
+}
+
+#[test]
+fn test_stack_wraps_within_page_one() {
+  // LDX #$00, TXS, LDA #$42, PHA, LDA #$00, PLA
+  let cpu = run(vec![
+    0xa2, 0x00, 0x9a, 0xa9, 0x42, 0x48, 0xa9, 0x00, 0x68, 0x00,
+  ]);
+  // the push went to $0100 and wrapped S to $ff, the pull wrapped back
+  assert_eq!(cpu.mem_peek(0x0100), 0x42);
+  assert_eq!(cpu.mem_peek(0x0200), 0x00);
+  assert_eq!(cpu.register_a, 0x42);
+  assert_eq!(cpu.stack_pointer, 0x00);
+}
+
+#[test]
+fn test_flag_ops() {
+  // SEC, SEI, SED, CLC, CLI
+  let cpu = run(vec![0x38, 0x78, 0xf8, 0x18, 0x58, 0x00]);
+  assert!(!cpu.status.contains(CpuFlags::CARRY));
+  assert!(!cpu.status.contains(CpuFlags::INTERRUPT_DISABLE));
+  assert!(cpu.status.contains(CpuFlags::DECIMAL_MODE));
+}
+
+#[test]
+fn test_bit() {
+  let mut cpu = CPU::new();
+  // LDA #$01, BIT $10
+  cpu.load(vec![0xa9, 0x01, 0x24, 0x10, 0x00]).unwrap();
+  cpu.mem_write(0x10, 0xc0);
+  cpu.run().unwrap();
+  assert!(cpu.status.contains(CpuFlags::ZERO));
+  assert!(cpu.status.contains(CpuFlags::NEGATIVE));
+  assert!(cpu.status.contains(CpuFlags::OVERFLOW));
+}
+
+#[test]
+fn test_rti() {
+  let mut cpu = CPU::new();
+  // push return address $8010 and status, then RTI
+  cpu
+    .load(vec![
+      0xa9, 0x80, 0x48, // LDA #$80, PHA
+      0xa9, 0x10, 0x48, // LDA #$10, PHA
+      0xa9, 0xc3, 0x48, // LDA #$c3, PHA
+      0x40, // RTI
+      0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+      0xe8, // $8010: INX
+    ])
+    .unwrap();
+  cpu.run().unwrap();
+  assert_eq!(cpu.register_x, 1);
+  assert!(cpu.status.contains(CpuFlags::CARRY));
+  assert!(cpu.status.contains(CpuFlags::OVERFLOW));
+}
+
+#[test]
+fn test_unofficial_opcodes() {
+  let mut cpu = CPU::new();
+  cpu
+    .load(vec![
+      0xa7, 0x10, // LAX $10 -> A = X = $81
+      0x87, 0x11, // SAX $11 -> $81 & $81
+      0xc7, 0x12, // DCP $12 -> $12 = $80, compare with A
+      0xe7, 0x13, // ISB $13 -> $13 = $01, A -= $01 (+ borrow)
+      0x07, 0x14, // SLO $14 -> $14 = $02, A |= $02
+      0x1a, 0x04, 0xff, 0x0c, 0xff, 0xff, // NOP, NOP $ff, NOP $ffff
+      0x00,
+    ])
+    .unwrap();
+  cpu.mem_write(0x10, 0x81);
+  cpu.mem_write(0x12, 0x81);
+  cpu.mem_write(0x14, 0x01);
+  cpu.run().unwrap();
+
+  assert_eq!(cpu.register_x, 0x81);
+  assert_eq!(cpu.mem_read(0x11), 0x81);
+  assert_eq!(cpu.mem_read(0x12), 0x80);
+  assert_eq!(cpu.mem_read(0x13), 0x01);
+  assert_eq!(cpu.mem_read(0x14), 0x02);
+  // DCP left carry set (A >= $80), so ISB gives $81 - 1 = $80, SLO ors in $02
+  assert_eq!(cpu.register_a, 0x82);
+}
+
+#[test]
+fn test_rla_sre_rra() {
+  let mut cpu = CPU::new();
+  cpu
+    .load(vec![
+      0xa9, 0xff, // LDA #$ff
+      0x27, 0x10, // RLA $10 -> $10 = $80, A = $80
+      0x47, 0x11, // SRE $11 -> $11 = $01, A = $81, C = 0
+      0x67, 0x12, // RRA $12 -> $12 = $02, A = $83
+      0x00,
+    ])
+    .unwrap();
+  cpu.mem_write(0x10, 0x40);
+  cpu.mem_write(0x11, 0x02);
+  cpu.mem_write(0x12, 0x04);
+  cpu.run().unwrap();
+
+  assert_eq!(cpu.mem_read(0x10), 0x80);
+  assert_eq!(cpu.mem_read(0x11), 0x01);
+  assert_eq!(cpu.mem_read(0x12), 0x02);
+  assert_eq!(cpu.register_a, 0x83);
+}
+
+#[test]
+fn test_indexed_read_across_a_page_reads_twice() {
+  #[rustfmt::skip]
+  let cpu = run(vec![
+    0xa9, 0x20, 0x8d, 0x06, 0x20, // $2006 = $2000
+    0xa9, 0x00, 0x8d, 0x06, 0x20,
+    0xa9, 0x11, 0x8d, 0x07, 0x20, // $11 $22 $33 into the nametable
+    0xa9, 0x22, 0x8d, 0x07, 0x20,
+    0xa9, 0x33, 0x8d, 0x07, 0x20,
+    0xa9, 0x20, 0x8d, 0x06, 0x20, // back to $2000
+    0xa9, 0x00, 0x8d, 0x06, 0x20,
+    0xad, 0x07, 0x20,             // LDA $2007, fills the read buffer
+    0xa2, 0x10,                   // LDX #$10
+    0xbd, 0xf7, 0x20,             // LDA $20F7,X: $2007 first, then $2107
+    0x00,
+  ]);
+  // the first try at $2007 took the $11 and moved on
+  assert_eq!(cpu.register_a, 0x22);
+}
+
+#[test]
+fn test_unofficial_opcode_in_strict_mode() {
+  let mut cpu = CPU::new();
+  cpu.strict = true;
+  let fault = Fault::UnofficialOpcode {
+    pc: 0x8001,
+    code: 0x1a,
+  };
+  assert_eq!(
+    cpu.load_and_run(vec![0xe8, 0x1a, 0xe8, 0x00]),
+    Err(EmuError::Fault(fault))
+  );
+  assert_eq!(cpu.fault(), Some(fault));
+  assert_eq!(cpu.program_counter, 0x8001);
+  assert_eq!(cpu.register_x, 1);
+  assert!(cpu.done());
+}
+
+#[test]
+fn test_unknown_opcode_stops_the_cpu() {
+  let mut cpu = CPU::new();
+  // JAM
+  let fault = Fault::UnknownOpcode {
+    pc: 0x8000,
+    code: 0x02,
+  };
+  assert_eq!(
+    cpu.load_and_run(vec![0x02, 0x00]),
+    Err(EmuError::Fault(fault))
+  );
+  assert_eq!(cpu.fault(), Some(fault));
+
+  cpu.reset();
+  assert_eq!(cpu.fault(), None);
+}
+
+#[test]
+fn test_decimal_flag_is_stored_but_ignored() {
+  // SED, LDA #$09, CLC, ADC #$01
+  let cpu = run(vec![0xf8, 0xa9, 0x09, 0x18, 0x69, 0x01, 0x00]);
+  assert_eq!(cpu.register_a, 0x0a);
+  assert!(cpu.status.contains(CpuFlags::DECIMAL_MODE));
+
+  // SED, PHP, CLD, PLA -> D survives the trip through the stack
+  let cpu = run(vec![0xf8, 0x08, 0xd8, 0x68, 0x00]);
+  assert_eq!(cpu.register_a & 0b0000_1000, 0b0000_1000);
+  assert!(!cpu.status.contains(CpuFlags::DECIMAL_MODE));
+
+  // LDA #$08, PHA, PLP -> D comes back from the stack
+  let cpu = run(vec![0xa9, 0x08, 0x48, 0x28, 0x00]);
+  assert!(cpu.status.contains(CpuFlags::DECIMAL_MODE));
+}
+
+fn run_decimal(program: Vec<u8>) -> CPU {
+  let mut cpu = CPU::new();
+  cpu.decimal_enabled = true;
+  cpu.load(program).unwrap();
+  cpu.run().unwrap();
+  cpu
+}
+
+#[test]
+fn test_pure_6502_decimal_arithmetic() {
+  // SED, CLC, LDA #$09, ADC #$01
+  let cpu = run_decimal(vec![0xf8, 0x18, 0xa9, 0x09, 0x69, 0x01, 0x00]);
+  assert_eq!(cpu.register_a, 0x10);
+  assert!(!cpu.status.contains(CpuFlags::CARRY));
+
+  // SED, CLC, LDA #$99, ADC #$01
+  let cpu = run_decimal(vec![0xf8, 0x18, 0xa9, 0x99, 0x69, 0x01, 0x00]);
+  assert_eq!(cpu.register_a, 0x00);
+  assert!(cpu.status.contains(CpuFlags::CARRY));
+
+  // SED, SEC, LDA #$10, SBC #$01
+  let cpu = run_decimal(vec![0xf8, 0x38, 0xa9, 0x10, 0xe9, 0x01, 0x00]);
+  assert_eq!(cpu.register_a, 0x09);
+  assert!(cpu.status.contains(CpuFlags::CARRY));
+
+  // SED, SEC, LDA #$00, SBC #$01 -> $99 with borrow
+  let cpu = run_decimal(vec![0xf8, 0x38, 0xa9, 0x00, 0xe9, 0x01, 0x00]);
+  assert_eq!(cpu.register_a, 0x99);
+  assert!(!cpu.status.contains(CpuFlags::CARRY));
+
+  // CLD falls back to binary
+  let cpu = run_decimal(vec![0xd8, 0x18, 0xa9, 0x09, 0x69, 0x01, 0x00]);
+  assert_eq!(cpu.register_a, 0x0a);
+}
+
+#[test]
+fn test_nmi() {
+  use flemu_core::nes::cartridge::Rom;
+
+  let mut rom = Rom::from_program(&[
+    0xa9, 0x80, // LDA #$80
+    0x8d, 0x00, 0x20, // STA $2000
+    0x4c, 0x05, 0x80, // JMP $8005
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+    0xe6, 0x10, // $8010: INC $10
+    0x40, // RTI
+  ]);
+  rom.prg_rom[0x7ffa] = 0x10;
+  rom.prg_rom[0x7ffb] = 0x80;
+  let mut cpu = CPU::new();
+  cpu.load_rom(rom).unwrap();
+
+  while cpu.bus.ppu.frame_count < 1 {
+    cpu.step_run(|_| {});
+  }
+  let sp = cpu.stack_pointer;
+  cpu.step_run(|_| {});
+  assert_eq!(cpu.program_counter, 0x8010);
+  assert!(cpu.status.contains(CpuFlags::INTERRUPT_DISABLE));
+  assert_eq!(cpu.stack_pointer, sp.wrapping_sub(3));
+  // pushed status has B clear
+  let pushed = cpu.mem_read(0x0100 + sp as u16 - 2);
+  assert_eq!(pushed & 0b0011_0000, 0b0010_0000);
+  assert_eq!(cpu.mem_read_u16(0x0100 + sp as u16 - 1), 0x8005);
+
+  while cpu.bus.ppu.frame_count < 3 {
+    cpu.step_run(|_| {});
+  }
+  assert_eq!(cpu.mem_read(0x10), 2);
+}
+
+#[test]
+fn test_brk_traps_through_the_irq_vector() {
+  use flemu_core::nes::cartridge::Rom;
+
+  let mut rom = Rom::from_program(&[
+    0x00, 0xff, // BRK and its padding byte
+    0xa0, 0x07, // LDY #$07
+    0x4c, 0x04, 0x80, // JMP $8004
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, //
+    0xa2, 0x42, // $8010: LDX #$42
+    0x40, // RTI
+  ]);
+  rom.prg_rom[0x7ffe] = 0x10;
+  rom.prg_rom[0x7fff] = 0x80;
+  let mut cpu = CPU::new();
+  cpu.load_rom(rom).unwrap();
+  cpu.halt_on_brk = false;
+
+  let sp = cpu.stack_pointer;
+  cpu.step_run(|_| {});
+  assert_eq!(cpu.program_counter, 0x8010);
+  assert!(cpu.status.contains(CpuFlags::INTERRUPT_DISABLE));
+  assert_eq!(cpu.cycles, 7);
+  // PC+2 and the status with B set
+  assert_eq!(cpu.mem_read_u16(0x0100 + sp as u16 - 1), 0x8002);
+  assert_eq!(
+    cpu.mem_read(0x0100 + sp as u16 - 2) & 0b0011_0000,
+    0b0011_0000
+  );
+
+  for _ in 0..3 {
+    cpu.step_run(|_| {});
+  }
+  assert_eq!(cpu.register_x, 0x42);
+  assert_eq!(cpu.register_y, 0x07);
+  assert_eq!(cpu.stack_pointer, sp);
+  assert!(!cpu.done());
+}
+
+struct IrqBus {
+  memory: Vec<u8>,
+  irq: bool,
+}
+
+impl Mem for IrqBus {
+  fn mem_read(&mut self, addr: u16) -> u8 {
+    self.memory[addr as usize]
+  }
+
+  fn mem_write(&mut self, addr: u16, data: u8) {
+    self.memory[addr as usize] = data;
+  }
+
+  fn mem_peek(&self, addr: u16) -> u8 {
+    self.memory[addr as usize]
+  }
+
+  fn poll_irq_status(&self) -> bool {
+    self.irq
+  }
+}
+
+#[test]
+fn test_irq_honors_the_interrupt_disable_flag() {
+  let mut memory = vec![0; 0x10000];
+  // SEI, NOP, CLI, NOP...
+  memory[0x8000..0x8004].copy_from_slice(&[0x78, 0xea, 0x58, 0xea]);
+  // handler: INC $10, RTI
+  memory[0x9000..0x9003].copy_from_slice(&[0xe6, 0x10, 0x40]);
+  memory[0xfffe] = 0x00;
+  memory[0xffff] = 0x90;
+  let mut cpu = CPU::with_bus(IrqBus { memory, irq: false });
+  cpu.program_counter = 0x8000;
+
+  cpu.step_run(|_| {});
+  cpu.bus.irq = true;
+  cpu.step_run(|_| {});
+  assert_eq!(cpu.program_counter, 0x8002);
+
+  cpu.step_run(|_| {});
+  cpu.step_run(|_| {});
+  assert_eq!(cpu.program_counter, 0x9000);
+  // B clear on the pushed status
+  assert_eq!(cpu.mem_peek(0x01fb) & 0b0011_0000, 0b0010_0000);
+
+  cpu.bus.irq = false;
+  cpu.step_run(|_| {});
+  cpu.step_run(|_| {});
+  assert_eq!(cpu.program_counter, 0x8003);
+  assert_eq!(cpu.mem_peek(0x10), 1);
+  assert!(!cpu.status.contains(CpuFlags::INTERRUPT_DISABLE));
+}
+
+#[test]
+fn test_program_counter_wraps_around() {
+  let mut memory = vec![0; 0x10000];
+  // LDA #$42 at $FFFF, its operand at $0000; then JSR $1234 at $FFFE
+  memory[0xffff] = 0xa9;
+  memory[0x0000] = 0x42;
+  memory[0x0001] = 0xea;
+  let mut cpu = CPU::with_bus(IrqBus { memory, irq: false });
+  cpu.halt_on_brk = false;
+  cpu.program_counter = 0xffff;
+  assert_eq!(cpu.step(), Ok(Some(2)));
+  assert_eq!(cpu.register_a, 0x42);
+  assert_eq!(cpu.program_counter, 0x0001);
+
+  cpu.bus.memory[0xfffe] = 0x20;
+  cpu.bus.memory[0xffff] = 0x34;
+  cpu.bus.memory[0x0000] = 0x12;
+  cpu.program_counter = 0xfffe;
+  assert_eq!(cpu.step(), Ok(Some(6)));
+  assert_eq!(cpu.program_counter, 0x1234);
+  // RTS comes back past $FFFF
+  cpu.bus.memory[0x1234] = 0x60;
+  cpu.step().unwrap();
+  assert_eq!(cpu.program_counter, 0x0001);
+}
+
+fn step_cycles(program: Vec<u8>, x: u8, steps: usize) -> Vec<u16> {
+  let mut cpu = CPU::new();
+  cpu.load(program).unwrap();
+  cpu.register_x = x;
+  (0..steps).map(|_| cpu.step().unwrap().unwrap()).collect()
+}
+
+#[test]
+fn test_step_returns_cycles() {
+  let mut cpu = CPU::new();
+  cpu.load(vec![0xa9, 0x01, 0xe8, 0x00]).unwrap();
+  assert_eq!(cpu.step(), Ok(Some(2)));
+  assert_eq!(cpu.step(), Ok(Some(2)));
+  assert_eq!(cpu.cycles, 4);
+  // BRK ends the program
+  assert_eq!(cpu.step(), Ok(None));
+}
+
+#[test]
+fn test_page_cross_penalty() {
+  // LDA $02f0,X
+  assert_eq!(step_cycles(vec![0xbd, 0xf0, 0x02], 0x0f, 1), vec![4]);
+  assert_eq!(step_cycles(vec![0xbd, 0xf0, 0x02], 0x10, 1), vec![5]);
+  // STA $02f0,X always takes 5
+  assert_eq!(step_cycles(vec![0x9d, 0xf0, 0x02], 0x0f, 1), vec![5]);
+  assert_eq!(step_cycles(vec![0x9d, 0xf0, 0x02], 0x10, 1), vec![5]);
+  // unofficial reads pay it too: *NOP $02f0,X
+  assert_eq!(step_cycles(vec![0x1c, 0xf0, 0x02], 0x0f, 1), vec![4]);
+  assert_eq!(step_cycles(vec![0x1c, 0xf0, 0x02], 0x10, 1), vec![5]);
+
+  // LDY #$10, LDA ($10),Y with $10 pointing at $02f0
+  let mut cpu = CPU::new();
+  cpu.mem_write_u16(0x10, 0x02f0);
+  cpu.load(vec![0xa0, 0x10, 0xb1, 0x10]).unwrap();
+  cpu.step().unwrap();
+  assert_eq!(cpu.step(), Ok(Some(6)));
+}
+
+#[test]
+fn test_branch_penalties() {
+  // LDX #$01 sets Z=0, then BNE +2
+  let program = vec![0xa2, 0x01, 0xd0, 0x02];
+  assert_eq!(step_cycles(program, 0, 2), vec![2, 3]);
+  // BEQ not taken
+  assert_eq!(step_cycles(vec![0xa2, 0x01, 0xf0, 0x02], 0, 2), vec![2, 2]);
+
+  // a taken BNE at $80fd lands in the next page
+  let mut program = vec![0xea; 0xfd];
+  program.extend_from_slice(&[0xd0, 0x02]);
+  let mut cpu = CPU::new();
+  cpu.load(program).unwrap();
+  cpu.program_counter = 0x80fd;
+  cpu.status.remove(CpuFlags::ZERO);
+  assert_eq!(cpu.step(), Ok(Some(4)));
+  assert_eq!(cpu.program_counter, 0x8101);
+}
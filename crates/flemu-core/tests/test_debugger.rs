@@ -0,0 +1,208 @@
+use flemu_core::nes::bus::{Bus, Mem};
+use flemu_core::nes::cartridge::*;
+use flemu_core::nes::cpu::CPU;
+use flemu_core::nes::debugger::*;
+
+// UxROM, 8 banks of 16KB, the last fixed at $C000
+fn uxrom() -> Bus {
+  let mut rom = Rom::from_program(&[]);
+  rom.prg_rom = vec![0; 8 * PRG_ROM_PAGE_SIZE];
+  rom.info.mapper = 2;
+  Bus::with_rom(rom).unwrap()
+}
+
+#[test]
+fn test_locations_follow_bank_switches() {
+  let mut bus = uxrom();
+  assert_eq!(
+    Location::resolve(&bus, 0x8123),
+    Location::Prg {
+      bank: 0,
+      offset: 0x0123
+    }
+  );
+  assert_eq!(
+    Location::resolve(&bus, 0xc123),
+    Location::Prg {
+      bank: 7,
+      offset: 0x0123
+    }
+  );
+  assert_eq!(Location::resolve(&bus, 0x0300), Location::Cpu(0x0300));
+
+  let mut breakpoints = Breakpoints::default();
+  breakpoints.add(Location::Prg {
+    bank: 3,
+    offset: 0x0123,
+  });
+  assert!(!breakpoints.hit(&bus, 0x8123));
+  bus.mem_write(0x8000, 3);
+  assert!(breakpoints.hit(&bus, 0x8123));
+  assert!(!breakpoints.hit(&bus, 0xc123));
+  breakpoints.remove(Location::Prg {
+    bank: 3,
+    offset: 0x0123,
+  });
+  assert!(!breakpoints.hit(&bus, 0x8123));
+}
+
+#[test]
+fn test_symbols() {
+  let mut bus = uxrom();
+  let mut symbols = Symbols::default();
+  symbols.add(Location::Prg { bank: 1, offset: 0 }, "load_level");
+  symbols.add(Location::Cpu(0x0010), "frame_counter");
+  assert_eq!(symbols.lookup(&bus, 0x8000), None);
+  bus.mem_write(0x8000, 1);
+  assert_eq!(symbols.lookup(&bus, 0x8000), Some("load_level"));
+  assert_eq!(symbols.lookup(&bus, 0x0010), Some("frame_counter"));
+
+  assert_eq!(
+    Location::Prg {
+      bank: 1,
+      offset: 0x2a
+    }
+    .to_string(),
+    "01:002A"
+  );
+  assert_eq!(Location::Cpu(0x0010).to_string(), "$0010");
+}
+
+// LDX #0; loop: JSR store; INX; JMP loop; store: STX $10; RTS
+#[rustfmt::skip]
+const LOOP: [u8; 13] = [
+  0xa2, 0x00,
+  0x20, 0x0a, 0x80,
+  0xe8,
+  0x4c, 0x02, 0x80,
+  0x00,
+  0x86, 0x10,
+  0x60,
+];
+
+fn looping() -> CPU {
+  let mut cpu = CPU::new();
+  cpu.load(LOOP.to_vec()).unwrap();
+  cpu
+}
+
+fn step(cpu: &mut CPU) -> bool {
+  cpu.step_instruction()
+}
+
+#[test]
+fn test_conditional_breakpoints() {
+  let mut cpu = looping();
+  let store = Location::resolve(&cpu.bus, 0x800a);
+  let mut breakpoints = Breakpoints::default();
+  breakpoints.add_conditional(
+    store,
+    Condition {
+      register: Register::X,
+      comparison: Comparison::Equal,
+      value: 3,
+    },
+  );
+  assert!(breakpoints.hit(&cpu.bus, 0x800a));
+
+  let stop = run_until(&mut cpu, &breakpoints, 1000, step, |_| false);
+  assert_eq!(stop, StopReason::Breakpoint { pc: 0x800a });
+  assert_eq!(cpu.register_x, 3);
+  // continuing doesn't stop at it again until it holds again
+  assert_eq!(
+    run_until(&mut cpu, &breakpoints, 1000, step, |_| false),
+    StopReason::Limit
+  );
+
+  breakpoints.add(store);
+  assert_eq!(
+    run_until(&mut cpu, &breakpoints, 1000, step, |_| false),
+    StopReason::Breakpoint { pc: 0x800a }
+  );
+  breakpoints.remove(store);
+  assert!(breakpoints.is_empty());
+
+  assert_eq!(Register::from_id("sp"), Some(Register::Sp));
+  assert_eq!(Comparison::from_id(">="), Some(Comparison::GreaterOrEqual));
+  assert!(Comparison::Less.holds(1, 2));
+  assert!(!Comparison::Greater.holds(2, 2));
+}
+
+#[test]
+fn test_watchpoints() {
+  let mut cpu = looping();
+  let breakpoints = Breakpoints::default();
+  cpu.bus.watchpoints.add(Watchpoint {
+    start: 0x0010,
+    end: 0x0010,
+    read: true,
+    write: false,
+  });
+  assert_eq!(
+    run_until(&mut cpu, &breakpoints, 100, step, |_| false),
+    StopReason::Limit
+  );
+
+  cpu.bus.watchpoints.remove(0x0010, 0x0010);
+  cpu.bus.watchpoints.add(Watchpoint {
+    start: 0x0000,
+    end: 0x00ff,
+    read: false,
+    write: true,
+  });
+  let x = cpu.register_x;
+  let stop = run_until(&mut cpu, &breakpoints, 100, step, |_| false);
+  assert_eq!(
+    stop,
+    StopReason::Watchpoint(WatchHit {
+      addr: 0x0010,
+      value: x,
+      access: Access::Write,
+    })
+  );
+  // stopped after the STX
+  assert_eq!(cpu.program_counter, 0x800c);
+}
+
+#[test]
+fn test_step_over_runs_the_subroutine() {
+  let mut cpu = looping();
+  let breakpoints = Breakpoints::default();
+  assert_eq!(
+    step_over(&mut cpu, &breakpoints, 100, step),
+    StopReason::Done
+  );
+  assert_eq!(cpu.program_counter, 0x8002);
+
+  cpu.mem_write(0x10, 0xff);
+  assert_eq!(
+    step_over(&mut cpu, &breakpoints, 100, step),
+    StopReason::Done
+  );
+  assert_eq!(cpu.program_counter, 0x8005);
+  assert_eq!(cpu.mem_read(0x10), 0);
+
+  // a breakpoint inside stops it, running out of instructions too
+  let mut breakpoints = Breakpoints::default();
+  breakpoints.add(Location::resolve(&cpu.bus, 0x800c));
+  cpu.program_counter = 0x8002;
+  assert_eq!(
+    step_over(&mut cpu, &breakpoints, 100, step),
+    StopReason::Breakpoint { pc: 0x800c }
+  );
+  cpu.program_counter = 0x8002;
+  assert_eq!(
+    step_over(&mut cpu, &Breakpoints::default(), 2, step),
+    StopReason::Limit
+  );
+}
+
+#[test]
+fn test_run_until_reports_a_halt() {
+  let mut cpu = CPU::new();
+  cpu.load(vec![0xe8, 0x00]).unwrap();
+  assert_eq!(
+    run_until(&mut cpu, &Breakpoints::default(), 100, step, |_| false),
+    StopReason::Halted
+  );
+}
@@ -0,0 +1,170 @@
+mod common;
+
+use flemu_core::nes::bus::Mem;
+use flemu_core::nes::cpu::CPU;
+use flemu_core::nes::desync::*;
+use flemu_core::nes::savestate::{StateError, StateWriter};
+
+fn console() -> CPU {
+  common::console("loop: INX\n JMP loop")
+}
+
+fn state(cpu: &CPU) -> Vec<u8> {
+  let mut w = StateWriter::new(cpu.bus.cartridge_checksum());
+  cpu.save_state(&mut w);
+  w.finish()
+}
+
+#[test]
+fn test_state_hash_covers_the_whole_machine() {
+  let mut a = console();
+  let mut b = console();
+  a.run_frame();
+  b.run_frame();
+  assert_eq!(state_hash(&a), state_hash(&b));
+
+  // a frame later
+  let mut later = a.clone();
+  later.run_frame();
+  assert_ne!(state_hash(&later), state_hash(&a));
+  // nothing on screen or in RAM, only the APU
+  b.bus.mem_write(0x4000, 0x3F);
+  assert_ne!(state_hash(&b), state_hash(&a));
+}
+
+#[test]
+fn test_trace_finds_the_first_divergent_frame() {
+  let (mut a, mut b) = (console(), console());
+  let (mut trace_a, mut trace_b) = (ChecksumTrace::new(), ChecksumTrace::new());
+  for frame in 0..5 {
+    if frame == 3 {
+      b.bus.mem_write(0x0010, 1);
+    }
+    trace_a.record(&a);
+    trace_b.record(&b);
+    a.run_frame();
+    b.run_frame();
+  }
+  assert_eq!(trace_a.len(), 5);
+  assert_eq!(
+    trace_a.iter().map(|(frame, _)| frame).collect::<Vec<_>>(),
+    vec![0, 1, 2, 3, 4]
+  );
+  assert_eq!(trace_a.first_divergence(&trace_b), Some(3));
+  assert_eq!(trace_a.first_divergence(&trace_a.clone()), None);
+
+  // only where both have frames
+  let mut short = ChecksumTrace::new();
+  short.insert(1, trace_b.get(1).unwrap());
+  short.insert(9, 0);
+  assert_eq!(trace_a.first_divergence(&short), None);
+}
+
+#[test]
+fn test_trace_text() {
+  let mut trace = ChecksumTrace::new();
+  trace.insert(60, 0x0123_4567_89ab_cdef);
+  trace.insert(61, 1);
+  let text = trace.to_text();
+  assert_eq!(text, "60\t0123456789abcdef\n61\t0000000000000001\n");
+  assert_eq!(ChecksumTrace::from_text(&text).unwrap(), trace);
+
+  for bad in &["60 0123", "x\t01", "60\tnothex", "60"] {
+    assert_eq!(
+      ChecksumTrace::from_text(&format!("\n{}", bad)),
+      Err(ParseTraceError { line: 2 }),
+      "{}",
+      bad
+    );
+  }
+}
+
+#[test]
+fn test_diff_names_the_subsystems() {
+  let mut a = console();
+  a.run_frame();
+  let mut b = a.clone();
+  assert!(diff(&a, &b).is_empty());
+
+  b.bus.mem_write(0x0123, 0xAA);
+  b.bus.mem_write(0x0124, 0xAA);
+  b.bus.ppu.vram[0x400] = 0x55;
+  b.bus.ppu.oam_data[7] = 0x10;
+  assert_eq!(
+    diff(&a, &b),
+    vec![
+      Difference {
+        subsystem: Subsystem::Ram,
+        first: 0x123,
+        count: 2,
+      },
+      Difference {
+        subsystem: Subsystem::Vram,
+        first: 0x400,
+        count: 1,
+      },
+      Difference {
+        subsystem: Subsystem::Oam,
+        first: 7,
+        count: 1,
+      },
+      // the writes left $AA on the open bus
+      Difference {
+        subsystem: Subsystem::Bus,
+        first: 4,
+        count: 1,
+      },
+    ]
+  );
+
+  let mut c = a.clone();
+  c.register_x ^= 1;
+  c.bus.mem_write(0x4001, 0x88);
+  let found: Vec<Subsystem> = diff(&a, &c).iter().map(|d| d.subsystem).collect();
+  assert_eq!(found, vec![Subsystem::Cpu, Subsystem::Apu, Subsystem::Bus]);
+
+  let mut d = a.clone();
+  d.rng.next_u64();
+  let found: Vec<Subsystem> = diff(&a, &d).iter().map(|d| d.subsystem).collect();
+  assert_eq!(found, vec![Subsystem::Rng]);
+}
+
+#[test]
+fn test_diff_states() {
+  let mut cpu = console();
+  let before = state(&cpu);
+  cpu.run_frame();
+  let after = state(&cpu);
+  let found: Vec<Subsystem> = diff_states(&cpu, &before, &after)
+    .unwrap()
+    .iter()
+    .map(|d| d.subsystem)
+    .collect();
+  assert!(found.contains(&Subsystem::Cpu) && found.contains(&Subsystem::Ppu));
+  assert!(diff_states(&cpu, &after, &after).unwrap().is_empty());
+  assert_eq!(
+    diff_states(&cpu, &after, b"junk"),
+    Err(StateError::NotAState)
+  );
+}
+
+#[test]
+fn test_subsystem_ids() {
+  let ids: Vec<&str> = Subsystem::ALL.iter().map(Subsystem::id).collect();
+  assert_eq!(
+    ids,
+    vec![
+      "cpu",
+      "ram",
+      "ppu",
+      "vram",
+      "palette",
+      "oam",
+      "apu",
+      "controllers",
+      "mapper",
+      "bus",
+      "rng"
+    ]
+  );
+}
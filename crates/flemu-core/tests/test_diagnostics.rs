@@ -0,0 +1,106 @@
+use flemu_core::nes::bus::Mem;
+use flemu_core::nes::cpu::*;
+use flemu_core::nes::diagnostics::*;
+
+#[test]
+fn test_trace_log_keeps_the_last_entries_oldest_first() {
+  let mut log = TraceLog::new();
+  let mut cpu = CPU::new();
+  for i in 0..(TRACE_LINES + 3) {
+    cpu.register_a = i as u8;
+    log.push(TraceEntry {
+      code: 0xea,
+      state: cpu.state(),
+    });
+  }
+
+  let a: Vec<u8> = log.iter().map(|entry| entry.state.a).collect();
+  assert_eq!(a.len(), TRACE_LINES);
+  assert_eq!(a[0], 3);
+  assert_eq!(*a.last().unwrap(), (TRACE_LINES + 2) as u8);
+}
+
+#[test]
+fn test_trace_line() {
+  let mut cpu = CPU::new();
+  cpu.load(vec![0x00]).unwrap();
+  let entry = TraceEntry {
+    code: 0x4c,
+    state: cpu.state(),
+  };
+  assert_eq!(
+    entry.line(),
+    "8000  4C JMP  A:00 X:00 Y:00 P:24 SP:FD CYC:0"
+  );
+}
+
+#[test]
+fn test_core_dump_after_fault() {
+  let mut cpu = CPU::new();
+  cpu.strict = true;
+  // LDA #$42, STA $10, NOP (unofficial)
+  let result = cpu.load_and_run(vec![0xa9, 0x42, 0x85, 0x10, 0x1a, 0x00]);
+  assert_eq!(
+    result,
+    Err(EmuError::Fault(Fault::UnofficialOpcode {
+      pc: 0x8004,
+      code: 0x1a
+    }))
+  );
+
+  let dump = CoreDump::capture(&cpu, 12);
+  assert_eq!(dump.reason, "Unofficial OpCode 1a at 8004 in strict mode");
+  assert_eq!(dump.frame, 12);
+  assert_eq!(dump.state.pc, 0x8004);
+  assert_eq!(dump.trace.len(), 3);
+  assert!(dump.trace[0].starts_with("8000  A9 LDA"));
+  assert!(dump.trace[2].starts_with("8004  1A *NOP"));
+  assert_eq!(dump.ram.len(), 0x800);
+  assert_eq!(dump.ram[0x10], 0x42);
+
+  let text = dump.to_text();
+  assert!(text.contains("reason: Unofficial OpCode 1a"));
+  assert!(text.contains("frame: 12"));
+  assert!(text.contains("0010: 42 00"));
+}
+
+#[test]
+fn test_nestest_log() {
+  let mut cpu = CPU::new();
+  #[rustfmt::skip]
+  cpu.load(vec![
+    0xa2, 0x02,       // LDX #$02
+    0x86, 0x10,       // STX $10
+    0xb5, 0x0e,       // LDA $0E,X
+    0x9d, 0x00, 0x03, // STA $0300,X
+    0xa1, 0x0e,       // LDA ($0E,X)
+    0x4a,             // LSR A
+    0xf0, 0x01,       // BEQ +1
+    0x00,
+    0x6c, 0xff, 0x00, // JMP ($00FF), high byte from $0000
+  ]).unwrap();
+  cpu.mem_write(0x0000, 0x80);
+  cpu.set_nestest_log(true);
+  for _ in 0..8 {
+    cpu.step_instruction();
+  }
+
+  assert_eq!(
+    cpu.take_nestest_log(),
+    vec![
+      "8000  A2 02     LDX #$02                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0,  0 CYC:0",
+      "8002  86 10     STX $10 = 00                    A:00 X:02 Y:00 P:24 SP:FD PPU:  0,  6 CYC:2",
+      "8004  B5 0E     LDA $0E,X @ 10 = 02             A:00 X:02 Y:00 P:24 SP:FD PPU:  0, 15 CYC:5",
+      "8006  9D 00 03  STA $0300,X @ 0302 = 00         A:02 X:02 Y:00 P:24 SP:FD PPU:  0, 27 CYC:9",
+      "8009  A1 0E     LDA ($0E,X) @ 10 = 0002 = 00    A:02 X:02 Y:00 P:24 SP:FD PPU:  0, 42 CYC:14",
+      "800B  4A        LSR A                           A:00 X:02 Y:00 P:26 SP:FD PPU:  0, 60 CYC:20",
+      "800C  F0 01     BEQ $800F                       A:00 X:02 Y:00 P:26 SP:FD PPU:  0, 66 CYC:22",
+      "800F  6C FF 00  JMP ($00FF) = 8000              A:00 X:02 Y:00 P:26 SP:FD PPU:  0, 75 CYC:25",
+    ]
+  );
+  assert!(cpu.take_nestest_log().is_empty());
+
+  cpu.set_nestest_log(false);
+  cpu.step_instruction();
+  assert!(cpu.take_nestest_log().is_empty());
+}
@@ -0,0 +1,105 @@
+#![cfg(feature = "async")]
+
+mod common;
+
+use common::counter;
+use flemu_core::nes::cartridge::Rom;
+use flemu_core::nes::cpu::CPU;
+use flemu_core::nes::driver::*;
+use flemu_core::nes::joypad::JoypadButton;
+use futures_core::Stream;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+struct NoWake;
+
+impl Wake for NoWake {
+  fn wake(self: Arc<Self>) {}
+}
+
+// the next item, which a driver always has ready
+fn next(driver: &mut NesDriver) -> Option<DriverFrame> {
+  let waker = Waker::from(Arc::new(NoWake));
+  match Pin::new(driver).poll_next(&mut Context::from_waker(&waker)) {
+    Poll::Ready(item) => item,
+    Poll::Pending => panic!("a driver is never pending"),
+  }
+}
+
+#[test]
+fn test_frames_come_one_per_poll() {
+  let (mut driver, _input) = NesDriver::new(counter());
+  let first = next(&mut driver).unwrap();
+  let second = next(&mut driver).unwrap();
+  assert_eq!(second.number, first.number + 1);
+  assert_eq!(driver.cpu().bus.ppu.frame_count, second.number);
+  assert!(!second.samples.is_empty());
+}
+
+#[test]
+fn test_input_applies_at_the_next_frame() {
+  let held = [false, true, true, false];
+  let mut by_hand = counter();
+  let expected: Vec<u8> = held
+    .iter()
+    .map(|&a| {
+      by_hand.bus.joypads[0].set_button_pressed_status(JoypadButton::A, a);
+      by_hand.run_frame();
+      by_hand.register_x
+    })
+    .collect();
+
+  let (mut driver, input) = NesDriver::new(counter());
+  let mut driven = Vec::new();
+  for &a in &held {
+    input
+      .send(DriverInput::Button {
+        player: 0,
+        button: JoypadButton::A,
+        pressed: a,
+      })
+      .unwrap();
+    next(&mut driver).unwrap();
+    driven.push(driver.cpu().register_x);
+  }
+  assert_eq!(driven, expected);
+
+  // from another thread too, and unknown players are left alone
+  let sender = input.clone();
+  std::thread::spawn(move || {
+    sender
+      .send(DriverInput::Hold {
+        player: 1,
+        buttons: JoypadButton::START | JoypadButton::B,
+      })
+      .unwrap();
+    sender
+      .send(DriverInput::Hold {
+        player: 9,
+        buttons: JoypadButton::all(),
+      })
+      .unwrap();
+  })
+  .join()
+  .unwrap();
+  next(&mut driver).unwrap();
+  assert_eq!(
+    driver.cpu().bus.joypads[1].held(),
+    JoypadButton::START | JoypadButton::B
+  );
+
+  // no senders left: it keeps running
+  drop(input);
+  assert!(next(&mut driver).is_some());
+}
+
+#[test]
+fn test_the_stream_ends_when_the_cpu_halts() {
+  // BRK
+  let mut cpu = CPU::new();
+  cpu.load_rom(Rom::from_program(&[0x00])).unwrap();
+  let (mut driver, _input) = NesDriver::new(cpu);
+  assert!(next(&mut driver).is_none());
+  assert!(next(&mut driver).is_none());
+}
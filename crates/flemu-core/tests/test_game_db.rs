@@ -0,0 +1,137 @@
+use flemu_core::nes::cartridge::{Mirroring, Region, Rom};
+use flemu_core::nes::game_db::{rom_crc32, GameDb, GameDbError, HeaderFix, Peripheral};
+use flemu_core::nes::mapper::{Mmc3, Mmc3Irq};
+use flemu_core::nes::patch::crc32;
+
+const DB: &str = "
+  # crc32  | title         | overrides
+  00000001 | Plain         |
+  00000002 | Fixed | mapper=4 submapper=1 mirroring=vertical region=pal battery
+  00000003 | Shooting      | zapper no-battery   # a comment
+  00000004 | Picky         | mapper=4 prg-ram=32k mmc3-irq=old battery
+";
+
+#[test]
+fn test_parse() {
+  let db = GameDb::parse(DB).unwrap();
+  assert_eq!(db.len(), 4);
+  assert_eq!(db.lookup(1).unwrap().title, "Plain");
+  assert_eq!(db.lookup(1).unwrap().fix, HeaderFix::default());
+  assert_eq!(
+    db.lookup(2).unwrap().fix,
+    HeaderFix {
+      mapper: Some(4),
+      submapper: Some(1),
+      mirroring: Some(Mirroring::Vertical),
+      battery: Some(true),
+      region: Some(Region::Pal),
+      ..HeaderFix::default()
+    }
+  );
+  let picky = db.lookup(4).unwrap();
+  assert_eq!(picky.fix.prg_ram_size, Some(0x8000));
+  assert_eq!(picky.fix.mmc3_irq, Some(Mmc3Irq::Old));
+  let shooting = db.lookup(3).unwrap();
+  assert_eq!(shooting.peripheral, Some(Peripheral::Zapper));
+  assert_eq!(shooting.fix.battery, Some(false));
+  assert!(db.lookup(5).is_none());
+  assert_eq!(
+    GameDb::parse("00000001 | Bytes | prg-ram=8192")
+      .unwrap()
+      .lookup(1)
+      .unwrap()
+      .fix
+      .prg_ram_size,
+    Some(0x2000)
+  );
+}
+
+#[test]
+fn test_bad_lines_say_where() {
+  for bad in &[
+    "1 | Short crc",
+    "0000000g | Not hex",
+    "00000001 |",
+    "00000001 | Title | mapper=x",
+    "00000001 | Title | mirroring=diagonal",
+    "00000001 | Title | turbo",
+    "00000001 | Title | prg-ram=8kb",
+    "00000001 | Title | mmc3-irq=mmc3a",
+    "00000001 | Title | | extra",
+  ] {
+    let text = format!("# header\n{}", bad);
+    assert_eq!(
+      GameDb::parse(&text).unwrap_err(),
+      GameDbError::Syntax { line: 2 },
+      "{}",
+      bad
+    );
+  }
+}
+
+#[test]
+fn test_builtin_parses() {
+  assert!(!GameDb::builtin().is_empty());
+}
+
+#[test]
+fn test_identify_ignores_the_header() {
+  let mut rom = Rom::from_program(&[0xa9, 0x01]);
+  let crc = rom_crc32(&rom);
+  assert_eq!(crc, crc32(&[&rom.prg_rom[..], &rom.chr_rom[..]].concat()));
+  let db = GameDb::parse(&format!("{:08x} | Test | mapper=2", crc)).unwrap();
+  rom.info.mapper = 1;
+  rom.info.battery = true;
+  assert_eq!(db.identify(&rom).unwrap().title, "Test");
+  rom.prg_rom[0] = 0xea;
+  assert!(db.identify(&rom).is_none());
+}
+
+#[test]
+fn test_apply_reports_what_changed() {
+  let db = GameDb::parse(DB).unwrap();
+  let mut info = Rom::from_program(&[]).info;
+  info.submapper = 1;
+  let fixed = db.lookup(2).unwrap().apply(&mut info);
+  // the submapper was right already
+  assert_eq!(fixed, vec!["mapper", "mirroring", "battery", "region"]);
+  assert_eq!(info.mapper, 4);
+  assert_eq!(info.mirroring, Mirroring::Vertical);
+  assert!(info.battery);
+  assert_eq!(info.region, Region::Pal);
+  assert!(db.lookup(2).unwrap().apply(&mut info).is_empty());
+}
+
+#[test]
+fn test_apply_prg_ram_and_mmc3_irq() {
+  let db = GameDb::parse(DB).unwrap();
+  let mut rom = Rom::from_program(&[]);
+  rom.info.prg_ram_size = 0x2000;
+  let fixed = db.lookup(4).unwrap().apply(&mut rom.info);
+  assert_eq!(fixed, vec!["mapper", "battery", "prg_ram_size", "mmc3_irq"]);
+  // battery-backed, as the fixed header says
+  assert_eq!(
+    (rom.info.prg_ram_size, rom.info.prg_nvram_size),
+    (0, 0x8000)
+  );
+  assert_eq!(rom.info.submapper, 4);
+  assert_eq!(Mmc3::new(rom.clone()).irq_variant(), Mmc3Irq::Old);
+  assert!(db.lookup(4).unwrap().apply(&mut rom.info).is_empty());
+
+  // a header already asking for the old counter keeps its submapper
+  let db = GameDb::parse("00000001 | Acclaim | mmc3-irq=old").unwrap();
+  rom.info.submapper = 3;
+  assert!(db.lookup(1).unwrap().apply(&mut rom.info).is_empty());
+  let db = GameDb::parse("00000001 | Sharp | mmc3-irq=new").unwrap();
+  assert_eq!(db.lookup(1).unwrap().apply(&mut rom.info), vec!["mmc3_irq"]);
+  assert_eq!(rom.info.submapper, 0);
+}
+
+#[test]
+fn test_extend_replaces_entries() {
+  let mut db = GameDb::parse(DB).unwrap();
+  db.extend(GameDb::parse("00000001 | Renamed\n00000009 | New").unwrap());
+  assert_eq!(db.len(), 5);
+  assert_eq!(db.lookup(1).unwrap().title, "Renamed");
+  assert_eq!(db.lookup(9).unwrap().title, "New");
+}
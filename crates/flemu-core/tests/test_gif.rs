@@ -0,0 +1,211 @@
+use flemu_core::gif::*;
+use flemu_core::nes::palette::Palette;
+use flemu_core::nes::ppu::Frame;
+
+// a frame of `color`s, with a diagonal of `color + 1` to make LZW work
+fn frame(color: u8) -> Frame {
+  let mut frame = Frame::new();
+  for (i, pixel) in frame.data.iter_mut().enumerate() {
+    *pixel = if i % 257 == 0 { color + 1 } else { color };
+  }
+  frame
+}
+
+// every color there is, on `emphases` emphasis settings line by line
+fn busy_frame(emphases: usize) -> Frame {
+  let mut frame = Frame::new();
+  for (i, pixel) in frame.data.iter_mut().enumerate() {
+    *pixel = ((i * 7 + i / 13) % 64) as u8;
+  }
+  for (line, emphasis) in frame.emphasis.iter_mut().enumerate() {
+    *emphasis = (line % emphases) as u8;
+  }
+  frame
+}
+
+struct Decoded {
+  delays: Vec<u16>,
+  // RGBA like `Palette::frame_rgba`
+  frames: Vec<Vec<u8>>,
+  looping: bool,
+}
+
+// just enough of a GIF decoder for what `encode_gif` writes
+fn decode(gif: &[u8]) -> Decoded {
+  assert_eq!(&gif[..6], b"GIF89a");
+  assert_eq!(&gif[6..10], &[0, 1, 240, 0]);
+  let mut at = 13;
+  let mut decoded = Decoded {
+    delays: Vec::new(),
+    frames: Vec::new(),
+    looping: false,
+  };
+  loop {
+    match gif[at] {
+      0x21 => {
+        let label = gif[at + 1];
+        if label == 0xF9 {
+          decoded
+            .delays
+            .push(u16::from_le_bytes([gif[at + 4], gif[at + 5]]));
+        }
+        if label == 0xFF && &gif[at + 3..at + 14] == b"NETSCAPE2.0" {
+          decoded.looping = gif[at + 16..at + 18] == [0, 0];
+        }
+        at += 2;
+        at = skip_blocks(gif, at).1;
+      }
+      0x2C => {
+        let packed = gif[at + 9];
+        assert_eq!(packed & 0x80, 0x80, "local color table");
+        let entries = 2 << (packed & 7);
+        let table = &gif[at + 10..at + 10 + entries * 3];
+        at += 10 + entries * 3;
+        let min_bits = gif[at];
+        let (data, end) = skip_blocks(gif, at + 1);
+        at = end;
+        let indices = lzw_decode(&data, min_bits);
+        assert_eq!(indices.len(), Frame::WIDTH * Frame::HEIGHT);
+        let rgba = indices
+          .iter()
+          .flat_map(|&i| {
+            let rgb = &table[i as usize * 3..i as usize * 3 + 3];
+            vec![rgb[0], rgb[1], rgb[2], 0xFF]
+          })
+          .collect();
+        decoded.frames.push(rgba);
+      }
+      0x3B => {
+        assert_eq!(at + 1, gif.len());
+        return decoded;
+      }
+      other => panic!("block {:02x} at {}", other, at),
+    }
+  }
+}
+
+// the sub-blocks from `at` on, joined, and where they end
+fn skip_blocks(gif: &[u8], mut at: usize) -> (Vec<u8>, usize) {
+  let mut data = Vec::new();
+  while gif[at] != 0 {
+    let len = gif[at] as usize;
+    data.extend_from_slice(&gif[at + 1..at + 1 + len]);
+    at += 1 + len;
+  }
+  (data, at + 1)
+}
+
+fn lzw_decode(data: &[u8], min_bits: u8) -> Vec<u8> {
+  let clear = 1usize << min_bits;
+  let end = clear + 1;
+  let reset = || -> Vec<Vec<u8>> {
+    let mut table: Vec<Vec<u8>> = (0..clear).map(|i| vec![i as u8]).collect();
+    table.push(Vec::new());
+    table.push(Vec::new());
+    table
+  };
+  let mut table = reset();
+  let mut bits = min_bits as usize + 1;
+  let mut out = Vec::new();
+  let mut previous: Option<Vec<u8>> = None;
+  let mut at = 0;
+  loop {
+    let mut code = 0;
+    for bit in 0..bits {
+      let index = at + bit;
+      code |= ((data[index / 8] >> (index % 8)) as usize & 1) << bit;
+    }
+    at += bits;
+    if code == clear {
+      table = reset();
+      bits = min_bits as usize + 1;
+      previous = None;
+      continue;
+    }
+    if code == end {
+      return out;
+    }
+    let entry = match (table.get(code), &previous) {
+      (Some(entry), _) => entry.clone(),
+      (None, Some(previous)) => {
+        assert_eq!(code, table.len(), "code out of order");
+        let mut entry = previous.clone();
+        entry.push(previous[0]);
+        entry
+      }
+      (None, None) => panic!("code {} first", code),
+    };
+    out.extend_from_slice(&entry);
+    if let Some(mut previous) = previous {
+      previous.push(entry[0]);
+      table.push(previous);
+      if table.len() == 1 << bits && bits < 12 {
+        bits += 1;
+      }
+    }
+    previous = Some(entry);
+  }
+}
+
+#[test]
+fn test_history_keeps_the_last_frames() {
+  let mut history = FrameHistory::new(3);
+  assert!(history.is_empty());
+  for color in 0..5 {
+    history.push(&frame(color * 2));
+  }
+  assert_eq!(history.len(), 3);
+  let firsts: Vec<u8> = history.recent(10).iter().map(|f| f.data[1]).collect();
+  assert_eq!(firsts, vec![4, 6, 8]);
+  let firsts: Vec<u8> = history.recent(2).iter().map(|f| f.data[1]).collect();
+  assert_eq!(firsts, vec![6, 8]);
+
+  history.set_capacity(1);
+  assert_eq!(history.recent(5)[0].data[1], 8);
+  history.set_capacity(0);
+  history.push(&frame(1));
+  assert!(history.is_empty());
+}
+
+#[test]
+fn test_gif_decodes_to_the_frames() {
+  let palette = Palette::default();
+  let frames = [frame(0x0F), frame(0x21), busy_frame(4)];
+  let refs: Vec<&Frame> = frames.iter().collect();
+  let decoded = decode(&encode_gif(&refs, &palette, 30.0));
+  assert!(decoded.looping);
+  assert_eq!(decoded.frames.len(), 3);
+  for (frame, rgba) in frames.iter().zip(&decoded.frames) {
+    assert!(*rgba == palette.frame_rgba(frame));
+  }
+}
+
+#[test]
+fn test_delays_keep_time() {
+  let frame = frame(0);
+  let refs = vec![&frame; 6];
+  // 60 frames a second kept every other frame
+  let decoded = decode(&encode_gif(&refs, &Palette::default(), 60.0988 / 2.0));
+  assert_eq!(decoded.delays, vec![3, 4, 3, 3, 4, 3]);
+  let decoded = decode(&encode_gif(&refs, &Palette::default(), 25.0));
+  assert_eq!(decoded.delays, vec![4; 6]);
+}
+
+#[test]
+fn test_too_many_colors_drop_emphasis() {
+  let palette = Palette::default();
+  let rgba = |frame: &Frame| {
+    decode(&encode_gif(&[frame], &palette, 30.0))
+      .frames
+      .remove(0)
+  };
+  // 64 colors on all 8 emphasis settings
+  let frame = busy_frame(8);
+  let mut plain = frame.clone();
+  plain.emphasis.iter_mut().for_each(|e| *e = 0);
+  assert!(rgba(&frame) == palette.frame_rgba(&plain));
+  assert!(rgba(&frame) != palette.frame_rgba(&frame));
+  // 4 of them fit
+  let frame = busy_frame(4);
+  assert!(rgba(&frame) == palette.frame_rgba(&frame));
+}
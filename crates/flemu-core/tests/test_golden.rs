@@ -0,0 +1,137 @@
+use flemu_core::nes::cartridge::Rom;
+use flemu_core::nes::golden::*;
+use flemu_core::nes::ppu::Frame;
+use flemu_core::video_dump;
+use std::fs;
+use std::path::PathBuf;
+
+/*
+  Reference frames live in tests/golden/<name>.png. Run with FLEMU_BLESS=1
+  to write them from the current build instead of checking, after making
+  sure the picture is right. A failing frame is written to
+  $TMPDIR/flemu-golden/<name>.png to look at.
+
+  Besides the programs built here, any test ROM or homebrew unpacked as
+  tests/roms/golden/<name>.nes (they aren't part of the repository) is
+  checked against tests/golden/<name>.png after EXTERNAL_FRAMES frames.
+*/
+
+// five seconds of emulated time, past most title screens' fade-in
+const EXTERNAL_FRAMES: u32 = 5 * 60;
+
+fn dir(path: &str) -> PathBuf {
+  PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(path)
+}
+
+fn check(name: &str, rom: Rom, frames: u32) {
+  let rgba = render(rom, frames).unwrap();
+  let png = video_dump::encode_png(Frame::WIDTH as u32, Frame::HEIGHT as u32, &rgba);
+  let reference = dir("tests/golden").join(format!("{}.png", name));
+  if std::env::var_os("FLEMU_BLESS").is_some() {
+    fs::write(&reference, png).unwrap();
+    return;
+  }
+  let expected = fs::read(&reference)
+    .unwrap_or_else(|e| panic!("{}: {}, FLEMU_BLESS=1 writes it", reference.display(), e));
+  if let Err(mismatch) = compare(&expected, &rgba) {
+    let failed = std::env::temp_dir().join("flemu-golden");
+    fs::create_dir_all(&failed).unwrap();
+    let actual = failed.join(format!("{}.png", name));
+    fs::write(&actual, png).unwrap();
+    panic!("{}: {}, see {}", name, mismatch, actual.display());
+  }
+}
+
+/// Palette, nametable, attributes and OAM all filled from one counter,
+/// over CHR patterns from another: every tile, attribute and sprite flag
+/// turns up somewhere on screen.
+fn counter_rom() -> Rom {
+  #[rustfmt::skip]
+  let mut rom = Rom::from_program(&[
+    0x78,             // $8000 SEI
+    0xd8,             //       CLD
+    0xa2, 0x02,       //       LDX #2
+    0x2c, 0x02, 0x20, // $8004 BIT $2002, two vblanks
+    0x10, 0xfb,       //       BPL $8004
+    0xca,             //       DEX
+    0xd0, 0xf8,       //       BNE $8004
+    0xa9, 0x3f, 0x8d, 0x06, 0x20, // $2006 = $3F00
+    0xa9, 0x00, 0x8d, 0x06, 0x20,
+    0xaa,             //       TAX
+    0x8a,             // $8017 TXA, palette = 0..31
+    0x8d, 0x07, 0x20, //       STA $2007
+    0xe8,             //       INX
+    0xe0, 0x20,       //       CPX #32
+    0xd0, 0xf7,       //       BNE $8017
+    0xa9, 0x20, 0x8d, 0x06, 0x20, // $2006 = $2000
+    0xa9, 0x00, 0x8d, 0x06, 0x20,
+    0xa0, 0x04,       //       LDY #4
+    0xaa,             //       TAX
+    0x8a,             // $802D TXA, 1 KiB of nametable
+    0x8d, 0x07, 0x20, //       STA $2007
+    0x9d, 0x00, 0x02, //       STA $0200,X, and OAM's page
+    0xe8,             //       INX
+    0xd0, 0xf6,       //       BNE $802D
+    0x88,             //       DEY
+    0xd0, 0xf3,       //       BNE $802D
+    0xa9, 0x02, 0x8d, 0x14, 0x40, // OAM DMA from $0200
+    0xa9, 0x00, 0x8d, 0x05, 0x20, // no scroll
+    0x8d, 0x05, 0x20,
+    0xa9, 0x08, 0x8d, 0x00, 0x20, // sprites from $1000
+    0xa9, 0x1e, 0x8d, 0x01, 0x20, // everything on
+    0x4c, 0x51, 0x80, // $8051 JMP $8051
+  ]);
+  for (i, byte) in rom.chr_rom.iter_mut().enumerate() {
+    *byte = ((i * 7) ^ (i >> 4)) as u8;
+  }
+  rom
+}
+
+#[test]
+fn test_counter_frame() {
+  check("counter", counter_rom(), 4);
+}
+
+#[test]
+fn test_external_roms() {
+  let roms = match fs::read_dir(dir("tests/roms/golden")) {
+    Ok(entries) => entries,
+    Err(_) => {
+      eprintln!("tests/roms/golden not found, skipping");
+      return;
+    }
+  };
+  let mut paths: Vec<PathBuf> = roms
+    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+    .filter(|path| path.extension().map_or(false, |ext| ext == "nes"))
+    .collect();
+  paths.sort();
+  for path in paths {
+    let name = path.file_stem().unwrap().to_string_lossy();
+    let rom = Rom::from_bytes(&fs::read(&path).unwrap()).unwrap();
+    check(&name, rom, EXTERNAL_FRAMES);
+  }
+}
+
+#[test]
+fn test_compare_finds_the_first_pixel() {
+  let rgba = vec![0x40; Frame::WIDTH * Frame::HEIGHT * 4];
+  let reference = video_dump::encode_png(Frame::WIDTH as u32, Frame::HEIGHT as u32, &rgba);
+  assert_eq!(compare(&reference, &rgba), Ok(()));
+
+  let mut changed = rgba.clone();
+  changed[(Frame::WIDTH * 2 + 5) * 4] = 0;
+  changed[(Frame::WIDTH * 9) * 4 + 1] = 0;
+  let mismatch = compare(&reference, &changed).unwrap_err();
+  assert_eq!(
+    mismatch,
+    Mismatch::Pixels {
+      count: 2,
+      first: (5, 2)
+    }
+  );
+  assert_eq!(mismatch.to_string(), "2 pixels differ, the first at 5,2");
+
+  let small = video_dump::encode_png(1, 1, &[0; 4]);
+  assert_eq!(compare(&small, &rgba), Err(Mismatch::BadReference));
+}
@@ -0,0 +1,189 @@
+use flemu_core::nes::bus::{Bus, Mem};
+use flemu_core::nes::cartridge::Rom;
+use flemu_core::nes::cpu::CPU;
+use flemu_core::nes::joypad::*;
+use flemu_core::nes::savestate::{StateReader, StateWriter};
+
+fn read_all(bus: &mut Bus, addr: u16) -> Vec<u8> {
+  (0..10).map(|_| bus.mem_read(addr) & 1).collect()
+}
+
+#[test]
+fn test_serial_reads() {
+  let mut bus = Bus::new();
+  bus.joypads[0].set_button_pressed_status(JoypadButton::A, true);
+  bus.joypads[0].set_button_pressed_status(JoypadButton::START, true);
+  bus.joypads[0].set_button_pressed_status(JoypadButton::RIGHT, true);
+  bus.mem_write(0x4016, 1);
+  bus.mem_write(0x4016, 0);
+  // eight buttons, then 1s
+  assert_eq!(
+    read_all(&mut bus, 0x4016),
+    vec![1, 0, 0, 1, 0, 0, 0, 1, 1, 1]
+  );
+
+  // a new strobe starts over
+  bus.mem_write(0x4016, 1);
+  bus.mem_write(0x4016, 0);
+  assert_eq!(bus.mem_read(0x4016), 0x41);
+}
+
+#[test]
+fn test_strobe_high_keeps_reporting_a() {
+  let mut pad = Joypad::new();
+  pad.write(1);
+  assert_eq!(pad.read() & 1, 0);
+  pad.set_button_pressed_status(JoypadButton::A, true);
+  assert_eq!(pad.read() & 1, 1);
+  assert_eq!(pad.read() & 1, 1);
+}
+
+#[test]
+fn test_buttons_latch_when_strobe_falls() {
+  let mut pad = Joypad::new();
+  pad.write(1);
+  pad.set_button_pressed_status(JoypadButton::B, true);
+  pad.write(0);
+  // let go and press something else after the latch: reads don't see it
+  pad.set_button_pressed_status(JoypadButton::B, false);
+  pad.set_button_pressed_status(JoypadButton::A, true);
+  let reads: Vec<u8> = (0..8).map(|_| pad.read() & 1).collect();
+  assert_eq!(reads, vec![0, 1, 0, 0, 0, 0, 0, 0]);
+  // however long a game keeps polling, past the eighth it's 1s
+  assert!((0..32).all(|_| pad.read() & 1 == 1));
+
+  // 0 written again without a strobe doesn't latch anew
+  pad.write(0);
+  assert_eq!(pad.peek() & 1, 1);
+  pad.write(1);
+  pad.write(0);
+  assert_eq!(pad.read() & 1, 1);
+  assert_eq!(pad.read() & 1, 0);
+}
+
+#[test]
+fn test_second_player_and_peek() {
+  let mut bus = Bus::new();
+  bus.joypads[1].set_button_pressed_status(JoypadButton::B, true);
+  // one strobe for both ports; $4017 writes go to the APU
+  bus.mem_write(0x4016, 1);
+  bus.mem_write(0x4016, 0);
+  bus.mem_write(0x4017, 0x40);
+  assert_eq!(bus.mem_read(0x4017) & 1, 0);
+  assert_eq!(bus.mem_peek(0x4017) & 1, 1);
+  assert_eq!(bus.mem_peek(0x4017) & 1, 1);
+  assert_eq!(bus.mem_read(0x4017) & 1, 1);
+  assert_eq!(read_all(&mut bus, 0x4016)[..8], [0; 8]);
+}
+
+#[test]
+fn test_four_score() {
+  let mut bus = Bus::new();
+  bus.set_four_score(true);
+  bus.joypads[0].set_button_pressed_status(JoypadButton::A, true);
+  bus.joypads[1].set_button_pressed_status(JoypadButton::B, true);
+  bus.joypads[2].set_button_pressed_status(JoypadButton::START, true);
+  bus.joypads[3].set_button_pressed_status(JoypadButton::RIGHT, true);
+  bus.mem_write(0x4016, 1);
+  bus.mem_write(0x4016, 0);
+
+  let read =
+    |bus: &mut Bus, addr: u16| -> Vec<u8> { (0..26).map(|_| bus.mem_read(addr) & 1).collect() };
+  #[rustfmt::skip]
+  assert_eq!(read(&mut bus, 0x4016), vec![
+    1, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 1, 0, 0, 0, 0,
+    0, 0, 0, 1, 0, 0, 0, 0,
+    1, 1,
+  ]);
+  #[rustfmt::skip]
+  assert_eq!(read(&mut bus, 0x4017), vec![
+    0, 1, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 1,
+    0, 0, 1, 0, 0, 0, 0, 0,
+    1, 1,
+  ]);
+
+  // unplugged, players 3 and 4 are gone
+  bus.set_four_score(false);
+  assert!(!bus.four_score());
+  bus.mem_write(0x4016, 1);
+  bus.mem_write(0x4016, 0);
+  assert_eq!(read(&mut bus, 0x4016)[8..], [1; 18]);
+}
+
+#[test]
+fn test_turbo_fires_at_its_rate() {
+  let mut pad = Joypad::new();
+  pad.set_button_pressed_status(JoypadButton::B, true);
+  pad.set_turbo_pressed_status(JoypadButton::A, true);
+  let fired = |pad: &mut Joypad, frames: u64| -> Vec<bool> {
+    (0..frames)
+      .map(|frame| {
+        pad.set_frame(frame);
+        assert!(pad.buttons().contains(JoypadButton::B));
+        pad.buttons().contains(JoypadButton::A)
+      })
+      .collect()
+  };
+  assert_eq!(pad.turbo_rate(), TurboRate::default());
+  assert_eq!(
+    fired(&mut pad, 8),
+    vec![true, true, false, false, true, true, false, false]
+  );
+
+  pad.set_turbo_rate(TurboRate { on: 1, off: 3 });
+  assert_eq!(fired(&mut pad, 5), vec![true, false, false, false, true]);
+  // never stuck pressed or released
+  pad.set_turbo_rate(TurboRate { on: 0, off: 0 });
+  assert_eq!(pad.turbo_rate(), TurboRate { on: 1, off: 1 });
+
+  assert_eq!(pad.held(), JoypadButton::B);
+  assert_eq!(pad.turbo(), JoypadButton::A);
+  pad.set_turbo_pressed_status(JoypadButton::A, false);
+  assert_eq!(fired(&mut pad, 2), vec![false, false]);
+}
+
+#[test]
+fn test_turbo_follows_the_ppu_frames() {
+  let mut cpu = CPU::new();
+  // JMP $8000
+  cpu
+    .load_rom(Rom::from_program(&[0x4C, 0x00, 0x80]))
+    .unwrap();
+  cpu.bus.joypads[0].set_turbo_pressed_status(JoypadButton::A, true);
+  for _ in 0..6 {
+    cpu.run_frame();
+    let frame = cpu.bus.ppu.frame_count;
+    assert_eq!(
+      cpu.bus.joypads[0].buttons().contains(JoypadButton::A),
+      frame % 4 < 2
+    );
+    // games read it as it fires
+    cpu.bus.mem_write(0x4016, 1);
+    assert_eq!(cpu.bus.mem_read(0x4016) & 1, (frame % 4 < 2) as u8);
+  }
+
+  // a savestate puts back the phase with the frame counter
+  let mut w = StateWriter::new(cpu.bus.cartridge_checksum());
+  cpu.save_state(&mut w);
+  let state = w.finish();
+  let expected = cpu.bus.joypads[0].buttons();
+  cpu.run_frame();
+  let mut r = StateReader::new(&state, cpu.bus.cartridge_checksum()).unwrap();
+  cpu.load_state(&mut r).unwrap();
+  assert_eq!(cpu.bus.joypads[0].buttons(), expected);
+}
+
+#[test]
+fn test_reads_count_as_polling_input() {
+  let mut bus = Bus::new();
+  assert!(!bus.take_input_polled());
+  // strobing and peeking isn't looking at the buttons
+  bus.mem_write(0x4016, 1);
+  bus.mem_peek(0x4016);
+  assert!(!bus.take_input_polled());
+  bus.mem_read(0x4017);
+  assert!(bus.take_input_polled());
+  assert!(!bus.take_input_polled());
+}
@@ -0,0 +1,1159 @@
+use flemu_core::nes::cartridge::*;
+use flemu_core::nes::mapper::{
+  self, MapperTestHarness, Mmc3, Mmc3Irq, SyntheticHeader, TileFetch, TileRow,
+};
+use flemu_core::nes::nametable::{NametablePage, NametableTarget};
+
+fn cart(mapper: u16, prg_rom_banks: usize, chr_rom_banks: usize) -> MapperTestHarness {
+  MapperTestHarness::new(SyntheticHeader {
+    mapper,
+    prg_rom_banks,
+    chr_rom_banks,
+    ..SyntheticHeader::default()
+  })
+  .unwrap()
+}
+
+#[test]
+fn test_synthetic_headers_load_as_written() {
+  let header = SyntheticHeader {
+    mapper: 0x104,
+    submapper: 3,
+    prg_rom_banks: 3,
+    chr_rom_banks: 0,
+    mirroring: Mirroring::FourScreen,
+    battery: true,
+    prg_ram_size: 0x2000,
+  };
+  let rom = Rom::from_bytes(&header.to_image()).unwrap();
+  assert_eq!(rom.info.format, HeaderFormat::Nes2);
+  assert_eq!((rom.info.mapper, rom.info.submapper), (0x104, 3));
+  assert_eq!(rom.info.mirroring, Mirroring::FourScreen);
+  assert_eq!(rom.info.prg_rom_size, 3 * PRG_ROM_PAGE_SIZE);
+  assert_eq!(
+    (rom.info.prg_ram_size, rom.info.prg_nvram_size),
+    (0, 0x2000)
+  );
+  assert_eq!(rom.info.chr_ram_size, 0x2000);
+  // each 1KB numbered
+  assert_eq!(&rom.prg_rom[0x7ffe..0x8002], &[31, 0, 32, 0]);
+}
+
+#[test]
+fn test_harness_pulses_a12_around_the_frame() {
+  // 241 to the frame: the pre-render line and the visible ones
+  let mut mmc5 = cart(5, 8, 32);
+  mmc5.write(0x5203, 239);
+  mmc5.write(0x5204, 0x80);
+  let fired: Vec<usize> = (0..241 * 2)
+    .filter(|_| {
+      let fired = mmc5.pulse_a12();
+      mmc5.mapper.prg_read_mut(0x5204);
+      fired
+    })
+    .collect();
+  assert_eq!(fired, [239, 241 + 239]);
+}
+
+#[test]
+#[should_panic(expected = "PRG ROM at $C000, expected 16KB bank 0")]
+fn test_harness_bank_asserts_say_where() {
+  cart(0, 2, 1).assert_prg_bank(0xc000, 0, 0x4000);
+}
+
+#[test]
+fn test_nrom_128_is_mirrored() {
+  let nrom = cart(0, 1, 1);
+  nrom.assert_prg_bank(0x8000, 0, 0x4000);
+  nrom.assert_prg_bank(0xc000, 0, 0x4000);
+}
+
+#[test]
+fn test_nrom_256() {
+  let nrom = cart(0, 2, 1);
+  nrom.assert_prg_bank(0x8000, 0, 0x4000);
+  nrom.assert_prg_bank(0xc000, 1, 0x4000);
+  assert_eq!(nrom.mapper.mirroring(), Mirroring::Vertical);
+}
+
+#[test]
+fn test_nrom_prg_rom_is_read_only_and_prg_ram_is_not() {
+  let mut nrom = cart(0, 2, 1);
+  nrom.write(0x8000, 0xff);
+  nrom.write(0x6001, 0x42);
+  nrom.assert_prg_bank(0x8000, 0, 0x4000);
+  assert_eq!(nrom.mapper.prg_read(0x6001), 0x42);
+}
+
+#[test]
+fn test_nrom_chr_rom_and_chr_ram() {
+  let mut with_rom = cart(0, 1, 1);
+  with_rom.mapper.chr_write(0x0410, 0x44);
+  with_rom.assert_chr_bank(0x0000, 0, 0x2000);
+
+  let mut with_ram = cart(0, 1, 0);
+  with_ram.mapper.chr_write(0x1fff, 0x44);
+  assert_eq!(with_ram.mapper.chr_read(0x1fff), 0x44);
+}
+
+#[test]
+fn test_boxed_mapper_clone_is_independent() {
+  let mut original = cart(0, 1, 0).mapper;
+  let copy = original.clone();
+  original.prg_write(0x6000, 0x01);
+  assert_eq!(original.prg_read(0x6000), 0x01);
+  assert_eq!(copy.prg_read(0x6000), 0x00);
+}
+
+#[test]
+fn test_unsupported_mapper() {
+  let error = MapperTestHarness::new(SyntheticHeader {
+    mapper: 163,
+    submapper: 1,
+    ..SyntheticHeader::default()
+  })
+  .err()
+  .unwrap();
+  assert_eq!(
+    error,
+    RomError::UnsupportedMapper {
+      number: 163,
+      submapper: 1,
+      known_name: Some("Nanjing"),
+    }
+  );
+  assert_eq!(error.to_string(), "mapper 163.1 (Nanjing) is not supported");
+
+  let error = MapperTestHarness::new(SyntheticHeader {
+    mapper: 4000,
+    ..SyntheticHeader::default()
+  })
+  .err()
+  .unwrap();
+  assert_eq!(error.to_string(), "mapper 4000 is not supported");
+}
+
+#[test]
+fn test_board_names() {
+  assert_eq!(mapper::board_name(0), Some("NROM"));
+  assert_eq!(mapper::board_name(4), Some("MMC3"));
+  assert_eq!(mapper::board_name(232), Some("Camerica Quattro"));
+  assert_eq!(mapper::board_name(6), None);
+}
+
+// five writes, least significant bit first
+fn mmc1_write(mmc1: &mut MapperTestHarness, addr: u16, value: u8) {
+  for bit in 0..5 {
+    mmc1.write(addr, (value >> bit) & 1);
+  }
+}
+
+#[test]
+fn test_mmc1_powers_on_with_last_bank_fixed() {
+  let mmc1 = cart(1, 8, 1);
+  mmc1.assert_prg_bank(0x8000, 0, 0x4000);
+  mmc1.assert_prg_bank(0xc000, 7, 0x4000);
+}
+
+#[test]
+fn test_mmc1_five_write_load_sequence() {
+  let mut mmc1 = cart(1, 8, 1);
+  for _ in 0..4 {
+    mmc1.write(0xe000, 1);
+    // nothing happens until the fifth write
+    mmc1.assert_prg_bank(0x8000, 0, 0x4000);
+  }
+  mmc1.write(0xe000, 0);
+  // 0b01111 = bank 7
+  mmc1.assert_prg_bank(0x8000, 7, 0x4000);
+
+  mmc1_write(&mut mmc1, 0xe000, 2);
+  mmc1.assert_prg_bank(0x8000, 2, 0x4000);
+  mmc1.assert_prg_bank(0xc000, 7, 0x4000);
+}
+
+#[test]
+fn test_mmc1_reset_on_bit_7() {
+  let mut mmc1 = cart(1, 8, 1);
+  // 32KB mode
+  mmc1_write(&mut mmc1, 0x8000, 0b00000);
+  mmc1_write(&mut mmc1, 0xe000, 2);
+  mmc1.assert_prg_bank(0x8000, 1, 0x8000);
+
+  // a partial load is thrown away
+  mmc1.write(0xe000, 1);
+  mmc1.write(0xe000, 1);
+  mmc1.write(0x8000, 0x80);
+  mmc1_write(&mut mmc1, 0xe000, 4);
+  mmc1.assert_prg_bank(0x8000, 4, 0x4000);
+
+  // and PRG mode 3 is back: last bank at $c000
+  mmc1.assert_prg_bank(0xc000, 7, 0x4000);
+}
+
+#[test]
+fn test_mmc1_prg_modes() {
+  let mut mmc1 = cart(1, 8, 1);
+
+  // mode 0/1: 32KB, low bit ignored
+  mmc1_write(&mut mmc1, 0x8000, 0b00000);
+  mmc1_write(&mut mmc1, 0xe000, 3);
+  mmc1.assert_prg_bank(0x8000, 1, 0x8000);
+
+  // mode 2: first bank fixed at $8000
+  mmc1_write(&mut mmc1, 0x8000, 0b01000);
+  mmc1.assert_prg_bank(0x8000, 0, 0x4000);
+  mmc1.assert_prg_bank(0xc000, 3, 0x4000);
+}
+
+#[test]
+fn test_mmc1_chr_modes() {
+  let mut mmc1 = cart(1, 2, 4);
+
+  // 8KB mode, low bit ignored
+  mmc1_write(&mut mmc1, 0xa000, 3);
+  mmc1.assert_chr_bank(0x0000, 1, 0x2000);
+
+  // two 4KB banks
+  mmc1_write(&mut mmc1, 0x8000, 0b11100);
+  mmc1_write(&mut mmc1, 0xa000, 5);
+  mmc1_write(&mut mmc1, 0xc000, 1);
+  mmc1.assert_chr_bank(0x0000, 5, 0x1000);
+  mmc1.assert_chr_bank(0x1000, 1, 0x1000);
+}
+
+#[test]
+fn test_mmc1_mirroring() {
+  let mut mmc1 = cart(1, 2, 1);
+  for &(value, expected) in &[
+    (0b01100, Mirroring::SingleScreenLower),
+    (0b01101, Mirroring::SingleScreenUpper),
+    (0b01110, Mirroring::Vertical),
+    (0b01111, Mirroring::Horizontal),
+  ] {
+    mmc1_write(&mut mmc1, 0x8000, value);
+    assert_eq!(mmc1.mapper.mirroring(), expected);
+  }
+}
+
+#[test]
+fn test_mmc1_prg_ram_enable() {
+  let mut mmc1 = cart(1, 2, 1);
+  mmc1.write(0x6000, 0x42);
+  assert_eq!(mmc1.mapper.prg_read(0x6000), 0x42);
+
+  mmc1_write(&mut mmc1, 0xe000, 0b10000);
+  assert_eq!(mmc1.mapper.prg_read(0x6000), 0);
+  mmc1.write(0x6000, 0x99);
+
+  mmc1_write(&mut mmc1, 0xe000, 0b00000);
+  assert_eq!(mmc1.mapper.prg_read(0x6000), 0x42);
+}
+
+#[test]
+fn test_uxrom_bank_switch() {
+  let mut uxrom = cart(2, 8, 0);
+  uxrom.assert_prg_bank(0x8000, 0, 0x4000);
+  uxrom.assert_prg_bank(0xc000, 7, 0x4000);
+
+  uxrom.write(0x8000, 5);
+  uxrom.assert_prg_bank(0x8000, 5, 0x4000);
+  uxrom.assert_prg_bank(0xc000, 7, 0x4000);
+
+  // CHR RAM
+  uxrom.mapper.chr_write(0x0100, 0x42);
+  assert_eq!(uxrom.mapper.chr_read(0x0100), 0x42);
+}
+
+#[test]
+fn test_cnrom_bank_switch() {
+  let mut cnrom = cart(3, 1, 4);
+  cnrom.assert_prg_bank(0x8000, 0, 0x4000);
+  cnrom.assert_prg_bank(0xc000, 0, 0x4000);
+  cnrom.assert_chr_bank(0x0000, 0, 0x2000);
+
+  cnrom.write(0xffff, 2);
+  cnrom.assert_chr_bank(0x0000, 2, 0x2000);
+  assert_eq!(cnrom.mapper.mirroring(), Mirroring::Vertical);
+
+  // CHR ROM stays read-only
+  cnrom.mapper.chr_write(0x0000, 0xff);
+  cnrom.assert_chr_bank(0x0000, 2, 0x2000);
+}
+
+#[test]
+fn test_axrom_bank_switch_and_mirroring() {
+  // 4 x 32KB
+  let mut axrom = cart(7, 8, 0);
+  axrom.assert_prg_bank(0x8000, 0, 0x8000);
+  assert_eq!(axrom.mapper.mirroring(), Mirroring::SingleScreenLower);
+
+  axrom.write(0x8000, 0b1_0011);
+  axrom.assert_prg_bank(0x8000, 3, 0x8000);
+  assert_eq!(axrom.mapper.mirroring(), Mirroring::SingleScreenUpper);
+}
+
+#[test]
+fn test_inl_nsf_4k_banks() {
+  let mut nsf = MapperTestHarness::new(SyntheticHeader {
+    mapper: 31,
+    chr_rom_banks: 0,
+    prg_ram_size: 0x2000,
+    ..SyntheticHeader::default()
+  })
+  .unwrap();
+  // the last bank, for the vectors
+  nsf.assert_prg_bank(0xf000, 7, 0x1000);
+  nsf.assert_prg_bank(0x8000, 0, 0x1000);
+
+  nsf.write(0x5ff8, 5);
+  nsf.assert_prg_bank(0x8000, 5, 0x1000);
+  nsf.assert_prg_bank(0x9000, 0, 0x1000);
+  // mirrored through $5000-$5FFF
+  nsf.write(0x5001, 6);
+  nsf.assert_prg_bank(0x9000, 6, 0x1000);
+
+  nsf.write(0x6000, 0x42);
+  assert_eq!(nsf.mapper.prg_read(0x6000), 0x42);
+}
+
+#[test]
+fn test_camerica_bank_switch() {
+  let mut camerica = cart(71, 8, 0);
+  camerica.assert_prg_bank(0x8000, 0, 0x4000);
+  camerica.assert_prg_bank(0xc000, 7, 0x4000);
+
+  // the register is at $C000-$FFFF only
+  camerica.write(0x8000, 3);
+  camerica.assert_prg_bank(0x8000, 0, 0x4000);
+  camerica.write(0xc000, 5);
+  camerica.assert_prg_bank(0x8000, 5, 0x4000);
+  camerica.assert_prg_bank(0xc000, 7, 0x4000);
+  assert_eq!(camerica.mapper.mirroring(), Mirroring::Vertical);
+}
+
+#[test]
+fn test_camerica_fire_hawk_mirroring() {
+  let mut camerica = MapperTestHarness::new(SyntheticHeader {
+    mapper: 71,
+    submapper: 1,
+    prg_rom_banks: 8,
+    chr_rom_banks: 0,
+    ..SyntheticHeader::default()
+  })
+  .unwrap();
+  assert_eq!(camerica.mapper.mirroring(), Mirroring::SingleScreenLower);
+
+  camerica.write(0x9000, 0x10);
+  assert_eq!(camerica.mapper.mirroring(), Mirroring::SingleScreenUpper);
+  camerica.assert_prg_bank(0x8000, 0, 0x4000);
+  camerica.write(0x9000, 0x00);
+  assert_eq!(camerica.mapper.mirroring(), Mirroring::SingleScreenLower);
+}
+
+#[test]
+fn test_unrom_180_fixes_the_first_bank() {
+  let mut unrom = cart(180, 8, 0);
+  unrom.assert_prg_bank(0x8000, 0, 0x4000);
+  unrom.assert_prg_bank(0xc000, 0, 0x4000);
+
+  unrom.write(0x8000, 6);
+  unrom.assert_prg_bank(0x8000, 0, 0x4000);
+  unrom.assert_prg_bank(0xc000, 6, 0x4000);
+}
+
+#[test]
+fn test_gxrom_bank_switch() {
+  // 4 x 32KB, 4 x 8KB
+  let mut gxrom = cart(66, 8, 4);
+  gxrom.assert_prg_bank(0x8000, 0, 0x8000);
+  gxrom.assert_chr_bank(0x0000, 0, 0x2000);
+
+  gxrom.write(0x8000, 0b0010_0011);
+  gxrom.assert_prg_bank(0x8000, 2, 0x8000);
+  gxrom.assert_chr_bank(0x0000, 3, 0x2000);
+}
+
+#[test]
+fn test_color_dreams_bank_switch() {
+  // 4 x 32KB, 16 x 8KB
+  let mut color_dreams = cart(11, 8, 16);
+  color_dreams.write(0x8000, 0b1010_0011);
+  color_dreams.assert_prg_bank(0x8000, 3, 0x8000);
+  color_dreams.assert_chr_bank(0x0000, 10, 0x2000);
+}
+
+#[test]
+fn test_jaleco_j87_swaps_the_chr_bank_bits() {
+  let mut jaleco = cart(87, 2, 4);
+  jaleco.assert_prg_bank(0x8000, 0, 0x8000);
+  jaleco.assert_chr_bank(0x0000, 0, 0x2000);
+
+  jaleco.write(0x6000, 0b01);
+  jaleco.assert_chr_bank(0x0000, 2, 0x2000);
+  jaleco.write(0x7fff, 0b10);
+  jaleco.assert_chr_bank(0x0000, 1, 0x2000);
+  // not a ROM register
+  jaleco.write(0x8000, 0b11);
+  jaleco.assert_chr_bank(0x0000, 1, 0x2000);
+}
+
+#[test]
+fn test_sunsoft1_4k_chr_banks() {
+  let mut sunsoft = cart(184, 2, 4);
+  sunsoft.write(0x6000, 0b0101_0010);
+  sunsoft.assert_chr_bank(0x0000, 2, 0x1000);
+  sunsoft.assert_chr_bank(0x1000, 5, 0x1000);
+  sunsoft.assert_prg_bank(0xc000, 1, 0x4000);
+}
+
+#[test]
+fn test_for_rom_picks_the_mapper() {
+  for &number in &[0, 1, 2, 3, 4, 5, 7, 11, 19, 31, 66, 69, 71, 87, 180, 184] {
+    assert!(MapperTestHarness::new(SyntheticHeader {
+      mapper: number,
+      ..SyntheticHeader::default()
+    })
+    .is_ok());
+  }
+}
+
+// 16 8KB PRG banks, 256 1KB CHR banks, and all the PRG RAM MMC5 can have
+fn mmc5() -> MapperTestHarness {
+  MapperTestHarness::new(SyntheticHeader {
+    mapper: 5,
+    prg_rom_banks: 8,
+    chr_rom_banks: 32,
+    prg_ram_size: 0x10000,
+    ..SyntheticHeader::default()
+  })
+  .unwrap()
+}
+
+fn tile_fetch(v: u16, column: u8) -> TileFetch {
+  TileFetch {
+    v,
+    column,
+    scanline: 0,
+    pattern_table: 0,
+    tall_sprites: false,
+  }
+}
+
+#[test]
+fn test_mmc5_prg_modes() {
+  let mut mmc5 = mmc5();
+  // four 8KB banks, the last one at $E000
+  mmc5.assert_prg_bank(0xe000, 15, 0x2000);
+  mmc5.write(0x5114, 0x83);
+  mmc5.assert_prg_bank(0x8000, 3, 0x2000);
+
+  // one 32KB bank, $5117's low bits ignored
+  mmc5.write(0x5100, 0);
+  mmc5.write(0x5117, 0x05);
+  mmc5.assert_prg_bank(0x8000, 1, 0x8000);
+
+  // two 16KB banks
+  mmc5.write(0x5100, 1);
+  mmc5.write(0x5115, 0x86);
+  mmc5.write(0x5117, 0x0a);
+  mmc5.assert_prg_bank(0x8000, 3, 0x4000);
+  mmc5.assert_prg_bank(0xc000, 5, 0x4000);
+
+  // 16KB, then 8KB and 8KB
+  mmc5.write(0x5100, 2);
+  mmc5.write(0x5116, 0x89);
+  mmc5.write(0x5117, 0x0c);
+  mmc5.assert_prg_bank(0x8000, 3, 0x4000);
+  mmc5.assert_prg_bank(0xc000, 9, 0x2000);
+  mmc5.assert_prg_bank(0xe000, 12, 0x2000);
+}
+
+#[test]
+fn test_mmc5_prg_ram_banks_and_write_protect() {
+  let mut mmc5 = mmc5();
+  mmc5.write(0x6000, 0x42);
+  assert_eq!(mmc5.mapper.prg_read(0x6000), 0);
+  mmc5.write(0x5102, 2);
+  mmc5.write(0x5103, 1);
+  mmc5.write(0x6000, 0x42);
+  assert_eq!(mmc5.mapper.prg_read(0x6000), 0x42);
+
+  // RAM bank 2 at $8000, as bit 7 of the bank is clear
+  mmc5.write(0x5114, 0x02);
+  mmc5.write(0x8001, 0x99);
+  mmc5.write(0x5113, 0x02);
+  assert_eq!(mmc5.mapper.prg_read(0x6001), 0x99);
+  assert_eq!(mmc5.mapper.prg_bank(0x8000), None);
+  assert_eq!(mmc5.mapper.prg_ram().unwrap().len(), 0x10000);
+
+  // $E000 is always ROM
+  mmc5.write(0x5117, 0x01);
+  mmc5.assert_prg_bank(0xe000, 1, 0x2000);
+}
+
+#[test]
+fn test_mmc5_chr_modes_and_sets() {
+  let mut mmc5 = mmc5();
+  // 8KB: bank 1 is 1KB banks 8-15
+  mmc5.write(0x5127, 1);
+  mmc5.assert_chr_bank(0x0000, 1, 0x2000);
+
+  // 4KB
+  mmc5.write(0x5101, 1);
+  mmc5.write(0x5123, 3);
+  mmc5.assert_chr_bank(0x0000, 3, 0x1000);
+  mmc5.assert_chr_bank(0x1000, 1, 0x1000);
+
+  // 1KB, set A then set B in both pattern tables
+  mmc5.write(0x5101, 3);
+  for register in 0..8u16 {
+    mmc5.write(0x5120 + register, register as u8 + 1);
+  }
+  mmc5.assert_chr_bank(0x1c00, 8, 0x400);
+  for register in 0..4u16 {
+    mmc5.write(0x5128 + register, register as u8 + 0x20);
+  }
+  mmc5.assert_chr_bank(0x0400, 0x21, 0x400);
+  mmc5.assert_chr_bank(0x1400, 0x21, 0x400);
+
+  // 8x16 sprites keep to set A and the background to set B
+  assert_eq!(mmc5.mapper.sprite_chr_read(0x1400, true), 6);
+  assert_eq!(mmc5.mapper.sprite_chr_read(0x1400, false), 0x21);
+  let row = mmc5.mapper.background_tile(
+    &TileFetch {
+      tall_sprites: true,
+      ..tile_fetch(0, 0)
+    },
+    &|_| 0,
+  );
+  assert_eq!((row.lo, row.hi), (0x20, 0x20));
+}
+
+#[test]
+fn test_mmc5_multiplier() {
+  let mut mmc5 = mmc5();
+  assert_eq!(mmc5.mapper.prg_read(0x5205), 0x01);
+  assert_eq!(mmc5.mapper.prg_read(0x5206), 0xfe);
+  mmc5.write(0x5205, 200);
+  mmc5.write(0x5206, 3);
+  assert_eq!(mmc5.mapper.prg_read(0x5205), 600u16 as u8);
+  assert_eq!(mmc5.mapper.prg_read(0x5206), 2);
+}
+
+#[test]
+fn test_mmc5_scanline_irq() {
+  let mut mmc5 = mmc5();
+  mmc5.write(0x5203, 3);
+  mmc5.write(0x5204, 0x80);
+  // lines with rendering off don't count
+  mmc5.mapper.ppu_scanline(0, false);
+  assert_eq!(mmc5.mapper.prg_read(0x5204), 0);
+
+  // the pre-render line starts the frame
+  assert!(!mmc5.pulse_a12());
+  assert_eq!(mmc5.mapper.prg_read(0x5204), 0x40);
+  assert!(!mmc5.pulse_a12());
+  assert!(!mmc5.pulse_a12());
+  assert!(mmc5.pulse_a12());
+
+  // debuggers can look without acknowledging it
+  assert_eq!(mmc5.mapper.prg_read(0x5204), 0xc0);
+  assert!(mmc5.mapper.irq_pending());
+  assert_eq!(mmc5.mapper.prg_read_mut(0x5204), 0xc0);
+  assert!(!mmc5.mapper.irq_pending());
+
+  // out of the frame at line 240
+  for _ in 4..=240 {
+    mmc5.pulse_a12();
+  }
+  assert_eq!(mmc5.mapper.prg_read(0x5204), 0);
+
+  // pending but disabled doesn't assert IRQ
+  mmc5.write(0x5204, 0);
+  for _ in 0..4 {
+    assert!(!mmc5.pulse_a12());
+  }
+  assert_eq!(mmc5.mapper.prg_read(0x5204), 0xc0);
+}
+
+#[test]
+fn test_mmc5_exram_and_fill_nametables() {
+  let mut mmc5 = mmc5();
+  mmc5.write(0x5105, 0b11_10_01_00);
+  assert_eq!(
+    mmc5.mapper.nametables().pages,
+    [
+      NametablePage::Ciram(0),
+      NametablePage::Ciram(1),
+      NametablePage::Cartridge(0),
+      NametablePage::Cartridge(1),
+    ]
+  );
+  mmc5.write(0x5106, 0x42);
+  mmc5.write(0x5107, 2);
+  assert_eq!(mmc5.mapper.nametable_read(0x0400), 0x42);
+  assert_eq!(mmc5.mapper.nametable_read(0x07c0), 0xaa);
+
+  // as CPU RAM it's no nametable
+  assert!(!mmc5.mapper.prg_mapped(0x5c00));
+  mmc5.write(0x5104, 2);
+  mmc5.write(0x5c00, 7);
+  assert_eq!(mmc5.mapper.prg_read(0x5c00), 7);
+  assert_eq!(mmc5.mapper.nametable_read(0x0000), 0);
+  mmc5.write(0x5104, 3);
+  mmc5.write(0x5c00, 8);
+  assert_eq!(mmc5.mapper.prg_read(0x5c00), 7);
+
+  // as a nametable, the CPU only writes it while the PPU renders
+  mmc5.write(0x5104, 0);
+  assert_eq!(mmc5.mapper.nametable_read(0x0000), 7);
+  mmc5.write(0x5c01, 9);
+  assert_eq!(mmc5.mapper.nametable_read(0x0001), 0);
+  mmc5.pulse_a12();
+  mmc5.write(0x5c01, 9);
+  assert_eq!(mmc5.mapper.nametable_read(0x0001), 9);
+}
+
+#[test]
+fn test_mmc5_extended_attributes() {
+  let mut mmc5 = mmc5();
+  // palette 2 and 4KB bank 3 for the tile at $2005
+  mmc5.write(0x5104, 2);
+  mmc5.write(0x5c05, 0b1000_0011);
+  mmc5.write(0x5104, 1);
+  let nametable = |addr| if addr == 0x2005 { 0x41 } else { 0 };
+  // tile $41 is at $410 in the bank, 1KB bank 13
+  assert_eq!(
+    mmc5
+      .mapper
+      .background_tile(&tile_fetch(0x0005, 5), &nametable),
+    TileRow {
+      lo: 13,
+      hi: 13,
+      palette: 2,
+    }
+  );
+}
+
+#[test]
+fn test_mmc5_vertical_split() {
+  let mut mmc5 = mmc5();
+  // the 4 columns on the left from ExRAM and 4KB bank 2, scrolled 8 down
+  mmc5.write(0x5200, 0x80 | 4);
+  mmc5.write(0x5201, 8);
+  mmc5.write(0x5202, 2);
+  mmc5.pulse_a12();
+  mmc5.write(0x5c22, 0x41);
+  mmc5.write(0x5fc0, 0b0000_1100);
+  let row = mmc5.mapper.background_tile(&tile_fetch(0, 2), &|_| 0);
+  assert_eq!(
+    row,
+    TileRow {
+      lo: 9,
+      hi: 9,
+      palette: 3,
+    }
+  );
+  // the rest of the line is the usual background
+  let row = mmc5.mapper.background_tile(&tile_fetch(0, 4), &|_| 0);
+  assert_eq!(row.lo, 0);
+  assert_eq!(row.palette, 0);
+}
+
+fn mmc3_header(submapper: u8) -> SyntheticHeader {
+  SyntheticHeader {
+    mapper: 4,
+    submapper,
+    prg_rom_banks: 8,
+    chr_rom_banks: 32,
+    ..SyntheticHeader::default()
+  }
+}
+
+fn mmc3(submapper: u8) -> MapperTestHarness {
+  MapperTestHarness::new(mmc3_header(submapper)).unwrap()
+}
+
+fn mmc3_write(mmc3: &mut MapperTestHarness, register: u8, value: u8) {
+  mmc3.write(0x8000, register);
+  mmc3.write(0x8001, value);
+}
+
+#[test]
+fn test_mmc3_prg_modes() {
+  let mut mmc3 = mmc3(0);
+  mmc3_write(&mut mmc3, 6, 3);
+  mmc3_write(&mut mmc3, 7, 5);
+  for &(addr, bank) in &[(0x8000, 3), (0xa000, 5), (0xc000, 14), (0xe000, 15)] {
+    mmc3.assert_prg_bank(addr, bank, 0x2000);
+  }
+
+  // R6 and the second-last bank trade places
+  mmc3.write(0x8000, 0x40);
+  for &(addr, bank) in &[(0x8000, 14), (0xa000, 5), (0xc000, 3), (0xe000, 15)] {
+    mmc3.assert_prg_bank(addr, bank, 0x2000);
+  }
+}
+
+#[test]
+fn test_mmc3_chr_banks_and_inversion() {
+  let mut mmc3 = mmc3(0);
+  // 2KB, low bit ignored
+  mmc3_write(&mut mmc3, 0, 9);
+  mmc3_write(&mut mmc3, 2, 20);
+  mmc3_write(&mut mmc3, 5, 23);
+  mmc3.assert_chr_bank(0x0000, 4, 0x800);
+  mmc3.assert_chr_bank(0x1000, 20, 0x400);
+  mmc3.assert_chr_bank(0x1c00, 23, 0x400);
+
+  // A12 inverted: the 2KB banks move to $1000
+  mmc3.write(0x8000, 0x80);
+  mmc3.assert_chr_bank(0x0000, 20, 0x400);
+  mmc3.assert_chr_bank(0x1000, 4, 0x800);
+  mmc3.assert_chr_bank(0x0c00, 23, 0x400);
+}
+
+#[test]
+fn test_mmc3_mirroring_and_prg_ram() {
+  let mut mmc3 = mmc3(0);
+  assert_eq!(mmc3.mapper.mirroring(), Mirroring::Vertical);
+  mmc3.write(0xa000, 1);
+  assert_eq!(mmc3.mapper.mirroring(), Mirroring::Horizontal);
+
+  mmc3.write(0x6000, 0x42);
+  assert_eq!(mmc3.mapper.prg_read(0x6000), 0x42);
+  // write protected
+  mmc3.write(0xa001, 0xc0);
+  mmc3.write(0x6000, 0x43);
+  assert_eq!(mmc3.mapper.prg_read(0x6000), 0x42);
+  // disabled
+  mmc3.write(0xa001, 0x00);
+  assert!(!mmc3.mapper.prg_mapped(0x6000));
+}
+
+// `count` rises of A12
+fn mmc3_lines(mmc3: &mut MapperTestHarness, count: u16) -> Vec<bool> {
+  (0..count)
+    .map(|_| {
+      let fired = mmc3.pulse_a12();
+      // acknowledge, leave enabled
+      mmc3.write(0xe000, 0);
+      mmc3.write(0xe001, 0);
+      fired
+    })
+    .collect()
+}
+
+#[test]
+fn test_mmc3_scanline_irq() {
+  let mut mmc3 = mmc3(0);
+  mmc3.write(0xc000, 2);
+  mmc3.write(0xc001, 0);
+  mmc3.write(0xe001, 0);
+  // reload to 2, then 1, then 0
+  assert_eq!(
+    mmc3_lines(&mut mmc3, 6),
+    [false, false, true, false, false, true]
+  );
+
+  // nothing counts with rendering off or in vblank
+  mmc3.write(0xc001, 0);
+  mmc3.mapper.ppu_scanline(10, false);
+  mmc3.mapper.ppu_scanline(241, true);
+  assert_eq!(mmc3_lines(&mut mmc3, 3), [false, false, true]);
+
+  // disabled: counts, doesn't fire
+  mmc3.write(0xe000, 0);
+  for _ in 0..3 {
+    assert!(!mmc3.pulse_a12());
+  }
+}
+
+#[test]
+fn test_mmc3_irq_variants_by_submapper() {
+  let variant = |submapper| {
+    let rom = Rom::from_bytes(&mmc3_header(submapper).to_image()).unwrap();
+    Mmc3::new(rom).irq_variant()
+  };
+  assert_eq!(variant(0), Mmc3Irq::New);
+  assert_eq!(variant(3), Mmc3Irq::Old);
+  assert_eq!(variant(4), Mmc3Irq::Old);
+
+  // a latch of 0: the new chips fire on every line, the old ones only
+  // right after $C001
+  for &(submapper, expected) in &[(0, [true, true, true]), (4, [true, false, false])] {
+    let mut mmc3 = mmc3(submapper);
+    mmc3.write(0xc000, 0);
+    mmc3.write(0xc001, 0);
+    mmc3.write(0xe001, 0);
+    assert_eq!(
+      mmc3_lines(&mut mmc3, 3),
+      expected,
+      "submapper {}",
+      submapper
+    );
+  }
+
+  // counting down to 0 fires on both
+  let mut old = mmc3(4);
+  old.write(0xc000, 1);
+  old.write(0xc001, 0);
+  old.write(0xe001, 0);
+  assert_eq!(mmc3_lines(&mut old, 4), [false, true, false, true]);
+}
+
+fn fme7() -> MapperTestHarness {
+  cart(69, 8, 32)
+}
+
+fn fme7_command(fme7: &mut MapperTestHarness, command: u8, value: u8) {
+  fme7.write(0x8000, command);
+  fme7.write(0xa000, value);
+}
+
+#[test]
+fn test_fme7_prg_and_chr_banks() {
+  let mut fme7 = fme7();
+  for (command, bank) in (0..8).zip(&[7, 6, 5, 4, 3, 2, 1, 200]) {
+    fme7_command(&mut fme7, command, *bank);
+  }
+  for &(addr, bank) in &[(0x0000, 7), (0x0400, 6), (0x1800, 1), (0x1c00, 200)] {
+    fme7.assert_chr_bank(addr, bank, 0x400);
+  }
+
+  fme7_command(&mut fme7, 9, 3);
+  fme7_command(&mut fme7, 0xa, 4);
+  fme7_command(&mut fme7, 0xb, 0x45);
+  fme7.assert_prg_bank(0x8000, 3, 0x2000);
+  fme7.assert_prg_bank(0xa000, 4, 0x2000);
+  // 6 bits, past the end wraps
+  fme7.assert_prg_bank(0xc000, 5, 0x2000);
+  fme7.assert_prg_bank(0xe000, 15, 0x2000);
+}
+
+#[test]
+fn test_fme7_rom_or_ram_at_6000() {
+  let mut fme7 = fme7();
+  fme7_command(&mut fme7, 8, 9);
+  fme7.assert_prg_bank(0x6000, 9, 0x2000);
+  fme7.write(0x6000, 0x42);
+  fme7.assert_prg_bank(0x6000, 9, 0x2000);
+
+  // RAM, but not enabled: nothing answers
+  fme7_command(&mut fme7, 8, 0x40);
+  assert!(!fme7.mapper.prg_mapped(0x6000));
+  fme7_command(&mut fme7, 8, 0xc0);
+  fme7.write(0x6000, 0x42);
+  assert_eq!(fme7.mapper.prg_read(0x6000), 0x42);
+  assert!(fme7.mapper.prg_bank(0x6000).is_none());
+}
+
+#[test]
+fn test_fme7_mirroring() {
+  let mut fme7 = fme7();
+  for (value, mirroring) in [
+    Mirroring::Vertical,
+    Mirroring::Horizontal,
+    Mirroring::SingleScreenLower,
+    Mirroring::SingleScreenUpper,
+  ]
+  .iter()
+  .enumerate()
+  {
+    fme7_command(&mut fme7, 0xc, value as u8);
+    assert_eq!(fme7.mapper.mirroring(), *mirroring);
+  }
+}
+
+#[test]
+fn test_fme7_cpu_cycle_irq() {
+  let mut fme7 = fme7();
+  fme7_command(&mut fme7, 0xe, 0x10);
+  fme7_command(&mut fme7, 0xf, 0x00);
+  // counting, but not firing
+  fme7_command(&mut fme7, 0xd, 0x80);
+  fme7.mapper.cpu_cycles(100);
+  assert!(!fme7.mapper.irq_pending());
+
+  fme7_command(&mut fme7, 0xe, 0x10);
+  fme7_command(&mut fme7, 0xf, 0x00);
+  fme7_command(&mut fme7, 0xd, 0x81);
+  // 16 down to 0, then the wrap to $FFFF
+  fme7.mapper.cpu_cycles(16);
+  assert!(!fme7.mapper.irq_pending());
+  fme7.mapper.cpu_cycles(1);
+  assert!(fme7.mapper.irq_pending());
+
+  // writing the control register acknowledges
+  fme7_command(&mut fme7, 0xd, 0x01);
+  assert!(!fme7.mapper.irq_pending());
+  // and without bit 7 nothing counts
+  fme7.mapper.cpu_cycles(0x20000);
+  assert!(!fme7.mapper.irq_pending());
+}
+
+fn fme7_audio(fme7: &mut MapperTestHarness, register: u8, value: u8) {
+  fme7.write(0xc000, register);
+  fme7.write(0xe000, value);
+}
+
+#[test]
+fn test_fme7_5b_square_channels() {
+  let mut fme7 = fme7();
+  assert_eq!(fme7.mapper.audio_level(), 0);
+  // channel A, period 2: toggles every 32 cycles
+  fme7_audio(&mut fme7, 0x00, 2);
+  fme7_audio(&mut fme7, 0x07, 0b0011_1110);
+  fme7_audio(&mut fme7, 0x08, 0x0f);
+  let levels: Vec<u32> = (0..4)
+    .map(|_| {
+      fme7.mapper.cpu_cycles(32);
+      fme7.mapper.audio_level()
+    })
+    .collect();
+  let loud = levels[0];
+  assert!(loud > 0);
+  assert_eq!(levels, [loud, 0, loud, 0]);
+
+  // each volume step is 3dB
+  fme7_audio(&mut fme7, 0x08, 0x0e);
+  fme7.mapper.cpu_cycles(32);
+  let quieter = fme7.mapper.audio_level();
+  assert!((quieter as f64 / loud as f64 - 0.708).abs() < 0.01);
+
+  // tone and noise both off: a flat level, for samples
+  fme7_audio(&mut fme7, 0x07, 0b0011_1111);
+  fme7_audio(&mut fme7, 0x09, 0x0f);
+  assert_eq!(fme7.mapper.audio_level(), quieter + loud);
+  fme7.mapper.cpu_cycles(32);
+  assert_eq!(fme7.mapper.audio_level(), quieter + loud);
+}
+
+#[test]
+fn test_fme7_5b_envelope() {
+  let mut fme7 = fme7();
+  fme7_audio(&mut fme7, 0x07, 0b0011_1111);
+  fme7_audio(&mut fme7, 0x08, 0x10);
+  fme7_audio(&mut fme7, 0x0b, 1);
+  // attack once, then hold at the top
+  fme7_audio(&mut fme7, 0x0d, 0b1101);
+  let mut last = fme7.mapper.audio_level();
+  for _ in 0..31 {
+    fme7.mapper.cpu_cycles(16);
+    assert!(fme7.mapper.audio_level() > last);
+    last = fme7.mapper.audio_level();
+  }
+  fme7.mapper.cpu_cycles(16 * 100);
+  assert_eq!(fme7.mapper.audio_level(), last);
+
+  // decay once, then silence
+  fme7_audio(&mut fme7, 0x0d, 0b0000);
+  assert_eq!(fme7.mapper.audio_level(), last);
+  fme7.mapper.cpu_cycles(16 * 32);
+  assert_eq!(fme7.mapper.audio_level(), 0);
+}
+
+#[test]
+fn test_fme7_state_round_trip() {
+  let mut fme7 = fme7();
+  fme7_command(&mut fme7, 9, 5);
+  fme7_command(&mut fme7, 0xd, 0x81);
+  fme7_audio(&mut fme7, 0x08, 0x0f);
+  fme7.mapper.cpu_cycles(1000);
+
+  let mut restored = fme7.reloaded();
+  for cart in [&mut fme7, &mut restored].iter_mut() {
+    cart.mapper.cpu_cycles(0x10000);
+  }
+  restored.assert_prg_bank(0x8000, 5, 0x2000);
+  assert_eq!(restored.mapper.irq_pending(), fme7.mapper.irq_pending());
+  assert_eq!(restored.mapper.audio_level(), fme7.mapper.audio_level());
+}
+
+fn namco163() -> MapperTestHarness {
+  cart(19, 8, 32)
+}
+
+#[test]
+fn test_namco163_prg_and_chr_banks() {
+  let mut n163 = namco163();
+  for slot in 0..8 {
+    n163.write(0x8000 + slot * 0x800, 0x80 + slot as u8);
+  }
+  for slot in 0..8 {
+    n163.assert_chr_bank(slot * 0x400, 0x80 + slot as usize, 0x400);
+  }
+
+  n163.write(0xe000, 0x41);
+  n163.write(0xe800, 2);
+  n163.write(0xf000, 0x43);
+  for &(addr, bank) in &[(0x8000, 1), (0xa000, 2), (0xc000, 3), (0xe000, 15)] {
+    n163.assert_prg_bank(addr, bank, 0x2000);
+  }
+}
+
+#[test]
+fn test_namco163_nametables_from_ciram_or_chr_rom() {
+  let mut n163 = namco163();
+  assert_eq!(n163.mapper.mirroring(), Mirroring::Vertical);
+  for (table, &bank) in [0xe0, 0xe0, 0xe1, 0xe1].iter().enumerate() {
+    n163.write(0xc000 + table as u16 * 0x800, bank);
+  }
+  assert_eq!(n163.mapper.mirroring(), Mirroring::Horizontal);
+
+  n163.write(0xd800, 0x15);
+  assert_eq!(
+    n163.mapper.nametables().pages,
+    [
+      NametablePage::Ciram(0),
+      NametablePage::Ciram(0),
+      NametablePage::Ciram(1),
+      NametablePage::Cartridge(3),
+    ]
+  );
+  let offset = match n163.mapper.nametables().translate(0x2c10) {
+    NametableTarget::Cartridge(offset) => offset,
+    target => panic!("{:?}", target),
+  };
+  assert_eq!(n163.mapper.nametable_read(offset), 0x15);
+  // CHR ROM doesn't take writes
+  n163.mapper.nametable_write(offset, 0);
+  assert_eq!(n163.mapper.nametable_read(offset), 0x15);
+}
+
+#[test]
+fn test_namco163_internal_ram_auto_increment() {
+  let mut n163 = namco163();
+  n163.write(0xf800, 0x80 | 0x7e);
+  for value in 1..=3 {
+    n163.write(0x4800, value);
+  }
+  // wraps within the 128 bytes
+  n163.write(0xf800, 0x7e);
+  assert_eq!(n163.mapper.prg_read_mut(0x4800), 1);
+  assert_eq!(n163.mapper.prg_read_mut(0x4800), 1);
+  n163.write(0xf800, 0x80 | 0x7f);
+  assert_eq!(n163.mapper.prg_read_mut(0x4800), 2);
+  assert_eq!(n163.mapper.prg_read_mut(0x4800), 3);
+  // debuggers peek without moving on
+  n163.write(0xf800, 0x80 | 0x7e);
+  assert_eq!(n163.mapper.prg_read(0x4800), 1);
+  assert_eq!(n163.mapper.prg_read(0x4800), 1);
+}
+
+#[test]
+fn test_namco163_prg_ram_write_protection() {
+  let mut n163 = namco163();
+  n163.write(0x6000, 0x42);
+  assert_eq!(n163.mapper.prg_read(0x6000), 0);
+  // writes on, but $6800-$6FFF protected
+  n163.write(0xf800, 0x42);
+  n163.write(0x6000, 0x42);
+  n163.write(0x6800, 0x42);
+  assert_eq!(n163.mapper.prg_read(0x6000), 0x42);
+  assert_eq!(n163.mapper.prg_read(0x6800), 0);
+}
+
+#[test]
+fn test_namco163_irq_counts_up_to_7fff() {
+  let mut n163 = namco163();
+  n163.write(0x5000, 0xf0);
+  n163.write(0x5800, 0x7f);
+  n163.mapper.cpu_cycles(100);
+  assert_eq!(n163.mapper.prg_read(0x5000), 0xf0);
+
+  n163.write(0x5800, 0xff);
+  n163.mapper.cpu_cycles(14);
+  assert!(!n163.mapper.irq_pending());
+  n163.mapper.cpu_cycles(1);
+  assert!(n163.mapper.irq_pending());
+  // and stays there
+  n163.mapper.cpu_cycles(100);
+  assert_eq!(
+    (n163.mapper.prg_read(0x5800), n163.mapper.prg_read(0x5000)),
+    (0xff, 0xff)
+  );
+
+  n163.write(0x5000, 0);
+  assert!(!n163.mapper.irq_pending());
+}
+
+// a 4-sample wave, 15 0 0 0, on channel 7 at `frequency`
+fn namco163_wave(n163: &mut MapperTestHarness, frequency: u32, enabled: u8) {
+  n163.write(0xf800, 0x80);
+  n163.write(0x4800, 0x0f);
+  n163.write(0xf800, 0x80 | 0x78);
+  for &value in &[
+    frequency as u8,
+    0,
+    (frequency >> 8) as u8,
+    0,
+    0xfc | (frequency >> 16) as u8,
+    0,
+    0,
+    (enabled - 1) << 4 | 0x0f,
+  ] {
+    n163.write(0x4800, value);
+  }
+}
+
+#[test]
+fn test_namco163_wavetable_channel() {
+  let mut n163 = namco163();
+  assert_eq!(n163.mapper.audio_level(), 0);
+  namco163_wave(&mut n163, 0x10000, 1);
+  let levels: Vec<u32> = (0..5)
+    .map(|_| {
+      n163.mapper.cpu_cycles(15);
+      n163.mapper.audio_level()
+    })
+    .collect();
+  let loud = 15 * 15 * 87;
+  assert_eq!(levels, [0, 0, 0, loud, 0]);
+
+  // half a sample per update
+  namco163_wave(&mut n163, 0x8000, 1);
+  n163.mapper.cpu_cycles(15);
+  assert_eq!(n163.mapper.audio_level(), loud);
+  n163.mapper.cpu_cycles(15);
+  assert_eq!(n163.mapper.audio_level(), 0);
+
+  // sound off
+  n163.write(0xe000, 0x40);
+  n163.mapper.cpu_cycles(15 * 8);
+  assert_eq!(n163.mapper.audio_level(), 0);
+}
+
+#[test]
+fn test_namco163_channels_share_the_chip() {
+  let mut n163 = namco163();
+  namco163_wave(&mut n163, 0, 2);
+  // channel 7 sits on its loud first sample, channel 6 is silent
+  n163.mapper.cpu_cycles(15);
+  assert_eq!(n163.mapper.audio_level(), 15 * 15 * 87 / 2);
+  // with two on, channel 7 only moves every other update
+  namco163_wave(&mut n163, 0x10000, 2);
+  n163.mapper.cpu_cycles(15);
+  assert_eq!(n163.mapper.audio_level(), 15 * 15 * 87 / 2);
+  n163.mapper.cpu_cycles(15);
+  assert_eq!(n163.mapper.audio_level(), 0);
+}
+
+#[test]
+fn test_namco163_state_round_trip() {
+  let mut n163 = namco163();
+  n163.write(0xe000, 5);
+  n163.write(0x5800, 0x80);
+  namco163_wave(&mut n163, 0x4000, 1);
+  n163.mapper.cpu_cycles(1000);
+
+  let mut restored = n163.reloaded();
+  for cart in [&mut n163, &mut restored].iter_mut() {
+    cart.mapper.cpu_cycles(0x8000);
+  }
+  restored.assert_prg_bank(0x8000, 5, 0x2000);
+  assert_eq!(restored.mapper.irq_pending(), n163.mapper.irq_pending());
+  assert!(restored.mapper.irq_pending());
+  assert_eq!(restored.mapper.audio_level(), n163.mapper.audio_level());
+}
@@ -0,0 +1,124 @@
+use flemu_core::nes::bus::{Bus, Mem};
+use flemu_core::nes::cartridge::{Mirroring, Rom, PRG_ROM_PAGE_SIZE};
+use flemu_core::nes::memory_map::*;
+
+fn region(start: u16, end: u16, description: &str) -> Region {
+  Region {
+    start,
+    end,
+    description: description.to_string(),
+  }
+}
+
+fn uxrom_bus() -> Bus {
+  let mut rom = Rom::from_program(&[]);
+  rom.prg_rom = vec![0; 4 * PRG_ROM_PAGE_SIZE];
+  rom.chr_rom = vec![];
+  rom.info.mapper = 2;
+  rom.info.mirroring = Mirroring::Vertical;
+  Bus::with_rom(rom).unwrap()
+}
+
+#[test]
+fn test_cpu_map_without_cartridge() {
+  let map = cpu_map(&Bus::new());
+  assert_eq!(map[0], region(0x0000, 0x07ff, "RAM"));
+  assert_eq!(map[2], region(0x2000, 0x2007, "PPU registers"));
+  assert_eq!(
+    map.last().unwrap(),
+    &region(0x8000, 0xffff, "empty cartridge slot")
+  );
+  // covers every address exactly once
+  for pair in map.windows(2) {
+    assert_eq!(pair[0].end as u32 + 1, pair[1].start as u32);
+  }
+}
+
+#[test]
+fn test_cpu_map_follows_bank_switches() {
+  let mut bus = uxrom_bus();
+  let map = cpu_map(&bus);
+  let n = map.len();
+  assert_eq!(
+    map[n - 2],
+    region(0x8000, 0xbfff, "PRG ROM bank 0 (16KB, UxROM)")
+  );
+  assert_eq!(
+    map[n - 1],
+    region(0xc000, 0xffff, "PRG ROM bank 3 (16KB, UxROM)")
+  );
+
+  bus.mem_write(0x8000, 2);
+  assert_eq!(
+    describe_cpu_address(&bus, 0x9234),
+    "PRG ROM bank 2 (16KB, UxROM)"
+  );
+}
+
+#[test]
+fn test_describe_cpu_address() {
+  let bus = Bus::new();
+  assert_eq!(describe_cpu_address(&bus, 0x0012), "RAM");
+  assert_eq!(describe_cpu_address(&bus, 0x0812), "RAM mirror of $0012");
+  assert_eq!(describe_cpu_address(&bus, 0x2002), "PPUSTATUS");
+  assert_eq!(describe_cpu_address(&bus, 0x3ffa), "PPUSTATUS mirror");
+  assert_eq!(describe_cpu_address(&bus, 0x4016), "JOY1");
+}
+
+#[test]
+fn test_ppu_map() {
+  let bus = uxrom_bus();
+  let map = ppu_map(&bus);
+  assert_eq!(
+    map[0],
+    region(0x0000, 0x0fff, "pattern table 0, CHR bank 0 (8KB, UxROM)")
+  );
+  assert_eq!(map[2], region(0x2000, 0x23ff, "nametable 0, CIRAM A"));
+  assert_eq!(map[3], region(0x2400, 0x27ff, "nametable 1, CIRAM B"));
+  assert_eq!(
+    describe_ppu_address(&bus, 0x3c10),
+    "nametable 3 mirror, CIRAM B"
+  );
+  assert_eq!(describe_ppu_address(&bus, 0x3f11), "palette RAM");
+  assert_eq!(
+    map.last().unwrap(),
+    &region(0x3f20, 0x3fff, "palette RAM mirrors")
+  );
+}
+
+#[test]
+fn test_read_and_write_either_space() {
+  let mut bus = uxrom_bus();
+  write_byte(&mut bus, AddressSpace::Cpu, 0x0010, 0x42);
+  write_byte(&mut bus, AddressSpace::Cpu, 0xffff, 0x99); // a bank switch
+  assert_eq!(
+    read_range(&bus, AddressSpace::Cpu, 0x080f, 3),
+    vec![0x00, 0x42, 0x00]
+  );
+  assert_eq!(
+    read_range(&bus, AddressSpace::Ppu, 0x0000, 2),
+    vec![0x00, 0x00]
+  );
+
+  // CHR RAM, a nametable through its mirror, the palette
+  write_byte(&mut bus, AddressSpace::Ppu, 0x0001, 0x11);
+  write_byte(&mut bus, AddressSpace::Ppu, 0x2800, 0x22);
+  write_byte(&mut bus, AddressSpace::Ppu, 0x3f1f, 0x0f);
+  assert_eq!(
+    read_range(&bus, AddressSpace::Ppu, 0x0000, 2),
+    vec![0x00, 0x11]
+  );
+  assert_eq!(read_range(&bus, AddressSpace::Ppu, 0x2000, 1), vec![0x22]);
+  // $3FFF mirrors $3F1F, then it wraps around to the pattern tables
+  assert_eq!(
+    read_range(&bus, AddressSpace::Ppu, 0x3fff, 3),
+    vec![0x0f, 0x00, 0x11]
+  );
+  assert_eq!(
+    read_range(&bus, AddressSpace::Ppu, 0, 0x10000).len(),
+    0x4000
+  );
+
+  assert_eq!(AddressSpace::from_id("ppu"), Some(AddressSpace::Ppu));
+  assert_eq!(AddressSpace::from_id("apu"), None);
+}
@@ -0,0 +1,187 @@
+mod common;
+
+use common::counter;
+use flemu_core::nes::cartridge::Rom;
+use flemu_core::nes::cpu::CPU;
+use flemu_core::nes::joypad::{JoypadButton, TurboRate};
+use flemu_core::nes::movie::*;
+use flemu_core::nes::savestate::StateError;
+
+const FM2: &str = "version 3
+emuVersion 22020
+rerecordCount 17
+palFlag 0
+romFilename smb
+romChecksum base64:jjYwGG411HcjG/j9UOVM3Q==
+guid 452DE2C3-EF43-2FA9-77AC-0677FC51543B
+fourscore 0
+port0 1
+port1 1
+port2 0
+comment author someone
+|2|........|........||
+|0|R......A|........||
+|1|.L..T.B.|RLDUTSBA||
+|0|   U   A|........||
+";
+
+#[test]
+fn test_fm2_import() {
+  let movie = Movie::from_fm2(FM2).unwrap();
+  assert_eq!(movie.start, MovieStart::PowerOn);
+  assert_eq!(movie.rerecords, 17);
+  assert!(!movie.pal && !movie.four_score);
+  assert_eq!(movie.len(), 4);
+  assert_eq!(movie.frames[0].commands, POWER);
+  assert_eq!(
+    movie.frames[1].buttons[0],
+    JoypadButton::RIGHT | JoypadButton::A
+  );
+  assert_eq!(movie.frames[2].commands, RESET);
+  assert_eq!(
+    movie.frames[2].buttons[0],
+    JoypadButton::LEFT | JoypadButton::START | JoypadButton::B
+  );
+  assert_eq!(movie.frames[2].buttons[1], JoypadButton::all());
+  // spaces are buttons that are up, like dots
+  assert_eq!(
+    movie.frames[3].buttons[0],
+    JoypadButton::UP | JoypadButton::A
+  );
+  assert_eq!(
+    movie.header[0],
+    ("emuVersion".to_string(), "22020".to_string())
+  );
+  assert!(movie
+    .header
+    .contains(&("comment".to_string(), "author someone".to_string())));
+}
+
+#[test]
+fn test_fm2_round_trip() {
+  let movie = Movie::from_fm2(FM2).unwrap();
+  let text = movie.to_fm2();
+  assert!(text.contains("|1|.L..T.B.|RLDUTSBA||\n"));
+  assert!(text.contains("romChecksum base64:jjYwGG411HcjG/j9UOVM3Q==\n"));
+  assert_eq!(Movie::from_fm2(&text).unwrap(), movie);
+
+  let mut movie = Movie::new(MovieStart::State(vec![1, 2, 3, 4, 5]), true, true);
+  movie.frames.push(MovieFrame::default());
+  movie.frames[0].buttons[3] = JoypadButton::SELECT;
+  let text = movie.to_fm2();
+  assert!(text.contains("flemuSavestate base64:AQIDBAU=\n"));
+  assert!(text.contains("|0|........|........|........|.....S..||\n"));
+  assert_eq!(Movie::from_fm2(&text).unwrap(), movie);
+}
+
+#[test]
+fn test_fm2_errors() {
+  assert_eq!(
+    Movie::from_fm2("version 3\n|0|........|\n"),
+    Err(MovieError::Syntax { line: 2 })
+  );
+  assert_eq!(
+    Movie::from_fm2("version 2\n"),
+    Err(MovieError::Syntax { line: 1 })
+  );
+  // power cycles only at the start
+  assert_eq!(
+    Movie::from_fm2("|0|........|........||\n|2|........|........||\n"),
+    Err(MovieError::Syntax { line: 2 })
+  );
+  for header in &["binary 1", "port1 2", "savestate base64:AAAA"] {
+    match Movie::from_fm2(header) {
+      Err(MovieError::Unsupported(_)) => {}
+      other => panic!("{}: {:?}", header, other),
+    }
+  }
+}
+
+#[test]
+fn test_recording_replays_the_same_game() {
+  let mut cpu = counter();
+  let start = cpu.clone();
+  let mut movie = Movie::new(MovieStart::PowerOn, false, false);
+  for frame in 0..6 {
+    cpu.bus.joypads[0].set_button_pressed_status(JoypadButton::A, frame % 3 == 1);
+    movie.record(&cpu, if frame == 4 { RESET } else { 0 });
+    if frame == 4 {
+      cpu.reset();
+      cpu.program_counter = 0x8000;
+    }
+    cpu.run_frame();
+  }
+
+  let mut replay = start;
+  let mut index = 0;
+  while movie.play(index, &mut replay) {
+    replay.run_frame();
+    index += 1;
+  }
+  assert_eq!(index, 6);
+  assert_eq!(replay.register_x, cpu.register_x);
+  assert_eq!(replay.cycles, cpu.cycles);
+  assert_eq!(replay.program_counter, cpu.program_counter);
+}
+
+#[test]
+fn test_turbo_is_recorded_as_it_fires() {
+  let mut cpu = counter();
+  let start = cpu.clone();
+  let mut movie = Movie::new(MovieStart::PowerOn, false, false);
+  cpu.bus.joypads[0].set_turbo_pressed_status(JoypadButton::A, true);
+  for _ in 0..8 {
+    movie.record(&cpu, 0);
+    cpu.run_frame();
+  }
+  let fired: Vec<bool> = movie
+    .frames
+    .iter()
+    .map(|frame| frame.buttons[0].contains(JoypadButton::A))
+    .collect();
+  assert!(fired.contains(&true) && fired.contains(&false));
+
+  // played back with turbo A held at another rate, it's the recording that
+  // counts
+  let mut replay = start;
+  replay.bus.joypads[0].set_turbo_pressed_status(JoypadButton::A, true);
+  replay.bus.joypads[0].set_turbo_rate(TurboRate { on: 1, off: 1 });
+  let mut index = 0;
+  while movie.play(index, &mut replay) {
+    replay.run_frame();
+    index += 1;
+  }
+  assert_eq!(replay.register_x, cpu.register_x);
+}
+
+#[test]
+fn test_controller_latches_carry_over() {
+  let mut cpu = counter();
+  cpu.bus.joypads[0].set_button_pressed_status(JoypadButton::A | JoypadButton::B, true);
+  cpu.bus.joypads[0].write(1);
+  cpu.bus.joypads[0].write(0);
+  // A is out, B is next
+  cpu.bus.joypads[0].read();
+  let mut movie = Movie::new(MovieStart::PowerOn, false, false);
+  movie.latch_controllers(&cpu);
+  let text = movie.to_fm2();
+  assert!(text.contains("flemuControllers base64:"));
+  let movie = Movie::from_fm2(&text).unwrap();
+
+  let mut replay = counter();
+  movie.restore_controllers(&mut replay).unwrap();
+  let reads: Vec<u8> = (0..3).map(|_| replay.bus.joypads[0].read() & 1).collect();
+  assert_eq!(reads, vec![1, 0, 0]);
+
+  // they're the shift registers of this cartridge's console
+  let mut other = CPU::new();
+  other.load_rom(Rom::from_program(&[0xEA])).unwrap();
+  assert_eq!(
+    movie.restore_controllers(&mut other),
+    Err(StateError::WrongCartridge)
+  );
+  // movies from elsewhere have none, and leave the controllers alone
+  let movie = Movie::from_fm2(FM2).unwrap();
+  assert_eq!(movie.controllers, None);
+  assert_eq!(movie.restore_controllers(&mut other), Ok(()));
+}
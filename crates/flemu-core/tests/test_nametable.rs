@@ -0,0 +1,131 @@
+use flemu_core::nes::cartridge::{Mirroring, Rom};
+use flemu_core::nes::mapper;
+use flemu_core::nes::nametable::*;
+use flemu_core::nes::savestate::{StateReader, StateWriter};
+use NametableTarget::*;
+
+fn targets(map: &NametableMap) -> Vec<NametableTarget> {
+  vec![0x2000, 0x2400, 0x2800, 0x2c00]
+    .into_iter()
+    .map(|addr| map.translate(addr + 0x10))
+    .collect()
+}
+
+#[test]
+fn test_horizontal() {
+  let map = NametableMap::from_mirroring(Mirroring::Horizontal);
+  assert_eq!(
+    targets(&map),
+    vec![Ciram(0x010), Ciram(0x010), Ciram(0x410), Ciram(0x410)]
+  );
+}
+
+#[test]
+fn test_vertical() {
+  let map = NametableMap::from_mirroring(Mirroring::Vertical);
+  assert_eq!(
+    targets(&map),
+    vec![Ciram(0x010), Ciram(0x410), Ciram(0x010), Ciram(0x410)]
+  );
+}
+
+#[test]
+fn test_single_screen() {
+  let lower = NametableMap::from_mirroring(Mirroring::SingleScreenLower);
+  let upper = NametableMap::from_mirroring(Mirroring::SingleScreenUpper);
+  assert_eq!(targets(&lower), vec![Ciram(0x010); 4]);
+  assert_eq!(targets(&upper), vec![Ciram(0x410); 4]);
+}
+
+#[test]
+fn test_four_screen() {
+  let map = NametableMap::from_mirroring(Mirroring::FourScreen);
+  assert_eq!(
+    targets(&map),
+    vec![
+      Ciram(0x010),
+      Ciram(0x410),
+      Cartridge(0x010),
+      Cartridge(0x410)
+    ]
+  );
+}
+
+#[test]
+fn test_3000_mirrors_2000() {
+  let map = NametableMap::from_mirroring(Mirroring::Vertical);
+  assert_eq!(map.translate(0x3000), map.translate(0x2000));
+  assert_eq!(map.translate(0x3eff), map.translate(0x2eff));
+}
+
+#[test]
+fn test_custom_routing() {
+  // e.g. MMC5 with ExRAM as the third nametable
+  let map = NametableMap {
+    pages: [
+      NametablePage::Ciram(0),
+      NametablePage::Ciram(1),
+      NametablePage::Cartridge(0),
+      NametablePage::Ciram(0),
+    ],
+  };
+  assert_eq!(map.translate(0x2805), Cartridge(0x005));
+  assert_eq!(map.translate(0x2c05), Ciram(0x005));
+}
+
+#[test]
+fn test_four_screen_boards_bring_their_own_vram() {
+  // NROM, MMC1, UxROM, CNROM
+  for &number in &[0, 1, 2, 3] {
+    let mut rom = Rom::from_program(&[]);
+    rom.info.mapper = number;
+    rom.info.mirroring = Mirroring::FourScreen;
+    let mut cart = mapper::for_rom(rom).unwrap();
+    assert_eq!(cart.mirroring(), Mirroring::FourScreen);
+
+    let offsets: Vec<_> = [0x2801, 0x2c01]
+      .iter()
+      .map(|&addr| match cart.nametables().translate(addr) {
+        Cartridge(offset) => offset,
+        target => panic!("expected cartridge VRAM, got {:?}", target),
+      })
+      .collect();
+    cart.nametable_write(offsets[0], 0x42);
+    cart.nametable_write(offsets[1], 0x43);
+    assert_eq!(cart.nametable_read(offsets[0]), 0x42, "mapper {}", number);
+    assert_eq!(cart.nametable_read(offsets[1]), 0x43, "mapper {}", number);
+  }
+}
+
+#[test]
+fn test_four_screen_ignores_the_boards_mirroring() {
+  let mut rom = Rom::from_program(&[]);
+  rom.info.mapper = 1;
+  rom.info.mirroring = Mirroring::FourScreen;
+  let mut cart = mapper::for_rom(rom).unwrap();
+  // MMC1 control: one-screen lower
+  for _ in 0..5 {
+    cart.prg_write(0x8000, 0);
+  }
+  assert_eq!(
+    cart.nametables(),
+    NametableMap::from_mirroring(Mirroring::FourScreen)
+  );
+}
+
+#[test]
+fn test_four_screen_vram_is_in_savestates() {
+  let mut rom = Rom::from_program(&[]);
+  rom.info.mirroring = Mirroring::FourScreen;
+  let mut cart = mapper::for_rom(rom).unwrap();
+  cart.nametable_write(0x7ff, 0x42);
+
+  let mut w = StateWriter::new(0);
+  cart.save_state(&mut w);
+  let state = w.finish();
+  cart.nametable_write(0x7ff, 0);
+
+  let mut r = StateReader::new(&state, 0).unwrap();
+  cart.load_state(&mut r).unwrap();
+  assert_eq!(cart.nametable_read(0x7ff), 0x42);
+}
@@ -0,0 +1,156 @@
+use flemu_core::nes::cpu::*;
+use flemu_core::nes::rollback::RollbackBuffer;
+
+#[test]
+fn test_0xa9_lda_immidiate_load_data() {
+  let mut cpu = CPU::new();
+  cpu.load_and_run(vec![0xa9, 0x05, 0x00]).unwrap();
+  assert_eq!(cpu.register_a, 0x05);
+  assert!(cpu.status.bits() & 0b0000_0010 == 0b00);
+  assert!(cpu.status.bits() & 0b1000_0000 == 0);
+}
+
+#[test]
+fn test_0xaa_tax_move_a_to_x() {
+  let mut cpu = CPU::new();
+  cpu.load_and_run(vec![0xa9, 0x0a, 0xaa, 0x00]).unwrap();
+  assert_eq!(cpu.register_x, 0x0a)
+}
+
+#[test]
+fn test_5_ops_working_together() {
+  let mut cpu = CPU::new();
+  cpu
+    .load_and_run(vec![0xa9, 0xc0, 0xaa, 0xe8, 0x00])
+    .unwrap();
+
+  assert_eq!(cpu.register_x, 0xc1)
+}
+
+#[test]
+fn test_inx_overflow() {
+  let mut cpu = CPU::new();
+  cpu
+    .load_and_run(vec![0xa2, 0xff, 0xe8, 0xe8, 0x00])
+    .unwrap();
+
+  assert_eq!(cpu.register_x, 1)
+}
+
+#[test]
+fn test_lda_from_memory() {
+  let mut cpu = CPU::new();
+  cpu.mem_write(0x10, 0x55);
+
+  cpu.load(vec![0xa5, 0x10, 0x00]).unwrap();
+  cpu.run().unwrap();
+
+  assert_eq!(cpu.register_a, 0x55);
+}
+
+#[test]
+fn test_cpu_state_snapshot() {
+  let mut cpu = CPU::new();
+  cpu
+    .load_and_run(vec![0xa9, 0xc0, 0xaa, 0xe8, 0x00])
+    .unwrap();
+
+  let state = cpu.state();
+  assert_eq!(state.a, 0xc0);
+  assert_eq!(state.x, 0xc1);
+  assert_eq!(state.sp, 0xfd);
+  assert_eq!(state.flags, cpu.status.bits());
+  // LDA #imm (2) + TAX (2) + INX (2)
+  assert_eq!(state.cycles, 6);
+}
+
+#[test]
+fn test_rollback_snapshot_restore() {
+  let mut rollback = RollbackBuffer::new(2);
+  let mut cpu = CPU::new();
+  cpu.load_and_run(vec![0xa9, 0x05, 0x00]).unwrap();
+  rollback.snapshot(10, &cpu);
+
+  cpu.load_and_run(vec![0xa9, 0x07, 0x00]).unwrap();
+  rollback.snapshot(11, &cpu);
+  assert!(rollback.restore(10, &mut cpu));
+  assert_eq!(cpu.register_a, 0x05);
+
+  // frame 12 reuses frame 10's slot
+  rollback.snapshot(12, &cpu);
+  assert!(!rollback.restore(10, &mut cpu));
+  assert!(rollback.restore(11, &mut cpu));
+  assert_eq!(cpu.register_a, 0x07);
+}
+
+#[test]
+fn test_achievement_peek() {
+  use flemu_core::nes::achievements::{find_region, peek, RegionKind};
+
+  let mut cpu = CPU::new();
+  // SRAM lives on the cartridge
+  cpu.load(vec![0x00]).unwrap();
+  cpu.mem_write(0x0010, 0x34);
+  cpu.mem_write(0x0011, 0x12);
+  cpu.mem_write(0x6000, 0x99);
+
+  assert_eq!(peek(&cpu, 0x0010, 2), 0x1234);
+  // mirrors of system RAM
+  assert_eq!(peek(&cpu, 0x0810, 1), 0x34);
+  assert_eq!(peek(&cpu, 0x6000, 1), 0x99);
+  assert_eq!(peek(&cpu, 0x2002, 1), 0);
+  assert_eq!(peek(&cpu, 0x10000, 1), 0);
+  // only the sizes rcheevos asks for
+  assert_eq!(peek(&cpu, 0x0010, 3), 0);
+  assert_eq!(peek(&cpu, 0x0010, 8), 0);
+  assert_eq!(peek(&cpu, u32::MAX, 4), 0);
+
+  assert_eq!(find_region(0x6000).unwrap().kind, RegionKind::SaveRam);
+}
+
+#[test]
+fn test_achievement_hook_runs_once_a_frame() {
+  use flemu_core::nes::achievements;
+
+  let mut cpu = CPU::new();
+  // JMP $8000
+  cpu.load(vec![0x4c, 0x00, 0x80]).unwrap();
+  cpu.halt_on_brk = false;
+  let mut frames = Vec::new();
+  let mut hook = |cpu: &CPU| frames.push(cpu.bus.ppu.frame_count);
+  assert!(achievements::run_frame(&mut cpu, &mut hook));
+  assert!(achievements::run_frame(&mut cpu, &mut hook));
+  assert_eq!(frames, vec![1, 2]);
+}
+
+#[test]
+fn test_run_loop_slices() {
+  let mut cpu = CPU::new();
+  // JMP $8000
+  cpu.load(vec![0x4c, 0x00, 0x80]).unwrap();
+  cpu.halt_on_brk = false;
+
+  assert!(cpu.step_instruction());
+  assert_eq!(cpu.cycles, 3);
+
+  let scanline = cpu.bus.ppu.scanline;
+  assert!(cpu.step_scanline());
+  assert_eq!(cpu.bus.ppu.scanline, scanline + 1);
+
+  let frame = cpu.bus.ppu.frame_count;
+  assert!(cpu.run_frame());
+  assert!(cpu.run_frame());
+  assert_eq!(cpu.bus.ppu.frame_count, frame + 2);
+  // vblank starts on scanline 241
+  assert_eq!(cpu.bus.ppu.scanline, 241);
+}
+
+#[test]
+fn test_run_loop_stops_with_the_program() {
+  let mut cpu = CPU::new();
+  cpu.load(vec![0xea, 0x00]).unwrap();
+  assert!(cpu.step_instruction());
+  assert!(!cpu.step_instruction());
+  assert!(!cpu.run_frame());
+  assert!(!cpu.step_scanline());
+}
@@ -0,0 +1,84 @@
+use flemu_core::nes::cartridge::Rom;
+use flemu_core::nes::diagnostics::*;
+use std::fs;
+use std::path::PathBuf;
+
+/*
+  The nestest ROM and its known-good log aren't part of the repository.
+  Drop nestest.nes and nestest.log into tests/roms (or point NESTEST_DIR at
+  where they are) and test_nestest_matches_the_golden_log diffs the whole
+  run against the log; without them it's skipped.
+*/
+
+fn nestest_files() -> Option<(Vec<u8>, String)> {
+  let dir = std::env::var_os("NESTEST_DIR")
+    .map(PathBuf::from)
+    .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/roms"));
+  let rom = fs::read(dir.join("nestest.nes")).ok()?;
+  let log = fs::read_to_string(dir.join("nestest.log")).ok()?;
+  Some((rom, log))
+}
+
+#[test]
+fn test_nestest_matches_the_golden_log() {
+  let (rom, golden) = match nestest_files() {
+    Some(files) => files,
+    None => {
+      eprintln!("nestest.nes and nestest.log not found, skipping");
+      return;
+    }
+  };
+  let lines = golden.lines().count();
+  let log = nestest_run(Rom::from_bytes(&rom).unwrap(), lines).unwrap();
+  if let Some(mismatch) = first_log_mismatch(&golden, &log) {
+    panic!("{}", mismatch);
+  }
+}
+
+#[test]
+fn test_nestest_run_starts_like_the_log() {
+  let mut rom = Rom::from_program(&[]);
+  // NOP; JMP $C000
+  rom.prg_rom[0x4000..0x4004].copy_from_slice(&[0xea, 0x4c, 0x00, 0xc0]);
+  let log = nestest_run(rom, 3).unwrap();
+  assert_eq!(
+    log,
+    vec![
+      "C000  EA        NOP                             A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7",
+      "C001  4C 00 C0  JMP $C000                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 27 CYC:9",
+      "C000  EA        NOP                             A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 36 CYC:12",
+    ]
+  );
+}
+
+#[test]
+fn test_first_log_mismatch() {
+  let golden = "C000  A\r\nC001  B  \nC002  C\n";
+  let log = |lines: &[&str]| {
+    lines
+      .iter()
+      .map(|line| line.to_string())
+      .collect::<Vec<_>>()
+  };
+  assert_eq!(
+    first_log_mismatch(golden, &log(&["C000  A", "C001  B", "C002  C"])),
+    None
+  );
+
+  let mismatch = first_log_mismatch(golden, &log(&["C000  A", "C001  X", "C002  C"])).unwrap();
+  assert_eq!(mismatch.line, 2);
+  assert_eq!(
+    mismatch.to_string(),
+    "line 2 differs\nexpected: C001  B\nactual:   C001  X"
+  );
+
+  // stopping early is a mismatch too
+  assert_eq!(
+    first_log_mismatch(golden, &log(&["C000  A"])),
+    Some(LogMismatch {
+      line: 2,
+      expected: Some("C001  B".to_string()),
+      actual: None,
+    })
+  );
+}
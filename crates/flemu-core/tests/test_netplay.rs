@@ -0,0 +1,237 @@
+mod common;
+
+use flemu_core::nes::bus::Mem;
+use flemu_core::nes::cpu::CPU;
+use flemu_core::nes::joypad::JoypadButton;
+use flemu_core::nes::netplay::{self, Message, Netplay, NetplayError, CHECKSUM_INTERVAL};
+use flemu_core::nes::rollback::RollbackBuffer;
+use flemu_core::nes::savestate::{StateReader, StateWriter};
+
+const MAX_ROLLBACK: u32 = 8;
+
+// adds controller 1's buttons and then controller 2's to a counter in RAM
+// every frame, so any difference in input is a different machine
+fn console() -> CPU {
+  common::console(
+    "
+    frame:
+      LDA #$01
+      STA $4016
+      LDA #$00
+      STA $4016
+      LDX #$08
+    bit:
+      LDA $4016
+      ADC $10
+      STA $10
+      LDA $4017
+      ADC $11
+      STA $11
+      DEX
+      BNE bit
+      JMP frame
+    ",
+  )
+}
+
+struct Side {
+  netplay: Netplay,
+  cpu: CPU,
+  snapshots: RollbackBuffer<CPU>,
+  outbox: Vec<Vec<u8>>,
+  rollbacks: u32,
+}
+
+impl Side {
+  fn new(netplay: Netplay, cpu: CPU) -> Self {
+    Side {
+      netplay,
+      cpu,
+      snapshots: RollbackBuffer::new(MAX_ROLLBACK as usize + 1),
+      outbox: Vec::new(),
+      rollbacks: 0,
+    }
+  }
+
+  fn run(&mut self, frame: u32, inputs: [u8; 2]) {
+    self.snapshots.snapshot(frame, &self.cpu);
+    for (pad, &buttons) in self.cpu.bus.joypads.iter_mut().zip(&inputs) {
+      pad.set_button_pressed_status(JoypadButton::all(), false);
+      pad.set_button_pressed_status(JoypadButton::from_bits_truncate(buttons), true);
+    }
+    assert!(self.cpu.run_frame());
+  }
+
+  fn receive(&mut self, bytes: &[u8]) {
+    let message = Message::from_bytes(bytes).unwrap();
+    if let Some(state) = self.netplay.receive(message).unwrap() {
+      let mut r = StateReader::new(&state, self.cpu.bus.cartridge_checksum()).unwrap();
+      self.cpu.load_state(&mut r).unwrap();
+    }
+  }
+
+  // what a frontend's netplay frame does: catch up, then one more frame
+  // if the other side isn't too far behind
+  fn frame(&mut self, buttons: u8) -> bool {
+    if let Some(from) = self.netplay.take_rollback() {
+      self.rollbacks += 1;
+      assert!(self.snapshots.restore(from, &mut self.cpu));
+      for frame in from..self.netplay.frame() {
+        let inputs = self.netplay.inputs(frame);
+        self.run(frame, inputs);
+      }
+    }
+    while let Some(frame) = self.netplay.checksum_due() {
+      let checksum = if frame == self.netplay.frame() {
+        Some(netplay::checksum(&self.cpu))
+      } else {
+        self.snapshots.get(frame).map(netplay::checksum)
+      };
+      if let Some(message) = self.netplay.checksum(frame, checksum) {
+        self.outbox.push(message.to_bytes());
+      }
+    }
+    if !self.netplay.ready() {
+      return false;
+    }
+    let frame = self.netplay.frame();
+    let (message, inputs) = self.netplay.advance(buttons);
+    self.outbox.push(message.to_bytes());
+    self.run(frame, inputs);
+    true
+  }
+}
+
+fn state(cpu: &CPU) -> Vec<u8> {
+  let mut w = StateWriter::new(cpu.bus.cartridge_checksum());
+  cpu.save_state(&mut w);
+  w.finish()
+}
+
+// host and guest, the guest on a console that isn't in the host's state
+fn session(delay: u8) -> (Side, Side) {
+  let host = Side::new(Netplay::host(delay, MAX_ROLLBACK), console());
+  let mut guest_cpu = console();
+  guest_cpu.run_frame();
+  let mut guest = Side::new(Netplay::join(MAX_ROLLBACK), guest_cpu);
+  let message = host.netplay.state_message(state(&host.cpu));
+  guest.receive(&message.to_bytes());
+  (host, guest)
+}
+
+fn deliver(from: &mut Side, to: &mut Side) {
+  for bytes in std::mem::take(&mut from.outbox) {
+    to.receive(&bytes);
+  }
+}
+
+fn buttons(frame: u32, player: u32) -> u8 {
+  // changes every few frames, so guesses go wrong
+  ((frame / 5 + player) % 3) as u8
+}
+
+#[test]
+fn test_messages_round_trip() {
+  let messages = [
+    Message::State {
+      delay: 2,
+      state: vec![1, 2, 3],
+    },
+    Message::Input {
+      frame: 0x0102_0304,
+      buttons: 0x81,
+    },
+    Message::Checksum {
+      frame: 60,
+      checksum: 0xDEAD_BEEF,
+    },
+  ];
+  for message in &messages {
+    assert_eq!(
+      Message::from_bytes(&message.to_bytes()).as_ref(),
+      Ok(message)
+    );
+  }
+  assert_eq!(Message::from_bytes(&[]), Err(NetplayError::BadMessage));
+  assert_eq!(
+    Message::from_bytes(&[1, 0, 0]),
+    Err(NetplayError::BadMessage)
+  );
+  assert_eq!(Message::from_bytes(&[9]), Err(NetplayError::BadMessage));
+}
+
+#[test]
+fn test_joining_waits_for_the_host_state() {
+  let mut guest = Netplay::join(MAX_ROLLBACK);
+  assert!(!guest.ready());
+  let message = Netplay::host(3, MAX_ROLLBACK).state_message(vec![7]);
+  assert_eq!(guest.receive(message), Ok(Some(vec![7])));
+  assert!(guest.ready());
+  assert_eq!(guest.player(), 1);
+  assert_eq!(guest.delay(), 3);
+  assert_eq!(guest.remote_frame(), 3);
+}
+
+#[test]
+fn test_lockstep_with_input_in_time() {
+  let (mut host, mut guest) = session(2);
+  for frame in 0..CHECKSUM_INTERVAL + 10 {
+    assert!(host.frame(buttons(frame, 0)));
+    assert!(guest.frame(buttons(frame, 1)));
+    deliver(&mut host, &mut guest);
+    deliver(&mut guest, &mut host);
+  }
+  assert_eq!((host.rollbacks, guest.rollbacks), (0, 0));
+  assert_eq!(state(&host.cpu), state(&guest.cpu));
+  assert_eq!(host.netplay.desync(), None);
+  assert_eq!(guest.netplay.desync(), None);
+}
+
+#[test]
+fn test_late_input_rolls_back_to_the_same_machine() {
+  let (mut host, mut guest) = session(1);
+  for frame in 0..CHECKSUM_INTERVAL + 10 {
+    assert!(host.frame(buttons(frame, 0)));
+    assert!(guest.frame(buttons(frame, 1)));
+    // the host's input takes 4 frames to arrive
+    if frame % 4 == 3 {
+      deliver(&mut host, &mut guest);
+    }
+    deliver(&mut guest, &mut host);
+  }
+  // with the rest of the input in, the guest goes back over its guesses
+  deliver(&mut host, &mut guest);
+  assert!(guest.frame(0));
+  assert!(host.frame(0));
+  assert!(guest.rollbacks > 0);
+  assert_eq!(state(&host.cpu), state(&guest.cpu));
+  assert_eq!(host.netplay.desync(), None);
+  assert_eq!(guest.netplay.desync(), None);
+}
+
+#[test]
+fn test_waits_rather_than_getting_too_far_ahead() {
+  let (mut host, _guest) = session(0);
+  for frame in 0..MAX_ROLLBACK {
+    assert!(host.frame(buttons(frame, 0)));
+  }
+  assert!(!host.frame(0));
+  assert_eq!(host.netplay.frame(), MAX_ROLLBACK);
+}
+
+#[test]
+fn test_different_machines_are_a_desync() {
+  let (mut host, mut guest) = session(2);
+  for frame in 0..CHECKSUM_INTERVAL + 4 {
+    if frame == 10 {
+      guest.cpu.bus.mem_write(0x0200, 1);
+    }
+    host.frame(buttons(frame, 0));
+    guest.frame(buttons(frame, 1));
+    deliver(&mut host, &mut guest);
+    deliver(&mut guest, &mut host);
+  }
+  assert_eq!(host.netplay.desync(), Some(CHECKSUM_INTERVAL));
+  assert_eq!(guest.netplay.desync(), Some(CHECKSUM_INTERVAL));
+  assert!(!host.frame(0));
+}
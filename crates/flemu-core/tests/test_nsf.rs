@@ -0,0 +1,121 @@
+use flemu_core::nes::bus::Mem;
+use flemu_core::nes::cartridge::Region;
+use flemu_core::nes::cpu::CPU;
+use flemu_core::nes::nsf::*;
+use flemu_core::nes::timing::Timing;
+
+/// An NSF header for `songs` songs, the code and data loaded at $8000.
+fn nsf_file(songs: u8, speed: u16, code: &[u8]) -> Vec<u8> {
+  let mut file = vec![0; 0x80];
+  file[..5].copy_from_slice(b"NESM\x1a");
+  file[5] = 1;
+  file[6] = songs;
+  file[7] = 2;
+  // load and init at $8000, play at $8010
+  file[0x08..0x0E].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x10, 0x80]);
+  file[0x0E..0x13].copy_from_slice(b"Title");
+  file[0x2E..0x34].copy_from_slice(b"Artist");
+  file[0x6E..0x70].copy_from_slice(&speed.to_le_bytes());
+  file[0x78..0x7A].copy_from_slice(&20_000u16.to_le_bytes());
+  file.extend_from_slice(code);
+  file
+}
+
+#[rustfmt::skip]
+const COUNTING: [u8; 19] = [
+  0x85, 0x00,       // $8000 init: STA $00, the song
+  0x86, 0x02,       //       STX $02, PAL or not
+  0xa9, 0x01,       //       LDA #1
+  0x8d, 0x00, 0x40, //       STA $4000, a register for it to clear
+  0x60,             //       RTS
+  0, 0, 0, 0, 0, 0,
+  0xe6, 0x01,       // $8010 play: INC $01
+  0x60,             //       RTS
+];
+
+fn started(file: &[u8]) -> (CPU, NsfPlayer) {
+  let nsf = Nsf::from_bytes(file).unwrap();
+  let mut cpu = CPU::new();
+  cpu.load_rom(nsf.to_rom()).unwrap();
+  let mut player = NsfPlayer::new(nsf);
+  let song = player.song();
+  player.start(&mut cpu, song);
+  (cpu, player)
+}
+
+#[test]
+fn test_header() {
+  let nsf = Nsf::from_bytes(&nsf_file(3, 16_639, &COUNTING)).unwrap();
+  assert_eq!(nsf.songs, 3);
+  assert_eq!(nsf.first_song, 1);
+  assert_eq!((nsf.load, nsf.init, nsf.play), (0x8000, 0x8000, 0x8010));
+  assert_eq!(nsf.title, "Title");
+  assert_eq!(nsf.artist, "Artist");
+  assert_eq!(nsf.copyright, "");
+  assert_eq!(nsf.banks, None);
+  assert_eq!(nsf.region, Region::Ntsc);
+  assert_eq!(nsf.data, COUNTING.to_vec());
+  assert!((nsf.play_period(Timing::Ntsc) - 29_780.5).abs() < 1.0);
+  assert!((nsf.play_period(Timing::Pal) - 33_252.1).abs() < 1.0);
+
+  assert_eq!(Nsf::from_bytes(b"NES\x1a"), Err(NsfError::NotNsf));
+  assert_eq!(Nsf::from_bytes(b"NESM\x1a\x01"), Err(NsfError::Truncated));
+  let mut vrc6 = nsf_file(1, 16_639, &COUNTING);
+  vrc6[0x7B] = 1;
+  let error = Nsf::from_bytes(&vrc6).unwrap_err();
+  assert_eq!(error.to_string(), "VRC6 audio is not supported");
+}
+
+#[test]
+fn test_play_is_called_at_the_header_rate() {
+  let (mut cpu, mut player) = started(&nsf_file(3, 16_639, &COUNTING));
+  for _ in 0..60 {
+    assert!(player.run_frame(&mut cpu));
+  }
+  assert_eq!(cpu.mem_read(0x0000), 1);
+  assert_eq!(cpu.mem_read(0x0002), 0);
+  assert!((59..=61).contains(&cpu.mem_read(0x0001)));
+  // init started from a silent APU
+  assert_eq!(cpu.bus.apu.pulse1.output(), 0);
+
+  // twice a frame
+  let (mut cpu, mut player) = started(&nsf_file(3, 8_320, &COUNTING));
+  for _ in 0..60 {
+    player.run_frame(&mut cpu);
+  }
+  assert!((119..=121).contains(&cpu.mem_read(0x0001)));
+}
+
+#[test]
+fn test_changing_songs_starts_over() {
+  let (mut cpu, mut player) = started(&nsf_file(3, 16_639, &COUNTING));
+  for _ in 0..10 {
+    player.run_frame(&mut cpu);
+  }
+  player.start(&mut cpu, 5);
+  assert_eq!(player.song(), 2);
+  player.run_frame(&mut cpu);
+  assert_eq!(cpu.mem_read(0x0000), 2);
+  assert_eq!(cpu.mem_read(0x0001), 1);
+}
+
+#[test]
+fn test_bankswitched_data_starts_at_the_load_offset() {
+  let mut file = nsf_file(1, 16_639, &[0xaa; 0x1000]);
+  // loaded at $8100, the data's second bank at $8000
+  file[0x08..0x0A].copy_from_slice(&[0x00, 0x81]);
+  file[0x70..0x78].copy_from_slice(&[1, 0, 0, 0, 0, 0, 0, 0]);
+  let nsf = Nsf::from_bytes(&file).unwrap();
+  assert_eq!(nsf.banks, Some([1, 0, 0, 0, 0, 0, 0, 0]));
+  let rom = nsf.to_rom();
+  assert_eq!(rom.info.mapper, 31);
+  assert_eq!(rom.prg_rom.len(), 0x2000);
+  assert_eq!(rom.prg_rom[0x00ff], 0);
+  assert_eq!(rom.prg_rom[0x0100], 0xaa);
+
+  let mut cpu = CPU::new();
+  cpu.load_rom(rom).unwrap();
+  NsfPlayer::new(nsf).start(&mut cpu, 0);
+  assert_eq!(cpu.mem_read(0x80ff), 0xaa);
+  assert_eq!(cpu.mem_read(0x9100), 0xaa);
+}
@@ -0,0 +1,119 @@
+use flemu_core::nes::palette::*;
+use flemu_core::nes::ppu::Frame;
+
+#[test]
+fn test_presets() {
+  for preset in PalettePreset::ALL.iter() {
+    assert_eq!(PalettePreset::from_id(preset.id()), Some(*preset));
+  }
+  assert_eq!(PalettePreset::from_id("nope"), None);
+
+  let fceux = Palette::preset(PalettePreset::Fceux);
+  assert_eq!(fceux.rgb(0x00), (0x75, 0x75, 0x75));
+  assert_eq!(fceux.rgb(0x30), (0xff, 0xff, 0xff));
+  // only the low 6 bits pick a color
+  assert_eq!(fceux.rgb(0x41), fceux.rgb(0x01));
+  assert_ne!(Palette::preset(PalettePreset::SonyCxa), fceux);
+}
+
+#[test]
+fn test_pal_files() {
+  let bytes: Vec<u8> = (0..192).map(|i| i as u8).collect();
+  let palette = Palette::from_pal(&bytes).unwrap();
+  assert_eq!(palette.rgb(1), (3, 4, 5));
+
+  // without emphasis tables emphasis dims the other channels
+  assert_eq!(palette.emphasized(1, 0b001), (3, 3, 4));
+  assert_eq!(palette.emphasized(1, 0), palette.rgb(1));
+
+  // emphasis tables are used as they are
+  let mut with_emphasis = bytes.clone();
+  with_emphasis.resize(192 * 8, 0xff);
+  let palette = Palette::from_pal(&with_emphasis).unwrap();
+  assert_eq!(palette.rgb(1), (3, 4, 5));
+  assert_eq!(palette.emphasized(1, 0b101), (0xff, 0xff, 0xff));
+  assert_eq!(palette.colors().len(), COLORS * EMPHASIS_TABLES);
+
+  assert_eq!(
+    Palette::from_pal(&bytes[..191]),
+    Err(PaletteError { size: 191 })
+  );
+}
+
+#[test]
+fn test_to_rgba() {
+  let palette = Palette::preset(PalettePreset::NesDev);
+  assert_eq!(
+    palette.to_rgba(&[0x00, 0x20]),
+    vec![0x62, 0x62, 0x62, 0xff, 0xff, 0xff, 0xff, 0xff]
+  );
+}
+
+#[test]
+fn test_emphasis() {
+  let palette = Palette::preset(PalettePreset::NesDev);
+  // white: red emphasis keeps red and dims green and blue
+  assert_eq!(palette.emphasized(0x20, 0b001), (0xff, 0xd0, 0xd0));
+  assert_eq!(palette.emphasized(0x20, 0b110), (0xaa, 0xd0, 0xd0));
+  assert_eq!(
+    palette.emphasized(0x60, 0b1001),
+    palette.emphasized(0x20, 0b001)
+  );
+
+  let mut frame = Frame::new();
+  frame.set_pixel(0, 1, 0x20);
+  frame.set_emphasis(1, 0b100);
+  let rgba = palette.frame_rgba(&frame);
+  assert_eq!(&rgba[..4], &[0x62, 0x62, 0x62, 0xff]);
+  let second_line = Frame::WIDTH * 4;
+  assert_eq!(
+    &rgba[second_line..second_line + 4],
+    &[0xd0, 0xd0, 0xff, 0xff]
+  );
+}
+
+#[test]
+fn test_adjusting_a_preset() {
+  let mut palette = Palette::preset(PalettePreset::Fceux);
+  ColorAdjustments {
+    brightness: -1.0,
+    ..ColorAdjustments::default()
+  }
+  .apply_palette(palette.colors_mut());
+  assert!(palette.colors().iter().all(|&rgb| rgb == (0, 0, 0)));
+}
+
+#[test]
+fn test_hsl_round_trip() {
+  for rgb in &[
+    (240, 10, 10),
+    (10, 240, 10),
+    (10, 10, 240),
+    (34, 34, 34),
+    (240, 240, 10),
+  ] {
+    assert_eq!(hsl_to_rgb(rgb_to_hsl(*rgb)), *rgb);
+  }
+
+  let (h, s, l) = rgb_to_hsl((255, 0, 0));
+  assert_eq!((h, s, l), (0.0, 1.0, 0.5));
+}
+
+#[test]
+fn test_adjustments() {
+  assert_eq!(adjust_brightness((100, 100, 100), 1.0), (255, 255, 255));
+  assert_eq!(adjust_contrast((200, 50, 128), 0.0), (128, 128, 128));
+  assert_eq!(adjust_saturation((240, 10, 10), 0.0), (125, 125, 125));
+  assert_eq!(rotate_hue((255, 0, 0), 120.0), (0, 255, 0));
+  assert_eq!(blend((0, 0, 0), (255, 255, 255), 0.5), (128, 128, 128));
+}
+
+#[test]
+fn test_color_adjustments_apply_palette() {
+  let mut palette = [(240, 10, 10), (120, 120, 120)];
+  ColorAdjustments::default().apply_palette(&mut palette);
+  assert_eq!(palette, [(240, 10, 10), (120, 120, 120)]);
+
+  ColorAdjustments::vivid().apply_palette(&mut palette);
+  assert_ne!(palette[0], (240, 10, 10));
+}
@@ -0,0 +1,130 @@
+use flemu_core::nes::patch::*;
+
+#[test]
+fn test_crc32() {
+  assert_eq!(crc32(b"123456789"), 0xcbf43926);
+  assert_eq!(crc32(b""), 0);
+}
+
+#[test]
+fn test_ips() {
+  let rom = vec![0u8; 8];
+  let mut patch = b"PATCH".to_vec();
+  // 2 bytes at $000002
+  patch.extend(&[0x00, 0x00, 0x02, 0x00, 0x02, 0xaa, 0xbb]);
+  // RLE: 3 x $cc at $000005
+  patch.extend(&[0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x03, 0xcc]);
+  // grows the rom
+  patch.extend(&[0x00, 0x00, 0x09, 0x00, 0x01, 0xdd]);
+  patch.extend(b"EOF");
+
+  let patched = apply(&rom, &patch).unwrap();
+  assert_eq!(
+    patched,
+    vec![0, 0, 0xaa, 0xbb, 0, 0xcc, 0xcc, 0xcc, 0, 0xdd]
+  );
+}
+
+#[test]
+fn test_ips_truncation() {
+  let mut patch = b"PATCH".to_vec();
+  patch.extend(b"EOF");
+  patch.extend(&[0x00, 0x00, 0x04]);
+  assert_eq!(
+    apply(&[1, 2, 3, 4, 5, 6], &patch).unwrap(),
+    vec![1, 2, 3, 4]
+  );
+}
+
+#[test]
+fn test_ips_truncated_patch() {
+  let mut patch = b"PATCH".to_vec();
+  patch.extend(&[0x00, 0x00, 0x02, 0x00, 0x04, 0xaa]);
+  assert_eq!(apply(&[0; 8], &patch), Err(PatchError::Truncated));
+}
+
+#[test]
+fn test_unknown_format() {
+  assert_eq!(apply(&[0; 8], b"NOPE"), Err(PatchError::UnknownFormat));
+}
+
+fn varint(mut data: usize, out: &mut Vec<u8>) {
+  loop {
+    let x = (data & 0x7f) as u8;
+    data >>= 7;
+    if data == 0 {
+      out.push(0x80 | x);
+      break;
+    }
+    out.push(x);
+    data -= 1;
+  }
+}
+
+// (length - 1) << 2 | command
+fn action(length: usize, command: usize, out: &mut Vec<u8>) {
+  varint(((length - 1) << 2) | command, out);
+}
+
+fn bps(source: &[u8], target: &[u8], actions: &[u8]) -> Vec<u8> {
+  let mut patch = b"BPS1".to_vec();
+  varint(source.len(), &mut patch);
+  varint(target.len(), &mut patch);
+  varint(0, &mut patch);
+  patch.extend(actions);
+  patch.extend(&crc32(source).to_le_bytes());
+  patch.extend(&crc32(target).to_le_bytes());
+  let crc = crc32(&patch);
+  patch.extend(&crc.to_le_bytes());
+  patch
+}
+
+#[test]
+fn test_bps() {
+  let source = b"ABCDEFGH".to_vec();
+  let target = b"ABCxyxyxyFGH".to_vec();
+
+  let mut actions = vec![];
+  // SourceRead 3: "ABC"
+  action(3, 0, &mut actions);
+  // TargetRead 2: "xy"
+  action(2, 1, &mut actions);
+  actions.extend(b"xy");
+  // TargetCopy 4 from offset 3: "xyxy"
+  action(4, 3, &mut actions);
+  varint(3 << 1, &mut actions);
+  // SourceCopy 3 from offset 5: "FGH"
+  action(3, 2, &mut actions);
+  varint(5 << 1, &mut actions);
+
+  let patch = bps(&source, &target, &actions);
+  assert_eq!(apply(&source, &patch).unwrap(), target);
+}
+
+#[test]
+fn test_bps_checksums() {
+  let source = b"ABCD".to_vec();
+  let target = b"ABCD".to_vec();
+  let mut actions = vec![];
+  action(4, 0, &mut actions);
+  let patch = bps(&source, &target, &actions);
+
+  match apply(b"ABCE", &patch) {
+    Err(PatchError::SourceMismatch { expected, .. }) => assert_eq!(expected, crc32(&source)),
+    other => panic!("expected a source mismatch, got {:?}", other),
+  }
+
+  let mut corrupted = patch;
+  corrupted[5] ^= 0xff;
+  assert!(matches!(
+    apply(&source, &corrupted),
+    Err(PatchError::PatchChecksum { .. })
+  ));
+
+  // a patch that promises a different target
+  let lying = bps(&source, b"ABCX", &actions);
+  assert!(matches!(
+    apply(&source, &lying),
+    Err(PatchError::TargetChecksum { .. })
+  ));
+}
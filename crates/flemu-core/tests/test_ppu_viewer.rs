@@ -0,0 +1,154 @@
+use flemu_core::nes::bus::Bus;
+use flemu_core::nes::cartridge::Rom;
+use flemu_core::nes::palette::Palette;
+use flemu_core::nes::ppu_viewer::{self, Sprite, NAMETABLES_HEIGHT, NAMETABLES_WIDTH};
+
+// NROM, horizontal mirroring, `chr` in CHR ROM and palette entry n
+// holding color n, apart from the ones that mirror the backdrops
+fn console(chr: &[(usize, u8)]) -> Bus {
+  let mut rom = Rom::from_program(&[]);
+  for &(addr, value) in chr {
+    rom.chr_rom[addr] = value;
+  }
+  let mut bus = Bus::with_rom(rom).unwrap();
+  for i in (0..32).filter(|i| i < &16 || i % 4 != 0) {
+    bus.ppu_poke(0x3F00 + i, i as u8);
+  }
+  bus
+}
+
+fn pixel(image: &ppu_viewer::Image, x: usize, y: usize) -> [u8; 4] {
+  let at = (y * image.width + x) * 4;
+  [
+    image.rgba[at],
+    image.rgba[at + 1],
+    image.rgba[at + 2],
+    image.rgba[at + 3],
+  ]
+}
+
+fn rgba(color: u8) -> [u8; 4] {
+  let (r, g, b) = Palette::default().rgb(color);
+  [r, g, b, 0xFF]
+}
+
+#[test]
+fn test_pattern_tables() {
+  // tile 1 of table 0: a row of colors 1, 2, 3, 0
+  // tile 0 of table 1: color 3 in its top left corner
+  let bus = console(&[
+    (0x0010, 0b1010_0000),
+    (0x0018, 0b0110_0000),
+    (0x1000, 0x80),
+    (0x1008, 0x80),
+  ]);
+  let colors = Palette::default();
+  let image = ppu_viewer::pattern_table(&bus, 0, 1, &colors);
+  assert_eq!((image.width, image.height), (128, 128));
+  assert_eq!(pixel(&image, 8, 0), rgba(0x05));
+  assert_eq!(pixel(&image, 9, 0), rgba(0x06));
+  assert_eq!(pixel(&image, 10, 0), rgba(0x07));
+  // color 0 is the backdrop whatever the palette
+  assert_eq!(pixel(&image, 11, 0), rgba(0x00));
+
+  let image = ppu_viewer::pattern_table(&bus, 1, 6, &colors);
+  assert_eq!(pixel(&image, 0, 0), rgba(0x1B));
+  assert_eq!(pixel(&image, 1, 0), rgba(0x00));
+}
+
+#[test]
+fn test_nametables_use_attributes_and_mirroring() {
+  let mut bus = console(&[(0x0010, 0xFF)]);
+  // tile 1 in the top left of $2800 with palette 2, the bottom row of
+  // the screen with horizontal mirroring
+  bus.ppu_poke(0x2800, 1);
+  bus.ppu_poke(0x2BC0, 0b10);
+  let image = ppu_viewer::nametables(&bus, &Palette::default(), false);
+  assert_eq!(
+    (image.width, image.height),
+    (NAMETABLES_WIDTH, NAMETABLES_HEIGHT)
+  );
+  assert_eq!(pixel(&image, 0, 240), rgba(0x09));
+  assert_eq!(pixel(&image, 256, 240), rgba(0x09));
+  assert_eq!(pixel(&image, 0, 0), rgba(0x00));
+  assert_eq!(pixel(&image, 8, 240), rgba(0x00));
+}
+
+#[test]
+fn test_scroll_rectangle_wraps() {
+  let mut bus = console(&[]);
+  // coarse X 31 + fine X 3 in the right nametable, coarse Y 29 + fine Y 7
+  // in the bottom one: the screen's corner is at (507, 479)
+  bus.ppu.t = 7 << 12 | 0b11 << 10 | 29 << 5 | 31;
+  bus.ppu.x = 3;
+  assert_eq!(ppu_viewer::scroll_position(&bus), (507, 479));
+
+  let white = [0xFF, 0xFF, 0xFF, 0xFF];
+  let image = ppu_viewer::nametables(&bus, &Palette::default(), true);
+  assert_eq!(pixel(&image, 507, 479), white);
+  // the top edge carries on in the left nametables
+  assert_eq!(pixel(&image, 4, 479), white);
+  // and the left edge at the top
+  assert_eq!(pixel(&image, 507, 0), white);
+  // the right edge is 256 across
+  assert_eq!(pixel(&image, 250, 100), white);
+  assert_eq!(pixel(&image, 300, 100), rgba(0x00));
+
+  let image = ppu_viewer::nametables(&bus, &Palette::default(), false);
+  assert_eq!(pixel(&image, 507, 479), rgba(0x00));
+}
+
+#[test]
+fn test_palette() {
+  let mut bus = console(&[]);
+  bus.ppu_poke(0x3F00, 0x2A);
+  let image = ppu_viewer::palette(&bus, &Palette::default());
+  assert_eq!((image.width, image.height), (16, 2));
+  assert_eq!(pixel(&image, 0, 0), rgba(0x2A));
+  assert_eq!(pixel(&image, 5, 0), rgba(0x05));
+  // $3F10 mirrors $3F00, $3F11 is its own
+  assert_eq!(pixel(&image, 0, 1), rgba(0x2A));
+  assert_eq!(pixel(&image, 1, 1), rgba(0x11));
+}
+
+#[test]
+fn test_sprites_are_decoded() {
+  let mut bus = console(&[]);
+  bus.ppu.oam_data[4..8].copy_from_slice(&[0x1F, 0x42, 0b1110_0010, 0x80]);
+  let sprites = ppu_viewer::sprites(&bus);
+  assert_eq!(sprites.len(), 64);
+  assert_eq!(
+    sprites[1],
+    Sprite {
+      index: 1,
+      x: 0x80,
+      y: 0x20,
+      tile: 0x42,
+      palette: 6,
+      behind_background: true,
+      flip_horizontal: true,
+      flip_vertical: true,
+    }
+  );
+}
+
+#[test]
+fn test_sprite_sheet_flips_and_keeps_transparency() {
+  // tile 1: color 1 in the top left corner
+  let mut bus = console(&[(0x0010, 0x80)]);
+  // sprite 9 (column 1, row 1), tile 1 flipped both ways, palette 5
+  bus.ppu.oam_data[36..40].copy_from_slice(&[0, 1, 0b1100_0001, 0]);
+  let image = ppu_viewer::sprite_sheet(&bus, &Palette::default());
+  assert_eq!((image.width, image.height), (64, 128));
+  assert_eq!(pixel(&image, 8 + 7, 16 + 7), rgba(0x15));
+  assert_eq!(pixel(&image, 8, 16), [0, 0, 0, 0]);
+  // the lower half of an 8x8 sprite's cell stays empty
+  assert_eq!(image.rgba.chunks(4).filter(|p| p[3] != 0).count(), 1);
+
+  // 8x16: odd tiles come from $1000, tile 1 is the top of $1000's 0 and 1
+  let mut bus = console(&[(0x1010, 0x01)]);
+  bus.ppu.write_to_ctrl(0x20);
+  bus.ppu.oam_data[0..4].copy_from_slice(&[0, 1, 0, 0]);
+  let image = ppu_viewer::sprite_sheet(&bus, &Palette::default());
+  assert_eq!(pixel(&image, 7, 8), rgba(0x11));
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use flemu_core::nes::cpu::CPU;
+use flemu_core::nes::profile::{ComponentTimes, Profiler, SAMPLE_EVERY};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+// a millisecond later every time it's read
+fn clock() -> f64 {
+  TICKS.fetch_add(1, Ordering::SeqCst) as f64
+}
+
+fn console() -> CPU {
+  common::console("loop: JMP loop")
+}
+
+#[test]
+fn test_profiler_times_one_slice_in_so_many() {
+  let mut profiler = Profiler::new(clock);
+  let timed = (0..SAMPLE_EVERY * 3)
+    .filter(|_| profiler.sample().is_some())
+    .count();
+  assert_eq!(timed, 3);
+
+  profiler.add(1.0, 0.5);
+  assert_eq!(
+    profiler.take(),
+    ComponentTimes {
+      ppu_ms: SAMPLE_EVERY as f64,
+      apu_ms: SAMPLE_EVERY as f64 / 2.0,
+    }
+  );
+  assert_eq!(profiler.take(), ComponentTimes::default());
+}
+
+#[test]
+fn test_profiling_a_frame_changes_nothing_about_it() {
+  let mut plain = console();
+  let mut profiled = console();
+  profiled.bus.set_profiler(Some(clock));
+  assert!(plain.run_frame());
+  assert!(profiled.run_frame());
+  assert_eq!(plain.cycles, profiled.cycles);
+  assert_eq!(plain.bus.ppu.frame_count, profiled.bus.ppu.frame_count);
+
+  // every timed slice reads the clock three times, a tick apart
+  let times = profiled.bus.take_component_times();
+  assert!(times.ppu_ms >= SAMPLE_EVERY as f64);
+  assert!((times.ppu_ms - times.apu_ms).abs() < 1e-9);
+  assert_eq!(plain.bus.take_component_times(), ComponentTimes::default());
+
+  profiled.bus.set_profiler(None);
+  profiled.run_frame();
+  assert_eq!(
+    profiled.bus.take_component_times(),
+    ComponentTimes::default()
+  );
+}
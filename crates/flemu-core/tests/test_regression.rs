@@ -0,0 +1,54 @@
+use flemu_core::nes::cartridge::Rom;
+use flemu_core::nes::regression::*;
+
+// INX; JMP $8000
+const LOOP: &[u8] = &[0xe8, 0x4c, 0x00, 0x80];
+
+#[test]
+fn test_record_is_deterministic() {
+  let a = record(Rom::from_program(LOOP), 3).unwrap();
+  let b = record(Rom::from_program(LOOP), 3).unwrap();
+  assert_eq!(a.len(), 3);
+  assert_eq!(a, b);
+  assert_ne!(a[0], a[1]);
+  assert_eq!(first_divergence(&a, &b), None);
+}
+
+#[test]
+fn test_first_divergence() {
+  let a = record(Rom::from_program(LOOP), 2).unwrap();
+  // INY instead of INX
+  let b = record(Rom::from_program(&[0xc8, 0x4c, 0x00, 0x80]), 2).unwrap();
+  assert_eq!(first_divergence(&a, &b), Some(0));
+
+  assert_eq!(first_divergence(&[1, 2, 3], &[1, 2, 4]), Some(2));
+  // a run that stops early diverges where it stopped
+  assert_eq!(first_divergence(&[1, 2, 3], &[1, 2]), Some(2));
+}
+
+#[test]
+fn test_record_stops_with_the_program() {
+  // a JAM opcode
+  let hashes = record(Rom::from_program(&[0x02]), 5).unwrap();
+  assert!(hashes.is_empty());
+}
+
+#[test]
+fn test_hash_log_text() {
+  let mut log = HashLog::new();
+  log.insert("snake.nes", vec![0x0123_4567_89ab_cdef, 1]);
+  log.insert("with\ttab.nes", vec![2]);
+
+  let text = log.to_text();
+  assert!(text.starts_with("snake.nes\t0\t0123456789abcdef\n"));
+  assert_eq!(HashLog::from_text(&text).unwrap(), log);
+
+  assert_eq!(
+    HashLog::from_text("a\t0\t1\na\t2\t1\n"),
+    Err(ParseHashLogError { line: 2 })
+  );
+  assert_eq!(
+    HashLog::from_text("a\t0\tzz\n"),
+    Err(ParseHashLogError { line: 1 })
+  );
+}
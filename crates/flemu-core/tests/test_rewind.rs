@@ -0,0 +1,132 @@
+use flemu_core::nes::palette::Palette;
+use flemu_core::nes::ppu::Frame;
+use flemu_core::nes::rewind::*;
+
+fn state(seed: u8) -> Vec<u8> {
+  let mut state = vec![0; 4096];
+  state[100] = seed;
+  state[2000..2010].copy_from_slice(&[seed; 10]);
+  state
+}
+
+// every pixel `seed`
+fn frame(seed: u8) -> Frame {
+  let mut frame = Frame::new();
+  frame.data.iter_mut().for_each(|pixel| *pixel = seed);
+  frame
+}
+
+#[test]
+fn test_delta_round_trip() {
+  let base = state(1);
+  let mut changed = base.clone();
+  changed[0] = 9;
+  changed[1] = 9;
+  // equal bytes inside a literal
+  changed[3] = 9;
+  changed[4095] = 7;
+  let encoded = delta(&changed, &base);
+  assert!(encoded.len() < 20);
+  assert_eq!(apply(&base, &encoded), changed);
+  assert_eq!(apply(&base, &delta(&base, &base)), base);
+}
+
+#[test]
+fn test_pops_states_newest_first() {
+  let mut rewind = Rewind::new(1, 8);
+  for seed in 0..5 {
+    rewind.push(state(seed), &Frame::new());
+  }
+  assert_eq!(rewind.len(), 5);
+  // one whole state, the rest a few bytes each, and the thumbnails
+  let thumbnails = 5 * (Thumbnail::WIDTH + 1) * Thumbnail::HEIGHT;
+  assert!(rewind.bytes() < 4096 + 4 * 32 + thumbnails);
+  for seed in (0..5).rev() {
+    assert_eq!(rewind.pop(), Some(state(seed)));
+  }
+  assert!(rewind.is_empty());
+  assert_eq!(rewind.pop(), None);
+}
+
+#[test]
+fn test_drops_the_oldest_when_full() {
+  let mut rewind = Rewind::new(1, 3);
+  for seed in 0..10 {
+    rewind.push(state(seed), &frame(seed));
+  }
+  assert_eq!(rewind.len(), 3);
+  assert_eq!(rewind.thumbnail(2), Some(&Thumbnail::new(&frame(7))));
+  assert_eq!(rewind.thumbnail(3), None);
+  let popped: Vec<_> = std::iter::from_fn(|| rewind.pop()).collect();
+  assert_eq!(popped, vec![state(9), state(8), state(7)]);
+}
+
+#[test]
+fn test_states_are_due_every_interval() {
+  let mut rewind = Rewind::new(3, 8);
+  let due: Vec<bool> = (0..7).map(|_| rewind.tick()).collect();
+  assert_eq!(due, [true, false, false, true, false, false, true]);
+
+  let mut off = Rewind::new(1, 0);
+  assert!(!off.tick());
+  off.push(state(1), &Frame::new());
+  assert!(off.is_empty());
+}
+
+#[test]
+fn test_states_and_thumbnails_by_index() {
+  let mut rewind = Rewind::new(1, 8);
+  for seed in 0..5 {
+    rewind.push(state(seed), &frame(seed));
+  }
+  // counted back from the newest, and left in
+  assert_eq!(rewind.get(0), Some(state(4)));
+  assert_eq!(rewind.get(3), Some(state(1)));
+  assert_eq!(rewind.get(5), None);
+  assert_eq!(rewind.thumbnail(3).unwrap().data[0], 1);
+  assert_eq!(rewind.len(), 5);
+
+  assert_eq!(rewind.seek(2), Some(state(2)));
+  assert_eq!(rewind.len(), 2);
+  assert_eq!(rewind.get(0), Some(state(1)));
+  assert_eq!(rewind.thumbnail(0).unwrap().data[0], 1);
+  assert_eq!(rewind.seek(2), None);
+  assert_eq!(rewind.len(), 2);
+  assert_eq!(rewind.seek(0), Some(state(1)));
+  assert_eq!(rewind.pop(), Some(state(0)));
+  assert_eq!(rewind.thumbnail(0), None);
+}
+
+#[test]
+fn test_thumbnails_shrink_the_frame() {
+  let mut frame = Frame::new();
+  // a block of color 0x16 at the top left, emphasis on line 2
+  for y in 0..THUMBNAIL_SCALE {
+    for x in 0..THUMBNAIL_SCALE {
+      frame.set_pixel(x, y, 0x16);
+    }
+  }
+  frame.set_emphasis(2, 0b001);
+  let thumbnail = Thumbnail::new(&frame);
+  assert_eq!((Thumbnail::WIDTH, Thumbnail::HEIGHT), (64, 60));
+  assert_eq!(thumbnail.data.len(), 64 * 60);
+  assert_eq!(thumbnail.data[..2], [0x16, 0x00]);
+  assert_eq!(thumbnail.data[64], 0x00);
+  assert_eq!(thumbnail.emphasis[..2], [0b001, 0]);
+
+  let palette = Palette::default();
+  let rgba = thumbnail.rgba(&palette);
+  assert_eq!(rgba.len(), 64 * 60 * 4);
+  let (r, g, b) = palette.emphasized(0x16, 0b001);
+  assert_eq!(rgba[..4], [r, g, b, 0xFF]);
+}
+
+#[test]
+fn test_another_layout_keeps_one_thumbnail() {
+  let mut rewind = Rewind::new(1, 8);
+  rewind.push(state(1), &frame(1));
+  rewind.push(vec![0; 10], &frame(2));
+  assert_eq!(rewind.len(), 1);
+  assert_eq!(rewind.thumbnail(0), Some(&Thumbnail::new(&frame(2))));
+  assert_eq!(rewind.thumbnail(1), None);
+}
@@ -0,0 +1,39 @@
+use flemu_core::nes::cpu::*;
+use flemu_core::rng::SeededRng;
+
+#[test]
+fn test_seeded_rng_is_reproducible() {
+  let mut a = SeededRng::new(42);
+  let mut b = SeededRng::new(42);
+  for _ in 0..100 {
+    assert_eq!(a.next_u64(), b.next_u64());
+  }
+
+  let mut c = SeededRng::new(43);
+  assert_ne!(a.next_u64(), c.next_u64());
+}
+
+#[test]
+fn test_seeded_rng_resumes_from_state() {
+  let mut rng = SeededRng::new(0);
+  rng.next_u64();
+  let mut resumed = SeededRng::from_state(rng.state());
+
+  assert_eq!(rng.next_u64(), resumed.next_u64());
+}
+
+#[test]
+fn test_randomize_ram() {
+  let mut cpu = CPU::new();
+  cpu.rng = SeededRng::new(7);
+  cpu.randomize_ram();
+
+  let mut expected = [0; 0x800];
+  SeededRng::new(7).fill(&mut expected);
+  for (addr, value) in expected.iter().enumerate() {
+    assert_eq!(cpu.mem_read(addr as u16), *value);
+  }
+  // $0800 is a mirror of $0000
+  assert_eq!(cpu.mem_read(0x0800), expected[0]);
+  assert_eq!(cpu.mem_read(0x6000), 0);
+}
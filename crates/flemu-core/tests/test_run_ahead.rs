@@ -0,0 +1,88 @@
+mod common;
+
+use flemu_core::nes::cpu::CPU;
+use flemu_core::nes::joypad::JoypadButton;
+use flemu_core::nes::ppu::Frame;
+use flemu_core::nes::run_ahead::RunAhead;
+use flemu_core::nes::savestate::StateWriter;
+
+// keeps writing a counter to the backdrop color, so every frame looks
+// different; the counter goes up by 2 while A is held
+fn console() -> CPU {
+  common::console(
+    "
+    loop:
+      LDA #$3F
+      STA $2006
+      LDA #$00
+      STA $2006
+      LDA #$01
+      STA $4016
+      LDA #$00
+      STA $4016
+      LDA $4016
+      AND #$01
+      BEQ up
+      INX
+    up: INX
+      STX $2007
+      JMP loop
+    ",
+  )
+}
+
+// everything but the picture
+fn state(cpu: &CPU) -> Vec<u8> {
+  let mut cpu = cpu.clone();
+  cpu.bus.ppu.frame = Frame::new();
+  let mut w = StateWriter::new(cpu.bus.cartridge_checksum());
+  cpu.save_state(&mut w);
+  w.finish()
+}
+
+#[test]
+fn test_run_ahead_frames() {
+  let mut run_ahead = RunAhead::new();
+  assert_eq!(run_ahead.frames(), 0);
+  assert!(run_ahead.set_frames(RunAhead::MAX_FRAMES));
+  assert!(!run_ahead.set_frames(3));
+  assert_eq!(run_ahead.frames(), RunAhead::MAX_FRAMES);
+}
+
+#[test]
+fn test_run_ahead_shows_a_later_frame_and_changes_nothing_else() {
+  let mut cpu = console();
+  cpu.run_frame();
+  cpu.bus.joypads[0].set_button_pressed_status(JoypadButton::A, true);
+  cpu.run_frame();
+  let mut ahead = cpu.clone();
+  ahead.run_frame();
+  ahead.run_frame();
+
+  let mut run_ahead = RunAhead::new();
+  run_ahead.set_frames(2);
+  let before = state(&cpu);
+  let frame = cpu.bus.ppu.frame_count;
+  assert!(run_ahead.run(&mut cpu));
+  assert_eq!(cpu.bus.ppu.frame.data, ahead.bus.ppu.frame.data);
+  assert_eq!(state(&cpu), before);
+  assert_eq!(cpu.bus.ppu.frame_count, frame);
+  assert!(!cpu.bus.ppu.skip_rendering);
+
+  // the real timeline carries on as if nothing happened
+  cpu.run_frame();
+  cpu.run_frame();
+  assert_eq!(state(&cpu), state(&ahead));
+  assert_eq!(cpu.bus.ppu.frame.data, ahead.bus.ppu.frame.data);
+}
+
+#[test]
+fn test_run_ahead_off_leaves_the_frame() {
+  let mut cpu = console();
+  cpu.run_frame();
+  let shown = cpu.bus.ppu.frame.data.clone();
+
+  let mut run_ahead = RunAhead::new();
+  assert!(run_ahead.run(&mut cpu));
+  assert_eq!(cpu.bus.ppu.frame.data, shown);
+}
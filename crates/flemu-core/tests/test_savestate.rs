@@ -0,0 +1,132 @@
+use flemu_core::nes::bus::Mem;
+use flemu_core::nes::cpu::*;
+use flemu_core::nes::savestate::*;
+use flemu_core::rng::SeededRng;
+
+// rendering on, a pulse note held; then INX; STX $10; STX $4011 forever
+#[rustfmt::skip]
+const PROGRAM: [u8; 34] = [
+  0xa9, 0x1e, 0x8d, 0x01, 0x20,
+  0xa9, 0x0f, 0x8d, 0x15, 0x40,
+  0xa9, 0x9f, 0x8d, 0x00, 0x40,
+  0xa9, 0xfd, 0x8d, 0x02, 0x40,
+  0xa9, 0x08, 0x8d, 0x03, 0x40,
+  0xe8, 0x86, 0x10, 0x8e, 0x11, 0x40, 0x4c, 0x19, 0x80,
+];
+
+fn running_cpu() -> CPU {
+  let mut cpu = CPU::new();
+  cpu.load(PROGRAM.to_vec()).unwrap();
+  cpu.halt_on_brk = false;
+  cpu.run_frame();
+  cpu
+}
+
+fn save(cpu: &CPU) -> Vec<u8> {
+  let mut w = StateWriter::new(cpu.bus.cartridge_checksum());
+  cpu.save_state(&mut w);
+  w.finish()
+}
+
+fn load(cpu: &mut CPU, state: &[u8]) -> Result<(), StateError> {
+  let mut r = StateReader::new(state, cpu.bus.cartridge_checksum())?;
+  cpu.load_state(&mut r)?;
+  r.finish()
+}
+
+// mid-scanline, so the beam position has to come back exactly
+fn advance(cpu: &mut CPU) {
+  cpu.run_frame();
+  for _ in 0..1000 {
+    cpu.step_instruction();
+  }
+}
+
+#[test]
+fn test_resumes_where_it_was_saved() {
+  let mut cpu = running_cpu();
+  let state = save(&cpu);
+  advance(&mut cpu);
+  let expected = (
+    cpu.state(),
+    cpu.mem_peek(0x10),
+    cpu.bus.ppu.frame.data.clone(),
+    cpu.bus.ppu.scanline,
+    cpu.bus.ppu.cycle,
+    cpu.bus.apu.channel_states(),
+  );
+
+  // into the same machine, and into another one further along
+  let mut other = running_cpu();
+  for _ in 0..3 {
+    advance(&mut other);
+  }
+  assert_ne!(other.mem_peek(0x10), expected.1);
+  for mut target in [cpu, other] {
+    load(&mut target, &state).unwrap();
+    advance(&mut target);
+    let actual = (
+      target.state(),
+      target.mem_peek(0x10),
+      target.bus.ppu.frame.data.clone(),
+      target.bus.ppu.scanline,
+      target.bus.ppu.cycle,
+      target.bus.apu.channel_states(),
+    );
+    assert_eq!(actual, expected);
+  }
+}
+
+#[test]
+fn test_refuses_what_it_cant_load() {
+  let mut cpu = running_cpu();
+  let state = save(&cpu);
+
+  assert_eq!(load(&mut cpu, b"FLMV"), Err(StateError::NotAState));
+  assert_eq!(load(&mut cpu, &[]), Err(StateError::NotAState));
+
+  let mut newer = state.clone();
+  newer[4..8].copy_from_slice(&(VERSION + 1).to_le_bytes());
+  assert_eq!(
+    load(&mut cpu, &newer),
+    Err(StateError::Version {
+      found: VERSION + 1,
+      supported: VERSION
+    })
+  );
+
+  assert_eq!(
+    load(&mut cpu, &state[..state.len() - 1]),
+    Err(StateError::Truncated)
+  );
+  let mut longer = state.clone();
+  longer.push(0);
+  assert_eq!(load(&mut cpu, &longer), Err(StateError::Corrupt));
+
+  let mut other = CPU::new();
+  other.load(vec![0xea]).unwrap();
+  assert_eq!(load(&mut other, &state), Err(StateError::WrongCartridge));
+}
+
+#[test]
+fn test_settings_are_not_part_of_the_state() {
+  let mut cpu = running_cpu();
+  let state = save(&cpu);
+  cpu.strict = true;
+  cpu.bus.ppu.layers.background = false;
+  load(&mut cpu, &state).unwrap();
+  assert!(cpu.strict);
+  assert!(!cpu.bus.ppu.layers.background);
+}
+
+#[test]
+fn test_the_rng_goes_with_the_machine() {
+  let mut cpu = running_cpu();
+  cpu.rng = SeededRng::new(7);
+  let state = save(&cpu);
+  let expected = cpu.rng.next_u64();
+
+  cpu.rng = SeededRng::new(8);
+  load(&mut cpu, &state).unwrap();
+  assert_eq!(cpu.rng.next_u64(), expected);
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use flemu_core::nes::cpu::CPU;
+use flemu_core::nes::palette::Palette;
+use flemu_core::nes::ppu_viewer::{self, EVENT_TINTS};
+use flemu_core::nes::scanline_events::*;
+
+// NMIs copy OAM in; with `sound` the frame IRQ and a looping DMC sample
+// are on too
+fn console(sound: bool) -> CPU {
+  let setup = if sound {
+    "LDA #$00\n STA $4017\n LDA #$4F\n STA $4010\n LDA #$FF\n STA $4013\n LDA #$10\n STA $4015\n CLI"
+  } else {
+    "LDA #$40\n STA $4017"
+  };
+  let source = format!(
+    "
+      LDA #$80
+      STA $2000
+      {}
+    loop: JMP loop
+    nmi:
+      LDA #$02
+      STA $4014
+      RTI
+    irq:
+      LDA $4015
+      RTI
+      .org $FFFA
+      .word nmi
+      .word $8000
+      .word irq
+    ",
+    setup
+  );
+  let mut cpu = common::console(&source);
+  cpu.bus.set_scanline_events(true);
+  for _ in 0..3 {
+    cpu.run_frame();
+  }
+  cpu
+}
+
+fn lines_with(log: &ScanlineEvents, event: LineEvents) -> Vec<usize> {
+  (0..log.last_frame().len())
+    .filter(|&line| log.line(line as u16).contains(event))
+    .collect()
+}
+
+#[test]
+fn test_nmi_and_oam_dma_land_on_the_vblank_line() {
+  let cpu = console(false);
+  let log = cpu.bus.scanline_events().unwrap();
+  assert_eq!(lines_with(log, LineEvents::NMI), vec![241]);
+  assert_eq!(lines_with(log, LineEvents::OAM_DMA), vec![241]);
+  assert!(lines_with(log, LineEvents::IRQ).is_empty());
+  assert!(lines_with(log, LineEvents::DMC_DMA).is_empty());
+}
+
+#[test]
+fn test_irqs_and_dmc_fetches_are_logged() {
+  let cpu = console(true);
+  let log = cpu.bus.scanline_events().unwrap();
+  assert_eq!(lines_with(log, LineEvents::NMI), vec![241]);
+  assert_eq!(lines_with(log, LineEvents::IRQ).len(), 1);
+  // the fastest rate takes a byte every 432 cycles, about 4 lines
+  let fetches = lines_with(log, LineEvents::DMC_DMA).len();
+  assert!((60..=75).contains(&fetches), "{}", fetches);
+}
+
+#[test]
+fn test_log_is_off_until_asked_for() {
+  let mut cpu = console(false);
+  cpu.bus.set_scanline_events(false);
+  cpu.run_frame();
+  assert!(cpu.bus.scanline_events().is_none());
+  let image = ppu_viewer::event_frame(&cpu.bus, &Palette::default());
+  // nothing tinted: every offscreen line the same grey
+  let row = |y: usize| &image.rgba[y * 256 * 4..(y + 1) * 256 * 4];
+  assert_eq!(row(241), row(250));
+}
+
+#[test]
+fn test_event_frame_tints_the_lines() {
+  let cpu = console(false);
+  let palette = Palette::default();
+  let image = ppu_viewer::event_frame(&cpu.bus, &palette);
+  assert_eq!((image.width, image.height), (256, 262));
+  assert_eq!(image.rgba.len(), 256 * 262 * 4);
+
+  // the picture unchanged; the PPU drew the backdrop on all of it
+  let picture = palette.frame_rgba(&cpu.bus.ppu.frame);
+  assert_eq!(&image.rgba[..picture.len()], &picture[..]);
+
+  let pixel = |y: usize| &image.rgba[y * 256 * 4..y * 256 * 4 + 4];
+  // line 241 is halfway from the grey to the NMI and OAM DMA tints mixed
+  let nmi = EVENT_TINTS[0].1;
+  let dma = EVENT_TINTS[2].1;
+  let grey = pixel(250)[0] as i32;
+  let expected = [
+    (grey + (nmi.0 as i32 + dma.0 as i32) / 2) / 2,
+    (grey + (nmi.1 as i32 + dma.1 as i32) / 2) / 2,
+    (grey + (nmi.2 as i32 + dma.2 as i32) / 2) / 2,
+  ];
+  for (channel, &value) in expected.iter().enumerate() {
+    assert!((pixel(241)[channel] as i32 - value).abs() <= 1);
+  }
+  assert_eq!(pixel(241)[3], 0xFF);
+  assert_ne!(pixel(241), pixel(240));
+  assert_eq!(pixel(240), pixel(250));
+}
@@ -0,0 +1,37 @@
+use flemu_core::nes::self_test::*;
+
+#[test]
+fn test_self_test_passes() {
+  let report = run();
+  assert_eq!(report.results.len(), CHECKS.len());
+  for result in &report.results {
+    assert_eq!(result.error, None, "{} {}", result.component, result.name);
+  }
+  assert!(report.passed());
+}
+
+#[test]
+fn test_report_text() {
+  let report = Report {
+    results: vec![
+      CheckResult {
+        component: "cpu",
+        name: "arithmetic",
+        error: None,
+      },
+      CheckResult {
+        component: "apu",
+        name: "frame IRQ",
+        error: Some("frame IRQ: expected true, got false".to_string()),
+      },
+    ],
+  };
+  assert!(!report.passed());
+  assert_eq!(report.failures(), 1);
+  assert_eq!(
+    report.to_text(),
+    "ok    cpu arithmetic\n\
+     FAIL  apu frame IRQ: frame IRQ: expected true, got false\n\
+     1 of 2 checks passed\n"
+  );
+}
@@ -0,0 +1,246 @@
+#![cfg(feature = "server")]
+
+use flemu_core::nes::asm;
+use flemu_core::server::json::{Json, JsonError};
+use flemu_core::server::{serve_client, Session};
+use flemu_core::video_dump;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::{fs, thread};
+
+// NROM with CHR RAM: every NMI counts in $10 and reads controller 1 into
+// $11, A in bit 7 down to Right in bit 0
+fn ines() -> Vec<u8> {
+  let program = asm::assemble(
+    "
+      LDA #$80
+      STA $2000
+    loop: JMP loop
+    nmi:
+      INC $10
+      LDA #$01
+      STA $4016
+      LDA #$00
+      STA $4016
+      LDX #$08
+    bit: LDA $4016
+      LSR A
+      ROL $11
+      DEX
+      BNE bit
+      RTI
+      .org $FFFA
+      .word nmi
+      .word $8000
+      .word nmi
+    ",
+  )
+  .unwrap();
+  let mut bytes = vec![
+    0x4E, 0x45, 0x53, 0x1A, 0x02, 0x00, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+  ];
+  bytes.extend_from_slice(&program);
+  bytes
+}
+
+fn base64(bytes: &[u8]) -> String {
+  const LETTERS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+  let mut out = String::new();
+  for chunk in bytes.chunks(3) {
+    let n = chunk
+      .iter()
+      .enumerate()
+      .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+    for i in 0..=chunk.len() {
+      out.push(LETTERS[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+    }
+    out.push_str(&"=="[..3 - chunk.len()]);
+  }
+  out
+}
+
+fn request(session: &mut Session, text: &str) -> Json {
+  Json::parse(&session.handle(text)).unwrap()
+}
+
+fn loaded() -> Session {
+  let mut session = Session::new();
+  let rom = base64(&ines());
+  let reply = request(
+    &mut session,
+    &format!(r#"{{"id": 1, "cmd": "load", "rom": "{}"}}"#, rom),
+  );
+  assert_eq!(reply.get("ok"), Some(&Json::Bool(true)), "{}", reply);
+  session
+}
+
+#[test]
+fn test_json_round_trip() {
+  let text = r#" {"a": [1, -2.5, 1e3, true, null], "s": "q\"\\\n\u00e9\ud83d\ude00", "o": {}} "#;
+  let json = Json::parse(text).unwrap();
+  assert_eq!(json.get("a").unwrap().as_array().unwrap().len(), 5);
+  assert_eq!(
+    json.get("s").unwrap().as_str(),
+    Some("q\"\\\n\u{e9}\u{1F600}")
+  );
+  assert_eq!(
+    json.to_string(),
+    "{\"a\":[1,-2.5,1000,true,null],\"s\":\"q\\\"\\\\\\n\u{e9}\u{1F600}\",\"o\":{}}"
+  );
+  assert_eq!(Json::parse(&json.to_string()), Ok(json));
+  assert_eq!(Json::parse("[1, 2"), Err(JsonError { offset: 5 }));
+  assert_eq!(Json::parse("{} x"), Err(JsonError { offset: 3 }));
+  assert_eq!(Json::parse("\"\\ud800\""), Err(JsonError { offset: 7 }));
+}
+
+#[test]
+fn test_errors_are_replies() {
+  let mut session = Session::new();
+  let reply = request(&mut session, r#"{"id": "x", "cmd": "step"}"#);
+  assert_eq!(
+    reply.to_string(),
+    r#"{"id":"x","ok":false,"error":"no ROM loaded"}"#
+  );
+  let reply = request(&mut session, r#"{"id": 2, "cmd": "fly"}"#);
+  assert_eq!(reply.get("error"), Some(&Json::from("unknown cmd fly")));
+  let reply = request(&mut session, "{oops");
+  assert_eq!(reply.get("id"), Some(&Json::Null));
+  assert_eq!(reply.get("ok"), Some(&Json::Bool(false)));
+  let reply = request(&mut session, r#"{"cmd": "load", "rom": "!!"}"#);
+  assert_eq!(reply.get("error"), Some(&Json::from("rom isn't base64")));
+  assert!(session.cpu().is_none());
+}
+
+#[test]
+fn test_load_from_a_path() {
+  let path = std::env::temp_dir().join("flemu-server-test.nes");
+  fs::write(&path, ines()).unwrap();
+  let mut session = Session::new();
+  let command = Json::Object(vec![
+    ("cmd".to_string(), "load".into()),
+    ("path".to_string(), path.to_str().unwrap().into()),
+  ]);
+  let reply = request(&mut session, &command.to_string());
+  assert_eq!(reply.get("mapper"), Some(&Json::from(0)), "{}", reply);
+  assert!(session.cpu().is_some());
+}
+
+#[test]
+fn test_step_input_and_read() {
+  let mut session = loaded();
+  let reply = request(&mut session, r#"{"cmd": "step", "frames": 10}"#);
+  assert_eq!(reply.get("frame"), Some(&Json::from(10)));
+  let reply = request(
+    &mut session,
+    r#"{"cmd": "input", "player": 0, "buttons": ["a", "right"]}"#,
+  );
+  assert_eq!(reply.get("ok"), Some(&Json::Bool(true)));
+  request(&mut session, r#"{"cmd": "step"}"#);
+  let reply = request(&mut session, r#"{"cmd": "read", "addr": 16, "len": 2}"#);
+  let data = reply.get("data").unwrap().as_array().unwrap();
+  // a frame ends at vblank, before its NMI: 10 of the 11 have run
+  assert_eq!(data, &[Json::from(10), Json::from(0x81)]);
+
+  let reply = request(&mut session, r#"{"cmd": "input", "buttons": ["turbo"]}"#);
+  assert_eq!(
+    reply.get("error"),
+    Some(&Json::from("unknown button turbo"))
+  );
+  let reply = request(&mut session, r#"{"cmd": "read", "addr": 65535, "len": 2}"#);
+  assert_eq!(reply.get("ok"), Some(&Json::Bool(false)));
+  let reply = request(&mut session, r#"{"cmd": "step", "frames": 100000}"#);
+  assert_eq!(reply.get("ok"), Some(&Json::Bool(false)));
+}
+
+#[test]
+fn test_screenshot_is_a_png() {
+  let mut session = loaded();
+  request(&mut session, r#"{"cmd": "step", "frames": 2}"#);
+  let reply = request(&mut session, r#"{"cmd": "screenshot"}"#);
+  let png = reply.get("png").unwrap().as_str().unwrap();
+  let letters = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+  let mut bytes = Vec::new();
+  let (mut n, mut bits) = (0u32, 0);
+  for letter in png.trim_end_matches('=').bytes() {
+    n = n << 6 | letters.iter().position(|&l| l == letter).unwrap() as u32;
+    bits += 6;
+    if bits >= 8 {
+      bits -= 8;
+      bytes.push((n >> bits) as u8);
+      n &= (1 << bits) - 1;
+    }
+  }
+  let (width, height, _) = video_dump::decode_png(&bytes).unwrap();
+  assert_eq!((width, height), (256, 240));
+}
+
+// the RFC's example handshake, then a masked request and a close
+#[test]
+fn test_websocket_session() {
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  let addr = listener.local_addr().unwrap();
+  let server = thread::spawn(move || {
+    let (stream, _) = listener.accept().unwrap();
+    let mut session = loaded();
+    serve_client(stream, &mut session).unwrap();
+  });
+
+  let mut client = TcpStream::connect(addr).unwrap();
+  write!(
+    client,
+    "GET / HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+     Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+    addr
+  )
+  .unwrap();
+  let mut reader = BufReader::new(client.try_clone().unwrap());
+  let mut response = String::new();
+  while !response.ends_with("\r\n\r\n") {
+    reader.read_line(&mut response).unwrap();
+  }
+  assert!(response.starts_with("HTTP/1.1 101"));
+  assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+  let send = |client: &mut TcpStream, opcode: u8, payload: &[u8]| {
+    let mask = [0x12, 0x34, 0x56, 0x78];
+    let mut frame = vec![0x80 | opcode, 0x80 | payload.len() as u8];
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+    client.write_all(&frame).unwrap();
+  };
+  send(
+    &mut client,
+    0x1,
+    br#"{"id": 7, "cmd": "step", "frames": 3}"#,
+  );
+  let mut head = [0; 2];
+  reader.read_exact(&mut head).unwrap();
+  assert_eq!(head[0], 0x81);
+  let mut reply = vec![0; head[1] as usize];
+  reader.read_exact(&mut reply).unwrap();
+  assert_eq!(
+    String::from_utf8(reply).unwrap(),
+    r#"{"id":7,"ok":true,"frame":3}"#
+  );
+
+  // a fragmented message is one request
+  let mask = [0, 0, 0, 0];
+  for (opcode, part) in &[(0x01u8, &br#"{"cmd": "#[..]), (0x80, &br#""reset"}"#[..])] {
+    let mut frame = vec![*opcode, 0x80 | part.len() as u8];
+    frame.extend_from_slice(&mask);
+    frame.extend_from_slice(part);
+    client.write_all(&frame).unwrap();
+  }
+  reader.read_exact(&mut head).unwrap();
+  let mut reply = vec![0; head[1] as usize];
+  reader.read_exact(&mut reply).unwrap();
+  assert_eq!(
+    String::from_utf8(reply).unwrap(),
+    r#"{"id":null,"ok":true}"#
+  );
+
+  send(&mut client, 0x8, &[0x03, 0xE8]);
+  reader.read_exact(&mut head).unwrap();
+  assert_eq!(head, [0x88, 2]);
+  server.join().unwrap();
+}
@@ -0,0 +1,24 @@
+use flemu_core::nes::ppu::Frame;
+use flemu_core::nes::splash;
+
+#[test]
+fn test_splash_draws_the_logo() {
+  let mut frame = Frame::new();
+  splash::draw(&mut frame, 0);
+  // top-left corner of the F
+  assert_eq!(frame.pixel(70, 64), 0x21);
+  assert_eq!(frame.pixel(0, 0), 0x0f);
+  assert_ne!(frame.pixel(0, 239), 0x0f);
+}
+
+#[test]
+fn test_splash_is_animated_and_deterministic() {
+  let mut a = Frame::new();
+  let mut b = Frame::new();
+  splash::draw(&mut a, 10);
+  splash::draw(&mut b, 10);
+  assert_eq!(a.data, b.data);
+
+  splash::draw(&mut b, 11);
+  assert_ne!(a.data, b.data);
+}
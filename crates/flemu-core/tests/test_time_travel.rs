@@ -0,0 +1,52 @@
+use flemu_core::nes::bus::Mem;
+use flemu_core::nes::cpu::*;
+use flemu_core::nes::time_travel::*;
+
+// INX; STX $10; JMP $8000
+fn counting_cpu() -> CPU {
+  let mut cpu = CPU::new();
+  cpu.load(vec![0xe8, 0x86, 0x10, 0x4c, 0x00, 0x80]).unwrap();
+  cpu.halt_on_brk = false;
+  cpu
+}
+
+#[test]
+fn test_step_back_restores_every_instruction() {
+  let mut cpu = counting_cpu();
+  let mut travel = TimeTravel::new(4, 5);
+  let mut states = vec![cpu.state()];
+  for _ in 0..12 {
+    travel.step(&mut cpu).unwrap();
+    states.push(cpu.state());
+  }
+  assert_eq!(travel.position(), 12);
+
+  for position in (0..12).rev() {
+    travel.step_back(&mut cpu).unwrap();
+    assert_eq!(travel.position(), position);
+    assert_eq!(cpu.state(), states[position as usize]);
+  }
+  assert_eq!(cpu.mem_peek(0x10), 0);
+  assert_eq!(travel.step_back(&mut cpu), Err(TimeTravelError::AtStart));
+
+  // and forward again, the trace log rebuilt with it
+  travel.seek(&mut cpu, 7).unwrap();
+  assert_eq!(cpu.state(), states[7]);
+  assert_eq!(cpu.trace().iter().last().unwrap().state, states[6]);
+}
+
+#[test]
+fn test_window() {
+  let mut cpu = counting_cpu();
+  let mut travel = TimeTravel::new(2, 3);
+  for _ in 0..10 {
+    travel.step(&mut cpu).unwrap();
+  }
+  // checkpoints at 6 and 9 are left
+  travel.seek(&mut cpu, 6).unwrap();
+  assert_eq!(
+    travel.seek(&mut cpu, 5),
+    Err(TimeTravelError::OutOfWindow(5))
+  );
+  assert_eq!(travel.position(), 6);
+}
@@ -0,0 +1,116 @@
+use flemu_core::nes::apu::Apu;
+use flemu_core::nes::bus::{Bus, Mem};
+use flemu_core::nes::cartridge::{Region, Rom};
+use flemu_core::nes::mapper::NoCartridge;
+use flemu_core::nes::ppu::{NesPPU, StatusRegister, DOTS_PER_SCANLINE};
+use flemu_core::nes::timing::Timing;
+
+fn rom(region: Region) -> Rom {
+  let mut rom = Rom::from_program(&[]);
+  rom.info.region = region;
+  rom
+}
+
+// CPU cycles from the next vblank to the one `frames` later
+fn frame_cycles(bus: &mut Bus, frames: u64) -> u32 {
+  let frame = bus.ppu.frame_count;
+  while bus.ppu.frame_count == frame {
+    bus.tick(1);
+  }
+  let mut cycles = 0;
+  while bus.ppu.frame_count < frame + 1 + frames {
+    bus.tick(1);
+    cycles += 1;
+  }
+  cycles
+}
+
+#[test]
+fn test_timing_ids() {
+  for timing in Timing::ALL.iter() {
+    assert_eq!(Timing::from_id(timing.id()), Some(*timing));
+  }
+  assert_eq!(Timing::from_id("secam"), None);
+  assert_eq!(Timing::for_region(Region::MultiRegion), Timing::Ntsc);
+  assert_eq!(Timing::for_region(Region::Dendy), Timing::Dendy);
+}
+
+#[test]
+fn test_frame_rates() {
+  assert!((Timing::Ntsc.frame_rate() - 60.0988).abs() < 0.001);
+  assert!((Timing::Pal.frame_rate() - 50.007).abs() < 0.001);
+  assert!((Timing::Dendy.frame_rate() - 50.0).abs() < 0.01);
+}
+
+#[test]
+fn test_pal_and_dendy_frames_are_312_lines() {
+  for &(timing, vblank) in [(Timing::Pal, 241), (Timing::Dendy, 291)].iter() {
+    let mut ppu = NesPPU::new();
+    ppu.set_timing(timing);
+    // rendering on: no dot is skipped on odd frames
+    ppu.write_to_mask(0b0000_1000);
+    let frame = DOTS_PER_SCANLINE as u32 * 312;
+
+    ppu.tick(&NoCartridge, DOTS_PER_SCANLINE as u32 * vblank as u32 + 1);
+    assert_eq!((ppu.scanline, ppu.cycle), (vblank, 1));
+    assert!(ppu.status.contains(StatusRegister::VBLANK_STARTED));
+    ppu.tick(&NoCartridge, frame);
+    assert_eq!((ppu.scanline, ppu.cycle), (vblank, 1));
+    ppu.tick(&NoCartridge, frame);
+    assert_eq!((ppu.scanline, ppu.cycle), (vblank, 1));
+    assert_eq!(ppu.frame_count, 3);
+  }
+}
+
+#[test]
+fn test_pal_runs_3_2_dots_per_cycle() {
+  let mut bus = Bus::with_rom(rom(Region::Pal)).unwrap();
+  // 341 * 312 / 3.2 = 33247.5
+  assert_eq!(frame_cycles(&mut bus, 2), 66495);
+
+  bus.set_timing_override(Some(Timing::Dendy));
+  assert_eq!(frame_cycles(&mut bus, 1), 341 * 312 / 3);
+}
+
+#[test]
+fn test_timing_follows_the_header_unless_overridden() {
+  assert_eq!(Bus::new().timing(), Timing::Ntsc);
+  let mut bus = Bus::with_rom(rom(Region::Pal)).unwrap();
+  assert_eq!(bus.timing(), Timing::Pal);
+  bus.set_timing_override(Some(Timing::Ntsc));
+  assert_eq!(bus.timing(), Timing::Ntsc);
+  assert_eq!(bus.ppu.timing(), Timing::Ntsc);
+
+  // the override outlives cartridge swaps
+  bus.insert_cartridge(rom(Region::Dendy)).unwrap();
+  assert_eq!(bus.timing(), Timing::Ntsc);
+  bus.set_timing_override(None);
+  assert_eq!(bus.timing(), Timing::Dendy);
+}
+
+#[test]
+fn test_pal_frame_counter() {
+  let mut apu = Apu::new();
+  apu.set_timing(Timing::Pal);
+  // past NTSC's sequence, not yet at PAL's
+  apu.tick(&NoCartridge, 33251);
+  assert!(!apu.irq_pending());
+  apu.tick(&NoCartridge, 1);
+  assert!(apu.irq_pending());
+}
+
+#[test]
+fn test_pal_noise_and_dmc_periods() {
+  let mut apu = Apu::new();
+  apu.write_register(0x400E, 0x0F);
+  apu.write_register(0x4010, 0x00);
+  assert_eq!(apu.noise.timer_period(), 4067);
+  assert_eq!(apu.dmc.timer_period(), 427);
+
+  // switching over retunes the channels without another write
+  apu.set_timing(Timing::Pal);
+  assert_eq!(apu.noise.timer_period(), 3777);
+  assert_eq!(apu.dmc.timer_period(), 397);
+  apu.set_timing(Timing::Dendy);
+  assert_eq!(apu.noise.timer_period(), 4067);
+}
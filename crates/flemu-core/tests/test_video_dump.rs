@@ -0,0 +1,109 @@
+use flemu_core::video_dump::*;
+
+fn be32(bytes: &[u8]) -> u32 {
+  u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Chunks of a PNG as (type, data).
+fn chunks(png: &[u8]) -> Vec<(String, Vec<u8>)> {
+  let mut chunks = Vec::new();
+  let mut at = 8;
+  while at < png.len() {
+    let len = be32(&png[at..]) as usize;
+    let kind = String::from_utf8(png[at + 4..at + 8].to_vec()).unwrap();
+    chunks.push((kind, png[at + 8..at + 8 + len].to_vec()));
+    at += 12 + len;
+  }
+  chunks
+}
+
+// a PNG of another color type, as an image editor might save one
+fn foreign_png(width: u32, color: png::ColorType, pixels: &[u8]) -> Vec<u8> {
+  let mut out = Vec::new();
+  let mut encoder = png::Encoder::new(&mut out, width, 1);
+  encoder.set_color(color);
+  encoder.set_depth(png::BitDepth::Eight);
+  if color == png::ColorType::Indexed {
+    encoder.set_palette(vec![0x10, 0x20, 0x30, 0xa0, 0xb0, 0xc0]);
+  }
+  let mut writer = encoder.write_header().unwrap();
+  writer.write_image_data(pixels).unwrap();
+  drop(writer);
+  out
+}
+
+#[test]
+fn test_png_structure() {
+  let rgba: Vec<u8> = (0..3 * 2 * 4).map(|i| i as u8).collect();
+  let png = encode_png(3, 2, &rgba);
+  assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+
+  let chunks = chunks(&png);
+  let kinds: Vec<&str> = chunks.iter().map(|(kind, _)| kind.as_str()).collect();
+  assert_eq!(kinds, vec!["IHDR", "IDAT", "IEND"]);
+  // 8-bit RGBA, deflate, adaptive filtering, no interlace
+  assert_eq!(chunks[0].1, vec![0, 0, 0, 3, 0, 0, 0, 2, 8, 6, 0, 0, 0]);
+  // the well-known CRC of an empty IEND
+  assert_eq!(be32(&png[png.len() - 4..]), 0xAE42_6082);
+}
+
+#[test]
+fn test_png_of_a_full_frame_is_compressed() {
+  let rgba = vec![0x80; 256 * 240 * 4];
+  let png = encode_png(256, 240, &rgba);
+  assert!(png.len() < 4096, "{} bytes", png.len());
+}
+
+#[test]
+fn test_decode_reads_back_what_encode_wrote() {
+  let rgba: Vec<u8> = (0..256 * 240 * 4).map(|i| (i * 7) as u8).collect();
+  let png = encode_png(256, 240, &rgba);
+  assert_eq!(decode_png(&png), Some((256, 240, rgba)));
+
+  assert_eq!(decode_png(&png[..png.len() / 2]), None);
+  assert_eq!(decode_png(b"\x89PNG"), None);
+}
+
+#[test]
+fn test_decode_turns_other_color_types_into_rgba() {
+  let rgb = foreign_png(2, png::ColorType::RGB, &[1, 2, 3, 4, 5, 6]);
+  assert_eq!(
+    decode_png(&rgb),
+    Some((2, 1, vec![1, 2, 3, 0xff, 4, 5, 6, 0xff]))
+  );
+
+  let gray = foreign_png(2, png::ColorType::Grayscale, &[7, 8]);
+  assert_eq!(
+    decode_png(&gray),
+    Some((2, 1, vec![7, 7, 7, 0xff, 8, 8, 8, 0xff]))
+  );
+
+  let indexed = foreign_png(2, png::ColorType::Indexed, &[1, 0]);
+  assert_eq!(
+    decode_png(&indexed),
+    Some((2, 1, vec![0xa0, 0xb0, 0xc0, 0xff, 0x10, 0x20, 0x30, 0xff]))
+  );
+}
+
+#[test]
+fn test_every_nth_frame_once() {
+  let mut dump = VideoDump::new(3);
+  let dumped: Vec<u64> = [1, 2, 3, 3, 4, 6, 7, 9, 9]
+    .iter()
+    .copied()
+    .filter(|&frame| dump.should_dump(frame))
+    .collect();
+  assert_eq!(dumped, vec![3, 6, 9]);
+  assert_eq!(VideoDump::new(0).every(), 1);
+  assert_eq!(frame_file_name(42), "frame-000042.png");
+}
+
+#[test]
+fn test_crop() {
+  // 3x3 pixels numbered 0-8, one byte of each channel
+  let rgba: Vec<u8> = (0..9).flat_map(|pixel| vec![pixel; 4]).collect();
+  let cropped = crop_rgba(&rgba, 3, (1, 1, 2, 2));
+  let pixels: Vec<u8> = cropped.chunks(4).map(|pixel| pixel[0]).collect();
+  assert_eq!(pixels, vec![4, 5, 7, 8]);
+  assert_eq!(crop_rgba(&rgba, 3, (0, 0, 3, 3)), rgba);
+}
@@ -0,0 +1,69 @@
+use flemu_core::nes::bus::{Bus, Mem};
+use flemu_core::nes::cartridge::Rom;
+use flemu_core::nes::cpu::CPU;
+use flemu_core::nes::warnings::*;
+
+#[test]
+fn test_rate_limit_per_kind() {
+  let mut warnings = Warnings::default();
+  for _ in 0..10 {
+    warnings.report(0, Warning::OpenBusRead { addr: 0x2000 });
+  }
+  warnings.report(
+    1,
+    Warning::RomWrite {
+      addr: 0x8000,
+      value: 1,
+    },
+  );
+  let reports = warnings.take();
+  assert_eq!(reports.len(), MAX_PER_WINDOW as usize + 1);
+  assert_eq!(reports.last().unwrap().frame, 1);
+  assert_eq!(warnings.suppressed(), 10 - MAX_PER_WINDOW as u64);
+
+  // a new window lets them through again
+  warnings.report(WINDOW_FRAMES, Warning::OpenBusRead { addr: 0x2000 });
+  assert_eq!(warnings.take().len(), 1);
+  assert!(warnings.take().is_empty());
+}
+
+#[test]
+fn test_bus_reports() {
+  let mut bus = Bus::with_rom(Rom::from_program(&[])).unwrap();
+  bus.mem_read(0x2000);
+  bus.mem_write(0x8000, 0x42);
+  // fine: RAM, a readable register, peeks of write-only ones
+  bus.mem_read(0x0000);
+  bus.mem_read(0x2002);
+  bus.mem_peek(0x2001);
+
+  let warnings: Vec<Warning> = bus.warnings.take().iter().map(|r| r.warning).collect();
+  assert_eq!(
+    warnings,
+    vec![
+      Warning::OpenBusRead { addr: 0x2000 },
+      Warning::RomWrite {
+        addr: 0x8000,
+        value: 0x42
+      },
+    ]
+  );
+  assert_eq!(warnings[1].kind(), "rom-write");
+  assert_eq!(warnings[1].to_string(), "write of $42 to ROM at $8000");
+}
+
+#[test]
+fn test_unofficial_opcode() {
+  let mut cpu = CPU::new();
+  // NOP $00 (unofficial DOP)
+  cpu.load(vec![0x04, 0x00, 0x00]).unwrap();
+  cpu.step().unwrap();
+  let reports = cpu.bus.warnings.take();
+  assert_eq!(
+    reports[0].warning,
+    Warning::UnofficialOpcode {
+      pc: 0x8000,
+      code: 0x04
+    }
+  );
+}
@@ -0,0 +1,30 @@
+use flemu_core::wav::*;
+
+fn le32(bytes: &[u8]) -> u32 {
+  u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+#[test]
+fn test_wav_header() {
+  let wav = encode_wav(44_100, &[0, -1, 0x1234]);
+  assert_eq!(wav.len(), 44 + 6);
+  assert_eq!(&wav[..4], b"RIFF");
+  assert_eq!(le32(&wav[4..]), 36 + 6);
+  assert_eq!(&wav[8..16], b"WAVEfmt ");
+  // PCM, mono
+  assert_eq!(&wav[20..24], &[1, 0, 1, 0]);
+  assert_eq!(le32(&wav[24..]), 44_100);
+  assert_eq!(le32(&wav[28..]), 88_200);
+  assert_eq!(&wav[36..40], b"data");
+  assert_eq!(le32(&wav[40..]), 6);
+  assert_eq!(&wav[44..], &[0, 0, 0xff, 0xff, 0x34, 0x12]);
+}
+
+#[test]
+fn test_recorder_spans_the_sample_range() {
+  let mut recorder = WavRecorder::new(4);
+  recorder.push(&[0.0, 1.0, 0.5, 2.0]);
+  assert_eq!(recorder.samples(), &[-32768, 32767, 0, 32767]);
+  assert!((recorder.duration() - 1.0).abs() < 1e-9);
+  assert_eq!(recorder.to_wav(), encode_wav(4, recorder.samples()));
+}
@@ -0,0 +1,68 @@
+use flemu_core::nes::bus::{Bus, Mem};
+use flemu_core::nes::ppu::Frame;
+use flemu_core::nes::zapper::*;
+
+// white box around (100, 50) on black, as Duck Hunt flashes
+fn target_frame() -> Frame {
+  let mut frame = Frame::new();
+  frame.data.iter_mut().for_each(|pixel| *pixel = 0x0F);
+  for y in 40..60 {
+    for x in 90..110 {
+      frame.set_pixel(x, y, 0x30);
+    }
+  }
+  frame
+}
+
+#[test]
+fn test_light_sense_follows_the_beam() {
+  let frame = target_frame();
+  let mut zapper = Zapper::default();
+  zapper.aim(100, 50);
+  // not drawn yet this frame, then lit, then faded
+  assert!(!zapper.light_sensed(&frame, 30));
+  assert!(zapper.light_sensed(&frame, 55));
+  assert!(!zapper.light_sensed(&frame, 100));
+  assert_eq!(zapper.read(&frame, 55), 0x40);
+  assert_eq!(zapper.read(&frame, 100), 0x48);
+
+  // pointing at black or off screen
+  zapper.aim(10, 50);
+  assert!(!zapper.light_sensed(&frame, 55));
+  zapper.aim(300, 50);
+  assert_eq!(zapper.aimed_at(), None);
+  assert!(!zapper.light_sensed(&frame, 55));
+
+  assert!(is_bright(0x20) && is_bright(0x3C));
+  assert!(!is_bright(0x0F) && !is_bright(0x16) && !is_bright(0x2D));
+}
+
+#[test]
+fn test_trigger_pulse() {
+  let mut zapper = Zapper::default();
+  zapper.pull_trigger();
+  for _ in 0..TRIGGER_FRAMES {
+    assert!(zapper.trigger_pulled());
+    zapper.end_frame();
+  }
+  assert!(!zapper.trigger_pulled());
+}
+
+#[test]
+fn test_port_two() {
+  let mut bus = Bus::new();
+  bus.set_zapper(true);
+  bus.ppu.frame = target_frame();
+  bus.ppu.scanline = 55;
+  let zapper = bus.zapper_mut().unwrap();
+  zapper.aim(100, 50);
+  zapper.pull_trigger();
+  // no shifting, every read says the same
+  assert_eq!(bus.mem_read(0x4017), 0x50);
+  assert_eq!(bus.mem_read(0x4017), 0x50);
+  assert_eq!(bus.mem_peek(0x4017), 0x50);
+
+  bus.set_zapper(false);
+  assert!(bus.zapper().is_none());
+  assert_eq!(bus.mem_read(0x4017), 0x40);
+}
@@ -0,0 +1,107 @@
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, SampleFormat, SizedSample, Stream, StreamConfig};
+use flemu_core::nes::apu::SampleRing;
+use std::sync::{Arc, Mutex};
+
+/*
+  Sound through the default output device. cpal calls back from its own
+  thread, so the APU's samples cross over through a shared ring: the main
+  loop pushes what every frame produced, the callback pops, holding the
+  last level when it runs dry. How full the ring is also paces the
+  emulation, see `Audio::wants_frame`.
+*/
+
+// about 100ms at 48kHz
+const RING_CAPACITY: usize = 4_800;
+// keep roughly two frames queued
+const TARGET_FRAMES: usize = 2;
+
+pub struct Audio {
+  ring: Arc<Mutex<SampleRing>>,
+  sample_rate: u32,
+  // kept alive: dropping the stream stops the sound
+  _stream: Stream,
+}
+
+impl Audio {
+  /// Open the default output device, None (with a warning) if there is no
+  /// usable one, in which case the game runs silent.
+  pub fn open() -> Option<Audio> {
+    match Audio::try_open() {
+      Ok(audio) => Some(audio),
+      Err(e) => {
+        eprintln!("no sound: {}", e);
+        None
+      }
+    }
+  }
+
+  fn try_open() -> Result<Audio, String> {
+    let device = cpal::default_host()
+      .default_output_device()
+      .ok_or_else(|| "no output device".to_string())?;
+    let supported = device.default_output_config().map_err(|e| e.to_string())?;
+    let format = supported.sample_format();
+    let config: StreamConfig = supported.into();
+    let ring = Arc::new(Mutex::new(SampleRing::new(RING_CAPACITY)));
+    let stream = match format {
+      SampleFormat::F32 => build::<f32>(&device, &config, ring.clone()),
+      SampleFormat::I16 => build::<i16>(&device, &config, ring.clone()),
+      SampleFormat::U16 => build::<u16>(&device, &config, ring.clone()),
+      format => return Err(format!("unsupported sample format {}", format)),
+    }?;
+    stream.play().map_err(|e| e.to_string())?;
+    Ok(Audio {
+      ring,
+      sample_rate: config.sample_rate.0,
+      _stream: stream,
+    })
+  }
+
+  pub fn sample_rate(&self) -> u32 {
+    self.sample_rate
+  }
+
+  pub fn push(&self, samples: &[f32]) {
+    let mut ring = self.ring.lock().unwrap();
+    for &sample in samples {
+      ring.push(sample);
+    }
+  }
+
+  /// Whether the device is running low and the emulator should produce
+  /// another frame. Running to the sound card's clock keeps the audio
+  /// gapless; the picture follows at whatever rate that works out to.
+  pub fn wants_frame(&self, samples_per_frame: usize) -> bool {
+    self.ring.lock().unwrap().len() < samples_per_frame * TARGET_FRAMES
+  }
+}
+
+fn build<T>(
+  device: &cpal::Device,
+  config: &StreamConfig,
+  ring: Arc<Mutex<SampleRing>>,
+) -> Result<Stream, String>
+where
+  T: SizedSample + FromSample<f32>,
+{
+  let channels = config.channels as usize;
+  let mut mono = Vec::new();
+  device
+    .build_output_stream(
+      config,
+      move |out: &mut [T], _: &cpal::OutputCallbackInfo| {
+        // the APU is mono: the same sample on every channel
+        mono.resize(out.len() / channels, 0.0);
+        ring.lock().unwrap().pop_into(&mut mono);
+        for (frame, &sample) in out.chunks_mut(channels).zip(&mono) {
+          for channel in frame {
+            *channel = T::from_sample(sample);
+          }
+        }
+      },
+      |e| eprintln!("sound: {}", e),
+      None,
+    )
+    .map_err(|e| e.to_string())
+}
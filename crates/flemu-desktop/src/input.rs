@@ -0,0 +1,124 @@
+use flemu_core::nes::joypad::JoypadButton;
+use gilrs::{Axis, Button, Gilrs};
+use minifb::{Key, Window};
+
+/*
+  Keyboard and gamepads to controller buttons. The keyboard drives the
+  first controller with the same layout as the web player's defaults;
+  gamepads take the ports in the order they were connected, the first one
+  sharing port 0 with the keyboard. Turbo A and B are on S and A, and
+  above A and B on a pad.
+*/
+
+pub const PORTS: usize = 4;
+
+const KEYS: [(Key, JoypadButton); 8] = [
+  (Key::X, JoypadButton::A),
+  (Key::Z, JoypadButton::B),
+  (Key::RightShift, JoypadButton::SELECT),
+  (Key::Enter, JoypadButton::START),
+  (Key::Up, JoypadButton::UP),
+  (Key::Down, JoypadButton::DOWN),
+  (Key::Left, JoypadButton::LEFT),
+  (Key::Right, JoypadButton::RIGHT),
+];
+
+const TURBO_KEYS: [(Key, JoypadButton); 2] = [(Key::S, JoypadButton::A), (Key::A, JoypadButton::B)];
+
+// NES A sits right of B, like East of South on a modern pad
+const BUTTONS: [(Button, JoypadButton); 8] = [
+  (Button::East, JoypadButton::A),
+  (Button::South, JoypadButton::B),
+  (Button::Select, JoypadButton::SELECT),
+  (Button::Start, JoypadButton::START),
+  (Button::DPadUp, JoypadButton::UP),
+  (Button::DPadDown, JoypadButton::DOWN),
+  (Button::DPadLeft, JoypadButton::LEFT),
+  (Button::DPadRight, JoypadButton::RIGHT),
+];
+
+const TURBO_BUTTONS: [(Button, JoypadButton); 2] = [
+  (Button::North, JoypadButton::A),
+  (Button::West, JoypadButton::B),
+];
+
+/// What one controller has held.
+#[derive(Debug, Clone, Copy)]
+pub struct Held {
+  pub buttons: JoypadButton,
+  /// The buttons whose turbo button is held.
+  pub turbo: JoypadButton,
+}
+
+impl Default for Held {
+  fn default() -> Self {
+    Held {
+      buttons: JoypadButton::empty(),
+      turbo: JoypadButton::empty(),
+    }
+  }
+}
+
+// how far the left stick has to lean to count as the d-pad
+const STICK_THRESHOLD: f32 = 0.5;
+
+pub struct Input {
+  // None when the platform has no gamepad support; the keyboard still works
+  gilrs: Option<Gilrs>,
+}
+
+impl Input {
+  pub fn new() -> Self {
+    let gilrs = match Gilrs::new() {
+      Ok(gilrs) => Some(gilrs),
+      Err(e) => {
+        eprintln!("no gamepads: {}", e);
+        None
+      }
+    };
+    Input { gilrs }
+  }
+
+  /// Buttons held on every port right now.
+  pub fn poll(&mut self, window: &Window) -> [Held; PORTS] {
+    let mut ports = [Held::default(); PORTS];
+    for &(key, button) in KEYS.iter() {
+      ports[0].buttons.set(button, window.is_key_down(key));
+    }
+    for &(key, button) in TURBO_KEYS.iter() {
+      ports[0].turbo.set(button, window.is_key_down(key));
+    }
+
+    if let Some(gilrs) = self.gilrs.as_mut() {
+      // gamepad state only moves forward as its events are read
+      while gilrs.next_event().is_some() {}
+      for (held, (_, pad)) in ports.iter_mut().zip(gilrs.gamepads()) {
+        for &(pad_button, button) in TURBO_BUTTONS.iter() {
+          if pad.is_pressed(pad_button) {
+            held.turbo.insert(button);
+          }
+        }
+        let port = &mut held.buttons;
+        for &(pad_button, button) in BUTTONS.iter() {
+          if pad.is_pressed(pad_button) {
+            port.insert(button);
+          }
+        }
+        let (x, y) = (pad.value(Axis::LeftStickX), pad.value(Axis::LeftStickY));
+        if x < -STICK_THRESHOLD {
+          port.insert(JoypadButton::LEFT);
+        }
+        if x > STICK_THRESHOLD {
+          port.insert(JoypadButton::RIGHT);
+        }
+        if y > STICK_THRESHOLD {
+          port.insert(JoypadButton::UP);
+        }
+        if y < -STICK_THRESHOLD {
+          port.insert(JoypadButton::DOWN);
+        }
+      }
+    }
+    ports
+  }
+}
@@ -0,0 +1,131 @@
+//! Play a ROM in a native window.
+//!
+//!   flemu-desktop <rom> [scale]
+//!
+//! Keyboard on controller 1: arrows, X = A, Z = B, Enter = Start,
+//! right Shift = Select; gamepads take controllers 1 to 4 in the order
+//! they were plugged in. `scale` is 1, 2 (the default) or 4. Escape or
+//! closing the window quits.
+
+mod audio;
+mod input;
+
+use audio::Audio;
+use flemu_core::nes::cartridge::Rom;
+use flemu_core::nes::cpu::CPU;
+use flemu_core::nes::game_db::{GameDb, Peripheral};
+use flemu_core::nes::joypad::JoypadButton;
+use flemu_core::nes::palette::Palette;
+use flemu_core::nes::ppu::Frame;
+use input::Input;
+use minifb::{Key, Scale, Window, WindowOptions};
+use std::time::Duration;
+use std::{env, fs, process, thread};
+
+const USAGE: &str = "usage: flemu-desktop <rom> [scale]";
+
+fn fail(message: String) -> ! {
+  eprintln!("{}", message);
+  process::exit(2);
+}
+
+fn scale(arg: Option<&String>) -> Scale {
+  match arg.map(String::as_str) {
+    None | Some("2") => Scale::X2,
+    Some("1") => Scale::X1,
+    Some("4") => Scale::X4,
+    Some(_) => fail(USAGE.to_string()),
+  }
+}
+
+// the frame as minifb's 0RGB pixels
+fn pixels(palette: &Palette, frame: &Frame, out: &mut Vec<u32>) {
+  out.clear();
+  out.extend(
+    palette
+      .frame_rgba(frame)
+      .chunks(4)
+      .map(|p| (p[0] as u32) << 16 | (p[1] as u32) << 8 | p[2] as u32),
+  );
+}
+
+fn main() {
+  let args: Vec<String> = env::args().skip(1).collect();
+  if args.is_empty() || args.len() > 2 {
+    fail(USAGE.to_string());
+  }
+  let rom_path = &args[0];
+  let scale = scale(args.get(1));
+
+  let bytes = fs::read(rom_path).unwrap_or_else(|e| fail(format!("{}: {}", rom_path, e)));
+  let mut rom = Rom::from_bytes(&bytes).unwrap_or_else(|e| fail(format!("{}: {}", rom_path, e)));
+  // the database knows better than a bad header
+  let game = GameDb::builtin().identify(&rom).cloned();
+  if let Some(game) = &game {
+    game.apply(&mut rom.info);
+  }
+  let mut cpu = CPU::new();
+  cpu.reset();
+  cpu.halt_on_brk = false;
+  cpu
+    .load_rom(rom)
+    .unwrap_or_else(|e| fail(format!("{}: {}", rom_path, e)));
+  // there's no mouse for a Zapper, but gamepads can be players 3 and 4
+  let four_score = game.as_ref().and_then(|game| game.peripheral) == Some(Peripheral::FourScore);
+  cpu.bus.set_four_score(four_score);
+
+  let frame_rate = cpu.bus.timing().frame_rate();
+
+  let options = WindowOptions {
+    scale,
+    ..WindowOptions::default()
+  };
+  let title = game.map_or("flemu".to_string(), |game| {
+    format!("flemu - {}", game.title)
+  });
+  let mut window = Window::new(&title, Frame::WIDTH, Frame::HEIGHT, options)
+    .unwrap_or_else(|e| fail(format!("cannot open a window: {}", e)));
+  let audio = Audio::open();
+  match &audio {
+    Some(audio) => cpu.bus.apu.set_sample_rate(audio.sample_rate()),
+    // nothing to pace against: let the window hold the console's rate
+    None => window.set_target_fps(frame_rate.round() as usize),
+  }
+  let samples_per_frame = audio.as_ref().map_or(0, |audio| {
+    (audio.sample_rate() as f64 / frame_rate) as usize
+  });
+  let mut input = Input::new();
+
+  let palette = Palette::default();
+  let mut buffer = Vec::with_capacity(Frame::WIDTH * Frame::HEIGHT);
+  while window.is_open() && !window.is_key_down(Key::Escape) {
+    let ready = match &audio {
+      Some(audio) => audio.wants_frame(samples_per_frame),
+      None => true,
+    };
+    if !ready {
+      window.update();
+      thread::sleep(Duration::from_millis(1));
+      continue;
+    }
+
+    for (pad, held) in cpu.bus.joypads.iter_mut().zip(&input.poll(&window)) {
+      pad.set_button_pressed_status(JoypadButton::all(), false);
+      pad.set_button_pressed_status(held.buttons, true);
+      pad.set_turbo_pressed_status(JoypadButton::all(), false);
+      pad.set_turbo_pressed_status(held.turbo, true);
+    }
+    if !cpu.run_frame() {
+      fail(format!("stopped at frame {}", cpu.bus.ppu.frame_count));
+    }
+    let samples = cpu.bus.apu.take_samples();
+    if let Some(audio) = &audio {
+      audio.push(&samples);
+    }
+
+    pixels(&palette, &cpu.bus.ppu.frame, &mut buffer);
+    window
+      .update_with_buffer(&buffer, Frame::WIDTH, Frame::HEIGHT)
+      .unwrap_or_else(|e| fail(e.to_string()));
+  }
+}
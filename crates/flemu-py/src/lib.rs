@@ -0,0 +1,160 @@
+//! The `flemu` Python module: a console to load a ROM into, step, press
+//! buttons on and read the results of, frames and memory as `bytes` so
+//! numpy can take them without copying.
+//!
+//!   import flemu
+//!   nes = flemu.Nes.load("game.nes")
+//!   nes.set_buttons(0, flemu.A | flemu.RIGHT)
+//!   nes.step(60)
+//!   screen = numpy.frombuffer(nes.frame(), numpy.uint8).reshape(240, 256, 3)
+//!   lives = nes.read(0x075A)[0]
+//!
+//! Buttons are held until set again. Memory is CPU address space, read
+//! without side effects; writes go through the bus as a store would.
+
+use flemu_core::nes::bus::Mem;
+use flemu_core::nes::cartridge::Rom;
+use flemu_core::nes::cpu::CPU;
+use flemu_core::nes::joypad::JoypadButton;
+use flemu_core::nes::palette::Palette;
+use flemu_core::nes::ppu::Frame;
+use pyo3::exceptions::{PyIndexError, PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use std::fs;
+
+/// The module's button constants, to OR together for `set_buttons`.
+const BUTTONS: [(&str, JoypadButton); 8] = [
+  ("A", JoypadButton::A),
+  ("B", JoypadButton::B),
+  ("SELECT", JoypadButton::SELECT),
+  ("START", JoypadButton::START),
+  ("UP", JoypadButton::UP),
+  ("DOWN", JoypadButton::DOWN),
+  ("LEFT", JoypadButton::LEFT),
+  ("RIGHT", JoypadButton::RIGHT),
+];
+
+/// A console with a cartridge in it.
+#[pyclass(unsendable)]
+struct Nes {
+  cpu: CPU,
+  palette: Palette,
+}
+
+#[pymethods]
+impl Nes {
+  /// The console with `rom`, the bytes of an iNES or NES 2.0 file.
+  #[new]
+  fn new(rom: &[u8]) -> PyResult<Self> {
+    let rom = Rom::from_bytes(rom).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    // BRK is an instruction, not the end of the program
+    let mut cpu = CPU::new();
+    cpu.reset();
+    cpu.halt_on_brk = false;
+    cpu
+      .load_rom(rom)
+      .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    Ok(Nes {
+      cpu,
+      palette: Palette::default(),
+    })
+  }
+
+  /// The console with the ROM file at `path`.
+  #[staticmethod]
+  fn load(path: &str) -> PyResult<Self> {
+    Nes::new(&fs::read(path)?)
+  }
+
+  /// The reset button.
+  fn reset(&mut self) {
+    self.cpu.reset();
+  }
+
+  /// Run `frames` frames, returning the frame count after. Raises
+  /// RuntimeError if the CPU stops.
+  #[pyo3(signature = (frames = 1))]
+  fn step(&mut self, frames: u32) -> PyResult<u64> {
+    for _ in 0..frames {
+      if !self.cpu.run_frame() {
+        return Err(PyRuntimeError::new_err(format!(
+          "stopped at ${:04X}",
+          self.cpu.program_counter
+        )));
+      }
+    }
+    Ok(self.cpu.bus.ppu.frame_count)
+  }
+
+  /// Frames run since power on.
+  #[getter]
+  fn frame_count(&self) -> u64 {
+    self.cpu.bus.ppu.frame_count
+  }
+
+  /// The last frame, WIDTH x HEIGHT RGB pixels, rows top to bottom.
+  fn frame<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+    let rgba = self.palette.frame_rgba(&self.cpu.bus.ppu.frame);
+    let rgb: Vec<u8> = rgba
+      .chunks(4)
+      .flat_map(|pixel| pixel[..3].iter().copied())
+      .collect();
+    PyBytes::new(py, &rgb)
+  }
+
+  /// The last frame as palette indices, a byte a pixel, for smaller
+  /// observations than `frame`.
+  fn frame_indices<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+    PyBytes::new(py, &self.cpu.bus.ppu.frame.data)
+  }
+
+  /// Hold exactly `buttons`, the module's constants ORed together, on
+  /// controller `player` (0-3).
+  fn set_buttons(&mut self, player: usize, buttons: u8) -> PyResult<()> {
+    let pad = self
+      .cpu
+      .bus
+      .joypads
+      .get_mut(player)
+      .ok_or_else(|| PyIndexError::new_err(format!("no player {}", player)))?;
+    pad.set_button_pressed_status(JoypadButton::all(), false);
+    pad.set_button_pressed_status(JoypadButton::from_bits_truncate(buttons), true);
+    Ok(())
+  }
+
+  /// `length` bytes from `addr`, without side effects; no further than
+  /// $FFFF.
+  #[pyo3(signature = (addr, length = 1))]
+  fn read<'py>(&self, py: Python<'py>, addr: u16, length: usize) -> PyResult<Bound<'py, PyBytes>> {
+    if addr as usize + length > 0x10000 {
+      return Err(PyIndexError::new_err("read goes past $FFFF"));
+    }
+    let data: Vec<u8> = (0..length)
+      .map(|i| self.cpu.mem_peek(addr + i as u16))
+      .collect();
+    Ok(PyBytes::new(py, &data))
+  }
+
+  /// Store `data` from `addr` on, as the CPU would, registers and all.
+  fn write(&mut self, addr: u16, data: &[u8]) -> PyResult<()> {
+    if addr as usize + data.len() > 0x10000 {
+      return Err(PyIndexError::new_err("write goes past $FFFF"));
+    }
+    for (i, &byte) in data.iter().enumerate() {
+      self.cpu.mem_write(addr + i as u16, byte);
+    }
+    Ok(())
+  }
+}
+
+#[pymodule]
+fn flemu(m: &Bound<'_, PyModule>) -> PyResult<()> {
+  m.add_class::<Nes>()?;
+  m.add("WIDTH", Frame::WIDTH)?;
+  m.add("HEIGHT", Frame::HEIGHT)?;
+  for (name, button) in BUTTONS.iter() {
+    m.add(*name, button.bits())?;
+  }
+  Ok(())
+}
@@ -0,0 +1,86 @@
+use crate::error::FlemuError;
+use crate::nes::movie::Movie;
+use crate::nes::savestate;
+use crate::{invalid, Emulator, MovieMode};
+use log::warn;
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+impl Emulator {
+  /// Attract mode: play `fm2` whenever the inserted cartridge is in and
+  /// nobody has pressed anything for `set_attract_idle` seconds, until
+  /// someone does. Each cartridge keeps its own; this replaces the
+  /// inserted one's.
+  pub fn set_demo_movie(&mut self, fm2: &str) -> Result<(), JsValue> {
+    let movie = Movie::from_fm2(fm2).map_err(FlemuError::from)?;
+    self.attract_input();
+    let rom = self
+      .rom
+      .as_ref()
+      .ok_or_else(|| invalid("no cartridge inserted".to_string()))?;
+    self
+      .attract
+      .set_demo(rom, movie)
+      .map_err(FlemuError::from)?;
+    Ok(())
+  }
+
+  /// Forget the inserted cartridge's demo, stopping it if it's playing.
+  pub fn clear_demo_movie(&mut self) {
+    self.attract_input();
+    if let Some(rom) = &self.rom {
+      let checksum = savestate::cartridge_checksum(&rom.prg_rom, &rom.chr_rom);
+      self.attract.remove_demo(checksum);
+    }
+  }
+
+  pub fn has_demo_movie(&self) -> bool {
+    self.rom.as_ref().map_or(false, |rom| {
+      let checksum = savestate::cartridge_checksum(&rom.prg_rom, &rom.chr_rom);
+      self.attract.demo(checksum).is_some()
+    })
+  }
+
+  /// How long the controls sit untouched before the demo starts, at the
+  /// console's frame rate. 30 by default.
+  pub fn set_attract_idle(&mut self, seconds: f64) {
+    let frames = seconds * self.cpu.bus.timing().frame_rate();
+    self.attract.set_idle_frames(frames.round() as u32);
+  }
+
+  pub fn attract_playing(&self) -> bool {
+    self.attract.playing()
+  }
+
+  /// Stop the demo as a button press would, back to where the game was.
+  pub fn stop_attract(&mut self) {
+    self.attract_input();
+  }
+}
+
+impl Emulator {
+  // someone's at the controls: the idle time starts over and a demo
+  // playing gives the console back
+  pub(crate) fn attract_input(&mut self) {
+    match self.attract.input(&mut self.cpu) {
+      Ok(true) => self.time_travel.clear(),
+      Ok(false) => {}
+      Err(e) => warn!("couldn't leave the demo: {}", e),
+    }
+  }
+
+  // attract mode's turn as a frame starts; TAS work, movies and NSFs
+  // keep it from starting, as input would
+  pub(crate) fn attract_frame(&mut self) {
+    let movie = self
+      .movie
+      .as_ref()
+      .map_or(false, |session| session.mode != MovieMode::Off);
+    if self.tas || movie || self.nsf.is_some() {
+      self.attract_input();
+    } else if let Err(e) = self.attract.frame(&mut self.cpu) {
+      warn!("couldn't play the demo: {}", e);
+      self.attract_input();
+    }
+  }
+}
@@ -0,0 +1,192 @@
+use crate::nes::apu::MixerInput;
+use crate::wav::WavRecorder;
+use crate::{js_object, Emulator};
+use js_sys::Array;
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+impl Emulator {
+  /// Match the APU's output to the AudioContext's sample rate.
+  pub fn set_sample_rate(&mut self, rate: u32) {
+    self.cpu.bus.apu.set_sample_rate(rate);
+  }
+
+  /// How many milliseconds of sound to keep queued, from 20 to 500 (100 by
+  /// default): lower answers faster, higher crackles less on slow frames.
+  /// Sizes the audio buffer, dropping what's in it, and returns the
+  /// latency actually set.
+  pub fn set_audio_latency(&mut self, ms: u32) -> u32 {
+    self.cpu.bus.apu.set_latency(ms);
+    self.cpu.bus.apu.latency()
+  }
+
+  pub fn audio_latency(&self) -> u32 {
+    self.cpu.bus.apu.latency()
+  }
+
+  /// Samples per audio callback that fit the latency at the sample rate: a
+  /// power of two from 256 to 16384, at most half the latency.
+  pub fn audio_buffer_size(&self) -> u32 {
+    let apu = &self.cpu.bus.apu;
+    let half = apu.sample_rate() as u64 * apu.latency() as u64 / 2000;
+    let mut size = 256;
+    while size < 16384 && size * 2 <= half {
+      size *= 2;
+    }
+    size as u32
+  }
+
+  /// What `set_audio_fill` is a fraction of, in samples: the size of the
+  /// APU's own buffer. A queue elsewhere should hold at least as many.
+  pub fn audio_queue_capacity(&self) -> u32 {
+    self.cpu.bus.apu.sample_ring().capacity() as u32
+  }
+
+  /// Mono samples in [0, 1) generated since the last call.
+  pub fn audio_samples(&mut self) -> Vec<f32> {
+    self.cpu.bus.apu.take_samples()
+  }
+
+  /// For a worker queueing `audio_samples` for the page to play: how full
+  /// that queue is, from 0 to 1 of `audio_queue_capacity`, so the sound
+  /// keeps pace with the audio clock and the latency like `fill_audio`
+  /// does on its own.
+  pub fn set_audio_fill(&mut self, fill: f64) {
+    self.cpu.bus.apu.match_fill(fill);
+  }
+
+  /// Fill the audio node's output buffer from the APU's ring buffer,
+  /// returning how many samples were real rather than padding.
+  pub fn fill_audio(&mut self, out: &mut [f32]) -> usize {
+    self.cpu.bus.apu.fill_audio(out)
+  }
+
+  /// `[underruns, overruns]` of the audio buffer since the sample rate was
+  /// set.
+  pub fn audio_glitches(&self) -> Vec<u32> {
+    let ring = self.cpu.bus.apu.sample_ring();
+    vec![ring.underruns(), ring.overruns()]
+  }
+
+  /// How loud one of the `mixer_inputs` is, 0 to 1. Returns false for an
+  /// unknown id.
+  pub fn set_channel_gain(&mut self, id: &str, gain: f32) -> bool {
+    match MixerInput::from_id(id) {
+      Some(input) => {
+        self.cpu.bus.apu.mixer.set_gain(input, gain);
+        true
+      }
+      None => false,
+    }
+  }
+
+  /// Silence one of the `mixer_inputs` or not. Returns false for an
+  /// unknown id.
+  pub fn set_channel_muted(&mut self, id: &str, muted: bool) -> bool {
+    match MixerInput::from_id(id) {
+      Some(input) => {
+        self.cpu.bus.apu.mixer.set_muted(input, muted);
+        true
+      }
+      None => false,
+    }
+  }
+
+  /// Solo one of the `mixer_inputs` or not: with any soloed, only they
+  /// are heard. Returns false for an unknown id.
+  pub fn set_channel_solo(&mut self, id: &str, solo: bool) -> bool {
+    match MixerInput::from_id(id) {
+      Some(input) => {
+        self.cpu.bus.apu.mixer.set_solo(input, solo);
+        true
+      }
+      None => false,
+    }
+  }
+
+  /// The mixer's settings: `[{ id, name, gain, muted, solo }]`.
+  pub fn channel_mix(&self) -> JsValue {
+    let mixer = &self.cpu.bus.apu.mixer;
+    MixerInput::ALL
+      .iter()
+      .map(|&input| {
+        js_object(&[
+          ("id", input.id().into()),
+          ("name", input.name().into()),
+          ("gain", mixer.gain(input).into()),
+          ("muted", mixer.muted(input).into()),
+          ("solo", mixer.solo(input).into()),
+        ])
+      })
+      .collect::<Array>()
+      .into()
+  }
+
+  /// Volume of what's played, 0 to 1, clamped. Audio captures keep the
+  /// full level.
+  pub fn set_master_volume(&mut self, volume: f32) {
+    self.cpu.bus.apu.mixer.volume = volume.max(0.0).min(1.0);
+  }
+
+  pub fn master_volume(&self) -> f32 {
+    self.cpu.bus.apu.mixer.volume
+  }
+
+  /// Start recording the sound, at `rate` Hz or the output's sample rate.
+  /// The recording is steady where the output isn't: never sped up or
+  /// slowed down to keep up with the AudioContext, nor turned down while
+  /// fast-forwarding, so the same run records the same samples.
+  pub fn start_audio_capture(&mut self, rate: Option<u32>) {
+    let rate = rate
+      .unwrap_or_else(|| self.cpu.bus.apu.sample_rate())
+      .max(1);
+    self.cpu.bus.apu.set_capture_rate(Some(rate));
+    self.audio_capture = Some(WavRecorder::new(rate));
+  }
+
+  /// Stop recording and return the sound since `start_audio_capture` as
+  /// WAV bytes, undefined if it wasn't recording.
+  pub fn stop_audio_capture(&mut self) -> Option<Vec<u8>> {
+    self.cpu.bus.apu.set_capture_rate(None);
+    self.audio_capture.take().map(|recorder| recorder.to_wav())
+  }
+
+  /// Seconds recorded so far, undefined if not recording.
+  pub fn audio_capture_seconds(&self) -> Option<f64> {
+    self.audio_capture.as_ref().map(WavRecorder::duration)
+  }
+
+  /// What the APU channels played in each frame since the last call, the
+  /// last 8 frames at most: `[{ frame, channels: [{ channel, period,
+  /// frequency, volume, playing }] }]`, `channel` one of "pulse1",
+  /// "pulse2", "triangle", "noise", "dmc". For piano rolls and the like.
+  pub fn take_channel_states(&mut self) -> JsValue {
+    self
+      .cpu
+      .bus
+      .channels
+      .take()
+      .iter()
+      .map(|frame| {
+        let channels = frame
+          .channels
+          .iter()
+          .map(|state| {
+            js_object(&[
+              ("channel", state.channel.id().into()),
+              ("period", state.period.into()),
+              ("frequency", state.frequency.into()),
+              ("volume", state.volume.into()),
+              ("playing", state.playing.into()),
+            ])
+          })
+          .collect::<Array>();
+        js_object(&[
+          ("frame", (frame.frame as f64).into()),
+          ("channels", channels.into()),
+        ])
+      })
+      .collect::<Array>()
+      .into()
+  }
+}
@@ -0,0 +1,193 @@
+use crate::error::FlemuError;
+use crate::nes::cartridge::RomInfo;
+use crate::nes::game_db::GameDb;
+use crate::nes::patch;
+use crate::{idb, invalid, js_object, Cartridge, Emulator, BATTERY_SLOT};
+use js_sys::{Array, Promise, Uint8Array};
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+impl Emulator {
+  /// Parse an iNES file and insert it, resetting the CPU to its reset
+  /// vector. An NSF file starts playing its first track instead, see
+  /// `nsf_info`. A game in the database runs with its header corrected
+  /// and gets the Zapper or Four Score it's played with plugged in, see
+  /// `game_info`. A file that doesn't parse, or is for a board that isn't
+  /// emulated, leaves the running game as it was.
+  pub fn load_rom(&mut self, bytes: &[u8]) -> Result<(), JsValue> {
+    let cartridge = Cartridge::parse(bytes, &self.game_db)?;
+    Ok(self.insert(cartridge)?)
+  }
+
+  /// `load_rom` for a Uint8Array, e.g. a file dropped on the page, that
+  /// returns what was loaded: `{ title, mapper, submapper, prg_rom_size,
+  /// chr_rom_size, battery, nsf }`. `title` is the game database's or the
+  /// NSF's, null when neither knows it; sizes are in bytes, CHR 0 for
+  /// boards with CHR RAM.
+  pub fn load_rom_bytes(&mut self, bytes: Uint8Array) -> Result<JsValue, JsValue> {
+    let cartridge = Cartridge::parse(&bytes.to_vec(), &self.game_db)?;
+    let summary = cartridge.summary();
+    self.insert(cartridge)?;
+    Ok(summary)
+  }
+
+  /// What the game database knows about the inserted cartridge: `{
+  /// title, crc32, fixed, peripheral }`, `crc32` as 8 hex digits of its
+  /// PRG and CHR ROM, `fixed` the header fields it corrected ("mapper",
+  /// "mirroring", ...) and `peripheral` "zapper", "four-score" or null.
+  /// Null for a cartridge it doesn't know.
+  pub fn game_info(&self) -> JsValue {
+    let (game, fixed) = match &self.game {
+      Some(game) => game,
+      None => return JsValue::NULL,
+    };
+    js_object(&[
+      ("title", game.title.as_str().into()),
+      ("crc32", format!("{:08x}", game.crc32).into()),
+      (
+        "fixed",
+        fixed
+          .iter()
+          .map(|&field| JsValue::from(field))
+          .collect::<Array>()
+          .into(),
+      ),
+      (
+        "peripheral",
+        game.peripheral.map_or(JsValue::NULL, |p| p.id().into()),
+      ),
+    ])
+  }
+
+  /// Add the games in `text`, in the format of the built-in database
+  /// (see `nes::game_db`), replacing entries for the same ROM. Returns how
+  /// many there were; they count from the next `load_rom`. Throws with
+  /// code "game-db" and the `line` that didn't parse.
+  pub fn load_game_db(&mut self, text: &str) -> Result<u32, JsValue> {
+    let db = GameDb::parse(text).map_err(FlemuError::from)?;
+    let count = db.len() as u32;
+    self.game_db.extend(db);
+    Ok(count)
+  }
+
+  /// With an NSF loaded, `{ title, artist, copyright, tracks, track }`,
+  /// tracks counted from 0; null otherwise.
+  pub fn nsf_info(&self) -> JsValue {
+    let player = match &self.nsf {
+      Some(player) => player,
+      None => return JsValue::NULL,
+    };
+    let nsf = player.nsf();
+    js_object(&[
+      ("title", nsf.title.as_str().into()),
+      ("artist", nsf.artist.as_str().into()),
+      ("copyright", nsf.copyright.as_str().into()),
+      ("tracks", nsf.songs.into()),
+      ("track", player.song().into()),
+    ])
+  }
+
+  /// Play NSF track `track` (from 0) from the start.
+  pub fn select_track(&mut self, track: u32) -> Result<(), JsValue> {
+    let player = self
+      .nsf
+      .as_mut()
+      .ok_or_else(|| invalid("no NSF loaded".to_string()))?;
+    if track >= player.nsf().songs as u32 {
+      return Err(invalid(format!(
+        "track {} of {}",
+        track,
+        player.nsf().songs
+      )));
+    }
+    player.start(&mut self.cpu, track as u8);
+    self.time_travel.clear();
+    Ok(())
+  }
+
+  /// The next NSF track, wrapping around after the last one. Returns the
+  /// track now playing.
+  pub fn next_track(&mut self) -> Result<u32, JsValue> {
+    self.step_track(1)
+  }
+
+  /// The previous NSF track, wrapping around before the first one.
+  pub fn prev_track(&mut self) -> Result<u32, JsValue> {
+    self.step_track(-1)
+  }
+
+  /// Apply an IPS or BPS patch (translations, ROM hacks) to an iNES file in
+  /// memory, then load the result.
+  pub fn load_patched_rom(&mut self, bytes: &[u8], patch: &[u8]) -> Result<(), JsValue> {
+    let patched = patch::apply(bytes, patch).map_err(FlemuError::from)?;
+    self.load_rom(&patched)
+  }
+
+  /// Copy of the cartridge's battery-backed RAM, for the frontend to
+  /// persist. None without a battery.
+  pub fn battery_ram(&self) -> Option<Vec<u8>> {
+    self.cpu.bus.battery_ram().map(|ram| ram.to_vec())
+  }
+
+  /// What the inserted ROM's battery saves are stored under: 8 hex digits
+  /// of a checksum of its PRG and CHR ROM. None without a cartridge.
+  pub fn rom_hash(&self) -> Option<String> {
+    self
+      .cpu
+      .bus
+      .mapper()
+      .map(|_| format!("{:08x}", self.cpu.bus.cartridge_checksum()))
+  }
+
+  /// Write battery RAM to IndexedDB under `rom_hash` if the game changed
+  /// it since the last call. Resolves to whether anything was written;
+  /// rejects with a "storage" error, in which case the next call writes
+  /// again.
+  pub fn persist_battery_ram(&mut self) -> Promise {
+    let joined = self
+      .netplay
+      .as_ref()
+      .map_or(false, |session| session.netplay.player() == 1);
+    // a demo's saves aren't the player's; they're kept for after it
+    let changed = !joined
+      && !self.attract.playing()
+      && self.cpu.bus.take_battery_dirty() | self.battery_retry.replace(false);
+    let save = match (self.rom_hash(), self.cpu.bus.battery_ram()) {
+      (Some(hash), Some(ram)) if changed => Some((hash, ram.to_vec())),
+      _ => None,
+    };
+    let retry = self.battery_retry.clone();
+    wasm_bindgen_futures::future_to_promise(async move {
+      let (hash, ram) = match save {
+        Some(save) => save,
+        None => return Ok(false.into()),
+      };
+      match idb::put(&hash, BATTERY_SLOT, &ram).await {
+        Ok(()) => Ok(true.into()),
+        Err(error) => {
+          retry.set(true);
+          Err(FlemuError::from(error).into())
+        }
+      }
+    })
+  }
+
+  /// Restore a save made by `battery_ram` into the cartridge. A save of
+  /// another size only fills what both have in common. False when the
+  /// cartridge has no battery.
+  pub fn load_battery_ram(&mut self, save: &[u8]) -> bool {
+    match self.cpu.bus.battery_ram_mut() {
+      Some(ram) => {
+        let len = ram.len().min(save.len());
+        ram[..len].copy_from_slice(&save[..len]);
+        true
+      }
+      None => false,
+    }
+  }
+
+  /// Header details of the inserted cartridge, if any.
+  pub fn rom_info(&self) -> Option<RomInfo> {
+    self.cpu.bus.rom_info().copied()
+  }
+}
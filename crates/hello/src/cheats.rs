@@ -0,0 +1,79 @@
+use crate::error::FlemuError;
+use crate::{js_object, Emulator};
+use js_sys::Array;
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+impl Emulator {
+  /// Lock a RAM address to `value`, enforced every frame, e.g. one found
+  /// with a RAM search.
+  pub fn freeze_memory(&mut self, addr: u16, value: u8) -> Result<(), JsValue> {
+    self
+      .cpu
+      .bus
+      .cheats
+      .freeze(addr, value)
+      .map_err(FlemuError::from)?;
+    self.cpu.bus.apply_cheats();
+    Ok(())
+  }
+
+  pub fn unfreeze_memory(&mut self, addr: u16) {
+    self.cpu.bus.cheats.unfreeze(addr);
+  }
+
+  /// Frozen addresses: `[{ addr, value }]`.
+  pub fn frozen_memory(&self) -> JsValue {
+    self
+      .cpu
+      .bus
+      .cheats
+      .freezes()
+      .iter()
+      .map(|freeze| js_object(&[("addr", freeze.addr.into()), ("value", freeze.value.into())]))
+      .collect::<Array>()
+      .into()
+  }
+
+  /// Enable a 6 or 8 letter Game Genie code, e.g. "SXIOPO". It replaces
+  /// any code for the same address.
+  pub fn add_game_genie(&mut self, code: &str) -> Result<(), JsValue> {
+    self
+      .cpu
+      .bus
+      .cheats
+      .add_game_genie(code)
+      .map_err(FlemuError::from)?;
+    Ok(())
+  }
+
+  pub fn remove_game_genie(&mut self, code: &str) {
+    self.cpu.bus.cheats.remove_game_genie(code);
+  }
+
+  /// Game Genie codes enabled: `[{ code, addr, value, compare }]`,
+  /// `compare` null for 6 letter codes.
+  pub fn game_genie_codes(&self) -> JsValue {
+    self
+      .cpu
+      .bus
+      .cheats
+      .game_genie_codes()
+      .iter()
+      .map(|code| {
+        js_object(&[
+          ("code", code.code.as_str().into()),
+          ("addr", code.addr.into()),
+          ("value", code.value.into()),
+          ("compare", code.compare.map_or(JsValue::NULL, JsValue::from)),
+        ])
+      })
+      .collect::<Array>()
+      .into()
+  }
+
+  /// Turn off every cheat, freezes and Game Genie codes.
+  pub fn clear_cheats(&mut self) {
+    self.cpu.bus.cheats.clear();
+  }
+}
@@ -0,0 +1,521 @@
+use crate::error::FlemuError;
+use crate::nes::cpu::CpuState;
+use crate::nes::debugger::{
+  self, Comparison, Condition, Location, Register, StopReason, Watchpoint,
+};
+use crate::nes::diagnostics::CoreDump;
+use crate::nes::memory_map::{self, AddressSpace, Region};
+use crate::nes::ppu_viewer::{self, Image};
+use crate::nes::scanline_events::LineEvents;
+use crate::{invalid, js_object, Emulator};
+use js_sys::{Array, Reflect, Uint8Array};
+use wasm_bindgen::prelude::*;
+
+// how long step over waits for a subroutine to return, about 5 seconds
+const STEP_OVER_LIMIT: u32 = 3_000_000;
+
+fn js_regions(regions: Vec<Region>) -> JsValue {
+  regions
+    .iter()
+    .map(|region| {
+      js_object(&[
+        ("start", region.start.into()),
+        ("end", region.end.into()),
+        ("description", region.description.as_str().into()),
+      ])
+    })
+    .collect::<Array>()
+    .into()
+}
+
+// bank and offset as the debugger API passes them: no bank for addresses
+// below $8000
+fn location(bank: Option<u32>, offset: u16) -> Location {
+  match bank {
+    Some(bank) => Location::Prg {
+      bank: bank as usize,
+      offset,
+    },
+    None => Location::Cpu(offset),
+  }
+}
+
+fn js_location(location: Location) -> JsValue {
+  let (bank, offset) = match location {
+    Location::Cpu(addr) => (JsValue::NULL, addr),
+    Location::Prg { bank, offset } => ((bank as u32).into(), offset),
+  };
+  js_object(&[("bank", bank), ("offset", offset.into())])
+}
+
+fn address_space(id: Option<String>) -> Result<AddressSpace, JsValue> {
+  match id {
+    Some(id) => {
+      AddressSpace::from_id(&id).ok_or_else(|| invalid(format!("unknown address space {}", id)))
+    }
+    None => Ok(AddressSpace::Cpu),
+  }
+}
+
+fn js_image(image: Image) -> JsValue {
+  js_object(&[
+    ("width", (image.width as u32).into()),
+    ("height", (image.height as u32).into()),
+    ("data", Uint8Array::from(&image.rgba[..]).into()),
+  ])
+}
+
+#[wasm_bindgen]
+impl Emulator {
+  /// Run until the PPU moves to the next scanline, false if the CPU
+  /// stopped.
+  pub fn step_scanline(&mut self) -> bool {
+    self.cpu.step_scanline()
+  }
+
+  /// Execute one instruction, false if the CPU stopped. Unlike
+  /// `debug_step` nothing is kept for stepping back.
+  pub fn step_instruction(&mut self) -> bool {
+    self.cpu.step_instruction()
+  }
+
+  /// Why emulation stopped, if it hit an unrecoverable error: an Error
+  /// with code "cpu-fault" as thrown by the other methods, else null.
+  pub fn fault(&self) -> JsValue {
+    match self.cpu.fault() {
+      Some(fault) => FlemuError::from(fault).into(),
+      None => JsValue::NULL,
+    }
+  }
+
+  /// Text bundle with the fault, registers, recent trace and RAM, for the
+  /// frontend to offer as a download in bug reports.
+  pub fn core_dump(&self, frame: u32) -> String {
+    CoreDump::capture(&self.cpu, frame as u64).to_text()
+  }
+
+  /// What each CPU address range is right now, banks included:
+  /// `[{ start, end, description }]`.
+  pub fn cpu_memory_map(&self) -> JsValue {
+    js_regions(memory_map::cpu_map(&self.cpu.bus))
+  }
+
+  /// Same as `cpu_memory_map` for the PPU's $0000-$3FFF.
+  pub fn ppu_memory_map(&self) -> JsValue {
+    js_regions(memory_map::ppu_map(&self.cpu.bus))
+  }
+
+  /// Pattern table `table` (0 or 1) in the colors of palette `palette`,
+  /// 0-3 background and 4-7 sprites: `{ width, height, data }`, `data` a
+  /// Uint8Array of 128x128 RGBA pixels. Like the other `ppu_` pictures
+  /// it's the PPU as it is now, with the CHR banks switched in.
+  pub fn ppu_pattern_table(&self, table: u8, palette: u8) -> JsValue {
+    js_image(ppu_viewer::pattern_table(
+      &self.cpu.bus,
+      table,
+      palette,
+      &self.palette,
+    ))
+  }
+
+  /// The four nametables, 512x480, with the screen the scroll shows
+  /// outlined in white when `scroll`.
+  pub fn ppu_nametables(&self, scroll: bool) -> JsValue {
+    js_image(ppu_viewer::nametables(&self.cpu.bus, &self.palette, scroll))
+  }
+
+  /// Palette RAM, 16x2: one pixel per entry, the sprite palettes on the
+  /// second row.
+  pub fn ppu_palette(&self) -> JsValue {
+    js_image(ppu_viewer::palette(&self.cpu.bus, &self.palette))
+  }
+
+  /// The 64 sprites in OAM: `[{ index, x, y, tile, palette,
+  /// behind_background, flip_horizontal, flip_vertical }]`, `y` the top
+  /// row and `palette` 4-7.
+  pub fn ppu_sprites(&self) -> JsValue {
+    ppu_viewer::sprites(&self.cpu.bus)
+      .into_iter()
+      .map(|sprite| {
+        js_object(&[
+          ("index", sprite.index.into()),
+          ("x", sprite.x.into()),
+          ("y", sprite.y.into()),
+          ("tile", sprite.tile.into()),
+          ("palette", sprite.palette.into()),
+          ("behind_background", sprite.behind_background.into()),
+          ("flip_horizontal", sprite.flip_horizontal.into()),
+          ("flip_vertical", sprite.flip_vertical.into()),
+        ])
+      })
+      .collect::<Array>()
+      .into()
+  }
+
+  /// Every sprite drawn as the PPU would, transparent where it is, eight
+  /// to a row in 8x16 cells: 64x128.
+  pub fn ppu_sprite_sheet(&self) -> JsValue {
+    js_image(ppu_viewer::sprite_sheet(&self.cpu.bus, &self.palette))
+  }
+
+  /// Log the NMIs, IRQs and DMAs of every scanline from the next frame
+  /// on, for `scanline_events` and `debug_frame_buffer`, or stop.
+  pub fn set_scanline_events(&mut self, enabled: bool) {
+    self.cpu.bus.set_scanline_events(enabled);
+  }
+
+  /// The last frame's lines with something on them: `[{ scanline, nmi,
+  /// irq, oam_dma, dmc_dma }]`, empty while the log is off.
+  pub fn scanline_events(&self) -> JsValue {
+    let log = match self.cpu.bus.scanline_events() {
+      Some(log) => log,
+      None => return Array::new().into(),
+    };
+    log
+      .last_frame()
+      .iter()
+      .enumerate()
+      .filter(|(_, events)| !events.is_empty())
+      .map(|(scanline, events)| {
+        js_object(&[
+          ("scanline", (scanline as u32).into()),
+          ("nmi", events.contains(LineEvents::NMI).into()),
+          ("irq", events.contains(LineEvents::IRQ).into()),
+          ("oam_dma", events.contains(LineEvents::OAM_DMA).into()),
+          ("dmc_dma", events.contains(LineEvents::DMC_DMA).into()),
+        ])
+      })
+      .collect::<Array>()
+      .into()
+  }
+
+  /// The last frame with every scanline, 256x262 on NTSC and 256x312 on
+  /// PAL, the ones under the picture grey; with `set_scanline_events` on,
+  /// lines are tinted red for an NMI, green an IRQ, blue an OAM DMA and
+  /// yellow a DMC fetch.
+  pub fn debug_frame_buffer(&self) -> JsValue {
+    js_image(ppu_viewer::event_frame(&self.cpu.bus, &self.palette))
+  }
+
+  /// Label for a CPU address, for trace logs and hex viewers.
+  pub fn describe_address(&self, addr: u16) -> String {
+    memory_map::describe_cpu_address(&self.cpu.bus, addr)
+  }
+
+  /// `len` bytes from `addr` in `space`, "cpu" (the default) or "ppu",
+  /// for hex viewers and RAM watches. Reading has no side effects on
+  /// registers; past the end of the space it wraps around.
+  pub fn read_range(&self, addr: u16, len: u32, space: Option<String>) -> Result<Vec<u8>, JsValue> {
+    let space = address_space(space)?;
+    Ok(memory_map::read_range(
+      &self.cpu.bus,
+      space,
+      addr,
+      len as usize,
+    ))
+  }
+
+  /// Write a byte at `addr` in `space`, like `read_range`. A CPU write is
+  /// what the CPU writing it would do, so registers react and mappers
+  /// switch banks; PPU writes only change RAM.
+  pub fn write_byte(&mut self, addr: u16, value: u8, space: Option<String>) -> Result<(), JsValue> {
+    let space = address_space(space)?;
+    memory_map::write_byte(&mut self.cpu.bus, space, addr, value);
+    Ok(())
+  }
+
+  /// Suspicious things the game did since the last call, rate limited per
+  /// kind: `[{ frame, kind, message }]`, `kind` one of "rom-write",
+  /// "open-bus-read", "unofficial-opcode", "oam-dma-during-rendering".
+  pub fn take_warnings(&mut self) -> JsValue {
+    self
+      .cpu
+      .bus
+      .warnings
+      .take()
+      .iter()
+      .map(|report| {
+        js_object(&[
+          ("frame", (report.frame as f64).into()),
+          ("kind", report.warning.kind().into()),
+          ("message", report.warning.to_string().into()),
+        ])
+      })
+      .collect::<Array>()
+      .into()
+  }
+
+  /// How many warnings the rate limit dropped.
+  pub fn suppressed_warnings(&self) -> f64 {
+    self.cpu.bus.warnings.suppressed() as f64
+  }
+
+  /// Debugger: execute one instruction, keeping what's needed to step
+  /// back over it. Throws with code "cpu-fault" if it halts the CPU.
+  pub fn debug_step(&mut self) -> Result<CpuState, JsValue> {
+    self
+      .time_travel
+      .step(&mut self.cpu)
+      .map_err(FlemuError::from)?;
+    Ok(self.cpu.state())
+  }
+
+  /// Debugger: undo the last instruction, within the last ~32K executed
+  /// through `debug_step`.
+  pub fn debug_step_back(&mut self) -> Result<CpuState, JsValue> {
+    let layers = self.cpu.bus.ppu.layers;
+    let mixer = self.cpu.bus.apu.mixer;
+    let watchpoints = std::mem::take(&mut self.cpu.bus.watchpoints);
+    let stepped = self.time_travel.step_back(&mut self.cpu);
+    self.cpu.bus.ppu.layers = layers;
+    self.cpu.bus.apu.mixer = mixer;
+    self.cpu.bus.watchpoints = watchpoints;
+    stepped.map_err(FlemuError::from)?;
+    Ok(self.cpu.state())
+  }
+
+  /// Debugger: step until the CPU reaches a breakpoint, at most
+  /// `max_instructions`. Returns whether a breakpoint or watchpoint
+  /// stopped it.
+  pub fn debug_run(&mut self, max_instructions: u32) -> bool {
+    let time_travel = &mut self.time_travel;
+    let stop = debugger::run_until(
+      &mut self.cpu,
+      &self.breakpoints,
+      max_instructions,
+      |cpu| matches!(time_travel.step(cpu), Ok(Some(_))),
+      |_| false,
+    );
+    matches!(
+      stop,
+      StopReason::Breakpoint { .. } | StopReason::Watchpoint(_)
+    )
+  }
+
+  /// Debugger: execute one instruction, or service a pending interrupt,
+  /// keeping it for stepping back. Returns why it stopped, like
+  /// `run_frame`: "done", or "breakpoint" when that lands on one.
+  pub fn step_into(&mut self) -> Result<JsValue, JsValue> {
+    let time_travel = &mut self.time_travel;
+    let stop = debugger::run_until(
+      &mut self.cpu,
+      &self.breakpoints,
+      1,
+      |cpu| matches!(time_travel.step(cpu), Ok(Some(_))),
+      |_| true,
+    );
+    self.stopped(stop)
+  }
+
+  /// Debugger: like `step_into`, but run a subroutine called with JSR to
+  /// its end. Gives up with "limit" if it doesn't return within a few
+  /// seconds of emulated time.
+  pub fn step_over(&mut self) -> Result<JsValue, JsValue> {
+    let time_travel = &mut self.time_travel;
+    let stop = debugger::step_over(&mut self.cpu, &self.breakpoints, STEP_OVER_LIMIT, |cpu| {
+      matches!(time_travel.step(cpu), Ok(Some(_)))
+    });
+    self.stopped(stop)
+  }
+
+  /// Debugger: run until the CPU is about to execute CPU address `addr`,
+  /// at most `max_instructions`. Breakpoints and watchpoints on the way
+  /// still stop it.
+  pub fn run_to(&mut self, addr: u16, max_instructions: u32) -> Result<JsValue, JsValue> {
+    let time_travel = &mut self.time_travel;
+    let stop = debugger::run_until(
+      &mut self.cpu,
+      &self.breakpoints,
+      max_instructions,
+      |cpu| matches!(time_travel.step(cpu), Ok(Some(_))),
+      |cpu| cpu.program_counter == addr,
+    );
+    self.stopped(stop)
+  }
+
+  /// What CPU address `addr` maps to now: `{ bank, offset }`, `bank` null
+  /// below $8000. The form breakpoints and symbols take.
+  pub fn location_of(&self, addr: u16) -> JsValue {
+    js_location(Location::resolve(&self.cpu.bus, addr))
+  }
+
+  /// Break when the CPU executes `offset` of PRG bank `bank`, whatever
+  /// address it's mapped at; with no bank, at CPU address `offset`.
+  pub fn add_breakpoint(&mut self, bank: Option<u32>, offset: u16) {
+    self.breakpoints.add(location(bank, offset));
+  }
+
+  /// Break at a location, addressed like `add_breakpoint`, only when a
+  /// register compares to `value`: `register` one of "a", "x", "y", "sp",
+  /// "p", `comparison` one of "==", "!=", "<", "<=", ">", ">=".
+  pub fn add_conditional_breakpoint(
+    &mut self,
+    bank: Option<u32>,
+    offset: u16,
+    register: &str,
+    comparison: &str,
+    value: u8,
+  ) -> Result<(), JsValue> {
+    let condition = Condition {
+      register: Register::from_id(register)
+        .ok_or_else(|| invalid(format!("unknown register {}", register)))?,
+      comparison: Comparison::from_id(comparison)
+        .ok_or_else(|| invalid(format!("unknown comparison {}", comparison)))?,
+      value,
+    };
+    self
+      .breakpoints
+      .add_conditional(location(bank, offset), condition);
+    Ok(())
+  }
+
+  /// Every breakpoint at the location, conditional or not.
+  pub fn remove_breakpoint(&mut self, bank: Option<u32>, offset: u16) {
+    self.breakpoints.remove(location(bank, offset));
+  }
+
+  /// `[{ bank, offset, condition }]` in the order they were added,
+  /// `condition` null or `{ register, comparison, value }`.
+  pub fn breakpoints(&self) -> JsValue {
+    self
+      .breakpoints
+      .breakpoints()
+      .iter()
+      .map(|breakpoint| {
+        let location = js_location(breakpoint.location);
+        let condition = match breakpoint.condition {
+          Some(condition) => js_object(&[
+            ("register", condition.register.id().into()),
+            ("comparison", condition.comparison.id().into()),
+            ("value", condition.value.into()),
+          ]),
+          None => JsValue::NULL,
+        };
+        // only fails on frozen objects or proxies
+        Reflect::set(&location, &JsValue::from_str("condition"), &condition).unwrap();
+        location
+      })
+      .collect::<Array>()
+      .into()
+  }
+
+  /// Stop when the CPU reads (`read`) or writes (`write`) an address in
+  /// `start..=end`, after the instruction that did it.
+  pub fn add_watchpoint(&mut self, start: u16, end: u16, read: bool, write: bool) {
+    self.cpu.bus.watchpoints.add(Watchpoint {
+      start,
+      end,
+      read,
+      write,
+    });
+  }
+
+  pub fn remove_watchpoint(&mut self, start: u16, end: u16) {
+    self.cpu.bus.watchpoints.remove(start, end);
+  }
+
+  /// `[{ start, end, read, write }]`, in the order they were added.
+  pub fn watchpoints(&self) -> JsValue {
+    self
+      .cpu
+      .bus
+      .watchpoints
+      .watchpoints()
+      .iter()
+      .map(|watchpoint| {
+        js_object(&[
+          ("start", watchpoint.start.into()),
+          ("end", watchpoint.end.into()),
+          ("read", watchpoint.read.into()),
+          ("write", watchpoint.write.into()),
+        ])
+      })
+      .collect::<Array>()
+      .into()
+  }
+
+  /// Name a location, addressed like `add_breakpoint`.
+  pub fn add_symbol(&mut self, bank: Option<u32>, offset: u16, name: &str) {
+    self.symbols.add(location(bank, offset), name);
+  }
+
+  pub fn remove_symbol(&mut self, bank: Option<u32>, offset: u16) {
+    self.symbols.remove(location(bank, offset));
+  }
+
+  /// The symbol for what CPU address `addr` maps to now.
+  pub fn symbol_at(&self, addr: u16) -> Option<String> {
+    self.symbols.lookup(&self.cpu.bus, addr).map(str::to_string)
+  }
+
+  /// Debugger: the last OAM DMA as `{ page, copied, stall_cycles,
+  /// stall_remaining, frame, scanline }`, null before the first. `page` is
+  /// the $XX of $XX00-$XXFF and the stall is in CPU cycles.
+  pub fn oam_dma(&self) -> JsValue {
+    match self.cpu.bus.last_oam_dma() {
+      Some(dma) => js_object(&[
+        ("page", dma.page.into()),
+        ("copied", dma.copied.into()),
+        ("stall_cycles", dma.stall_cycles.into()),
+        ("stall_remaining", dma.stall_remaining.into()),
+        ("frame", (dma.frame as f64).into()),
+        ("scanline", dma.scanline.into()),
+      ]),
+      None => JsValue::NULL,
+    }
+  }
+
+  /// Debugger: draw the background layer or not. Only the picture
+  /// changes, sprite 0 hits still happen.
+  pub fn set_background_visible(&mut self, visible: bool) {
+    self.cpu.bus.ppu.layers.background = visible;
+  }
+
+  /// Debugger: draw sprites or not.
+  pub fn set_sprites_visible(&mut self, visible: bool) {
+    self.cpu.bus.ppu.layers.sprites = visible;
+  }
+
+  /// Draw at most 8 sprites a line like the console (the default), or all
+  /// of them, which stops the flicker of games that cycle their sprites.
+  /// Games still see the overflow flag as on the console.
+  pub fn set_sprite_limit(&mut self, enabled: bool) {
+    self.cpu.bus.ppu.layers.sprite_limit = enabled;
+  }
+
+  /// Debugger: draw OAM entry `index` (0-63) or not.
+  pub fn set_sprite_visible(&mut self, index: u8, visible: bool) {
+    self
+      .cpu
+      .bus
+      .ppu
+      .layers
+      .set_sprite_visible(index as usize, visible);
+  }
+
+  /// Instructions executed through `debug_step` since the ROM was loaded,
+  /// less those stepped back over.
+  pub fn debug_position(&self) -> f64 {
+    self.time_travel.position() as f64
+  }
+
+  /// The last instructions executed, one nestest-like line each, oldest
+  /// first.
+  pub fn trace_log(&self) -> String {
+    let lines: Vec<String> = self.cpu.trace().iter().map(|entry| entry.line()).collect();
+    lines.join("\n")
+  }
+
+  /// Collect every instruction executed from now on as a line of the
+  /// canonical nestest.log, for comparing against reference logs. Off by
+  /// default, it formats a string per instruction.
+  pub fn set_nestest_log(&mut self, enabled: bool) {
+    self.cpu.set_nestest_log(enabled);
+  }
+
+  /// The nestest.log lines collected since the last call, newline
+  /// separated; empty when collection is off.
+  pub fn take_nestest_log(&mut self) -> String {
+    self.cpu.take_nestest_log().join("\n")
+  }
+}
@@ -0,0 +1,318 @@
+use crate::input::ConfigError;
+use crate::nes::cartridge::RomError;
+use crate::nes::cheats::CheatError;
+use crate::nes::combo::ComboError;
+use crate::nes::cpu::{EmuError, Fault};
+use crate::nes::desync::ParseTraceError;
+use crate::nes::game_db::GameDbError;
+use crate::nes::movie::MovieError;
+use crate::nes::netplay::NetplayError;
+use crate::nes::nsf::NsfError;
+use crate::nes::palette::PaletteError;
+use crate::nes::patch::PatchError;
+use crate::nes::savestate::StateError;
+use crate::nes::time_travel::TimeTravelError;
+use crate::storage::StorageError;
+use js_sys::Reflect;
+use std::fmt;
+use wasm_bindgen::JsValue;
+
+/*
+  Everything a call from JS can fail with. Each module keeps its own error
+  type; this wraps them at the wasm boundary, where they turn into JS
+  Error objects with a stable `code` next to the usual `message` (and the
+  values behind the message where there are any, e.g. `mapper`), so a UI
+  can tell "not a ROM" from "board not emulated yet" without parsing
+  English.
+*/
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FlemuError {
+  /// Not a ROM we can read. Unsupported mappers are their own variant.
+  RomParse(RomError),
+  /// A board that isn't emulated, with its common name if it's a known one.
+  UnsupportedMapper {
+    number: u16,
+    submapper: u8,
+    known_name: Option<&'static str>,
+  },
+  /// A savestate written by an incompatible version of the format.
+  StateVersion {
+    found: u32,
+    supported: u32,
+  },
+  /// Not a savestate, cut short, or made with another cartridge.
+  BadState(StateError),
+  /// The CPU stopped on something it can't execute.
+  CpuFault(Fault),
+  Storage(StorageError),
+  Patch(PatchError),
+  Palette(PaletteError),
+  InputConfig(ConfigError),
+  /// A combo's text that doesn't parse, or one too long.
+  Combo(ComboError),
+  Cheat(CheatError),
+  TimeTravel(TimeTravelError),
+  Movie(MovieError),
+  /// A game database that doesn't parse.
+  GameDb(GameDbError),
+  /// Not an NSF we can play, e.g. one for expansion audio.
+  Nsf(NsfError),
+  /// A netplay message that makes no sense, or the sides out of step.
+  Netplay(NetplayError),
+  /// A checksum trace that doesn't parse.
+  ChecksumTrace(ParseTraceError),
+  /// A ROM that couldn't be downloaded, with the HTTP status if the
+  /// server answered.
+  Fetch {
+    url: String,
+    status: Option<u16>,
+  },
+  /// An argument from JS that makes no sense, with what was wrong.
+  InvalidArgument(String),
+}
+
+impl FlemuError {
+  /// Stable id for JS to branch on: "rom-parse", "unsupported-mapper", ...
+  pub fn code(&self) -> &'static str {
+    match self {
+      FlemuError::RomParse(_) => "rom-parse",
+      FlemuError::UnsupportedMapper { .. } => "unsupported-mapper",
+      FlemuError::StateVersion { .. } => "state-version",
+      FlemuError::BadState(_) => "bad-state",
+      FlemuError::CpuFault(_) => "cpu-fault",
+      FlemuError::Storage(_) => "storage",
+      FlemuError::Patch(_) => "patch",
+      FlemuError::Palette(_) => "palette",
+      FlemuError::InputConfig(_) => "input-config",
+      FlemuError::Combo(_) => "combo",
+      FlemuError::Cheat(_) => "cheat",
+      FlemuError::TimeTravel(_) => "time-travel",
+      FlemuError::Movie(_) => "movie",
+      FlemuError::GameDb(_) => "game-db",
+      FlemuError::Nsf(_) => "nsf",
+      FlemuError::Netplay(_) => "netplay",
+      FlemuError::ChecksumTrace(_) => "checksum-trace",
+      FlemuError::Fetch { .. } => "fetch",
+      FlemuError::InvalidArgument(_) => "invalid-argument",
+    }
+  }
+
+  // extra fields on the JS object
+  fn details(&self) -> Vec<(&'static str, f64)> {
+    match self {
+      FlemuError::UnsupportedMapper {
+        number, submapper, ..
+      } => vec![("mapper", *number as f64), ("submapper", *submapper as f64)],
+      FlemuError::StateVersion { found, supported } => {
+        vec![("found", *found as f64), ("supported", *supported as f64)]
+      }
+      FlemuError::CpuFault(Fault::UnknownOpcode { pc, code })
+      | FlemuError::CpuFault(Fault::UnofficialOpcode { pc, code })
+      | FlemuError::CpuFault(Fault::Unimplemented { pc, code }) => {
+        vec![("pc", *pc as f64), ("opcode", *code as f64)]
+      }
+      FlemuError::InputConfig(error) => vec![("line", error.line as f64)],
+      FlemuError::Combo(ComboError::Syntax { token }) => vec![("token", *token as f64)],
+      FlemuError::Cheat(CheatError::NotRam(addr)) => vec![("address", *addr as f64)],
+      FlemuError::Movie(MovieError::Syntax { line })
+      | FlemuError::GameDb(GameDbError::Syntax { line })
+      | FlemuError::ChecksumTrace(ParseTraceError { line }) => vec![("line", *line as f64)],
+      FlemuError::Netplay(NetplayError::Desync { frame })
+      | FlemuError::Netplay(NetplayError::TooLate { frame }) => vec![("frame", *frame as f64)],
+      FlemuError::Fetch {
+        status: Some(status),
+        ..
+      } => vec![("status", *status as f64)],
+      _ => vec![],
+    }
+  }
+
+  // extra text fields on the JS object
+  fn labels(&self) -> Vec<(&'static str, &'static str)> {
+    match self {
+      FlemuError::UnsupportedMapper {
+        known_name: Some(name),
+        ..
+      } => vec![("board", name)],
+      _ => vec![],
+    }
+  }
+}
+
+impl fmt::Display for FlemuError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      FlemuError::RomParse(error) => write!(f, "{}", error),
+      FlemuError::UnsupportedMapper {
+        number,
+        submapper,
+        known_name,
+      } => write!(
+        f,
+        "{}",
+        RomError::UnsupportedMapper {
+          number: *number,
+          submapper: *submapper,
+          known_name: *known_name
+        }
+      ),
+      FlemuError::StateVersion { found, supported } => write!(
+        f,
+        "savestate version {} can't be loaded, this build reads version {}",
+        found, supported
+      ),
+      FlemuError::BadState(error) => write!(f, "{}", error),
+      FlemuError::CpuFault(fault) => write!(f, "{}", fault),
+      FlemuError::Storage(error) => write!(f, "{}", error),
+      FlemuError::Patch(error) => write!(f, "{}", error),
+      FlemuError::Palette(error) => write!(f, "{}", error),
+      FlemuError::InputConfig(error) => write!(f, "{}", error),
+      FlemuError::Combo(error) => write!(f, "{}", error),
+      FlemuError::Cheat(error) => write!(f, "{}", error),
+      FlemuError::TimeTravel(error) => write!(f, "{}", error),
+      FlemuError::Movie(error) => write!(f, "{}", error),
+      FlemuError::GameDb(error) => write!(f, "{}", error),
+      FlemuError::Nsf(error) => write!(f, "{}", error),
+      FlemuError::Netplay(error) => write!(f, "{}", error),
+      FlemuError::ChecksumTrace(error) => write!(f, "{}", error),
+      FlemuError::Fetch { url, status: None } => write!(f, "couldn't fetch {}", url),
+      FlemuError::Fetch {
+        url,
+        status: Some(status),
+      } => write!(f, "couldn't fetch {}: HTTP {}", url, status),
+      FlemuError::InvalidArgument(message) => write!(f, "{}", message),
+    }
+  }
+}
+
+impl std::error::Error for FlemuError {}
+
+impl From<RomError> for FlemuError {
+  fn from(error: RomError) -> Self {
+    match error {
+      RomError::UnsupportedMapper {
+        number,
+        submapper,
+        known_name,
+      } => FlemuError::UnsupportedMapper {
+        number,
+        submapper,
+        known_name,
+      },
+      error => FlemuError::RomParse(error),
+    }
+  }
+}
+
+impl From<StateError> for FlemuError {
+  fn from(error: StateError) -> Self {
+    match error {
+      StateError::Version { found, supported } => FlemuError::StateVersion { found, supported },
+      error => FlemuError::BadState(error),
+    }
+  }
+}
+
+impl From<Fault> for FlemuError {
+  fn from(fault: Fault) -> Self {
+    FlemuError::CpuFault(fault)
+  }
+}
+
+impl From<EmuError> for FlemuError {
+  fn from(error: EmuError) -> Self {
+    match error {
+      EmuError::Rom(error) => FlemuError::from(error),
+      EmuError::Fault(fault) => FlemuError::CpuFault(fault),
+    }
+  }
+}
+
+impl From<StorageError> for FlemuError {
+  fn from(error: StorageError) -> Self {
+    FlemuError::Storage(error)
+  }
+}
+
+impl From<PatchError> for FlemuError {
+  fn from(error: PatchError) -> Self {
+    FlemuError::Patch(error)
+  }
+}
+
+impl From<PaletteError> for FlemuError {
+  fn from(error: PaletteError) -> Self {
+    FlemuError::Palette(error)
+  }
+}
+
+impl From<ConfigError> for FlemuError {
+  fn from(error: ConfigError) -> Self {
+    FlemuError::InputConfig(error)
+  }
+}
+
+impl From<ComboError> for FlemuError {
+  fn from(error: ComboError) -> Self {
+    FlemuError::Combo(error)
+  }
+}
+
+impl From<CheatError> for FlemuError {
+  fn from(error: CheatError) -> Self {
+    FlemuError::Cheat(error)
+  }
+}
+
+impl From<TimeTravelError> for FlemuError {
+  fn from(error: TimeTravelError) -> Self {
+    FlemuError::TimeTravel(error)
+  }
+}
+
+impl From<MovieError> for FlemuError {
+  fn from(error: MovieError) -> Self {
+    FlemuError::Movie(error)
+  }
+}
+
+impl From<GameDbError> for FlemuError {
+  fn from(error: GameDbError) -> Self {
+    FlemuError::GameDb(error)
+  }
+}
+
+impl From<NsfError> for FlemuError {
+  fn from(error: NsfError) -> Self {
+    FlemuError::Nsf(error)
+  }
+}
+
+impl From<NetplayError> for FlemuError {
+  fn from(error: NetplayError) -> Self {
+    FlemuError::Netplay(error)
+  }
+}
+
+impl From<ParseTraceError> for FlemuError {
+  fn from(error: ParseTraceError) -> Self {
+    FlemuError::ChecksumTrace(error)
+  }
+}
+
+/// A JS `Error` with `code` and the variant's details as properties, e.g.
+/// `mapper`, `submapper` and `board` for an unsupported mapper.
+impl From<FlemuError> for JsValue {
+  fn from(error: FlemuError) -> Self {
+    let object = js_sys::Error::new(&error.to_string());
+    Reflect::set(&object, &"code".into(), &error.code().into()).unwrap();
+    for (key, value) in error.details() {
+      Reflect::set(&object, &key.into(), &value.into()).unwrap();
+    }
+    for (key, value) in error.labels() {
+      Reflect::set(&object, &key.into(), &value.into()).unwrap();
+    }
+    object.into()
+  }
+}
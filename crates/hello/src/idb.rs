@@ -0,0 +1,99 @@
+use crate::storage::StorageError;
+use js_sys::{Function, Promise, Reflect, Uint8Array};
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{IdbDatabase, IdbFactory, IdbRequest, IdbTransactionMode};
+
+/*
+  Blobs in the browser's IndexedDB, the async counterpart of
+  `StorageBackend`: one object store, keys "<rom hash>/<slot>", values
+  Uint8Arrays. IndexedDB only answers through callbacks, so every request
+  is turned into a future that settles when its success or error event
+  fires.
+*/
+
+const DATABASE: &str = "flemu";
+const DATABASE_VERSION: u32 = 1;
+const STORE: &str = "blobs";
+
+fn storage_error(error: JsValue) -> StorageError {
+  let message = error
+    .dyn_ref::<js_sys::Error>()
+    .map(|error| String::from(error.message()))
+    .or_else(|| error.as_string())
+    .unwrap_or_else(|| format!("{:?}", error));
+  StorageError(message)
+}
+
+// the request's result once it succeeded
+async fn settled(request: &IdbRequest) -> Result<JsValue, StorageError> {
+  let promise = Promise::new(&mut |resolve: Function, reject: Function| {
+    let done = request.clone();
+    let on_success = Closure::once_into_js(move || {
+      resolve.call1(&JsValue::NULL, &done.result().unwrap_or(JsValue::UNDEFINED))
+    });
+    let failed = request.clone();
+    let on_error = Closure::once_into_js(move || {
+      let error = match failed.error() {
+        Ok(Some(error)) => JsValue::from(error.message()),
+        _ => JsValue::from("request failed"),
+      };
+      reject.call1(&JsValue::NULL, &error)
+    });
+    request.set_onsuccess(Some(on_success.unchecked_ref()));
+    request.set_onerror(Some(on_error.unchecked_ref()));
+  });
+  JsFuture::from(promise).await.map_err(storage_error)
+}
+
+async fn open() -> Result<IdbDatabase, StorageError> {
+  // the page's or a worker's, whichever this runs in
+  let factory = Reflect::get(&js_sys::global(), &"indexedDB".into())
+    .ok()
+    .and_then(|factory| factory.dyn_into::<IdbFactory>().ok())
+    .ok_or_else(|| StorageError("IndexedDB is not available".to_string()))?;
+  let request = factory
+    .open_with_u32(DATABASE, DATABASE_VERSION)
+    .map_err(storage_error)?;
+  // first open, or an older version: create the store
+  let upgrading = request.clone();
+  let on_upgrade = Closure::once_into_js(move || {
+    if let Ok(database) = upgrading.result() {
+      let database: IdbDatabase = database.unchecked_into();
+      if !database.object_store_names().contains(STORE) {
+        database.create_object_store(STORE).ok();
+      }
+    }
+  });
+  request.set_onupgradeneeded(Some(on_upgrade.unchecked_ref()));
+  Ok(settled(&request).await?.unchecked_into())
+}
+
+fn key(rom_hash: &str, slot: &str) -> JsValue {
+  format!("{}/{}", rom_hash, slot).into()
+}
+
+/// The blob in `slot` of `rom_hash`, None if nothing was put there.
+pub async fn get(rom_hash: &str, slot: &str) -> Result<Option<Vec<u8>>, StorageError> {
+  let database = open().await?;
+  let store = database
+    .transaction_with_str(STORE)
+    .and_then(|transaction| transaction.object_store(STORE))
+    .map_err(storage_error)?;
+  let request = store.get(&key(rom_hash, slot)).map_err(storage_error)?;
+  let value = settled(&request).await?;
+  Ok(value.dyn_ref::<Uint8Array>().map(Uint8Array::to_vec))
+}
+
+pub async fn put(rom_hash: &str, slot: &str, data: &[u8]) -> Result<(), StorageError> {
+  let database = open().await?;
+  let store = database
+    .transaction_with_str_and_mode(STORE, IdbTransactionMode::Readwrite)
+    .and_then(|transaction| transaction.object_store(STORE))
+    .map_err(storage_error)?;
+  let request = store
+    .put_with_key(&Uint8Array::from(data), &key(rom_hash, slot))
+    .map_err(storage_error)?;
+  settled(&request).await.map(|_| ())
+}
@@ -0,0 +1,994 @@
+use crate::error::FlemuError;
+use crate::nes::combo::{Combo, ComboRecorder};
+use crate::nes::joypad::{JoypadButton, TurboRate};
+use crate::{
+  connected_gamepads, gamepad_snapshots, invalid, js_object, press, webgl, Emulator, KeyMap,
+};
+use js_sys::Array;
+use std::fmt;
+use wasm_bindgen::prelude::*;
+
+/// One button on an input device, described for remapping UIs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ButtonDescriptor {
+  /// Stable identifier used in bindings and saved configs.
+  pub id: &'static str,
+  /// Label to show to the user.
+  pub name: &'static str,
+  /// Icon id, for frontends that ship an icon set.
+  pub icon: &'static str,
+  /// Bit in the device's report (standard pad: shift register order,
+  /// then turbo A and B at 8 and 9, which only the emulator sees).
+  pub bit: u8,
+}
+
+/// A kind of device that can be plugged into a controller port.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeviceDescriptor {
+  pub id: &'static str,
+  pub name: &'static str,
+  pub buttons: &'static [ButtonDescriptor],
+}
+
+pub const STANDARD_CONTROLLER: DeviceDescriptor = DeviceDescriptor {
+  id: "standard",
+  name: "NES Controller",
+  buttons: &[
+    ButtonDescriptor {
+      id: "a",
+      name: "A",
+      icon: "button-a",
+      bit: 0,
+    },
+    ButtonDescriptor {
+      id: "b",
+      name: "B",
+      icon: "button-b",
+      bit: 1,
+    },
+    ButtonDescriptor {
+      id: "select",
+      name: "Select",
+      icon: "button-select",
+      bit: 2,
+    },
+    ButtonDescriptor {
+      id: "start",
+      name: "Start",
+      icon: "button-start",
+      bit: 3,
+    },
+    ButtonDescriptor {
+      id: "up",
+      name: "Up",
+      icon: "dpad-up",
+      bit: 4,
+    },
+    ButtonDescriptor {
+      id: "down",
+      name: "Down",
+      icon: "dpad-down",
+      bit: 5,
+    },
+    ButtonDescriptor {
+      id: "left",
+      name: "Left",
+      icon: "dpad-left",
+      bit: 6,
+    },
+    ButtonDescriptor {
+      id: "right",
+      name: "Right",
+      icon: "dpad-right",
+      bit: 7,
+    },
+    ButtonDescriptor {
+      id: "turbo-a",
+      name: "Turbo A",
+      icon: "button-a",
+      bit: 8,
+    },
+    ButtonDescriptor {
+      id: "turbo-b",
+      name: "Turbo B",
+      icon: "button-b",
+      bit: 9,
+    },
+  ],
+};
+
+/// Controllers the console can take: two, plus two more through a Four
+/// Score.
+pub const PORTS: u8 = 4;
+
+/// Default keyboard layout for the first controller, as KeyboardEvent.code
+/// values. The second controller starts unbound.
+const DEFAULT_KEYS: [(&str, &str); 10] = [
+  ("a", "KeyX"),
+  ("b", "KeyZ"),
+  ("select", "ShiftRight"),
+  ("start", "Enter"),
+  ("up", "ArrowUp"),
+  ("down", "ArrowDown"),
+  ("left", "ArrowLeft"),
+  ("right", "ArrowRight"),
+  ("turbo-a", "KeyS"),
+  ("turbo-b", "KeyA"),
+];
+
+/// Emulator actions a key can trigger instead of a controller button.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hotkey {
+  SaveState,
+  LoadState,
+  Rewind,
+  FastForward,
+  Screenshot,
+  Pause,
+}
+
+impl Hotkey {
+  pub const ALL: [Hotkey; 6] = [
+    Hotkey::SaveState,
+    Hotkey::LoadState,
+    Hotkey::Rewind,
+    Hotkey::FastForward,
+    Hotkey::Screenshot,
+    Hotkey::Pause,
+  ];
+
+  pub fn id(self) -> &'static str {
+    match self {
+      Hotkey::SaveState => "save-state",
+      Hotkey::LoadState => "load-state",
+      Hotkey::Rewind => "rewind",
+      Hotkey::FastForward => "fast-forward",
+      Hotkey::Screenshot => "screenshot",
+      Hotkey::Pause => "pause",
+    }
+  }
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Hotkey::SaveState => "Save state",
+      Hotkey::LoadState => "Load state",
+      Hotkey::Rewind => "Rewind",
+      Hotkey::FastForward => "Fast forward",
+      Hotkey::Screenshot => "Screenshot",
+      Hotkey::Pause => "Pause",
+    }
+  }
+
+  pub fn from_id(id: &str) -> Option<Hotkey> {
+    Hotkey::ALL.iter().copied().find(|hotkey| hotkey.id() == id)
+  }
+
+  /// Whether the action lasts while the key is down (rewind, fast
+  /// forward) rather than firing once per press.
+  pub fn is_held(self) -> bool {
+    matches!(self, Hotkey::Rewind | Hotkey::FastForward)
+  }
+}
+
+const DEFAULT_HOTKEYS: [(Hotkey, &str); 6] = [
+  (Hotkey::SaveState, "F5"),
+  (Hotkey::LoadState, "F7"),
+  (Hotkey::Rewind, "Backspace"),
+  (Hotkey::FastForward, "Tab"),
+  (Hotkey::Screenshot, "F9"),
+  (Hotkey::Pause, "KeyP"),
+];
+
+/// A control on a browser gamepad, numbered as in the Gamepad API's
+/// "standard" layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GamepadInput {
+  Button(u8),
+  /// An axis pushed past `AXIS_THRESHOLD` towards -1 (left, up).
+  AxisNegative(u8),
+  AxisPositive(u8),
+}
+
+/// How far a stick has to be pushed to count as pressed.
+pub const AXIS_THRESHOLD: f64 = 0.5;
+
+impl GamepadInput {
+  /// "button-9", "axis-1-", "axis-0+".
+  pub fn id(self) -> String {
+    match self {
+      GamepadInput::Button(index) => format!("button-{}", index),
+      GamepadInput::AxisNegative(index) => format!("axis-{}-", index),
+      GamepadInput::AxisPositive(index) => format!("axis-{}+", index),
+    }
+  }
+
+  pub fn from_id(id: &str) -> Option<GamepadInput> {
+    if let Some(index) = id.strip_prefix("button-") {
+      return index.parse().ok().map(GamepadInput::Button);
+    }
+    let axis = id.strip_prefix("axis-")?;
+    if let Some(index) = axis.strip_suffix('-') {
+      index.parse().ok().map(GamepadInput::AxisNegative)
+    } else {
+      let index = axis.strip_suffix('+')?;
+      index.parse().ok().map(GamepadInput::AxisPositive)
+    }
+  }
+
+  /// Whether this control is held in a pad's current `buttons` and `axes`.
+  pub fn is_active(self, buttons: &[bool], axes: &[f64]) -> bool {
+    match self {
+      GamepadInput::Button(index) => buttons.get(index as usize) == Some(&true),
+      GamepadInput::AxisNegative(index) => axes
+        .get(index as usize)
+        .map_or(false, |&axis| axis <= -AXIS_THRESHOLD),
+      GamepadInput::AxisPositive(index) => axes
+        .get(index as usize)
+        .map_or(false, |&axis| axis >= AXIS_THRESHOLD),
+    }
+  }
+}
+
+/// Default pad layout: B and A on the bottom and right face buttons, where
+/// they sit on a NES pad, their turbo buttons above them, and both the
+/// d-pad and the left stick steering.
+const DEFAULT_GAMEPAD: [(&str, GamepadInput); 14] = [
+  ("a", GamepadInput::Button(1)),
+  ("b", GamepadInput::Button(0)),
+  ("select", GamepadInput::Button(8)),
+  ("start", GamepadInput::Button(9)),
+  ("up", GamepadInput::Button(12)),
+  ("down", GamepadInput::Button(13)),
+  ("left", GamepadInput::Button(14)),
+  ("right", GamepadInput::Button(15)),
+  ("turbo-a", GamepadInput::Button(3)),
+  ("turbo-b", GamepadInput::Button(2)),
+  ("up", GamepadInput::AxisNegative(1)),
+  ("down", GamepadInput::AxisPositive(1)),
+  ("left", GamepadInput::AxisNegative(0)),
+  ("right", GamepadInput::AxisPositive(0)),
+];
+
+/// Every device the core can emulate.
+pub fn devices() -> &'static [DeviceDescriptor] {
+  &[STANDARD_CONTROLLER]
+}
+
+/// What is plugged into `port`, if anything.
+pub fn device_for_port(port: u8) -> Option<&'static DeviceDescriptor> {
+  if port < PORTS {
+    Some(&STANDARD_CONTROLLER)
+  } else {
+    None
+  }
+}
+
+/// A key bound to a button on the device in `port`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+  pub port: u8,
+  pub button: &'static ButtonDescriptor,
+  pub key: String,
+}
+
+/// What a key is bound to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyTarget {
+  Button(u8, &'static ButtonDescriptor),
+  Hotkey(Hotkey),
+}
+
+/// A key that plays a combo on the controller in `port`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComboBinding {
+  pub port: u8,
+  pub key: String,
+  pub combo: Combo,
+}
+
+/// Current key and gamepad bindings. A key drives at most one button,
+/// hotkey or combo. Gamepad n drives the controller in port n, all through
+/// the same layout, where a button can have several inputs but an input
+/// drives one button.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bindings {
+  bindings: Vec<Binding>,
+  hotkeys: Vec<(Hotkey, String)>,
+  gamepad: Vec<(&'static ButtonDescriptor, GamepadInput)>,
+  combos: Vec<ComboBinding>,
+}
+
+impl Default for Bindings {
+  fn default() -> Self {
+    let mut bindings = Bindings::empty();
+    for (button, key) in DEFAULT_KEYS.iter() {
+      bindings.bind(0, button, key);
+    }
+    for (hotkey, key) in DEFAULT_HOTKEYS.iter() {
+      bindings.bind_hotkey(*hotkey, key);
+    }
+    for (button, input) in DEFAULT_GAMEPAD.iter() {
+      bindings.bind_gamepad(button, *input);
+    }
+    bindings
+  }
+}
+
+impl Bindings {
+  pub fn empty() -> Self {
+    Bindings {
+      bindings: Vec::new(),
+      hotkeys: Vec::new(),
+      gamepad: Vec::new(),
+      combos: Vec::new(),
+    }
+  }
+
+  /// Bind `key` to `button` on `port`, replacing whatever the button or the
+  /// key were bound to. Returns false for an unknown port or button.
+  pub fn bind(&mut self, port: u8, button: &str, key: &str) -> bool {
+    let button = match find_button(port, button) {
+      Some(button) => button,
+      None => return false,
+    };
+    self
+      .bindings
+      .retain(|b| b.key != key && !(b.port == port && b.button.id == button.id));
+    self.hotkeys.retain(|(_, k)| k != key);
+    self.combos.retain(|c| c.key != key);
+    self.bindings.push(Binding {
+      port,
+      button,
+      key: key.to_string(),
+    });
+    true
+  }
+
+  pub fn unbind(&mut self, port: u8, button: &str) {
+    self
+      .bindings
+      .retain(|b| !(b.port == port && b.button.id == button));
+  }
+
+  /// Free `key`, whatever it was bound to.
+  pub fn unbind_key(&mut self, key: &str) {
+    self.bindings.retain(|b| b.key != key);
+    self.hotkeys.retain(|(_, k)| k != key);
+    self.combos.retain(|c| c.key != key);
+  }
+
+  /// Hotkey bindings.
+  pub fn hotkey_iter(&self) -> impl Iterator<Item = &(Hotkey, String)> {
+    self.hotkeys.iter()
+  }
+
+  pub fn key_for(&self, port: u8, button: &str) -> Option<&str> {
+    self
+      .bindings
+      .iter()
+      .find(|b| b.port == port && b.button.id == button)
+      .map(|b| b.key.as_str())
+  }
+
+  /// Which port and button a key press should drive.
+  pub fn button_for_key(&self, key: &str) -> Option<(u8, &'static ButtonDescriptor)> {
+    self
+      .bindings
+      .iter()
+      .find(|b| b.key == key)
+      .map(|b| (b.port, b.button))
+  }
+
+  /// Button bindings.
+  pub fn iter(&self) -> impl Iterator<Item = &Binding> {
+    self.bindings.iter()
+  }
+
+  /// Bind `key` to `hotkey`, replacing whatever either was bound to.
+  pub fn bind_hotkey(&mut self, hotkey: Hotkey, key: &str) {
+    self.bindings.retain(|b| b.key != key);
+    self.combos.retain(|c| c.key != key);
+    self.hotkeys.retain(|(h, k)| *h != hotkey && k != key);
+    self.hotkeys.push((hotkey, key.to_string()));
+  }
+
+  pub fn unbind_hotkey(&mut self, hotkey: Hotkey) {
+    self.hotkeys.retain(|(h, _)| *h != hotkey);
+  }
+
+  pub fn key_for_hotkey(&self, hotkey: Hotkey) -> Option<&str> {
+    self
+      .hotkeys
+      .iter()
+      .find(|(h, _)| *h == hotkey)
+      .map(|(_, key)| key.as_str())
+  }
+
+  pub fn hotkey_for_key(&self, key: &str) -> Option<Hotkey> {
+    self
+      .hotkeys
+      .iter()
+      .find(|(_, k)| k == key)
+      .map(|(hotkey, _)| *hotkey)
+  }
+
+  pub fn target_for_key(&self, key: &str) -> Option<KeyTarget> {
+    match self.button_for_key(key) {
+      Some((port, button)) => Some(KeyTarget::Button(port, button)),
+      None => self.hotkey_for_key(key).map(KeyTarget::Hotkey),
+    }
+  }
+
+  /// Make `key` play `combo` on `port`, replacing whatever the key was
+  /// bound to. Returns false for an unknown port or an empty combo.
+  pub fn bind_combo(&mut self, port: u8, key: &str, combo: Combo) -> bool {
+    if device_for_port(port).is_none() || combo.is_empty() {
+      return false;
+    }
+    self.unbind_key(key);
+    self.combos.push(ComboBinding {
+      port,
+      key: key.to_string(),
+      combo,
+    });
+    true
+  }
+
+  pub fn combo_for_key(&self, key: &str) -> Option<&ComboBinding> {
+    self.combos.iter().find(|c| c.key == key)
+  }
+
+  /// Combo bindings.
+  pub fn combo_iter(&self) -> impl Iterator<Item = &ComboBinding> {
+    self.combos.iter()
+  }
+
+  /// Make `input` press `button`, taking it away from any other button.
+  /// Returns false for an unknown button.
+  pub fn bind_gamepad(&mut self, button: &str, input: GamepadInput) -> bool {
+    let button = match find_button(0, button) {
+      Some(button) => button,
+      None => return false,
+    };
+    self.gamepad.retain(|(_, i)| *i != input);
+    self.gamepad.push((button, input));
+    true
+  }
+
+  /// Remove every gamepad input of `button`.
+  pub fn unbind_gamepad(&mut self, button: &str) {
+    self.gamepad.retain(|(b, _)| b.id != button);
+  }
+
+  pub fn gamepad_inputs(&self, button: &str) -> Vec<GamepadInput> {
+    self
+      .gamepad
+      .iter()
+      .filter(|(b, _)| b.id == button)
+      .map(|(_, input)| *input)
+      .collect()
+  }
+
+  pub fn gamepad_iter(&self) -> impl Iterator<Item = &(&'static ButtonDescriptor, GamepadInput)> {
+    self.gamepad.iter()
+  }
+
+  /// Controller buttons held on a pad with these `buttons` and `axes`, as
+  /// a mask of `ButtonDescriptor::bit`s.
+  pub fn gamepad_buttons(&self, buttons: &[bool], axes: &[f64]) -> u16 {
+    self
+      .gamepad
+      .iter()
+      .filter(|(_, input)| input.is_active(buttons, axes))
+      .fold(0, |mask, (button, _)| mask | 1 << button.bit)
+  }
+
+  /// Text form for saving, one binding per line:
+  ///
+  /// ```text
+  /// button 0 a KeyX
+  /// hotkey save-state F5
+  /// gamepad a button-1
+  /// combo 0 KeyQ D*2 RD*2 R*1 RB*3
+  /// ```
+  pub fn to_config(&self) -> String {
+    let mut config = String::new();
+    for b in &self.bindings {
+      config.push_str(&format!("button {} {} {}\n", b.port, b.button.id, b.key));
+    }
+    for (hotkey, key) in &self.hotkeys {
+      config.push_str(&format!("hotkey {} {}\n", hotkey.id(), key));
+    }
+    for (button, input) in &self.gamepad {
+      config.push_str(&format!("gamepad {} {}\n", button.id, input.id()));
+    }
+    for c in &self.combos {
+      config.push_str(&format!(
+        "combo {} {} {}\n",
+        c.port,
+        c.key,
+        c.combo.to_text()
+      ));
+    }
+    config
+  }
+
+  /// Parse `to_config` output. Anything not mentioned is left unbound.
+  pub fn from_config(config: &str) -> Result<Bindings, ConfigError> {
+    let mut bindings = Bindings::empty();
+    for (index, line) in config.lines().enumerate() {
+      let error = ConfigError { line: index + 1 };
+      let fields: Vec<&str> = line.split_whitespace().collect();
+      match fields.as_slice() {
+        [] => {}
+        ["button", port, button, key] => {
+          let port = port.parse().map_err(|_| error.clone())?;
+          if !bindings.bind(port, button, key) {
+            return Err(error);
+          }
+        }
+        ["hotkey", hotkey, key] => {
+          let hotkey = Hotkey::from_id(hotkey).ok_or(error)?;
+          bindings.bind_hotkey(hotkey, key);
+        }
+        ["gamepad", button, input] => {
+          let input = GamepadInput::from_id(input).ok_or_else(|| error.clone())?;
+          if !bindings.bind_gamepad(button, input) {
+            return Err(error);
+          }
+        }
+        ["combo", port, key, combo @ ..] => {
+          let port = port.parse().map_err(|_| error.clone())?;
+          let combo = Combo::from_text(&combo.join(" ")).map_err(|_| error.clone())?;
+          if !bindings.bind_combo(port, key, combo) {
+            return Err(error);
+          }
+        }
+        _ => return Err(error),
+      }
+    }
+    Ok(bindings)
+  }
+}
+
+/// A line of a bindings config that doesn't parse, or names an unknown
+/// port, button or hotkey, or has a bad combo.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+  pub line: usize,
+}
+
+impl fmt::Display for ConfigError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "bad key binding on line {}", self.line)
+  }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// A hotkey going down or, for held ones, back up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HotkeyEvent {
+  pub hotkey: Hotkey,
+  pub pressed: bool,
+}
+
+/// Turns raw key events into hotkey events, so every frontend gets the
+/// same behaviour: one event per press for one-shot hotkeys however long
+/// the key auto-repeats, and press and release for held ones.
+#[derive(Debug, Clone, Default)]
+pub struct HotkeyState {
+  down: Vec<Hotkey>,
+}
+
+impl HotkeyState {
+  pub fn key_event(
+    &mut self,
+    bindings: &Bindings,
+    key: &str,
+    pressed: bool,
+  ) -> Option<HotkeyEvent> {
+    let hotkey = bindings.hotkey_for_key(key)?;
+    let was_down = self.is_down(hotkey);
+    if pressed == was_down {
+      // auto-repeat, or a release we never saw the press of
+      return None;
+    }
+    if pressed {
+      self.down.push(hotkey);
+    } else {
+      self.down.retain(|h| *h != hotkey);
+    }
+    if pressed || hotkey.is_held() {
+      Some(HotkeyEvent { hotkey, pressed })
+    } else {
+      None
+    }
+  }
+
+  pub fn is_down(&self, hotkey: Hotkey) -> bool {
+    self.down.contains(&hotkey)
+  }
+
+  /// Forget every key, e.g. when the page loses focus and the releases
+  /// won't arrive. Returns release events for held hotkeys that were down.
+  pub fn release_all(&mut self) -> Vec<HotkeyEvent> {
+    self
+      .down
+      .drain(..)
+      .filter(|hotkey| hotkey.is_held())
+      .map(|hotkey| HotkeyEvent {
+        hotkey,
+        pressed: false,
+      })
+      .collect()
+  }
+}
+
+fn find_button(port: u8, id: &str) -> Option<&'static ButtonDescriptor> {
+  device_for_port(port)?.buttons.iter().find(|b| b.id == id)
+}
+
+fn js_hotkey_event(event: HotkeyEvent) -> JsValue {
+  js_object(&[
+    ("hotkey", event.hotkey.id().into()),
+    ("pressed", event.pressed.into()),
+  ])
+}
+
+#[wasm_bindgen]
+impl Emulator {
+  /// Current key bindings: `[{ port, device, button, key }]`.
+  pub fn input_bindings(&self) -> JsValue {
+    self
+      .bindings
+      .iter()
+      .map(|binding| {
+        let device = device_for_port(binding.port).map_or("", |d| d.id);
+        js_object(&[
+          ("port", binding.port.into()),
+          ("device", device.into()),
+          ("button", binding.button.id.into()),
+          ("key", binding.key.as_str().into()),
+        ])
+      })
+      .collect::<Array>()
+      .into()
+  }
+
+  /// Bind a KeyboardEvent.code to a button. Returns false for an unknown
+  /// port or button.
+  pub fn bind_key(&mut self, port: u8, button: &str, key: &str) -> bool {
+    self.bindings.bind(port, button, key)
+  }
+
+  pub fn unbind_key(&mut self, port: u8, button: &str) {
+    self.bindings.unbind(port, button);
+  }
+
+  /// Every hotkey and what it's bound to: `[{ id, name, held, key }]`,
+  /// `key` null when unbound.
+  pub fn hotkeys(&self) -> JsValue {
+    Hotkey::ALL
+      .iter()
+      .map(|&hotkey| {
+        let key = self
+          .bindings
+          .key_for_hotkey(hotkey)
+          .map_or(JsValue::NULL, JsValue::from);
+        js_object(&[
+          ("id", hotkey.id().into()),
+          ("name", hotkey.name().into()),
+          ("held", hotkey.is_held().into()),
+          ("key", key),
+        ])
+      })
+      .collect::<Array>()
+      .into()
+  }
+
+  /// Bind a KeyboardEvent.code to a hotkey. Returns false for an unknown
+  /// hotkey.
+  pub fn bind_hotkey(&mut self, hotkey: &str, key: &str) -> bool {
+    match Hotkey::from_id(hotkey) {
+      Some(hotkey) => {
+        self.bindings.bind_hotkey(hotkey, key);
+        true
+      }
+      None => false,
+    }
+  }
+
+  pub fn unbind_hotkey(&mut self, hotkey: &str) {
+    if let Some(hotkey) = Hotkey::from_id(hotkey) {
+      self.bindings.unbind_hotkey(hotkey);
+    }
+  }
+
+  /// Plug in or remove a Four Score, the adapter that takes players 3
+  /// and 4.
+  pub fn set_four_score(&mut self, connected: bool) {
+    self.cpu.bus.set_four_score(connected);
+  }
+
+  /// Plug a Zapper into port 2 in place of the second controller, or take
+  /// it out.
+  pub fn set_zapper(&mut self, connected: bool) {
+    self.cpu.bus.set_zapper(connected);
+  }
+
+  /// Point the Zapper at mouse position (`x`, `y`) on a canvas of
+  /// `width`x`height` CSS pixels showing the picture as `render` draws
+  /// it, e.g. a mousemove's offsetX/offsetY and the canvas'
+  /// clientWidth/clientHeight.
+  pub fn zapper_aim(&mut self, x: f64, y: f64, width: f64, height: f64) {
+    let (filter, options) = (self.video_filter, self.video_options);
+    if let Some(zapper) = self.cpu.bus.zapper_mut() {
+      if width > 0.0 && height > 0.0 {
+        let (px, py, pw, ph) =
+          webgl::viewport(filter, options, width.round() as u32, height.round() as u32);
+        let (sx, sy, sw, sh) = options.source();
+        zapper.aim(
+          (sx as f64 + (x - px as f64) * sw as f64 / pw as f64).floor() as i32,
+          (sy as f64 + (y - py as f64) * sh as f64 / ph as f64).floor() as i32,
+        );
+      } else {
+        zapper.aim_off_screen();
+      }
+    }
+  }
+
+  /// The mouse left the canvas: the Zapper sees no light.
+  pub fn zapper_leave(&mut self) {
+    if let Some(zapper) = self.cpu.bus.zapper_mut() {
+      zapper.aim_off_screen();
+    }
+  }
+
+  /// A click: pull the trigger for a few frames.
+  pub fn zapper_trigger(&mut self) {
+    self.attract_input();
+    if let Some(zapper) = self.cpu.bus.zapper_mut() {
+      zapper.pull_trigger();
+    }
+  }
+
+  /// Press or release `button` ("a", "start", ... as in `input_devices`)
+  /// on `player`'s (0-3) controller. Returns false for an unknown player or
+  /// button.
+  pub fn set_button_state(&mut self, player: u8, button: &str, pressed: bool) -> bool {
+    let bit = match device_for_port(player)
+      .and_then(|device| device.buttons.iter().find(|b| b.id == button))
+    {
+      Some(button) => button.bit,
+      None => return false,
+    };
+    self.attract_input();
+    press(
+      &mut self.cpu.bus.joypads[player as usize],
+      1 << bit,
+      pressed,
+    );
+    true
+  }
+
+  /// How fast turbo buttons fire on every controller: `on` frames
+  /// pressed, then `off` released, each at least 1.
+  pub fn set_turbo_rate(&mut self, on: u8, off: u8) {
+    for pad in self.cpu.bus.joypads.iter_mut() {
+      pad.set_turbo_rate(TurboRate { on, off });
+    }
+  }
+
+  /// `[on, off]`, as `set_turbo_rate` took them.
+  pub fn turbo_rate(&self) -> Vec<u8> {
+    let rate = self.cpu.bus.joypads[0].turbo_rate();
+    vec![rate.on, rate.off]
+  }
+
+  /// Feed a raw keydown (`pressed`) or keyup. Keys bound to buttons drive
+  /// the controllers; for hotkeys returns the action to take as
+  /// `{ hotkey, pressed }`, otherwise null.
+  pub fn key_event(&mut self, key: &str, pressed: bool) -> JsValue {
+    self.attract_input();
+    if let Some(KeyTarget::Button(port, button)) = self.bindings.target_for_key(key) {
+      self.set_button_state(port, button.id, pressed);
+      return JsValue::NULL;
+    }
+    if let Some(binding) = self.bindings.combo_for_key(key) {
+      // once per press, however long the key auto-repeats
+      let was_down = self.combo_keys.iter().any(|k| k == key);
+      if pressed && !was_down {
+        self.cpu.bus.joypads[binding.port as usize].play_combo(&binding.combo);
+        self.combo_keys.push(key.to_string());
+      } else if !pressed {
+        self.combo_keys.retain(|k| k != key);
+      }
+      return JsValue::NULL;
+    }
+    self
+      .hotkeys
+      .key_event(&self.bindings, key, pressed)
+      .map_or(JsValue::NULL, js_hotkey_event)
+  }
+
+  /// Forget held keys when the page loses focus, releasing every button
+  /// and stopping combos. Returns the release events of held hotkeys,
+  /// shaped like `key_event`'s.
+  pub fn release_keys(&mut self) -> JsValue {
+    for pad in self.cpu.bus.joypads.iter_mut() {
+      pad.set_button_pressed_status(JoypadButton::all(), false);
+      pad.set_turbo_pressed_status(JoypadButton::all(), false);
+      pad.stop_combo();
+    }
+    self.combo_keys.clear();
+    self
+      .hotkeys
+      .release_all()
+      .into_iter()
+      .map(js_hotkey_event)
+      .collect::<Array>()
+      .into()
+  }
+
+  /// Start recording a combo from `player`'s (0-3) controller: what it
+  /// presses every frame from the first press on. Returns false for an
+  /// unknown player.
+  pub fn record_combo(&mut self, player: u8) -> bool {
+    if device_for_port(player).is_none() {
+      return false;
+    }
+    self.combo_recorder = Some((player, ComboRecorder::new()));
+    true
+  }
+
+  pub fn combo_recording(&self) -> bool {
+    self.combo_recorder.is_some()
+  }
+
+  /// Stop recording. Returns the combo in text form (`D*2 RD*2 R*1`,
+  /// see `nes::combo`) for `bind_combo`, or null if nothing was pressed.
+  pub fn stop_combo_recording(&mut self) -> Option<String> {
+    let (_, recorder) = self.combo_recorder.take()?;
+    let combo = recorder.finish();
+    if combo.is_empty() {
+      None
+    } else {
+      Some(combo.to_text())
+    }
+  }
+
+  /// Make a KeyboardEvent.code play `combo`, text as from
+  /// `stop_combo_recording`, on `player`'s controller, replacing whatever
+  /// the key was bound to.
+  pub fn bind_combo(&mut self, key: &str, player: u8, combo: &str) -> Result<(), JsValue> {
+    let combo = Combo::from_text(combo).map_err(FlemuError::from)?;
+    if !self.bindings.bind_combo(player, key, combo) {
+      return Err(invalid(format!(
+        "can't play an empty combo on player {}",
+        player
+      )));
+    }
+    Ok(())
+  }
+
+  /// Key combos: `[{ port, key, combo }]`.
+  pub fn combos(&self) -> JsValue {
+    self
+      .bindings
+      .combo_iter()
+      .map(|binding| {
+        js_object(&[
+          ("port", binding.port.into()),
+          ("key", binding.key.as_str().into()),
+          ("combo", binding.combo.to_text().into()),
+        ])
+      })
+      .collect::<Array>()
+      .into()
+  }
+
+  pub fn unbind_combo(&mut self, key: &str) {
+    if self.bindings.combo_for_key(key).is_some() {
+      self.bindings.unbind_key(key);
+    }
+  }
+
+  /// Play `combo` on `player`'s controller now, as its key would.
+  pub fn play_combo(&mut self, player: u8, combo: &str) -> Result<(), JsValue> {
+    let combo = Combo::from_text(combo).map_err(FlemuError::from)?;
+    if device_for_port(player).is_none() || combo.is_empty() {
+      return Err(invalid(format!(
+        "can't play an empty combo on player {}",
+        player
+      )));
+    }
+    self.cpu.bus.joypads[player as usize].play_combo(&combo);
+    Ok(())
+  }
+
+  /// Read the connected gamepads and press or release controller buttons
+  /// to match; the first pad drives port 0, the next port 1. Meant to be
+  /// called once per frame. Only buttons whose pad state changed are
+  /// touched, so the keyboard can drive the same controller.
+  pub fn poll_gamepads(&mut self) -> Result<(), JsValue> {
+    let pads = connected_gamepads()?;
+    self.hold_gamepads(&pads);
+    Ok(())
+  }
+
+  /// `poll_gamepads` for an emulator in a worker, which can't read them:
+  /// the connected pads as the page saw them, an array of `{ buttons,
+  /// axes }` with whether each button is pressed and each axis' position.
+  pub fn set_gamepads(&mut self, pads: JsValue) -> Result<(), JsValue> {
+    let pads = gamepad_snapshots(&pads)?;
+    self.hold_gamepads(&pads);
+    Ok(())
+  }
+
+  /// The first input held on any gamepad, e.g. "button-3" or "axis-1+",
+  /// for "press a button" remapping prompts.
+  pub fn active_gamepad_input(&self) -> Result<Option<String>, JsValue> {
+    Ok(connected_gamepads()?.iter().find_map(|(buttons, axes)| {
+      let pressed = buttons.iter().position(|&pressed| pressed);
+      let axis = axes.iter().position(|axis| axis.abs() >= AXIS_THRESHOLD);
+      match (pressed, axis) {
+        (Some(index), _) => Some(GamepadInput::Button(index as u8).id()),
+        (None, Some(index)) if axes[index] < 0.0 => {
+          Some(GamepadInput::AxisNegative(index as u8).id())
+        }
+        (None, Some(index)) => Some(GamepadInput::AxisPositive(index as u8).id()),
+        (None, None) => None,
+      }
+    }))
+  }
+
+  /// Gamepad layout: `[{ button, input }]`, a button possibly listed more
+  /// than once.
+  pub fn gamepad_bindings(&self) -> JsValue {
+    self
+      .bindings
+      .gamepad_iter()
+      .map(|(button, input)| {
+        js_object(&[("button", button.id.into()), ("input", input.id().into())])
+      })
+      .collect::<Array>()
+      .into()
+  }
+
+  /// Make a gamepad input (as from `active_gamepad_input`) press `button`.
+  /// Returns false for an unknown button or input.
+  pub fn bind_gamepad(&mut self, button: &str, input: &str) -> bool {
+    match GamepadInput::from_id(input) {
+      Some(input) => self.bindings.bind_gamepad(button, input),
+      None => false,
+    }
+  }
+
+  pub fn unbind_gamepad(&mut self, button: &str) {
+    self.bindings.unbind_gamepad(button);
+  }
+
+  /// A copy of the current bindings.
+  pub fn key_map(&self) -> KeyMap {
+    KeyMap {
+      bindings: self.bindings.clone(),
+    }
+  }
+
+  /// Replace every binding.
+  pub fn set_key_map(&mut self, map: &KeyMap) {
+    self.bindings = map.bindings.clone();
+  }
+
+  /// Button, hotkey and gamepad bindings as text, for the page to persist.
+  pub fn input_config(&self) -> String {
+    self.bindings.to_config()
+  }
+
+  /// Replace every binding with a saved `input_config`.
+  pub fn load_input_config(&mut self, config: &str) -> Result<(), JsValue> {
+    self.bindings = Bindings::from_config(config).map_err(FlemuError::from)?;
+    Ok(())
+  }
+}
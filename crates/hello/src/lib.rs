@@ -1,14 +1,44 @@
-#![feature(once_cell)] // 1.53.0-nightly (2021-04-01 d474075a8f28ae9a410e)
-use crate::nes::cpu::Mem;
-use crate::nes::cpu::{read_screen_state, render_screen};
-use kurbo::*;
-use piet::*;
-use piet_web::*;
-use rand::Rng;
-use std::{lazy::SyncLazy, sync::Mutex};
+use crate::error::FlemuError;
+use crate::gif::FrameHistory;
+use crate::input::{Bindings, GamepadInput, Hotkey, HotkeyState};
+use crate::nes::achievements;
+use crate::nes::apu::MixerInput;
+use crate::nes::attract::Attract;
+use crate::nes::bus::Mem;
+use crate::nes::cartridge::Rom;
+use crate::nes::combo::{Combo, ComboRecorder};
+use crate::nes::cpu::{CpuState, CPU};
+use crate::nes::debugger::{self, Breakpoints, StopReason, Symbols};
+use crate::nes::desync::ChecksumTrace;
+use crate::nes::game_db::{Game, GameDb, Peripheral};
+use crate::nes::joypad::{Joypad, JoypadButton};
+use crate::nes::movie::{self, Movie};
+use crate::nes::netplay::{self, Message, Netplay, NetplayError};
+use crate::nes::nsf::{Nsf, NsfPlayer};
+use crate::nes::palette::{Palette, PalettePreset};
+use crate::nes::ppu::Frame;
+use crate::nes::rewind::Rewind;
+use crate::nes::rollback::RollbackBuffer;
+use crate::nes::run_ahead::RunAhead;
+use crate::nes::savestate::StateWriter;
+use crate::nes::self_test;
+use crate::nes::splash;
+use crate::nes::time_travel::TimeTravel;
+use crate::nes::timing::Timing;
+use crate::perf::{AudioQueue, PerfCounters, PerfMeter};
+use crate::rng::SeededRng;
+use crate::video_dump::VideoDump;
+use crate::wav::WavRecorder;
+use crate::webgl::{Aspect, Filter, Renderer, VideoOptions};
+use js_sys::{Array, Float32Array, Function, Object, Promise, Reflect, Uint8Array, JSON};
+use log::{info, LevelFilter};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::Rc;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
-use web_sys::{window, CanvasRenderingContext2d};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{window, Gamepad, GamepadButton, HtmlCanvasElement, Response, RtcDataChannel};
 
 // use std::time::Duration;
 // use wasm_timer::sleep;
@@ -21,24 +51,220 @@ use gloo_events::EventListener;
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
-static CPU: SyncLazy<Mutex<nes::cpu::CPU>> = SyncLazy::new(|| Mutex::new(nes::cpu::CPU::new()));
-
 // Import the `window.alert` function from the Web.
 #[wasm_bindgen]
 extern "C" {
   fn alert(s: &str);
-  // Use `js_namespace` here to bind `console.log(..)` instead of just
-  // `log(..)`
-  #[wasm_bindgen(js_namespace = console)]
-  fn log(s: &str);
+
+  // in pages and workers both, unlike window.performance
+  #[wasm_bindgen(js_namespace = performance, js_name = now)]
+  fn performance_now() -> f64;
+
+  // fetch, in pages and workers both too
+  fn fetch(url: &str) -> Promise;
+}
+
+/// Change log verbosity at runtime: "off", "error", "warn", "info",
+/// "debug" or "trace".
+#[wasm_bindgen]
+pub fn set_log_level(level: &str) -> Result<(), JsValue> {
+  let level = level
+    .parse::<LevelFilter>()
+    .map_err(|_| invalid(format!("unknown log level {}", level)))?;
+  logger::set_level(level);
+  Ok(())
+}
+
+/// Every emulated input device and its buttons, so frontends can build
+/// remapping UIs: `[{ id, name, buttons: [{ id, name, icon, bit }] }]`.
+#[wasm_bindgen]
+pub fn input_devices() -> JsValue {
+  input::devices()
+    .iter()
+    .map(|device| {
+      let buttons: Array = device
+        .buttons
+        .iter()
+        .map(|button| {
+          js_object(&[
+            ("id", button.id.into()),
+            ("name", button.name.into()),
+            ("icon", button.icon.into()),
+            ("bit", button.bit.into()),
+          ])
+        })
+        .collect();
+      js_object(&[
+        ("id", device.id.into()),
+        ("name", device.name.into()),
+        ("buttons", buttons.into()),
+      ])
+    })
+    .collect::<Array>()
+    .into()
+}
+
+/// Built-in palettes for `Emulator::set_palette`: `[{ id, name }]`.
+#[wasm_bindgen]
+pub fn palette_presets() -> JsValue {
+  PalettePreset::ALL
+    .iter()
+    .map(|preset| js_object(&[("id", preset.id().into()), ("name", preset.name().into())]))
+    .collect::<Array>()
+    .into()
+}
+
+/// Post-processing for `Emulator::set_video_filter`: `[{ id, name }]`.
+#[wasm_bindgen]
+pub fn video_filters() -> JsValue {
+  Filter::ALL
+    .iter()
+    .map(|filter| js_object(&[("id", filter.id().into()), ("name", filter.name().into())]))
+    .collect::<Array>()
+    .into()
+}
+
+/// Pixel shapes for `Emulator::set_aspect_ratio`: `[{ id, name }]`.
+#[wasm_bindgen]
+pub fn aspect_ratios() -> JsValue {
+  Aspect::ALL
+    .iter()
+    .map(|aspect| js_object(&[("id", aspect.id().into()), ("name", aspect.name().into())]))
+    .collect::<Array>()
+    .into()
+}
+
+/// What `Emulator::set_channel_gain` and friends take: `[{ id, name }]`,
+/// the five APU channels and "expansion".
+#[wasm_bindgen]
+pub fn mixer_inputs() -> JsValue {
+  MixerInput::ALL
+    .iter()
+    .map(|input| js_object(&[("id", input.id().into()), ("name", input.name().into())]))
+    .collect::<Array>()
+    .into()
 }
 
-macro_rules! console_log {
-	// Note that this is using the `log` function imported above during
-	// `bare_bones`
-	($($t:tt)*) => (log(&format_args!($($t)*).to_string()))
+/// Run the built-in CPU/PPU/APU checks on a scratch machine:
+/// `{ passed, text, results: [{ component, name, error }] }`, `error` null
+/// for checks that passed.
+#[wasm_bindgen]
+pub fn self_test() -> JsValue {
+  let report = self_test::run();
+  let results: Array = report
+    .results
+    .iter()
+    .map(|result| {
+      js_object(&[
+        ("component", result.component.into()),
+        ("name", result.name.into()),
+        (
+          "error",
+          result.error.as_deref().map_or(JsValue::NULL, JsValue::from),
+        ),
+      ])
+    })
+    .collect();
+  js_object(&[
+    ("passed", report.passed().into()),
+    ("text", report.to_text().into()),
+    ("results", results.into()),
+  ])
 }
 
+// pressed buttons and axis positions of one gamepad
+type GamepadState = (Vec<bool>, Vec<f64>);
+
+/// State of each connected gamepad, in index order.
+fn connected_gamepads() -> Result<Vec<GamepadState>, JsValue> {
+  // workers can't see gamepads, the page sends them with `set_gamepads`
+  let window =
+    window().ok_or_else(|| invalid("gamepads are only readable from the page".to_string()))?;
+  let pads = window.navigator().get_gamepads()?;
+  Ok(
+    pads
+      .iter()
+      .filter_map(|pad| pad.dyn_into::<Gamepad>().ok())
+      .filter(|pad| pad.connected())
+      .map(|pad| {
+        let buttons = pad
+          .buttons()
+          .iter()
+          .map(|button| button.unchecked_into::<GamepadButton>().pressed())
+          .collect();
+        let axes = pad
+          .axes()
+          .iter()
+          .map(|axis| axis.as_f64().unwrap_or(0.0))
+          .collect();
+        (buttons, axes)
+      })
+      .collect(),
+  )
+}
+
+// `set_gamepads`' `{ buttons, axes }` snapshots
+fn gamepad_snapshots(pads: &JsValue) -> Result<Vec<GamepadState>, JsValue> {
+  let field = |pad: &JsValue, name: &str| -> Result<Array, JsValue> {
+    Reflect::get(pad, &name.into())?
+      .dyn_into::<Array>()
+      .map_err(|_| invalid(format!("gamepad {} should be an array", name)))
+  };
+  let pads = pads
+    .dyn_ref::<Array>()
+    .ok_or_else(|| invalid("gamepads should be an array".to_string()))?;
+  pads
+    .iter()
+    .map(|pad| {
+      let buttons = field(&pad, "buttons")?
+        .iter()
+        .map(|pressed| pressed.is_truthy())
+        .collect();
+      let axes = field(&pad, "axes")?
+        .iter()
+        .map(|axis| axis.as_f64().unwrap_or(0.0))
+        .collect();
+      Ok((buttons, axes))
+    })
+    .collect()
+}
+
+// press or release the buttons in `mask`, `ButtonDescriptor::bit`s, the
+// turbo ones above the shift register's eight
+fn press(pad: &mut Joypad, mask: u16, pressed: bool) {
+  pad.set_button_pressed_status(JoypadButton::from_bits_truncate(mask as u8), pressed);
+  pad.set_turbo_pressed_status(JoypadButton::from_bits_truncate((mask >> 8) as u8), pressed);
+}
+
+// why a run stopped as `{ reason, ... }`, or the fault that stopped it
+// thrown as a "cpu-fault" error
+fn js_stop_reason(cpu: &CPU, stop: StopReason) -> Result<JsValue, JsValue> {
+  if let (StopReason::Halted, Some(fault)) = (stop, cpu.fault()) {
+    return Err(FlemuError::from(fault).into());
+  }
+  let reason = ("reason", stop.id().into());
+  Ok(match stop {
+    StopReason::Breakpoint { pc } => js_object(&[reason, ("pc", pc.into())]),
+    StopReason::Watchpoint(hit) => js_object(&[
+      reason,
+      ("address", hit.addr.into()),
+      ("value", hit.value.into()),
+      ("access", hit.access.id().into()),
+    ]),
+    _ => js_object(&[reason]),
+  })
+}
+
+fn js_object(fields: &[(&str, JsValue)]) -> JsValue {
+  let object = Object::new();
+  for (key, value) in fields {
+    // only fails on frozen objects or proxies
+    Reflect::set(&object, &JsValue::from_str(key), value).unwrap();
+  }
+  object.into()
+}
+
+#[allow(dead_code)]
 fn request_animation_frame(f: &Closure<dyn FnMut()>) {
   window()
     .unwrap()
@@ -46,110 +272,1232 @@ fn request_animation_frame(f: &Closure<dyn FnMut()>) {
     .expect("should register `requestAnimationFrame` OK");
 }
 
+/// Standard controller buttons, for `KeyMap`, in shift register order
+/// like `input::STANDARD_CONTROLLER`.
 #[wasm_bindgen]
-pub fn make_nes(canvas_id: &str) -> Result<(), JsValue> {
-  let mut cpu = CPU.lock().unwrap();
-  cpu.reset();
-
-  let game_code = vec![
-    0x20, 0x06, 0x06, 0x20, 0x38, 0x06, 0x20, 0x0d, 0x06, 0x20, 0x2a, 0x06, 0x60, 0xa9, 0x02, 0x85,
-    0x02, 0xa9, 0x04, 0x85, 0x03, 0xa9, 0x11, 0x85, 0x10, 0xa9, 0x10, 0x85, 0x12, 0xa9, 0x0f, 0x85,
-    0x14, 0xa9, 0x04, 0x85, 0x11, 0x85, 0x13, 0x85, 0x15, 0x60, 0xa5, 0xfe, 0x85, 0x00, 0xa5, 0xfe,
-    0x29, 0x03, 0x18, 0x69, 0x02, 0x85, 0x01, 0x60, 0x20, 0x4d, 0x06, 0x20, 0x8d, 0x06, 0x20, 0xc3,
-    0x06, 0x20, 0x19, 0x07, 0x20, 0x20, 0x07, 0x20, 0x2d, 0x07, 0x4c, 0x38, 0x06, 0xa5, 0xff, 0xc9,
-    0x77, 0xf0, 0x0d, 0xc9, 0x64, 0xf0, 0x14, 0xc9, 0x73, 0xf0, 0x1b, 0xc9, 0x61, 0xf0, 0x22, 0x60,
-    0xa9, 0x04, 0x24, 0x02, 0xd0, 0x26, 0xa9, 0x01, 0x85, 0x02, 0x60, 0xa9, 0x08, 0x24, 0x02, 0xd0,
-    0x1b, 0xa9, 0x02, 0x85, 0x02, 0x60, 0xa9, 0x01, 0x24, 0x02, 0xd0, 0x10, 0xa9, 0x04, 0x85, 0x02,
-    0x60, 0xa9, 0x02, 0x24, 0x02, 0xd0, 0x05, 0xa9, 0x08, 0x85, 0x02, 0x60, 0x60, 0x20, 0x94, 0x06,
-    0x20, 0xa8, 0x06, 0x60, 0xa5, 0x00, 0xc5, 0x10, 0xd0, 0x0d, 0xa5, 0x01, 0xc5, 0x11, 0xd0, 0x07,
-    0xe6, 0x03, 0xe6, 0x03, 0x20, 0x2a, 0x06, 0x60, 0xa2, 0x02, 0xb5, 0x10, 0xc5, 0x10, 0xd0, 0x06,
-    0xb5, 0x11, 0xc5, 0x11, 0xf0, 0x09, 0xe8, 0xe8, 0xe4, 0x03, 0xf0, 0x06, 0x4c, 0xaa, 0x06, 0x4c,
-    0x35, 0x07, 0x60, 0xa6, 0x03, 0xca, 0x8a, 0xb5, 0x10, 0x95, 0x12, 0xca, 0x10, 0xf9, 0xa5, 0x02,
-    0x4a, 0xb0, 0x09, 0x4a, 0xb0, 0x19, 0x4a, 0xb0, 0x1f, 0x4a, 0xb0, 0x2f, 0xa5, 0x10, 0x38, 0xe9,
-    0x20, 0x85, 0x10, 0x90, 0x01, 0x60, 0xc6, 0x11, 0xa9, 0x01, 0xc5, 0x11, 0xf0, 0x28, 0x60, 0xe6,
-    0x10, 0xa9, 0x1f, 0x24, 0x10, 0xf0, 0x1f, 0x60, 0xa5, 0x10, 0x18, 0x69, 0x20, 0x85, 0x10, 0xb0,
-    0x01, 0x60, 0xe6, 0x11, 0xa9, 0x06, 0xc5, 0x11, 0xf0, 0x0c, 0x60, 0xc6, 0x10, 0xa5, 0x10, 0x29,
-    0x1f, 0xc9, 0x1f, 0xf0, 0x01, 0x60, 0x4c, 0x35, 0x07, 0xa0, 0x00, 0xa5, 0xfe, 0x91, 0x00, 0x60,
-    0xa6, 0x03, 0xa9, 0x00, 0x81, 0x10, 0xa2, 0x00, 0xa9, 0x01, 0x81, 0x10, 0x60, 0xa6, 0xff, 0xea,
-    0xea, 0xca, 0xd0, 0xfb, 0x60,
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Button {
+  A,
+  B,
+  Select,
+  Start,
+  Up,
+  Down,
+  Left,
+  Right,
+}
+
+impl Button {
+  const ALL: [Button; 8] = [
+    Button::A,
+    Button::B,
+    Button::Select,
+    Button::Start,
+    Button::Up,
+    Button::Down,
+    Button::Left,
+    Button::Right,
   ];
 
-  cpu.load(game_code);
+  /// Same ids as `input_devices`.
+  fn id(self) -> &'static str {
+    input::STANDARD_CONTROLLER.buttons[self as usize].id
+  }
 
-  // get canvas and webgl context
-  let window = window().unwrap();
-  let document = web_sys::window().unwrap().document().unwrap();
-  let wasm_div = document.get_element_by_id("wasm").unwrap();
-  let canvas = document.get_element_by_id(canvas_id).unwrap();
-  let canvas: web_sys::HtmlCanvasElement = canvas.dyn_into::<web_sys::HtmlCanvasElement>()?;
+  fn from_id(id: &str) -> Option<Button> {
+    Button::ALL.iter().copied().find(|button| button.id() == id)
+  }
+}
 
-  let context = canvas
-    .get_context("2d")?
-    .unwrap()
-    .dyn_into::<CanvasRenderingContext2d>()?;
+/// Key, hotkey and gamepad bindings as a value the page can edit, e.g. on
+/// a controls screen, then hand to `Emulator::set_key_map`.
+#[wasm_bindgen]
+#[derive(Clone)]
+pub struct KeyMap {
+  bindings: Bindings,
+}
+
+impl Default for KeyMap {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[wasm_bindgen]
+impl KeyMap {
+  /// The default layout.
+  #[wasm_bindgen(constructor)]
+  pub fn new() -> KeyMap {
+    KeyMap {
+      bindings: Bindings::default(),
+    }
+  }
+
+  /// Nothing bound.
+  pub fn empty() -> KeyMap {
+    KeyMap {
+      bindings: Bindings::empty(),
+    }
+  }
 
-  let mut rc = WebRenderContext::new(context, window);
+  /// Bind a KeyboardEvent.code to a button of the first controller.
+  pub fn set_binding(&mut self, key: &str, button: Button) {
+    self.set_player_binding(0, key, button);
+  }
 
-  // let whole_board = Rect::new(0., 0., 32 as f64, 32 as f64);
-  // rc.clear(whole_board, Color::rgb8(0xf4,0xf4,0xf4));
+  /// Returns false for an unknown port.
+  pub fn set_player_binding(&mut self, port: u8, key: &str, button: Button) -> bool {
+    self.bindings.bind(port, button.id(), key)
+  }
 
-  // 32 cols with 3bytes per point, and 32 rows
-  let mut screen_state = [0 as u8; 32 * 3 * 32];
+  pub fn clear_binding(&mut self, key: &str) {
+    self.bindings.unbind_key(key);
+  }
 
-  // map screen with above 32x32
-  let mut cscr = Rect::new(0., 0., 320.0, 320.0);
-  // clear above
-  rc.clear(cscr, Color::rgb8(0xff, 0xff, 0xff));
+  /// The first controller's button a key drives, if any.
+  pub fn binding(&self, key: &str) -> Option<Button> {
+    match self.bindings.button_for_key(key) {
+      Some((0, button)) => Button::from_id(button.id),
+      _ => None,
+    }
+  }
+
+  /// `{ keys: [{ port, button, key }], hotkeys: [{ hotkey, key }],
+  /// gamepad: [{ button, input }], combos: [{ port, key, combo }] }` as
+  /// JSON.
+  pub fn to_json(&self) -> String {
+    let keys: Array = self
+      .bindings
+      .iter()
+      .map(|b| {
+        js_object(&[
+          ("port", b.port.into()),
+          ("button", b.button.id.into()),
+          ("key", b.key.as_str().into()),
+        ])
+      })
+      .collect();
+    let hotkeys: Array = self
+      .bindings
+      .hotkey_iter()
+      .map(|(hotkey, key)| {
+        js_object(&[("hotkey", hotkey.id().into()), ("key", key.as_str().into())])
+      })
+      .collect();
+    let gamepad: Array = self
+      .bindings
+      .gamepad_iter()
+      .map(|(button, input)| {
+        js_object(&[("button", button.id.into()), ("input", input.id().into())])
+      })
+      .collect();
+    let combos: Array = self
+      .bindings
+      .combo_iter()
+      .map(|c| {
+        js_object(&[
+          ("port", c.port.into()),
+          ("key", c.key.as_str().into()),
+          ("combo", c.combo.to_text().into()),
+        ])
+      })
+      .collect();
+    let map = js_object(&[
+      ("keys", keys.into()),
+      ("hotkeys", hotkeys.into()),
+      ("gamepad", gamepad.into()),
+      ("combos", combos.into()),
+    ]);
+    // plain objects of strings and numbers always stringify
+    JSON::stringify(&map).unwrap().into()
+  }
+
+  /// Parse `to_json` output. Missing lists are left unbound; an unknown
+  /// port, button, hotkey or input is an error.
+  pub fn from_json(json: &str) -> Result<KeyMap, JsValue> {
+    let map = JSON::parse(json)?;
+    let mut bindings = Bindings::empty();
+    for entry in js_list(&map, "keys")? {
+      let port = js_field(&entry, "port")?.as_f64().unwrap_or(-1.0);
+      let button = js_string(&entry, "button")?;
+      let key = js_string(&entry, "key")?;
+      if !(0.0..=255.0).contains(&port) || !bindings.bind(port as u8, &button, &key) {
+        return Err(invalid(format!("can't bind {} to {}", key, button)));
+      }
+    }
+    for entry in js_list(&map, "hotkeys")? {
+      let id = js_string(&entry, "hotkey")?;
+      let hotkey = Hotkey::from_id(&id).ok_or_else(|| invalid(format!("unknown hotkey {}", id)))?;
+      bindings.bind_hotkey(hotkey, &js_string(&entry, "key")?);
+    }
+    for entry in js_list(&map, "gamepad")? {
+      let button = js_string(&entry, "button")?;
+      let id = js_string(&entry, "input")?;
+      match GamepadInput::from_id(&id) {
+        Some(input) if bindings.bind_gamepad(&button, input) => {}
+        _ => return Err(invalid(format!("can't bind {} to {}", id, button))),
+      }
+    }
+    for entry in js_list(&map, "combos")? {
+      let port = js_field(&entry, "port")?.as_f64().unwrap_or(-1.0);
+      let key = js_string(&entry, "key")?;
+      let combo = Combo::from_text(&js_string(&entry, "combo")?).map_err(FlemuError::from)?;
+      if !(0.0..=255.0).contains(&port) || !bindings.bind_combo(port as u8, &key, combo) {
+        return Err(invalid(format!("can't bind {} to a combo", key)));
+      }
+    }
+    Ok(KeyMap { bindings })
+  }
+}
+
+fn invalid(message: String) -> JsValue {
+  FlemuError::InvalidArgument(message).into()
+}
+
+fn js_field(object: &JsValue, field: &str) -> Result<JsValue, JsValue> {
+  Reflect::get(object, &JsValue::from_str(field))
+}
 
-  let mut rng = rand::thread_rng();
+fn js_string(object: &JsValue, field: &str) -> Result<String, JsValue> {
+  js_field(object, field)?
+    .as_string()
+    .ok_or_else(|| invalid(format!("{} should be a string", field)))
+}
+
+fn js_list(object: &JsValue, field: &str) -> Result<Vec<JsValue>, JsValue> {
+  let list = js_field(object, field)?;
+  if list.is_undefined() {
+    return Ok(Vec::new());
+  }
+  if !Array::is_array(&list) {
+    return Err(invalid(format!("{} should be a list", field)));
+  }
+  Ok(Array::from(&list).iter().collect())
+}
+
+// where battery saves go, next to the ROM hash
+const BATTERY_SLOT: &str = "sram";
+
+// a state every other frame for the last 30 seconds
+const REWIND_INTERVAL: u32 = 2;
+const REWIND_SECONDS: u32 = 30;
+const FRAMES_PER_SECOND: u32 = 60;
+// every other frame of the last 10 seconds, for export_gif
+const GIF_INTERVAL: u32 = 2;
+const GIF_SECONDS: u32 = 10;
+
+// frames kept around for rollback netcode
+const ROLLBACK_FRAMES: usize = 8;
+// debugger step-back window: 32 checkpoints 1024 instructions apart, a
+// bit over a frame
+const TIME_TRAVEL_CHECKPOINTS: usize = 32;
+const TIME_TRAVEL_INTERVAL: u64 = 1024;
+// slowest and fastest set_speed takes
+const MIN_SPEED: f64 = 0.25;
+const MAX_SPEED: f64 = 8.0;
+// milliseconds of each animation frame turbo mode spends emulating
+const TURBO_BUDGET_MS: f64 = 12.0;
 
-  // keyboard event??
-  let on_keydown = EventListener::new(&canvas, "keydown", move |event| {
-    let keyboard_event = event.clone().dyn_into::<web_sys::KeyboardEvent>().unwrap();
-    let mut event_string = String::from("");
-    event_string.push_str(&event.type_());
-    event_string.push_str(&" : ");
-    event_string.push_str(&keyboard_event.key());
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MovieMode {
+  Off,
+  Recording,
+  Playing,
+}
 
-    unsafe {
-      console_log!("key event, {}", event_string);
+impl MovieMode {
+  fn id(self) -> &'static str {
+    match self {
+      MovieMode::Off => "off",
+      MovieMode::Recording => "recording",
+      MovieMode::Playing => "playing",
     }
-  });
-  on_keydown.forget();
+  }
+}
+
+// callbacks subscribed with the `on_*` methods
+#[derive(Default)]
+struct Hooks {
+  frame: Option<Function>,
+  audio: Option<Function>,
+  breakpoint: Option<Function>,
+  sram_write: Option<Function>,
+  achievement_frame: Option<Function>,
+  // what achievement_frame threw, for the run to throw once it's done
+  achievement_error: Option<JsValue>,
+  // the bus' battery_writes when on_sram_write was last called
+  battery_writes: u32,
+}
 
-  // keyboard event??
-  let on_keyup = EventListener::new(&canvas, "keyup", move |event| {
-    let keyboard_event = event.clone().dyn_into::<web_sys::KeyboardEvent>().unwrap();
-    let mut event_string = String::from("");
-    event_string.push_str(&event.type_());
-    event_string.push_str(&" : ");
-    event_string.push_str(&keyboard_event.key());
+// a netplay session and the data channel it runs over
+struct NetplaySession {
+  netplay: Netplay,
+  channel: RtcDataChannel,
+  // what came in since the last netplay_frame
+  inbox: Rc<RefCell<VecDeque<Vec<u8>>>>,
+  _on_message: EventListener,
+}
 
-    unsafe {
-      console_log!("key event, {}", event_string);
+impl NetplaySession {
+  fn send(&self, message: &Message) -> Result<(), JsValue> {
+    self.channel.send_with_u8_array(&message.to_bytes())
+  }
+}
+
+// the last movie recorded or loaded, and how far into it emulation is
+struct MovieSession {
+  movie: Movie,
+  mode: MovieMode,
+  // the next frame to record or play
+  frame: usize,
+  // the PPU's frame_count when the last frame's input went in, so a frame
+  // a breakpoint splits in two counts once
+  at: Option<u64>,
+  // and when the first one did, to find a loaded state's place in it
+  first: Option<u64>,
+  // reset was pressed while recording, it goes into the next frame
+  reset: bool,
+}
+
+impl MovieSession {
+  // a state from frame `frame_count` was loaded
+  fn rerecord(&mut self, frame_count: u64) {
+    let index = match self.first {
+      Some(first) if self.mode == MovieMode::Recording && frame_count >= first => {
+        (frame_count - first) as usize
+      }
+      _ => self.movie.len() + 1,
+    };
+    if index > self.movie.len() {
+      self.mode = MovieMode::Off;
+      return;
     }
-  });
+    self.movie.frames.truncate(index);
+    self.movie.rerecords += 1;
+    self.frame = index;
+    self.at = None;
+    self.reset = false;
+  }
+}
+
+/// One console: CPU, and through its bus the PPU, APU and cartridge, plus
+/// what the frontend attached to it. Each instance is independent, so a
+/// page can run several side by side.
+#[wasm_bindgen]
+pub struct Emulator {
+  cpu: nes::cpu::CPU,
+  rollback: RollbackBuffer<nes::cpu::CPU>,
+  rewind: Rewind,
+  time_travel: TimeTravel,
+  breakpoints: Breakpoints,
+  symbols: Symbols,
+  bindings: Bindings,
+  hotkeys: HotkeyState,
+  // keys of combos held down, so auto-repeat doesn't restart them
+  combo_keys: Vec<String>,
+  // the player being recorded into a combo
+  combo_recorder: Option<(u8, ComboRecorder)>,
+  // controller buttons each port's gamepad held at the last poll
+  gamepad_held: [u16; input::PORTS as usize],
+  palette: Palette,
+  // frame picker, callback, and whether it wants PNGs
+  video_dump: Option<(VideoDump, Function, bool)>,
+  // every `GIF_INTERVAL`th frame shown lately
+  recent_frames: FrameHistory,
+  audio_capture: Option<WavRecorder>,
+  checksum_trace: Option<ChecksumTrace>,
+  splash: Frame,
+  splash_tick: u64,
+  renderer: Option<Renderer>,
+  video_filter: Filter,
+  video_options: VideoOptions,
+  speed: f64,
+  turbo: bool,
+  run_ahead: RunAhead,
+  // the cartridge as inserted, for movies that start from power-on
+  rom: Option<Rom>,
+  // playing an NSF rather than running a cartridge
+  nsf: Option<NsfPlayer>,
+  game_db: GameDb,
+  // the database's entry for the cartridge and the header fields it fixed
+  game: Option<(Game, Vec<&'static str>)>,
+  movie: Option<MovieSession>,
+  tas: bool,
+  // the cartridges' demos, and the one playing
+  attract: Attract,
+  // the last frame didn't read the controllers, and how many haven't
+  lag_frame: bool,
+  lag_frames: u32,
+  // the last battery RAM write to IndexedDB failed, try again next time
+  battery_retry: Rc<Cell<bool>>,
+  hooks: Hooks,
+  perf: PerfMeter,
+  netplay: Option<NetplaySession>,
+}
 
-  // listen forever
-  on_keyup.forget();
+impl Default for Emulator {
+  fn default() -> Self {
+    Self::new()
+  }
+}
 
-  // run the game cycle
-  cpu.step_run(move |cpu| {
-    unsafe {
-      console_log!("Running inside {}", canvas_id);
+impl Emulator {
+  fn screen(&mut self) -> &Frame {
+    if self.cpu.bus.mapper().is_some() {
+      return &self.cpu.bus.ppu.frame;
     }
+    splash::draw(&mut self.splash, self.splash_tick);
+    self.splash_tick += 1;
+    &self.splash
+  }
 
-    if read_screen_state(cpu, &mut screen_state) {
-      // map screen_state to cscr
-      render_screen(&mut rc, &mut screen_state)
+  // press and release what changed on the pads since the last time
+  fn hold_gamepads(&mut self, pads: &[GamepadState]) {
+    for port in 0..self.gamepad_held.len() {
+      let held = pads.get(port).map_or(0, |(buttons, axes)| {
+        self.bindings.gamepad_buttons(buttons, axes)
+      });
+      let changed = held ^ self.gamepad_held[port];
+      if changed != 0 {
+        self.attract_input();
+      }
+      let pad = &mut self.cpu.bus.joypads[port];
+      press(pad, changed & held, true);
+      press(pad, changed & !held, false);
+      self.gamepad_held[port] = held;
     }
+  }
 
-    // ::std::thread::sleep(std::time::Duration::new(0, 70_000));
-    // ::std::thread::sleep(std::time::Duration::new(0, 70_000));
-    // sleep(Duration::new(0, 70_000));
-  });
-  Ok(())
+  // one frame, as `run_frame`
+  fn step_frame(&mut self) -> StopReason {
+    self.attract_frame();
+    self.movie_input();
+    if let Some((player, recorder)) = &mut self.combo_recorder {
+      recorder.record(&self.cpu.bus.joypads[*player as usize]);
+    }
+    self.trace_checksum();
+    // NSF code only makes sense with the player calling it, breakpoints
+    // or not
+    let stop = if let Some(player) = &mut self.nsf {
+      if player.run_frame(&mut self.cpu) {
+        StopReason::Done
+      } else {
+        StopReason::Halted
+      }
+    } else if self.breakpoints.is_empty() && self.cpu.bus.watchpoints.watchpoints().is_empty() {
+      if self.cpu.run_frame() {
+        StopReason::Done
+      } else {
+        StopReason::Halted
+      }
+    } else {
+      let frame = self.cpu.bus.ppu.frame_count;
+      debugger::run_until(
+        &mut self.cpu,
+        &self.breakpoints,
+        u32::MAX,
+        |cpu| cpu.step_instruction(),
+        |cpu| cpu.bus.ppu.frame_count != frame,
+      )
+    };
+    // a demo isn't the player's doing
+    let demo = self.attract.playing();
+    if stop == StopReason::Done && !demo {
+      self.achievement_frame();
+    }
+    if let Some(recorder) = &mut self.audio_capture {
+      recorder.push(&self.cpu.bus.apu.take_captured());
+    }
+    if stop == StopReason::Done
+      && !self.cpu.bus.ppu.skip_rendering
+      && self.cpu.bus.ppu.frame_count % GIF_INTERVAL as u64 == 0
+    {
+      self.recent_frames.push(&self.cpu.bus.ppu.frame);
+    }
+    if stop == StopReason::Done && !demo && self.rewind.tick() {
+      let state = self.save_state();
+      self.rewind.push(state, &self.cpu.bus.ppu.frame);
+    }
+    if stop == StopReason::Done {
+      self.lag_frame = !self.cpu.bus.take_input_polled();
+      self.lag_frames += self.lag_frame as u32;
+    }
+    // only frames that get seen are worth looking ahead from, and TAS work
+    // wants to see the frame it's on
+    if stop == StopReason::Done
+      && !self.cpu.bus.ppu.skip_rendering
+      && !self.tas
+      && self.nsf.is_none()
+    {
+      self.run_ahead.run(&mut self.cpu);
+    }
+    stop
+  }
+
+  // on_achievement_frame's callback, at the end of a frame
+  fn achievement_frame(&mut self) {
+    let hooks = &mut self.hooks;
+    if let Some(callback) = &hooks.achievement_frame {
+      let frame_count = JsValue::from(self.cpu.bus.ppu.frame_count as f64);
+      if let Err(error) = callback.call1(&JsValue::NULL, &frame_count) {
+        hooks.achievement_error.get_or_insert(error);
+      }
+    }
+  }
+
+  fn step_track(&mut self, by: i32) -> Result<u32, JsValue> {
+    let (track, tracks) = match &self.nsf {
+      Some(player) => (player.song() as i32, player.nsf().songs as i32),
+      None => return Err(invalid("no NSF loaded".to_string())),
+    };
+    let track = (track + by).rem_euclid(tracks) as u32;
+    self.select_track(track)?;
+    Ok(track)
+  }
+
+  // record the held buttons, or hold the movie's, once at the start of
+  // every frame
+  fn movie_input(&mut self) {
+    let session = match &mut self.movie {
+      Some(session) if session.mode != MovieMode::Off => session,
+      _ => return,
+    };
+    let frame_count = self.cpu.bus.ppu.frame_count;
+    if session.at == Some(frame_count) {
+      return;
+    }
+    session.at = Some(frame_count);
+    session.first = session.first.or(Some(frame_count));
+    if session.mode == MovieMode::Recording {
+      let commands = if session.reset { movie::RESET } else { 0 };
+      session.reset = false;
+      session.movie.record(&self.cpu, commands);
+    } else if !session.movie.play(session.frame, &mut self.cpu) {
+      info!("movie finished after {} frames", session.frame);
+      session.mode = MovieMode::Off;
+      return;
+    }
+    session.frame += 1;
+  }
+
+  // the console switched off and on again with the same cartridge, as
+  // movies from power-on start
+  fn power_on(&mut self, four_score: bool) -> Result<(), JsValue> {
+    let rom = self
+      .rom
+      .clone()
+      .ok_or_else(|| invalid("no cartridge inserted".to_string()))?;
+    let mut fresh = nes::cpu::CPU::new();
+    fresh.load_rom(rom).map_err(FlemuError::from)?;
+    fresh.rng = self.cpu.rng;
+    // savestates only carry a Four Score's state if one is plugged in
+    fresh.bus.set_four_score(four_score);
+    self.cpu.bus.set_four_score(four_score);
+    let mut w = StateWriter::new(fresh.bus.cartridge_checksum());
+    fresh.save_state(&mut w);
+    self.load_state(&w.finish())
+  }
+
+  // a cartridge `Cartridge::parse` accepted in place of the running one,
+  // into a console of its own that only takes over once it's in
+  fn insert(&mut self, cartridge: Cartridge) -> Result<(), FlemuError> {
+    let Cartridge { rom, mut nsf, game } = cartridge;
+    self.attract_input();
+    let mut cpu = nes::cpu::CPU::new();
+    // cartridges install their own BRK handler
+    cpu.halt_on_brk = false;
+    cpu.load_rom(rom.clone())?;
+    cpu.rng = self.cpu.rng;
+    cpu.bus.take_settings(&mut self.cpu.bus);
+    if let Some((game, _)) = &game {
+      match game.peripheral {
+        Some(Peripheral::Zapper) => cpu.bus.set_zapper(true),
+        Some(Peripheral::FourScore) => cpu.bus.set_four_score(true),
+        None => {}
+      }
+    }
+    if let Some(player) = &mut nsf {
+      let track = player.nsf().first_song;
+      player.start(&mut cpu, track);
+    }
+    self.cpu = cpu;
+    self.rom = Some(rom);
+    self.nsf = nsf;
+    self.game = game;
+    self.time_travel.clear();
+    self.rewind.clear();
+    self.recent_frames.clear();
+    self.movie = None;
+    self.lag_frames = 0;
+    Ok(())
+  }
+
+  /// `load_rom` for native callers, with `rom` already parsed: the
+  /// error rather than a thrown one if it won't go in, the running game
+  /// left as it was.
+  pub fn insert_rom(&mut self, rom: Rom) -> Result<(), FlemuError> {
+    self.insert(Cartridge {
+      rom,
+      nsf: None,
+      game: None,
+    })
+  }
+
+  // `count` frames, drawing the last one
+  fn run_skipping(&mut self, count: u32) -> StopReason {
+    let mut stop = StopReason::Done;
+    for left in (0..count).rev() {
+      self.cpu.bus.ppu.skip_rendering = left > 0;
+      stop = self.step_frame();
+      if stop != StopReason::Done {
+        break;
+      }
+    }
+    stop
+  }
+
+  // frames until the budget is spent; the one expected to end past it is
+  // drawn
+  fn run_turbo(&mut self) -> StopReason {
+    let start = js_sys::Date::now();
+    let mut frames = 0;
+    loop {
+      let elapsed = js_sys::Date::now() - start;
+      let per_frame = if frames == 0 {
+        0.0
+      } else {
+        elapsed / frames as f64
+      };
+      let last = elapsed + 2.0 * per_frame >= TURBO_BUDGET_MS;
+      self.cpu.bus.ppu.skip_rendering = !last;
+      let stop = self.step_frame();
+      frames += 1;
+      if stop != StopReason::Done || last {
+        // roughly how far ahead of real time that was, for the sound
+        self.cpu.bus.apu.set_speed(frames as f64);
+        return stop;
+      }
+    }
+  }
+
+  // tell the hooks what the frames just run did, then return `stop`
+  // when and at which CPU cycle frames started running
+  fn perf_start(&self) -> (f64, u64) {
+    (performance_now(), self.cpu.cycles)
+  }
+
+  fn perf_record(&mut self, (start_ms, cycles): (f64, u64), frame: u64) {
+    let frames = (self.cpu.bus.ppu.frame_count - frame) as u32;
+    let times = self.cpu.bus.take_component_times();
+    let apu = &self.cpu.bus.apu;
+    let audio = AudioQueue {
+      fill: apu.sample_ring().len() as f64 / apu.sample_ring().capacity() as f64,
+      latency_ms: apu.measured_latency(),
+    };
+    self.perf.record(
+      start_ms,
+      performance_now(),
+      frames,
+      self.cpu.cycles - cycles,
+      times,
+      audio,
+    );
+  }
+
+  fn finish_frames(&mut self, stop: StopReason, frame: u64) -> Result<JsValue, JsValue> {
+    let frame_count = self.cpu.bus.ppu.frame_count;
+    if let Some(callback) = self.hooks.frame.clone() {
+      if frame_count != frame {
+        let rgba = self.frame_rgba();
+        callback.call2(
+          &JsValue::NULL,
+          &Uint8Array::from(&rgba[..]),
+          &JsValue::from(frame_count as f64),
+        )?;
+      }
+    }
+    if let Some(callback) = &self.hooks.audio {
+      let samples = self.cpu.bus.apu.take_samples();
+      if !samples.is_empty() {
+        callback.call1(&JsValue::NULL, &Float32Array::from(&samples[..]))?;
+      }
+    }
+    let writes = self.cpu.bus.battery_writes();
+    if let Some(callback) = &self.hooks.sram_write {
+      if writes != self.hooks.battery_writes && !self.attract.playing() {
+        if let Some(ram) = self.cpu.bus.battery_ram() {
+          callback.call1(&JsValue::NULL, &Uint8Array::from(ram))?;
+        }
+      }
+    }
+    self.hooks.battery_writes = writes;
+    if let Some(error) = self.hooks.achievement_error.take() {
+      return Err(error);
+    }
+    self.stopped(stop)
+  }
+
+  // `stop` for JS, after telling on_breakpoint about breakpoints and
+  // watchpoints
+  fn stopped(&self, stop: StopReason) -> Result<JsValue, JsValue> {
+    let reason = js_stop_reason(&self.cpu, stop)?;
+    if let Some(callback) = &self.hooks.breakpoint {
+      if matches!(
+        stop,
+        StopReason::Breakpoint { .. } | StopReason::Watchpoint(_)
+      ) {
+        callback.call1(&JsValue::NULL, &reason)?;
+      }
+    }
+    Ok(reason)
+  }
+
+  // the hash of the machine as a frame starts, when tracing
+  fn trace_checksum(&mut self) {
+    if let Some(trace) = &mut self.checksum_trace {
+      trace.record(&self.cpu);
+    }
+  }
+
+  // one netplay frame, snapshotted first for going back to
+  fn run_netplay_frame(&mut self, frame: u32, inputs: [u8; 2]) -> StopReason {
+    self.snapshot(frame);
+    self.trace_checksum();
+    // turbo presses come in `inputs` as they fired
+    for (joypad, &buttons) in self.cpu.bus.joypads.iter_mut().zip(&inputs) {
+      joypad.set_button_pressed_status(JoypadButton::all(), false);
+      joypad.set_turbo_pressed_status(JoypadButton::all(), false);
+      joypad.set_button_pressed_status(JoypadButton::from_bits_truncate(buttons), true);
+    }
+    if self.cpu.run_frame() {
+      StopReason::Done
+    } else {
+      StopReason::Halted
+    }
+  }
+
+  // `netplay_frame` with the session taken out of `self`
+  fn step_netplay(&mut self, session: &mut NetplaySession) -> Result<JsValue, JsValue> {
+    let messages: Vec<_> = session.inbox.borrow_mut().drain(..).collect();
+    for bytes in messages {
+      let message = Message::from_bytes(&bytes).map_err(FlemuError::from)?;
+      if let Some(state) = session.netplay.receive(message).map_err(FlemuError::from)? {
+        self.load_state(&state)?;
+        // the host's battery RAM isn't this side's to save
+        self.cpu.bus.take_battery_dirty();
+      }
+    }
+
+    if let Some(from) = session.netplay.take_rollback() {
+      // the sound of the frames run on a guess was heard already
+      let mut output = self.cpu.bus.apu.clone();
+      if !self.restore(from) {
+        return Err(FlemuError::from(NetplayError::TooLate { frame: from }).into());
+      }
+      let upto = session.netplay.frame();
+      for frame in from..upto {
+        let inputs = session.netplay.inputs(frame);
+        self.cpu.bus.ppu.skip_rendering = frame + 1 < upto;
+        if self.run_netplay_frame(frame, inputs) != StopReason::Done {
+          break;
+        }
+      }
+      self.cpu.bus.ppu.skip_rendering = false;
+      self.cpu.bus.apu.swap_output(&mut output);
+    }
+
+    while let Some(frame) = session.netplay.checksum_due() {
+      let checksum = if frame == session.netplay.frame() {
+        Some(netplay::checksum(&self.cpu))
+      } else {
+        self.rollback.get(frame).map(netplay::checksum)
+      };
+      if let Some(message) = session.netplay.checksum(frame, checksum) {
+        session.send(&message)?;
+      }
+    }
+    if let Some(frame) = session.netplay.desync() {
+      return Err(FlemuError::from(NetplayError::Desync { frame }).into());
+    }
+    if !session.netplay.ready() {
+      return Ok(js_object(&[("reason", "waiting".into())]));
+    }
+
+    // controller 1 has what the local keys and gamepad hold, turbo
+    // buttons as they fire this frame
+    let pad = &self.cpu.bus.joypads[0];
+    let (held, turbo) = (pad.held(), pad.turbo());
+    let buttons = pad.buttons().bits();
+    let frame = session.netplay.frame();
+    let (message, inputs) = session.netplay.advance(buttons);
+    session.send(&message)?;
+    let frame_count = self.cpu.bus.ppu.frame_count;
+    let stop = self.run_netplay_frame(frame, inputs);
+    if stop == StopReason::Done {
+      self.achievement_frame();
+    }
+    if let Some(recorder) = &mut self.audio_capture {
+      recorder.push(&self.cpu.bus.apu.take_captured());
+    }
+    // back to the local player's buttons, for key events to change
+    let pad = &mut self.cpu.bus.joypads[0];
+    pad.set_button_pressed_status(JoypadButton::all(), false);
+    pad.set_button_pressed_status(held, true);
+    pad.set_turbo_pressed_status(turbo, true);
+    self.cpu.bus.joypads[1].set_button_pressed_status(JoypadButton::all(), false);
+    self.finish_frames(stop, frame_count)
+  }
+}
+
+#[wasm_bindgen]
+impl Emulator {
+  /// A console with no cartridge, showing the splash screen.
+  #[wasm_bindgen(constructor)]
+  pub fn new() -> Emulator {
+    logger::init(LevelFilter::Info);
+    let mut cpu = nes::cpu::CPU::new();
+    cpu.rng = SeededRng::new(rand::random());
+    Emulator {
+      cpu,
+      rollback: RollbackBuffer::new(ROLLBACK_FRAMES),
+      rewind: Rewind::new(
+        REWIND_INTERVAL,
+        rewind_capacity(REWIND_SECONDS, REWIND_INTERVAL),
+      ),
+      time_travel: TimeTravel::new(TIME_TRAVEL_CHECKPOINTS, TIME_TRAVEL_INTERVAL),
+      breakpoints: Breakpoints::default(),
+      symbols: Symbols::default(),
+      bindings: Bindings::default(),
+      hotkeys: HotkeyState::default(),
+      combo_keys: Vec::new(),
+      combo_recorder: None,
+      gamepad_held: [0; input::PORTS as usize],
+      palette: Palette::default(),
+      video_dump: None,
+      recent_frames: FrameHistory::new(rewind_capacity(GIF_SECONDS, GIF_INTERVAL)),
+      audio_capture: None,
+      checksum_trace: None,
+      splash: Frame::new(),
+      splash_tick: 0,
+      renderer: None,
+      video_filter: Filter::default(),
+      video_options: VideoOptions::default(),
+      speed: 1.0,
+      turbo: false,
+      run_ahead: RunAhead::new(),
+      rom: None,
+      nsf: None,
+      game_db: GameDb::builtin(),
+      game: None,
+      movie: None,
+      tas: false,
+      attract: Attract::new(),
+      lag_frame: false,
+      lag_frames: 0,
+      battery_retry: Rc::new(Cell::new(false)),
+      hooks: Hooks::default(),
+      perf: PerfMeter::new(),
+      netplay: None,
+    }
+  }
+
+  /// Run until the PPU finishes the next frame, or a breakpoint or
+  /// watchpoint stops it first. Returns why it stopped, like the
+  /// debugger's steps: `{ reason: "done" }` at the end of the frame,
+  /// "halted" if a bare program stopped on a BRK, `{ reason:
+  /// "breakpoint", pc }` or `{ reason: "watchpoint", address, value,
+  /// access }`. The next call picks up where a breakpoint left off and
+  /// runs to the end of that frame. A CPU fault throws an Error with code
+  /// "cpu-fault" and the `pc` and `opcode` it stopped at, as do the
+  /// debugger's steps; `fault` has it again afterwards.
+  pub fn run_frame(&mut self) -> Result<JsValue, JsValue> {
+    let frame = self.cpu.bus.ppu.frame_count;
+    let started = self.perf_start();
+    let stop = self.step_frame();
+    self.perf_record(started, frame);
+    self.finish_frames(stop, frame)
+  }
+
+  /// Run `count` frames back to back, e.g. the frames due in one animation
+  /// frame at `speed`, drawing only the last one. Stops early like
+  /// `run_frame` and returns why the last frame stopped. In turbo mode it
+  /// ignores `count` and runs as many as fit in about 12ms.
+  pub fn run_frames(&mut self, count: u32) -> Result<JsValue, JsValue> {
+    let frame = self.cpu.bus.ppu.frame_count;
+    let started = self.perf_start();
+    let stop = if self.turbo && !self.tas {
+      self.run_turbo()
+    } else {
+      self.run_skipping(count)
+    };
+    self.cpu.bus.ppu.skip_rendering = false;
+    self.perf_record(started, frame);
+    self.finish_frames(stop, frame)
+  }
+
+  /// Time the CPU, PPU and APU apart in `perf_counters`. The timing
+  /// costs a little, so it's off until asked for.
+  pub fn set_profiling(&mut self, on: bool) {
+    let clock = if on {
+      Some(performance_now as fn() -> f64)
+    } else {
+      None
+    };
+    self.cpu.bus.set_profiler(clock);
+  }
+
+  /// How the last `run_frame` or `run_frames` went: `cpu_cycles`,
+  /// `frame_ms`, `cpu_ms`, `ppu_ms`, `apu_ms`, `audio_fill`,
+  /// `audio_latency_ms` and `fps`.
+  pub fn perf_counters(&self) -> PerfCounters {
+    self.perf.counters()
+  }
+
+  /// How many emulated seconds pass per real second: below 1 for slow
+  /// motion, above for fast-forward, from 0.25 to 8. Frontends run that
+  /// many times the frames; the sound is squeezed or stretched to match.
+  pub fn set_speed(&mut self, multiplier: f64) -> Result<(), JsValue> {
+    if !(MIN_SPEED..=MAX_SPEED).contains(&multiplier) {
+      return Err(invalid(format!(
+        "speed {} is outside {}-{}",
+        multiplier, MIN_SPEED, MAX_SPEED
+      )));
+    }
+    self.speed = multiplier;
+    if !self.turbo {
+      self.cpu.bus.apu.set_speed(multiplier);
+    }
+    Ok(())
+  }
+
+  pub fn speed(&self) -> f64 {
+    self.speed
+  }
+
+  /// Uncapped fast-forward: `run_frames` runs as fast as it can, e.g.
+  /// while the fast-forward hotkey is held. Off goes back to `speed`.
+  pub fn set_turbo(&mut self, on: bool) {
+    self.turbo = on;
+    if !on {
+      self.cpu.bus.apu.set_speed(self.speed);
+    }
+  }
+
+  pub fn turbo(&self) -> bool {
+    self.turbo
+  }
+
+  /// Show each frame as it will look `frames` frames later with the
+  /// buttons held now, 0 to 2, so input appears that much sooner. Costs
+  /// that many extra frames of emulation per frame; games differ in how
+  /// much lag they have to hide, so frontends keep it per title.
+  pub fn set_run_ahead(&mut self, frames: u32) -> Result<(), JsValue> {
+    if !self.run_ahead.set_frames(frames) {
+      return Err(invalid(format!(
+        "run-ahead of {} frames is more than {}",
+        frames,
+        RunAhead::MAX_FRAMES
+      )));
+    }
+    Ok(())
+  }
+
+  pub fn run_ahead(&self) -> u32 {
+    self.run_ahead.frames()
+  }
+
+  /// The reset button: registers back to power-up values and the CPU
+  /// restarting at the reset vector. Memory and the cartridge stay as
+  /// they are.
+  pub fn reset(&mut self) {
+    self.attract_input();
+    if let Some(session) = &mut self.movie {
+      session.reset |= session.mode == MovieMode::Recording;
+    }
+    match &mut self.nsf {
+      // the song starts over
+      Some(player) => {
+        let track = player.song();
+        player.start(&mut self.cpu, track);
+      }
+      None => {
+        self.cpu.reset();
+        self.cpu.program_counter = self.cpu.mem_read_u16(0xFFFC);
+      }
+    }
+    self.time_travel.clear();
+  }
+
+  /// Registers, flags and cycle count of the CPU at this moment.
+  pub fn cpu_state(&self) -> CpuState {
+    self.cpu.state()
+  }
+
+  /// Read 1, 2 or 4 bytes at an achievement (rcheevos) address; 0 for any
+  /// other size.
+  pub fn achievement_peek(&self, address: u32, num_bytes: u32) -> u32 {
+    achievements::peek(&self.cpu, address, num_bytes)
+  }
+
+  /// Call `callback(rgba, frame)` whenever `run_frame` or `run_frames`
+  /// finishes a frame, with the `frame_rgba` it ended on and its PPU frame
+  /// count, instead of polling for it. Frames `run_frames` skips drawing
+  /// aren't passed. Null or undefined unsubscribes. An error the callback
+  /// throws is thrown from the run, after the emulation it did.
+  pub fn on_frame(&mut self, callback: Option<Function>) {
+    self.hooks.frame = callback;
+  }
+
+  /// Call `callback(samples)` after `run_frame` or `run_frames` with a
+  /// Float32Array of the samples they generated, like `audio_samples`.
+  /// Those samples are taken off the ring buffer, so a frontend uses this
+  /// or `fill_audio`, not both. Null or undefined unsubscribes.
+  pub fn on_audio(&mut self, callback: Option<Function>) {
+    self.hooks.audio = callback;
+  }
+
+  /// Call `callback(stop)` when a breakpoint or watchpoint stops a run or
+  /// a debugger step, with the stop the call returns too. Null or
+  /// undefined unsubscribes.
+  pub fn on_breakpoint(&mut self, callback: Option<Function>) {
+    self.hooks.breakpoint = callback;
+  }
+
+  /// Call `callback(ram)` after `run_frame` or `run_frames` when the game
+  /// wrote to battery RAM during them, with a copy of it as a Uint8Array
+  /// like `battery_ram`. Doesn't change what `persist_battery_ram` sees as
+  /// unsaved. Null or undefined unsubscribes.
+  pub fn on_sram_write(&mut self, callback: Option<Function>) {
+    self.hooks.battery_writes = self.cpu.bus.battery_writes();
+    self.hooks.sram_write = callback;
+  }
+
+  /// Call `callback(frame)` at the end of every frame `run_frame` or
+  /// `run_frames` runs, drawn or not, with its PPU frame count: where an
+  /// achievements runtime does its frame (rcheevos' `rc_runtime_do_frame`),
+  /// reading memory with `achievement_peek`. Null or undefined
+  /// unsubscribes. An error the callback throws is thrown from the run,
+  /// after the emulation it did.
+  pub fn on_achievement_frame(&mut self, callback: Option<Function>) {
+    self.hooks.achievement_frame = callback;
+  }
+
+  /// Console timing the machine runs with: "ntsc", "pal" or "dendy".
+  /// Follows the ROM header unless `set_region` forced one.
+  pub fn region(&self) -> String {
+    self.cpu.bus.timing().id().to_string()
+  }
+
+  /// Run as an NTSC, PAL or Dendy console ("ntsc", "pal", "dendy")
+  /// whatever the ROM header says, or with no region go back to the
+  /// header's. Stays in effect across ROM loads.
+  pub fn set_region(&mut self, region: Option<String>) -> Result<(), JsValue> {
+    let timing = match region {
+      Some(id) => {
+        Some(Timing::from_id(&id).ok_or_else(|| invalid(format!("unknown region {}", id)))?)
+      }
+      None => None,
+    };
+    self.cpu.bus.set_timing_override(timing);
+    Ok(())
+  }
+
+  /// Frames per second of the current region, what `run_frame` should be
+  /// called at: about 60.1 for NTSC, 50 for PAL and Dendy.
+  pub fn frame_rate(&self) -> f64 {
+    self.cpu.bus.timing().frame_rate()
+  }
+}
+
+fn rewind_capacity(seconds: u32, interval: u32) -> usize {
+  (seconds * FRAMES_PER_SECOND / interval.max(1)) as usize
+}
+
+// a ROM or NSF file, parsed and checked against the boards we emulate
+// before anything of the running game is thrown away
+struct Cartridge {
+  rom: Rom,
+  nsf: Option<NsfPlayer>,
+  game: Option<(Game, Vec<&'static str>)>,
+}
+
+impl Cartridge {
+  fn parse(bytes: &[u8], db: &GameDb) -> Result<Cartridge, FlemuError> {
+    let cartridge = if Nsf::is_nsf(bytes) {
+      let nsf = Nsf::from_bytes(bytes)?;
+      Cartridge {
+        rom: nsf.to_rom(),
+        nsf: Some(NsfPlayer::new(nsf)),
+        game: None,
+      }
+    } else {
+      let mut rom = Rom::from_bytes(bytes)?;
+      let game = db.identify(&rom).cloned().map(|game| {
+        let fixed = game.apply(&mut rom.info);
+        info!("recognised {}, header fixes: {:?}", game.title, fixed);
+        (game, fixed)
+      });
+      Cartridge {
+        rom,
+        nsf: None,
+        game,
+      }
+    };
+    // the header read, but is it a board we have?
+    nes::mapper::for_rom(cartridge.rom.clone())?;
+    Ok(cartridge)
+  }
+
+  // what `load_rom_bytes` returns
+  fn summary(&self) -> JsValue {
+    let title = match (&self.game, &self.nsf) {
+      (Some((game, _)), _) => game.title.as_str().into(),
+      (None, Some(player)) if !player.nsf().title.is_empty() => player.nsf().title.as_str().into(),
+      _ => JsValue::NULL,
+    };
+    let info = &self.rom.info;
+    js_object(&[
+      ("title", title),
+      ("mapper", info.mapper.into()),
+      ("submapper", info.submapper.into()),
+      ("prg_rom_size", (self.rom.prg_rom.len() as u32).into()),
+      ("chr_rom_size", (self.rom.chr_rom.len() as u32).into()),
+      ("battery", info.battery.into()),
+      ("nsf", self.nsf.is_some().into()),
+    ])
+  }
 }
 
-pub mod color;
-pub mod nes;
+/// The battery save IndexedDB holds for `rom_hash`, as a Uint8Array for
+/// `load_battery_ram`, or null if the game never saved.
+#[wasm_bindgen]
+pub async fn stored_battery_ram(rom_hash: String) -> Result<JsValue, JsValue> {
+  match idb::get(&rom_hash, BATTERY_SLOT)
+    .await
+    .map_err(FlemuError::from)?
+  {
+    Some(ram) => Ok(Uint8Array::from(&ram[..]).into()),
+    None => Ok(JsValue::NULL),
+  }
+}
+
+/// Fetch a ROM or NSF and check it can be played, without loading it:
+/// the summary `load_rom_bytes` returns, from the built-in game database,
+/// with the file as a Uint8Array in `bytes` to pass on to it, in this
+/// thread or a worker's. Throws with code "fetch", and `status` when the
+/// server answered, if it can't be fetched, and as `load_rom` does if it
+/// can't be played.
+#[wasm_bindgen]
+pub async fn load_rom_url(url: String) -> Result<JsValue, JsValue> {
+  let failed = |status| FlemuError::Fetch {
+    url: url.clone(),
+    status,
+  };
+  let response: Response = JsFuture::from(fetch(&url))
+    .await
+    .and_then(|response| response.dyn_into())
+    .map_err(|_| failed(None))?;
+  if !response.ok() {
+    return Err(failed(Some(response.status())).into());
+  }
+  let buffer = JsFuture::from(response.array_buffer().map_err(|_| failed(None))?)
+    .await
+    .map_err(|_| failed(None))?;
+  let bytes = Uint8Array::new(&buffer);
+  let summary = Cartridge::parse(&bytes.to_vec(), &GameDb::builtin())?.summary();
+  Reflect::set(&summary, &"bytes".into(), &bytes)?;
+  Ok(summary)
+}
+
+/// The drawing half of an Emulator, for pages running theirs in a worker:
+/// frames come in as RGBA, e.g. out of a SharedArrayBuffer the worker
+/// writes `frame_rgba` to, and go through the same filters.
+#[wasm_bindgen]
+pub struct Display {
+  renderer: Renderer,
+  filter: Filter,
+  options: VideoOptions,
+}
+
+#[wasm_bindgen]
+impl Display {
+  #[wasm_bindgen(constructor)]
+  pub fn new(canvas: HtmlCanvasElement) -> Result<Display, JsValue> {
+    Ok(Display {
+      renderer: Renderer::new(&canvas)?,
+      filter: Filter::default(),
+      options: VideoOptions::default(),
+    })
+  }
+
+  /// Put a 256x240 RGBA frame on the canvas.
+  pub fn draw(&self, rgba: &[u8]) -> Result<(), JsValue> {
+    if rgba.len() != Frame::WIDTH * Frame::HEIGHT * 4 {
+      return Err(invalid(format!(
+        "a frame is {} bytes, not {}",
+        Frame::WIDTH * Frame::HEIGHT * 4,
+        rgba.len()
+      )));
+    }
+    self.renderer.draw(rgba, self.filter, self.options)
+  }
+
+  /// Like `Emulator::set_video_filter`.
+  pub fn set_video_filter(&mut self, filter: &str) -> bool {
+    match Filter::from_id(filter) {
+      Some(filter) => {
+        self.filter = filter;
+        true
+      }
+      None => false,
+    }
+  }
+
+  pub fn video_filter(&self) -> String {
+    self.filter.id().to_string()
+  }
+
+  /// Like `Emulator::set_crop_overscan`.
+  pub fn set_crop_overscan(&mut self, crop: bool) {
+    self.options.crop_overscan = crop;
+  }
+
+  /// Like `Emulator::set_aspect_ratio`.
+  pub fn set_aspect_ratio(&mut self, aspect: &str) -> bool {
+    match Aspect::from_id(aspect) {
+      Some(aspect) => {
+        self.options.aspect = aspect;
+        true
+      }
+      None => false,
+    }
+  }
+
+  /// Like `Emulator::set_integer_scaling`.
+  pub fn set_integer_scaling(&mut self, integer: bool) {
+    self.options.integer_scale = integer;
+  }
+}
+
+pub mod error;
+pub mod idb;
+pub mod input;
+pub mod logger;
+pub mod perf;
+pub mod sram;
+pub mod stats;
+pub mod storage;
+pub mod webgl;
+
+mod attract;
+mod audio;
+mod cartridge;
+mod cheats;
+mod debug;
+mod multiplayer;
+mod state;
+mod tas;
+mod video;
+
+// the emulator itself, where the rest of the crate expects it
+pub use flemu_core::{bare, gif, nes, rng, video_dump, wav};
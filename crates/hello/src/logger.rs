@@ -0,0 +1,57 @@
+use log::LevelFilter;
+
+/// Install the `log` backend: the browser console in wasm builds,
+/// env_logger (stderr, honoring `RUST_LOG`) natively. Calling it again only
+/// changes the level.
+pub fn init(level: LevelFilter) {
+  #[cfg(target_arch = "wasm32")]
+  {
+    // fails when a logger is already installed, which is fine
+    let _ = log::set_logger(&console::CONSOLE_LOGGER);
+  }
+  #[cfg(not(target_arch = "wasm32"))]
+  {
+    let _ = env_logger::Builder::new()
+      .filter_level(level)
+      .parse_default_env()
+      .try_init();
+  }
+  set_level(level);
+}
+
+/// Change verbosity at runtime, e.g. `Trace` to follow every instruction.
+pub fn set_level(level: LevelFilter) {
+  log::set_max_level(level);
+}
+
+#[cfg(target_arch = "wasm32")]
+mod console {
+  use log::{Level, Log, Metadata, Record};
+  use wasm_bindgen::JsValue;
+  use web_sys::console;
+
+  pub static CONSOLE_LOGGER: ConsoleLogger = ConsoleLogger;
+
+  pub struct ConsoleLogger;
+
+  impl Log for ConsoleLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+      metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+      if !self.enabled(record.metadata()) {
+        return;
+      }
+      let message = JsValue::from(format!("[{}] {}", record.target(), record.args()));
+      match record.level() {
+        Level::Error => console::error_1(&message),
+        Level::Warn => console::warn_1(&message),
+        Level::Info => console::info_1(&message),
+        Level::Debug | Level::Trace => console::debug_1(&message),
+      }
+    }
+
+    fn flush(&self) {}
+  }
+}
@@ -0,0 +1,135 @@
+use crate::nes::netplay::Netplay;
+use crate::{invalid, js_object, Emulator, NetplaySession, ROLLBACK_FRAMES};
+use gloo_events::EventListener;
+use js_sys::Uint8Array;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{MessageEvent, RtcDataChannel, RtcDataChannelState, RtcDataChannelType};
+
+// most frames of input delay netplay takes, about 170ms
+const MAX_NETPLAY_DELAY: u8 = 10;
+
+#[wasm_bindgen]
+impl Emulator {
+  /// Play against another browser over `channel`, an open RTCDataChannel
+  /// the page set up between the two, reliable and ordered as data
+  /// channels are by default. One side is the `host`, on controller 1,
+  /// who picks the `delay` in frames (0 to 10) before buttons count, and
+  /// whose game the other side, on controller 2, takes over. Either way
+  /// the local player uses controller 1's bindings; from now on frames go
+  /// through `netplay_frame` alone. The joining side doesn't persist
+  /// battery RAM while the session lasts.
+  pub fn start_netplay(
+    &mut self,
+    channel: RtcDataChannel,
+    host: bool,
+    delay: u8,
+  ) -> Result<(), JsValue> {
+    if self.cpu.bus.mapper().is_none() {
+      return Err(invalid("no cartridge inserted".to_string()));
+    }
+    if channel.ready_state() != RtcDataChannelState::Open {
+      return Err(invalid("the data channel isn't open".to_string()));
+    }
+    if delay > MAX_NETPLAY_DELAY {
+      return Err(invalid(format!(
+        "delay of {} frames is more than {}",
+        delay, MAX_NETPLAY_DELAY
+      )));
+    }
+    channel.set_binary_type(RtcDataChannelType::Arraybuffer);
+    let inbox = Rc::new(RefCell::new(VecDeque::new()));
+    let queue = inbox.clone();
+    let on_message = EventListener::new(&channel, "message", move |event| {
+      let data = event.unchecked_ref::<MessageEvent>().data();
+      queue
+        .borrow_mut()
+        .push_back(Uint8Array::new(&data).to_vec());
+    });
+    let session = NetplaySession {
+      netplay: if host {
+        Netplay::host(delay, ROLLBACK_FRAMES as u32)
+      } else {
+        Netplay::join(ROLLBACK_FRAMES as u32)
+      },
+      channel,
+      inbox,
+      _on_message: on_message,
+    };
+    if host {
+      session.send(&session.netplay.state_message(self.save_state()))?;
+    }
+    self.rollback.clear();
+    self.netplay = Some(session);
+    Ok(())
+  }
+
+  /// Leave the session, the game carrying on from where it got to. The
+  /// channel stays open for the page to close.
+  pub fn stop_netplay(&mut self) {
+    self.netplay = None;
+  }
+
+  /// Netplay's `run_frame`: take in the other side's input, go back over
+  /// frames it was guessed wrong for, and run the next frame unless the
+  /// other side is too far behind, returning `{ reason: "waiting" }`
+  /// then. Call it once per frame; the screen and sound are the game's
+  /// as usual. A desync, the two machines no longer matching, throws an
+  /// Error with code "netplay" and the `frame` it showed at.
+  pub fn netplay_frame(&mut self) -> Result<JsValue, JsValue> {
+    let mut session = self
+      .netplay
+      .take()
+      .ok_or_else(|| invalid("no netplay session".to_string()))?;
+    let stop = self.step_netplay(&mut session);
+    self.netplay = Some(session);
+    stop
+  }
+
+  /// `{ player, delay, frame, remote_frame }` of the session: the
+  /// controller played here (0 or 1), the frame to run next and the
+  /// first one the other side's input hasn't come for. Null without one.
+  pub fn netplay_status(&self) -> JsValue {
+    match &self.netplay {
+      Some(session) => js_object(&[
+        ("player", (session.netplay.player() as u32).into()),
+        ("delay", session.netplay.delay().into()),
+        ("frame", session.netplay.frame().into()),
+        ("remote_frame", session.netplay.remote_frame().into()),
+      ]),
+      None => JsValue::NULL,
+    }
+  }
+
+  /// Cheap in-memory snapshot of the machine tagged with `frame`, meant to
+  /// be called every frame by a rollback netplay layer.
+  pub fn snapshot(&mut self, frame: u32) {
+    self.rollback.snapshot(frame, &self.cpu);
+  }
+
+  /// Roll the machine back to `frame`. Returns false when the frame is no
+  /// longer buffered.
+  pub fn restore(&mut self, frame: u32) -> bool {
+    match self.rollback.get(frame) {
+      Some(cpu) => {
+        // what the debugger hides or watches, and the mixer, aren't part of
+        // the machine
+        let layers = self.cpu.bus.ppu.layers;
+        let mixer = self.cpu.bus.apu.mixer;
+        let watchpoints = std::mem::take(&mut self.cpu.bus.watchpoints);
+        let capture_rate = self.cpu.bus.apu.capture_rate();
+        self.cpu.clone_from(cpu);
+        self.cpu.bus.ppu.layers = layers;
+        self.cpu.bus.apu.mixer = mixer;
+        self.cpu.bus.watchpoints = watchpoints;
+        self.cpu.bus.apu.set_capture_rate(capture_rate);
+        self.time_travel.clear();
+        true
+      }
+      None => false,
+    }
+  }
+}
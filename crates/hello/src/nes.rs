@@ -1,5 +0,0 @@
-pub mod cpu;
-mod opcodes;
-
-// expose data
-pub use opcodes::OpCode;
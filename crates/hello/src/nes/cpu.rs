@@ -1,1281 +0,0 @@
-use crate::nes::opcodes;
-use bitflags::bitflags;
-use std::collections::HashMap;
-
-use kurbo::Rect;
-use piet::{Color as PColor, RenderContext};
-use piet_web::*;
-
-use crate::color::Color;
-
-bitflags! {
-  /// # Status Register (P) http://wiki.nesdev.com/w/index.php/Status_flags
-  ///
-  ///  7 6 5 4 3 2 1 0
-  ///  N V _ B D I Z C
-  ///  | |   | | | | +--- Carry Flag
-  ///  | |   | | | +----- Zero Flag
-  ///  | |   | | +------- Interrupt Disable
-  ///  | |   | +--------- Decimal Mode (not used on NES)
-  ///  | |   +----------- Break Command
-  ///  | +--------------- Overflow Flag
-  ///  +----------------- Negative Flag
-  ///
-  #[wasm_bindgen]
-  pub struct CpuFlags: u8 {
-    const CARRY             = 0b00000001;
-    const ZERO              = 0b00000010;
-    const INTERRUPT_DISABLE = 0b00000100;
-    const DECIMAL_MODE      = 0b00001000;
-    const BREAK             = 0b00010000;
-    const BREAK2            = 0b00100000;
-    const OVERFLOW          = 0b01000000;
-    const NEGATIVE          = 0b10000000;
-  }
-}
-
-fn color(byte: u8) -> Color {
-  match byte {
-    0 => Color::Black,
-    1 => Color::White,
-    2 | 9 => Color::Grey,
-    3 | 10 => Color::Red,
-    4 | 11 => Color::Green,
-    5 | 12 => Color::Blue,
-    6 | 13 => Color::Magenta,
-    7 | 14 => Color::Yellow,
-    _ => Color::Cyan,
-  }
-}
-
-pub fn read_screen_state(cpu: &CPU, frame: &mut [u8; 32 * 3 * 32]) -> bool {
-  let mut frame_idx = 0;
-  let mut update = false;
-  for i in 0x0200..0x600 {
-    let color_idx = cpu.mem_read(i as u16);
-    let (b1, b2, b3) = color(color_idx).rgb();
-    if frame[frame_idx] != b1 || frame[frame_idx + 1] != b2 || frame[frame_idx + 2] != b3 {
-      frame[frame_idx] = b1;
-      frame[frame_idx + 1] = b2;
-      frame[frame_idx + 2] = b3;
-      update = true;
-    }
-    frame_idx += 3;
-  }
-  update
-}
-
-pub fn render_screen(rc: &mut WebRenderContext, frame: &mut [u8; 32 * 3 * 32]) {
-  let mut i = 0;
-  let mut col = 0.0;
-  let mut row = 0.0;
-  let cell_width = 10.0;
-  let cell_heigh = 10.0;
-
-  while i < 32 * 3 * 32 {
-    let r = frame[i];
-    let g = frame[i + 1];
-    let b = frame[i + 2];
-
-    // cell_width
-    let point = Rect::new(col * cell_width, row * cell_heigh, cell_width, cell_heigh);
-    // cell_heigh
-    // startx
-    // starty
-
-    let color = PColor::rgb8(r, g, b);
-    // position
-    rc.clear(point, color);
-
-    i += 3;
-    col += 1.0;
-    if col == 32.0 {
-      col = 0.0;
-      row += 1.0;
-    }
-  }
-}
-
-const STACK: u16 = 0x0100;
-const STACK_RESET: u8 = 0xfd;
-
-/*
- NES CPU can address 65536 memory cells. It takes
- 2 bytes to store an address. NES CPU uses
- Little-Endian addressing rather than Big-Endian.
- That means that the 8 least significant bits of
- an address will be stored before the 8 most
- significant bits.
-
- For example:
-
-     Real address: 0x8000
-     address packed in big-endian:    80 00
-     address packed in little-endian: 00 80
-
- ASM Example:
-
-     LDA $8000   <=>    ad 00 80
-*/
-#[wasm_bindgen]
-#[derive(Copy, Clone)]
-pub struct CPU {
-  // accumulator
-  pub register_a: u8,
-  // index x
-  pub register_x: u8,
-  // index y
-  pub register_y: u8,
-  // processor status
-  pub status: CpuFlags,
-  // current program address
-  pub program_counter: u16,
-  // current stack_pointer
-  pub stack_pointer: u8,
-  // ram
-  memory: [u8; 0xFFFF],
-}
-
-/*
-  NES platform has a special mechanism to mark where
-  the CPU should start the execution. Upon inserting
-  a new cartridge, the CPU receives a special signal
-  called "Reset interrupt" that instructs CPU to:
-
-  - reset the state (registers and flags)
-  - set program_counter to the 16-bit address that is stored at 0xFFFC
-*/
-const DEFAULT_PROGRAM_COUNTER: u16 = 0x8000;
-
-pub trait Mem {
-  fn mem_read(&self, addr: u16) -> u8;
-
-  fn mem_write(&mut self, addr: u16, data: u8);
-
-  fn mem_read_u16(&self, pos: u16) -> u16 {
-    let lo = self.mem_read(pos) as u16;
-    let hi = self.mem_read(pos + 1) as u16;
-    (hi << 8) | (lo as u16)
-  }
-
-  fn mem_write_u16(&mut self, pos: u16, data: u16) {
-    let hi = (data >> 8) as u8;
-    let lo = (data & 0xff) as u8;
-    self.mem_write(pos, lo);
-    self.mem_write(pos + 1, hi);
-  }
-}
-
-impl Mem for CPU {
-  fn mem_read(&self, addr: u16) -> u8 {
-    self.memory[addr as usize]
-  }
-
-  fn mem_write(&mut self, addr: u16, data: u8) {
-    self.memory[addr as usize] = data;
-  }
-}
-
-#[derive(Debug)]
-#[allow(non_camel_case_types)]
-pub enum AddressingMode {
-  Immediate,
-  ZeroPage,
-  ZeroPage_X,
-  ZeroPage_Y,
-  Absolute,
-  Absolute_X,
-  Absolute_Y,
-  Indirect_X,
-  Indirect_Y,
-  NoneAddressing,
-}
-use wasm_bindgen::prelude::*;
-
-impl CPU {
-  pub fn new() -> Self {
-    CPU {
-      register_a: 0,
-      register_x: 0,
-      register_y: 0,
-
-      status: CpuFlags::from_bits_truncate(0b100100),
-      program_counter: 0,
-      stack_pointer: STACK_RESET,
-      memory: [0; 0xFFFF],
-    }
-  }
-  fn get_operand_address(&self, mode: &AddressingMode) -> u16 {
-    match mode {
-      AddressingMode::Immediate => self.program_counter,
-
-      AddressingMode::ZeroPage => self.mem_read(self.program_counter) as u16,
-
-      AddressingMode::Absolute => self.mem_read_u16(self.program_counter),
-
-      AddressingMode::ZeroPage_X => {
-        let pos = self.mem_read(self.program_counter);
-        let addr = pos.wrapping_add(self.register_x) as u16;
-        addr
-      }
-      AddressingMode::ZeroPage_Y => {
-        let pos = self.mem_read(self.program_counter);
-        let addr = pos.wrapping_add(self.register_y) as u16;
-        addr
-      }
-
-      AddressingMode::Absolute_X => {
-        let base = self.mem_read_u16(self.program_counter);
-        let addr = base.wrapping_add(self.register_x as u16);
-        addr
-      }
-      AddressingMode::Absolute_Y => {
-        let base = self.mem_read_u16(self.program_counter);
-        let addr = base.wrapping_add(self.register_y as u16);
-        addr
-      }
-
-      AddressingMode::Indirect_X => {
-        let base = self.mem_read(self.program_counter);
-
-        let ptr: u8 = (base as u8).wrapping_add(self.register_x);
-        let lo = self.mem_read(ptr as u16);
-        let hi = self.mem_read(ptr.wrapping_add(1) as u16);
-        (hi as u16) << 8 | (lo as u16)
-      }
-      AddressingMode::Indirect_Y => {
-        let base = self.mem_read(self.program_counter);
-
-        let lo = self.mem_read(base as u16);
-        let hi = self.mem_read((base as u8).wrapping_add(1) as u16);
-        let deref_base = (hi as u16) << 8 | (lo as u16);
-        let deref = deref_base.wrapping_add(self.register_y as u16);
-        deref
-      }
-
-      AddressingMode::NoneAddressing => {
-        panic!("mode {:?} is not supported", mode);
-      }
-    }
-  }
-
-  pub fn load_and_run(&mut self, program: Vec<u8>) {
-    self.reset();
-    self.load(program);
-    self.run();
-  }
-  pub fn load(&mut self, program: Vec<u8>) {
-    self.memory[DEFAULT_PROGRAM_COUNTER as usize..DEFAULT_PROGRAM_COUNTER as usize + program.len()]
-      .copy_from_slice(&program[..]);
-    // we dont have cartridge - :trollface:
-    self.mem_write_u16(0xFFFC, DEFAULT_PROGRAM_COUNTER);
-    self.program_counter = DEFAULT_PROGRAM_COUNTER;
-  }
-  pub fn reset(&mut self) {
-    self.register_a = 0;
-    self.register_x = 0;
-    self.register_y = 0;
-
-    self.stack_pointer = STACK_RESET;
-    self.status = CpuFlags::from_bits_truncate(0b100100);
-    self.memory = [0; 0xFFFF];
-
-    self.program_counter = 0;
-  }
-
-  pub fn run(&mut self) {
-    self.run_with_callback(|_| {});
-  }
-
-  pub fn done(self) -> bool {
-    if self.program_counter == 0 {
-      // no program loaded/running???
-      return false;
-    }
-
-    let code = self.mem_read(self.program_counter);
-    code == 0x00
-  }
-
-  pub fn step_run<F>(&mut self, mut callback: F)
-  where
-    F: FnMut(&mut CPU),
-  {
-    if self.done() {
-      return;
-    }
-
-    let ref opcodes: HashMap<u8, &'static opcodes::OpCode> = *opcodes::OPCODES_MAP;
-    // TODO - we might have run as address in future
-    let code = self.mem_read(self.program_counter);
-    self.program_counter += 1;
-    let program_counter_state = self.program_counter;
-
-    let opcode = opcodes
-      .get(&code)
-      .expect(&format!("OpCode {:x} is not recognized", code));
-    match code {
-      /* LDA */
-      0xa9 | 0xa5 | 0xb5 | 0xbd | 0xb9 | 0xa1 | 0xb1 | 0xad => {
-        self.lda(&opcode.mode);
-      }
-
-      /* TAX */ 0xAA => self.tax(),
-
-      /* INX */ 0xE8 => self.inx(),
-
-      /* BRK */ 0x00 => {
-        self.program_counter = 0;
-        return;
-      }
-
-      /* CLD */ 0xd8 => self.status.remove(CpuFlags::DECIMAL_MODE),
-
-      /* CLI */ 0x58 => self.status.remove(CpuFlags::INTERRUPT_DISABLE),
-
-      /* CLV */ 0xb8 => self.status.remove(CpuFlags::OVERFLOW),
-
-      /* CLC */ 0x18 => self.clear_carry_flag(),
-
-      /* SEC */ 0x38 => self.set_carry_flag(),
-
-      /* SEI */ 0x78 => self.status.insert(CpuFlags::INTERRUPT_DISABLE),
-
-      /* SED */ 0xf8 => self.status.insert(CpuFlags::DECIMAL_MODE),
-
-      /* PHA */ 0x48 => self.stack_push(self.register_a),
-
-      /* PLA */
-      0x68 => {
-        self.pla();
-      }
-
-      /* PHP */
-      0x08 => {
-        self.php();
-      }
-
-      /* PLP */
-      0x28 => {
-        self.plp();
-      }
-
-      /* ADC */
-      0x69 | 0x65 | 0x75 | 0x6d | 0x7d | 0x79 | 0x61 | 0x71 => {
-        self.adc(&opcode.mode);
-      }
-
-      /* SBC */
-      0xe9 | 0xe5 | 0xf5 | 0xed | 0xfd | 0xf9 | 0xe1 | 0xf1 => {
-        self.sbc(&opcode.mode);
-      }
-
-      /* AND */
-      0x29 | 0x25 | 0x35 | 0x2d | 0x3d | 0x39 | 0x21 | 0x31 => {
-        self.and(&opcode.mode);
-      }
-
-      /* EOR */
-      0x49 | 0x45 | 0x55 | 0x4d | 0x5d | 0x59 | 0x41 | 0x51 => {
-        self.eor(&opcode.mode);
-      }
-
-      /* ORA */
-      0x09 | 0x05 | 0x15 | 0x0d | 0x1d | 0x19 | 0x01 | 0x11 => {
-        self.ora(&opcode.mode);
-      }
-
-      /* LSR */ 0x4a => self.lsr_accumulator(),
-
-      /* LSR */
-      0x46 | 0x56 | 0x4e | 0x5e => {
-        self.lsr(&opcode.mode);
-      }
-
-      /*ASL*/ 0x0a => self.asl_accumulator(),
-
-      /* ASL */
-      0x06 | 0x16 | 0x0e | 0x1e => {
-        self.asl(&opcode.mode);
-      }
-      /* ROL */ 0x2a => self.rol_accumulator(),
-
-      /* ROL */
-      0x26 | 0x36 | 0x2e | 0x3e => {
-        self.rol(&opcode.mode);
-      }
-
-      /* ROR */ 0x6a => self.ror_accumulator(),
-
-      /* ROR */
-      0x66 | 0x76 | 0x6e | 0x7e => {
-        self.ror(&opcode.mode);
-      }
-
-      /* INC */
-      0xe6 | 0xf6 | 0xee | 0xfe => {
-        self.inc(&opcode.mode);
-      }
-
-      /* INY */
-      0xc8 => self.iny(),
-
-      /* DEC */
-      0xc6 | 0xd6 | 0xce | 0xde => {
-        self.dec(&opcode.mode);
-      }
-
-      /* DEX */
-      0xca => {
-        self.dex();
-      }
-
-      /* DEY */
-      0x88 => {
-        self.dey();
-      }
-
-      /* CMP */
-      0xc9 | 0xc5 | 0xd5 | 0xcd | 0xdd | 0xd9 | 0xc1 | 0xd1 => {
-        self.compare(&opcode.mode, self.register_a);
-      }
-
-      /* CPY */
-      0xc0 | 0xc4 | 0xcc => {
-        self.compare(&opcode.mode, self.register_y);
-      }
-
-      /* CPX */
-      0xe0 | 0xe4 | 0xec => self.compare(&opcode.mode, self.register_x),
-
-      /* JMP Absolute */
-      0x4c => {
-        let mem_address = self.mem_read_u16(self.program_counter);
-        self.program_counter = mem_address;
-      }
-
-      /* JMP Indirect */
-      0x6c => {
-        let mem_address = self.mem_read_u16(self.program_counter);
-        // let indirect_ref = self.mem_read_u16(mem_address);
-        //6502 bug mode with with page boundary:
-        //  if address $3000 contains $40, $30FF contains $80, and $3100 contains $50,
-        // the result of JMP ($30FF) will be a transfer of control to $4080 rather than $5080 as you intended
-        // i.e. the 6502 took the low byte of the address from $30FF and the high byte from $3000
-
-        let indirect_ref = if mem_address & 0x00FF == 0x00FF {
-          let lo = self.mem_read(mem_address);
-          let hi = self.mem_read(mem_address & 0xFF00);
-          (hi as u16) << 8 | (lo as u16)
-        } else {
-          self.mem_read_u16(mem_address)
-        };
-
-        self.program_counter = indirect_ref;
-      }
-
-      /* JSR */
-      0x20 => {
-        self.stack_push_u16(self.program_counter + 2 - 1);
-        let target_address = self.mem_read_u16(self.program_counter);
-        self.program_counter = target_address
-      }
-
-      /* RTS */
-      0x60 => {
-        self.program_counter = self.stack_pop_u16() + 1;
-      }
-
-      /* RTI */
-      0x40 => {
-        self.status.bits = self.stack_pop();
-        self.status.remove(CpuFlags::BREAK);
-        self.status.insert(CpuFlags::BREAK2);
-
-        self.program_counter = self.stack_pop_u16();
-      }
-
-      /* BNE */
-      0xd0 => {
-        self.branch(!self.status.contains(CpuFlags::ZERO));
-      }
-
-      /* BVS */
-      0x70 => {
-        self.branch(self.status.contains(CpuFlags::OVERFLOW));
-      }
-
-      /* BVC */
-      0x50 => {
-        self.branch(!self.status.contains(CpuFlags::OVERFLOW));
-      }
-
-      /* BPL */
-      0x10 => {
-        self.branch(!self.status.contains(CpuFlags::NEGATIVE));
-      }
-
-      /* BMI */
-      0x30 => {
-        self.branch(self.status.contains(CpuFlags::NEGATIVE));
-      }
-
-      /* BEQ */
-      0xf0 => {
-        self.branch(self.status.contains(CpuFlags::ZERO));
-      }
-
-      /* BCS */
-      0xb0 => {
-        self.branch(self.status.contains(CpuFlags::CARRY));
-      }
-
-      /* BCC */
-      0x90 => {
-        self.branch(!self.status.contains(CpuFlags::CARRY));
-      }
-
-      /* BIT */
-      0x24 | 0x2c => {
-        self.bit(&opcode.mode);
-      }
-
-      /* STA */
-      0x85 | 0x95 | 0x8d | 0x9d | 0x99 | 0x81 | 0x91 => {
-        self.sta(&opcode.mode);
-      }
-
-      /* STX */
-      0x86 | 0x96 | 0x8e => {
-        let addr = self.get_operand_address(&opcode.mode);
-        self.mem_write(addr, self.register_x);
-      }
-
-      /* STY */
-      0x84 | 0x94 | 0x8c => {
-        let addr = self.get_operand_address(&opcode.mode);
-        self.mem_write(addr, self.register_y);
-      }
-
-      /* LDX */
-      0xa2 | 0xa6 | 0xb6 | 0xae | 0xbe => {
-        self.ldx(&opcode.mode);
-      }
-
-      /* LDY */
-      0xa0 | 0xa4 | 0xb4 | 0xac | 0xbc => {
-        self.ldy(&opcode.mode);
-      }
-
-      /* NOP */
-      0xea => {
-        //do nothing
-      }
-
-      /* TAY */
-      0xa8 => {
-        self.register_y = self.register_a;
-        self.update_zero_and_negative_flags(self.register_y);
-      }
-
-      /* TSX */
-      0xba => {
-        self.register_x = self.stack_pointer;
-        self.update_zero_and_negative_flags(self.register_x);
-      }
-
-      /* TXA */
-      0x8a => {
-        self.register_a = self.register_x;
-        self.update_zero_and_negative_flags(self.register_a);
-      }
-
-      /* TXS */
-      0x9a => {
-        self.stack_pointer = self.register_x;
-      }
-
-      /* TYA */
-      0x98 => {
-        self.register_a = self.register_y;
-        self.update_zero_and_negative_flags(self.register_a);
-      }
-      _ => todo!(),
-    }
-
-    if program_counter_state == self.program_counter {
-      self.program_counter += (opcode.len - 1) as u16;
-    }
-
-    callback(self);
-  }
-
-  pub fn run_with_callback<F>(&mut self, mut callback: F)
-  where
-    F: FnMut(&mut CPU),
-  {
-    let ref opcodes: HashMap<u8, &'static opcodes::OpCode> = *opcodes::OPCODES_MAP;
-    // TODO - we might have run as address in future
-    self.program_counter = self.mem_read_u16(0xFFFC);
-    loop {
-      let code = self.mem_read(self.program_counter);
-      self.program_counter += 1;
-      let program_counter_state = self.program_counter;
-
-      let opcode = opcodes
-        .get(&code)
-        .expect(&format!("OpCode {:x} is not recognized", code));
-      match code {
-        /* LDA */
-        0xa9 | 0xa5 | 0xb5 | 0xbd | 0xb9 | 0xa1 | 0xb1 | 0xad => {
-          self.lda(&opcode.mode);
-        }
-
-        /* TAX */ 0xAA => self.tax(),
-
-        /* INX */ 0xE8 => self.inx(),
-
-        /* BRK */ 0x00 => {
-          self.program_counter = 0;
-          return;
-        }
-
-        /* CLD */ 0xd8 => self.status.remove(CpuFlags::DECIMAL_MODE),
-
-        /* CLI */ 0x58 => self.status.remove(CpuFlags::INTERRUPT_DISABLE),
-
-        /* CLV */ 0xb8 => self.status.remove(CpuFlags::OVERFLOW),
-
-        /* CLC */ 0x18 => self.clear_carry_flag(),
-
-        /* SEC */ 0x38 => self.set_carry_flag(),
-
-        /* SEI */ 0x78 => self.status.insert(CpuFlags::INTERRUPT_DISABLE),
-
-        /* SED */ 0xf8 => self.status.insert(CpuFlags::DECIMAL_MODE),
-
-        /* PHA */ 0x48 => self.stack_push(self.register_a),
-
-        /* PLA */
-        0x68 => {
-          self.pla();
-        }
-
-        /* PHP */
-        0x08 => {
-          self.php();
-        }
-
-        /* PLP */
-        0x28 => {
-          self.plp();
-        }
-
-        /* ADC */
-        0x69 | 0x65 | 0x75 | 0x6d | 0x7d | 0x79 | 0x61 | 0x71 => {
-          self.adc(&opcode.mode);
-        }
-
-        /* SBC */
-        0xe9 | 0xe5 | 0xf5 | 0xed | 0xfd | 0xf9 | 0xe1 | 0xf1 => {
-          self.sbc(&opcode.mode);
-        }
-
-        /* AND */
-        0x29 | 0x25 | 0x35 | 0x2d | 0x3d | 0x39 | 0x21 | 0x31 => {
-          self.and(&opcode.mode);
-        }
-
-        /* EOR */
-        0x49 | 0x45 | 0x55 | 0x4d | 0x5d | 0x59 | 0x41 | 0x51 => {
-          self.eor(&opcode.mode);
-        }
-
-        /* ORA */
-        0x09 | 0x05 | 0x15 | 0x0d | 0x1d | 0x19 | 0x01 | 0x11 => {
-          self.ora(&opcode.mode);
-        }
-
-        /* LSR */ 0x4a => self.lsr_accumulator(),
-
-        /* LSR */
-        0x46 | 0x56 | 0x4e | 0x5e => {
-          self.lsr(&opcode.mode);
-        }
-
-        /*ASL*/ 0x0a => self.asl_accumulator(),
-
-        /* ASL */
-        0x06 | 0x16 | 0x0e | 0x1e => {
-          self.asl(&opcode.mode);
-        }
-        /* ROL */ 0x2a => self.rol_accumulator(),
-
-        /* ROL */
-        0x26 | 0x36 | 0x2e | 0x3e => {
-          self.rol(&opcode.mode);
-        }
-
-        /* ROR */ 0x6a => self.ror_accumulator(),
-
-        /* ROR */
-        0x66 | 0x76 | 0x6e | 0x7e => {
-          self.ror(&opcode.mode);
-        }
-
-        /* INC */
-        0xe6 | 0xf6 | 0xee | 0xfe => {
-          self.inc(&opcode.mode);
-        }
-
-        /* INY */
-        0xc8 => self.iny(),
-
-        /* DEC */
-        0xc6 | 0xd6 | 0xce | 0xde => {
-          self.dec(&opcode.mode);
-        }
-
-        /* DEX */
-        0xca => {
-          self.dex();
-        }
-
-        /* DEY */
-        0x88 => {
-          self.dey();
-        }
-
-        /* CMP */
-        0xc9 | 0xc5 | 0xd5 | 0xcd | 0xdd | 0xd9 | 0xc1 | 0xd1 => {
-          self.compare(&opcode.mode, self.register_a);
-        }
-
-        /* CPY */
-        0xc0 | 0xc4 | 0xcc => {
-          self.compare(&opcode.mode, self.register_y);
-        }
-
-        /* CPX */
-        0xe0 | 0xe4 | 0xec => self.compare(&opcode.mode, self.register_x),
-
-        /* JMP Absolute */
-        0x4c => {
-          let mem_address = self.mem_read_u16(self.program_counter);
-          self.program_counter = mem_address;
-        }
-
-        /* JMP Indirect */
-        0x6c => {
-          let mem_address = self.mem_read_u16(self.program_counter);
-          // let indirect_ref = self.mem_read_u16(mem_address);
-          //6502 bug mode with with page boundary:
-          //  if address $3000 contains $40, $30FF contains $80, and $3100 contains $50,
-          // the result of JMP ($30FF) will be a transfer of control to $4080 rather than $5080 as you intended
-          // i.e. the 6502 took the low byte of the address from $30FF and the high byte from $3000
-
-          let indirect_ref = if mem_address & 0x00FF == 0x00FF {
-            let lo = self.mem_read(mem_address);
-            let hi = self.mem_read(mem_address & 0xFF00);
-            (hi as u16) << 8 | (lo as u16)
-          } else {
-            self.mem_read_u16(mem_address)
-          };
-
-          self.program_counter = indirect_ref;
-        }
-
-        /* JSR */
-        0x20 => {
-          self.stack_push_u16(self.program_counter + 2 - 1);
-          let target_address = self.mem_read_u16(self.program_counter);
-          self.program_counter = target_address
-        }
-
-        /* RTS */
-        0x60 => {
-          self.program_counter = self.stack_pop_u16() + 1;
-        }
-
-        /* RTI */
-        0x40 => {
-          self.status.bits = self.stack_pop();
-          self.status.remove(CpuFlags::BREAK);
-          self.status.insert(CpuFlags::BREAK2);
-
-          self.program_counter = self.stack_pop_u16();
-        }
-
-        /* BNE */
-        0xd0 => {
-          self.branch(!self.status.contains(CpuFlags::ZERO));
-        }
-
-        /* BVS */
-        0x70 => {
-          self.branch(self.status.contains(CpuFlags::OVERFLOW));
-        }
-
-        /* BVC */
-        0x50 => {
-          self.branch(!self.status.contains(CpuFlags::OVERFLOW));
-        }
-
-        /* BPL */
-        0x10 => {
-          self.branch(!self.status.contains(CpuFlags::NEGATIVE));
-        }
-
-        /* BMI */
-        0x30 => {
-          self.branch(self.status.contains(CpuFlags::NEGATIVE));
-        }
-
-        /* BEQ */
-        0xf0 => {
-          self.branch(self.status.contains(CpuFlags::ZERO));
-        }
-
-        /* BCS */
-        0xb0 => {
-          self.branch(self.status.contains(CpuFlags::CARRY));
-        }
-
-        /* BCC */
-        0x90 => {
-          self.branch(!self.status.contains(CpuFlags::CARRY));
-        }
-
-        /* BIT */
-        0x24 | 0x2c => {
-          self.bit(&opcode.mode);
-        }
-
-        /* STA */
-        0x85 | 0x95 | 0x8d | 0x9d | 0x99 | 0x81 | 0x91 => {
-          self.sta(&opcode.mode);
-        }
-
-        /* STX */
-        0x86 | 0x96 | 0x8e => {
-          let addr = self.get_operand_address(&opcode.mode);
-          self.mem_write(addr, self.register_x);
-        }
-
-        /* STY */
-        0x84 | 0x94 | 0x8c => {
-          let addr = self.get_operand_address(&opcode.mode);
-          self.mem_write(addr, self.register_y);
-        }
-
-        /* LDX */
-        0xa2 | 0xa6 | 0xb6 | 0xae | 0xbe => {
-          self.ldx(&opcode.mode);
-        }
-
-        /* LDY */
-        0xa0 | 0xa4 | 0xb4 | 0xac | 0xbc => {
-          self.ldy(&opcode.mode);
-        }
-
-        /* NOP */
-        0xea => {
-          //do nothing
-        }
-
-        /* TAY */
-        0xa8 => {
-          self.register_y = self.register_a;
-          self.update_zero_and_negative_flags(self.register_y);
-        }
-
-        /* TSX */
-        0xba => {
-          self.register_x = self.stack_pointer;
-          self.update_zero_and_negative_flags(self.register_x);
-        }
-
-        /* TXA */
-        0x8a => {
-          self.register_a = self.register_x;
-          self.update_zero_and_negative_flags(self.register_a);
-        }
-
-        /* TXS */
-        0x9a => {
-          self.stack_pointer = self.register_x;
-        }
-
-        /* TYA */
-        0x98 => {
-          self.register_a = self.register_y;
-          self.update_zero_and_negative_flags(self.register_a);
-        }
-        _ => todo!(),
-      }
-
-      if program_counter_state == self.program_counter {
-        self.program_counter += (opcode.len - 1) as u16;
-      }
-
-      callback(self);
-    }
-  }
-
-  fn ldy(&mut self, mode: &AddressingMode) {
-    let addr = self.get_operand_address(mode);
-    let data = self.mem_read(addr);
-    self.register_y = data;
-    self.update_zero_and_negative_flags(self.register_y);
-  }
-
-  fn ldx(&mut self, mode: &AddressingMode) {
-    let addr = self.get_operand_address(mode);
-    let data = self.mem_read(addr);
-    self.register_x = data;
-    self.update_zero_and_negative_flags(self.register_x);
-  }
-
-  fn lda(&mut self, mode: &AddressingMode) {
-    let addr = self.get_operand_address(&mode);
-    let value = self.mem_read(addr);
-    println!("lda {} {}", addr, value);
-    self.set_register_a(value);
-  }
-
-  fn sta(&mut self, mode: &AddressingMode) {
-    let addr = self.get_operand_address(mode);
-    self.mem_write(addr, self.register_a);
-  }
-
-  fn set_register_a(&mut self, value: u8) {
-    self.register_a = value;
-    self.update_zero_and_negative_flags(self.register_a);
-  }
-
-  fn and(&mut self, mode: &AddressingMode) {
-    let addr = self.get_operand_address(mode);
-    let data = self.mem_read(addr);
-    self.set_register_a(data & self.register_a);
-  }
-
-  fn eor(&mut self, mode: &AddressingMode) {
-    let addr = self.get_operand_address(mode);
-    let data = self.mem_read(addr);
-    self.set_register_a(data ^ self.register_a);
-  }
-
-  fn ora(&mut self, mode: &AddressingMode) {
-    let addr = self.get_operand_address(mode);
-    let data = self.mem_read(addr);
-    self.set_register_a(data | self.register_a);
-  }
-
-  fn tax(&mut self) {
-    self.register_x = self.register_a;
-    self.update_zero_and_negative_flags(self.register_x);
-  }
-
-  fn update_zero_and_negative_flags(&mut self, result: u8) {
-    if result == 0 {
-      self.status.insert(CpuFlags::ZERO);
-    } else {
-      self.status.remove(CpuFlags::ZERO);
-    }
-
-    if result & 0b1000_0000 != 0 {
-      self.status.insert(CpuFlags::NEGATIVE);
-    } else {
-      self.status.remove(CpuFlags::NEGATIVE);
-    }
-  }
-
-  fn inx(&mut self) {
-    self.register_x = self.register_x.wrapping_add(1);
-    self.update_zero_and_negative_flags(self.register_x);
-  }
-
-  fn iny(&mut self) {
-    self.register_y = self.register_y.wrapping_add(1);
-    self.update_zero_and_negative_flags(self.register_y);
-  }
-
-  fn set_carry_flag(&mut self) {
-    self.status.insert(CpuFlags::CARRY)
-  }
-
-  fn clear_carry_flag(&mut self) {
-    self.status.remove(CpuFlags::CARRY)
-  }
-
-  /// note: ignoring decimal mode
-  /// http://www.righto.com/2012/12/the-6502-overflow-flag-explained.html
-  fn add_to_register_a(&mut self, data: u8) {
-    let sum = self.register_a as u16
-      + data as u16
-      + (if self.status.contains(CpuFlags::CARRY) {
-        1
-      } else {
-        0
-      }) as u16;
-
-    let carry = sum > 0xff;
-
-    if carry {
-      self.status.insert(CpuFlags::CARRY);
-    } else {
-      self.status.remove(CpuFlags::CARRY);
-    }
-
-    let result = sum as u8;
-
-    if (data ^ result) & (result ^ self.register_a) & 0x80 != 0 {
-      self.status.insert(CpuFlags::OVERFLOW);
-    } else {
-      self.status.remove(CpuFlags::OVERFLOW)
-    }
-
-    self.set_register_a(result);
-  }
-
-  fn sbc(&mut self, mode: &AddressingMode) {
-    let addr = self.get_operand_address(&mode);
-    let data = self.mem_read(addr);
-    self.add_to_register_a(((data as i8).wrapping_neg().wrapping_sub(1)) as u8);
-  }
-
-  fn adc(&mut self, mode: &AddressingMode) {
-    let addr = self.get_operand_address(mode);
-    let value = self.mem_read(addr);
-    self.add_to_register_a(value);
-  }
-
-  fn stack_pop(&mut self) -> u8 {
-    self.stack_pointer = self.stack_pointer.wrapping_add(1);
-    self.mem_read((STACK as u16) + self.stack_pointer as u16)
-  }
-
-  fn stack_push(&mut self, data: u8) {
-    self.mem_write((STACK as u16) + self.stack_pointer as u16, data);
-    self.stack_pointer = self.stack_pointer.wrapping_sub(1)
-  }
-
-  fn stack_push_u16(&mut self, data: u16) {
-    let hi = (data >> 8) as u8;
-    let lo = (data & 0xff) as u8;
-    self.stack_push(hi);
-    self.stack_push(lo);
-  }
-
-  fn stack_pop_u16(&mut self) -> u16 {
-    let lo = self.stack_pop() as u16;
-    let hi = self.stack_pop() as u16;
-
-    hi << 8 | lo
-  }
-
-  fn asl_accumulator(&mut self) {
-    let mut data = self.register_a;
-    if data >> 7 == 1 {
-      self.set_carry_flag();
-    } else {
-      self.clear_carry_flag();
-    }
-    data = data << 1;
-    self.set_register_a(data)
-  }
-
-  fn asl(&mut self, mode: &AddressingMode) -> u8 {
-    let addr = self.get_operand_address(mode);
-    let mut data = self.mem_read(addr);
-    if data >> 7 == 1 {
-      self.set_carry_flag();
-    } else {
-      self.clear_carry_flag();
-    }
-    data = data << 1;
-    self.mem_write(addr, data);
-    self.update_zero_and_negative_flags(data);
-    data
-  }
-
-  fn lsr_accumulator(&mut self) {
-    let mut data = self.register_a;
-    if data & 1 == 1 {
-      self.set_carry_flag();
-    } else {
-      self.clear_carry_flag();
-    }
-    data = data >> 1;
-    self.set_register_a(data)
-  }
-
-  fn lsr(&mut self, mode: &AddressingMode) -> u8 {
-    let addr = self.get_operand_address(mode);
-    let mut data = self.mem_read(addr);
-    if data & 1 == 1 {
-      self.set_carry_flag();
-    } else {
-      self.clear_carry_flag();
-    }
-    data = data >> 1;
-    self.mem_write(addr, data);
-    self.update_zero_and_negative_flags(data);
-    data
-  }
-
-  fn rol(&mut self, mode: &AddressingMode) -> u8 {
-    let addr = self.get_operand_address(mode);
-    let mut data = self.mem_read(addr);
-    let old_carry = self.status.contains(CpuFlags::CARRY);
-
-    if data >> 7 == 1 {
-      self.set_carry_flag();
-    } else {
-      self.clear_carry_flag();
-    }
-    data = data << 1;
-    if old_carry {
-      data = data | 1;
-    }
-    self.mem_write(addr, data);
-    self.update_zero_and_negative_flags(data);
-    data
-  }
-
-  fn rol_accumulator(&mut self) {
-    let mut data = self.register_a;
-    let old_carry = self.status.contains(CpuFlags::CARRY);
-
-    if data >> 7 == 1 {
-      self.set_carry_flag();
-    } else {
-      self.clear_carry_flag();
-    }
-    data = data << 1;
-    if old_carry {
-      data = data | 1;
-    }
-    self.set_register_a(data);
-  }
-
-  fn ror(&mut self, mode: &AddressingMode) -> u8 {
-    let addr = self.get_operand_address(mode);
-    let mut data = self.mem_read(addr);
-    let old_carry = self.status.contains(CpuFlags::CARRY);
-
-    if data & 1 == 1 {
-      self.set_carry_flag();
-    } else {
-      self.clear_carry_flag();
-    }
-    data = data >> 1;
-    if old_carry {
-      data = data | 0b10000000;
-    }
-    self.mem_write(addr, data);
-    self.update_zero_and_negative_flags(data);
-    data
-  }
-
-  fn ror_accumulator(&mut self) {
-    let mut data = self.register_a;
-    let old_carry = self.status.contains(CpuFlags::CARRY);
-
-    if data & 1 == 1 {
-      self.set_carry_flag();
-    } else {
-      self.clear_carry_flag();
-    }
-    data = data >> 1;
-    if old_carry {
-      data = data | 0b10000000;
-    }
-    self.set_register_a(data);
-  }
-
-  fn inc(&mut self, mode: &AddressingMode) -> u8 {
-    let addr = self.get_operand_address(mode);
-    let mut data = self.mem_read(addr);
-    data = data.wrapping_add(1);
-    self.mem_write(addr, data);
-    self.update_zero_and_negative_flags(data);
-    data
-  }
-
-  fn dey(&mut self) {
-    self.register_y = self.register_y.wrapping_sub(1);
-    self.update_zero_and_negative_flags(self.register_y);
-  }
-
-  fn dex(&mut self) {
-    self.register_x = self.register_x.wrapping_sub(1);
-    self.update_zero_and_negative_flags(self.register_x);
-  }
-
-  fn dec(&mut self, mode: &AddressingMode) -> u8 {
-    let addr = self.get_operand_address(mode);
-    let mut data = self.mem_read(addr);
-    data = data.wrapping_sub(1);
-    self.mem_write(addr, data);
-    self.update_zero_and_negative_flags(data);
-    data
-  }
-
-  fn pla(&mut self) {
-    let data = self.stack_pop();
-    self.set_register_a(data);
-  }
-
-  fn plp(&mut self) {
-    self.status.bits = self.stack_pop();
-    self.status.remove(CpuFlags::BREAK);
-    self.status.insert(CpuFlags::BREAK2);
-  }
-
-  fn php(&mut self) {
-    //http://wiki.nesdev.com/w/index.php/CPU_status_flag_behavior
-    let mut flags = self.status.clone();
-    flags.insert(CpuFlags::BREAK);
-    flags.insert(CpuFlags::BREAK2);
-    self.stack_push(flags.bits());
-  }
-
-  fn bit(&mut self, mode: &AddressingMode) {
-    let addr = self.get_operand_address(mode);
-    let data = self.mem_read(addr);
-    let and = self.register_a & data;
-    if and == 0 {
-      self.status.insert(CpuFlags::ZERO);
-    } else {
-      self.status.remove(CpuFlags::ZERO);
-    }
-
-    self.status.set(CpuFlags::NEGATIVE, data & 0b10000000 > 0);
-    self.status.set(CpuFlags::OVERFLOW, data & 0b01000000 > 0);
-  }
-
-  fn compare(&mut self, mode: &AddressingMode, compare_with: u8) {
-    let addr = self.get_operand_address(mode);
-    let data = self.mem_read(addr);
-    if data <= compare_with {
-      self.status.insert(CpuFlags::CARRY);
-    } else {
-      self.status.remove(CpuFlags::CARRY);
-    }
-
-    self.update_zero_and_negative_flags(compare_with.wrapping_sub(data));
-  }
-
-  fn branch(&mut self, condition: bool) {
-    if condition {
-      let jump: i8 = self.mem_read(self.program_counter) as i8;
-      let jump_addr = self
-        .program_counter
-        .wrapping_add(1)
-        .wrapping_add(jump as u16);
-
-      self.program_counter = jump_addr;
-    }
-  }
-}
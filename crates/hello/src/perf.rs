@@ -0,0 +1,91 @@
+use crate::nes::profile::ComponentTimes;
+use wasm_bindgen::prelude::*;
+
+// how long FPS is averaged over
+const FPS_WINDOW_MS: f64 = 1000.0;
+
+/// How the last frame went, for performance HUDs and for putting numbers
+/// on regressions. Averages when one call ran several frames.
+#[wasm_bindgen]
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct PerfCounters {
+  /// Emulated CPU cycles.
+  pub cpu_cycles: u32,
+  /// Real time it took to run, in milliseconds.
+  pub frame_ms: f64,
+  /// How `frame_ms` splits up, with `Emulator::set_profiling` on (all 0
+  /// otherwise). The CPU gets what the PPU and APU didn't take.
+  pub cpu_ms: f64,
+  pub ppu_ms: f64,
+  pub apu_ms: f64,
+  /// The audio buffer from 0 (empty) to 1 (full).
+  pub audio_fill: f64,
+  /// Milliseconds of sound queued ahead of the audio callback, to compare
+  /// with `Emulator::audio_latency`.
+  pub audio_latency_ms: f64,
+  /// Frames run per real second, over the last second or so.
+  pub fps: f64,
+}
+
+/// The audio buffer after a run: how full, 0 to 1, and how much sound
+/// that is.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct AudioQueue {
+  pub fill: f64,
+  pub latency_ms: f64,
+}
+
+/// Turns what the core reports into `PerfCounters`. The core keeps no
+/// time, so the frontend passes timestamps in, like `SessionTracker`.
+#[derive(Debug, Default)]
+pub struct PerfMeter {
+  counters: PerfCounters,
+  window_start_ms: Option<f64>,
+  window_frames: u32,
+}
+
+impl PerfMeter {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// One call that ran `frames` frames and `cycles` CPU cycles from
+  /// `start_ms` to `end_ms`, leaving the audio buffer at `audio`.
+  pub fn record(
+    &mut self,
+    start_ms: f64,
+    end_ms: f64,
+    frames: u32,
+    cycles: u64,
+    times: ComponentTimes,
+    audio: AudioQueue,
+  ) {
+    self.counters.audio_fill = audio.fill;
+    self.counters.audio_latency_ms = audio.latency_ms;
+    let window_start = *self.window_start_ms.get_or_insert(start_ms);
+    self.window_frames += frames;
+    if end_ms - window_start >= FPS_WINDOW_MS {
+      self.counters.fps = self.window_frames as f64 * 1000.0 / (end_ms - window_start);
+      self.window_start_ms = Some(end_ms);
+      self.window_frames = 0;
+    }
+    if frames == 0 {
+      return;
+    }
+    let per_frame = |total: f64| total / frames as f64;
+    let elapsed = end_ms - start_ms;
+    self.counters.cpu_cycles = (cycles / frames as u64) as u32;
+    self.counters.frame_ms = per_frame(elapsed);
+    self.counters.ppu_ms = per_frame(times.ppu_ms);
+    self.counters.apu_ms = per_frame(times.apu_ms);
+    self.counters.cpu_ms = if times == ComponentTimes::default() {
+      0.0
+    } else {
+      per_frame((elapsed - times.ppu_ms - times.apu_ms).max(0.0))
+    };
+  }
+
+  pub fn counters(&self) -> PerfCounters {
+    self.counters
+  }
+}
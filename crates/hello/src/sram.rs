@@ -0,0 +1,225 @@
+use crate::storage::{StorageBackend, StorageError};
+
+/*
+  Battery saves are written as a journal instead of whole blobs, so a tab
+  closed or a crash in the middle of a write can't leave a half-written
+  save behind.
+
+  Every flush stores each page that changed since the last one as its own
+  entry, "sram-journal-<sequence>", numbered one after the other. Loading
+  starts from the newest base snapshot and replays entries in order,
+  stopping at the first missing or corrupt one: a torn write only costs the
+  changes made after it.
+
+  When the journal grows long it's folded into a new base snapshot. There
+  are two base slots, written alternately, so the previous base is intact
+  until the new one is complete; entries already in a base are removed
+  afterwards, and leftovers of an interrupted cleanup on the next load.
+
+  Every blob ends with an FNV-1a checksum of what comes before it.
+    base:  sequence u64, RAM, checksum u32
+    entry: sequence u64, page u16, page bytes, checksum u32
+*/
+
+const BASE_SLOTS: [&str; 2] = ["sram-a", "sram-b"];
+const PAGE_SIZE: usize = 256;
+// entries kept before folding them into a base
+const MAX_JOURNAL_ENTRIES: u64 = 64;
+
+fn entry_slot(sequence: u64) -> String {
+  format!("sram-journal-{}", sequence)
+}
+
+fn checksum(bytes: &[u8]) -> u32 {
+  bytes.iter().fold(0x811c_9dc5, |hash: u32, &byte| {
+    (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+  })
+}
+
+fn seal(mut blob: Vec<u8>) -> Vec<u8> {
+  let sum = checksum(&blob);
+  blob.extend_from_slice(&sum.to_le_bytes());
+  blob
+}
+
+/// The payload of a blob whose checksum matches.
+fn unseal(blob: &[u8]) -> Option<&[u8]> {
+  if blob.len() < 4 {
+    return None;
+  }
+  let (payload, sum) = blob.split_at(blob.len() - 4);
+  let mut expected = [0; 4];
+  expected.copy_from_slice(sum);
+  if checksum(payload) == u32::from_le_bytes(expected) {
+    Some(payload)
+  } else {
+    None
+  }
+}
+
+fn sequence_of(payload: &[u8]) -> u64 {
+  let mut sequence = [0; 8];
+  sequence.copy_from_slice(&payload[..8]);
+  u64::from_le_bytes(sequence)
+}
+
+/// Battery RAM of one ROM as last persisted, and the journal position to
+/// continue from.
+pub struct SramJournal {
+  rom_hash: String,
+  saved: Vec<u8>,
+  // sequence of the newest base, and of the newest entry
+  base_sequence: u64,
+  sequence: u64,
+  base_slot: usize,
+}
+
+impl SramJournal {
+  /// Rebuild the `size` bytes of battery RAM stored for `rom_hash`, zeros
+  /// when nothing was saved yet.
+  pub fn load(
+    storage: &mut dyn StorageBackend,
+    rom_hash: &str,
+    size: usize,
+  ) -> Result<Self, StorageError> {
+    let mut journal = SramJournal {
+      rom_hash: rom_hash.to_string(),
+      saved: vec![0; size],
+      base_sequence: 0,
+      sequence: 0,
+      base_slot: 1,
+    };
+
+    let mut newest: Option<(usize, u64, Vec<u8>)> = None;
+    for (slot, name) in BASE_SLOTS.iter().enumerate() {
+      let blob = match storage.get(rom_hash, name)? {
+        Some(blob) => blob,
+        None => continue,
+      };
+      if let Some(payload) = unseal(&blob).filter(|payload| payload.len() >= 8) {
+        let sequence = sequence_of(payload);
+        if newest
+          .as_ref()
+          .map_or(true, |(_, newest, _)| sequence > *newest)
+        {
+          newest = Some((slot, sequence, payload[8..].to_vec()));
+        }
+      }
+    }
+    if let Some((slot, sequence, ram)) = newest {
+      let len = ram.len().min(size);
+      journal.saved[..len].copy_from_slice(&ram[..len]);
+      journal.base_sequence = sequence;
+      journal.base_slot = slot;
+    }
+    journal.sequence = journal.base_sequence;
+
+    // entries a base already holds, left by an interrupted cleanup
+    journal.remove_entries_through(storage, journal.base_sequence)?;
+
+    while let Some(blob) = storage.get(rom_hash, &entry_slot(journal.sequence + 1))? {
+      if !journal.replay(&blob) {
+        break;
+      }
+      journal.sequence += 1;
+    }
+    Ok(journal)
+  }
+
+  fn replay(&mut self, blob: &[u8]) -> bool {
+    let payload = match unseal(blob) {
+      Some(payload) if payload.len() == 8 + 2 + PAGE_SIZE => payload,
+      _ => return false,
+    };
+    if sequence_of(payload) != self.sequence + 1 {
+      return false;
+    }
+    let page = u16::from_le_bytes([payload[8], payload[9]]) as usize;
+    let start = page * PAGE_SIZE;
+    if start >= self.saved.len() {
+      return false;
+    }
+    let end = (start + PAGE_SIZE).min(self.saved.len());
+    self.saved[start..end].copy_from_slice(&payload[10..10 + end - start]);
+    true
+  }
+
+  /// Battery RAM as of the last flush.
+  pub fn sram(&self) -> &[u8] {
+    &self.saved
+  }
+
+  /// Journal entries written since the last base snapshot.
+  pub fn pending_entries(&self) -> u64 {
+    self.sequence - self.base_sequence
+  }
+
+  /// Persist the pages of `sram` that changed since the last flush.
+  /// Returns how many were written.
+  pub fn flush(
+    &mut self,
+    storage: &mut dyn StorageBackend,
+    sram: &[u8],
+  ) -> Result<usize, StorageError> {
+    let mut written = 0;
+    for (page, chunk) in sram.chunks(PAGE_SIZE).enumerate() {
+      let start = page * PAGE_SIZE;
+      if start >= self.saved.len() {
+        break;
+      }
+      let end = (start + chunk.len()).min(self.saved.len());
+      let chunk = &chunk[..end - start];
+      if self.saved[start..end] == *chunk {
+        continue;
+      }
+      let sequence = self.sequence + 1;
+      let mut entry = Vec::with_capacity(8 + 2 + PAGE_SIZE + 4);
+      entry.extend_from_slice(&sequence.to_le_bytes());
+      entry.extend_from_slice(&(page as u16).to_le_bytes());
+      entry.extend_from_slice(chunk);
+      // the last page of an odd-sized RAM is padded
+      entry.resize(8 + 2 + PAGE_SIZE, 0);
+      storage.put(&self.rom_hash, &entry_slot(sequence), &seal(entry))?;
+
+      self.saved[start..end].copy_from_slice(chunk);
+      self.sequence = sequence;
+      written += 1;
+    }
+    if self.pending_entries() >= MAX_JOURNAL_ENTRIES {
+      self.compact(storage)?;
+    }
+    Ok(written)
+  }
+
+  /// Fold the journal into a new base snapshot.
+  pub fn compact(&mut self, storage: &mut dyn StorageBackend) -> Result<(), StorageError> {
+    if self.pending_entries() == 0 {
+      return Ok(());
+    }
+    let slot = 1 - self.base_slot;
+    let mut base = Vec::with_capacity(8 + self.saved.len() + 4);
+    base.extend_from_slice(&self.sequence.to_le_bytes());
+    base.extend_from_slice(&self.saved);
+    storage.put(&self.rom_hash, BASE_SLOTS[slot], &seal(base))?;
+    self.base_slot = slot;
+    self.base_sequence = self.sequence;
+    self.remove_entries_through(storage, self.sequence)
+  }
+
+  // entries are removed oldest first, so whatever an interruption leaves
+  // is a run ending at `last`
+  fn remove_entries_through(
+    &self,
+    storage: &mut dyn StorageBackend,
+    last: u64,
+  ) -> Result<(), StorageError> {
+    let mut first = last;
+    while first > 0 && storage.get(&self.rom_hash, &entry_slot(first))?.is_some() {
+      first -= 1;
+    }
+    for sequence in first + 1..=last {
+      storage.remove(&self.rom_hash, &entry_slot(sequence))?;
+    }
+    Ok(())
+  }
+}
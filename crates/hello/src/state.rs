@@ -0,0 +1,168 @@
+use crate::error::FlemuError;
+use crate::nes::desync::{self, ChecksumTrace};
+use crate::nes::rewind::Rewind;
+use crate::nes::savestate::{StateReader, StateWriter};
+use crate::rng::SeededRng;
+use crate::{js_object, rewind_capacity, Emulator};
+use js_sys::Array;
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+impl Emulator {
+  /// Go back to the last state kept for rewinding, `interval` frames
+  /// apart as set by `set_rewind`. Call once per frame while the rewind
+  /// key is held, instead of `run_frame`. False once nothing older is
+  /// left.
+  pub fn rewind(&mut self) -> bool {
+    match self.rewind.pop() {
+      Some(state) => self.load_state(&state).is_ok(),
+      None => false,
+    }
+  }
+
+  /// Keep a state every `interval` frames for the last `seconds`, 0
+  /// seconds to stop rewinding. Forgets what was kept so far.
+  pub fn set_rewind(&mut self, seconds: u32, interval: u32) {
+    self.rewind = Rewind::new(interval, rewind_capacity(seconds, interval));
+  }
+
+  /// Memory taken by the rewind states and their thumbnails, in bytes.
+  pub fn rewind_bytes(&self) -> usize {
+    self.rewind.bytes()
+  }
+
+  /// Rewind states kept, for `rewind_thumbnail` and `seek_rewind` to
+  /// count back into from the newest, 0.
+  pub fn rewind_len(&self) -> usize {
+    self.rewind.len()
+  }
+
+  /// The picture rewind state `back` was taken at, 64x60 RGBA, for a
+  /// filmstrip to scrub through. Undefined past the oldest.
+  pub fn rewind_thumbnail(&self, back: usize) -> Option<Vec<u8>> {
+    self
+      .rewind
+      .thumbnail(back)
+      .map(|thumbnail| thumbnail.rgba(&self.palette))
+  }
+
+  /// Rewind state `back` as a `save_state` snapshot, leaving it kept.
+  pub fn rewind_state(&self, back: usize) -> Option<Vec<u8>> {
+    self.rewind.get(back)
+  }
+
+  /// Go back to rewind state `back` in one go, forgetting the newer ones
+  /// as `rewind` would have on the way. False past the oldest.
+  pub fn seek_rewind(&mut self, back: usize) -> bool {
+    match self.rewind.seek(back) {
+      Some(state) => self.load_state(&state).is_ok(),
+      None => false,
+    }
+  }
+
+  /// Snapshot of the whole console (CPU, RAM, PPU, APU, cartridge board,
+  /// random source), to resume from with `load_state` any time later.
+  pub fn save_state(&self) -> Vec<u8> {
+    let mut w = StateWriter::new(self.cpu.bus.cartridge_checksum());
+    self.cpu.save_state(&mut w);
+    w.finish()
+  }
+
+  /// Resume from a `save_state` snapshot of the same ROM. On an error
+  /// ("state-version", "bad-state") the running game is left untouched.
+  /// Stops a movie being played back. One being recorded goes on from
+  /// the state's frame, dropping what came after and counting a
+  /// re-record; a state from outside the recording stops it too.
+  pub fn load_state(&mut self, state: &[u8]) -> Result<(), JsValue> {
+    self.attract_input();
+    let mut r =
+      StateReader::new(state, self.cpu.bus.cartridge_checksum()).map_err(FlemuError::from)?;
+    let mut cpu = self.cpu.clone();
+    cpu.load_state(&mut r).map_err(FlemuError::from)?;
+    r.finish().map_err(FlemuError::from)?;
+    self.cpu = cpu;
+    self.time_travel.clear();
+    if let Some(session) = &mut self.movie {
+      session.rerecord(self.cpu.bus.ppu.frame_count);
+    }
+    Ok(())
+  }
+
+  /// Hash of the whole machine, as a savestate of it would be, for
+  /// checking two runs are still the same. The random source isn't in
+  /// it.
+  pub fn state_hash(&self) -> u64 {
+    desync::state_hash(&self.cpu)
+  }
+
+  /// Keep `state_hash` at the start of every frame from now on, or stop
+  /// and forget them. Off by default.
+  pub fn set_checksum_trace(&mut self, enabled: bool) {
+    self.checksum_trace = if enabled {
+      Some(ChecksumTrace::new())
+    } else {
+      None
+    };
+  }
+
+  /// The hashes kept since `set_checksum_trace`, a `<frame>\t<hash>`
+  /// line each; undefined when not tracing.
+  pub fn checksum_trace(&self) -> Option<String> {
+    self.checksum_trace.as_ref().map(ChecksumTrace::to_text)
+  }
+
+  /// The first frame whose hash differs from the one in `trace`, another
+  /// run's `checksum_trace`, comparing the frames both have; null while
+  /// they agree or when not tracing.
+  pub fn checksum_divergence(&self, trace: &str) -> Result<Option<f64>, JsValue> {
+    let theirs = ChecksumTrace::from_text(trace).map_err(FlemuError::from)?;
+    Ok(
+      self
+        .checksum_trace
+        .as_ref()
+        .and_then(|ours| ours.first_divergence(&theirs))
+        .map(|frame| frame as f64),
+    )
+  }
+
+  /// What differs between two `save_state` snapshots of this cartridge:
+  /// `[{ subsystem, name, first, count }]` in a fixed order, `first`
+  /// being the address or offset of the first byte that differs and
+  /// `count` how many do. Empty when they're the same.
+  pub fn diff_states(&self, a: &[u8], b: &[u8]) -> Result<JsValue, JsValue> {
+    let differences: Array = desync::diff_states(&self.cpu, a, b)
+      .map_err(FlemuError::from)?
+      .iter()
+      .map(|difference| {
+        js_object(&[
+          ("subsystem", difference.subsystem.id().into()),
+          ("name", difference.subsystem.name().into()),
+          ("first", (difference.first as u32).into()),
+          ("count", (difference.count as u32).into()),
+        ])
+      })
+      .collect();
+    Ok(differences.into())
+  }
+
+  /// Reseed the emulator's random source, making RAM randomization and
+  /// other frontend randomness reproducible.
+  pub fn set_seed(&mut self, seed: u64) {
+    self.cpu.rng = SeededRng::new(seed);
+  }
+
+  /// Internal state of the random source, which savestates carry too.
+  pub fn rng_state(&self) -> u64 {
+    self.cpu.rng.state()
+  }
+
+  pub fn set_rng_state(&mut self, state: u64) {
+    self.cpu.rng = SeededRng::from_state(state);
+  }
+
+  /// Fill the 2 KiB of internal RAM with random values, like a console that
+  /// was just powered on.
+  pub fn randomize_ram(&mut self) {
+    self.cpu.randomize_ram();
+  }
+}
@@ -0,0 +1,102 @@
+use crate::storage::{StorageBackend, StorageError};
+
+// storage slot the per-ROM statistics live in
+const STATS_SLOT: &str = "stats";
+
+/// Lifetime statistics of one ROM, for "recently played" library views.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct RomStats {
+  pub playtime_ms: u64,
+  pub frames: u64,
+  pub saves: u32,
+  /// unix timestamp in ms, 0 when never played
+  pub last_played_ms: u64,
+}
+
+impl RomStats {
+  const ENCODED_LEN: usize = 8 + 8 + 4 + 8;
+
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(Self::ENCODED_LEN);
+    bytes.extend_from_slice(&self.playtime_ms.to_le_bytes());
+    bytes.extend_from_slice(&self.frames.to_le_bytes());
+    bytes.extend_from_slice(&self.saves.to_le_bytes());
+    bytes.extend_from_slice(&self.last_played_ms.to_le_bytes());
+    bytes
+  }
+
+  pub fn from_bytes(bytes: &[u8]) -> Option<RomStats> {
+    if bytes.len() != Self::ENCODED_LEN {
+      return None;
+    }
+    let u64_at = |at: usize| {
+      let mut b = [0; 8];
+      b.copy_from_slice(&bytes[at..at + 8]);
+      u64::from_le_bytes(b)
+    };
+    let mut saves = [0; 4];
+    saves.copy_from_slice(&bytes[16..20]);
+    Some(RomStats {
+      playtime_ms: u64_at(0),
+      frames: u64_at(8),
+      saves: u32::from_le_bytes(saves),
+      last_played_ms: u64_at(20),
+    })
+  }
+}
+
+/// Accumulates statistics for the ROM being played and persists them
+/// through a `StorageBackend`.
+///
+/// The core has no wall clock of its own (it must stay deterministic), so
+/// the frontend passes timestamps in.
+pub struct SessionTracker {
+  rom_hash: String,
+  stats: RomStats,
+  started_ms: Option<u64>,
+}
+
+impl SessionTracker {
+  /// Pick up the stored statistics of `rom_hash`, starting from zero when
+  /// none were saved before.
+  pub fn load(storage: &dyn StorageBackend, rom_hash: &str) -> Result<Self, StorageError> {
+    let stats = storage
+      .get(rom_hash, STATS_SLOT)?
+      .and_then(|bytes| RomStats::from_bytes(&bytes))
+      .unwrap_or_default();
+    Ok(SessionTracker {
+      rom_hash: rom_hash.to_string(),
+      stats,
+      started_ms: None,
+    })
+  }
+
+  pub fn stats(&self) -> RomStats {
+    self.stats
+  }
+
+  pub fn start(&mut self, now_ms: u64) {
+    self.started_ms = Some(now_ms);
+    self.stats.last_played_ms = now_ms;
+  }
+
+  /// Close the running session (if any) and add its duration to playtime.
+  pub fn stop(&mut self, now_ms: u64) {
+    if let Some(started_ms) = self.started_ms.take() {
+      self.stats.playtime_ms += now_ms.saturating_sub(started_ms);
+      self.stats.last_played_ms = now_ms;
+    }
+  }
+
+  pub fn add_frames(&mut self, frames: u64) {
+    self.stats.frames += frames;
+  }
+
+  pub fn record_save(&mut self) {
+    self.stats.saves += 1;
+  }
+
+  pub fn persist(&self, storage: &mut dyn StorageBackend) -> Result<(), StorageError> {
+    storage.put(&self.rom_hash, STATS_SLOT, &self.stats.to_bytes())
+  }
+}
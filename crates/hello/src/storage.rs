@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+use std::fmt;
+
+/// Failure reported by a storage backend (quota exceeded, network down, ...)
+#[derive(Debug, Clone, PartialEq)]
+pub struct StorageError(pub String);
+
+impl fmt::Display for StorageError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "storage error: {}", self.0)
+  }
+}
+
+impl std::error::Error for StorageError {}
+
+/// Persistence used by savestates and battery-backed SRAM.
+///
+/// Blobs are keyed by the hash of the ROM they belong to and a slot name
+/// (e.g. `"sram"` or `"state-1"`), so frontends can back this with
+/// IndexedDB, the filesystem or a remote service without the core caring.
+pub trait StorageBackend {
+  fn get(&self, rom_hash: &str, slot: &str) -> Result<Option<Vec<u8>>, StorageError>;
+
+  fn put(&mut self, rom_hash: &str, slot: &str, data: &[u8]) -> Result<(), StorageError>;
+
+  fn remove(&mut self, rom_hash: &str, slot: &str) -> Result<(), StorageError>;
+}
+
+/// Keeps everything in a HashMap, lost when dropped. Useful as a default
+/// and in tests.
+#[derive(Default)]
+pub struct MemoryStorage {
+  blobs: HashMap<(String, String), Vec<u8>>,
+}
+
+impl MemoryStorage {
+  pub fn new() -> Self {
+    MemoryStorage::default()
+  }
+}
+
+impl StorageBackend for MemoryStorage {
+  fn get(&self, rom_hash: &str, slot: &str) -> Result<Option<Vec<u8>>, StorageError> {
+    Ok(
+      self
+        .blobs
+        .get(&(rom_hash.to_string(), slot.to_string()))
+        .cloned(),
+    )
+  }
+
+  fn put(&mut self, rom_hash: &str, slot: &str, data: &[u8]) -> Result<(), StorageError> {
+    self
+      .blobs
+      .insert((rom_hash.to_string(), slot.to_string()), data.to_vec());
+    Ok(())
+  }
+
+  fn remove(&mut self, rom_hash: &str, slot: &str) -> Result<(), StorageError> {
+    self.blobs.remove(&(rom_hash.to_string(), slot.to_string()));
+    Ok(())
+  }
+}
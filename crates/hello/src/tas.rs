@@ -0,0 +1,147 @@
+use crate::error::FlemuError;
+use crate::nes::joypad::JoypadButton;
+use crate::nes::movie::{Movie, MovieStart};
+use crate::nes::timing::Timing;
+use crate::{invalid, Emulator, MovieMode, MovieSession};
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+impl Emulator {
+  /// TAS mode: nothing about the game depends on the clock of the machine
+  /// running it. Turbo and run-ahead are ignored, leaving frames to go one
+  /// at a time through `advance_frame` (or `run_frame`); the emulation
+  /// itself, sound included, only ever depends on the input.
+  pub fn set_tas_mode(&mut self, on: bool) {
+    self.tas = on;
+  }
+
+  pub fn tas_mode(&self) -> bool {
+    self.tas
+  }
+
+  /// Frame advance: hold `input` for one frame, a byte of buttons per
+  /// controller from player 1 (bit 0 A, B, Select, Start, Up, Down, Left,
+  /// bit 7 Right), then run it like `run_frame`. Controllers not in
+  /// `input` keep what they hold. A movie being played overrides it, one
+  /// being recorded takes it down.
+  pub fn advance_frame(&mut self, input: &[u8]) -> Result<JsValue, JsValue> {
+    self.attract_input();
+    for (joypad, &buttons) in self.cpu.bus.joypads.iter_mut().zip(input) {
+      joypad.set_button_pressed_status(JoypadButton::all(), false);
+      joypad.set_button_pressed_status(JoypadButton::from_bits_truncate(buttons), true);
+    }
+    self.run_frame()
+  }
+
+  /// Whether the last frame was a lag frame: the game never read the
+  /// controllers, so nothing pressed during it counted.
+  pub fn lag_frame(&self) -> bool {
+    self.lag_frame
+  }
+
+  /// Lag frames since the ROM was loaded.
+  pub fn lag_frames(&self) -> u32 {
+    self.lag_frames
+  }
+
+  /// How many times a state was loaded into the movie being recorded, as
+  /// FM2 keeps it. 0 without a movie.
+  pub fn rerecord_count(&self) -> u32 {
+    self
+      .movie
+      .as_ref()
+      .map_or(0, |session| session.movie.rerecords)
+  }
+
+  /// Start recording the controllers frame by frame, from power-on (the
+  /// console restarts, as TAS movies do) or from a savestate of right
+  /// now. Replaces the last movie.
+  pub fn record_movie(&mut self, from_power_on: bool) -> Result<(), JsValue> {
+    self.attract_input();
+    if self.rom.is_none() {
+      return Err(invalid("no cartridge inserted".to_string()));
+    }
+    let start = if from_power_on {
+      let four_score = self.cpu.bus.four_score();
+      self.power_on(four_score)?;
+      MovieStart::PowerOn
+    } else {
+      MovieStart::State(self.save_state())
+    };
+    let pal = self.cpu.bus.timing() == Timing::Pal;
+    let mut movie = Movie::new(start, self.cpu.bus.four_score(), pal);
+    movie.latch_controllers(&self.cpu);
+    self.movie = Some(MovieSession {
+      movie,
+      mode: MovieMode::Recording,
+      frame: 0,
+      at: None,
+      first: None,
+      reset: false,
+    });
+    Ok(())
+  }
+
+  /// Play back an FM2 movie, e.g. one exported from FCEUX, from where it
+  /// starts. Until it's over its input replaces the controllers'; then
+  /// `movie_mode` goes back to "off". Throws a "movie" error for text
+  /// that isn't FM2 or needs something not emulated.
+  pub fn play_movie(&mut self, fm2: &str) -> Result<(), JsValue> {
+    let movie = Movie::from_fm2(fm2).map_err(FlemuError::from)?;
+    // before its timing goes in
+    self.attract_input();
+    match &movie.start {
+      MovieStart::PowerOn => {
+        let pal = self.cpu.bus.timing() == Timing::Pal;
+        if movie.pal != pal {
+          let timing = if movie.pal { Timing::Pal } else { Timing::Ntsc };
+          self.cpu.bus.set_timing_override(Some(timing));
+        }
+        self.power_on(movie.four_score)?;
+      }
+      MovieStart::State(state) => self.load_state(state)?,
+    }
+    movie
+      .restore_controllers(&mut self.cpu)
+      .map_err(FlemuError::from)?;
+    self.movie = Some(MovieSession {
+      movie,
+      mode: MovieMode::Playing,
+      frame: 0,
+      at: None,
+      first: None,
+      reset: false,
+    });
+    Ok(())
+  }
+
+  /// Stop recording or playing. The movie is kept for `movie_fm2`.
+  pub fn stop_movie(&mut self) {
+    if let Some(session) = &mut self.movie {
+      session.mode = MovieMode::Off;
+    }
+  }
+
+  /// "recording", "playing" or "off".
+  pub fn movie_mode(&self) -> String {
+    let mode = self
+      .movie
+      .as_ref()
+      .map_or(MovieMode::Off, |session| session.mode);
+    mode.id().to_string()
+  }
+
+  /// Frames recorded or played back so far.
+  pub fn movie_frame(&self) -> u32 {
+    self
+      .movie
+      .as_ref()
+      .map_or(0, |session| session.frame as u32)
+  }
+
+  /// The last movie recorded or played, as FM2 text. One that starts from
+  /// a savestate carries it along, which only this emulator can play.
+  pub fn movie_fm2(&self) -> Option<String> {
+    self.movie.as_ref().map(|session| session.movie.to_fm2())
+  }
+}
@@ -0,0 +1,203 @@
+use crate::error::FlemuError;
+use crate::nes::palette::{Palette, PalettePreset};
+use crate::nes::ppu::Frame;
+use crate::video_dump::VideoDump;
+use crate::webgl::{Aspect, Filter, Renderer};
+use crate::{gif, invalid, rewind_capacity, video_dump, Emulator, GIF_INTERVAL};
+use js_sys::{Function, Uint8Array};
+use wasm_bindgen::prelude::*;
+use web_sys::HtmlCanvasElement;
+
+#[wasm_bindgen]
+impl Emulator {
+  /// Last frame rendered by the PPU: 256×240 NES palette indices, row by
+  /// row. Without a cartridge this is the splash screen, advanced by a
+  /// frame on every call.
+  pub fn frame_buffer(&mut self) -> Vec<u8> {
+    self.screen().data.clone()
+  }
+
+  /// `frame_buffer` as RGBA, through the current palette and with the
+  /// emphasis bits applied.
+  pub fn frame_rgba(&mut self) -> Vec<u8> {
+    let screen = self.screen().clone();
+    self.palette.frame_rgba(&screen)
+  }
+
+  /// Draw frames on `canvas` from now on, through WebGL2. The canvas'
+  /// drawing buffer is kept at its size on the page; size it with CSS.
+  pub fn attach_canvas(&mut self, canvas: HtmlCanvasElement) -> Result<(), JsValue> {
+    self.renderer = Some(Renderer::new(&canvas)?);
+    Ok(())
+  }
+
+  /// Put `frame_rgba` on the attached canvas, if any.
+  pub fn render(&mut self) -> Result<(), JsValue> {
+    if self.renderer.is_none() {
+      return Ok(());
+    }
+    let rgba = self.frame_rgba();
+    self
+      .renderer
+      .as_ref()
+      .unwrap()
+      .draw(&rgba, self.video_filter, self.video_options)
+  }
+
+  /// Switch `render` to one of `video_filters`. Returns false for an
+  /// unknown id.
+  pub fn set_video_filter(&mut self, filter: &str) -> bool {
+    match Filter::from_id(filter) {
+      Some(filter) => {
+        self.video_filter = filter;
+        true
+      }
+      None => false,
+    }
+  }
+
+  pub fn video_filter(&self) -> String {
+    self.video_filter.id().to_string()
+  }
+
+  /// Have `render` leave out the 8 lines at the top and at the bottom
+  /// that TVs hid.
+  pub fn set_crop_overscan(&mut self, crop: bool) {
+    self.video_options.crop_overscan = crop;
+  }
+
+  pub fn crop_overscan(&self) -> bool {
+    self.video_options.crop_overscan
+  }
+
+  /// Draw pixels in one of the `aspect_ratios` shapes. Returns false for
+  /// an unknown id.
+  pub fn set_aspect_ratio(&mut self, aspect: &str) -> bool {
+    match Aspect::from_id(aspect) {
+      Some(aspect) => {
+        self.video_options.aspect = aspect;
+        true
+      }
+      None => false,
+    }
+  }
+
+  pub fn aspect_ratio(&self) -> String {
+    self.video_options.aspect.id().to_string()
+  }
+
+  /// Scale the picture by whole multiples only, centered on the canvas.
+  pub fn set_integer_scaling(&mut self, integer: bool) {
+    self.video_options.integer_scale = integer;
+  }
+
+  pub fn integer_scaling(&self) -> bool {
+    self.video_options.integer_scale
+  }
+
+  /// The current frame as PNG bytes. `crop_overscan` leaves out the 8
+  /// lines at the top and at the bottom that TVs hid. With `filtered` it's
+  /// the picture on the attached canvas, through the video filter and at
+  /// the canvas' size, rather than the 256x240 frame.
+  pub fn screenshot(
+    &mut self,
+    crop_overscan: Option<bool>,
+    filtered: Option<bool>,
+  ) -> Result<Vec<u8>, JsValue> {
+    let (width, height, rgba) = if filtered.unwrap_or(false) {
+      let renderer = self
+        .renderer
+        .as_ref()
+        .ok_or_else(|| invalid("a filtered screenshot needs attach_canvas".to_string()))?;
+      renderer.read_pixels(self.video_filter, self.video_options)?
+    } else {
+      let rgba = self.frame_rgba();
+      (Frame::WIDTH as u32, Frame::HEIGHT as u32, rgba)
+    };
+    // the canvas may have left them out already
+    let cropped = filtered.unwrap_or(false) && self.video_options.crop_overscan;
+    if !crop_overscan.unwrap_or(false) || cropped {
+      return Ok(video_dump::encode_png(width, height, &rgba));
+    }
+    // the same share of a scaled picture
+    let lines = (video_dump::OVERSCAN_LINES as u32 * height / Frame::HEIGHT as u32) as usize;
+    let picture = (0, lines, width as usize, height as usize - 2 * lines);
+    let cropped = video_dump::crop_rgba(&rgba, width as usize, picture);
+    Ok(video_dump::encode_png(width, picture.3 as u32, &cropped))
+  }
+
+  /// Start handing every `every`th frame to `callback(frame, data)`, `data`
+  /// a Uint8Array of PNG bytes if `png`, else of RGBA pixels (256x240,
+  /// through the current palette). Frames are numbered by PPU frame count;
+  /// `poll_video_dump` has to be called at least once per emulated frame
+  /// for none to be missed.
+  pub fn start_video_dump(&mut self, every: u32, png: bool, callback: Function) {
+    self.video_dump = Some((VideoDump::new(every as u64), callback, png));
+  }
+
+  pub fn stop_video_dump(&mut self) {
+    self.video_dump = None;
+  }
+
+  /// Send the current frame to the dump callback if it's due. Returns
+  /// whether it was sent; callback errors are passed on.
+  pub fn poll_video_dump(&mut self) -> Result<bool, JsValue> {
+    let (dump, callback, png) = match &mut self.video_dump {
+      Some(video_dump) => video_dump,
+      None => return Ok(false),
+    };
+    let frame = self.cpu.bus.ppu.frame_count;
+    if !dump.should_dump(frame) {
+      return Ok(false);
+    }
+    let rgba = self.palette.frame_rgba(&self.cpu.bus.ppu.frame);
+    let data = if *png {
+      video_dump::encode_png(Frame::WIDTH as u32, Frame::HEIGHT as u32, &rgba)
+    } else {
+      rgba
+    };
+    callback.call2(
+      &JsValue::NULL,
+      &JsValue::from(frame as f64),
+      &Uint8Array::from(&data[..]),
+    )?;
+    Ok(true)
+  }
+
+  /// Keep the last `seconds` of frames for `export_gif`, 0 to keep none.
+  /// Forgets what was kept so far.
+  pub fn set_gif_seconds(&mut self, seconds: u32) {
+    self.recent_frames = gif::FrameHistory::new(rewind_capacity(seconds, GIF_INTERVAL));
+  }
+
+  /// The last `seconds` of gameplay, or as much as was kept, as a looping
+  /// GIF through the current palette, at every other frame. Frames fast
+  /// forward skipped drawing aren't in it.
+  pub fn export_gif(&self, seconds: f64) -> Result<Vec<u8>, JsValue> {
+    let fps = self.frame_rate() / GIF_INTERVAL as f64;
+    let frames = self
+      .recent_frames
+      .recent((seconds.max(0.0) * fps).round() as usize);
+    if frames.is_empty() {
+      return Err(invalid("no frames kept to make a GIF of".to_string()));
+    }
+    Ok(gif::encode_gif(&frames, &self.palette, fps))
+  }
+
+  /// Switch to one of `palette_presets`. Returns false for an unknown id.
+  pub fn set_palette(&mut self, preset: &str) -> bool {
+    match PalettePreset::from_id(preset) {
+      Some(preset) => {
+        self.palette = Palette::preset(preset);
+        true
+      }
+      None => false,
+    }
+  }
+
+  /// Use a custom .pal file.
+  pub fn load_palette(&mut self, bytes: &[u8]) -> Result<(), JsValue> {
+    self.palette = Palette::from_pal(bytes).map_err(FlemuError::from)?;
+    Ok(())
+  }
+}
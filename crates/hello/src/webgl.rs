@@ -0,0 +1,469 @@
+use crate::nes::ppu::Frame;
+use crate::video_dump::OVERSCAN_LINES;
+use js_sys::{Float32Array, Object, Reflect};
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{
+  window, HtmlCanvasElement, WebGl2RenderingContext as GL, WebGlProgram, WebGlShader, WebGlTexture,
+  WebGlUniformLocation, WebGlVertexArrayObject,
+};
+
+/*
+  Puts frames on a canvas: the RGBA frame is uploaded into a 256x240
+  texture and drawn as one quad covering the canvas. A frame is one
+  texSubImage2D call, where a 2d context would be filling it rectangle by
+  rectangle.
+
+  The canvas' drawing buffer follows its size on the page (in device
+  pixels), and the quad goes through one of the `Filter` fragment shaders
+  on its way there; the texture itself is always sampled nearest, the
+  filters do their own blending. `VideoOptions` decide which part of the
+  frame that is (all of it, or without the overscan lines) and how it's
+  fitted into the canvas.
+*/
+
+const VERTEX_SHADER: &str = r#"#version 300 es
+layout(location = 0) in vec2 position;
+// the part of the texture to show: x, y, width, height
+uniform vec4 source;
+out vec2 uv;
+void main() {
+  // clip space to texture space, row 0 at the top
+  uv = source.xy + vec2(position.x + 1.0, 1.0 - position.y) * 0.5 * source.zw;
+  gl_Position = vec4(position, 0.0, 1.0);
+}
+"#;
+const POSITION: u32 = 0;
+
+const NEAREST_SHADER: &str = r#"#version 300 es
+precision mediump float;
+in vec2 uv;
+uniform sampler2D frame;
+out vec4 color;
+void main() {
+  color = texture(frame, uv);
+}
+"#;
+
+// darker towards the edges of every source line
+const SCANLINES_SHADER: &str = r#"#version 300 es
+precision mediump float;
+in vec2 uv;
+uniform sampler2D frame;
+out vec4 color;
+void main() {
+  vec3 texel = texture(frame, uv).rgb;
+  float edge = abs(fract(uv.y * 240.0) - 0.5) * 2.0;
+  color = vec4(texel * mix(1.1, 0.5, edge * edge), 1.0);
+}
+"#;
+
+/*
+  Not a signal level simulation (that would need the palette indices and
+  emphasis bits, not RGB), but what makes composite look like composite:
+  in YIQ, luma is only softened a little while chroma is smeared over
+  about 3 pixels each way, and some chroma leaks back into luma at the
+  subcarrier phase. On the NES a pixel is 2/3 of a color cycle and a
+  scanline starts 1/3 of a cycle later than the one before, which gives
+  the diagonal fringing.
+*/
+const NTSC_SHADER: &str = r#"#version 300 es
+precision mediump float;
+in vec2 uv;
+uniform sampler2D frame;
+out vec4 color;
+const float PI = 3.14159265;
+const mat3 RGB_TO_YIQ = mat3(0.299, 0.596, 0.211, 0.587, -0.274, -0.523, 0.114, -0.322, 0.312);
+const mat3 YIQ_TO_RGB = mat3(1.0, 1.0, 1.0, 0.956, -0.272, -1.106, 0.621, -0.647, 1.703);
+vec3 yiq(float dx) {
+  return RGB_TO_YIQ * texture(frame, uv + vec2(dx / 256.0, 0.0)).rgb;
+}
+void main() {
+  float y = 0.25 * yiq(-1.0).x + 0.5 * yiq(0.0).x + 0.25 * yiq(1.0).x;
+  vec2 iq = vec2(0.0);
+  for (int i = -3; i <= 3; i++) {
+    iq += yiq(float(i)).yz;
+  }
+  iq /= 7.0;
+  float phase = (floor(uv.x * 256.0) * 2.0 + floor(uv.y * 240.0)) * 2.0 * PI / 3.0;
+  y += 0.15 * dot(iq, vec2(cos(phase), sin(phase)));
+  color = vec4(clamp(YIQ_TO_RGB * vec3(y, iq), 0.0, 1.0), 1.0);
+}
+"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+  /// Sharp pixels stretched over the whole canvas.
+  Nearest,
+  /// Sharp pixels at the largest whole multiple that fits, centered.
+  Integer,
+  Scanlines,
+  Ntsc,
+}
+
+impl Filter {
+  pub const ALL: [Filter; 4] = [
+    Filter::Nearest,
+    Filter::Integer,
+    Filter::Scanlines,
+    Filter::Ntsc,
+  ];
+
+  pub fn id(self) -> &'static str {
+    match self {
+      Filter::Nearest => "nearest",
+      Filter::Integer => "integer",
+      Filter::Scanlines => "scanlines",
+      Filter::Ntsc => "ntsc",
+    }
+  }
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Filter::Nearest => "Nearest",
+      Filter::Integer => "Integer scaling",
+      Filter::Scanlines => "CRT scanlines",
+      Filter::Ntsc => "NTSC composite",
+    }
+  }
+
+  pub fn from_id(id: &str) -> Option<Filter> {
+    Filter::ALL.iter().copied().find(|filter| filter.id() == id)
+  }
+
+  fn shader(self) -> &'static str {
+    match self {
+      Filter::Nearest | Filter::Integer => NEAREST_SHADER,
+      Filter::Scanlines => SCANLINES_SHADER,
+      Filter::Ntsc => NTSC_SHADER,
+    }
+  }
+}
+
+impl Default for Filter {
+  fn default() -> Self {
+    Filter::Nearest
+  }
+}
+
+/// The shape of a pixel on the canvas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aspect {
+  /// Whatever shape fills the canvas.
+  Stretch,
+  Square,
+  /// 8:7, a little wider than tall, as an NTSC TV draws them.
+  Ntsc,
+}
+
+impl Aspect {
+  pub const ALL: [Aspect; 3] = [Aspect::Stretch, Aspect::Square, Aspect::Ntsc];
+
+  pub fn id(self) -> &'static str {
+    match self {
+      Aspect::Stretch => "stretch",
+      Aspect::Square => "square",
+      Aspect::Ntsc => "8:7",
+    }
+  }
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Aspect::Stretch => "Stretch to fit",
+      Aspect::Square => "Square pixels",
+      Aspect::Ntsc => "8:7 pixels (TV)",
+    }
+  }
+
+  pub fn from_id(id: &str) -> Option<Aspect> {
+    Aspect::ALL.iter().copied().find(|aspect| aspect.id() == id)
+  }
+
+  // width over height, None to fill the canvas
+  fn pixel_aspect(self) -> Option<f64> {
+    match self {
+      Aspect::Stretch => None,
+      Aspect::Square => Some(1.0),
+      Aspect::Ntsc => Some(8.0 / 7.0),
+    }
+  }
+}
+
+impl Default for Aspect {
+  fn default() -> Self {
+    Aspect::Stretch
+  }
+}
+
+/// How the frame is fitted into the canvas, whatever the filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct VideoOptions {
+  /// Leave out the 8 lines at the top and at the bottom that TVs hid.
+  pub crop_overscan: bool,
+  pub aspect: Aspect,
+  /// Only whole multiples of the frame's size, centered. Stretched
+  /// pixels are square then.
+  pub integer_scale: bool,
+}
+
+impl VideoOptions {
+  /// The part of the 256x240 frame that's shown: x, y, width, height.
+  pub fn source(&self) -> (u32, u32, u32, u32) {
+    let lines = if self.crop_overscan {
+      OVERSCAN_LINES as u32
+    } else {
+      0
+    };
+    (
+      0,
+      lines,
+      Frame::WIDTH as u32,
+      Frame::HEIGHT as u32 - 2 * lines,
+    )
+  }
+}
+
+/// Where the picture goes in a `width`x`height` drawing buffer: x, y,
+/// width, height.
+pub fn viewport(
+  filter: Filter,
+  options: VideoOptions,
+  width: u32,
+  height: u32,
+) -> (u32, u32, u32, u32) {
+  let integer = options.integer_scale || filter == Filter::Integer;
+  let pixel_aspect = match (options.aspect.pixel_aspect(), integer) {
+    (None, false) => return (0, 0, width, height),
+    (None, true) => 1.0,
+    (Some(pixel_aspect), _) => pixel_aspect,
+  };
+  let (_, _, source_width, source_height) = options.source();
+  let picture_width = source_width as f64 * pixel_aspect;
+  let scale = (width as f64 / picture_width).min(height as f64 / source_height as f64);
+  // the height a whole multiple, 8:7 pixels can't be both ways
+  let scale = if integer {
+    scale.floor().max(1.0)
+  } else {
+    scale
+  };
+  let w = (picture_width * scale).round() as u32;
+  let h = (source_height as f64 * scale).round() as u32;
+  (
+    width.saturating_sub(w) / 2,
+    height.saturating_sub(h) / 2,
+    w,
+    h,
+  )
+}
+
+// triangle strip over the whole of clip space
+const QUAD: [f32; 8] = [-1.0, -1.0, 1.0, -1.0, -1.0, 1.0, 1.0, 1.0];
+
+pub fn compile_shader(gl: &GL, kind: u32, source: &str) -> Result<WebGlShader, String> {
+  let shader = gl
+    .create_shader(kind)
+    .ok_or_else(|| "unable to create shader".to_string())?;
+  gl.shader_source(&shader, source);
+  gl.compile_shader(&shader);
+
+  if gl
+    .get_shader_parameter(&shader, GL::COMPILE_STATUS)
+    .as_bool()
+    .unwrap_or(false)
+  {
+    Ok(shader)
+  } else {
+    Err(
+      gl.get_shader_info_log(&shader)
+        .unwrap_or_else(|| "unknown error creating shader".to_string()),
+    )
+  }
+}
+
+pub fn link_program(
+  gl: &GL,
+  vertex: &WebGlShader,
+  fragment: &WebGlShader,
+) -> Result<WebGlProgram, String> {
+  let program = gl
+    .create_program()
+    .ok_or_else(|| "unable to create program".to_string())?;
+  gl.attach_shader(&program, vertex);
+  gl.attach_shader(&program, fragment);
+  gl.link_program(&program);
+
+  if gl
+    .get_program_parameter(&program, GL::LINK_STATUS)
+    .as_bool()
+    .unwrap_or(false)
+  {
+    Ok(program)
+  } else {
+    Err(
+      gl.get_program_info_log(&program)
+        .unwrap_or_else(|| "unknown error linking program".to_string()),
+    )
+  }
+}
+
+pub struct Renderer {
+  canvas: HtmlCanvasElement,
+  gl: GL,
+  // one per filter, in `Filter::ALL` order
+  programs: Vec<WebGlProgram>,
+  // each program's `source` uniform
+  sources: Vec<Option<WebGlUniformLocation>>,
+  vao: WebGlVertexArrayObject,
+  texture: WebGlTexture,
+}
+
+impl Renderer {
+  pub fn new(canvas: &HtmlCanvasElement) -> Result<Renderer, JsValue> {
+    // keep the last frame around for toDataURL (screenshots)
+    let options = Object::new();
+    Reflect::set(&options, &"preserveDrawingBuffer".into(), &true.into())?;
+    let gl = canvas
+      .get_context_with_context_options("webgl2", &options)?
+      .ok_or_else(|| JsValue::from_str("WebGL2 isn't available"))?
+      .dyn_into::<GL>()?;
+
+    let vertex = compile_shader(&gl, GL::VERTEX_SHADER, VERTEX_SHADER)?;
+    let mut programs = Vec::new();
+    let mut sources = Vec::new();
+    for filter in &Filter::ALL {
+      let fragment = compile_shader(&gl, GL::FRAGMENT_SHADER, filter.shader())?;
+      let program = link_program(&gl, &vertex, &fragment)?;
+      gl.use_program(Some(&program));
+      gl.uniform1i(gl.get_uniform_location(&program, "frame").as_ref(), 0);
+      sources.push(gl.get_uniform_location(&program, "source"));
+      programs.push(program);
+    }
+
+    let vao = gl
+      .create_vertex_array()
+      .ok_or_else(|| JsValue::from_str("unable to create vertex array"))?;
+    gl.bind_vertex_array(Some(&vao));
+    let buffer = gl
+      .create_buffer()
+      .ok_or_else(|| JsValue::from_str("unable to create buffer"))?;
+    gl.bind_buffer(GL::ARRAY_BUFFER, Some(&buffer));
+    gl.buffer_data_with_array_buffer_view(
+      GL::ARRAY_BUFFER,
+      &Float32Array::from(&QUAD[..]),
+      GL::STATIC_DRAW,
+    );
+    gl.enable_vertex_attrib_array(POSITION);
+    gl.vertex_attrib_pointer_with_i32(POSITION, 2, GL::FLOAT, false, 0, 0);
+
+    let texture = gl
+      .create_texture()
+      .ok_or_else(|| JsValue::from_str("unable to create texture"))?;
+    gl.bind_texture(GL::TEXTURE_2D, Some(&texture));
+    for &(param, value) in &[
+      (GL::TEXTURE_MIN_FILTER, GL::NEAREST),
+      (GL::TEXTURE_MAG_FILTER, GL::NEAREST),
+      (GL::TEXTURE_WRAP_S, GL::CLAMP_TO_EDGE),
+      (GL::TEXTURE_WRAP_T, GL::CLAMP_TO_EDGE),
+    ] {
+      gl.tex_parameteri(GL::TEXTURE_2D, param, value as i32);
+    }
+    // allocated once, frames only replace the contents
+    gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+      GL::TEXTURE_2D,
+      0,
+      GL::RGBA8 as i32,
+      Frame::WIDTH as i32,
+      Frame::HEIGHT as i32,
+      0,
+      GL::RGBA,
+      GL::UNSIGNED_BYTE,
+      None,
+    )?;
+
+    Ok(Renderer {
+      canvas: canvas.clone(),
+      gl,
+      programs,
+      sources,
+      vao,
+      texture,
+    })
+  }
+
+  // drawing buffer as large as the canvas shows up on the page
+  fn fit_canvas(&self) -> (u32, u32) {
+    let ratio = window().map_or(1.0, |window| window.device_pixel_ratio());
+    let size = |css: i32, fallback: usize| match (css as f64 * ratio).round() as u32 {
+      0 => fallback as u32,
+      size => size,
+    };
+    let width = size(self.canvas.client_width(), Frame::WIDTH);
+    let height = size(self.canvas.client_height(), Frame::HEIGHT);
+    if self.canvas.width() != width || self.canvas.height() != height {
+      self.canvas.set_width(width);
+      self.canvas.set_height(height);
+    }
+    (width, height)
+  }
+
+  /// Draw a 256x240 RGBA frame, e.g. `Emulator::frame_rgba`.
+  pub fn draw(&self, rgba: &[u8], filter: Filter, options: VideoOptions) -> Result<(), JsValue> {
+    let gl = &self.gl;
+    let (width, height) = self.fit_canvas();
+    let (x, y, w, h) = viewport(filter, options, width, height);
+    gl.viewport(0, 0, width as i32, height as i32);
+    gl.clear_color(0.0, 0.0, 0.0, 1.0);
+    gl.clear(GL::COLOR_BUFFER_BIT);
+    gl.viewport(x as i32, y as i32, w as i32, h as i32);
+    let index = Filter::ALL.iter().position(|&f| f == filter).unwrap();
+    gl.use_program(Some(&self.programs[index]));
+    let (sx, sy, sw, sh) = options.source();
+    let (frame_width, frame_height) = (Frame::WIDTH as f32, Frame::HEIGHT as f32);
+    gl.uniform4f(
+      self.sources[index].as_ref(),
+      sx as f32 / frame_width,
+      sy as f32 / frame_height,
+      sw as f32 / frame_width,
+      sh as f32 / frame_height,
+    );
+    gl.bind_vertex_array(Some(&self.vao));
+    gl.active_texture(GL::TEXTURE0);
+    gl.bind_texture(GL::TEXTURE_2D, Some(&self.texture));
+    gl.tex_sub_image_2d_with_i32_and_i32_and_u32_and_type_and_opt_u8_array(
+      GL::TEXTURE_2D,
+      0,
+      0,
+      0,
+      Frame::WIDTH as i32,
+      Frame::HEIGHT as i32,
+      GL::RGBA,
+      GL::UNSIGNED_BYTE,
+      Some(rgba),
+    )?;
+    gl.draw_arrays(GL::TRIANGLE_STRIP, 0, 4);
+    Ok(())
+  }
+
+  /// The picture of the last `draw` as it is on the canvas, filter and
+  /// all, without the letterboxing: width, height and RGBA rows from the
+  /// top.
+  pub fn read_pixels(
+    &self,
+    filter: Filter,
+    options: VideoOptions,
+  ) -> Result<(u32, u32, Vec<u8>), JsValue> {
+    let (x, y, w, h) = viewport(filter, options, self.canvas.width(), self.canvas.height());
+    let mut rgba = vec![0; (w * h * 4) as usize];
+    self.gl.read_pixels_with_opt_u8_array(
+      x as i32,
+      y as i32,
+      w as i32,
+      h as i32,
+      GL::RGBA,
+      GL::UNSIGNED_BYTE,
+      Some(&mut rgba),
+    )?;
+    // GL counts rows from the bottom
+    let rows: Vec<&[u8]> = rgba.chunks((w * 4) as usize).rev().collect();
+    Ok((w, h, rows.concat()))
+  }
+}
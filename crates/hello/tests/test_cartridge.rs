@@ -0,0 +1,51 @@
+use hello::error::FlemuError;
+use hello::nes::asm;
+use hello::nes::cartridge::Rom;
+use hello::Emulator;
+
+// stores `tag` in $12 and spins
+fn rom(tag: u8) -> Rom {
+  let program = asm::assemble(&format!(
+    "
+      LDA #${:02X}
+      STA $12
+    loop: JMP loop
+    ",
+    tag
+  ))
+  .unwrap();
+  Rom::from_program(&program)
+}
+
+#[test]
+fn test_failed_insert_leaves_the_running_cartridge() {
+  let mut emulator = Emulator::new();
+  emulator.insert_rom(rom(7)).unwrap();
+  emulator.debug_run(100);
+  assert_eq!(emulator.achievement_peek(0x12, 1), 7);
+  let before = emulator.cpu_state();
+
+  let mut unsupported = rom(9);
+  unsupported.info.mapper = 4095;
+  let error = emulator.insert_rom(unsupported).unwrap_err();
+  assert!(matches!(
+    error,
+    FlemuError::UnsupportedMapper { number: 4095, .. }
+  ));
+  assert_eq!(emulator.cpu_state(), before);
+  assert_eq!(emulator.achievement_peek(0x12, 1), 7);
+  // and it goes on running
+  assert!(emulator.step_instruction());
+  assert_eq!(emulator.cpu_state().pc, before.pc);
+}
+
+#[test]
+fn test_insert_starts_a_fresh_console() {
+  let mut emulator = Emulator::new();
+  emulator.insert_rom(rom(7)).unwrap();
+  emulator.debug_run(100);
+  emulator.insert_rom(rom(9)).unwrap();
+  assert_eq!(emulator.cpu_state().cycles, 0);
+  emulator.debug_run(100);
+  assert_eq!(emulator.achievement_peek(0x12, 1), 9);
+}
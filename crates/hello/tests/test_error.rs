@@ -0,0 +1,124 @@
+use hello::error::FlemuError;
+use hello::nes::cartridge::{Rom, RomError};
+use hello::nes::combo::ComboError;
+use hello::nes::cpu::{EmuError, Fault};
+use hello::nes::desync::ParseTraceError;
+use hello::nes::game_db::GameDbError;
+use hello::nes::movie::MovieError;
+use hello::nes::netplay::NetplayError;
+use hello::nes::nsf::NsfError;
+use hello::nes::patch::PatchError;
+use hello::nes::savestate::StateError;
+
+#[test]
+fn test_rom_errors_split_out_unsupported_mappers() {
+  let error = FlemuError::from(RomError::UnsupportedMapper {
+    number: 4,
+    submapper: 0,
+    known_name: Some("MMC3"),
+  });
+  assert_eq!(
+    error,
+    FlemuError::UnsupportedMapper {
+      number: 4,
+      submapper: 0,
+      known_name: Some("MMC3")
+    }
+  );
+  assert_eq!(error.code(), "unsupported-mapper");
+  assert_eq!(error.to_string(), "mapper 4 (MMC3) is not supported");
+
+  let error = FlemuError::from(Rom::from_bytes(b"not a rom").unwrap_err());
+  assert_eq!(error, FlemuError::RomParse(RomError::NotINes));
+  assert_eq!(error.code(), "rom-parse");
+}
+
+#[test]
+fn test_emu_errors_keep_their_codes() {
+  let error = FlemuError::from(EmuError::Rom(RomError::UnsupportedMapper {
+    number: 4,
+    submapper: 0,
+    known_name: None,
+  }));
+  assert_eq!(error.code(), "unsupported-mapper");
+  let fault = Fault::UnknownOpcode {
+    pc: 0x8000,
+    code: 0x02,
+  };
+  assert_eq!(
+    FlemuError::from(EmuError::Fault(fault)),
+    FlemuError::CpuFault(fault)
+  );
+}
+
+#[test]
+fn test_state_errors_split_out_versions() {
+  let error = FlemuError::from(StateError::Version {
+    found: 3,
+    supported: 2,
+  });
+  assert_eq!(
+    error,
+    FlemuError::StateVersion {
+      found: 3,
+      supported: 2
+    }
+  );
+  let error = FlemuError::from(StateError::WrongCartridge);
+  assert_eq!(error.code(), "bad-state");
+  assert_eq!(
+    error.to_string(),
+    "savestate was made with another cartridge"
+  );
+}
+
+#[test]
+fn test_codes_and_messages() {
+  let fault = Fault::UnknownOpcode {
+    pc: 0x8000,
+    code: 0x02,
+  };
+  assert_eq!(FlemuError::from(fault).code(), "cpu-fault");
+  assert_eq!(FlemuError::from(fault).to_string(), fault.to_string());
+  let error = FlemuError::from(Fault::Unimplemented {
+    pc: 0xc123,
+    code: 0x6b,
+  });
+  assert_eq!(error.code(), "cpu-fault");
+  assert_eq!(error.to_string(), "OpCode 6b at c123 is not implemented");
+  assert_eq!(FlemuError::from(PatchError::Truncated).code(), "patch");
+  let error = FlemuError::from(MovieError::Syntax { line: 3 });
+  assert_eq!(error.code(), "movie");
+  assert_eq!(error.to_string(), "bad movie line 3");
+  let error = FlemuError::from(ComboError::Syntax { token: 2 });
+  assert_eq!(error.code(), "combo");
+  assert_eq!(error.to_string(), "bad combo token 2");
+  let error = FlemuError::from(GameDbError::Syntax { line: 2 });
+  assert_eq!(error.code(), "game-db");
+  assert_eq!(error.to_string(), "bad game database line 2");
+  let error = FlemuError::from(NsfError::ExpansionAudio("FDS"));
+  assert_eq!(error.code(), "nsf");
+  assert_eq!(error.to_string(), "FDS audio is not supported");
+  let error = FlemuError::from(NetplayError::Desync { frame: 120 });
+  assert_eq!(error.code(), "netplay");
+  assert_eq!(error.to_string(), "netplay desynced at frame 120");
+  let error = FlemuError::from(ParseTraceError { line: 4 });
+  assert_eq!(error.code(), "checksum-trace");
+  assert_eq!(error.to_string(), "malformed checksum trace line 4");
+  let error = FlemuError::Fetch {
+    url: "game.nes".to_string(),
+    status: Some(404),
+  };
+  assert_eq!(error.code(), "fetch");
+  assert_eq!(error.to_string(), "couldn't fetch game.nes: HTTP 404");
+
+  let error = FlemuError::StateVersion {
+    found: 3,
+    supported: 2,
+  };
+  assert_eq!(error.code(), "state-version");
+  assert_eq!(
+    error.to_string(),
+    "savestate version 3 can't be loaded, this build reads version 2"
+  );
+}
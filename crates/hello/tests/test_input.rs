@@ -0,0 +1,239 @@
+use hello::input::*;
+use hello::nes::combo::Combo;
+
+#[test]
+fn test_standard_controller_buttons_follow_the_shift_register() {
+  let ids: Vec<&str> = STANDARD_CONTROLLER.buttons.iter().map(|b| b.id).collect();
+  assert_eq!(
+    ids,
+    vec!["a", "b", "select", "start", "up", "down", "left", "right", "turbo-a", "turbo-b"]
+  );
+  for (i, button) in STANDARD_CONTROLLER.buttons.iter().enumerate() {
+    assert_eq!(button.bit as usize, i);
+  }
+  assert_eq!(devices(), &[STANDARD_CONTROLLER]);
+  assert!(device_for_port(1).is_some());
+  assert!(device_for_port(PORTS).is_none());
+}
+
+#[test]
+fn test_default_bindings() {
+  let bindings = Bindings::default();
+  assert_eq!(bindings.key_for(0, "a"), Some("KeyX"));
+  assert_eq!(bindings.key_for(0, "start"), Some("Enter"));
+  assert_eq!(bindings.key_for(1, "a"), None);
+
+  let (port, button) = bindings.button_for_key("ArrowLeft").unwrap();
+  assert_eq!(port, 0);
+  assert_eq!(button.id, "left");
+  assert_eq!(bindings.key_for(0, "turbo-a"), Some("KeyS"));
+  assert_eq!(bindings.iter().count(), 10);
+}
+
+#[test]
+fn test_rebinding() {
+  let mut bindings = Bindings::default();
+
+  assert!(bindings.bind(0, "a", "KeyK"));
+  assert_eq!(bindings.key_for(0, "a"), Some("KeyK"));
+  assert_eq!(bindings.button_for_key("KeyX"), None);
+
+  // taking a key away from another button
+  assert!(bindings.bind(1, "b", "KeyK"));
+  assert_eq!(bindings.key_for(0, "a"), None);
+  assert_eq!(bindings.button_for_key("KeyK").unwrap().0, 1);
+
+  bindings.unbind(1, "b");
+  assert_eq!(bindings.button_for_key("KeyK"), None);
+}
+
+#[test]
+fn test_unknown_port_or_button() {
+  let mut bindings = Bindings::empty();
+  assert!(!bindings.bind(0, "turbo", "KeyT"));
+  assert!(!bindings.bind(PORTS, "a", "KeyT"));
+  assert_eq!(bindings.iter().count(), 0);
+}
+
+#[test]
+fn test_hotkey_bindings() {
+  let mut bindings = Bindings::default();
+  assert_eq!(bindings.key_for_hotkey(Hotkey::SaveState), Some("F5"));
+  assert_eq!(
+    bindings.target_for_key("KeyP"),
+    Some(KeyTarget::Hotkey(Hotkey::Pause))
+  );
+  assert_eq!(Hotkey::from_id("fast-forward"), Some(Hotkey::FastForward));
+
+  // a key drives one thing, button or hotkey
+  bindings.bind_hotkey(Hotkey::Screenshot, "KeyX");
+  assert_eq!(bindings.key_for(0, "a"), None);
+  assert!(bindings.bind(0, "a", "KeyP"));
+  assert_eq!(bindings.key_for_hotkey(Hotkey::Pause), None);
+  match bindings.target_for_key("KeyP") {
+    Some(KeyTarget::Button(0, button)) => assert_eq!(button.id, "a"),
+    other => panic!("unexpected {:?}", other),
+  }
+}
+
+#[test]
+fn test_one_shot_hotkeys_ignore_repeats() {
+  let bindings = Bindings::default();
+  let mut state = HotkeyState::default();
+  let save = Some(HotkeyEvent {
+    hotkey: Hotkey::SaveState,
+    pressed: true,
+  });
+  assert_eq!(state.key_event(&bindings, "F5", true), save);
+  assert_eq!(state.key_event(&bindings, "F5", true), None);
+  assert_eq!(state.key_event(&bindings, "F5", false), None);
+  assert_eq!(state.key_event(&bindings, "F5", true), save);
+  assert_eq!(state.key_event(&bindings, "KeyX", true), None);
+}
+
+#[test]
+fn test_held_hotkeys_report_release() {
+  let bindings = Bindings::default();
+  let mut state = HotkeyState::default();
+  state.key_event(&bindings, "Tab", true);
+  assert!(state.is_down(Hotkey::FastForward));
+  assert_eq!(
+    state.key_event(&bindings, "Tab", false),
+    Some(HotkeyEvent {
+      hotkey: Hotkey::FastForward,
+      pressed: false,
+    })
+  );
+
+  state.key_event(&bindings, "Backspace", true);
+  state.key_event(&bindings, "KeyP", true);
+  assert_eq!(
+    state.release_all(),
+    vec![HotkeyEvent {
+      hotkey: Hotkey::Rewind,
+      pressed: false,
+    }]
+  );
+  assert!(!state.is_down(Hotkey::Pause));
+}
+
+#[test]
+fn test_config_round_trip() {
+  let mut bindings = Bindings::default();
+  bindings.bind(1, "start", "Digit1");
+  bindings.unbind_hotkey(Hotkey::Screenshot);
+  let config = bindings.to_config();
+  assert!(config.contains("button 1 start Digit1\n"));
+  assert!(config.contains("hotkey save-state F5\n"));
+  assert_eq!(Bindings::from_config(&config), Ok(bindings));
+
+  assert_eq!(
+    Bindings::from_config("button 0 a KeyX\nhotkey turbo KeyT\n"),
+    Err(ConfigError { line: 2 })
+  );
+  assert_eq!(
+    Bindings::from_config("button 9 a KeyX")
+      .unwrap_err()
+      .to_string(),
+    "bad key binding on line 1"
+  );
+}
+
+#[test]
+fn test_combo_bindings() {
+  let mut bindings = Bindings::default();
+  let combo = Combo::from_text("D*2 RD*2 R*1 RB*3").unwrap();
+  // a key drives one thing, combos included
+  assert!(bindings.bind_combo(0, "KeyX", combo.clone()));
+  assert_eq!(bindings.key_for(0, "a"), None);
+  assert_eq!(bindings.combo_for_key("KeyX").unwrap().combo, combo);
+  assert!(bindings.bind(0, "a", "KeyX"));
+  assert!(bindings.combo_for_key("KeyX").is_none());
+  assert!(bindings.bind_combo(1, "F5", combo.clone()));
+  assert_eq!(bindings.key_for_hotkey(Hotkey::SaveState), None);
+  bindings.bind_hotkey(Hotkey::SaveState, "F5");
+  assert_eq!(bindings.combo_iter().count(), 0);
+
+  assert!(!bindings.bind_combo(PORTS, "KeyQ", combo));
+  assert!(!bindings.bind_combo(0, "KeyQ", Combo::default()));
+  assert!(bindings.combo_for_key("KeyQ").is_none());
+}
+
+#[test]
+fn test_combo_config_round_trip() {
+  let mut bindings = Bindings::empty();
+  bindings.bind_combo(1, "KeyQ", Combo::from_text("D*2 RD*2 R*1 RB*3").unwrap());
+  let config = bindings.to_config();
+  assert_eq!(config, "combo 1 KeyQ D*2 RD*2 R*1 RB*3\n");
+  assert_eq!(Bindings::from_config(&config), Ok(bindings));
+  for bad in &["combo 0 KeyQ", "combo 0 KeyQ D*x", "combo 9 KeyQ A*1"] {
+    assert_eq!(
+      Bindings::from_config(bad),
+      Err(ConfigError { line: 1 }),
+      "{}",
+      bad
+    );
+  }
+}
+
+#[test]
+fn test_gamepad_input_ids() {
+  for input in &[
+    GamepadInput::Button(9),
+    GamepadInput::AxisNegative(1),
+    GamepadInput::AxisPositive(0),
+  ] {
+    assert_eq!(GamepadInput::from_id(&input.id()), Some(*input));
+  }
+  assert_eq!(GamepadInput::Button(9).id(), "button-9");
+  assert_eq!(GamepadInput::from_id("axis-1"), None);
+  assert_eq!(GamepadInput::from_id("trigger-2"), None);
+}
+
+#[test]
+fn test_gamepad_buttons() {
+  let mut bindings = Bindings::default();
+  let mut buttons = vec![false; 17];
+  let mut axes = vec![0.0; 4];
+  assert_eq!(bindings.gamepad_buttons(&buttons, &axes), 0);
+
+  // A and start, and the stick pushed left past the threshold
+  buttons[1] = true;
+  buttons[9] = true;
+  axes[0] = -0.8;
+  axes[1] = 0.3;
+  assert_eq!(bindings.gamepad_buttons(&buttons, &axes), 0b0100_1001);
+
+  // moving the A input to B; the d-pad and the stick both steer
+  assert!(bindings.bind_gamepad("b", GamepadInput::Button(1)));
+  assert_eq!(
+    bindings.gamepad_inputs("left"),
+    vec![GamepadInput::Button(14), GamepadInput::AxisNegative(0)]
+  );
+  assert_eq!(bindings.gamepad_buttons(&buttons, &axes), 0b0100_1010);
+  assert!(!bindings.bind_gamepad("turbo", GamepadInput::Button(2)));
+
+  bindings.unbind_gamepad("left");
+  assert_eq!(bindings.gamepad_buttons(&buttons, &axes), 0b0000_1010);
+  // a pad with fewer controls than bound
+  assert_eq!(bindings.gamepad_buttons(&[false, true], &[]), 0b0000_0010);
+  // turbo buttons come after the shift register's eight
+  let mut turbo = vec![false; 17];
+  turbo[3] = true;
+  assert_eq!(bindings.gamepad_buttons(&turbo, &[]), 0b01_0000_0000);
+
+  let config = bindings.to_config();
+  assert!(config.contains("gamepad b button-1\n"));
+  assert_eq!(Bindings::from_config(&config), Ok(bindings));
+}
+
+#[test]
+fn test_unbind_key() {
+  let mut bindings = Bindings::default();
+  bindings.unbind_key("KeyX");
+  bindings.unbind_key("F5");
+  assert_eq!(bindings.key_for(0, "a"), None);
+  assert_eq!(bindings.key_for_hotkey(Hotkey::SaveState), None);
+  assert_eq!(bindings.iter().count(), 9);
+  assert_eq!(bindings.hotkey_iter().count(), Hotkey::ALL.len() - 1);
+}
@@ -0,0 +1,67 @@
+use hello::nes::profile::ComponentTimes;
+use hello::perf::*;
+
+fn audio(fill: f64, latency_ms: f64) -> AudioQueue {
+  AudioQueue { fill, latency_ms }
+}
+
+#[test]
+fn test_counters_are_per_frame() {
+  let mut meter = PerfMeter::new();
+  let times = ComponentTimes {
+    ppu_ms: 6.0,
+    apu_ms: 2.0,
+  };
+  meter.record(0.0, 20.0, 2, 59_560, times, audio(0.5, 50.0));
+  assert_eq!(
+    meter.counters(),
+    PerfCounters {
+      cpu_cycles: 29_780,
+      frame_ms: 10.0,
+      cpu_ms: 6.0,
+      ppu_ms: 3.0,
+      apu_ms: 1.0,
+      audio_fill: 0.5,
+      audio_latency_ms: 50.0,
+      fps: 0.0,
+    }
+  );
+
+  // without profiling there's no split
+  meter.record(
+    20.0,
+    25.0,
+    1,
+    29_780,
+    ComponentTimes::default(),
+    audio(0.25, 25.0),
+  );
+  assert_eq!(
+    meter.counters(),
+    PerfCounters {
+      cpu_cycles: 29_780,
+      frame_ms: 5.0,
+      audio_fill: 0.25,
+      audio_latency_ms: 25.0,
+      ..PerfCounters::default()
+    }
+  );
+}
+
+#[test]
+fn test_fps_over_a_second() {
+  let mut meter = PerfMeter::new();
+  for frame in 0..60 {
+    let start = frame as f64 * 20.0;
+    meter.record(
+      start,
+      start + 5.0,
+      1,
+      0,
+      ComponentTimes::default(),
+      AudioQueue::default(),
+    );
+  }
+  // 51 frames by 1005ms, whatever they took to run
+  assert!((meter.counters().fps - 51.0 * 1000.0 / 1005.0).abs() < 1e-9);
+}
@@ -0,0 +1,142 @@
+use hello::nes::bus::{Bus, Mem};
+use hello::nes::cartridge::Rom;
+use hello::sram::*;
+use hello::storage::*;
+
+const ROM: &str = "abcd";
+const SIZE: usize = 0x2000;
+
+fn written(ram: &mut [u8], at: &[usize]) {
+  for (i, &addr) in at.iter().enumerate() {
+    ram[addr] = i as u8 + 1;
+  }
+}
+
+#[test]
+fn test_fresh_save_is_zeroed() {
+  let mut storage = MemoryStorage::new();
+  let journal = SramJournal::load(&mut storage, ROM, SIZE).unwrap();
+  assert_eq!(journal.sram(), &[0; SIZE][..]);
+  assert_eq!(journal.pending_entries(), 0);
+}
+
+#[test]
+fn test_flush_journals_dirty_pages() {
+  let mut storage = MemoryStorage::new();
+  let mut journal = SramJournal::load(&mut storage, ROM, SIZE).unwrap();
+  let mut ram = vec![0; SIZE];
+
+  // two bytes on page 0, one on page 31
+  written(&mut ram, &[0x0000, 0x00ff, 0x1f00]);
+  assert_eq!(journal.flush(&mut storage, &ram), Ok(2));
+  assert_eq!(journal.flush(&mut storage, &ram), Ok(0));
+  assert!(storage.get(ROM, "sram-journal-1").unwrap().is_some());
+  assert!(storage.get(ROM, "sram-journal-2").unwrap().is_some());
+  assert_eq!(storage.get(ROM, "sram-journal-3"), Ok(None));
+
+  let reloaded = SramJournal::load(&mut storage, ROM, SIZE).unwrap();
+  assert_eq!(reloaded.sram(), &ram[..]);
+  assert_eq!(reloaded.pending_entries(), 2);
+}
+
+#[test]
+fn test_torn_entry_is_dropped() {
+  let mut storage = MemoryStorage::new();
+  let mut journal = SramJournal::load(&mut storage, ROM, SIZE).unwrap();
+  let mut ram = vec![0; SIZE];
+  ram[0x10] = 0xaa;
+  journal.flush(&mut storage, &ram).unwrap();
+  ram[0x110] = 0xbb;
+  journal.flush(&mut storage, &ram).unwrap();
+
+  // the second write was cut short
+  let mut entry = storage.get(ROM, "sram-journal-2").unwrap().unwrap();
+  entry.truncate(entry.len() / 2);
+  storage.put(ROM, "sram-journal-2", &entry).unwrap();
+
+  let mut journal = SramJournal::load(&mut storage, ROM, SIZE).unwrap();
+  assert_eq!(journal.sram()[0x10], 0xaa);
+  assert_eq!(journal.sram()[0x110], 0x00);
+
+  // the next flush writes over the torn entry
+  assert_eq!(journal.flush(&mut storage, &ram), Ok(1));
+  let journal = SramJournal::load(&mut storage, ROM, SIZE).unwrap();
+  assert_eq!(journal.sram(), &ram[..]);
+}
+
+#[test]
+fn test_long_journal_is_compacted() {
+  let mut storage = MemoryStorage::new();
+  let mut journal = SramJournal::load(&mut storage, ROM, SIZE).unwrap();
+  let mut ram = vec![0; SIZE];
+  for round in 0..100 {
+    ram[round] = round as u8 + 1;
+    journal.flush(&mut storage, &ram).unwrap();
+  }
+  // folded in after 64 entries, the 36 since are still journaled
+  assert_eq!(journal.pending_entries(), 36);
+  assert_eq!(storage.get(ROM, "sram-journal-64"), Ok(None));
+  assert!(storage.get(ROM, "sram-a").unwrap().is_some());
+
+  let mut journal = SramJournal::load(&mut storage, ROM, SIZE).unwrap();
+  assert_eq!(journal.sram(), &ram[..]);
+
+  journal.compact(&mut storage).unwrap();
+  assert_eq!(journal.pending_entries(), 0);
+  assert!(storage.get(ROM, "sram-b").unwrap().is_some());
+  assert_eq!(storage.get(ROM, "sram-journal-100"), Ok(None));
+  let journal = SramJournal::load(&mut storage, ROM, SIZE).unwrap();
+  assert_eq!(journal.sram(), &ram[..]);
+}
+
+#[test]
+fn test_interrupted_compaction() {
+  let mut storage = MemoryStorage::new();
+  let mut journal = SramJournal::load(&mut storage, ROM, SIZE).unwrap();
+  let mut ram = vec![0; SIZE];
+  written(&mut ram, &[0x0000, 0x0100, 0x0200]);
+  journal.flush(&mut storage, &ram).unwrap();
+  let entries: Vec<_> = (1..=3)
+    .map(|sequence| {
+      let slot = format!("sram-journal-{}", sequence);
+      (slot.clone(), storage.get(ROM, &slot).unwrap().unwrap())
+    })
+    .collect();
+  journal.compact(&mut storage).unwrap();
+
+  // as if the cleanup after writing the base never ran
+  for (slot, entry) in entries.iter() {
+    storage.put(ROM, slot, entry).unwrap();
+  }
+  let journal = SramJournal::load(&mut storage, ROM, SIZE).unwrap();
+  assert_eq!(journal.sram(), &ram[..]);
+  assert_eq!(journal.pending_entries(), 0);
+  assert_eq!(storage.get(ROM, "sram-journal-1"), Ok(None));
+
+  // a corrupt new base falls back to the previous one
+  let mut storage = MemoryStorage::new();
+  let mut journal = SramJournal::load(&mut storage, ROM, SIZE).unwrap();
+  ram[0x10] = 0x42;
+  journal.flush(&mut storage, &ram).unwrap();
+  journal.compact(&mut storage).unwrap();
+  ram[0x10] = 0x43;
+  journal.flush(&mut storage, &ram).unwrap();
+  journal.compact(&mut storage).unwrap();
+  storage.put(ROM, "sram-b", &[1, 2, 3, 4, 5]).unwrap();
+  let journal = SramJournal::load(&mut storage, ROM, SIZE).unwrap();
+  assert_eq!(journal.sram()[0x10], 0x42);
+}
+
+#[test]
+fn test_bus_battery_ram() {
+  let mut rom = Rom::from_program(&[]);
+  assert!(Bus::with_rom(rom.clone()).unwrap().battery_ram().is_none());
+
+  rom.info.battery = true;
+  let mut bus = Bus::with_rom(rom).unwrap();
+  bus.mem_write(0x6001, 0x5a);
+  assert_eq!(bus.battery_ram().unwrap()[1], 0x5a);
+
+  bus.battery_ram_mut().unwrap()[2] = 0xa5;
+  assert_eq!(bus.mem_read(0x6002), 0xa5);
+}
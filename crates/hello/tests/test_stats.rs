@@ -0,0 +1,42 @@
+use hello::stats::*;
+use hello::storage::*;
+
+#[test]
+fn test_session_tracker_accumulates_and_persists() {
+  let mut storage = MemoryStorage::new();
+
+  let mut tracker = SessionTracker::load(&storage, "abcd").unwrap();
+  assert_eq!(tracker.stats(), RomStats::default());
+  tracker.start(1_000);
+  tracker.add_frames(120);
+  tracker.record_save();
+  tracker.stop(3_000);
+  tracker.persist(&mut storage).unwrap();
+
+  let mut tracker = SessionTracker::load(&storage, "abcd").unwrap();
+  tracker.start(10_000);
+  tracker.add_frames(60);
+  tracker.stop(11_000);
+
+  assert_eq!(
+    tracker.stats(),
+    RomStats {
+      playtime_ms: 3_000,
+      frames: 180,
+      saves: 1,
+      last_played_ms: 11_000,
+    }
+  );
+}
+
+#[test]
+fn test_rom_stats_round_trip() {
+  let stats = RomStats {
+    playtime_ms: 1,
+    frames: 2,
+    saves: 3,
+    last_played_ms: 4,
+  };
+  assert_eq!(RomStats::from_bytes(&stats.to_bytes()), Some(stats));
+  assert_eq!(RomStats::from_bytes(&[1, 2, 3]), None);
+}
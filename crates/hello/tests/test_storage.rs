@@ -0,0 +1,24 @@
+use hello::storage::*;
+
+#[test]
+fn test_memory_storage_put_get() {
+  let mut storage = MemoryStorage::new();
+  assert_eq!(storage.get("abcd", "sram"), Ok(None));
+
+  storage.put("abcd", "sram", &[1, 2, 3]).unwrap();
+  storage.put("abcd", "state-1", &[4]).unwrap();
+  storage.put("ef01", "sram", &[5]).unwrap();
+
+  assert_eq!(storage.get("abcd", "sram"), Ok(Some(vec![1, 2, 3])));
+  assert_eq!(storage.get("abcd", "state-1"), Ok(Some(vec![4])));
+  assert_eq!(storage.get("ef01", "sram"), Ok(Some(vec![5])));
+}
+
+#[test]
+fn test_memory_storage_remove() {
+  let mut storage = MemoryStorage::new();
+  storage.put("abcd", "sram", &[1]).unwrap();
+  storage.remove("abcd", "sram").unwrap();
+
+  assert_eq!(storage.get("abcd", "sram"), Ok(None));
+}
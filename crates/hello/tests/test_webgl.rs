@@ -0,0 +1,88 @@
+use hello::webgl::*;
+
+#[test]
+fn test_filter_ids() {
+  for &filter in &Filter::ALL {
+    assert_eq!(Filter::from_id(filter.id()), Some(filter));
+  }
+  assert_eq!(Filter::from_id("sepia"), None);
+  assert_eq!(Filter::default(), Filter::Nearest);
+}
+
+#[test]
+fn test_aspect_ids() {
+  for &aspect in &Aspect::ALL {
+    assert_eq!(Aspect::from_id(aspect.id()), Some(aspect));
+  }
+  assert_eq!(Aspect::from_id("4:3"), None);
+  assert_eq!(Aspect::default(), Aspect::Stretch);
+}
+
+#[test]
+fn test_integer_viewport() {
+  let options = VideoOptions::default();
+  // 3x fits 800x800 (768x720), centered
+  assert_eq!(
+    viewport(Filter::Integer, options, 800, 800),
+    (16, 40, 768, 720)
+  );
+  assert_eq!(
+    viewport(Filter::Integer, options, 512, 480),
+    (0, 0, 512, 480)
+  );
+  // never below 1x, even if it overflows
+  assert_eq!(
+    viewport(Filter::Integer, options, 200, 200),
+    (0, 0, 256, 240)
+  );
+  assert_eq!(
+    viewport(Filter::Scanlines, options, 800, 800),
+    (0, 0, 800, 800)
+  );
+
+  // the same with any filter
+  let options = VideoOptions {
+    integer_scale: true,
+    ..options
+  };
+  assert_eq!(
+    viewport(Filter::Ntsc, options, 800, 800),
+    (16, 40, 768, 720)
+  );
+}
+
+#[test]
+fn test_aspect_viewport() {
+  let square = VideoOptions {
+    aspect: Aspect::Square,
+    ..VideoOptions::default()
+  };
+  assert_eq!(
+    viewport(Filter::Nearest, square, 800, 800),
+    (0, 25, 800, 750)
+  );
+
+  // 8:7 pixels, with whole multiples of the height
+  let tv = VideoOptions {
+    aspect: Aspect::Ntsc,
+    integer_scale: true,
+    ..VideoOptions::default()
+  };
+  assert_eq!(viewport(Filter::Nearest, tv, 800, 600), (107, 60, 585, 480));
+}
+
+#[test]
+fn test_cropped_overscan() {
+  let options = VideoOptions {
+    crop_overscan: true,
+    aspect: Aspect::Ntsc,
+    ..VideoOptions::default()
+  };
+  assert_eq!(options.source(), (0, 8, 256, 224));
+  assert_eq!(VideoOptions::default().source(), (0, 0, 256, 240));
+  // 224 lines fill the height
+  assert_eq!(
+    viewport(Filter::Nearest, options, 800, 600),
+    (8, 0, 784, 600)
+  );
+}
@@ -0,0 +1,37 @@
+// Plays the APU's samples. A ScriptProcessorNode pulls from the emulator on
+// the main thread, so there's no worklet module to serve; the ring buffer
+// and the rate matching that keeps it from crackling live in the APU. With
+// the emulator in a worker, the source is the AudioRing it fills instead.
+
+const BUFFER_SIZE = 2048
+
+interface SampleSource {
+  set_sample_rate(rate: number): void
+  fill_audio(out: Float32Array): number
+  // samples per callback for the latency set, BUFFER_SIZE without
+  audio_buffer_size?(): number
+}
+
+export function startAudio(nes: SampleSource): AudioContext {
+  const context = new AudioContext()
+  nes.set_sample_rate(Math.round(context.sampleRate))
+
+  // DC blocker: the APU mixes to [0, 1), speakers want it centred
+  let lastIn = 0
+  let lastOut = 0
+
+  const size = nes.audio_buffer_size ? nes.audio_buffer_size() : BUFFER_SIZE
+  const node = context.createScriptProcessor(size, 0, 1)
+  node.onaudioprocess = (event) => {
+    const out = event.outputBuffer.getChannelData(0)
+    nes.fill_audio(out)
+    for (let i = 0; i < out.length; i++) {
+      const sample = out[i]
+      lastOut = sample - lastIn + 0.995 * lastOut
+      lastIn = sample
+      out[i] = lastOut
+    }
+  }
+  node.connect(context.destination)
+  return context
+}
@@ -0,0 +1,163 @@
+// The emulation loop of a FlemuWorkerPlayer, off the main thread: runs the
+// frames that are due on its own clock, publishes each one to the
+// SharedFrame and queues the sound in the AudioRing. The page only draws,
+// plays and forwards input, so a busy page doesn't cost frames.
+
+import init, { Emulator, stored_battery_ram } from 'hello'
+import { AudioRing, SharedFrame } from './shared'
+import type { WorkerCommand, WorkerEvent } from './shared'
+import { MAX_FRAMES_PER_TICK, SAVE_EVERY } from './settings'
+
+// checks for due frames about twice a frame, timers aren't precise
+const TICK_MS = 8
+// the worker's global scope, which the DOM typings don't describe
+const scope = self as unknown as Worker
+
+let nes: Emulator
+let frame: SharedFrame
+let audio: AudioRing
+let running = false
+let loaded = false
+let rewinding = false
+let timer: ReturnType<typeof setTimeout>
+// emulated frames due, at the region's frame rate
+let owed = 0
+let lastTick = 0
+let sinceSave = 0
+
+function post(event: WorkerEvent) {
+  scope.postMessage(event)
+}
+
+function save() {
+  if (!loaded) return
+  nes
+    .persist_battery_ram()
+    .catch((error) => console.warn("couldn't keep the save:", error))
+}
+
+function run() {
+  if (running) return
+  running = true
+  owed = 0
+  lastTick = performance.now()
+  timer = setTimeout(tick, TICK_MS)
+}
+
+function pause() {
+  running = false
+  clearTimeout(timer)
+}
+
+function tick() {
+  if (!running) return
+  const now = performance.now()
+  const elapsed = Math.max(now - lastTick, 0)
+  lastTick = now
+  const speed = nes.speed()
+  owed = Math.min(
+    owed + (elapsed * nes.frame_rate() * speed) / 1000,
+    MAX_FRAMES_PER_TICK * speed
+  )
+  const due = Math.floor(owed)
+  owed -= due
+  if (loaded && rewinding) {
+    for (let i = 0; i < due; i++) nes.rewind()
+  } else if (loaded && (due > 0 || nes.turbo())) {
+    let stop
+    try {
+      stop = nes.run_frames(due)
+    } catch (error) {
+      pause()
+      const { code, message, pc, opcode } = error
+      post({ type: 'cpu-fault', error: { code, message, pc, opcode } })
+      return
+    }
+    if (stop.reason === 'breakpoint' || stop.reason === 'watchpoint') {
+      pause()
+      post({ type: 'debugger-stop', stop })
+    }
+  }
+  if (due > 0 || !loaded) {
+    // without a cartridge frame_rgba is the splash screen
+    frame.publish(nes.frame_rgba())
+    audio.push(nes.audio_samples())
+    nes.set_audio_fill(audio.buffered() / nes.audio_queue_capacity())
+  }
+  sinceSave += due
+  if (sinceSave >= SAVE_EVERY) {
+    sinceSave -= SAVE_EVERY
+    save()
+  }
+  if (running) timer = setTimeout(tick, TICK_MS)
+}
+
+async function load(command: Extract<WorkerCommand, { type: 'load' }>) {
+  save()
+  nes.load_rom(command.rom)
+  // before the first frame, games read their save while booting
+  const stored = await stored_battery_ram(nes.rom_hash())
+  const ram = stored || command.legacySave
+  if (ram) nes.load_battery_ram(ram)
+  loaded = true
+  post({ type: 'loaded', romHash: nes.rom_hash() })
+}
+
+async function start(command: Extract<WorkerCommand, { type: 'start' }>) {
+  await init()
+  nes = new Emulator()
+  frame = new SharedFrame(command.buffers)
+  audio = new AudioRing(command.buffers)
+  try {
+    if (command.input) nes.load_input_config(command.input)
+  } catch (error) {
+    console.warn('ignoring saved key bindings:', error)
+  }
+  const hotkeyKeys = nes
+    .hotkeys()
+    .map((hotkey: { key: string | null }) => hotkey.key)
+    .filter((key: string | null) => key)
+  post({ type: 'ready', hotkeyKeys })
+}
+
+function onKey(code: string, pressed: boolean) {
+  const action = nes.key_event(code, pressed)
+  if (action && action.hotkey === 'rewind') rewinding = action.pressed
+  if (action && action.hotkey === 'fast-forward') nes.set_turbo(action.pressed)
+}
+
+// commands are handled one at a time, in order, even the async ones
+let queue = Promise.resolve()
+
+async function handle(command: WorkerCommand) {
+  switch (command.type) {
+    case 'start':
+      return start(command)
+    case 'load':
+      return load(command)
+    case 'run':
+      return run()
+    case 'pause':
+      return pause()
+    case 'key':
+      return onKey(command.code, command.pressed)
+    case 'release-keys':
+      rewinding = false
+      nes.release_keys()
+      return
+    case 'gamepads':
+      return nes.set_gamepads(command.pads)
+    case 'sample-rate':
+      return nes.set_sample_rate(command.rate)
+    case 'run-ahead':
+      return nes.set_run_ahead(command.frames)
+    case 'save':
+      return save()
+  }
+}
+
+scope.onmessage = (message: MessageEvent<WorkerCommand>) => {
+  queue = queue
+    .then(() => handle(message.data))
+    .catch((error) => post({ type: 'error', message: String(error) }))
+}
@@ -0,0 +1,206 @@
+// <flemu-player rom-url="game.nes"> for embedding the emulator in any page:
+// creates its canvas, fetches and runs the ROM (or one dropped on it),
+// takes keyboard and gamepads, plays sound after the first click and keeps
+// battery saves in IndexedDB. Optional, not registered until
+// defineFlemuPlayer() is called. Every player runs its own Emulator, so a
+// page can hold several, in a worker of its own where the page allows it
+// (FlemuWorkerPlayer).
+
+import init, { Emulator, load_rom_url, stored_battery_ram } from 'hello'
+import { startAudio } from './audio'
+import { FlemuWorkerPlayer } from './flemu-worker-player'
+import {
+  INPUT_KEY,
+  LEGACY_SAVE_PREFIX,
+  MAX_FRAMES_PER_TICK,
+  RUN_AHEAD_PREFIX,
+  SAVE_EVERY,
+  decode,
+} from './settings'
+
+export class FlemuPlayer extends HTMLElement {
+  static get observedAttributes(): string[] {
+    return ['rom-url']
+  }
+
+  private canvas: HTMLCanvasElement
+  private nes: Emulator
+  private audio: AudioContext
+  private frame = 0
+  private running = false
+  private loaded = false
+  private rewinding = false
+  // emulated frames due, at the region's frame rate
+  private owed = 0
+  private lastTick = 0
+
+  connectedCallback(): void {
+    if (this.canvas) {
+      // moved around the page: pick up where it left off
+      if (this.nes && !this.running) this.resume()
+      return
+    }
+    this.canvas = document.createElement('canvas')
+    this.canvas.tabIndex = 0
+    this.canvas.style.imageRendering = 'pixelated'
+    this.canvas.addEventListener('keydown', (event) => this.onKey(event, true))
+    this.canvas.addEventListener('keyup', (event) => this.onKey(event, false))
+    this.canvas.addEventListener('focusout', () => {
+      this.rewinding = false
+      if (this.nes) this.nes.release_keys()
+    })
+    // browsers only allow audio to start from a user gesture
+    this.canvas.addEventListener('click', () => {
+      if (this.nes && !this.audio) this.audio = startAudio(this.nes)
+    })
+    this.canvas.addEventListener('dragover', (event) => event.preventDefault())
+    this.canvas.addEventListener('drop', (event) => this.onDrop(event))
+    // closing the tab doesn't disconnect the element
+    window.addEventListener('pagehide', () => this.save())
+    this.appendChild(this.canvas)
+    this.start()
+  }
+
+  disconnectedCallback(): void {
+    this.save()
+    this.running = false
+    if (this.audio) this.audio.close()
+    this.audio = undefined
+  }
+
+  attributeChangedCallback(name: string, old: string, url: string): void {
+    if (this.nes && url && url !== old) this.load(url)
+  }
+
+  private async start() {
+    await init()
+    this.nes = new Emulator()
+    this.nes.attach_canvas(this.canvas)
+    const input = localStorage.getItem(INPUT_KEY)
+    try {
+      if (input) this.nes.load_input_config(input)
+    } catch (error) {
+      console.warn('ignoring saved key bindings:', error)
+    }
+    const url = this.getAttribute('rom-url')
+    if (url) await this.load(url)
+    this.resume()
+  }
+
+  // also carries on after a breakpoint or watchpoint paused it
+  resume(): void {
+    this.running = true
+    this.owed = 0
+    this.lastTick = performance.now()
+    requestAnimationFrame((now) => this.everyFrame(now))
+  }
+
+  private async load(url: string) {
+    const { bytes } = await load_rom_url(url)
+    await this.loadBytes(bytes, localStorage.getItem(LEGACY_SAVE_PREFIX + url))
+  }
+
+  private async onDrop(event: DragEvent) {
+    event.preventDefault()
+    const file = event.dataTransfer.files[0]
+    if (!this.nes || !file) return
+    try {
+      await this.loadBytes(new Uint8Array(await file.arrayBuffer()), null)
+    } catch (error) {
+      console.warn(`can't load ${file.name}:`, error)
+    }
+  }
+
+  private async loadBytes(bytes: Uint8Array, legacy: string | null) {
+    this.save()
+    this.nes.load_rom_bytes(bytes)
+    // before the first frame, games read their save while booting
+    const save = await stored_battery_ram(this.nes.rom_hash())
+    if (save) this.nes.load_battery_ram(save)
+    else if (legacy) this.nes.load_battery_ram(decode(legacy))
+    const runAhead = localStorage.getItem(RUN_AHEAD_PREFIX + this.nes.rom_hash())
+    try {
+      this.nes.set_run_ahead(runAhead ? Number(runAhead) : 0)
+    } catch (error) {
+      console.warn('ignoring saved run-ahead:', error)
+    }
+    this.loaded = true
+  }
+
+  // frames of run-ahead for the loaded game, remembered for next time
+  setRunAhead(frames: number): void {
+    if (!this.loaded) return
+    this.nes.set_run_ahead(frames)
+    localStorage.setItem(RUN_AHEAD_PREFIX + this.nes.rom_hash(), String(frames))
+  }
+
+  // only writes when the game changed its save
+  private save() {
+    if (!this.nes) return
+    this.nes
+      .persist_battery_ram()
+      .catch((error) => console.warn("couldn't keep the save:", error))
+  }
+
+  private onKey(event: KeyboardEvent, pressed: boolean) {
+    if (!this.nes) return
+    const action = this.nes.key_event(event.code, pressed)
+    if (action && action.hotkey === 'rewind') this.rewinding = action.pressed
+    if (action && action.hotkey === 'fast-forward') this.nes.set_turbo(action.pressed)
+    if (this.nes.hotkeys().some((hotkey) => hotkey.key === event.code)) {
+      event.preventDefault()
+    }
+  }
+
+  private everyFrame(now: number) {
+    if (!this.running) return
+    // the display's refresh rate isn't the console's: PAL runs at 50fps
+    const elapsed = Math.max(now - this.lastTick, 0)
+    this.lastTick = now
+    const speed = this.nes.speed()
+    this.owed = Math.min(
+      this.owed + (elapsed * this.nes.frame_rate() * speed) / 1000,
+      MAX_FRAMES_PER_TICK * speed
+    )
+    const due = Math.floor(this.owed)
+    this.owed -= due
+    this.nes.poll_gamepads()
+    // without a cartridge frame_rgba is the splash screen
+    if (this.loaded && this.rewinding) {
+      // held rewind steps back a state per frame, holding still at the
+      // oldest; playing goes on from wherever it stopped
+      for (let i = 0; i < due; i++) this.nes.rewind()
+    } else if (this.loaded && (due > 0 || this.nes.turbo())) {
+      // turbo runs as many frames as fit, whatever is due
+      let stop
+      try {
+        stop = this.nes.run_frames(due)
+      } catch (error) {
+        // a CPU fault: the game can't go on, but the page can
+        this.running = false
+        this.dispatchEvent(new CustomEvent('cpu-fault', { detail: error }))
+        return
+      }
+      if (stop.reason === 'breakpoint' || stop.reason === 'watchpoint') {
+        // the debugger takes over, resume() carries on
+        this.running = false
+        this.dispatchEvent(new CustomEvent('debugger-stop', { detail: stop }))
+      }
+    }
+    this.frame += due
+    if (this.frame >= SAVE_EVERY) {
+      this.frame -= SAVE_EVERY
+      this.save()
+    }
+    this.nes.render()
+    if (this.running) requestAnimationFrame((now) => this.everyFrame(now))
+  }
+}
+
+// The page has to be cross-origin isolated (served with COOP and COEP
+// headers) to share memory with a worker; elsewhere the emulator runs on
+// the main thread.
+export function defineFlemuPlayer(name = 'flemu-player'): void {
+  if (customElements.get(name)) return
+  customElements.define(name, self.crossOriginIsolated ? FlemuWorkerPlayer : FlemuPlayer)
+}
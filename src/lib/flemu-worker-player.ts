@@ -0,0 +1,189 @@
+// <flemu-player> with its Emulator in a worker (emulator-worker.ts): same
+// attribute, input, sound and saves as FlemuPlayer, but all this element
+// does on the main thread is upload the newest shared frame, feed the
+// audio callback from the shared ring and forward keys and gamepads.
+
+import init, { Display } from 'hello'
+import { startAudio } from './audio'
+import { AudioRing, SharedFrame, createSharedBuffers } from './shared'
+import type { WorkerCommand, WorkerEvent } from './shared'
+import { INPUT_KEY, LEGACY_SAVE_PREFIX, RUN_AHEAD_PREFIX, decode } from './settings'
+
+export class FlemuWorkerPlayer extends HTMLElement {
+  static get observedAttributes(): string[] {
+    return ['rom-url']
+  }
+
+  private canvas: HTMLCanvasElement
+  private display: Display
+  private worker: Worker
+  private frame: SharedFrame
+  private ring: AudioRing
+  private audio: AudioContext
+  private hotkeyKeys: string[] = []
+  private romHash: string
+  private drawn = 0
+  private running = false
+  private ready: Promise<void>
+
+  connectedCallback(): void {
+    if (this.canvas) {
+      if (this.worker && !this.running) this.resume()
+      return
+    }
+    this.canvas = document.createElement('canvas')
+    this.canvas.tabIndex = 0
+    this.canvas.style.imageRendering = 'pixelated'
+    this.canvas.addEventListener('keydown', (event) => this.onKey(event, true))
+    this.canvas.addEventListener('keyup', (event) => this.onKey(event, false))
+    this.canvas.addEventListener('focusout', () => this.send({ type: 'release-keys' }))
+    // browsers only allow audio to start from a user gesture
+    this.canvas.addEventListener('click', () => {
+      if (this.worker && !this.audio) {
+        this.audio = startAudio({
+          set_sample_rate: (rate) => this.send({ type: 'sample-rate', rate }),
+          fill_audio: (out) => this.ring.fill_audio(out),
+        })
+      }
+    })
+    window.addEventListener('pagehide', () => this.send({ type: 'save' }))
+    this.appendChild(this.canvas)
+    this.ready = this.start()
+  }
+
+  disconnectedCallback(): void {
+    this.send({ type: 'save' })
+    this.send({ type: 'pause' })
+    this.running = false
+    if (this.audio) this.audio.close()
+    this.audio = undefined
+  }
+
+  attributeChangedCallback(name: string, old: string, url: string): void {
+    if (this.worker && url && url !== old) this.ready.then(() => this.load(url))
+  }
+
+  private send(command: WorkerCommand) {
+    if (this.worker) this.worker.postMessage(command)
+  }
+
+  private async start() {
+    await init()
+    this.display = new Display(this.canvas)
+    const buffers = createSharedBuffers()
+    this.frame = new SharedFrame(buffers)
+    this.ring = new AudioRing(buffers)
+    this.worker = new Worker(new URL('./emulator-worker.ts', import.meta.url), {
+      type: 'module',
+    })
+    const ready = new Promise<void>((resolve) => {
+      this.worker.onmessage = (message: MessageEvent<WorkerEvent>) => {
+        if (message.data.type === 'ready') resolve()
+        this.onEvent(message.data)
+      }
+    })
+    this.send({ type: 'start', buffers, input: localStorage.getItem(INPUT_KEY) })
+    await ready
+    const url = this.getAttribute('rom-url')
+    if (url) await this.load(url)
+    this.resume()
+  }
+
+  private onEvent(event: WorkerEvent) {
+    switch (event.type) {
+      case 'ready':
+        this.hotkeyKeys = event.hotkeyKeys
+        break
+      case 'loaded':
+        this.romHash = event.romHash
+        break
+      case 'debugger-stop':
+        // the debugger takes over, resume() carries on
+        this.running = false
+        this.dispatchEvent(new CustomEvent('debugger-stop', { detail: event.stop }))
+        break
+      case 'cpu-fault':
+        // the game can't go on, but the page can
+        this.running = false
+        this.dispatchEvent(new CustomEvent('cpu-fault', { detail: event.error }))
+        break
+      case 'error':
+        console.warn('emulator worker:', event.message)
+        break
+    }
+  }
+
+  // also carries on after a breakpoint or watchpoint paused it
+  resume(): void {
+    this.running = true
+    this.send({ type: 'run' })
+    requestAnimationFrame(() => this.everyFrame())
+  }
+
+  private async load(url: string) {
+    const response = await fetch(url)
+    if (!response.ok) {
+      throw new Error(`can't fetch ${url}: ${response.status}`)
+    }
+    const rom = new Uint8Array(await response.arrayBuffer())
+    const legacy = localStorage.getItem(LEGACY_SAVE_PREFIX + url)
+    this.send({ type: 'load', rom, legacySave: legacy ? decode(legacy) : null })
+    await new Promise<void>((resolve) => {
+      // an unreadable ROM comes back as an error instead
+      const onLoaded = (message: MessageEvent<WorkerEvent>) => {
+        if (message.data.type !== 'loaded' && message.data.type !== 'error') return
+        this.worker.removeEventListener('message', onLoaded)
+        resolve()
+      }
+      this.worker.addEventListener('message', onLoaded)
+    })
+    // remembered by ROM hash, which only the worker can work out
+    const runAhead = localStorage.getItem(RUN_AHEAD_PREFIX + this.romHash)
+    if (runAhead) this.send({ type: 'run-ahead', frames: Number(runAhead) })
+  }
+
+  // frames of run-ahead for the loaded game, remembered for next time
+  setRunAhead(frames: number): void {
+    if (!this.romHash) return
+    this.send({ type: 'run-ahead', frames })
+    localStorage.setItem(RUN_AHEAD_PREFIX + this.romHash, String(frames))
+  }
+
+  // how frames are fitted into the canvas, like Emulator's
+  // set_crop_overscan, set_aspect_ratio and set_integer_scaling
+  setVideoOptions(options: {
+    cropOverscan?: boolean
+    aspect?: string
+    integerScaling?: boolean
+  }): void {
+    this.ready.then(() => {
+      const { cropOverscan, aspect, integerScaling } = options
+      if (cropOverscan !== undefined) this.display.set_crop_overscan(cropOverscan)
+      if (aspect !== undefined) this.display.set_aspect_ratio(aspect)
+      if (integerScaling !== undefined) this.display.set_integer_scaling(integerScaling)
+    })
+  }
+
+  private onKey(event: KeyboardEvent, pressed: boolean) {
+    this.send({ type: 'key', code: event.code, pressed })
+    if (this.hotkeyKeys.includes(event.code)) event.preventDefault()
+  }
+
+  private everyFrame() {
+    if (!this.running) return
+    // the worker can't read gamepads
+    const pads = Array.from(navigator.getGamepads())
+      .filter((pad): pad is Gamepad => pad !== null && pad.connected)
+      .map((pad) => ({
+        buttons: pad.buttons.map((button) => button.pressed),
+        axes: Array.from(pad.axes),
+      }))
+    this.send({ type: 'gamepads', pads })
+    const sequence = this.frame.sequence()
+    if (sequence !== this.drawn) {
+      this.drawn = sequence
+      this.display.draw(this.frame.latest())
+    }
+    requestAnimationFrame(() => this.everyFrame())
+  }
+}
@@ -0,0 +1,16 @@
+// What both players keep in localStorage, and the pace they run at.
+
+export const INPUT_KEY = 'flemu.input'
+// frames of run-ahead, per ROM hash
+export const RUN_AHEAD_PREFIX = 'flemu.run-ahead.'
+// where saves were kept before IndexedDB, still read if there's no newer one
+export const LEGACY_SAVE_PREFIX = 'flemu.sram.'
+// check for changed battery RAM about once a second
+export const SAVE_EVERY = 60
+// most frames one animation frame catches up on at normal speed, after a
+// stall or a background tab
+export const MAX_FRAMES_PER_TICK = 2
+
+export function decode(text: string): Uint8Array {
+  return Uint8Array.from(atob(text), (char) => char.charCodeAt(0))
+}
@@ -0,0 +1,125 @@
+// What an emulator worker and the page share: the frames and the sound go
+// through SharedArrayBuffers, so neither waits on the other; everything
+// else is a message. The worker only writes to the buffers and the page
+// only reads them, apart from the audio ring's read position.
+
+const FRAME_BYTES = 256 * 240 * 4
+// more than the APU's own ring at the longest latency, a second at 96kHz,
+// so the worker's fill can be passed as a fraction of that ring
+const AUDIO_CAPACITY = 1 << 17
+const READ = 0
+const WRITE = 1
+
+export interface SharedBuffers {
+  // two frames, so the page never uploads one the worker is writing
+  frames: SharedArrayBuffer
+  // frames published so far
+  frameCount: SharedArrayBuffer
+  audio: SharedArrayBuffer
+  // the audio ring's read and write positions
+  audioPositions: SharedArrayBuffer
+}
+
+export function createSharedBuffers(): SharedBuffers {
+  return {
+    frames: new SharedArrayBuffer(2 * FRAME_BYTES),
+    frameCount: new SharedArrayBuffer(4),
+    audio: new SharedArrayBuffer(AUDIO_CAPACITY * 4),
+    audioPositions: new SharedArrayBuffer(8),
+  }
+}
+
+export class SharedFrame {
+  private frames: Uint8Array
+  private count: Int32Array
+
+  constructor(buffers: SharedBuffers) {
+    this.frames = new Uint8Array(buffers.frames)
+    this.count = new Int32Array(buffers.frameCount)
+  }
+
+  // worker: write a frame_rgba into the spare half, then flip to it
+  publish(rgba: Uint8Array): void {
+    const next = Atomics.load(this.count, 0) + 1
+    this.frames.set(rgba, (next % 2) * FRAME_BYTES)
+    Atomics.store(this.count, 0, next)
+  }
+
+  // page: changes whenever there's a new frame
+  sequence(): number {
+    return Atomics.load(this.count, 0)
+  }
+
+  // page: the newest frame, a view into the shared memory
+  latest(): Uint8Array {
+    const offset = (this.sequence() % 2) * FRAME_BYTES
+    return this.frames.subarray(offset, offset + FRAME_BYTES)
+  }
+}
+
+// One writer (the worker) and one reader (the page's audio callback).
+// One slot always stays empty, so equal positions mean empty.
+export class AudioRing {
+  private samples: Float32Array
+  private positions: Int32Array
+  private last = 0
+
+  constructor(buffers: SharedBuffers) {
+    this.samples = new Float32Array(buffers.audio)
+    this.positions = new Int32Array(buffers.audioPositions)
+  }
+
+  // samples queued, for Emulator.set_audio_fill over audio_queue_capacity
+  buffered(): number {
+    const read = Atomics.load(this.positions, READ)
+    const write = Atomics.load(this.positions, WRITE)
+    return (write - read + AUDIO_CAPACITY) % AUDIO_CAPACITY
+  }
+
+  // worker: queue what fits, dropping the rest
+  push(input: Float32Array): void {
+    let write = Atomics.load(this.positions, WRITE)
+    const count = Math.min(input.length, AUDIO_CAPACITY - 1 - this.buffered())
+    for (let i = 0; i < count; i++) {
+      this.samples[write] = input[i]
+      write = (write + 1) % AUDIO_CAPACITY
+    }
+    Atomics.store(this.positions, WRITE, write)
+  }
+
+  // page: fill an audio callback's buffer like Emulator.fill_audio,
+  // holding the last level when the worker is behind
+  fill_audio(out: Float32Array): number {
+    let read = Atomics.load(this.positions, READ)
+    const count = Math.min(out.length, this.buffered())
+    for (let i = 0; i < count; i++) {
+      out[i] = this.samples[read]
+      read = (read + 1) % AUDIO_CAPACITY
+    }
+    Atomics.store(this.positions, READ, read)
+    if (count > 0) this.last = out[count - 1]
+    out.fill(this.last, count)
+    return count
+  }
+}
+
+// page to worker
+export type WorkerCommand =
+  | { type: 'start'; buffers: SharedBuffers; input: string | null }
+  | { type: 'load'; rom: Uint8Array; legacySave: Uint8Array | null }
+  | { type: 'run' }
+  | { type: 'pause' }
+  | { type: 'key'; code: string; pressed: boolean }
+  | { type: 'release-keys' }
+  | { type: 'gamepads'; pads: { buttons: boolean[]; axes: number[] }[] }
+  | { type: 'sample-rate'; rate: number }
+  | { type: 'run-ahead'; frames: number }
+  | { type: 'save' }
+
+// worker to page
+export type WorkerEvent =
+  | { type: 'ready'; hotkeyKeys: string[] }
+  | { type: 'loaded'; romHash: string }
+  | { type: 'debugger-stop'; stop: unknown }
+  | { type: 'cpu-fault'; error: { code: string; message: string; pc: number; opcode: number } }
+  | { type: 'error'; message: string }
@@ -3,7 +3,15 @@ import { svelte } from '@sveltejs/vite-plugin-svelte'
 import windicss from 'vite-plugin-windicss'
 import ViteRsw from 'vite-plugin-rsw'
 
+// cross-origin isolation, without which there's no SharedArrayBuffer and
+// <flemu-player> runs the emulator on the main thread
+const isolation = {
+  'Cross-Origin-Opener-Policy': 'same-origin',
+  'Cross-Origin-Embedder-Policy': 'require-corp',
+}
+
 export default defineConfig({
+  server: { headers: isolation },
   plugins: [
     svelte(),
     ViteRsw.default({